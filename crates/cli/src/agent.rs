@@ -0,0 +1,93 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Context;
+use ecsdk::prelude::*;
+use guest::client::Client;
+use machine::config::SystemConfig;
+use machine::driver::LibvirtDriver;
+use machine::guest::AgentConnector;
+use tokio::sync::Mutex;
+
+/// Subcommands of `rum agent`.
+#[derive(clap::Subcommand, Clone, Copy, Debug)]
+pub enum AgentCmd {
+    /// Replace the guest's agent with the one built into this rum, without
+    /// recreating the machine.
+    Update,
+}
+
+/// How long a cached agent connection gets to answer before it is dropped.
+const LIVENESS_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The daemon's guest agent connection, shared by every request it serves.
+///
+/// Calls are multiplexed over one agent connection, so exec, copy and
+/// provisioning requests do not open their own next to the lifecycle's.
+#[derive(Resource, Clone, Default)]
+pub struct SharedAgent(Arc<Mutex<Option<(AgentConnector, Client<AgentConnector>)>>>);
+
+impl SharedAgent {
+    /// A client for the guest of `driver`, connecting on first use, again
+    /// once the guest's CID or agent transport changed, and again once the
+    /// cached connection stopped answering, e.g. after the agent restarted.
+    pub async fn client(&self, driver: &LibvirtDriver) -> Result<Client<AgentConnector>, String> {
+        let connector = driver
+            .agent_connector()
+            .map_err(|error| format!("guest connection is not ready: {error}"))?;
+        let mut slot = self.0.lock().await;
+        if let Some((known, client)) = slot.as_ref()
+            && *known == connector
+        {
+            let alive = tokio::time::timeout(LIVENESS_TIMEOUT, client.rpc().ping())
+                .await
+                .is_ok_and(|pong| pong.is_ok());
+            if alive {
+                return Ok(client.clone());
+            }
+            tracing::debug!("guest agent connection broke; reconnecting");
+            machine::guest::forget_agent_client(&connector);
+        }
+        *slot = None;
+
+        let client = machine::guest::wait_for_agent(connector.clone())
+            .await
+            .map_err(|error| format!("failed to connect to guest agent: {error}"))?;
+        *slot = Some((connector, client.clone()));
+        Ok(client)
+    }
+}
+
+/// Manage the rum agent of the running machine.
+pub async fn run(system: &SystemConfig, cmd: AgentCmd) -> anyhow::Result<()> {
+    match cmd {
+        AgentCmd::Update => update(system).await,
+    }
+}
+
+async fn update(system: &SystemConfig) -> anyhow::Result<()> {
+    let binary = machine::guest::agent_binary(system.guest_arch())?;
+    let connector = LibvirtDriver::new(system.clone())
+        .agent_connector()
+        .context("the machine is not running")?;
+    // Skip the protocol check: an incompatible agent is what gets replaced.
+    let client = Client::connect(connector.clone());
+    let before = client
+        .rpc()
+        .ping()
+        .await
+        .map_err(|error| anyhow::anyhow!("the agent did not answer: {error}"))?;
+    client.update_agent(&binary).await?;
+    drop(client);
+
+    // The agent re-execs half a second after answering.
+    tokio::time::sleep(Duration::from_secs(1)).await;
+    let after = Client::connect(connector).wait_ready().await?;
+    println!(
+        "updated the agent of {}: v{} -> v{}",
+        system.display_name(),
+        before.version,
+        after.version
+    );
+    Ok(())
+}
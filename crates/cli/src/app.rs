@@ -20,8 +20,14 @@ pub fn create_isomorphic_app(
     iso.add_plugin(crate::cp::CopyFeature);
     iso.add_plugin(crate::down::DownFeature);
     iso.add_plugin(crate::destroy::DestroyFeature);
+    iso.add_plugin(crate::reboot::RebootFeature);
     iso.add_plugin(crate::exec::ExecFeature);
     iso.add_plugin(crate::status::StatusFeature);
+    iso.add_plugin(crate::sync::SyncFeature);
+    iso.add_plugin(crate::reload::ReloadFeature);
+    iso.add_plugin(crate::provision::ProvisionFeature);
+    iso.add_plugin(crate::test::TestFeature);
+    iso.add_plugin(crate::cancel::CancelFeature);
     iso.add_plugin(crate::restart::ProtocolRestartPlugin::new(
         restart_requested,
     ));
@@ -0,0 +1,112 @@
+use std::time::SystemTime;
+
+use ecsdk::network::IsomorphicPlugin;
+use ecsdk::prelude::*;
+use ecsdk::tasks::SpawnTask;
+use machine::driver::LibvirtDriver;
+use orchestrator::ManagedInstance;
+
+use crate::agent::SharedAgent;
+use crate::protocol::{CancelRequest, CancelResponse};
+
+/// Shared request feature that stops a guest command started by `rum exec`
+/// or `rum provision` when its client is interrupted.
+pub struct CancelFeature;
+
+impl IsomorphicPlugin for CancelFeature {
+    fn build_shared(&self, app: &mut App) {
+        CancelRequest::register(app);
+    }
+
+    fn build_server(&self, app: &mut App) {
+        app.init_resource::<SharedAgent>();
+        app.add_observer(handle_cancel_request);
+    }
+
+    fn build_client(&self, app: &mut App) {
+        app.add_observer(handle_cancel_response);
+    }
+}
+
+/// A fresh id for one guest command, unique across clients of the daemon.
+pub fn new_execution_id() -> String {
+    let nanos = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or_default();
+    format!("{}-{nanos}", std::process::id())
+}
+
+#[derive(Resource, Clone)]
+struct CancelTarget(String);
+
+/// Cancel the guest command `execution_id` on the first Ctrl+C and leave it
+/// to finish on the second. The client keeps running until the daemon
+/// reports how the command ended.
+pub fn cancel_on_ctrl_c(app: &mut App, execution_id: String) {
+    app.insert_resource(CancelTarget(execution_id));
+    app.add_systems(Startup, spawn_ctrl_c_handler);
+}
+
+fn spawn_ctrl_c_handler(target: Res<CancelTarget>, mut commands: Commands) {
+    let execution_id = target.0.clone();
+    commands.spawn_empty().spawn_task(move |task| async move {
+        if tokio::signal::ctrl_c().await.is_err() {
+            return;
+        }
+        task.queue_cmd_wake(move |world: &mut World| {
+            eprintln!("cancelling; press Ctrl+C again to detach");
+            world
+                .commands()
+                .client_trigger(CancelRequest { execution_id });
+        });
+        if tokio::signal::ctrl_c().await.is_ok() {
+            task.queue_cmd_wake(|world: &mut World| {
+                world.write_message(AppExit::from_code(130));
+            });
+        }
+    });
+}
+
+fn handle_cancel_request(
+    trigger: On<FromClient<CancelRequest>>,
+    instances: Query<&ManagedInstance<LibvirtDriver>>,
+    agent: Res<SharedAgent>,
+    mut commands: Commands,
+) {
+    let client_id = trigger.event().client_id;
+    let Some(instance) = instances.iter().next() else {
+        CancelRequest::reply(
+            &mut commands,
+            client_id,
+            CancelResponse { cancelled: false },
+        );
+        return;
+    };
+
+    let execution_id = trigger.event().message.execution_id.clone();
+    let driver = instance.driver();
+    let agent = agent.clone();
+    commands.spawn_empty().spawn_task(move |task| async move {
+        let cancelled = match agent.client(&driver).await {
+            Ok(client) => client.cancel(execution_id).await.unwrap_or_else(|error| {
+                tracing::warn!(%error, "failed to cancel guest command");
+                false
+            }),
+            Err(error) => {
+                tracing::warn!(%error, "failed to cancel guest command");
+                false
+            }
+        };
+        task.queue_cmd_wake(move |world: &mut World| {
+            let mut commands = world.commands();
+            CancelRequest::reply(&mut commands, client_id, CancelResponse { cancelled });
+        });
+    });
+}
+
+fn handle_cancel_response(trigger: On<CancelResponse>) {
+    if !trigger.event().cancelled {
+        eprintln!("the command was no longer running in the guest");
+    }
+}
@@ -0,0 +1,238 @@
+//! CI mode: plain output folded into the CI system's collapsible groups, no
+//! prompts, bounded timeouts, and the logs copied to a directory the job can
+//! upload as an artifact.
+
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use anyhow::Context;
+use ecsdk::prelude::*;
+use machine::config::SystemConfig;
+
+/// Where CI mode copies the logs when no `--log-dir` is given, relative to
+/// the working directory.
+pub const DEFAULT_LOG_DIR: &str = "rum-logs";
+
+/// CI system whose log viewer the output is grouped for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CiProvider {
+    GitHub,
+    GitLab,
+    /// Any other CI: plain output without group markers.
+    Other,
+}
+
+impl CiProvider {
+    /// The CI system the environment belongs to, if any.
+    pub fn detect() -> Option<Self> {
+        Self::from_vars(|name| std::env::var(name).ok())
+    }
+
+    /// The CI system whose variables `var` looks up, if any.
+    fn from_vars(var: impl Fn(&str) -> Option<String>) -> Option<Self> {
+        let flag = |name: &str| var(name).is_some_and(|value| is_true(&value));
+        if flag("GITHUB_ACTIONS") {
+            Some(Self::GitHub)
+        } else if flag("GITLAB_CI") {
+            Some(Self::GitLab)
+        } else {
+            flag("CI").then_some(Self::Other)
+        }
+    }
+}
+
+/// Set for clients running in CI mode.
+#[derive(Resource, Debug, Clone)]
+pub struct CiMode {
+    pub provider: CiProvider,
+    pub log_dir: PathBuf,
+}
+
+impl CiMode {
+    /// CI mode when `--ci` is given (`forced`) or the environment is a CI
+    /// job, with logs copied to `log_dir` or [`DEFAULT_LOG_DIR`].
+    pub fn detect(forced: bool, log_dir: Option<PathBuf>) -> Option<Self> {
+        let provider = CiProvider::detect().or(forced.then_some(CiProvider::Other))?;
+        Some(Self {
+            provider,
+            log_dir: log_dir.unwrap_or_else(|| DEFAULT_LOG_DIR.into()),
+        })
+    }
+
+    /// Marker line that opens a collapsible group titled `title`; `id` has to
+    /// be passed to [`CiMode::end_group`] again.
+    pub fn start_group(&self, id: &str, title: &str) -> Option<String> {
+        match self.provider {
+            CiProvider::GitHub => Some(format!("::group::{title}")),
+            CiProvider::GitLab => Some(format!(
+                "\x1b[0Ksection_start:{}:{id}[collapsed=true]\r\x1b[0K{title}",
+                unix_time()
+            )),
+            CiProvider::Other => None,
+        }
+    }
+
+    pub fn end_group(&self, id: &str) -> Option<String> {
+        match self.provider {
+            CiProvider::GitHub => Some("::endgroup::".into()),
+            CiProvider::GitLab => Some(format!("\x1b[0Ksection_end:{}:{id}\r\x1b[0K", unix_time())),
+            CiProvider::Other => None,
+        }
+    }
+
+    /// Copy `rum.log` with its rotated files, and the provisioning script
+    /// logs under `scripts/`, into the log dir.
+    pub fn collect_logs(&self, system: &SystemConfig) -> anyhow::Result<()> {
+        let daemon_log = machine::paths::daemon_log_path(&system.id, system.name.as_deref());
+        let logs_dir = machine::paths::logs_dir(&system.id, system.name.as_deref());
+        match daemon_log.parent() {
+            Some(work_dir) => self.copy_logs(work_dir, &logs_dir),
+            None => Ok(()),
+        }
+    }
+
+    /// Copy the `rum.log*` files of `work_dir` and everything in `logs_dir`
+    /// into the log dir.
+    fn copy_logs(&self, work_dir: &Path, logs_dir: &Path) -> anyhow::Result<()> {
+        let scripts_dir = self.log_dir.join("scripts");
+        std::fs::create_dir_all(&scripts_dir)
+            .with_context(|| format!("creating {}", scripts_dir.display()))?;
+
+        copy_files(work_dir, &self.log_dir, |name| name.starts_with("rum.log"))?;
+        copy_files(logs_dir, &scripts_dir, |_| true)
+    }
+}
+
+/// Bound the timeouts of `system` when the daemon runs in a CI job, which
+/// `rum --ci` marks it as even outside one.
+pub fn bound_timeouts(system: &mut SystemConfig) {
+    if CiProvider::detect().is_some() {
+        system.config.timeouts = system.config.timeouts.for_ci();
+    }
+}
+
+/// Copy the files in `from` whose name passes `keep` into `to`. A missing
+/// `from` has nothing to copy.
+fn copy_files(from: &Path, to: &Path, keep: impl Fn(&str) -> bool) -> anyhow::Result<()> {
+    let Ok(entries) = std::fs::read_dir(from) else {
+        return Ok(());
+    };
+    for entry in entries.filter_map(|entry| entry.ok()) {
+        let path = entry.path();
+        let name = entry.file_name();
+        if !path.is_file() || !name.to_str().is_some_and(&keep) {
+            continue;
+        }
+        std::fs::copy(&path, to.join(&name))
+            .with_context(|| format!("copying {}", path.display()))?;
+    }
+    Ok(())
+}
+
+/// Whether an environment flag set to `value` is on: anything other than
+/// empty, `0` or `false`.
+fn is_true(value: &str) -> bool {
+    !matches!(
+        value.trim().to_ascii_lowercase().as_str(),
+        "" | "0" | "false"
+    )
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn provider(vars: &[(&str, &str)]) -> Option<CiProvider> {
+        CiProvider::from_vars(|name| {
+            vars.iter()
+                .find(|(key, _)| *key == name)
+                .map(|(_, value)| value.to_string())
+        })
+    }
+
+    fn ci(provider: CiProvider, log_dir: PathBuf) -> CiMode {
+        CiMode { provider, log_dir }
+    }
+
+    #[test]
+    fn detects_the_provider_from_its_variables() {
+        assert_eq!(provider(&[]), None);
+        assert_eq!(provider(&[("CI", "true")]), Some(CiProvider::Other));
+        assert_eq!(
+            provider(&[("CI", "true"), ("GITLAB_CI", "true")]),
+            Some(CiProvider::GitLab)
+        );
+        assert_eq!(
+            provider(&[("GITHUB_ACTIONS", "true"), ("GITLAB_CI", "true")]),
+            Some(CiProvider::GitHub)
+        );
+        assert_eq!(provider(&[("CI", "0"), ("GITHUB_ACTIONS", "FALSE")]), None);
+        assert_eq!(provider(&[("CI", " ")]), None);
+    }
+
+    #[test]
+    fn group_markers_follow_the_provider() {
+        let github = ci(CiProvider::GitHub, DEFAULT_LOG_DIR.into());
+        assert_eq!(
+            github.start_group("rum_booting", "dev: booting").as_deref(),
+            Some("::group::dev: booting")
+        );
+        assert_eq!(
+            github.end_group("rum_booting").as_deref(),
+            Some("::endgroup::")
+        );
+
+        let gitlab = ci(CiProvider::GitLab, DEFAULT_LOG_DIR.into());
+        let start = gitlab.start_group("rum_booting", "dev: booting").unwrap();
+        assert!(start.starts_with("\x1b[0Ksection_start:"), "{start:?}");
+        assert!(
+            start.ends_with(":rum_booting[collapsed=true]\r\x1b[0Kdev: booting"),
+            "{start:?}"
+        );
+        let end = gitlab.end_group("rum_booting").unwrap();
+        assert!(end.starts_with("\x1b[0Ksection_end:"), "{end:?}");
+        assert!(end.ends_with(":rum_booting\r\x1b[0K"), "{end:?}");
+
+        let other = ci(CiProvider::Other, DEFAULT_LOG_DIR.into());
+        assert_eq!(other.start_group("rum_booting", "dev: booting"), None);
+        assert_eq!(other.end_group("rum_booting"), None);
+    }
+
+    #[test]
+    fn copy_logs_takes_daemon_logs_and_script_logs() {
+        let root = std::env::temp_dir().join(format!("rum-ci-logs-{}", std::process::id()));
+        let work_dir = root.join("work");
+        let logs_dir = work_dir.join("logs");
+        std::fs::create_dir_all(&logs_dir).unwrap();
+        for name in ["rum.log", "rum.log.1", "disk.qcow2"] {
+            std::fs::write(work_dir.join(name), name).unwrap();
+        }
+        std::fs::write(logs_dir.join("10-setup.log"), "ran").unwrap();
+
+        let mode = ci(CiProvider::Other, root.join("out"));
+        mode.copy_logs(&work_dir, &logs_dir).unwrap();
+        let out = root.join("out");
+        assert_eq!(
+            std::fs::read_to_string(out.join("rum.log.1")).unwrap(),
+            "rum.log.1"
+        );
+        assert!(out.join("rum.log").is_file());
+        assert!(!out.join("disk.qcow2").exists());
+        assert!(!out.join("logs").exists(), "directories are not copied");
+        assert_eq!(
+            std::fs::read_to_string(out.join("scripts/10-setup.log")).unwrap(),
+            "ran"
+        );
+
+        // A machine that never wrote script logs has nothing to copy.
+        mode.copy_logs(&work_dir, &root.join("missing")).unwrap();
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
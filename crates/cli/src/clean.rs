@@ -0,0 +1,47 @@
+use std::time::Duration;
+
+use machine::clean::{apply, plan};
+
+/// Run the host-wide `rum clean` command.
+///
+/// With `dry_run` the removable items are only listed. Orphaned machines
+/// that are still running are skipped unless `force` is set.
+pub fn run(
+    libvirt_uri: &str,
+    cache_ttl_days: u64,
+    dry_run: bool,
+    force: bool,
+) -> anyhow::Result<()> {
+    let ttl = Duration::from_secs(cache_ttl_days.saturating_mul(86_400));
+    let items = plan(libvirt_uri, ttl)?;
+    if items.is_empty() {
+        println!("nothing to clean");
+        return Ok(());
+    }
+
+    for item in &items {
+        println!(
+            "{:<8} {}  ({})",
+            item.kind.label(),
+            item.target,
+            item.reason
+        );
+    }
+
+    let (items, skipped): (Vec<_>, Vec<_>) =
+        items.into_iter().partition(|item| force || !item.running);
+
+    if dry_run {
+        println!("dry run: {} item(s) would be removed", items.len());
+    } else {
+        apply(libvirt_uri, &items, force)?;
+        println!("removed {} item(s)", items.len());
+    }
+    if !skipped.is_empty() {
+        println!(
+            "skipped {} running machine(s); pass --force to stop and remove them",
+            skipped.len()
+        );
+    }
+    Ok(())
+}
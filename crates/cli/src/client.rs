@@ -1,8 +1,13 @@
+use std::sync::{Arc, Mutex};
+
 use ecsdk::app::AsyncApp;
 use ecsdk::prelude::*;
-use orchestrator::OrchestratorMessage;
+use ecsdk::tasks::SpawnTask;
+use orchestrator::instance::instance_phase::{Failed, Running};
+use orchestrator::{InstancePhase, OrchestratorMessage};
 
 use crate::exit;
+use crate::protocol::DownRequest;
 
 /// Client-side observers for the first plain `rum up` flow.
 ///
@@ -15,12 +20,113 @@ impl Plugin for RumClientPlugin {
         app.add_observer(exit::on_running);
         app.add_observer(exit::on_stopped);
         app.add_observer(exit::on_failed);
+        app.add_observer(record_running);
+        app.add_observer(record_failed);
         app.add_systems(Update, exit::on_server_disconnect);
     }
 }
 
+/// Shared slot an up client fills with whether the machine reached running,
+/// for callers that keep going after `up` (e.g. `rum bake`).
+#[derive(Resource, Clone, Default)]
+pub struct UpOutcome(pub Arc<Mutex<Option<bool>>>);
+
+impl UpOutcome {
+    pub fn running(&self) -> bool {
+        *self.0.lock().expect("up outcome lock poisoned") == Some(true)
+    }
+
+    fn set(&self, running: bool) {
+        *self.0.lock().expect("up outcome lock poisoned") = Some(running);
+    }
+}
+
+fn record_running(_trigger: On<Add, Running>, outcome: Option<Res<UpOutcome>>) {
+    if let Some(outcome) = outcome {
+        outcome.set(true);
+    }
+}
+
+fn record_failed(_trigger: On<Add, Failed>, outcome: Option<Res<UpOutcome>>) {
+    if let Some(outcome) = outcome {
+        outcome.set(false);
+    }
+}
+
 /// Build the client app used by the initial `rum up` command.
-pub fn build_up_client(mut app: AsyncApp<OrchestratorMessage>) -> AsyncApp<OrchestratorMessage> {
+pub fn build_up_client(app: AsyncApp<OrchestratorMessage>) -> AsyncApp<OrchestratorMessage> {
+    build_up_client_with(app, false)
+}
+
+/// Build a `rum up` client. The `primary` client, the one that started the
+/// daemon, shuts the machine down on Ctrl+C; any other client just detaches.
+pub fn build_up_client_with(
+    mut app: AsyncApp<OrchestratorMessage>,
+    primary: bool,
+) -> AsyncApp<OrchestratorMessage> {
     app.add_plugins(RumClientPlugin);
+    if primary {
+        app.add_systems(Startup, shut_down_on_ctrl_c);
+    }
     app
 }
+
+/// Build the client app used by `rum attach`.
+///
+/// It observes the daemon until the machine stops or the daemon goes away;
+/// Ctrl+C detaches without affecting the machine.
+pub fn build_attach_client(
+    mut app: AsyncApp<OrchestratorMessage>,
+) -> AsyncApp<OrchestratorMessage> {
+    app.add_observer(exit::on_stopped);
+    app.add_observer(exit::on_failed);
+    app.add_observer(exit::on_crashed);
+    app.add_systems(Update, exit::on_server_disconnect);
+    app
+}
+
+/// How far Ctrl+C has escalated the shutdown of the machine.
+#[derive(Resource, Default)]
+struct CtrlCPresses {
+    asked: bool,
+    forced: bool,
+}
+
+/// Ask for a clean shutdown on the first Ctrl+C and force it on the second.
+/// A press while the machine is still booting or provisioning cancels that
+/// work straight away, and one after a forced stop leaves the daemon to
+/// finish on its own.
+fn shut_down_on_ctrl_c(mut commands: Commands) {
+    commands.init_resource::<CtrlCPresses>();
+    commands.spawn_empty().spawn_task(|task| async move {
+        while tokio::signal::ctrl_c().await.is_ok() {
+            task.queue_cmd_wake(on_ctrl_c);
+        }
+    });
+}
+
+fn on_ctrl_c(world: &mut World) {
+    let in_flight = world
+        .query::<&InstancePhase>()
+        .iter(world)
+        .next()
+        .copied()
+        .filter(InstancePhase::is_in_flight);
+    let mut presses = world.resource_mut::<CtrlCPresses>();
+    if presses.forced {
+        world.write_message(AppExit::from_code(130));
+        return;
+    }
+    let force = presses.asked || in_flight.is_some();
+    presses.asked = true;
+    presses.forced = force;
+    match (in_flight, force) {
+        (Some(phase), _) => eprintln!(
+            "cancelling ({}); press Ctrl+C again to detach",
+            phase.label().to_lowercase()
+        ),
+        (None, true) => eprintln!("forcing shutdown; press Ctrl+C again to detach"),
+        (None, false) => eprintln!("shutting down; press Ctrl+C again to force it"),
+    }
+    world.commands().client_trigger(DownRequest { force });
+}
@@ -0,0 +1,153 @@
+use std::path::Path;
+
+use facet::Facet;
+use machine::config::{Config, ResolvedFs, SystemConfig, check_config};
+
+/// Subcommands of `rum config`.
+#[derive(clap::Subcommand, Clone, Copy, Debug)]
+pub enum ConfigCmd {
+    /// Report every problem in the config file, not just the first.
+    Validate,
+    /// Print the config with defaults filled in and drives, filesystems and
+    /// mounts resolved.
+    Show {
+        #[arg(long, value_enum, default_value_t)]
+        format: ShowFormat,
+    },
+}
+
+/// Output format of `rum config show`.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default)]
+pub enum ShowFormat {
+    #[default]
+    Toml,
+    Json,
+}
+
+/// The loaded config together with everything rum derives from it.
+#[derive(Facet)]
+struct ResolvedConfig {
+    id: String,
+    name: Option<String>,
+    config_path: String,
+    drives: Vec<DriveView>,
+    filesystems: Vec<FsView>,
+    mounts: Vec<MountView>,
+    config: Config,
+}
+
+#[derive(Facet)]
+struct DriveView {
+    name: String,
+    dev: String,
+    path: String,
+    size: String,
+    format: String,
+    external: bool,
+    readonly: bool,
+    encrypted: bool,
+}
+
+#[derive(Facet)]
+struct FsView {
+    filesystem: String,
+    target: String,
+    devs: Vec<String>,
+    /// ZFS pool name.
+    pool: Option<String>,
+}
+
+#[derive(Facet)]
+struct MountView {
+    source: String,
+    target: String,
+    tag: String,
+    driver: String,
+    readonly: bool,
+}
+
+/// Run `rum config validate`, which works on configs that fail to load.
+pub fn validate(path: &Path, profile: Option<&str>) -> anyhow::Result<()> {
+    let report = check_config(path, profile)?;
+    if report.is_valid() {
+        println!("{} is valid", path.display());
+        return Ok(());
+    }
+    eprint!("{}", report.render());
+    anyhow::bail!(
+        "{} has {} problem(s)",
+        path.display(),
+        report.problems.len()
+    )
+}
+
+/// Run `rum config show` for a loaded config.
+pub fn show(system: &SystemConfig, format: ShowFormat) -> anyhow::Result<()> {
+    let drives = system.resolve_drives()?;
+    let filesystems = system.resolve_fs(&drives)?;
+    let mounts = system.resolve_mounts()?;
+
+    let resolved = ResolvedConfig {
+        id: system.id.clone(),
+        name: system.name.clone(),
+        config_path: system.config_path.display().to_string(),
+        drives: drives
+            .iter()
+            .map(|d| DriveView {
+                name: d.name.clone(),
+                dev: d.dev.clone(),
+                path: d.path.display().to_string(),
+                size: d.size.clone(),
+                format: d.format.clone(),
+                external: d.external,
+                readonly: d.readonly,
+                encrypted: d.encrypted,
+            })
+            .collect(),
+        filesystems: filesystems.iter().map(fs_view).collect(),
+        mounts: mounts
+            .iter()
+            .map(|m| MountView {
+                source: m.source.display().to_string(),
+                target: m.target.clone(),
+                tag: m.tag.clone(),
+                driver: m.driver.label().to_string(),
+                readonly: m.readonly,
+            })
+            .collect(),
+        config: system.config.clone(),
+    };
+
+    match format {
+        ShowFormat::Toml => {
+            let toml = facet_toml::to_string(&resolved)
+                .map_err(|e| anyhow::anyhow!("failed to render config as TOML: {e}"))?;
+            print!("{toml}");
+        }
+        ShowFormat::Json => println!("{}", facet_json::to_string(&resolved)),
+    }
+    Ok(())
+}
+
+fn fs_view(fs: &ResolvedFs) -> FsView {
+    match fs {
+        ResolvedFs::Zfs(z) => FsView {
+            filesystem: "zfs".into(),
+            target: z.target.clone(),
+            devs: z.devs.clone(),
+            pool: Some(z.pool.clone()),
+        },
+        ResolvedFs::Btrfs(b) => FsView {
+            filesystem: "btrfs".into(),
+            target: b.target.clone(),
+            devs: b.devs.clone(),
+            pool: None,
+        },
+        ResolvedFs::Simple(s) => FsView {
+            filesystem: s.filesystem.clone(),
+            target: s.target.clone(),
+            devs: vec![s.dev.clone()],
+            pool: None,
+        },
+    }
+}
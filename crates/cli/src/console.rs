@@ -0,0 +1,17 @@
+use anyhow::bail;
+use machine::config::SystemConfig;
+
+/// Attach to the machine's serial console with `virsh console`.
+pub fn run(system: &SystemConfig) -> anyhow::Result<()> {
+    let status = std::process::Command::new("virsh")
+        .args(["-c", system.libvirt_uri(), "console", system.display_name()])
+        .status();
+    match status {
+        Ok(status) if status.success() => Ok(()),
+        Ok(status) => bail!("virsh console exited with {status}"),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            bail!("virsh not found; install the libvirt client tools")
+        }
+        Err(e) => Err(e.into()),
+    }
+}
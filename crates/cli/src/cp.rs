@@ -4,13 +4,14 @@ use ecsdk::app::AsyncApp;
 use ecsdk::network::{InitialConnection, IsomorphicPlugin};
 use ecsdk::prelude::*;
 use ecsdk::tasks::SpawnTask;
-use guest::client::CopyDirection;
+use guest::client::{CopyDirection, TransferOptions};
 use machine::driver::LibvirtDriver;
-use machine::guest::VsockConnector;
 use orchestrator::ManagedInstance;
 use orchestrator::OrchestratorMessage;
 
+use crate::agent::SharedAgent;
 use crate::protocol::{CopyRequest, CopyResponse, CopySpec};
+use crate::render::RenderStyle;
 
 /// Shared request feature for daemon-backed guest file copies.
 pub struct CopyFeature;
@@ -21,6 +22,7 @@ impl IsomorphicPlugin for CopyFeature {
     }
 
     fn build_server(&self, app: &mut App) {
+        app.init_resource::<SharedAgent>();
         app.add_observer(handle_copy_request);
     }
 
@@ -37,7 +39,7 @@ struct PendingCopyRequest(CopyRequest);
 
 /// Parse the user-facing `rum cp` arguments and resolve the host-side path to
 /// an absolute path before handing control to the daemon.
-pub fn prepare_request(src: &str, dst: &str) -> anyhow::Result<CopyRequest> {
+pub fn prepare_request(src: &str, dst: &str, compress: bool) -> anyhow::Result<CopyRequest> {
     let direction = guest::client::parse_copy_args(src, dst)?;
     let spec = match direction {
         CopyDirection::Upload { local, guest } => CopySpec::Upload {
@@ -50,7 +52,10 @@ pub fn prepare_request(src: &str, dst: &str) -> anyhow::Result<CopyRequest> {
         },
     };
 
-    Ok(CopyRequest { spec: Some(spec) })
+    Ok(CopyRequest {
+        spec: Some(spec),
+        compress,
+    })
 }
 
 /// Build the client app used by `rum cp`.
@@ -74,6 +79,7 @@ fn send_copy_request_on_connect(
 fn handle_copy_request(
     trigger: On<FromClient<CopyRequest>>,
     instances: Query<&ManagedInstance<LibvirtDriver>>,
+    agent: Res<SharedAgent>,
     mut commands: Commands,
 ) {
     let Some(instance) = instances.iter().next() else {
@@ -88,7 +94,11 @@ fn handle_copy_request(
         return;
     };
 
-    let Some(spec) = trigger.event().message.spec.clone() else {
+    let request = &trigger.event().message;
+    let options = TransferOptions {
+        compress: request.compress,
+    };
+    let Some(spec) = request.spec.clone() else {
         CopyRequest::reply(
             &mut commands,
             trigger.event().client_id,
@@ -101,9 +111,10 @@ fn handle_copy_request(
     };
 
     let driver = instance.driver();
+    let agent = agent.clone();
     let client_id = trigger.event().client_id;
     commands.spawn_empty().spawn_task(move |task| async move {
-        let response = match run_copy(driver, spec).await {
+        let response = match run_copy(&agent, driver, spec, options).await {
             Ok(message) => CopyResponse {
                 success: true,
                 message,
@@ -121,17 +132,17 @@ fn handle_copy_request(
     });
 }
 
-async fn run_copy(driver: LibvirtDriver, spec: CopySpec) -> Result<String, String> {
-    let cid = driver
-        .get_vsock_cid()
-        .map_err(|error| format!("guest connection is not ready: {error}"))?;
-    let client = guest::client::wait_for_agent(VsockConnector::new(cid))
-        .await
-        .map_err(|error| format!("failed to connect to guest agent: {error}"))?;
+async fn run_copy(
+    agent: &SharedAgent,
+    driver: LibvirtDriver,
+    spec: CopySpec,
+    options: TransferOptions,
+) -> Result<String, String> {
+    let client = agent.client(&driver).await?;
 
     match spec {
         CopySpec::Upload { local, guest } => {
-            let bytes = guest::client::copy_to_guest(&client, &local, &guest)
+            let bytes = guest::client::copy_to_guest_with(&client, &local, &guest, options)
                 .await
                 .map_err(|error| error.to_string())?;
             Ok(format!(
@@ -142,7 +153,7 @@ async fn run_copy(driver: LibvirtDriver, spec: CopySpec) -> Result<String, Strin
             ))
         }
         CopySpec::Download { guest, local } => {
-            let bytes = guest::client::copy_from_guest(&client, &guest, &local)
+            let bytes = guest::client::copy_from_guest_with(&client, &guest, &local, options)
                 .await
                 .map_err(|error| error.to_string())?;
             Ok(format!(
@@ -155,13 +166,18 @@ async fn run_copy(driver: LibvirtDriver, spec: CopySpec) -> Result<String, Strin
     }
 }
 
-fn handle_copy_response(trigger: On<CopyResponse>, mut exit: MessageWriter<AppExit>) {
+fn handle_copy_response(
+    trigger: On<CopyResponse>,
+    style: Option<Res<RenderStyle>>,
+    mut exit: MessageWriter<AppExit>,
+) {
     let response = trigger.event();
+    let style = style.as_deref().copied().unwrap_or_default();
     if response.success {
-        println!("{}", response.message);
+        println!("{}", style.text(&response.message));
         exit.write(AppExit::Success);
     } else {
-        eprintln!("{}", response.message);
+        eprintln!("{}", style.error(&style.text(&response.message)));
         exit.write(AppExit::from_code(1));
     }
 }
@@ -0,0 +1,329 @@
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use anyhow::{Context, bail};
+use machine::config::{PROFILE_ENV, SystemConfig};
+use machine::paths;
+
+use crate::ipc::INTERNAL_DAEMON_CONFIG;
+
+const SYSTEM_UNIT_DIR: &str = "/etc/systemd/system";
+
+/// Subcommands of `rum daemon`.
+#[derive(clap::Subcommand, Clone, Debug)]
+pub enum DaemonCmd {
+    /// Run the machine's daemon as a systemd service, so the machine comes
+    /// back up after logout or a host reboot.
+    Install {
+        /// Install a system unit instead of a user unit. Needs root.
+        #[arg(long)]
+        system: bool,
+        /// Account the system unit runs as; defaults to the user who ran
+        /// sudo.
+        #[arg(long, requires = "system")]
+        user: Option<String>,
+    },
+    /// Stop and remove the unit installed by `rum daemon install`.
+    Uninstall {
+        /// Remove the system unit instead of the user unit.
+        #[arg(long)]
+        system: bool,
+    },
+}
+
+pub fn run(system: &SystemConfig, cmd: DaemonCmd) -> anyhow::Result<()> {
+    match cmd {
+        DaemonCmd::Install {
+            system: scope,
+            user,
+        } => install(system, scope, user.as_deref()),
+        DaemonCmd::Uninstall { system: scope } => uninstall(system, scope),
+    }
+}
+
+/// Stop and remove the units `rum daemon install` left for `system`, so a
+/// destroyed machine is not brought back at the next boot.
+pub fn remove_installed(system: &SystemConfig) -> anyhow::Result<()> {
+    for system_scope in [false, true] {
+        if unit_path(system, system_scope).is_ok_and(|path| path.exists()) {
+            let command = if system_scope {
+                "sudo rum daemon uninstall --system"
+            } else {
+                "rum daemon uninstall"
+            };
+            uninstall(system, system_scope)
+                .with_context(|| format!("failed to remove the daemon unit; run `{command}`"))?;
+        }
+    }
+    Ok(())
+}
+
+/// Name of the unit running the daemon of `system`.
+fn unit_name(system: &SystemConfig) -> String {
+    format!("rum-{}.service", system.id)
+}
+
+fn unit_path(system: &SystemConfig, system_scope: bool) -> anyhow::Result<PathBuf> {
+    let dir = if system_scope {
+        PathBuf::from(SYSTEM_UNIT_DIR)
+    } else {
+        paths::systemd_user_unit_dir().context("no config directory for user units")?
+    };
+    Ok(dir.join(unit_name(system)))
+}
+
+/// The account a system unit runs as: `--user`, or whoever ran sudo. The
+/// daemon has to run as the CLI's user to find the same work dir, and under
+/// sudo `$USER` is root.
+fn service_user(explicit: Option<&str>) -> anyhow::Result<String> {
+    let user = match explicit {
+        Some(user) => user.to_string(),
+        None => std::env::var("SUDO_USER")
+            .ok()
+            .filter(|user| !user.is_empty() && user != "root")
+            .context("pass --user to choose the account the system unit runs as")?,
+    };
+    let valid = user
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'));
+    if user.is_empty() || !valid {
+        bail!("'{user}' is not a valid user name");
+    }
+    Ok(user)
+}
+
+fn install(system: &SystemConfig, system_scope: bool, user: Option<&str>) -> anyhow::Result<()> {
+    let exe = std::env::current_exe()?;
+    let user = system_scope.then(|| service_user(user)).transpose()?;
+    let unit = render_unit(
+        &system.display_name(),
+        &system.config_path,
+        system.profile.as_deref(),
+        &exe,
+        user.as_deref(),
+    )?;
+
+    let path = unit_path(system, system_scope)?;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("failed to create {}", dir.display()))?;
+    }
+    std::fs::write(&path, unit).with_context(|| format!("failed to write {}", path.display()))?;
+
+    systemctl(system_scope, &["daemon-reload"])?;
+    systemctl(system_scope, &["enable", "--now", &unit_name(system)])?;
+    // User units stop with the user's last session unless lingering is on.
+    // Without a name, loginctl enables it for the caller.
+    if !system_scope {
+        let status = Command::new("loginctl")
+            .arg("enable-linger")
+            .status()
+            .context("failed to run loginctl")?;
+        if !status.success() {
+            eprintln!(
+                "could not enable lingering; {} stops when you log out",
+                system.display_name()
+            );
+        }
+    }
+
+    println!("installed {}", path.display());
+    println!(
+        "{} now runs as {}; `rum down` stops it until the next boot",
+        system.display_name(),
+        unit_name(system)
+    );
+    Ok(())
+}
+
+fn uninstall(system: &SystemConfig, system_scope: bool) -> anyhow::Result<()> {
+    let path = unit_path(system, system_scope)?;
+    if !path.exists() {
+        bail!("no unit installed at {}", path.display());
+    }
+    systemctl(system_scope, &["disable", "--now", &unit_name(system)])?;
+    std::fs::remove_file(&path).with_context(|| format!("failed to remove {}", path.display()))?;
+    systemctl(system_scope, &["daemon-reload"])?;
+    println!("removed {}", path.display());
+    Ok(())
+}
+
+/// The unit file running the daemon for `config_path` with `exe`. System
+/// units run as `user`, so the daemon finds the same work dir as the CLI.
+fn render_unit(
+    display_name: &str,
+    config_path: &Path,
+    profile: Option<&str>,
+    exe: &Path,
+    user: Option<&str>,
+) -> anyhow::Result<String> {
+    let config_dir = config_path
+        .parent()
+        .context("config path has no parent directory")?;
+    let config_name = config_path
+        .file_name()
+        .context("config path has no file name")?;
+
+    let mut unit = String::from("[Unit]\n");
+    unit.push_str(&format!(
+        "Description=rum machine {}\n",
+        escape_specifiers(display_name)?
+    ));
+    if user.is_some() {
+        unit.push_str("After=network-online.target libvirtd.service\n");
+        unit.push_str("Wants=network-online.target\n");
+    }
+
+    unit.push_str("\n[Service]\n");
+    if let Some(user) = user {
+        unit.push_str(&format!("User={user}\n"));
+    }
+    unit.push_str(&format!(
+        "WorkingDirectory={}\n",
+        escape_specifiers(utf8(config_dir.as_os_str())?)?
+    ));
+    unit.push_str(&format!(
+        "Environment={}\n",
+        quote_word(&format!("{INTERNAL_DAEMON_CONFIG}={}", utf8(config_name)?))?
+    ));
+    if let Some(profile) = profile {
+        unit.push_str(&format!(
+            "Environment={}\n",
+            quote_word(&format!("{PROFILE_ENV}={profile}"))?
+        ));
+    }
+    // `$` expands variables in command lines only.
+    let exe = quote_word(utf8(exe.as_os_str())?)?.replace('$', "$$");
+    unit.push_str(&format!("ExecStart={exe} daemon\n"));
+    // The daemon exits cleanly after `rum down`; only crashes are restarted.
+    unit.push_str("Restart=on-failure\n");
+    unit.push_str("RestartSec=5\n");
+
+    unit.push_str("\n[Install]\n");
+    let target = if user.is_some() {
+        "multi-user.target"
+    } else {
+        "default.target"
+    };
+    unit.push_str(&format!("WantedBy={target}\n"));
+    Ok(unit)
+}
+
+fn utf8(value: &OsStr) -> anyhow::Result<&str> {
+    value
+        .to_str()
+        .with_context(|| format!("{} is not valid UTF-8", value.to_string_lossy()))
+}
+
+/// `value` with systemd's `%` specifiers escaped. A unit setting cannot span
+/// lines, so line breaks are refused.
+fn escape_specifiers(value: &str) -> anyhow::Result<String> {
+    if value.contains(['\n', '\r']) {
+        bail!("{value:?} cannot be written into a systemd unit");
+    }
+    Ok(value.replace('%', "%%"))
+}
+
+/// `value` as one double-quoted word of a unit setting.
+fn quote_word(value: &str) -> anyhow::Result<String> {
+    let escaped = escape_specifiers(value)?
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"");
+    Ok(format!("\"{escaped}\""))
+}
+
+fn systemctl(system_scope: bool, args: &[&str]) -> anyhow::Result<()> {
+    let mut command = Command::new("systemctl");
+    if !system_scope {
+        command.arg("--user");
+    }
+    let status = command
+        .args(args)
+        .status()
+        .context("failed to run systemctl")?;
+    if !status.success() {
+        bail!("systemctl {} failed with {status}", args.join(" "));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn user_unit_runs_the_daemon_for_the_config() {
+        let unit = render_unit(
+            "dev",
+            Path::new("/home/me/project/dev.rum.toml"),
+            Some("ci"),
+            Path::new("/usr/bin/rum"),
+            None,
+        )
+        .unwrap();
+        assert!(unit.contains("Description=rum machine dev\n"));
+        assert!(unit.contains("WorkingDirectory=/home/me/project\n"));
+        assert!(unit.contains(&format!(
+            "Environment=\"{INTERNAL_DAEMON_CONFIG}=dev.rum.toml\"\n"
+        )));
+        assert!(unit.contains(&format!("Environment=\"{PROFILE_ENV}=ci\"\n")));
+        assert!(unit.contains("ExecStart=\"/usr/bin/rum\" daemon\n"));
+        assert!(unit.contains("WantedBy=default.target\n"));
+        assert!(!unit.contains("User="));
+        assert!(!unit.contains("After="));
+    }
+
+    #[test]
+    fn system_unit_runs_as_the_user() {
+        let unit = render_unit(
+            "dev",
+            Path::new("/srv/dev.rum.toml"),
+            None,
+            Path::new("/usr/bin/rum"),
+            Some("alice"),
+        )
+        .unwrap();
+        assert!(unit.contains("User=alice\n"));
+        assert!(unit.contains("After=network-online.target libvirtd.service\n"));
+        assert!(unit.contains("WantedBy=multi-user.target\n"));
+        assert!(!unit.contains(PROFILE_ENV));
+    }
+
+    #[test]
+    fn unit_escapes_specifiers_quotes_and_variables() {
+        let unit = render_unit(
+            "100%",
+            Path::new("/home/me/50% \"done\"/rum.toml"),
+            None,
+            Path::new("/opt/$HOME\\bin/rum"),
+            None,
+        )
+        .unwrap();
+        assert!(unit.contains("Description=rum machine 100%%\n"));
+        assert!(unit.contains("WorkingDirectory=/home/me/50%% \"done\"\n"));
+        assert!(unit.contains("ExecStart=\"/opt/$$HOME\\\\bin/rum\" daemon\n"));
+    }
+
+    #[test]
+    fn unit_refuses_line_breaks() {
+        let err = render_unit(
+            "dev",
+            Path::new("/home/me/a\nb/rum.toml"),
+            None,
+            Path::new("/usr/bin/rum"),
+            None,
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("systemd unit"), "{err}");
+        assert!(quote_word("A=b\rc").is_err());
+    }
+
+    #[test]
+    fn service_user_prefers_the_explicit_name() {
+        assert_eq!(service_user(Some("alice")).unwrap(), "alice");
+        assert!(service_user(Some("al ice")).is_err());
+        assert!(service_user(Some("")).is_err());
+        assert!(service_user(Some("%i")).is_err());
+    }
+}
@@ -61,14 +61,22 @@ fn handle_destroy_request(
         trigger.event().client_id,
         DestroyResponse { accepted: true },
     );
+    destroy_instance(&mut commands, phase, instance.driver());
+}
 
+/// Destroy the managed machine and exit the daemon. A running machine is
+/// shut down first and purged once it has stopped.
+pub(crate) fn destroy_instance(
+    commands: &mut Commands,
+    phase: Option<InstancePhase>,
+    driver: LibvirtDriver,
+) {
     match phase {
-        Some(InstancePhase::Running) => {
+        Some(InstancePhase::Running | InstancePhase::Unhealthy) => {
             commands.insert_resource(crate::server::DestroyRequested(true));
             commands.send_msg(OrchestratorMessage::RequestShutdown);
         }
         _ => {
-            let driver = instance.driver();
             commands.spawn_empty().spawn_task(move |task| async move {
                 match driver.destroy().await {
                     Ok(()) => {
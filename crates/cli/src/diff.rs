@@ -0,0 +1,28 @@
+use machine::config::{SystemConfig, applied_config, diff_configs};
+
+/// Print how the config differs from the one the machine was started with,
+/// grouped by how each change is applied.
+pub fn run(system: &SystemConfig) -> anyhow::Result<()> {
+    let name = system.display_name();
+    let path = machine::paths::applied_config_path(&system.id, system.name.as_deref());
+    let Some(applied) = applied_config(&path) else {
+        println!("{name} has no recorded config yet; it is saved the next time it starts");
+        return Ok(());
+    };
+
+    let mut changes = diff_configs(&applied, &system.config);
+    if changes.is_empty() {
+        println!("no changes since {name} was started");
+        return Ok(());
+    }
+    changes.sort_by_key(|change| change.apply);
+    let mut current = None;
+    for change in &changes {
+        if current != Some(change.apply) {
+            current = Some(change.apply);
+            println!("{}:", change.apply.hint());
+        }
+        println!("  {}: {}", change.key, change.detail);
+    }
+    Ok(())
+}
@@ -0,0 +1,95 @@
+use ecsdk::prelude::*;
+use ecsdk::tasks::SpawnTask;
+use machine::config::{SocketForward, SystemConfig};
+use machine::driver::LibvirtDriver;
+use orchestrator::instance::instance_phase::{Crashed, Running, ShuttingDown};
+use orchestrator::{HealthMonitor, ManagedInstance};
+use tokio::task::JoinHandle;
+
+/// Server-side plugin that forwards the `[docker]` engine's API socket to
+/// the host while the managed instance is running.
+pub struct DockerSocketPlugin;
+
+impl Plugin for DockerSocketPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<DockerSocket>();
+        app.add_observer(start_on_running);
+        app.add_observer(stop_on_shutdown);
+        app.add_observer(stop_on_crash);
+    }
+}
+
+/// The forwarded socket and the task accepting its connections.
+#[derive(Resource, Default)]
+pub struct DockerSocket(Option<(SocketForward, JoinHandle<()>)>);
+
+impl DockerSocket {
+    /// Stop accepting connections and remove the host socket.
+    fn stop(&mut self) {
+        let Some((forward, handle)) = self.0.take() else {
+            return;
+        };
+        handle.abort();
+        if let Err(error) = std::fs::remove_file(&forward.host) {
+            tracing::debug!(%error, path = %forward.host.display(), "failed to remove docker socket");
+        }
+    }
+}
+
+fn start_on_running(
+    _trigger: On<Add, Running>,
+    // Back from Unhealthy, the socket is still forwarded.
+    instances: Query<&ManagedInstance<LibvirtDriver>, Without<HealthMonitor>>,
+    mut commands: Commands,
+) {
+    if let Some(instance) = instances.iter().next() {
+        start(&mut commands, instance.driver());
+    }
+}
+
+fn start(commands: &mut Commands, driver: LibvirtDriver) {
+    let Some(forward) = driver.system().docker_forward() else {
+        return;
+    };
+    commands.spawn_empty().spawn_task(move |task| async move {
+        let started = driver
+            .get_vsock_cid()
+            .and_then(|cid| machine::guest::start_socket_forward(cid, &forward));
+        let handle = match started {
+            Ok(handle) => handle,
+            Err(error) => {
+                tracing::warn!(%error, "failed to forward the docker socket");
+                return;
+            }
+        };
+        tracing::info!(
+            host = %forward.host.display(),
+            guest = forward.guest,
+            "forwarding docker socket"
+        );
+        task.queue_cmd_wake(move |world: &mut World| {
+            let mut socket = world.resource_mut::<DockerSocket>();
+            if let Some((_, previous)) = socket.0.replace((forward, handle)) {
+                previous.abort();
+            }
+        });
+    });
+}
+
+/// Forward the socket the `[docker]` of `system` asks for from now on.
+pub fn reconfigure(world: &mut World, system: &SystemConfig) {
+    let Some(mut socket) = world.get_resource_mut::<DockerSocket>() else {
+        return;
+    };
+    socket.stop();
+    start(&mut world.commands(), LibvirtDriver::new(system.clone()));
+}
+
+fn stop_on_shutdown(_trigger: On<Add, ShuttingDown>, mut socket: ResMut<DockerSocket>) {
+    socket.stop();
+}
+
+/// A crashed guest has no engine left to forward to.
+fn stop_on_crash(_trigger: On<Add, Crashed>, mut socket: ResMut<DockerSocket>) {
+    socket.stop();
+}
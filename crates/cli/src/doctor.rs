@@ -0,0 +1,29 @@
+use machine::doctor::{CheckStatus, run_checks};
+
+/// Run the host-wide `rum doctor` command.
+///
+/// Fails when any check reports a hard failure; warnings only print hints.
+pub fn run(libvirt_uri: &str) -> anyhow::Result<()> {
+    let checks = run_checks(libvirt_uri);
+    let width = checks.iter().map(|c| c.name.len()).max().unwrap_or(0);
+    for check in &checks {
+        println!(
+            "[{:<4}] {:<width$}  {}",
+            check.status.label(),
+            check.name,
+            check.detail
+        );
+        if let Some(hint) = &check.hint {
+            println!("       {:<width$}  hint: {hint}", "");
+        }
+    }
+
+    let failed = checks
+        .iter()
+        .filter(|c| c.status == CheckStatus::Fail)
+        .count();
+    if failed > 0 {
+        anyhow::bail!("{failed} check(s) failed");
+    }
+    Ok(())
+}
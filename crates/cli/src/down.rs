@@ -1,47 +1,137 @@
 use ecsdk::app::AsyncApp;
+use ecsdk::network::{InitialConnection, IsomorphicPlugin};
 use ecsdk::prelude::*;
-use orchestrator::OrchestratorMessage;
+use orchestrator::{InstancePhase, OrchestratorMessage};
 
 use crate::exit;
-use crate::protocol::{DownRequest, DownResponse};
+use crate::protocol::{CancelWorkRequest, CancelWorkResponse, DownRequest, DownResponse};
+use crate::render::RenderStyle;
 
 /// Isomorphic request feature that lets a client ask the daemon to shut down
-/// the managed machine.
+/// the managed machine, or to cancel the lifecycle work in flight.
 pub struct DownFeature;
 
-impl RequestPlugin for DownFeature {
-    type Request = DownRequest;
-    type Trigger = ecsdk::network::InitialConnection;
-
-    fn auto_register_client() -> bool {
-        false
+impl IsomorphicPlugin for DownFeature {
+    fn build_shared(&self, app: &mut App) {
+        DownRequest::register(app);
+        CancelWorkRequest::register(app);
     }
 
-    fn build_server(app: &mut App) {
+    fn build_server(&self, app: &mut App) {
         app.add_observer(handle_down_request);
+        app.add_observer(handle_cancel_work_request);
     }
 
-    fn build_client(app: &mut App) {
+    fn build_client(&self, app: &mut App) {
         app.add_observer(handle_down_response);
-        app.add_observer(exit::on_stopped);
-        app.add_observer(exit::on_failed);
+        app.add_observer(handle_cancel_work_response);
         app.add_systems(Update, exit::on_server_disconnect);
     }
 }
 
+/// Client request state used to send the down request on the initial daemon
+/// connection.
+#[derive(Resource, Clone)]
+struct PendingDownRequest(DownRequest);
+
 /// Build the client app used by `rum down`.
-pub fn build_down_client(mut app: AsyncApp<OrchestratorMessage>) -> AsyncApp<OrchestratorMessage> {
-    DownFeature::register_client(&mut app);
+pub fn build_down_client(app: AsyncApp<OrchestratorMessage>) -> AsyncApp<OrchestratorMessage> {
+    build_down_client_with(app, false)
+}
+
+/// Build a `rum down` client; with `force`, the daemon abandons a boot or
+/// provisioning run in flight instead of waiting for it to finish.
+pub fn build_down_client_with(
+    mut app: AsyncApp<OrchestratorMessage>,
+    force: bool,
+) -> AsyncApp<OrchestratorMessage> {
+    app.insert_resource(PendingDownRequest(DownRequest { force }));
+    app.add_observer(send_down_request_on_connect);
+    app.add_observer(exit::on_stopped);
+    app.add_observer(exit::on_failed);
     app
 }
 
+/// Build the client app used by `rum cancel`: the daemon abandons the boot or
+/// provisioning run in flight and stops the machine, and the client exits
+/// once it has stopped.
+pub fn build_cancel_client(
+    mut app: AsyncApp<OrchestratorMessage>,
+) -> AsyncApp<OrchestratorMessage> {
+    app.add_observer(send_cancel_request_on_connect);
+    app.add_observer(exit::on_stopped);
+    app.add_observer(exit::on_failed);
+    app
+}
+
+fn send_cancel_request_on_connect(_trigger: On<Add, InitialConnection>, mut commands: Commands) {
+    commands.client_trigger(CancelWorkRequest);
+}
+
+fn send_down_request_on_connect(
+    _trigger: On<Add, InitialConnection>,
+    request: Res<PendingDownRequest>,
+    mut commands: Commands,
+) {
+    commands.client_trigger(request.0.clone());
+}
+
 fn handle_down_request(trigger: On<FromClient<DownRequest>>, mut commands: Commands) {
     DownRequest::reply(
         &mut commands,
         trigger.event().client_id,
         DownResponse { accepted: true },
     );
-    commands.send_msg(OrchestratorMessage::RequestShutdown);
+    if trigger.event().message.force {
+        commands.send_msg(OrchestratorMessage::ForceStop);
+    } else {
+        commands.send_msg(OrchestratorMessage::RequestShutdown);
+    }
+}
+
+fn handle_cancel_work_request(
+    trigger: On<FromClient<CancelWorkRequest>>,
+    phases: Query<&InstancePhase>,
+    mut commands: Commands,
+) {
+    let response = match phases.iter().next() {
+        Some(phase) if phase.is_in_flight() => {
+            commands.send_msg(OrchestratorMessage::ForceStop);
+            CancelWorkResponse {
+                cancelled: true,
+                message: None,
+            }
+        }
+        Some(phase) => CancelWorkResponse {
+            cancelled: false,
+            message: Some(format!(
+                "instance is {}; nothing is in progress",
+                phase.label()
+            )),
+        },
+        None => CancelWorkResponse {
+            cancelled: false,
+            message: Some("no managed instance was found".into()),
+        },
+    };
+    CancelWorkRequest::reply(&mut commands, trigger.event().client_id, response);
+}
+
+fn handle_cancel_work_response(
+    trigger: On<CancelWorkResponse>,
+    style: Option<Res<RenderStyle>>,
+    mut exit: MessageWriter<AppExit>,
+) {
+    let response = trigger.event();
+    let style = style.as_deref().copied().unwrap_or_default();
+    if response.cancelled {
+        tracing::info!("cancel request accepted");
+        return;
+    }
+    if let Some(message) = response.message.as_deref() {
+        eprintln!("nothing to cancel: {}", style.text(message));
+    }
+    exit.write(AppExit::from_code(1));
 }
 
 fn handle_down_response(trigger: On<DownResponse>) {
@@ -0,0 +1,153 @@
+use std::collections::BTreeMap;
+
+use machine::config::SystemConfig;
+use machine::driver::LibvirtDriver;
+
+/// Syntax `rum env` prints its variables in.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default)]
+pub enum EnvShell {
+    #[default]
+    Bash,
+    Zsh,
+    Fish,
+    /// One JSON object mapping variable names to values.
+    Json,
+}
+
+/// Print exports that point local tooling at the running machine, for
+/// `eval "$(rum env)"`.
+pub async fn run(system: &SystemConfig, shell: EnvShell) -> anyhow::Result<()> {
+    let driver = LibvirtDriver::new(system.clone());
+    let ip = driver.guest_ip(None, false).await?;
+    let ssh_key = driver.layout().ssh_key_path.display().to_string();
+    let vars = variables(system, &ip, &ssh_key);
+
+    match shell {
+        EnvShell::Bash | EnvShell::Zsh => {
+            for (name, value) in &vars {
+                println!("export {name}={}", posix_quote(value));
+            }
+            println!("# Run this command to configure your shell:");
+            println!("# eval \"$(rum env)\"");
+        }
+        EnvShell::Fish => {
+            for (name, value) in &vars {
+                println!("set -gx {name} {};", fish_quote(value));
+            }
+            println!("# Run this command to configure your shell:");
+            println!("# rum env --shell fish | source");
+        }
+        EnvShell::Json => {
+            let map: BTreeMap<String, String> = vars.into_iter().collect();
+            println!("{}", facet_json::to_string(&map));
+        }
+    }
+    Ok(())
+}
+
+/// The variables for a machine reachable at `ip`, in the order printed.
+///
+/// `DOCKER_HOST` is the forwarded API of the `[docker]` engine, or else the
+/// guest over ssh, which relies on ssh finding the key, e.g. through an
+/// entry from `rum ssh-config --install`.
+fn variables(system: &SystemConfig, ip: &str, ssh_key: &str) -> Vec<(String, String)> {
+    let user = &system.config.ssh.user;
+    let docker_host = system
+        .docker_host()
+        .unwrap_or_else(|| format!("ssh://{user}@{ip}"));
+    let mut vars = vec![
+        ("RUM_VM_NAME".to_string(), system.display_name().to_string()),
+        ("RUM_VM_IP".to_string(), ip.to_string()),
+        ("RUM_SSH_USER".to_string(), user.clone()),
+        ("RUM_SSH_KEY".to_string(), ssh_key.to_string()),
+        ("DOCKER_HOST".to_string(), docker_host),
+    ];
+    // A guest port forwarded more than once gets `_2`, `_3`, ... on the
+    // names after the first, in config order.
+    let mut seen: BTreeMap<u16, usize> = BTreeMap::new();
+    for port in &system.config.ports {
+        let count = seen.entry(port.guest).or_default();
+        *count += 1;
+        let name = match *count {
+            1 => format!("RUM_PORT_{}", port.guest),
+            n => format!("RUM_PORT_{}_{n}", port.guest),
+        };
+        vars.push((name, format!("{}:{}", port.bind_addr(), port.host)));
+    }
+    vars
+}
+
+/// `value` in single quotes for bash and zsh.
+fn posix_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+/// `value` in single quotes for fish, which escapes `\` and `'` inside them.
+fn fish_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\\', r"\\").replace('\'', r"\'"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn posix_quotes_close_around_single_quotes() {
+        assert_eq!(posix_quote("plain"), "'plain'");
+        assert_eq!(posix_quote("it's $HOME"), r"'it'\''s $HOME'");
+        assert_eq!(posix_quote(""), "''");
+    }
+
+    #[test]
+    fn fish_quotes_escape_backslashes_and_single_quotes() {
+        assert_eq!(fish_quote("plain"), "'plain'");
+        assert_eq!(fish_quote(r"a\b'c"), r"'a\\b\'c'");
+    }
+
+    #[test]
+    fn variables_name_every_forward_of_a_guest_port() {
+        let dir = std::env::temp_dir().join(format!("rum-env-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("rum.toml");
+        std::fs::write(
+            &path,
+            r#"
+[image]
+base = "ubuntu.qcow2"
+
+[resources]
+cpus = 1
+memory_mb = 512
+
+[[ports]]
+host = 8080
+guest = 80
+
+[[ports]]
+host = 9090
+guest = 80
+bind = "0.0.0.0"
+
+[[ports]]
+host = 8443
+guest = 443
+"#,
+        )
+        .unwrap();
+        let system = machine::config::load_config_with_profile(&path, None).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let vars: BTreeMap<String, String> = variables(&system, "192.168.122.10", "/keys/id")
+            .into_iter()
+            .collect();
+        assert_eq!(vars["RUM_VM_IP"], "192.168.122.10");
+        assert_eq!(vars["RUM_SSH_KEY"], "/keys/id");
+        assert_eq!(
+            vars["DOCKER_HOST"],
+            format!("ssh://{}@192.168.122.10", system.config.ssh.user)
+        );
+        assert_eq!(vars["RUM_PORT_80"], "127.0.0.1:8080");
+        assert_eq!(vars["RUM_PORT_80_2"], "0.0.0.0:9090");
+        assert_eq!(vars["RUM_PORT_443"], "127.0.0.1:8443");
+    }
+}
@@ -0,0 +1,540 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use ecsdk::app::AsyncApp;
+use ecsdk::prelude::*;
+use facet::Facet;
+use machine::instance::InstanceState;
+use orchestrator::{
+    EntityError, InstanceLabel, InstancePhase, OrchestratorMessage, ProvisionLogEntry,
+    ProvisionLogView, RecoveredState,
+};
+
+/// Layout version of the `rum events --output json` records. Bumped when a
+/// field is renamed, removed or changes meaning; new fields keep it.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// Output format of `rum events`.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum EventsOutput {
+    #[default]
+    Plain,
+    /// One JSON object per line.
+    Json,
+}
+
+/// One line of `rum events --output json`.
+#[derive(Facet)]
+struct EventRecord {
+    schema_version: u32,
+    /// Milliseconds since the Unix epoch when the event reached the client.
+    ts: u64,
+    /// `transition`, `step`, `log`, `progress` or `result`.
+    kind: &'static str,
+    instance: String,
+    /// Phase after a `transition`, the phase a `step` failed in, or the
+    /// final phase in the `result`.
+    phase: Option<&'static str>,
+    /// Phase before a `transition`; absent for the first one.
+    from: Option<&'static str>,
+    /// Recovered state of the machine, e.g. `running`.
+    state: Option<&'static str>,
+    /// Script or command a `log` line or `progress` record belongs to.
+    source: Option<String>,
+    /// Text of a `log` line.
+    line: Option<String>,
+    /// Why a `step` failed, or the error the `result` ended with.
+    error: Option<EventError>,
+    /// Exit status of `rum events`, only in the `result`.
+    exit_code: Option<i32>,
+}
+
+impl EventRecord {
+    fn new(kind: &'static str, instance: &str) -> Self {
+        Self {
+            schema_version: SCHEMA_VERSION,
+            ts: SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .map(|d| d.as_millis() as u64)
+                .unwrap_or_default(),
+            kind,
+            instance: instance.to_string(),
+            phase: None,
+            from: None,
+            state: None,
+            source: None,
+            line: None,
+            error: None,
+            exit_code: None,
+        }
+    }
+
+    /// A `transition` into `phase`.
+    fn transition(
+        instance: &str,
+        phase: InstancePhase,
+        from: Option<InstancePhase>,
+        state: Option<InstanceState>,
+    ) -> Self {
+        let mut record = Self::new("transition", instance);
+        record.phase = Some(phase_id(phase));
+        record.from = from.map(phase_id);
+        record.state = state.map(state_id);
+        record
+    }
+
+    /// A `step` that failed in `phase`.
+    fn step(instance: &str, phase: Option<InstancePhase>, error: &EntityError) -> Self {
+        let mut record = Self::new("step", instance);
+        record.phase = phase.map(phase_id);
+        record.error = Some(error.into());
+        record
+    }
+
+    /// `progress` into the output of `source` while in `phase`.
+    fn progress(instance: &str, phase: InstancePhase, source: &str) -> Self {
+        let mut record = Self::new("progress", instance);
+        record.phase = Some(phase_id(phase));
+        record.source = Some(source.to_string());
+        record
+    }
+
+    /// A `log` line of `source`.
+    fn log(instance: &str, source: &str, line: &str) -> Self {
+        let mut record = Self::new("log", instance);
+        record.source = Some(source.to_string());
+        record.line = Some(line.to_string());
+        record
+    }
+
+    fn to_json(&self) -> String {
+        facet_json::to_string(self)
+    }
+
+    fn print(&self) {
+        println!("{}", self.to_json());
+    }
+}
+
+#[derive(Facet)]
+struct EventError {
+    message: String,
+    /// Stable code, e.g. `RUM-AGENT-TIMEOUT`.
+    code: Option<String>,
+    hint: Option<String>,
+}
+
+impl From<&EntityError> for EventError {
+    fn from(error: &EntityError) -> Self {
+        Self {
+            message: error.message.clone(),
+            code: error.code.clone(),
+            hint: error.hint.clone(),
+        }
+    }
+}
+
+#[derive(Resource, Clone, Copy)]
+struct EventsOptions {
+    output: EventsOutput,
+    follow: bool,
+}
+
+/// Shared slot the events client fills with its exit status: `1` when the
+/// machine ended up failed, `0` otherwise.
+#[derive(Resource, Clone, Default)]
+pub struct EventsOutcome(pub Arc<Mutex<Option<i32>>>);
+
+impl EventsOutcome {
+    pub fn exit_code(&self) -> Option<i32> {
+        *self.0.lock().expect("events outcome lock poisoned")
+    }
+}
+
+/// Build the client app used by `rum events`.
+///
+/// Without `follow` the client exits once it has printed the current state;
+/// with it, once the daemon goes away. Either way it ends with a `result`.
+pub fn build_events_client(
+    mut app: AsyncApp<OrchestratorMessage>,
+    output: EventsOutput,
+    follow: bool,
+    outcome: EventsOutcome,
+) -> AsyncApp<OrchestratorMessage> {
+    app.insert_resource(EventsOptions { output, follow });
+    app.insert_resource(outcome);
+    app.init_resource::<SeenEvents>();
+    app.add_systems(Update, (print_events, finish_on_disconnect).chain());
+    app
+}
+
+/// The replicated components whose changes `rum events` reports.
+type EventSources = Or<(
+    Changed<InstancePhase>,
+    Changed<RecoveredState>,
+    Changed<EntityError>,
+    Changed<ProvisionLogView>,
+)>;
+
+#[derive(Resource, Default)]
+struct SeenEvents {
+    recovered: HashMap<Entity, InstanceState>,
+    phase: HashMap<Entity, InstancePhase>,
+    /// Last phase before `Failed`, the step an error belongs to.
+    active_phase: HashMap<Entity, InstancePhase>,
+    error: HashMap<Entity, EntityError>,
+    log_count: HashMap<Entity, usize>,
+    log_source: HashMap<Entity, String>,
+    /// The instance as last seen, for the `result`.
+    last: Option<Snapshot>,
+    finished: bool,
+}
+
+#[derive(Clone)]
+struct Snapshot {
+    instance: String,
+    phase: InstancePhase,
+    state: Option<InstanceState>,
+    error: Option<EntityError>,
+}
+
+#[allow(clippy::type_complexity)]
+fn print_events(
+    query: Query<
+        (
+            Entity,
+            Option<&InstanceLabel>,
+            Option<&RecoveredState>,
+            &InstancePhase,
+            Option<&EntityError>,
+            Option<&ProvisionLogView>,
+        ),
+        (Without<ecsdk::network::InitialConnection>, EventSources),
+    >,
+    log_entries: Query<&ProvisionLogEntry>,
+    options: Res<EventsOptions>,
+    outcome: Res<EventsOutcome>,
+    mut seen: ResMut<SeenEvents>,
+    mut exit: MessageWriter<AppExit>,
+) {
+    if seen.finished {
+        return;
+    }
+    let json = options.output == EventsOutput::Json;
+    let mut printed_any = false;
+    for (entity, label, recovered, phase, error, log_view) in &query {
+        let instance = label.map(|label| label.0.as_str()).unwrap_or("instance");
+        let state = recovered.map(|recovered| **recovered);
+
+        let state_changed =
+            state.is_some_and(|state| seen.recovered.insert(entity, state) != Some(state));
+        let from = seen.phase.insert(entity, *phase);
+        let phase_changed = from != Some(*phase);
+        if *phase != InstancePhase::Failed {
+            seen.active_phase.insert(entity, *phase);
+        }
+        if !json {
+            if let Some(state) = state.filter(|_| state_changed) {
+                println!("{instance}: recovered: {state}");
+            }
+            if phase_changed {
+                println!("{instance}: phase: {}", phase.label());
+            }
+        } else if state_changed || phase_changed {
+            EventRecord::transition(instance, *phase, from, state).print();
+        }
+
+        if let Some(error) = error
+            && seen.error.insert(entity, error.clone()).as_ref() != Some(error)
+        {
+            if json {
+                let active = seen.active_phase.get(&entity).copied();
+                EventRecord::step(instance, active, error).print();
+            } else {
+                println!("{instance}: error: {error}");
+            }
+        }
+
+        if let Some(log_view) = log_view {
+            let count = seen.log_count.get(&entity).copied().unwrap_or_default();
+            for entry_entity in log_view.iter().skip(count) {
+                let Ok(entry) = log_entries.get(entry_entity) else {
+                    continue;
+                };
+                if !json {
+                    println!("{instance}: log: {} | {}", entry.label, entry.message);
+                    continue;
+                }
+                if seen.log_source.get(&entity) != Some(&entry.label) {
+                    seen.log_source.insert(entity, entry.label.clone());
+                    EventRecord::progress(instance, *phase, &entry.label).print();
+                }
+                EventRecord::log(instance, &entry.label, &entry.message).print();
+            }
+            seen.log_count.insert(entity, log_view.iter().len());
+        }
+
+        seen.last = Some(Snapshot {
+            instance: instance.to_string(),
+            phase: *phase,
+            state,
+            error: error.cloned(),
+        });
+        printed_any = true;
+    }
+
+    if printed_any && !options.follow {
+        finish(&mut seen, options.output, &outcome);
+        exit.write(AppExit::Success);
+    }
+}
+
+fn finish_on_disconnect(
+    mut disconnects: MessageReader<ServerDisconnected>,
+    options: Res<EventsOptions>,
+    outcome: Res<EventsOutcome>,
+    mut seen: ResMut<SeenEvents>,
+    mut exit: MessageWriter<AppExit>,
+) {
+    if disconnects.read().next().is_some() {
+        finish(&mut seen, options.output, &outcome);
+        exit.write(AppExit::Success);
+    }
+}
+
+/// Print the `result` once and record the exit status.
+fn finish(seen: &mut SeenEvents, output: EventsOutput, outcome: &EventsOutcome) {
+    if std::mem::replace(&mut seen.finished, true) {
+        return;
+    }
+    let (record, exit_code) = result_record(seen.last.as_ref());
+    *outcome.0.lock().expect("events outcome lock poisoned") = Some(exit_code);
+    if output == EventsOutput::Json {
+        record.print();
+    }
+}
+
+/// The `result` for the instance as last seen, and the exit status it implies:
+/// 1 when it ended `failed` or `crashed`, or was never seen at all.
+fn result_record(last: Option<&Snapshot>) -> (EventRecord, i32) {
+    let exit_code = match last {
+        Some(last) if !matches!(last.phase, InstancePhase::Failed | InstancePhase::Crashed) => 0,
+        _ => 1,
+    };
+    let mut record = EventRecord::new(
+        "result",
+        last.map_or("instance", |last| last.instance.as_str()),
+    );
+    if let Some(last) = last {
+        record.phase = Some(phase_id(last.phase));
+        record.state = last.state.map(state_id);
+        record.error = last.error.as_ref().map(EventError::from);
+    }
+    record.exit_code = Some(exit_code);
+    (record, exit_code)
+}
+
+/// Stable name of a phase in JSON records.
+fn phase_id(phase: InstancePhase) -> &'static str {
+    match phase {
+        InstancePhase::Recovering => "recovering",
+        InstancePhase::Preparing => "preparing",
+        InstancePhase::Booting => "booting",
+        InstancePhase::ConnectingGuest => "connecting_guest",
+        InstancePhase::Provisioning => "provisioning",
+        InstancePhase::Running => "running",
+        InstancePhase::Unhealthy => "unhealthy",
+        InstancePhase::Crashed => "crashed",
+        InstancePhase::ShuttingDown => "shutting_down",
+        InstancePhase::Stopped => "stopped",
+        InstancePhase::Failed => "failed",
+    }
+}
+
+/// Stable name of a recovered state in JSON records.
+fn state_id(state: InstanceState) -> &'static str {
+    match state {
+        InstanceState::Missing => "missing",
+        InstanceState::ImageCached => "image_cached",
+        InstanceState::Prepared => "prepared",
+        InstanceState::PartialBoot => "partial_boot",
+        InstanceState::Stopped => "stopped",
+        InstanceState::Running => "running",
+        InstanceState::StaleConfig => "stale_config",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// What a consumer of `rum events --output json` reads back.
+    #[derive(Facet, Debug)]
+    struct Parsed {
+        schema_version: u32,
+        ts: u64,
+        kind: String,
+        instance: String,
+        phase: Option<String>,
+        from: Option<String>,
+        state: Option<String>,
+        source: Option<String>,
+        line: Option<String>,
+        error: Option<ParsedError>,
+        exit_code: Option<i32>,
+    }
+
+    #[derive(Facet, Debug)]
+    struct ParsedError {
+        message: String,
+        code: Option<String>,
+        hint: Option<String>,
+    }
+
+    fn parse(record: &EventRecord) -> Parsed {
+        facet_json::from_str(&record.to_json()).expect("record parses")
+    }
+
+    #[test]
+    fn transition_records_carry_both_phases_and_the_state() {
+        let parsed = parse(&EventRecord::transition(
+            "dev",
+            InstancePhase::ConnectingGuest,
+            Some(InstancePhase::Booting),
+            Some(InstanceState::PartialBoot),
+        ));
+        assert_eq!(parsed.schema_version, SCHEMA_VERSION);
+        assert!(parsed.ts > 0);
+        assert_eq!(parsed.kind, "transition");
+        assert_eq!(parsed.instance, "dev");
+        assert_eq!(parsed.phase.as_deref(), Some("connecting_guest"));
+        assert_eq!(parsed.from.as_deref(), Some("booting"));
+        assert_eq!(parsed.state.as_deref(), Some("partial_boot"));
+        assert!(parsed.error.is_none());
+        assert!(parsed.exit_code.is_none());
+    }
+
+    #[test]
+    fn first_transition_has_no_from() {
+        let parsed = parse(&EventRecord::transition(
+            "dev",
+            InstancePhase::Recovering,
+            None,
+            None,
+        ));
+        assert_eq!(parsed.phase.as_deref(), Some("recovering"));
+        assert!(parsed.from.is_none());
+        assert!(parsed.state.is_none());
+    }
+
+    #[test]
+    fn step_records_carry_the_error() {
+        let error = EntityError {
+            message: "agent did not answer".into(),
+            code: Some("RUM-AGENT-TIMEOUT".into()),
+            hint: Some("check the console".into()),
+        };
+        let parsed = parse(&EventRecord::step(
+            "dev",
+            Some(InstancePhase::Provisioning),
+            &error,
+        ));
+        assert_eq!(parsed.kind, "step");
+        assert_eq!(parsed.phase.as_deref(), Some("provisioning"));
+        let error = parsed.error.expect("step has an error");
+        assert_eq!(error.message, "agent did not answer");
+        assert_eq!(error.code.as_deref(), Some("RUM-AGENT-TIMEOUT"));
+        assert_eq!(error.hint.as_deref(), Some("check the console"));
+
+        let parsed = parse(&EventRecord::step(
+            "dev",
+            None,
+            &EntityError::message("boom"),
+        ));
+        let error = parsed.error.expect("step has an error");
+        assert_eq!(error.message, "boom");
+        assert!(error.code.is_none());
+        assert!(parsed.phase.is_none());
+    }
+
+    #[test]
+    fn progress_and_log_records_name_their_source() {
+        let parsed = parse(&EventRecord::progress(
+            "dev",
+            InstancePhase::Provisioning,
+            "setup.sh",
+        ));
+        assert_eq!(parsed.kind, "progress");
+        assert_eq!(parsed.phase.as_deref(), Some("provisioning"));
+        assert_eq!(parsed.source.as_deref(), Some("setup.sh"));
+        assert!(parsed.line.is_none());
+
+        let parsed = parse(&EventRecord::log("dev", "setup.sh", "installing \"git\""));
+        assert_eq!(parsed.kind, "log");
+        assert_eq!(parsed.source.as_deref(), Some("setup.sh"));
+        assert_eq!(parsed.line.as_deref(), Some("installing \"git\""));
+        assert!(parsed.phase.is_none());
+    }
+
+    #[test]
+    fn names_are_stable_snake_case() {
+        assert_eq!(phase_id(InstancePhase::ShuttingDown), "shutting_down");
+        assert_eq!(phase_id(InstancePhase::Failed), "failed");
+        assert_eq!(state_id(InstanceState::ImageCached), "image_cached");
+        assert_eq!(state_id(InstanceState::StaleConfig), "stale_config");
+    }
+
+    fn snapshot(phase: InstancePhase, error: Option<EntityError>) -> Snapshot {
+        Snapshot {
+            instance: "dev".into(),
+            phase,
+            state: Some(InstanceState::Running),
+            error,
+        }
+    }
+
+    #[test]
+    fn result_of_a_running_instance_exits_zero() {
+        let (record, exit_code) = result_record(Some(&snapshot(InstancePhase::Running, None)));
+        assert_eq!(exit_code, 0);
+        let parsed = parse(&record);
+        assert_eq!(parsed.schema_version, SCHEMA_VERSION);
+        assert_eq!(parsed.kind, "result");
+        assert_eq!(parsed.instance, "dev");
+        assert_eq!(parsed.phase.as_deref(), Some("running"));
+        assert_eq!(parsed.state.as_deref(), Some("running"));
+        assert_eq!(parsed.exit_code, Some(0));
+        assert!(parsed.error.is_none());
+    }
+
+    #[test]
+    fn result_of_a_failed_or_crashed_instance_exits_one() {
+        let failed = snapshot(
+            InstancePhase::Failed,
+            Some(EntityError::message("boot timed out")),
+        );
+        let (record, exit_code) = result_record(Some(&failed));
+        assert_eq!(exit_code, 1);
+        let parsed = parse(&record);
+        assert_eq!(parsed.phase.as_deref(), Some("failed"));
+        assert_eq!(parsed.exit_code, Some(1));
+        assert_eq!(
+            parsed.error.expect("result has the error").message,
+            "boot timed out"
+        );
+
+        let (_, exit_code) = result_record(Some(&snapshot(InstancePhase::Crashed, None)));
+        assert_eq!(exit_code, 1);
+    }
+
+    #[test]
+    fn result_without_an_instance_exits_one() {
+        let (record, exit_code) = result_record(None);
+        assert_eq!(exit_code, 1);
+        let parsed = parse(&record);
+        assert_eq!(parsed.instance, "instance");
+        assert!(parsed.phase.is_none());
+        assert_eq!(parsed.exit_code, Some(1));
+    }
+}
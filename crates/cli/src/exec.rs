@@ -1,14 +1,18 @@
+use std::sync::{Arc, Mutex};
+
 use ecsdk::app::AsyncApp;
 use ecsdk::network::{InitialConnection, IsomorphicPlugin};
 use ecsdk::prelude::*;
 use ecsdk::tasks::SpawnTask;
+use guest::agent::{EnvVar, ExecOptions};
 use machine::driver::LibvirtDriver;
-use machine::guest::VsockConnector;
 use orchestrator::{
     LogBuffer, ManagedInstance, OrchestratorMessage, ProvisionLogView,
 };
 
+use crate::agent::SharedAgent;
 use crate::protocol::{ExecRequest, ExecResponse};
+use crate::render::RenderStyle;
 
 /// Shared request feature for daemon-backed guest command execution.
 pub struct ExecFeature;
@@ -19,6 +23,7 @@ impl IsomorphicPlugin for ExecFeature {
     }
 
     fn build_server(&self, app: &mut App) {
+        app.init_resource::<SharedAgent>();
         app.add_observer(handle_exec_request);
     }
 
@@ -33,26 +38,76 @@ impl IsomorphicPlugin for ExecFeature {
 #[derive(Resource, Clone)]
 struct PendingExecRequest(ExecRequest);
 
-pub fn prepare_request(command: &[String]) -> anyhow::Result<ExecRequest> {
+/// Shared slot the exec client fills with the guest exit code, for callers
+/// that keep going after the exec app finishes (e.g. `rum run`).
+#[derive(Resource, Clone, Default)]
+pub struct ExecOutcome(pub Arc<Mutex<Option<i32>>>);
+
+impl ExecOutcome {
+    pub fn exit_code(&self) -> Option<i32> {
+        *self.0.lock().expect("exec outcome lock poisoned")
+    }
+}
+
+pub fn prepare_request(
+    command: &[String],
+    user: Option<String>,
+    workdir: Option<String>,
+    env: &[String],
+    timeout_s: Option<u64>,
+) -> anyhow::Result<ExecRequest> {
     if command.is_empty() {
         anyhow::bail!("missing command")
     }
 
+    let env = env
+        .iter()
+        .map(|pair| parse_env_pair(pair))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
     Ok(ExecRequest {
         command: Some(command.join(" ")),
+        user,
+        workdir,
+        env,
+        timeout_s,
+        execution_id: crate::cancel::new_execution_id(),
     })
 }
 
+/// Split a `KEY=VAL` argument into its name and value.
+fn parse_env_pair(pair: &str) -> anyhow::Result<(String, String)> {
+    let Some((name, value)) = pair.split_once('=') else {
+        anyhow::bail!("invalid --env '{pair}': expected KEY=VAL")
+    };
+    if name.is_empty() {
+        anyhow::bail!("invalid --env '{pair}': variable name is empty")
+    }
+    Ok((name.to_string(), value.to_string()))
+}
+
 /// Build the client app used by `rum exec`.
 pub fn build_exec_client(
     mut app: AsyncApp<OrchestratorMessage>,
     request: ExecRequest,
 ) -> AsyncApp<OrchestratorMessage> {
+    crate::cancel::cancel_on_ctrl_c(&mut app, request.execution_id.clone());
     app.insert_resource(PendingExecRequest(request));
     app.add_observer(send_exec_request_on_connect);
     app
 }
 
+/// Build an exec client that also reports the exit code through `outcome`.
+pub fn build_exec_client_with_outcome(
+    app: AsyncApp<OrchestratorMessage>,
+    request: ExecRequest,
+    outcome: ExecOutcome,
+) -> AsyncApp<OrchestratorMessage> {
+    let mut app = build_exec_client(app, request);
+    app.insert_resource(outcome);
+    app
+}
+
 fn send_exec_request_on_connect(
     _trigger: On<Add, InitialConnection>,
     request: Res<PendingExecRequest>,
@@ -66,6 +121,7 @@ fn handle_exec_request(
     instances: Query<(Entity, &ManagedInstance<LibvirtDriver>)>,
     views: Query<&ProvisionLogView>,
     mut buffers: Query<&mut LogBuffer>,
+    agent: Res<SharedAgent>,
     mut commands: Commands,
 ) {
     let Some((instance_entity, instance)) = instances.iter().next() else {
@@ -81,7 +137,8 @@ fn handle_exec_request(
         return;
     };
 
-    let Some(command) = trigger.event().message.command.clone() else {
+    let request = &trigger.event().message;
+    let Some(command) = request.command.clone() else {
         ExecRequest::reply(
             &mut commands,
             trigger.event().client_id,
@@ -103,7 +160,23 @@ fn handle_exec_request(
         }
     }
 
+    let options = ExecOptions {
+        user: request.user.clone(),
+        cwd: request.workdir.clone(),
+        env: request
+            .env
+            .iter()
+            .map(|(name, value)| EnvVar {
+                name: name.clone(),
+                value: value.clone(),
+            })
+            .collect(),
+        timeout_s: request.timeout_s,
+        execution_id: Some(request.execution_id.clone()),
+    };
+
     let driver = instance.driver();
+    let agent = agent.clone();
     let client_id = trigger.event().client_id;
     commands.spawn_empty().spawn_task(move |task| async move {
         let log_task = task.clone();
@@ -115,7 +188,7 @@ fn handle_exec_request(
             });
         };
 
-        let response = match run_exec(driver, command, on_output).await {
+        let response = match run_exec(&agent, driver, command, options, on_output).await {
             Ok(exit_code) => ExecResponse {
                 success: exit_code == 0,
                 exit_code,
@@ -136,30 +209,36 @@ fn handle_exec_request(
 }
 
 async fn run_exec<F>(
+    agent: &SharedAgent,
     driver: LibvirtDriver,
     command: String,
+    options: ExecOptions,
     on_output: F,
 ) -> Result<i32, String>
 where
     F: Fn(String) + Send + Sync,
 {
-    let cid = driver
-        .get_vsock_cid()
-        .map_err(|error| format!("guest connection is not ready: {error}"))?;
-    let client = guest::client::wait_for_agent(VsockConnector::new(cid))
-        .await
-        .map_err(|error| format!("failed to connect to guest agent: {error}"))?;
+    let client = agent.client(&driver).await?;
 
     client
-        .exec_with_output(command, move |event| on_output(event.message))
+        .exec_with_output(command, options, move |event| on_output(event.message))
         .await
         .map_err(|error| error.to_string())
 }
 
-fn handle_exec_response(trigger: On<ExecResponse>, mut exit: MessageWriter<AppExit>) {
+fn handle_exec_response(
+    trigger: On<ExecResponse>,
+    outcome: Option<Res<ExecOutcome>>,
+    style: Option<Res<RenderStyle>>,
+    mut exit: MessageWriter<AppExit>,
+) {
     let response = trigger.event();
+    let style = style.as_deref().copied().unwrap_or_default();
+    if let Some(outcome) = outcome {
+        *outcome.0.lock().expect("exec outcome lock poisoned") = Some(response.exit_code);
+    }
     if let Some(message) = response.message.as_deref() {
-        eprintln!("{message}");
+        eprintln!("{}", style.error(&style.text(message)));
     }
 
     if response.success {
@@ -1,5 +1,5 @@
 use ecsdk::prelude::*;
-use orchestrator::instance::instance_phase::{Failed, Running, Stopped};
+use orchestrator::instance::instance_phase::{Crashed, Failed, Running, Stopped};
 use orchestrator::EntityError;
 
 /// Exit the local client when the daemon disconnects.
@@ -39,9 +39,29 @@ pub fn on_failed(
 ) {
     let entity = trigger.event_target();
     if let Ok(error) = errors.get(entity) {
-        tracing::error!(entity = entity.index().index(), error = %error.0, "managed instance failed");
+        tracing::error!(entity = entity.index().index(), error = %error, "managed instance failed");
     } else {
         tracing::error!(entity = entity.index().index(), "managed instance failed");
     }
     exit.write(AppExit::Success);
 }
+
+/// Exit with a failure once the managed instance crashes. The daemon keeps
+/// it around for `rum restart` or `rum down`.
+pub fn on_crashed(
+    trigger: On<Add, Crashed>,
+    errors: Query<&EntityError>,
+    mut exit: MessageWriter<AppExit>,
+) {
+    let entity = trigger.event_target();
+    if let Ok(error) = errors.get(entity) {
+        tracing::error!(
+            entity = entity.index().index(),
+            error = %error,
+            "managed instance crashed"
+        );
+    } else {
+        tracing::error!(entity = entity.index().index(), "managed instance crashed");
+    }
+    exit.write(AppExit::from_code(1));
+}
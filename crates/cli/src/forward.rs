@@ -0,0 +1,63 @@
+use anyhow::{Context, bail};
+use machine::config::{PortForward, SystemConfig};
+use machine::driver::LibvirtDriver;
+
+/// Parse a `[BIND:]HOST:GUEST` forward spec such as `8080:80`,
+/// `0.0.0.0:5432:5432` or `[::1]:8080:80`.
+pub fn parse_spec(spec: &str) -> anyhow::Result<PortForward> {
+    let mut parts = spec.rsplitn(3, ':');
+    let (Some(guest), Some(host)) = (parts.next(), parts.next()) else {
+        bail!("invalid forward '{spec}'; expected [BIND:]HOST:GUEST");
+    };
+    let bind = match parts.next() {
+        Some(bind) => bind
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .to_string(),
+        None => "127.0.0.1".to_string(),
+    };
+    let forward = PortForward {
+        host: host
+            .parse()
+            .with_context(|| format!("invalid host port in '{spec}'"))?,
+        guest: guest
+            .parse()
+            .with_context(|| format!("invalid guest port in '{spec}'"))?,
+        bind,
+    };
+    if forward.host == 0 || forward.guest == 0 {
+        bail!("invalid forward '{spec}'; ports must be > 0");
+    }
+    if forward.socket_addr().is_none() {
+        bail!("invalid forward '{spec}'; bind must be localhost or an IP address");
+    }
+    Ok(forward)
+}
+
+/// Forward host ports into the running guest until Ctrl+C.
+///
+/// Uses the same vsock forwarding as `[[ports]]`, but nothing is written to
+/// the config and the daemon is not involved.
+pub async fn run(system: &SystemConfig, specs: &[String]) -> anyhow::Result<()> {
+    let ports = specs
+        .iter()
+        .map(|spec| parse_spec(spec))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    let cid = LibvirtDriver::new(system.clone()).get_vsock_cid()?;
+    let handles = machine::guest::start_port_forwards(cid, &ports).await?;
+    for pf in &ports {
+        if let Some(addr) = pf.socket_addr() {
+            println!("forwarding {addr} -> guest:{}", pf.guest);
+        }
+    }
+    println!("press Ctrl+C to stop");
+
+    tokio::signal::ctrl_c()
+        .await
+        .context("failed to wait for Ctrl+C")?;
+    for handle in handles {
+        handle.abort();
+    }
+    Ok(())
+}
@@ -0,0 +1,64 @@
+use anyhow::{Context, bail};
+use guest::agent::ServiceAction;
+use guest::client::Client;
+use machine::config::SystemConfig;
+use machine::driver::LibvirtDriver;
+use machine::guest::AgentConnector;
+
+/// Subcommands of `rum service`.
+#[derive(clap::Subcommand, Clone, Debug)]
+pub enum ServiceCmd {
+    /// Start a guest service.
+    Start { name: String },
+    /// Stop a guest service.
+    Stop { name: String },
+    /// Restart a guest service.
+    Restart { name: String },
+    /// Reload a guest service's configuration.
+    Reload { name: String },
+    /// Start a guest service on every boot.
+    Enable { name: String },
+    /// No longer start a guest service on boot.
+    Disable { name: String },
+    /// Show the status of one guest service, or list all of them.
+    Status { name: Option<String> },
+}
+
+/// Manage systemd services of the running machine through the guest agent.
+pub async fn run(system: &SystemConfig, cmd: ServiceCmd) -> anyhow::Result<()> {
+    let connector = LibvirtDriver::new(system.clone())
+        .agent_connector()
+        .context("the machine is not running")?;
+    let client = Client::connect(connector);
+
+    let (name, action) = match cmd {
+        ServiceCmd::Status { name: None } => return list(&client).await,
+        ServiceCmd::Status { name: Some(name) } => (name, ServiceAction::Status),
+        ServiceCmd::Start { name } => (name, ServiceAction::Start),
+        ServiceCmd::Stop { name } => (name, ServiceAction::Stop),
+        ServiceCmd::Restart { name } => (name, ServiceAction::Restart),
+        ServiceCmd::Reload { name } => (name, ServiceAction::Reload),
+        ServiceCmd::Enable { name } => (name, ServiceAction::Enable),
+        ServiceCmd::Disable { name } => (name, ServiceAction::Disable),
+    };
+    let result = client.service_action(&name, action).await?;
+    print!("{}", result.output);
+    if result.exit_code != 0 {
+        bail!(
+            "systemctl {} {name} exited with status {}",
+            action.verb(),
+            result.exit_code
+        );
+    }
+    Ok(())
+}
+
+async fn list(client: &Client<AgentConnector>) -> anyhow::Result<()> {
+    for service in client.list_services().await? {
+        println!(
+            "{:<40} {:<8} {:<10} {}",
+            service.name, service.active, service.sub, service.description
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,133 @@
+use std::os::unix::fs::MetadataExt;
+use std::time::SystemTime;
+
+use machine::audit::{self, AuditEntry};
+use machine::config::SystemConfig;
+use machine::driver::{LibvirtDriver, RecoverableDriver};
+
+/// Output format of `rum history`.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum HistoryOutput {
+    #[default]
+    Plain,
+    /// One JSON object per line, as stored in `audit.jsonl`.
+    Json,
+}
+
+/// Append the outcome of a command to the audit log of `system`. `result` is
+/// the command's result and process exit code.
+pub fn record(
+    system: &SystemConfig,
+    flow: &str,
+    started: SystemTime,
+    result: &anyhow::Result<i32>,
+) {
+    let state = LibvirtDriver::new(system.clone())
+        .recover()
+        .map(|state| state.to_string())
+        .unwrap_or_else(|_| "unknown".into());
+    let entry = AuditEntry {
+        at: started
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default(),
+        user: invoking_user(),
+        pid: std::process::id(),
+        command: std::env::args().collect::<Vec<_>>().join(" "),
+        flow: flow.to_string(),
+        config_hash: audit::config_hash(&system.config_path),
+        duration_ms: started
+            .elapsed()
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or_default(),
+        success: matches!(result, Ok(0)),
+        state,
+        error: result.as_ref().err().map(|error| format!("{error:#}")),
+    };
+    let path = machine::paths::audit_log_path(&system.id, system.name.as_deref());
+    if let Err(error) = audit::append(&path, &entry) {
+        tracing::warn!(%error, "failed to write the audit log");
+    }
+}
+
+/// The account running this process, from its uid rather than `$USER`,
+/// which is unset or spoofable. Under sudo the user who ran it is added.
+fn invoking_user() -> String {
+    let uid = std::fs::metadata("/proc/self").map(|metadata| metadata.uid());
+    let Ok(uid) = uid else {
+        return "unknown".into();
+    };
+    let passwd = std::fs::read_to_string("/etc/passwd").unwrap_or_default();
+    let user = passwd_name(&passwd, uid).map_or_else(|| uid.to_string(), str::to_string);
+    match std::env::var("SUDO_USER") {
+        Ok(sudo_user) if uid == 0 && !sudo_user.is_empty() => format!("{sudo_user} (sudo)"),
+        _ => user,
+    }
+}
+
+/// Name of `uid` in the `/etc/passwd` contents `passwd`.
+fn passwd_name(passwd: &str, uid: u32) -> Option<&str> {
+    passwd.lines().find_map(|line| {
+        let mut fields = line.split(':');
+        let name = fields.next()?;
+        let entry_uid = fields.nth(1)?;
+        (entry_uid.parse() == Ok(uid)).then_some(name)
+    })
+}
+
+/// Print the commands run against `system`, newest last, keeping
+/// only the last `limit` if set.
+pub fn run(
+    system: &SystemConfig,
+    limit: Option<usize>,
+    output: HistoryOutput,
+) -> anyhow::Result<()> {
+    let path = machine::paths::audit_log_path(&system.id, system.name.as_deref());
+    let entries = audit::read(&path);
+    let skip = limit.map_or(0, |limit| entries.len().saturating_sub(limit));
+    for entry in &entries[skip..] {
+        match output {
+            HistoryOutput::Json => println!("{}", facet_json::to_string(entry)),
+            HistoryOutput::Plain => print_entry(entry),
+        }
+    }
+    if entries.is_empty() && output == HistoryOutput::Plain {
+        println!("no commands recorded in {}", path.display());
+    }
+    Ok(())
+}
+
+fn print_entry(entry: &AuditEntry) {
+    let outcome = if entry.success { "ok" } else { "failed" };
+    println!(
+        "{}  {:<10} {:<9} {:<7} {:>7.1}s  {:<13} {}",
+        audit::format_utc(entry.at),
+        entry.user,
+        entry.flow,
+        outcome,
+        entry.duration_ms as f64 / 1000.0,
+        entry.state,
+        entry.command
+    );
+    if let Some(error) = &entry.error {
+        println!("    {error}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn passwd_names_come_from_the_uid_field() {
+        let passwd = "root:x:0:0:root:/root:/bin/bash\n\
+                      # comment\n\
+                      alice:x:1000:1000:Alice:/home/alice:/bin/zsh\n\
+                      bob:x:10000:100::/home/bob:/bin/sh\n";
+        assert_eq!(passwd_name(passwd, 0), Some("root"));
+        assert_eq!(passwd_name(passwd, 1000), Some("alice"));
+        assert_eq!(passwd_name(passwd, 10000), Some("bob"));
+        assert_eq!(passwd_name(passwd, 100), None);
+        assert_eq!(passwd_name("", 0), None);
+    }
+}
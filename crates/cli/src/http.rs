@@ -0,0 +1,627 @@
+//! HTTP control API of the daemon, enabled with `serve.http`.
+//!
+//! A small HTTP/1.1 server for tools that cannot speak the daemon's socket
+//! protocol: one request per connection, JSON bodies, and server-sent events
+//! for the log stream. Every request needs an `Authorization: Bearer <token>`
+//! header carrying the token the daemon writes to `http-token` in its work
+//! dir when it starts. The server is plain HTTP, so validation keeps it on a
+//! loopback address unless `serve.allow_remote` is set.
+//!
+//! - `GET /status`: phase, recovered state and last error.
+//! - `POST /down`: shut the machine down; `?force` forces it off.
+//! - `POST /destroy`: shut the machine down and delete it.
+//! - `POST /exec`: run `{"command": ...}` in the guest and return its output.
+//! - `GET /logs`: provisioning and exec output as server-sent events.
+//! - `GET /ports`: the port forwards the daemon listens on.
+//!
+//! The API only exists while the daemon runs, so there is no `up`: a
+//! successful `GET /status` means the machine is being brought up or is.
+
+use std::io::{Read, Write};
+use std::net::SocketAddr;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use ecsdk::network::InitialConnection;
+use ecsdk::prelude::*;
+use ecsdk::tasks::SpawnTask;
+use facet::Facet;
+use guest::agent::ExecOptions;
+use machine::config::SystemConfig;
+use machine::driver::LibvirtDriver;
+use orchestrator::{
+    EntityError, InstanceLabel, InstancePhase, ManagedInstance, OrchestratorMessage,
+    ProvisionLogEntry, ProvisionLogView, RecoveredState,
+};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, oneshot};
+
+use crate::agent::SharedAgent;
+use crate::ports::ActiveForwards;
+
+const MAX_HEADERS: usize = 64;
+/// Longest request line or header, including its line ending.
+const MAX_LINE: usize = 8 << 10;
+const MAX_BODY: usize = 1 << 20;
+/// How long a client gets to send its request; the read happens before the
+/// token is checked, so anyone who can connect holds a task until then.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+const LOG_POLL: Duration = Duration::from_millis(500);
+
+/// Server-side plugin that serves the HTTP control API on `addr`.
+pub struct HttpApiPlugin {
+    addr: SocketAddr,
+    token_path: PathBuf,
+}
+
+impl HttpApiPlugin {
+    /// The plugin for `system`, if its config sets `serve.http`.
+    pub fn from_system(system: &SystemConfig) -> Option<Self> {
+        let addr = system.config.serve.http.as_deref()?.parse().ok()?;
+        Some(Self {
+            addr,
+            token_path: machine::paths::http_token_path(&system.id, system.name.as_deref()),
+        })
+    }
+}
+
+impl Plugin for HttpApiPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(HttpApiAddr {
+            addr: self.addr,
+            token_path: self.token_path.clone(),
+        });
+        app.add_systems(Startup, start_http_api);
+    }
+}
+
+#[derive(Resource)]
+struct HttpApiAddr {
+    addr: SocketAddr,
+    token_path: PathBuf,
+}
+
+type WorldJob = Box<dyn FnOnce(&mut World) + Send>;
+
+/// Runs closures on the daemon's world for connection handlers, which live
+/// outside of it.
+#[derive(Clone)]
+struct WorldBridge(mpsc::UnboundedSender<WorldJob>);
+
+impl WorldBridge {
+    /// The result of `job` run on the world, or `None` once the daemon is
+    /// exiting.
+    async fn run<T: Send + 'static>(
+        &self,
+        job: impl FnOnce(&mut World) -> T + Send + 'static,
+    ) -> Option<T> {
+        let (tx, rx) = oneshot::channel();
+        self.0
+            .send(Box::new(move |world| {
+                let _ = tx.send(job(world));
+            }))
+            .ok()?;
+        rx.await.ok()
+    }
+}
+
+fn start_http_api(api: Res<HttpApiAddr>, mut commands: Commands) {
+    let (addr, token_path) = (api.addr, api.token_path.clone());
+    commands.spawn_empty().spawn_task(move |task| async move {
+        let (jobs, mut queue) = mpsc::unbounded_channel::<WorldJob>();
+        tokio::spawn(async move {
+            if let Err(error) = serve(addr, &token_path, WorldBridge(jobs)).await {
+                tracing::error!(error = %error, "HTTP control API failed");
+            }
+        });
+        while let Some(job) = queue.recv().await {
+            task.queue_cmd_wake(move |world: &mut World| job(world));
+        }
+    });
+}
+
+async fn serve(addr: SocketAddr, token_path: &Path, bridge: WorldBridge) -> anyhow::Result<()> {
+    let token: Arc<str> = write_token(token_path)?.into();
+    let listener = TcpListener::bind(addr).await?;
+    tracing::info!(%addr, token = %token_path.display(), "HTTP control API listening");
+
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        let (token, bridge) = (token.clone(), bridge.clone());
+        tokio::spawn(async move {
+            if let Err(error) = handle_connection(stream, &token, &bridge).await {
+                tracing::debug!(%peer, error = %error, "http: request failed");
+            }
+        });
+    }
+}
+
+/// Generate a fresh token and save it, readable only by the owner.
+fn write_token(path: &Path) -> anyhow::Result<String> {
+    let mut bytes = [0u8; 32];
+    std::fs::File::open("/dev/urandom")?.read_exact(&mut bytes)?;
+    let token: String = bytes.iter().map(|b| format!("{b:02x}")).collect();
+
+    // The mode only applies to new files.
+    let _ = std::fs::remove_file(path);
+    std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)?
+        .write_all(token.as_bytes())?;
+    Ok(token)
+}
+
+struct Request {
+    method: String,
+    path: String,
+    query: String,
+    authorization: Option<String>,
+    body: Vec<u8>,
+}
+
+async fn read_request<R: AsyncBufRead + Unpin>(reader: &mut R) -> anyhow::Result<Request> {
+    let mut line = String::new();
+    read_line(reader, &mut line).await?;
+    let mut parts = line.split_whitespace();
+    let (Some(method), Some(target), Some(version)) = (parts.next(), parts.next(), parts.next())
+    else {
+        anyhow::bail!("malformed request line");
+    };
+    if !version.starts_with("HTTP/") || parts.next().is_some() {
+        anyhow::bail!("malformed request line");
+    }
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let mut request = Request {
+        method: method.to_string(),
+        path: path.to_string(),
+        query: query.to_string(),
+        authorization: None,
+        body: Vec::new(),
+    };
+
+    let mut content_length = 0;
+    let mut headers = 0;
+    loop {
+        read_line(reader, &mut line).await?;
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        headers += 1;
+        if headers > MAX_HEADERS {
+            anyhow::bail!("more than {MAX_HEADERS} headers");
+        }
+        let Some((name, value)) = header.split_once(':') else {
+            anyhow::bail!("malformed header");
+        };
+        let value = value.trim();
+        if name.eq_ignore_ascii_case("content-length") {
+            content_length = value.parse()?;
+        } else if name.eq_ignore_ascii_case("authorization") {
+            request.authorization = Some(value.to_string());
+        }
+    }
+    if content_length > MAX_BODY {
+        anyhow::bail!("request body of {content_length} bytes is too large");
+    }
+    request.body.resize(content_length, 0);
+    reader.read_exact(&mut request.body).await?;
+    Ok(request)
+}
+
+/// Read one line of at most [`MAX_LINE`] bytes into `line`.
+async fn read_line<R: AsyncBufRead + Unpin>(
+    reader: &mut R,
+    line: &mut String,
+) -> anyhow::Result<()> {
+    line.clear();
+    let read = (&mut *reader).take(MAX_LINE as u64).read_line(line).await?;
+    if !line.ends_with('\n') {
+        if read == MAX_LINE {
+            anyhow::bail!("request line or header is longer than {MAX_LINE} bytes");
+        }
+        anyhow::bail!("connection closed in the middle of the request");
+    }
+    Ok(())
+}
+
+#[derive(Facet)]
+struct ErrorBody {
+    error: String,
+    /// Stable code of the failure, e.g. `RUM-AGENT-TIMEOUT`.
+    code: Option<String>,
+    hint: Option<String>,
+}
+
+#[derive(Facet)]
+struct StatusBody {
+    found: bool,
+    instance: Option<String>,
+    recovered: Option<String>,
+    phase: Option<String>,
+    error: Option<ErrorBody>,
+}
+
+#[derive(Facet)]
+struct AcceptedBody {
+    accepted: bool,
+}
+
+#[derive(Facet, Default)]
+#[facet(default)]
+struct ExecBody {
+    command: String,
+    user: Option<String>,
+    workdir: Option<String>,
+    timeout_s: Option<u64>,
+}
+
+#[derive(Facet)]
+struct ExecResult {
+    exit_code: i32,
+    output: Vec<String>,
+}
+
+#[derive(Facet)]
+struct PortBody {
+    bind: String,
+    host: u16,
+    guest: u16,
+}
+
+#[derive(Facet)]
+struct LogEvent {
+    source: String,
+    line: String,
+}
+
+type Reply = (u16, String);
+
+fn json<'a, T: Facet<'a>>(value: &T) -> Reply {
+    (200, facet_json::to_string(value))
+}
+
+fn error(status: u16, message: impl Into<String>) -> Reply {
+    let body = ErrorBody {
+        error: message.into(),
+        code: None,
+        hint: None,
+    };
+    (status, facet_json::to_string(&body))
+}
+
+async fn handle_connection(
+    stream: TcpStream,
+    token: &str,
+    bridge: &WorldBridge,
+) -> anyhow::Result<()> {
+    let mut reader = BufReader::new(stream);
+    let request = tokio::time::timeout(REQUEST_TIMEOUT, read_request(&mut reader))
+        .await
+        .map_err(|_| {
+            anyhow::anyhow!("no complete request within {}s", REQUEST_TIMEOUT.as_secs())
+        })??;
+    let stream = reader.get_mut();
+
+    let expected = format!("Bearer {token}");
+    let authorized = request
+        .authorization
+        .as_deref()
+        .is_some_and(|given| constant_time_eq(given.as_bytes(), expected.as_bytes()));
+    if !authorized {
+        let (status, body) = error(401, "missing or wrong bearer token");
+        return respond(stream, status, &body).await;
+    }
+
+    if (request.method.as_str(), request.path.as_str()) == ("GET", "/logs") {
+        return stream_logs(stream, bridge).await;
+    }
+    let (status, body) = route(&request, bridge).await;
+    respond(stream, status, &body).await
+}
+
+async fn route(request: &Request, bridge: &WorldBridge) -> Reply {
+    let reply = match (request.method.as_str(), request.path.as_str()) {
+        ("GET", "/status") => bridge.run(status).await,
+        ("POST", "/down") => {
+            let force = request
+                .query
+                .split('&')
+                .any(|param| matches!(param, "force" | "force=1" | "force=true"));
+            bridge.run(move |world| down(world, force)).await
+        }
+        ("POST", "/destroy") => bridge.run(destroy).await,
+        ("POST", "/exec") => Some(exec(&request.body, bridge).await),
+        ("GET", "/ports") => bridge.run(ports).await,
+        (method, path) => Some(error(404, format!("no endpoint {method} {path}"))),
+    };
+    reply.unwrap_or_else(|| error(503, "the daemon is shutting down"))
+}
+
+#[allow(clippy::type_complexity)]
+fn status(world: &mut World) -> Reply {
+    let mut query = world.query_filtered::<(
+        Option<&InstanceLabel>,
+        Option<&RecoveredState>,
+        &InstancePhase,
+        Option<&EntityError>,
+    ), Without<InitialConnection>>();
+    let body = match query.iter(world).next() {
+        Some((label, recovered, phase, error)) => StatusBody {
+            found: true,
+            instance: label.map(|label| label.0.clone()),
+            recovered: recovered.map(|recovered| recovered.0.to_string()),
+            phase: Some(phase.label().to_string()),
+            error: error.map(|error| ErrorBody {
+                error: error.message.clone(),
+                code: error.code.clone(),
+                hint: error.hint.clone(),
+            }),
+        },
+        None => StatusBody {
+            found: false,
+            instance: None,
+            recovered: None,
+            phase: None,
+            error: None,
+        },
+    };
+    json(&body)
+}
+
+fn down(world: &mut World, force: bool) -> Reply {
+    let message = if force {
+        OrchestratorMessage::ForceStop
+    } else {
+        OrchestratorMessage::RequestShutdown
+    };
+    world.commands().send_msg(message);
+    json(&AcceptedBody { accepted: true })
+}
+
+fn destroy(world: &mut World) -> Reply {
+    let mut query = world.query::<(&ManagedInstance<LibvirtDriver>, Option<&InstancePhase>)>();
+    let Some((instance, phase)) = query.iter(world).next() else {
+        return error(404, "no managed instance was found");
+    };
+    let (driver, phase) = (instance.driver(), phase.copied());
+    crate::destroy::destroy_instance(&mut world.commands(), phase, driver);
+    json(&AcceptedBody { accepted: true })
+}
+
+fn ports(world: &mut World) -> Reply {
+    let ports: Vec<PortBody> = world
+        .get_resource::<ActiveForwards>()
+        .map(|forwards| {
+            forwards
+                .0
+                .iter()
+                .map(|forward| PortBody {
+                    bind: forward.spec.bind_addr().to_string(),
+                    host: forward.spec.host,
+                    guest: forward.spec.guest,
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    json(&ports)
+}
+
+async fn exec(body: &[u8], bridge: &WorldBridge) -> Reply {
+    let request: ExecBody = match std::str::from_utf8(body)
+        .map_err(|e| e.to_string())
+        .and_then(|body| facet_json::from_str(body).map_err(|e| e.to_string()))
+    {
+        Ok(request) => request,
+        Err(message) => return error(400, format!("invalid exec request: {message}")),
+    };
+    if request.command.is_empty() {
+        return error(400, "missing command");
+    }
+
+    let target = bridge
+        .run(|world| {
+            let agent = world.get_resource_or_init::<SharedAgent>().clone();
+            let mut query = world.query::<&ManagedInstance<LibvirtDriver>>();
+            let driver = query.iter(world).next()?.driver();
+            Some((agent, driver))
+        })
+        .await;
+    let Some(target) = target else {
+        return error(503, "the daemon is shutting down");
+    };
+    let Some((agent, driver)) = target else {
+        return error(404, "no managed instance was found");
+    };
+    let client = match agent.client(&driver).await {
+        Ok(client) => client,
+        Err(message) => return error(409, message),
+    };
+
+    let options = ExecOptions {
+        user: request.user,
+        cwd: request.workdir,
+        env: Vec::new(),
+        timeout_s: request.timeout_s,
+        execution_id: None,
+    };
+    let output = Arc::new(Mutex::new(Vec::new()));
+    let sink = output.clone();
+    let result = client
+        .exec_with_output(request.command, options, move |event| {
+            sink.lock()
+                .expect("exec output lock poisoned")
+                .push(event.message);
+        })
+        .await;
+    match result {
+        Ok(exit_code) => json(&ExecResult {
+            exit_code,
+            output: std::mem::take(&mut *output.lock().expect("exec output lock poisoned")),
+        }),
+        Err(e) => error(500, e.to_string()),
+    }
+}
+
+/// Send the log lines of the managed instance as server-sent events until
+/// the client goes away or the daemon exits.
+async fn stream_logs(stream: &mut TcpStream, bridge: &WorldBridge) -> anyhow::Result<()> {
+    stream
+        .write_all(
+            b"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\n\
+              Cache-Control: no-cache\r\nConnection: close\r\n\r\n",
+        )
+        .await?;
+
+    let mut seen = 0;
+    loop {
+        let Some((events, count)) = bridge.run(move |world| new_log_lines(world, seen)).await
+        else {
+            return Ok(());
+        };
+        seen = count;
+        for event in events {
+            let data = format!("data: {}\n\n", facet_json::to_string(&event));
+            stream.write_all(data.as_bytes()).await?;
+        }
+        tokio::time::sleep(LOG_POLL).await;
+    }
+}
+
+/// Log lines after the first `seen`, and how many there are in total. The
+/// count starts over when an exec clears the log.
+fn new_log_lines(world: &mut World, seen: usize) -> (Vec<LogEvent>, usize) {
+    let mut views = world.query::<&ProvisionLogView>();
+    let Some(view) = views.iter(world).next() else {
+        return (Vec::new(), 0);
+    };
+    let entries: Vec<Entity> = view.iter().collect();
+    let skip = if entries.len() < seen { 0 } else { seen };
+    let events = entries[skip..]
+        .iter()
+        .filter_map(|&entity| world.get::<ProvisionLogEntry>(entity))
+        .map(|entry| LogEvent {
+            source: entry.label.clone(),
+            line: entry.message.clone(),
+        })
+        .collect();
+    (events, entries.len())
+}
+
+async fn respond(stream: &mut TcpStream, status: u16, body: &str) -> anyhow::Result<()> {
+    let reason = match status {
+        200 => "OK",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        404 => "Not Found",
+        409 => "Conflict",
+        503 => "Service Unavailable",
+        _ => "Internal Server Error",
+    };
+    let head = format!(
+        "HTTP/1.1 {status} {reason}\r\nContent-Type: application/json\r\n\
+         Content-Length: {}\r\nConnection: close\r\n\r\n",
+        body.len()
+    );
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(body.as_bytes()).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn parse(raw: &[u8]) -> anyhow::Result<Request> {
+        read_request(&mut BufReader::new(raw)).await
+    }
+
+    #[tokio::test]
+    async fn parses_a_request_with_a_body() {
+        let request = parse(
+            b"POST /down?force HTTP/1.1\r\nHost: localhost\r\n\
+              Authorization: Bearer abc\r\nContent-Length: 2\r\n\r\n{}",
+        )
+        .await
+        .unwrap();
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.path, "/down");
+        assert_eq!(request.query, "force");
+        assert_eq!(request.authorization.as_deref(), Some("Bearer abc"));
+        assert_eq!(request.body, b"{}");
+    }
+
+    #[tokio::test]
+    async fn rejects_oversized_requests() {
+        let mut long_line = b"GET /".to_vec();
+        long_line.resize(MAX_LINE * 4, b'a');
+        long_line.extend_from_slice(b" HTTP/1.1\r\n\r\n");
+        let err = parse(&long_line).await.unwrap_err().to_string();
+        assert!(err.contains("longer than"), "{err}");
+
+        let mut long_header = b"GET /status HTTP/1.1\r\nX-Pad: ".to_vec();
+        long_header.resize(MAX_LINE * 4, b'a');
+        long_header.extend_from_slice(b"\r\n\r\n");
+        assert!(parse(&long_header).await.is_err());
+
+        let mut many_headers = b"GET /status HTTP/1.1\r\n".to_vec();
+        for i in 0..=MAX_HEADERS {
+            many_headers.extend_from_slice(format!("X-{i}: 1\r\n").as_bytes());
+        }
+        many_headers.extend_from_slice(b"\r\n");
+        let err = parse(&many_headers).await.unwrap_err().to_string();
+        assert!(err.contains("headers"), "{err}");
+
+        let body = format!(
+            "POST /exec HTTP/1.1\r\nContent-Length: {}\r\n\r\n",
+            MAX_BODY + 1
+        );
+        let err = parse(body.as_bytes()).await.unwrap_err().to_string();
+        assert!(err.contains("too large"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn rejects_truncated_requests() {
+        let err = parse(b"GET /status HTTP/1.1")
+            .await
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("closed"), "{err}");
+        assert!(parse(b"GET /status HTTP/1.1\r\nHost: x\r\n").await.is_err());
+        assert!(
+            parse(b"POST /exec HTTP/1.1\r\nContent-Length: 10\r\n\r\n{}")
+                .await
+                .is_err()
+        );
+        assert!(parse(b"").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn rejects_malformed_requests() {
+        for raw in [
+            &b"GET\r\n\r\n"[..],
+            b"GET /status\r\n\r\n",
+            b"GET /status SMTP\r\n\r\n",
+            b"GET /status HTTP/1.1 extra\r\n\r\n",
+            b"GET /status HTTP/1.1\r\nno colon here\r\n\r\n",
+            b"POST /exec HTTP/1.1\r\nContent-Length: lots\r\n\r\n",
+            b"GET /\xff HTTP/1.1\r\n\r\n",
+        ] {
+            assert!(
+                parse(raw).await.is_err(),
+                "{}",
+                String::from_utf8_lossy(raw)
+            );
+        }
+    }
+}
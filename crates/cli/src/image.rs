@@ -0,0 +1,101 @@
+use std::path::PathBuf;
+
+use machine::image::{clear_cache, delete_cached, export_cached, import_cached, list_cached};
+use machine::paths;
+use machine::registry::{SearchFilter, search};
+
+/// Output format of `rum image search`.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default)]
+pub enum SearchOutput {
+    #[default]
+    Table,
+    Json,
+}
+
+/// Subcommands of `rum image`.
+#[derive(clap::Subcommand, Clone, Debug)]
+pub enum ImageCmd {
+    /// List cached base images.
+    List,
+    /// Delete one cached image by file name.
+    Delete { name: String },
+    /// Delete every cached image.
+    Clear,
+    /// Add an image to the cache from a tarball or a plain image file.
+    Import {
+        /// Tarball created by `rum image export`, or an image file.
+        path: PathBuf,
+        /// File name to store the image under. Defaults to the original name.
+        #[arg(long)]
+        name: Option<String>,
+    },
+    /// Search the configured image registries.
+    Search {
+        /// Distribution, e.g. `ubuntu` or `fedora`.
+        #[arg(long)]
+        distro: Option<String>,
+        /// Release codename or version, e.g. `noble` or `24.04`.
+        #[arg(long)]
+        release: Option<String>,
+        /// Architecture, e.g. `x86_64` (`amd64`) or `aarch64` (`arm64`).
+        #[arg(long)]
+        arch: Option<String>,
+        /// Re-fetch registry catalogs instead of using the cached copies.
+        #[arg(long)]
+        refresh: bool,
+        #[arg(long, value_enum, default_value_t)]
+        output: SearchOutput,
+    },
+    /// Pack a cached image into a tarball for another machine or CI cache.
+    Export {
+        /// Cached image file name, as shown by `rum image list`.
+        name: String,
+        /// Destination tarball path.
+        path: PathBuf,
+    },
+}
+
+/// Run a host-wide `rum image` subcommand against the image cache.
+pub async fn run(cmd: ImageCmd) -> anyhow::Result<()> {
+    let cache_dir = paths::cache_dir();
+    match cmd {
+        ImageCmd::List => list_cached(&cache_dir)?,
+        ImageCmd::Delete { name } => delete_cached(&cache_dir, &name)?,
+        ImageCmd::Clear => clear_cache(&cache_dir)?,
+        ImageCmd::Import { path, name } => import_cached(&cache_dir, &path, name.as_deref())?,
+        ImageCmd::Export { name, path } => export_cached(&cache_dir, &name, &path)?,
+        ImageCmd::Search {
+            distro,
+            release,
+            arch,
+            refresh,
+            output,
+        } => {
+            let filter = SearchFilter {
+                distro,
+                release,
+                arch,
+            };
+            print_results(&search(&filter, refresh).await?, output);
+        }
+    }
+    Ok(())
+}
+
+fn print_results(entries: &[machine::registry::ImageEntry], output: SearchOutput) {
+    match output {
+        SearchOutput::Json => println!("{}", facet_json::to_string(&entries.to_vec())),
+        SearchOutput::Table => {
+            if entries.is_empty() {
+                println!("no matching images");
+                return;
+            }
+            for entry in entries {
+                println!(
+                    "{:<8} {:<10} {:<16} {:<8} {}",
+                    entry.distro, entry.release, entry.title, entry.arch, entry.url
+                );
+            }
+        }
+    }
+}
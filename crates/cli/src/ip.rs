@@ -0,0 +1,43 @@
+use facet::Facet;
+use machine::config::SystemConfig;
+use machine::driver::LibvirtDriver;
+
+/// Output format of `rum ip`.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default)]
+pub enum IpOutput {
+    #[default]
+    Plain,
+    Json,
+}
+
+#[derive(Facet)]
+struct IpReport {
+    name: String,
+    /// Network of the NIC the address belongs to; `None` for the default NIC.
+    interface: Option<String>,
+    ip: String,
+}
+
+/// Print the guest IP address that `rum ssh` would connect to.
+pub async fn run(
+    system: &SystemConfig,
+    interface: Option<&str>,
+    ipv6: bool,
+    output: IpOutput,
+) -> anyhow::Result<()> {
+    let ip = LibvirtDriver::new(system.clone())
+        .guest_ip(interface, ipv6)
+        .await?;
+    match output {
+        IpOutput::Plain => println!("{ip}"),
+        IpOutput::Json => {
+            let report = IpReport {
+                name: system.display_name().to_string(),
+                interface: interface.map(str::to_string),
+                ip,
+            };
+            println!("{}", facet_json::to_string(&report));
+        }
+    }
+    Ok(())
+}
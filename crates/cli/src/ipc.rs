@@ -8,6 +8,13 @@ use interprocess::local_socket::{
 use interprocess::local_socket::ToFsName as _;
 use interprocess::local_socket::traits::tokio::Stream as _;
 
+/// Set for the daemon process; names the config file in its working
+/// directory.
+pub const INTERNAL_DAEMON_CONFIG: &str = "RUM_INTERNAL_DAEMON_CONFIG";
+
+/// Set for a daemon started by `rum up --resume`.
+pub const INTERNAL_DAEMON_RESUME: &str = "RUM_INTERNAL_DAEMON_RESUME";
+
 /// Convert a filesystem socket path into an interprocess socket name.
 pub fn socket_name(path: &Path) -> Name<'static> {
     path.to_string_lossy().into_owned().to_fs_name::<GenericFilePath>().unwrap()
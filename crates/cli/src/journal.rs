@@ -0,0 +1,47 @@
+use std::path::PathBuf;
+
+use ecsdk::prelude::*;
+use machine::driver::LibvirtDriver;
+use machine::journal::{self, JournalEntry};
+use orchestrator::{InstancePhase, ManagedInstance, ResolvedBaseImage};
+
+/// Server-side plugin that appends every lifecycle phase of the managed
+/// instance to its journal.
+pub struct JournalPlugin;
+
+impl Plugin for JournalPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Update, record_phase_changes);
+    }
+}
+
+#[allow(clippy::type_complexity)]
+fn record_phase_changes(
+    instances: Query<
+        (
+            &ManagedInstance<LibvirtDriver>,
+            &InstancePhase,
+            Option<&ResolvedBaseImage>,
+        ),
+        Changed<InstancePhase>,
+    >,
+) {
+    for (instance, phase, base_image) in &instances {
+        let system = instance.driver().system();
+        let path = machine::paths::journal_path(&system.id, system.name.as_deref());
+        let mut entry = JournalEntry::now(phase.label());
+        entry.base_image = base_image.map(|image| image.0.display().to_string());
+        if let Err(error) = journal::append(&path, &entry) {
+            tracing::warn!(%error, "failed to write lifecycle journal");
+        }
+    }
+}
+
+/// The base image journaled by an interrupted run of `system`, if it still
+/// exists.
+pub fn resumable_base_image(system: &machine::config::SystemConfig) -> Option<PathBuf> {
+    let path = machine::paths::journal_path(&system.id, system.name.as_deref());
+    journal::interrupted_run(&path)?
+        .base_image
+        .filter(|image| image.is_file())
+}
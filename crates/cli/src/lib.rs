@@ -1,16 +1,54 @@
+pub mod agent;
 pub mod app;
+pub mod cancel;
+pub mod ci;
+pub mod clean;
 pub mod client;
+pub mod config;
+pub mod console;
 pub mod cp;
 pub mod control;
+pub mod daemon_unit;
 pub mod destroy;
+pub mod diff;
+pub mod docker;
+pub mod doctor;
 pub mod down;
+pub mod env;
+pub mod events;
 pub mod exec;
 pub mod exit;
+pub mod forward;
+pub mod guest_service;
+pub mod history;
+pub mod http;
+pub mod image;
+pub mod ip;
 pub mod ipc;
+pub mod journal;
+pub mod list;
 pub mod log;
+pub mod logging;
+pub mod mdns;
+pub mod mount;
+pub mod mount_sync;
+pub mod net;
 pub mod network;
+pub mod notify;
+pub mod peers;
+pub mod plan;
+pub mod ports;
 pub mod protocol;
+pub mod provision;
+pub mod reboot;
+pub mod reload;
 pub mod render;
+pub mod resize;
 pub mod restart;
 pub mod server;
+pub mod ssh_config;
 pub mod status;
+pub mod sync;
+pub mod test;
+pub mod tunnel;
+pub mod view;
@@ -0,0 +1,66 @@
+use machine::driver::{DomainSummary, list_domains};
+
+/// One `--filter` expression accepted by `rum list`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Filter {
+    /// `label=KEY` matches domains carrying the label with any value.
+    LabelPresent(String),
+    /// `label=KEY=VALUE` matches domains whose label has exactly that value.
+    LabelEquals(String, String),
+}
+
+impl Filter {
+    fn parse(raw: &str) -> anyhow::Result<Self> {
+        let Some(selector) = raw.strip_prefix("label=") else {
+            anyhow::bail!("unsupported filter '{raw}': expected label=KEY or label=KEY=VALUE")
+        };
+        match selector.split_once('=') {
+            Some((key, value)) if !key.is_empty() => {
+                Ok(Self::LabelEquals(key.to_string(), value.to_string()))
+            }
+            None if !selector.is_empty() => Ok(Self::LabelPresent(selector.to_string())),
+            _ => anyhow::bail!("invalid filter '{raw}': label key is empty"),
+        }
+    }
+
+    fn matches(&self, summary: &DomainSummary) -> bool {
+        match self {
+            Self::LabelPresent(key) => summary.labels.contains_key(key),
+            Self::LabelEquals(key, value) => summary.labels.get(key) == Some(value),
+        }
+    }
+}
+
+/// Run the local `rum list` command against the libvirt host.
+///
+/// Every filter must match for a domain to be listed.
+pub fn run(libvirt_uri: &str, filters: &[String]) -> anyhow::Result<()> {
+    let filters = filters
+        .iter()
+        .map(|raw| Filter::parse(raw))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    let domains = list_domains(libvirt_uri)?;
+    for summary in domains
+        .iter()
+        .filter(|summary| filters.iter().all(|f| f.matches(summary)))
+    {
+        let state = if summary.running {
+            "running"
+        } else {
+            "stopped"
+        };
+        let labels = summary
+            .labels
+            .iter()
+            .map(|(key, value)| format!("{key}={value}"))
+            .collect::<Vec<_>>()
+            .join(",");
+        println!(
+            "{:<24} {:<8} {:<8} {labels}",
+            summary.name, summary.id, state
+        );
+    }
+
+    Ok(())
+}
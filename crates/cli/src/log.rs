@@ -1,6 +1,13 @@
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
 use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 use anyhow::Context;
+use facet::Facet;
+use guest::agent::JournalFilter;
+use guest::client::{Client, LogStamp, stamps_path};
 use machine::config::SystemConfig;
 use machine::driver::LibvirtDriver;
 
@@ -10,21 +17,76 @@ pub enum LogSelection {
     Latest,
     LatestFailed,
     List,
+    /// The newest log, then every script log as it is written.
+    Follow,
 }
 
+/// Output format of `rum log`.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LogOutput {
+    #[default]
+    Plain,
+    /// One JSON object per line.
+    Json,
+}
+
+/// One script log line printed by `rum log --output json`.
+#[derive(Facet)]
+struct LogRecord {
+    /// Milliseconds since the Unix epoch when the line was written; unset
+    /// for logs of older rum versions.
+    at_ms: Option<u64>,
+    script: String,
+    /// `stdout` or `stderr`; unset for logs of older rum versions.
+    stream: Option<String>,
+    line: String,
+}
+
+const FOLLOW_POLL: Duration = Duration::from_millis(250);
+
 /// Run the local `rum log` command against the current instance work directory.
-pub fn run(system: &SystemConfig, selection: LogSelection) -> anyhow::Result<()> {
+pub async fn run(
+    system: &SystemConfig,
+    selection: LogSelection,
+    output: LogOutput,
+) -> anyhow::Result<()> {
     let logs_dir = LibvirtDriver::new(system.clone()).layout().logs_dir.clone();
 
     match selection {
         LogSelection::List => list_logs(&logs_dir),
-        LogSelection::Latest => print_latest_log(&logs_dir, false),
-        LogSelection::LatestFailed => print_latest_log(&logs_dir, true),
+        LogSelection::Latest => print_latest_log(&logs_dir, false, output),
+        LogSelection::LatestFailed => print_latest_log(&logs_dir, true, output),
+        LogSelection::Follow => {
+            follow_logs(&logs_dir, &crate::ipc::socket_path(system), output).await
+        }
     }
 }
 
+/// Print the guest's system log, or only `unit`'s lines, through the guest
+/// agent. With `follow` new lines keep coming until Ctrl+C.
+pub async fn run_guest(
+    system: &SystemConfig,
+    unit: Option<String>,
+    lines: u32,
+    follow: bool,
+) -> anyhow::Result<()> {
+    let connector = LibvirtDriver::new(system.clone())
+        .agent_connector()
+        .context("the machine is not running")?;
+    let client = Client::connect(connector);
+    let filter = JournalFilter {
+        unit,
+        lines,
+        follow,
+    };
+    client
+        .stream_journal(filter, |event| println!("{}", event.message))
+        .await?;
+    Ok(())
+}
+
 fn list_logs(logs_dir: &Path) -> anyhow::Result<()> {
-    let entries = sorted_logs(logs_dir, None)?;
+    let entries = sorted_logs(logs_dir, Some(".log"))?;
     if entries.is_empty() {
         anyhow::bail!("no provisioning logs found in {}", logs_dir.display());
     }
@@ -39,7 +101,7 @@ fn list_logs(logs_dir: &Path) -> anyhow::Result<()> {
     Ok(())
 }
 
-fn print_latest_log(logs_dir: &Path, failed_only: bool) -> anyhow::Result<()> {
+fn print_latest_log(logs_dir: &Path, failed_only: bool, output: LogOutput) -> anyhow::Result<()> {
     let suffix = if failed_only {
         Some("_failed.log")
     } else {
@@ -64,10 +126,138 @@ fn print_latest_log(logs_dir: &Path, failed_only: bool) -> anyhow::Result<()> {
 
     let content = std::fs::read_to_string(&path)
         .with_context(|| format!("failed to read log file {}", path.display()))?;
-    print!("{content}");
+    if output == LogOutput::Plain {
+        print!("{content}");
+        return Ok(());
+    }
+    let script = script_name(&path);
+    let stamps = std::fs::read_to_string(stamps_path(&path)).unwrap_or_default();
+    let mut stamps = stamps.lines();
+    for line in content.lines() {
+        print_line(output, &script, line, stamps.next().and_then(LogStamp::parse));
+    }
     Ok(())
 }
 
+/// Print the newest log, then keep printing the lines of every script log
+/// the daemon writes. A script's log is written while it runs, so this shows
+/// the output of a `rum up` or `rum provision` in progress. Returns once the
+/// log being followed is finished or removed, no other script is running
+/// and the daemon is gone.
+async fn follow_logs(logs_dir: &Path, socket_path: &Path, output: LogOutput) -> anyhow::Result<()> {
+    let mut seen = HashSet::new();
+    let mut current = sorted_logs(logs_dir, Some(".log"))
+        .unwrap_or_default()
+        .into_iter()
+        .next()
+        .map(|path| TailedLog::open(path, &mut seen))
+        .transpose()?;
+
+    loop {
+        if let Some(log) = &mut current {
+            // A finished script's log is renamed; the open handle still
+            // reads the rest of it.
+            let finished = !log.running || !log.path.exists();
+            log.print_new_lines(output)?;
+            if finished {
+                current = None;
+            }
+        }
+        if current.is_none() {
+            match sorted_logs(logs_dir, Some("_running.log"))
+                .unwrap_or_default()
+                .into_iter()
+                .find(|path| !seen.contains(&run_id(path)))
+            {
+                Some(path) => current = Some(TailedLog::open(path, &mut seen)?),
+                None if crate::ipc::connect(socket_path).await.is_err() => return Ok(()),
+                None => {}
+            }
+        }
+        tokio::time::sleep(FOLLOW_POLL).await;
+    }
+}
+
+/// A script log read as it grows.
+struct TailedLog {
+    path: PathBuf,
+    script: String,
+    /// Whether the log was still being written when it was opened.
+    running: bool,
+    reader: BufReader<File>,
+    stamps: Option<BufReader<File>>,
+    /// Start of a line whose end has not been written yet.
+    pending: String,
+    stamp: String,
+}
+
+impl TailedLog {
+    fn open(path: PathBuf, seen: &mut HashSet<String>) -> anyhow::Result<Self> {
+        let file = File::open(&path)
+            .with_context(|| format!("failed to open log file {}", path.display()))?;
+        let stamps = File::open(stamps_path(&path)).ok().map(BufReader::new);
+        seen.insert(run_id(&path));
+        Ok(Self {
+            script: script_name(&path),
+            running: path.to_string_lossy().ends_with("_running.log"),
+            path,
+            reader: BufReader::new(file),
+            stamps,
+            pending: String::new(),
+            stamp: String::new(),
+        })
+    }
+
+    fn print_new_lines(&mut self, output: LogOutput) -> anyhow::Result<()> {
+        while self.reader.read_line(&mut self.pending)? > 0 {
+            let Some(line) = self.pending.strip_suffix('\n') else {
+                break;
+            };
+            // Stamps are written before their line, so this one is complete.
+            self.stamp.clear();
+            if let Some(stamps) = &mut self.stamps {
+                stamps.read_line(&mut self.stamp)?;
+            }
+            print_line(output, &self.script, line, LogStamp::parse(&self.stamp));
+            self.pending.clear();
+        }
+        Ok(())
+    }
+}
+
+fn print_line(output: LogOutput, script: &str, line: &str, stamp: Option<LogStamp<'_>>) {
+    match output {
+        LogOutput::Plain => println!("{line}"),
+        LogOutput::Json => {
+            let record = LogRecord {
+                at_ms: stamp.map(|stamp| stamp.at_ms),
+                script: script.to_string(),
+                stream: stamp.map(|stamp| stamp.stream.to_string()),
+                line: line.to_string(),
+            };
+            println!("{}", facet_json::to_string(&record));
+        }
+    }
+}
+
+/// Log file names look like `<timestamp>_<script>_<status>.log`; the part
+/// before the status stays the same when a finished script's log is
+/// renamed.
+fn run_id(path: &Path) -> String {
+    let name = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or_default();
+    name.rsplit_once('_').map_or(name, |(id, _)| id).to_string()
+}
+
+fn script_name(path: &Path) -> String {
+    let id = run_id(path);
+    id.split_once('_')
+        .map_or(id.as_str(), |(_, script)| script)
+        .to_string()
+}
+
 fn sorted_logs(logs_dir: &Path, suffix: Option<&str>) -> anyhow::Result<Vec<PathBuf>> {
     let entries = std::fs::read_dir(logs_dir)
         .with_context(|| format!("failed to read logs directory {}", logs_dir.display()))?;
@@ -0,0 +1,325 @@
+//! The daemon's `rum.log`.
+//!
+//! Tracing is set up before the daemon has loaded its config, so the file
+//! layers write through a [`DeferredFileWriter`] that only opens the log
+//! once [`DeferredFileWriter::open`] is called with the `[logging]` section.
+//! Events logged before that only reach stderr.
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use facet::Facet;
+use machine::config::LoggingConfig;
+use tracing::field::{Field, Visit};
+use tracing_subscriber::Layer;
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::layer::Context;
+
+const SECS_PER_DAY: u64 = 86_400;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LogFormat {
+    Text,
+    Json,
+}
+
+/// Shared handle to the daemon's log file, opened once the config is known.
+#[derive(Clone, Default)]
+pub struct DeferredFileWriter(Arc<Mutex<Option<RotatingFile>>>);
+
+impl DeferredFileWriter {
+    /// Start writing to `path` as `config` asks.
+    pub fn open(&self, path: PathBuf, config: &LoggingConfig) -> io::Result<()> {
+        let format = match config.format.as_str() {
+            "json" => LogFormat::Json,
+            _ => LogFormat::Text,
+        };
+        let file = RotatingFile::open(path, format, config)?;
+        *self.0.lock().expect("log file lock poisoned") = Some(file);
+        Ok(())
+    }
+
+    /// Layers writing the log in either format; only the configured one
+    /// writes anything.
+    pub fn layers<S>(&self) -> impl Layer<S> + Send + Sync + 'static
+    where
+        S: tracing::Subscriber + for<'a> tracing_subscriber::registry::LookupSpan<'a>,
+    {
+        let text = tracing_subscriber::fmt::layer()
+            .with_ansi(false)
+            .with_writer(FormatWriter {
+                file: self.clone(),
+                format: LogFormat::Text,
+            });
+        let json = JsonLayer(FormatWriter {
+            file: self.clone(),
+            format: LogFormat::Json,
+        });
+        text.and_then(json)
+    }
+
+    fn write(&self, format: LogFormat, buf: &[u8]) -> io::Result<()> {
+        let mut slot = self.0.lock().expect("log file lock poisoned");
+        match slot.as_mut() {
+            Some(file) if file.format == format => file.write(buf),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// A [`MakeWriter`] for the layer of one format.
+#[derive(Clone)]
+struct FormatWriter {
+    file: DeferredFileWriter,
+    format: LogFormat,
+}
+
+impl Write for FormatWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.file.write(self.format, buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<'a> MakeWriter<'a> for FormatWriter {
+    type Writer = FormatWriter;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        self.clone()
+    }
+}
+
+struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    format: LogFormat,
+    size: u64,
+    /// `0` never rotates by size.
+    max_size: u64,
+    max_files: usize,
+    rotate_daily: bool,
+    /// Days since the Unix epoch when the current file was started.
+    day: u64,
+}
+
+impl RotatingFile {
+    fn open(path: PathBuf, format: LogFormat, config: &LoggingConfig) -> io::Result<Self> {
+        let file = File::options().create(true).append(true).open(&path)?;
+        let metadata = file.metadata()?;
+        let started = metadata.modified().unwrap_or_else(|_| SystemTime::now());
+        Ok(Self {
+            size: metadata.len(),
+            day: epoch_day(started),
+            path,
+            file,
+            format,
+            max_size: config.max_size_mb.saturating_mul(1024 * 1024),
+            max_files: config.max_files,
+            rotate_daily: config.rotate_daily,
+        })
+    }
+
+    fn write(&mut self, buf: &[u8]) -> io::Result<()> {
+        let today = epoch_day(SystemTime::now());
+        let full =
+            self.max_size > 0 && self.size > 0 && self.size + buf.len() as u64 > self.max_size;
+        if full || (self.rotate_daily && today != self.day) {
+            self.rotate()?;
+            self.day = today;
+        }
+        self.file.write_all(buf)?;
+        self.size += buf.len() as u64;
+        Ok(())
+    }
+
+    /// Shift `rum.log.N` to `rum.log.N+1`, dropping the oldest, and start
+    /// an empty `rum.log`.
+    fn rotate(&mut self) -> io::Result<()> {
+        let _ = std::fs::remove_file(numbered(&self.path, self.max_files));
+        for n in (1..self.max_files).rev() {
+            let _ = std::fs::rename(numbered(&self.path, n), numbered(&self.path, n + 1));
+        }
+        std::fs::rename(&self.path, numbered(&self.path, 1))?;
+        self.file = File::options().create(true).append(true).open(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+fn numbered(path: &Path, n: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{n}"));
+    PathBuf::from(name)
+}
+
+fn epoch_day(time: SystemTime) -> u64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_secs() / SECS_PER_DAY)
+        .unwrap_or_default()
+}
+
+/// One `rum.log` line in the `json` format.
+#[derive(Facet)]
+struct JsonRecord {
+    /// Milliseconds since the Unix epoch.
+    at_ms: u64,
+    level: String,
+    target: String,
+    message: String,
+    fields: BTreeMap<String, String>,
+}
+
+struct JsonLayer(FormatWriter);
+
+impl<S: tracing::Subscriber> Layer<S> for JsonLayer {
+    fn on_event(&self, event: &tracing::Event<'_>, _cx: Context<'_, S>) {
+        let mut visitor = FieldVisitor::default();
+        event.record(&mut visitor);
+        let at_ms = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or_default();
+        let record = JsonRecord {
+            at_ms,
+            level: event.metadata().level().to_string(),
+            target: event.metadata().target().to_string(),
+            message: visitor.message,
+            fields: visitor.fields,
+        };
+        let line = format!("{}\n", facet_json::to_string(&record));
+        let _ = self.0.file.write(self.0.format, line.as_bytes());
+    }
+}
+
+#[derive(Default)]
+struct FieldVisitor {
+    message: String,
+    fields: BTreeMap<String, String>,
+}
+
+impl Visit for FieldVisitor {
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        self.record_str(field, &format!("{value:?}"));
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.message = value.to_string();
+        } else {
+            self.fields
+                .insert(field.name().to_string(), value.to_string());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn log_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("rum-logging-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn config(max_files: usize) -> LoggingConfig {
+        LoggingConfig {
+            max_files,
+            ..LoggingConfig::default()
+        }
+    }
+
+    fn read(path: &Path) -> String {
+        std::fs::read_to_string(path).unwrap_or_default()
+    }
+
+    #[test]
+    fn rotates_by_size_and_keeps_max_files() {
+        let dir = log_dir("size");
+        let path = dir.join("rum.log");
+        let mut file = RotatingFile::open(path.clone(), LogFormat::Text, &config(2)).unwrap();
+        file.max_size = 8;
+
+        for line in ["one\n", "two\n", "three\n", "four\n"] {
+            file.write(line.as_bytes()).unwrap();
+        }
+        assert_eq!(read(&path), "four\n");
+        assert_eq!(read(&numbered(&path, 1)), "three\n");
+        assert_eq!(read(&numbered(&path, 2)), "one\ntwo\n");
+        assert!(!numbered(&path, 3).exists());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn oversized_writes_go_to_an_empty_file_whole() {
+        let dir = log_dir("oversized");
+        let path = dir.join("rum.log");
+        let mut file = RotatingFile::open(path.clone(), LogFormat::Text, &config(3)).unwrap();
+        file.max_size = 4;
+
+        file.write(b"a long line\n").unwrap();
+        assert_eq!(read(&path), "a long line\n");
+        assert!(!numbered(&path, 1).exists());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn rotates_daily_when_asked() {
+        let dir = log_dir("daily");
+        let path = dir.join("rum.log");
+        let logging = LoggingConfig {
+            max_size_mb: 0,
+            rotate_daily: true,
+            ..config(3)
+        };
+        let mut file = RotatingFile::open(path.clone(), LogFormat::Text, &logging).unwrap();
+        file.write(b"today\n").unwrap();
+        assert!(!numbered(&path, 1).exists());
+
+        file.day -= 1;
+        file.write(b"tomorrow\n").unwrap();
+        assert_eq!(read(&path), "tomorrow\n");
+        assert_eq!(read(&numbered(&path, 1)), "today\n");
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn huge_size_limits_do_not_overflow() {
+        let dir = log_dir("huge");
+        let logging = LoggingConfig {
+            max_size_mb: u64::MAX,
+            ..config(1)
+        };
+        let file = RotatingFile::open(dir.join("rum.log"), LogFormat::Text, &logging).unwrap();
+        assert_eq!(file.max_size, u64::MAX);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn only_the_configured_format_writes() {
+        let dir = log_dir("format");
+        let path = dir.join("rum.log");
+        let writer = DeferredFileWriter::default();
+        writer.write(LogFormat::Text, b"before open\n").unwrap();
+        assert!(!path.exists());
+
+        let logging = LoggingConfig {
+            format: "json".into(),
+            ..config(1)
+        };
+        writer.open(path.clone(), &logging).unwrap();
+        writer.write(LogFormat::Text, b"text\n").unwrap();
+        writer.write(LogFormat::Json, b"{}\n").unwrap();
+        assert_eq!(read(&path), "{}\n");
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -1,3 +1,4 @@
+use std::io::IsTerminal;
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
 use std::sync::Arc;
@@ -5,29 +6,57 @@ use std::sync::atomic::AtomicBool;
 use std::time::Duration;
 
 use anyhow::Context;
-use clap::{Parser, Subcommand};
-use cli::render::{RenderMode, RumRenderPlugin};
-use machine::config::{SystemConfig, load_config};
-use machine::driver::{Driver, LibvirtDriver};
-use machine::instance::Instance;
+use clap::{Args, CommandFactory, FromArgMatches, Parser, Subcommand};
+use cli::ipc::{INTERNAL_DAEMON_CONFIG, INTERNAL_DAEMON_RESUME};
+use cli::render::{ColorChoice, RenderMode, RenderStyle, RumRenderPlugin};
+use machine::config::{
+    AdvancedConfig, CONFIG_ENV, PROFILE_ENV, SystemConfig, find_config, load_config_with_profile,
+    profile_from_env,
+};
+use machine::driver::{Driver, LibvirtDriver, RecoverableDriver};
+use machine::instance::{Instance, InstanceState};
 use tracing_subscriber::EnvFilter;
 use tracing_subscriber::layer::SubscriberExt as _;
 use tracing_subscriber::util::SubscriberInitExt as _;
 
-const INTERNAL_DAEMON_CONFIG: &str = "RUM_INTERNAL_DAEMON_CONFIG";
-
 #[derive(Parser)]
 #[command(name = "rum")]
 #[command(about = "Bootstraps rum orchestration flows")]
 struct Cli {
-    /// Path to the rum config file.
-    #[arg(short, long, default_value = "rum.toml")]
-    config: PathBuf,
+    /// Path to the rum config file. Defaults to `$RUM_CONFIG`, then the
+    /// nearest `rum.toml` or `*.rum.toml` in this or a parent directory.
+    #[arg(short, long)]
+    config: Option<PathBuf>,
 
     /// Output mode for the attached client.
     #[arg(long, value_enum, default_value_t = RenderMode::Plain)]
     output: RenderMode,
 
+    /// When to color the output. `auto` colors a terminal unless `$NO_COLOR`
+    /// is set.
+    #[arg(long, global = true, value_enum, default_value_t)]
+    color: ColorChoice,
+
+    /// Keep the output to plain ASCII, replacing emoji and other symbols,
+    /// e.g. from guest commands, that some terminals and log viewers mangle.
+    #[arg(long, global = true, visible_alias = "ascii")]
+    no_emoji: bool,
+
+    /// Run unattended for CI: plain output in collapsible groups, no
+    /// prompts, bounded timeouts, and the logs copied to `--log-dir`. On
+    /// when `$CI` is set.
+    #[arg(long, global = true)]
+    ci: bool,
+
+    /// Where CI mode copies `rum.log` and the provisioning script logs.
+    /// Defaults to `./rum-logs`.
+    #[arg(long, global = true, value_name = "DIR")]
+    log_dir: Option<PathBuf>,
+
+    /// Apply `[profile.<NAME>]` from the config. Defaults to `$RUM_PROFILE`.
+    #[arg(long, global = true, value_name = "NAME")]
+    profile: Option<String>,
+
     #[command(subcommand)]
     command: Command,
 }
@@ -47,7 +76,74 @@ enum Command {
 #[derive(Subcommand)]
 enum StartsDaemonCmd {
     /// Start or attach to the current machine.
-    Up,
+    Up {
+        /// Discard changes made since provisioning. Falls back to recreating
+        /// the machine from the base (or baked) image when no provisioned
+        /// layer has been saved yet.
+        #[arg(long)]
+        reset: bool,
+        /// Continue a run that was interrupted, e.g. by a crash, reusing the
+        /// base image it had already resolved.
+        #[arg(long, conflicts_with = "reset")]
+        resume: bool,
+        /// Print the steps `up` would take from the machine's current state
+        /// without changing anything.
+        #[arg(long, conflicts_with_all = ["reset", "resume"])]
+        dry_run: bool,
+    },
+    /// Provision the machine once, then save its disk as a reusable base image.
+    Bake,
+    /// Boot the machine, run one command in the guest, then shut it down.
+    Run {
+        /// Destroy the machine and its overlay once the command finishes.
+        #[arg(long)]
+        rm: bool,
+        #[command(flatten)]
+        exec: ExecArgs,
+    },
+    /// Boot the machine if needed and run its `[[tests]]` checks in the guest.
+    /// Exits non-zero if any of them fails.
+    Test {
+        /// Run only the tests with these names.
+        names: Vec<String>,
+        /// Also write the results to this file, e.g. for CI.
+        #[arg(long, value_name = "FILE")]
+        report: Option<PathBuf>,
+        #[arg(long, value_enum, default_value_t, requires = "report")]
+        report_format: cli::test::ReportFormat,
+    },
+}
+
+/// Guest command options shared by `rum exec` and `rum run`.
+#[derive(Args)]
+struct ExecArgs {
+    /// Run the command as this guest user instead of root.
+    #[arg(long)]
+    user: Option<String>,
+    /// Guest working directory for the command.
+    #[arg(long)]
+    workdir: Option<String>,
+    /// Set an environment variable for the command. Repeatable.
+    #[arg(long = "env", value_name = "KEY=VAL")]
+    env: Vec<String>,
+    /// Kill the command if it runs longer than this many seconds.
+    #[arg(long, value_name = "SECONDS")]
+    timeout: Option<u64>,
+    /// Command string to execute in the guest shell.
+    #[arg(required = true, trailing_var_arg = true, allow_hyphen_values = true)]
+    command: Vec<String>,
+}
+
+impl ExecArgs {
+    fn into_request(self) -> anyhow::Result<cli::protocol::ExecRequest> {
+        cli::exec::prepare_request(
+            &self.command,
+            self.user,
+            self.workdir,
+            &self.env,
+            self.timeout,
+        )
+    }
 }
 
 #[derive(Subcommand)]
@@ -61,18 +157,196 @@ enum DirectCmd {
         /// List available provisioning logs newest first.
         #[arg(long)]
         list: bool,
+
+        /// Show the guest's system log instead, read through the agent.
+        #[arg(long, conflicts_with_all = ["failed", "list"])]
+        guest: bool,
+
+        /// Only show lines of this guest systemd unit.
+        #[arg(short, long, requires = "guest")]
+        unit: Option<String>,
+
+        /// Keep printing new lines: script logs, including those of scripts
+        /// running now, until the daemon is done; `--guest` until Ctrl+C.
+        #[arg(short, long, conflicts_with_all = ["failed", "list"])]
+        follow: bool,
+
+        /// Print script log lines as they are, or as JSON objects.
+        #[arg(long, value_enum, default_value_t, conflicts_with = "guest")]
+        output: cli::log::LogOutput,
+
+        /// Guest log lines of history to show first.
+        #[arg(short = 'n', long, default_value_t = 50, requires = "guest")]
+        lines: u32,
+    },
+    /// Check or print the resolved config.
+    Config {
+        #[command(subcommand)]
+        cmd: cli::config::ConfigCmd,
+    },
+    /// Inspect derived networking for the current config.
+    Net {
+        #[command(subcommand)]
+        cmd: cli::net::NetCmd,
+    },
+    /// Print the guest IP address.
+    Ip {
+        /// Address on the NIC attached to this network instead of the default NIC.
+        #[arg(long)]
+        interface: Option<String>,
+        /// Print the guest's IPv6 address instead of its IPv4 address.
+        #[arg(short = '6', long)]
+        ipv6: bool,
+        #[arg(long, value_enum, default_value_t)]
+        output: cli::ip::IpOutput,
+    },
+    /// Forward host ports into the running machine until Ctrl+C.
+    Forward {
+        /// Forwards as `[BIND:]HOST:GUEST`, e.g. `8080:80` or `[::1]:5432:5432`.
+        #[arg(required = true, value_name = "SPEC")]
+        specs: Vec<String>,
+    },
+    /// Route host connections through the running machine.
+    Tunnel {
+        /// Run a SOCKS5 proxy on this host port.
+        #[arg(long, value_name = "PORT")]
+        socks: u16,
+        /// Address the proxy listens on.
+        #[arg(long, default_value = "127.0.0.1")]
+        bind: String,
+    },
+    /// Share a host directory with the running machine.
+    Mount {
+        /// Host directory to share.
+        source: String,
+        /// Absolute path to mount it at in the guest.
+        target: String,
+        /// Mount the share read-only.
+        #[arg(long)]
+        readonly: bool,
+    },
+    /// Unmount a share from the running machine.
+    Umount {
+        /// Guest path the share is mounted at.
+        target: String,
+    },
+    /// Open an ssh session to the running machine, or run a command over ssh.
+    Ssh {
+        /// Arguments passed to ssh after the destination, e.g. `-- uname -a`.
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+        args: Vec<String>,
+    },
+    /// Copy files over ssh with `scp`, for machines without the rum agent.
+    Scp {
+        /// Source path. Prefix the guest path with `:`.
+        src: String,
+        /// Destination path. Prefix the guest path with `:`.
+        dst: String,
+        /// Copy directories recursively.
+        #[arg(short, long)]
+        recursive: bool,
+    },
+    /// Print an OpenSSH `Host` entry for the machine.
+    SshConfig {
+        /// Write the entry into `~/.ssh/config` instead, replacing an earlier
+        /// one. `rum destroy` removes it.
+        #[arg(long)]
+        install: bool,
+    },
+    /// Print exports that point local tooling such as docker at the machine,
+    /// for `eval "$(rum env)"`.
+    Env {
+        #[arg(long, value_enum, default_value_t)]
+        shell: cli::env::EnvShell,
+    },
+    /// Attach to the machine's serial console, where the kernel and boot
+    /// output land. `Ctrl+]` detaches.
+    Console,
+    /// Open the machine's graphical console.
+    View {
+        /// Print the SPICE/VNC URI instead of launching virt-viewer.
+        #[arg(long)]
+        print: bool,
+    },
+    /// Change vCPUs or memory of the running machine without a restart.
+    Resize {
+        /// Online vCPU count, up to `resources.cpus_max`.
+        #[arg(long)]
+        cpus: Option<u32>,
+        /// Memory size such as `4G`, up to `resources.memory_max_mb`.
+        #[arg(long)]
+        memory: Option<String>,
+    },
+    /// List rum-managed machines on the libvirt host.
+    List {
+        /// Only show machines matching `label=KEY` or `label=KEY=VALUE`. Repeatable.
+        #[arg(long)]
+        filter: Vec<String>,
+    },
+    /// Manage the local base image cache.
+    Image {
+        #[command(subcommand)]
+        cmd: cli::image::ImageCmd,
+    },
+    /// Remove stale work dirs, orphaned libvirt objects, and old cached images.
+    Clean {
+        /// List what would be removed without deleting anything.
+        #[arg(long)]
+        dry_run: bool,
+        /// Also stop orphaned machines that are still running.
+        #[arg(long)]
+        force: bool,
+        /// Remove cached base images not modified for this many days.
+        #[arg(long, value_name = "DAYS", default_value_t = 30)]
+        cache_ttl_days: u64,
+    },
+    /// Check that the host can run rum machines, with hints for fixing problems.
+    Doctor,
+    /// Show config changes since the machine was started and how to apply them.
+    Diff,
+    /// Start, stop or inspect systemd services in the running machine.
+    Service {
+        #[command(subcommand)]
+        cmd: cli::guest_service::ServiceCmd,
+    },
+    /// Manage the rum agent inside the running machine.
+    Agent {
+        #[command(subcommand)]
+        cmd: cli::agent::AgentCmd,
+    },
+    /// Run the machine's daemon as a systemd service.
+    Daemon {
+        #[command(subcommand)]
+        cmd: cli::daemon_unit::DaemonCmd,
+    },
+    /// Show who ran which commands on the machine, and how they ended.
+    History {
+        /// Only show the last N commands.
+        #[arg(long, short = 'n', value_name = "N")]
+        limit: Option<usize>,
+        #[arg(long, value_enum, default_value_t)]
+        output: cli::history::HistoryOutput,
     },
 }
 
 #[derive(Subcommand)]
 enum RequiresDaemonCmd {
     /// Ask the daemon to shut down the current machine.
-    Down,
+    Down {
+        /// Abandon a boot or provisioning run in progress instead of
+        /// waiting for it to finish.
+        #[arg(long)]
+        force: bool,
+    },
+    /// Cancel the boot or provisioning run in progress and stop the
+    /// machine, e.g. from another terminal while `rum up` is attached.
+    Cancel,
+    /// Reboot the current machine, re-running boot provisioning scripts.
+    Restart,
     /// Execute a shell command in the managed guest.
     Exec {
-        /// Command string to execute in the guest shell.
-        #[arg(required = true, trailing_var_arg = true, allow_hyphen_values = true)]
-        command: Vec<String>,
+        #[command(flatten)]
+        exec: ExecArgs,
     },
     /// Copy files to or from the managed guest.
     Cp {
@@ -80,6 +354,46 @@ enum RequiresDaemonCmd {
         src: String,
         /// Destination path. Prefix the guest path with `:`.
         dst: String,
+        /// zstd-compress the data on the wire. Helps for text and build
+        /// artifacts, not for already-compressed files.
+        #[arg(long)]
+        compress: bool,
+    },
+    /// Sync a host directory into the guest, copying only changed files.
+    Sync {
+        /// Local directory to sync from.
+        src: String,
+        /// Guest directory to sync into, prefixed with `:`.
+        dst: String,
+    },
+    /// Re-read the config and apply port, mount, readiness and notification
+    /// changes to the running machine without a reboot.
+    Reload,
+    /// Run the provisioning scripts again on the running machine.
+    Provision {
+        /// Only run the step with this name.
+        #[arg(long, value_name = "STEP")]
+        only: Option<String>,
+        /// Also run the steps that are unchanged since their last run.
+        #[arg(long, visible_alias = "all")]
+        force: bool,
+        /// Run this host script in the guest instead of the plan, logged like
+        /// a provisioning step.
+        #[arg(long, value_name = "FILE", conflicts_with_all = ["only", "force"])]
+        script: Option<PathBuf>,
+    },
+    /// Follow the machine's lifecycle next to any other attached client until
+    /// it stops. Ctrl+C detaches.
+    Attach,
+    /// Print lifecycle events: recovered state, phase changes, errors and
+    /// log lines. Exits with status 1 if the machine has failed.
+    Events {
+        /// Keep printing events as they happen instead of exiting after the
+        /// current state.
+        #[arg(long)]
+        follow: bool,
+        #[arg(long, value_enum, default_value_t)]
+        output: cli::events::EventsOutput,
     },
     /// Query the daemon for the current machine status.
     Status {
@@ -101,32 +415,174 @@ enum MaybeDaemonCmd {
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    init_tracing();
+    let log_file = cli::logging::DeferredFileWriter::default();
     if let Some(config) = std::env::var_os(INTERNAL_DAEMON_CONFIG) {
-        return run_daemon(&PathBuf::from_str(
-            &config
-                .into_string()
-                .expect("failed to convert config path to string"),
-        )?)
+        init_tracing(&log_file, ColorChoice::Auto);
+        let resume = std::env::var_os(INTERNAL_DAEMON_RESUME).is_some();
+        return run_daemon(
+            &PathBuf::from_str(
+                &config
+                    .into_string()
+                    .expect("failed to convert config path to string"),
+            )?,
+            resume,
+            &log_file,
+        )
         .await;
     }
 
-    let cli = Cli::parse();
+    let arg_matches = Cli::command().get_matches();
+    let mut cli = Cli::from_arg_matches(&arg_matches).unwrap_or_else(|error| error.exit());
+    let flow = arg_matches.subcommand_name().unwrap_or("rum").to_string();
+    init_tracing(&log_file, cli.color);
+    let style = RenderStyle::new(cli.color, cli.no_emoji);
+    let ci = cli::ci::CiMode::detect(cli.ci, cli.log_dir.clone());
+    if ci.is_some() {
+        cli.output = RenderMode::Plain;
+    }
+    let profile = cli.profile.clone().or_else(profile_from_env);
+
+    if let Command::Direct(DirectCmd::Image { cmd }) = &cli.command {
+        return cli::image::run(cmd.clone()).await;
+    }
+
+    // `rum list`, `rum clean` and `rum doctor` are host-wide, so they work
+    // without a config in the current dir.
+    if let Command::Direct(
+        cmd @ (DirectCmd::List { .. } | DirectCmd::Clean { .. } | DirectCmd::Doctor),
+    ) = &cli.command
+    {
+        let libvirt_uri = resolve_config(&cli)
+            .ok()
+            .and_then(|config| load_config_with_profile(&config, profile.as_deref()).ok())
+            .map(|system| system.libvirt_uri().to_string())
+            .unwrap_or_else(|| AdvancedConfig::default().libvirt_uri);
+        return match cmd {
+            DirectCmd::List { filter } => cli::list::run(&libvirt_uri, filter),
+            DirectCmd::Clean {
+                dry_run,
+                force,
+                cache_ttl_days,
+            } => cli::clean::run(&libvirt_uri, *cache_ttl_days, *dry_run, *force),
+            DirectCmd::Doctor => cli::doctor::run(&libvirt_uri),
+            _ => unreachable!("only host-wide commands reach here"),
+        };
+    }
+
+    let config = resolve_config(&cli)?;
+
+    // Validation has to report configs that fail to load.
+    if let Command::Direct(DirectCmd::Config {
+        cmd: cli::config::ConfigCmd::Validate,
+    }) = &cli.command
+    {
+        return cli::config::validate(&config, profile.as_deref());
+    }
+
+    let system = load_config_with_profile(&config, profile.as_deref())
+        .context("failed to load machine config")?;
 
-    let system = load_config(&cli.config).context("failed to load machine config")?;
+    let direct = matches!(cli.command, Command::Direct(_));
+    let started = std::time::SystemTime::now();
+    let result = run_command(cli, config, system.clone(), style, ci.clone()).await;
+    cli::history::record(&system, &flow, started, &result);
+    if !direct
+        && let Some(ci) = &ci
+        && let Err(error) = ci.collect_logs(&system)
+    {
+        eprintln!("failed to collect logs for CI: {error:#}");
+    }
+    let exit_code = result?;
+    if exit_code != 0 {
+        std::process::exit(exit_code);
+    }
+
+    Ok(())
+}
 
+/// Run `cli`'s command against `system`, returning the process exit code.
+async fn run_command(
+    cli: Cli,
+    config: PathBuf,
+    system: SystemConfig,
+    style: RenderStyle,
+    ci: Option<cli::ci::CiMode>,
+) -> anyhow::Result<i32> {
     if let Command::Direct(cmd) = &cli.command {
         return match cmd {
-            DirectCmd::Log { failed, list } => {
-                let selection = match (*failed, *list) {
-                    (true, true) => anyhow::bail!("--failed and --list are mutually exclusive"),
-                    (true, false) => cli::log::LogSelection::LatestFailed,
-                    (false, true) => cli::log::LogSelection::List,
-                    (false, false) => cli::log::LogSelection::Latest,
+            DirectCmd::Log {
+                guest: true,
+                unit,
+                follow,
+                lines,
+                ..
+            } => cli::log::run_guest(&system, unit.clone(), *lines, *follow).await,
+            DirectCmd::Log {
+                failed,
+                list,
+                follow,
+                output,
+                ..
+            } => {
+                let selection = match (*failed, *list, *follow) {
+                    (true, true, _) => {
+                        anyhow::bail!("--failed and --list are mutually exclusive")
+                    }
+                    (true, false, _) => cli::log::LogSelection::LatestFailed,
+                    (false, true, _) => cli::log::LogSelection::List,
+                    (false, false, true) => cli::log::LogSelection::Follow,
+                    (false, false, false) => cli::log::LogSelection::Latest,
                 };
-                cli::log::run(&system, selection)
+                cli::log::run(&system, selection, *output).await
             }
-        };
+            DirectCmd::Config { cmd } => match cmd {
+                cli::config::ConfigCmd::Show { format } => cli::config::show(&system, *format),
+                cli::config::ConfigCmd::Validate => unreachable!("validate returns before loading"),
+            },
+            DirectCmd::Net { cmd } => cli::net::run(&system, *cmd),
+            DirectCmd::Ip {
+                interface,
+                ipv6,
+                output,
+            } => cli::ip::run(&system, interface.as_deref(), *ipv6, *output).await,
+            DirectCmd::Forward { specs } => cli::forward::run(&system, specs).await,
+            DirectCmd::Tunnel { socks, bind } => cli::tunnel::run(&system, bind, *socks).await,
+            DirectCmd::Mount {
+                source,
+                target,
+                readonly,
+            } => cli::mount::mount(&system, source, target, *readonly).await,
+            DirectCmd::Umount { target } => cli::mount::umount(&system, target).await,
+            DirectCmd::Ssh { args } => Ok(LibvirtDriver::new(system.clone()).ssh(args).await?),
+            DirectCmd::Scp {
+                src,
+                dst,
+                recursive,
+            } => {
+                let direction = guest::client::parse_copy_args(src, dst)?;
+                let driver = LibvirtDriver::new(system.clone());
+                Ok(driver.scp(&direction, *recursive).await?)
+            }
+            DirectCmd::SshConfig { install } => cli::ssh_config::run(&system, *install).await,
+            DirectCmd::Env { shell } => cli::env::run(&system, *shell).await,
+            DirectCmd::Console => cli::console::run(&system),
+            DirectCmd::View { print } => cli::view::run(&system, *print),
+            DirectCmd::Resize { cpus, memory } => {
+                cli::resize::run(&system, *cpus, memory.as_deref())
+            }
+            DirectCmd::Diff => cli::diff::run(&system),
+            DirectCmd::Service { cmd } => cli::guest_service::run(&system, cmd.clone()).await,
+            DirectCmd::Agent { cmd } => cli::agent::run(&system, *cmd).await,
+            DirectCmd::Daemon { cmd } => cli::daemon_unit::run(&system, cmd.clone()),
+            DirectCmd::History { limit, output } => cli::history::run(&system, *limit, *output),
+            DirectCmd::List { .. }
+            | DirectCmd::Clean { .. }
+            | DirectCmd::Doctor
+            | DirectCmd::Image { .. } => {
+                unreachable!("host-wide commands return before config loading")
+            }
+        }
+        .map(|()| 0);
     }
 
     let socket_path = cli::ipc::socket_path(&system);
@@ -134,31 +590,145 @@ async fn main() -> anyhow::Result<()> {
     let iso = cli::app::create_isomorphic_app(socket_path, restart_requested.clone());
 
     let mut app = iso.build_client();
-    let config_path = cli.config.canonicalize()?;
+    app.insert_resource(style);
+    if let Some(ci) = &ci {
+        app.insert_resource(ci.clone());
+    }
+    let config_path = config.canonicalize()?;
+    // Multi-phase commands need a fresh client app per phase, since running
+    // an app consumes it.
+    let new_client = || {
+        let iso = cli::app::create_isomorphic_app(
+            cli::ipc::socket_path(&system),
+            restart_requested.clone(),
+        );
+        let mut app = iso.build_client();
+        app.insert_resource(style);
+        if let Some(ci) = &ci {
+            app.insert_resource(ci.clone());
+        }
+        cli::app::build_client_app(app, cli.output, true)
+    };
 
     match cli.command {
         Command::Direct(_) => unreachable!("direct commands return before daemon setup"),
         Command::Starts(cmd) => match cmd {
-            StartsDaemonCmd::Up => {
+            StartsDaemonCmd::Up { dry_run: true, .. } => {
+                cli::plan::run(&system).await?;
+            }
+            StartsDaemonCmd::Up { reset, resume, .. } => {
+                let _lock = lock_vm(&system)?;
+                if reset {
+                    run_reset(&system, new_client).await?;
+                } else {
+                    note_interrupted_run(&system, resume);
+                }
                 app.add_plugins(RumRenderPlugin::new(cli.output));
-                run_up(&config_path, &system, app)
+                run_up(&config_path, &system, app, resume, ci.is_some())
                     .await
                     .context("failed to run up command")?;
             }
+            StartsDaemonCmd::Run { rm, exec } => {
+                let request = exec.into_request()?;
+                let _lock = lock_vm(&system)?;
+                app.add_plugins(RumRenderPlugin::new(cli.output));
+                let exit_code = run_oneshot(
+                    &config_path,
+                    &system,
+                    app,
+                    request,
+                    rm,
+                    ci.is_some(),
+                    new_client,
+                )
+                .await
+                .context("failed to run one-shot command")?;
+                return Ok(exit_code);
+            }
+            StartsDaemonCmd::Test {
+                names,
+                report,
+                report_format,
+            } => {
+                let _lock = lock_vm(&system)?;
+                app.add_plugins(RumRenderPlugin::new(cli.output));
+                run_up(&config_path, &system, app, false, ci.is_some())
+                    .await
+                    .context("failed to bring the machine up")?;
+                let report = report.map(|path| cli::test::TestReport {
+                    path,
+                    format: report_format,
+                    suite: system.display_name().to_string(),
+                });
+                let exit = cli::test::TestExit::default();
+                let request = cli::protocol::TestRequest { names };
+                cli::test::build_test_client(new_client(), request, report, exit.clone())
+                    .run()
+                    .await;
+                return Ok(exit.exit_code().unwrap_or(1));
+            }
+            StartsDaemonCmd::Bake => {
+                let _lock = lock_vm(&system)?;
+                app.add_plugins(RumRenderPlugin::new(cli.output));
+                run_bake(&config_path, &system, app, ci.is_some(), new_client)
+                    .await
+                    .context("failed to bake image")?;
+            }
         },
         Command::Requires(cmd) => {
-            ensure_connected(&cli.config, &system).await?;
+            ensure_connected(&config, &system, ci.is_some()).await?;
 
             match cmd {
-                RequiresDaemonCmd::Down => {
-                    run_down(app).await?;
+                RequiresDaemonCmd::Down { force } => {
+                    let _lock = lock_vm(&system)?;
+                    run_down(app, force).await?;
+                }
+                // No VM lock: the `rum up` being cancelled holds it.
+                RequiresDaemonCmd::Cancel => {
+                    cli::down::build_cancel_client(app).run().await;
+                }
+                RequiresDaemonCmd::Restart => {
+                    let _lock = lock_vm(&system)?;
+                    app.add_plugins(RumRenderPlugin::new(cli.output));
+                    cli::reboot::build_reboot_client(app).run().await;
+                }
+                RequiresDaemonCmd::Exec { exec } => {
+                    app.add_plugins(RumRenderPlugin::new(cli.output));
+                    run_exec(app, exec.into_request()?).await?;
+                }
+                RequiresDaemonCmd::Cp { src, dst, compress } => {
+                    run_cp(app, &src, &dst, compress).await?;
+                }
+                RequiresDaemonCmd::Sync { src, dst } => {
+                    run_sync(app, &src, &dst).await?;
                 }
-                RequiresDaemonCmd::Exec { command } => {
+                RequiresDaemonCmd::Reload => {
+                    cli::reload::build_reload_client(app, config_path.clone())
+                        .run()
+                        .await;
+                }
+                RequiresDaemonCmd::Events { follow, output } => {
+                    let outcome = cli::events::EventsOutcome::default();
+                    cli::events::build_events_client(app, output, follow, outcome.clone())
+                        .run()
+                        .await;
+                    return Ok(outcome.exit_code().unwrap_or_default());
+                }
+                RequiresDaemonCmd::Attach => {
                     app.add_plugins(RumRenderPlugin::new(cli.output));
-                    run_exec(app, &command).await?;
+                    cli::client::build_attach_client(app).run().await;
                 }
-                RequiresDaemonCmd::Cp { src, dst } => {
-                    run_cp(app, &src, &dst).await?;
+                RequiresDaemonCmd::Provision {
+                    only,
+                    force,
+                    script,
+                } => {
+                    let _lock = lock_vm(&system)?;
+                    let request = cli::provision::prepare_request(only, force, script.as_deref())?;
+                    app.add_plugins(RumRenderPlugin::new(cli.output));
+                    cli::provision::build_provision_client(app, request)
+                        .run()
+                        .await;
                 }
                 RequiresDaemonCmd::Status { watch, wait_ready } => {
                     let render_enabled = watch || wait_ready;
@@ -171,22 +741,54 @@ async fn main() -> anyhow::Result<()> {
         }
         Command::Maybe(cmd) => match cmd {
             MaybeDaemonCmd::Destroy => {
+                let _lock = lock_vm(&system)?;
                 let app = cli::app::build_client_app(app, cli.output, true);
                 run_destroy(system.clone(), app).await?;
             }
         },
-    };
+    }
+    Ok(0)
+}
 
-    Ok(())
+/// Hold the VM lock for the rest of a state-changing command, so a
+/// concurrent one fails fast instead of racing it.
+fn lock_vm(system: &SystemConfig) -> anyhow::Result<machine::lock::VmLock> {
+    let path = machine::paths::lock_path(&system.id, system.name.as_deref());
+    Ok(machine::lock::VmLock::acquire(
+        &path,
+        system.display_name(),
+    )?)
+}
+
+/// Config named by `--config`, else the default one.
+fn resolve_config(cli: &Cli) -> anyhow::Result<PathBuf> {
+    match &cli.config {
+        Some(path) => Ok(path.clone()),
+        None => default_config_path(),
+    }
+}
+
+/// Config used without `--config`: `$RUM_CONFIG`, else the nearest one in
+/// this or a parent directory, else `rum.toml` so the load error names it.
+fn default_config_path() -> anyhow::Result<PathBuf> {
+    if let Some(path) = std::env::var_os(CONFIG_ENV).filter(|p| !p.is_empty()) {
+        return Ok(PathBuf::from(path));
+    }
+    let cwd = std::env::current_dir().context("failed to read the current directory")?;
+    Ok(find_config(&cwd)?.unwrap_or_else(|| PathBuf::from("rum.toml")))
 }
 
-fn init_tracing() {
+/// Log to stderr, and to `rum.log` once the daemon opens `log_file`.
+fn init_tracing(log_file: &cli::logging::DeferredFileWriter, color: ColorChoice) {
+    let ansi = color.enabled(std::io::stderr().is_terminal());
     let _ = tracing_subscriber::registry()
         .with(
             tracing_subscriber::fmt::layer()
                 .with_writer(std::io::stderr)
+                .with_ansi(ansi)
                 .with_target(false),
         )
+        .with(log_file.layers())
         .with(EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")))
         .try_init();
 }
@@ -195,19 +797,143 @@ async fn run_up(
     config_path: &Path,
     system: &SystemConfig,
     app: ecsdk::app::AsyncApp<orchestrator::OrchestratorMessage>,
+    resume: bool,
+    ci: bool,
 ) -> anyhow::Result<()> {
     let socket_path = cli::ipc::socket_path(system);
-    ensure_daemon(config_path, &socket_path)
+    let profile = system.profile.as_deref();
+    let started = ensure_daemon(config_path, &socket_path, profile, resume, ci)
         .await
         .context("Failed to ensure daemon")?;
 
-    let app = cli::client::build_up_client(app);
+    // Only the client that started the daemon shuts it down on Ctrl+C.
+    let app = cli::client::build_up_client_with(app, started);
     app.run().await;
     Ok(())
 }
 
-async fn run_daemon(config_path: &Path) -> anyhow::Result<()> {
-    let spec = cli::server::load_server_spec(config_path).await?;
+/// Boot the machine, run `request` in the guest, then shut the machine down
+/// (or destroy it when `remove` is set). Returns the guest exit code.
+///
+/// Every phase is its own client app because running an app consumes it.
+async fn run_oneshot(
+    config_path: &Path,
+    system: &SystemConfig,
+    app: ecsdk::app::AsyncApp<orchestrator::OrchestratorMessage>,
+    request: cli::protocol::ExecRequest,
+    remove: bool,
+    ci: bool,
+    new_client: impl Fn() -> ecsdk::app::AsyncApp<orchestrator::OrchestratorMessage>,
+) -> anyhow::Result<i32> {
+    run_up(config_path, system, app, false, ci).await?;
+
+    let outcome = cli::exec::ExecOutcome::default();
+    let exec_app =
+        cli::exec::build_exec_client_with_outcome(new_client(), request, outcome.clone());
+    exec_app.run().await;
+
+    if remove {
+        cli::destroy::build_destroy_client(new_client()).run().await;
+    } else {
+        cli::down::build_down_client(new_client()).run().await;
+    }
+
+    Ok(outcome.exit_code().unwrap_or(1))
+}
+
+/// Stop the machine and drop its working layer, or destroy it entirely when
+/// there is no provisioned layer to fall back to.
+async fn run_reset(
+    system: &SystemConfig,
+    new_client: impl Fn() -> ecsdk::app::AsyncApp<orchestrator::OrchestratorMessage>,
+) -> anyhow::Result<()> {
+    let socket_path = cli::ipc::socket_path(system);
+    if cli::ipc::connect(&socket_path).await.is_ok() {
+        cli::down::build_down_client(new_client()).run().await;
+    }
+
+    let driver = LibvirtDriver::new(system.clone());
+    if driver.reset_working_layer()? {
+        println!("reset to provisioned layer");
+        return Ok(());
+    }
+    run_destroy(system.clone(), new_client()).await
+}
+
+/// Bring the machine up (running system provisioning), shut it down, and
+/// flatten its overlay into the baked image picked up by later `up --reset`.
+async fn run_bake(
+    config_path: &Path,
+    system: &SystemConfig,
+    mut app: ecsdk::app::AsyncApp<orchestrator::OrchestratorMessage>,
+    ci: bool,
+    new_client: impl Fn() -> ecsdk::app::AsyncApp<orchestrator::OrchestratorMessage>,
+) -> anyhow::Result<()> {
+    let outcome = cli::client::UpOutcome::default();
+    app.insert_resource(outcome.clone());
+    run_up(config_path, system, app, false, ci).await?;
+    anyhow::ensure!(
+        outcome.running(),
+        "the machine did not come up provisioned; nothing was baked"
+    );
+    cli::down::build_down_client(new_client()).run().await;
+
+    let driver = LibvirtDriver::new(system.clone());
+    anyhow::ensure!(
+        driver.recover()? != InstanceState::Running,
+        "the machine is still running; nothing was baked"
+    );
+    let dest = machine::paths::baked_image_path(&system.id, system.name.as_deref());
+    machine::image::bake_overlay(
+        &driver.layout().overlay_path,
+        &dest,
+        &system.config.image.base,
+    )
+    .await?;
+    println!("baked image saved to {}", dest.display());
+    Ok(())
+}
+
+/// Point out a previous `rum up` that stopped midway, such as a daemon that
+/// crashed while booting. Only `--resume` reuses what that run resolved;
+/// otherwise the new run starts over and the journal is dropped.
+fn note_interrupted_run(system: &SystemConfig, resume: bool) {
+    let path = machine::paths::journal_path(&system.id, system.name.as_deref());
+    let Some(run) = machine::journal::interrupted_run(&path) else {
+        if resume {
+            eprintln!(
+                "no interrupted run of {} to resume; starting normally",
+                system.display_name()
+            );
+        }
+        return;
+    };
+    let phase = run.phase.to_lowercase();
+    if resume {
+        eprintln!(
+            "resuming the run of {} interrupted while {phase}",
+            system.display_name()
+        );
+    } else {
+        eprintln!(
+            "the previous run of {} was interrupted while {phase}; \
+             run `rum up --resume` to reuse the base image it resolved",
+            system.display_name()
+        );
+    }
+}
+
+async fn run_daemon(
+    config_path: &Path,
+    resume: bool,
+    log_file: &cli::logging::DeferredFileWriter,
+) -> anyhow::Result<()> {
+    let spec = cli::server::load_server_spec(config_path, resume).await?;
+    let system = &spec.system;
+    let log_path = machine::paths::daemon_log_path(&system.id, system.name.as_deref());
+    if let Err(error) = log_file.open(log_path, &system.config.logging) {
+        tracing::warn!(%error, "failed to open rum.log");
+    }
     let socket_path = spec.socket_path.clone();
     let control_socket_path = cli::ipc::control_socket_path(&spec.system);
     tokio::spawn(async move {
@@ -220,16 +946,19 @@ async fn run_daemon(config_path: &Path) -> anyhow::Result<()> {
     let socket_path = spec.socket_path.clone();
     let iso =
         cli::app::create_isomorphic_app(spec.socket_path.clone(), Arc::new(AtomicBool::new(false)));
-    let app = cli::server::build_up_server(iso, spec);
+    let notifications = cli::notify::PendingNotifications::default();
+    let app = cli::server::build_up_server(iso, spec, notifications.clone());
     app.run().await;
+    notifications.flush().await;
     let _ = std::fs::remove_file(socket_path);
     Ok(())
 }
 
 async fn run_down(
     app: ecsdk::app::AsyncApp<orchestrator::OrchestratorMessage>,
+    force: bool,
 ) -> anyhow::Result<()> {
-    let app = cli::down::build_down_client(app);
+    let app = cli::down::build_down_client_with(app, force);
     app.run().await;
     Ok(())
 }
@@ -255,18 +984,29 @@ async fn run_cp(
     app: ecsdk::app::AsyncApp<orchestrator::OrchestratorMessage>,
     src: &str,
     dst: &str,
+    compress: bool,
 ) -> anyhow::Result<()> {
-    let request = cli::cp::prepare_request(src, dst)?;
+    let request = cli::cp::prepare_request(src, dst, compress)?;
     let app = cli::cp::build_cp_client(app, request);
     app.run().await;
     Ok(())
 }
 
+async fn run_sync(
+    app: ecsdk::app::AsyncApp<orchestrator::OrchestratorMessage>,
+    src: &str,
+    dst: &str,
+) -> anyhow::Result<()> {
+    let request = cli::sync::prepare_request(src, dst)?;
+    let app = cli::sync::build_sync_client(app, request);
+    app.run().await;
+    Ok(())
+}
+
 async fn run_exec(
     app: ecsdk::app::AsyncApp<orchestrator::OrchestratorMessage>,
-    command: &[String],
+    request: cli::protocol::ExecRequest,
 ) -> anyhow::Result<()> {
-    let request = cli::exec::prepare_request(command)?;
     let app = cli::exec::build_exec_client(app, request);
     app.run().await;
     Ok(())
@@ -276,6 +1016,9 @@ async fn run_destroy(
     system: SystemConfig,
     app: ecsdk::app::AsyncApp<orchestrator::OrchestratorMessage>,
 ) -> anyhow::Result<()> {
+    // An installed unit would bring the machine back at the next boot; stopping
+    // it also stops the daemon it runs.
+    cli::daemon_unit::remove_installed(&system)?;
     let socket_path = cli::ipc::socket_path(&system);
 
     if cli::ipc::connect(&socket_path).await.is_err() {
@@ -290,16 +1033,23 @@ async fn run_destroy(
     Ok(())
 }
 
-async fn ensure_daemon(config_path: &Path, socket_path: &Path) -> anyhow::Result<()> {
+/// Start a daemon unless one is running; returns whether this call started it.
+async fn ensure_daemon(
+    config_path: &Path,
+    socket_path: &Path,
+    profile: Option<&str>,
+    resume: bool,
+    ci: bool,
+) -> anyhow::Result<bool> {
     if cli::ipc::connect(socket_path).await.is_ok() {
-        return Ok(());
+        return Ok(false);
     }
-    spawn_daemon(config_path)?;
+    spawn_daemon(config_path, profile, resume, ci)?;
 
     for _ in 0..50 {
         tokio::time::sleep(Duration::from_millis(100)).await;
         if cli::ipc::connect(socket_path).await.is_ok() {
-            return Ok(());
+            return Ok(true);
         }
     }
 
@@ -309,15 +1059,23 @@ async fn ensure_daemon(config_path: &Path, socket_path: &Path) -> anyhow::Result
     ))
 }
 
-async fn ensure_connected(config: &Path, system: &SystemConfig) -> anyhow::Result<()> {
+async fn ensure_connected(config: &Path, system: &SystemConfig, ci: bool) -> anyhow::Result<()> {
     let socket_path = cli::ipc::socket_path(system);
     return match cli::ipc::connect(&socket_path).await {
         Ok(_) => Ok(()),
-        Err(_) => maybe_restart_daemon(config, system).await,
+        Err(_) => maybe_restart_daemon(config, system, ci).await,
     };
 }
 
-fn spawn_daemon(config_path: &Path) -> anyhow::Result<()> {
+/// Start the daemon for `config_path` in the background. The profile is
+/// handed down through the environment, so config reloads keep using it;
+/// so are `--resume` and CI mode, which the daemon detects from `$CI`.
+fn spawn_daemon(
+    config_path: &Path,
+    profile: Option<&str>,
+    resume: bool,
+    ci: bool,
+) -> anyhow::Result<()> {
     let exe = std::env::current_exe()?;
     let config_dir = config_path
         .parent()
@@ -328,7 +1086,20 @@ fn spawn_daemon(config_path: &Path) -> anyhow::Result<()> {
     let config_name = config_path
         .file_name()
         .context(format!("invalid config path: {}", &config_path.display()))?;
-    std::process::Command::new(&exe)
+    let mut command = std::process::Command::new(&exe);
+    match profile {
+        Some(profile) => command.env(PROFILE_ENV, profile),
+        None => command.env_remove(PROFILE_ENV),
+    };
+    if resume {
+        command.env(INTERNAL_DAEMON_RESUME, "1");
+    } else {
+        command.env_remove(INTERNAL_DAEMON_RESUME);
+    }
+    if ci {
+        command.env("CI", "true");
+    }
+    command
         .current_dir(config_dir)
         .env(INTERNAL_DAEMON_CONFIG, config_name)
         .arg("daemon")
@@ -340,7 +1111,11 @@ fn spawn_daemon(config_path: &Path) -> anyhow::Result<()> {
     Ok(())
 }
 
-async fn maybe_restart_daemon(config_path: &Path, system: &SystemConfig) -> anyhow::Result<()> {
+async fn maybe_restart_daemon(
+    config_path: &Path,
+    system: &SystemConfig,
+    ci: bool,
+) -> anyhow::Result<()> {
     let control_socket_path = cli::ipc::control_socket_path(system);
     let main_socket_path = cli::ipc::socket_path(system);
     let pid = cli::control::shutdown_daemon(&control_socket_path)
@@ -348,7 +1123,7 @@ async fn maybe_restart_daemon(config_path: &Path, system: &SystemConfig) -> anyh
         .context("Failed to shut down daemon")?;
 
     wait_for_pid_exit(pid).await?;
-    spawn_daemon(config_path)?;
+    spawn_daemon(config_path, system.profile.as_deref(), false, ci)?;
 
     for _ in 0..50 {
         tokio::time::sleep(Duration::from_millis(100)).await;
@@ -0,0 +1,119 @@
+use std::process::Stdio;
+use std::time::Duration;
+
+use ecsdk::prelude::*;
+use ecsdk::tasks::SpawnTask;
+use machine::config::SystemConfig;
+use machine::driver::LibvirtDriver;
+use orchestrator::instance::instance_phase::{Running, ShuttingDown};
+use orchestrator::{HealthMonitor, ManagedInstance};
+use tokio::task::JoinHandle;
+
+/// How long to wait before looking up the guest's address and publishing
+/// it again, after that failed or `avahi-publish` exited.
+const RETRY_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Server-side plugin that advertises the guest as `<hostname>.local` over
+/// mDNS while the managed instance is running, with `network.mdns` set.
+pub struct MdnsPlugin;
+
+impl Plugin for MdnsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<MdnsAdvertisement>();
+        app.add_observer(start_on_running);
+        app.add_observer(stop_on_shutdown);
+    }
+}
+
+/// The task running `avahi-publish` for the running instance. Aborting it
+/// kills the process, which withdraws the record.
+#[derive(Resource, Default)]
+pub struct MdnsAdvertisement(Option<JoinHandle<()>>);
+
+fn start_on_running(
+    _trigger: On<Add, Running>,
+    // Back from Unhealthy, the record is still published.
+    instances: Query<&ManagedInstance<LibvirtDriver>, Without<HealthMonitor>>,
+    mut commands: Commands,
+) {
+    let Some(instance) = instances.iter().next() else {
+        return;
+    };
+    let driver = instance.driver();
+    if driver.system().config.network.mdns {
+        start(&mut commands, driver);
+    }
+}
+
+/// Advertise the guest the way `network.mdns` of `system` asks for from
+/// now on.
+pub fn reconfigure(world: &mut World, system: &SystemConfig) {
+    let Some(mut advertisement) = world.get_resource_mut::<MdnsAdvertisement>() else {
+        return;
+    };
+    if let Some(handle) = advertisement.0.take() {
+        handle.abort();
+    }
+    if system.config.network.mdns {
+        start(&mut world.commands(), LibvirtDriver::new(system.clone()));
+    }
+}
+
+fn start(commands: &mut Commands, driver: LibvirtDriver) {
+    commands.spawn_empty().spawn_task(move |task| async move {
+        let handle = tokio::spawn(advertise(driver));
+        task.queue_cmd_wake(move |world: &mut World| {
+            let mut advertisement = world.resource_mut::<MdnsAdvertisement>();
+            if let Some(previous) = advertisement.0.replace(handle) {
+                previous.abort();
+            }
+        });
+    });
+}
+
+/// Publish the guest's address through the host's avahi daemon until the
+/// task is aborted. The address is looked up again whenever `avahi-publish`
+/// exits, since the guest may have got a new one.
+async fn advertise(driver: LibvirtDriver) {
+    let name = driver.system().mdns_name();
+    loop {
+        match driver.guest_ip(None, false).await {
+            Ok(ip) => {
+                tracing::info!(name, ip, "advertising over mDNS");
+                // No reverse record: another machine may have had this
+                // address before.
+                let output = tokio::process::Command::new("avahi-publish")
+                    .args(["--address", "--no-reverse", &name, &ip])
+                    .stdout(Stdio::null())
+                    .stderr(Stdio::piped())
+                    .kill_on_drop(true)
+                    .output()
+                    .await;
+                match output {
+                    Ok(output) => {
+                        let stderr = String::from_utf8_lossy(&output.stderr);
+                        tracing::warn!(name, stderr = %stderr.trim(), "avahi-publish exited");
+                    }
+                    Err(error) if error.kind() == std::io::ErrorKind::NotFound => {
+                        tracing::warn!(
+                            name,
+                            "avahi-publish is not installed; install avahi-utils to use network.mdns"
+                        );
+                        return;
+                    }
+                    Err(error) => tracing::warn!(%error, name, "failed to run avahi-publish"),
+                }
+            }
+            Err(error) => {
+                tracing::warn!(%error, name, "no guest address to advertise over mDNS yet");
+            }
+        }
+        tokio::time::sleep(RETRY_INTERVAL).await;
+    }
+}
+
+fn stop_on_shutdown(_trigger: On<Add, ShuttingDown>, mut advertisement: ResMut<MdnsAdvertisement>) {
+    if let Some(handle) = advertisement.0.take() {
+        handle.abort();
+    }
+}
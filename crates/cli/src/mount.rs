@@ -0,0 +1,49 @@
+use anyhow::{Context, bail};
+use machine::config::{MountConfig, SystemConfig};
+use machine::driver::LibvirtDriver;
+
+/// Share a host directory with the running machine.
+///
+/// The share is recorded in the work dir, so later boots keep it until
+/// `rum umount` removes it again.
+pub async fn mount(
+    system: &SystemConfig,
+    source: &str,
+    target: &str,
+    readonly: bool,
+) -> anyhow::Result<()> {
+    if !target.starts_with('/') {
+        bail!("mount target '{target}' must be an absolute guest path");
+    }
+    let source = std::fs::canonicalize(source)
+        .with_context(|| format!("mount source '{source}' not found"))?;
+    if !source.is_dir() {
+        bail!("mount source '{}' is not a directory", source.display());
+    }
+
+    let mount = MountConfig {
+        source: source.display().to_string(),
+        target: target.to_string(),
+        readonly,
+        ..Default::default()
+    };
+    let resolved = LibvirtDriver::new(system.clone()).mount_live(mount).await?;
+    println!(
+        "mounted {} at {}",
+        resolved.source.display(),
+        resolved.target
+    );
+    Ok(())
+}
+
+/// Unmount and unplug a share from the running machine.
+pub async fn umount(system: &SystemConfig, target: &str) -> anyhow::Result<()> {
+    let live = LibvirtDriver::new(system.clone())
+        .umount_live(target)
+        .await?;
+    println!("unmounted {target}");
+    if !live {
+        println!("{target} is configured in rum.toml; it comes back on the next boot");
+    }
+    Ok(())
+}
@@ -0,0 +1,101 @@
+use ecsdk::prelude::*;
+use ecsdk::tasks::SpawnTask;
+use machine::config::MountDriver;
+use machine::driver::LibvirtDriver;
+use machine::paths;
+use orchestrator::{HealthMonitor, ManagedInstance};
+use orchestrator::instance::instance_phase::{Crashed, Running, ShuttingDown};
+use tokio::task::JoinHandle;
+
+/// Server-side plugin that runs the two-way sync of `mode = "sync"` mounts
+/// while the managed instance is running.
+pub struct MountSyncPlugin;
+
+impl Plugin for MountSyncPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ActiveSyncs>();
+        app.add_observer(start_on_running);
+        app.add_observer(stop_on_shutdown);
+        app.add_observer(stop_on_crash);
+    }
+}
+
+/// Sync tasks of the running instance, one per synced mount.
+#[derive(Resource, Default)]
+pub struct ActiveSyncs(Vec<JoinHandle<()>>);
+
+fn start_on_running(
+    _trigger: On<Add, Running>,
+    // Back from Unhealthy, the health monitor is already running and so
+    // are the syncs.
+    instances: Query<&ManagedInstance<LibvirtDriver>, Without<HealthMonitor>>,
+    mut commands: Commands,
+) {
+    let Some(instance) = instances.iter().next() else {
+        return;
+    };
+    let driver = instance.driver();
+    commands.spawn_empty().spawn_task(move |task| async move {
+        let handles = match start(&driver) {
+            Ok(handles) => handles,
+            Err(error) => {
+                tracing::warn!(%error, "failed to start mount sync");
+                return;
+            }
+        };
+        task.queue_cmd_wake(move |world: &mut World| {
+            world.resource_mut::<ActiveSyncs>().0.extend(handles);
+        });
+    });
+}
+
+/// Spawn a sync task for every `mode = "sync"` mount.
+fn start(driver: &LibvirtDriver) -> Result<Vec<JoinHandle<()>>, machine::error::Error> {
+    let system = driver.system();
+    let mounts: Vec<_> = system
+        .resolve_mounts()?
+        .into_iter()
+        .filter(|m| m.driver == MountDriver::Sync)
+        .collect();
+    if mounts.is_empty() {
+        return Ok(Vec::new());
+    }
+    let connector = driver.agent_connector()?;
+
+    let mut handles = Vec::new();
+    for mount in mounts {
+        let Some(config) = system
+            .config
+            .mounts
+            .iter()
+            .find(|m| m.target == mount.target)
+        else {
+            continue;
+        };
+        let options = config.fs_watch_options();
+        let state = paths::sync_state_path(&system.id, system.name.as_deref(), &mount.tag);
+        let target = mount.target.clone();
+        let connector = connector.clone();
+        handles.push(tokio::spawn(async move {
+            if let Err(error) = machine::sync::run(connector, mount, options, state).await {
+                tracing::warn!(target, %error, "mount sync stopped");
+            }
+        }));
+    }
+    Ok(handles)
+}
+
+fn stop_on_shutdown(_trigger: On<Add, ShuttingDown>, syncs: ResMut<ActiveSyncs>) {
+    stop_all(syncs);
+}
+
+/// The guest side of the syncs went down with a crashed guest.
+fn stop_on_crash(_trigger: On<Add, Crashed>, syncs: ResMut<ActiveSyncs>) {
+    stop_all(syncs);
+}
+
+fn stop_all(mut syncs: ResMut<ActiveSyncs>) {
+    for handle in syncs.0.drain(..) {
+        handle.abort();
+    }
+}
@@ -0,0 +1,70 @@
+use machine::config::SystemConfig;
+use machine::driver::LibvirtDriver;
+
+/// Subcommands of `rum net`.
+#[derive(clap::Subcommand, Clone, Copy, Debug)]
+pub enum NetCmd {
+    /// Print every derived network value for the current config.
+    Explain,
+}
+
+/// Run a local `rum net` subcommand.
+pub fn run(system: &SystemConfig, cmd: NetCmd) -> anyhow::Result<()> {
+    match cmd {
+        NetCmd::Explain => explain(system),
+    }
+}
+
+fn explain(system: &SystemConfig) -> anyhow::Result<()> {
+    let driver = LibvirtDriver::new(system.clone());
+    let network = &system.config.network;
+
+    println!("vm:       {}", system.display_name());
+    println!("hostname: {}", system.hostname());
+    println!(
+        "nat:      {}",
+        if network.nat {
+            "libvirt network 'default' (no fixed MAC)"
+        } else {
+            "disabled"
+        }
+    );
+
+    let interfaces = driver.explain_networks()?;
+    if interfaces.is_empty() {
+        println!("no extra interfaces configured");
+        return Ok(());
+    }
+
+    for iface in &interfaces {
+        println!();
+        println!("interface {} ({})", iface.index, iface.network);
+        let shared = if iface.shared { " (shared)" } else { "" };
+        println!("  libvirt network: {}{shared}", iface.libvirt_name);
+        println!("  mac:             {}", iface.mac);
+        println!(
+            "  subnet:          {}.0/24 ({})",
+            iface.subnet, iface.subnet_source
+        );
+        println!("  gateway:         {}.1", iface.subnet);
+        println!("  dhcp range:      {}", iface.dhcp_range);
+        if let Some(prefix) = &iface.ipv6_prefix {
+            println!("  ipv6 prefix:     {prefix}");
+        }
+        if let Some(domain) = &iface.domain {
+            println!("  dns domain:      {domain}");
+        }
+        for host in &iface.hosts {
+            println!("  dns host:        {host}");
+        }
+        match &iface.reservation {
+            Some(reservation) => println!("  reservation:     {reservation}"),
+            None => println!("  reservation:     none (dynamic lease)"),
+        }
+        if let Some(conflict) = &iface.conflict {
+            println!("  CONFLICT:        subnet also used by libvirt network '{conflict}'");
+        }
+    }
+
+    Ok(())
+}
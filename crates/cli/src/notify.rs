@@ -0,0 +1,301 @@
+//! Desktop notifications and webhooks sent by the daemon, set up with
+//! `[notify]`. Only the daemon reads the section, so `rum reload` applies it.
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use ecsdk::prelude::*;
+use facet::Facet;
+use machine::config::{NotifyConfig, SystemConfig};
+use orchestrator::instance::instance_phase::{Crashed, Failed, Running, Stopped, Unhealthy};
+use orchestrator::{EntityError, HealthMonitor};
+use tokio::task::JoinHandle;
+
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Notifications still being sent. The daemon exits right after the machine
+/// stops or fails, so it waits for these with [`PendingNotifications::flush`]
+/// first.
+#[derive(Resource, Clone, Default)]
+pub struct PendingNotifications(Arc<Mutex<Vec<JoinHandle<()>>>>);
+
+impl PendingNotifications {
+    fn push(&self, handle: JoinHandle<()>) {
+        let mut pending = self.0.lock().expect("notification lock poisoned");
+        pending.retain(|handle| !handle.is_finished());
+        pending.push(handle);
+    }
+
+    /// Wait for the notifications in flight, at most [`WEBHOOK_TIMEOUT`].
+    pub async fn flush(&self) {
+        let pending = std::mem::take(&mut *self.0.lock().expect("notification lock poisoned"));
+        let deadline = tokio::time::Instant::now() + WEBHOOK_TIMEOUT;
+        for handle in pending {
+            let _ = tokio::time::timeout_at(deadline, handle).await;
+        }
+    }
+}
+
+/// Server-side plugin sending the notifications `[notify]` asks for.
+pub struct NotifyPlugin {
+    config: NotifyConfig,
+    label: String,
+    pending: PendingNotifications,
+}
+
+impl NotifyPlugin {
+    /// The plugin for `system`; it sends nothing until `[notify]` turns a
+    /// notification on.
+    pub fn from_system(system: &SystemConfig, pending: PendingNotifications) -> Self {
+        Self {
+            config: system.config.notify.clone(),
+            label: system.display_name(),
+            pending,
+        }
+    }
+}
+
+/// Send the notifications the `[notify]` of `system` asks for from now on.
+pub fn reconfigure(world: &mut World, system: &SystemConfig) {
+    if let Some(mut notifier) = world.get_resource_mut::<Notifier>() {
+        notifier.config = system.config.notify.clone();
+        notifier.label = system.display_name();
+    }
+}
+
+impl Plugin for NotifyPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(Notifier {
+            config: self.config.clone(),
+            label: self.label.clone(),
+        });
+        app.insert_resource(self.pending.clone());
+        app.add_observer(notify_running);
+        app.add_observer(notify_stopped);
+        app.add_observer(notify_failed);
+        app.add_observer(notify_crashed);
+        app.add_observer(notify_unhealthy);
+    }
+}
+
+#[derive(Resource)]
+struct Notifier {
+    config: NotifyConfig,
+    label: String,
+}
+
+/// Slack-compatible webhook payload.
+#[derive(Facet)]
+struct WebhookBody {
+    text: String,
+}
+
+fn notify_running(
+    trigger: On<Add, Running>,
+    monitors: Query<(), With<HealthMonitor>>,
+    notifier: Res<Notifier>,
+    pending: Res<PendingNotifications>,
+) {
+    if monitors.get(trigger.event_target()).is_ok() {
+        // Back from Unhealthy rather than freshly provisioned.
+        return;
+    }
+    let text = format!("{} is up: provisioning finished", notifier.label);
+    send(&notifier, &pending, text, true);
+}
+
+fn notify_stopped(
+    _trigger: On<Add, Stopped>,
+    notifier: Res<Notifier>,
+    pending: Res<PendingNotifications>,
+) {
+    let text = format!("{} stopped", notifier.label);
+    send(&notifier, &pending, text, false);
+}
+
+fn notify_failed(
+    trigger: On<Add, Failed>,
+    errors: Query<&EntityError>,
+    notifier: Res<Notifier>,
+    pending: Res<PendingNotifications>,
+) {
+    let text = match errors.get(trigger.event_target()) {
+        Ok(error) => format!("{} failed: {error}", notifier.label),
+        Err(_) => format!("{} failed", notifier.label),
+    };
+    send(&notifier, &pending, text, true);
+}
+
+fn notify_crashed(
+    trigger: On<Add, Crashed>,
+    errors: Query<&EntityError>,
+    notifier: Res<Notifier>,
+    pending: Res<PendingNotifications>,
+) {
+    let text = match errors.get(trigger.event_target()) {
+        Ok(error) => format!("{} crashed: {error}", notifier.label),
+        Err(_) => format!("{} crashed", notifier.label),
+    };
+    send(&notifier, &pending, text, true);
+}
+
+fn notify_unhealthy(
+    _trigger: On<Add, Unhealthy>,
+    notifier: Res<Notifier>,
+    pending: Res<PendingNotifications>,
+) {
+    let text = format!(
+        "{} is unhealthy: the guest agent stopped answering",
+        notifier.label
+    );
+    send(&notifier, &pending, text, true);
+}
+
+/// Send `text` to every webhook, and to the desktop if `desktop` and the
+/// config asks for it.
+fn send(notifier: &Notifier, pending: &PendingNotifications, text: String, desktop: bool) {
+    if desktop && notifier.config.desktop {
+        let text = text.clone();
+        pending.push(tokio::task::spawn_blocking(move || {
+            let shown = notify_rust::Notification::new()
+                .summary("rum")
+                .body(&text)
+                .show();
+            if let Err(error) = shown {
+                tracing::warn!(%error, "failed to show desktop notification");
+            }
+        }));
+    }
+
+    if notifier.config.webhooks.is_empty() {
+        return;
+    }
+    let body = facet_json::to_string(&WebhookBody { text });
+    for url in notifier.config.webhooks.clone() {
+        let body = body.clone();
+        pending.push(tokio::spawn(async move {
+            if let Err(error) = post(&url, body).await {
+                tracing::warn!(%error, %url, "failed to send webhook");
+            }
+        }));
+    }
+}
+
+async fn post(url: &str, body: String) -> reqwest::Result<()> {
+    reqwest::Client::new()
+        .post(url)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .timeout(WEBHOOK_TIMEOUT)
+        .body(body)
+        .send()
+        .await?
+        .error_for_status()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    /// Accept one webhook POST and return its body.
+    async fn receive_webhook(listener: TcpListener) -> String {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut reader = BufReader::new(stream);
+        let mut content_length = 0;
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).await.unwrap();
+            let line = line.trim_end().to_ascii_lowercase();
+            if line.is_empty() {
+                break;
+            }
+            if let Some(length) = line.strip_prefix("content-length:") {
+                content_length = length.trim().parse().unwrap();
+            }
+        }
+        let mut body = vec![0; content_length];
+        reader.read_exact(&mut body).await.unwrap();
+        reader
+            .get_mut()
+            .write_all(b"HTTP/1.1 204 No Content\r\nContent-Length: 0\r\n\r\n")
+            .await
+            .unwrap();
+        String::from_utf8(body).unwrap()
+    }
+
+    fn app(config: NotifyConfig, pending: &PendingNotifications) -> App {
+        let mut app = App::new();
+        app.add_plugins(NotifyPlugin {
+            config,
+            label: "dev".into(),
+            pending: pending.clone(),
+        });
+        app
+    }
+
+    fn webhook_config(listener: &TcpListener) -> NotifyConfig {
+        NotifyConfig {
+            desktop: false,
+            webhooks: vec![format!("http://{}/hook", listener.local_addr().unwrap())],
+        }
+    }
+
+    #[tokio::test]
+    async fn failures_are_posted_with_their_error() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let pending = PendingNotifications::default();
+        let mut app = app(webhook_config(&listener), &pending);
+
+        let received = tokio::spawn(receive_webhook(listener));
+        app.world_mut()
+            .spawn(EntityError::message("boot timed out"))
+            .insert(Failed);
+        pending.flush().await;
+        assert_eq!(
+            received.await.unwrap(),
+            r#"{"text":"dev failed: boot timed out"}"#
+        );
+    }
+
+    #[tokio::test]
+    async fn recovering_from_unhealthy_is_not_announced_as_up() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let pending = PendingNotifications::default();
+        let mut app = app(webhook_config(&listener), &pending);
+
+        let received = tokio::spawn(receive_webhook(listener));
+        let entity = app
+            .world_mut()
+            .spawn(HealthMonitor(Default::default()))
+            .insert(Running)
+            .id();
+        assert!(pending.0.lock().unwrap().is_empty());
+
+        app.world_mut().entity_mut(entity).remove::<HealthMonitor>();
+        app.world_mut().entity_mut(entity).remove::<Running>();
+        app.world_mut().entity_mut(entity).insert(Running);
+        pending.flush().await;
+        assert_eq!(
+            received.await.unwrap(),
+            r#"{"text":"dev is up: provisioning finished"}"#
+        );
+    }
+
+    #[tokio::test]
+    async fn nothing_is_sent_until_a_reload_turns_it_on() {
+        let pending = PendingNotifications::default();
+        let mut app = app(NotifyConfig::default(), &pending);
+        app.world_mut().spawn(Stopped);
+        assert!(pending.0.lock().unwrap().is_empty());
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        app.world_mut().resource_mut::<Notifier>().config = webhook_config(&listener);
+        let received = tokio::spawn(receive_webhook(listener));
+        app.world_mut().spawn(Stopped);
+        pending.flush().await;
+        assert_eq!(received.await.unwrap(), r#"{"text":"dev stopped"}"#);
+    }
+}
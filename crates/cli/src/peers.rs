@@ -0,0 +1,113 @@
+use std::time::Duration;
+
+use ecsdk::prelude::*;
+use ecsdk::tasks::SpawnTask;
+use guest::agent::{PEER_HOSTS_PROTOCOL, PeerHost};
+use machine::driver::LibvirtDriver;
+use orchestrator::instance::instance_phase::{Running, ShuttingDown};
+use orchestrator::{HealthMonitor, ManagedInstance};
+use tokio::task::JoinHandle;
+
+use crate::agent::SharedAgent;
+
+/// How often the other machines on the shared networks are looked up again,
+/// so one that started or changed its address shows up in `/etc/hosts`.
+const REFRESH_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Server-side plugin that keeps the guest's `/etc/hosts` records of the
+/// other rum machines on its shared networks current while it runs.
+pub struct PeerHostsPlugin;
+
+impl Plugin for PeerHostsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<SharedAgent>();
+        app.init_resource::<PeerRefresh>();
+        app.add_observer(start_on_running);
+        app.add_observer(stop_on_shutdown);
+    }
+}
+
+/// The refresh task of the running instance, if it has shared networks.
+#[derive(Resource, Default)]
+pub struct PeerRefresh(Option<JoinHandle<()>>);
+
+fn start_on_running(
+    _trigger: On<Add, Running>,
+    // Back from Unhealthy, the refresh task is still running.
+    instances: Query<&ManagedInstance<LibvirtDriver>, Without<HealthMonitor>>,
+    agent: Res<SharedAgent>,
+    mut commands: Commands,
+) {
+    let Some(instance) = instances.iter().next() else {
+        return;
+    };
+    let driver = instance.driver();
+    let network = &driver.system().config.network;
+    if !network.peer_hosts || network.interfaces.iter().all(|i| i.subnet.is_empty()) {
+        return;
+    }
+    let agent = agent.clone();
+    commands.spawn_empty().spawn_task(move |task| async move {
+        let handle = tokio::spawn(refresh(driver, agent));
+        task.queue_cmd_wake(move |world: &mut World| {
+            let mut refresh = world.resource_mut::<PeerRefresh>();
+            if let Some(previous) = refresh.0.replace(handle) {
+                previous.abort();
+            }
+        });
+    });
+}
+
+/// Push the peers to the guest right away, then whenever they differ from
+/// what it last got; a peer that stopped drops out on the next refresh.
+/// Agents too old to take them are left alone.
+async fn refresh(driver: LibvirtDriver, agent: SharedAgent) {
+    let mut pushed: Option<Vec<PeerHost>> = None;
+    let mut interval = tokio::time::interval(REFRESH_INTERVAL);
+    loop {
+        interval.tick().await;
+        let peers = match driver.peer_hosts() {
+            Ok(peers) => peers,
+            Err(error) => {
+                tracing::debug!(%error, "failed to look up peer machines");
+                continue;
+            }
+        };
+        if pushed.as_ref() == Some(&peers) {
+            continue;
+        }
+        let client = match agent.client(&driver).await {
+            Ok(client) => client,
+            Err(error) => {
+                tracing::warn!(%error, "failed to update peer hosts");
+                continue;
+            }
+        };
+        if let Ok(protocol) = client.rpc().protocol_version().await
+            && protocol < PEER_HOSTS_PROTOCOL
+        {
+            tracing::info!(
+                protocol,
+                "the guest agent cannot keep peer hosts; run `rum agent update`"
+            );
+            return;
+        }
+        let result = client
+            .set_peer_hosts(peers.clone())
+            .await
+            .map_err(|error| error.to_string());
+        match result {
+            Ok(()) => {
+                tracing::debug!(peers = peers.len(), "updated peer hosts");
+                pushed = Some(peers);
+            }
+            Err(error) => tracing::warn!(%error, "failed to update peer hosts"),
+        }
+    }
+}
+
+fn stop_on_shutdown(_trigger: On<Add, ShuttingDown>, mut refresh: ResMut<PeerRefresh>) {
+    if let Some(handle) = refresh.0.take() {
+        handle.abort();
+    }
+}
@@ -0,0 +1,142 @@
+use machine::config::{MountDriver, SystemConfig};
+use machine::driver::LibvirtDriver;
+use machine::image::{baked_image, is_cached, local_path};
+use machine::instance::Instance;
+use machine::paths;
+use orchestrator::{InstancePhase, planned_phases};
+
+/// Print what `rum up` would do for `system`, without changing anything.
+///
+/// The plan starts from the state recovered from disk and libvirt and
+/// assumes every step succeeds.
+pub async fn run(system: &SystemConfig) -> anyhow::Result<()> {
+    if crate::ipc::connect(&crate::ipc::socket_path(system))
+        .await
+        .is_ok()
+    {
+        println!(
+            "{} is managed by a running daemon; `rum up` attaches to it",
+            system.display_name()
+        );
+        return Ok(());
+    }
+
+    let instance = Instance::new(system.clone());
+    let state = instance.recover()?;
+    println!("{}: {state}", system.display_name());
+    for phase in planned_phases(state) {
+        println!("{}", phase.label());
+        for step in phase_steps(&instance, phase)? {
+            println!("  - {step}");
+        }
+    }
+    Ok(())
+}
+
+fn phase_steps(
+    instance: &Instance<LibvirtDriver>,
+    phase: InstancePhase,
+) -> anyhow::Result<Vec<String>> {
+    let system = instance.system();
+    let layout = instance.layout();
+    let config = &system.config;
+    let mut steps = Vec::new();
+    match phase {
+        InstancePhase::Preparing => {
+            steps.push(base_image_step(system));
+            if !layout.overlay_path.exists() {
+                steps.push(format!(
+                    "create overlay {} ({})",
+                    layout.overlay_path.display(),
+                    config.resources.disk
+                ));
+            }
+            for drive in system.resolve_drives()? {
+                if !drive.external && !drive.path.exists() {
+                    steps.push(format!("create drive '{}' ({})", drive.name, drive.size));
+                }
+            }
+            steps.push("write cloud-init seed".into());
+            steps.push(format!("define libvirt domain '{}'", system.display_name()));
+        }
+        InstancePhase::Booting => {
+            steps.push(format!("start libvirt domain '{}'", system.display_name()));
+        }
+        InstancePhase::ConnectingGuest => {
+            steps.push("connect to the guest agent".into());
+            if system
+                .resolve_mounts()?
+                .iter()
+                .any(|m| m.driver.fs_options().is_some())
+            {
+                steps.push("verify guest mounts".into());
+            }
+        }
+        InstancePhase::Provisioning => {
+            for script in crate::server::build_provision_plan(system, None, false)? {
+                let when = match script.run_on {
+                    guest::agent::RunOn::System => "once",
+                    guest::agent::RunOn::Boot => "every boot",
+                };
+                steps.push(format!("run '{}' ({when})", script.title));
+            }
+            if !config.ready.is_empty() {
+                steps.push(format!(
+                    "wait up to {}s for the readiness probes",
+                    config.ready.timeout_s
+                ));
+            }
+        }
+        InstancePhase::Running => {
+            for port in &config.ports {
+                steps.push(format!(
+                    "forward {}:{} to guest port {}",
+                    port.bind_addr(),
+                    port.host,
+                    port.guest
+                ));
+            }
+            if let Some(forward) = system.docker_forward() {
+                steps.push(format!(
+                    "forward {} to guest socket {}",
+                    forward.host.display(),
+                    forward.guest
+                ));
+            }
+            for mount in system.resolve_mounts()? {
+                if mount.driver == MountDriver::Sync {
+                    steps.push(format!(
+                        "sync {} with {}",
+                        mount.source.display(),
+                        mount.target
+                    ));
+                }
+            }
+        }
+        InstancePhase::Failed => {
+            steps.push(
+                "fail: the machine is running with an outdated config; `rum down` it first".into(),
+            );
+        }
+        InstancePhase::Recovering
+        | InstancePhase::Unhealthy
+        | InstancePhase::Crashed
+        | InstancePhase::ShuttingDown
+        | InstancePhase::Stopped => {}
+    }
+    Ok(steps)
+}
+
+fn base_image_step(system: &SystemConfig) -> String {
+    let base = &system.config.image.base;
+    let baked = paths::baked_image_path(&system.id, system.name.as_deref());
+    if let Some(path) = baked_image(&baked, base) {
+        format!("use baked image {}", path.display())
+    } else if let Some(path) = local_path(base) {
+        format!("use local image {}", path.display())
+    } else if is_cached(base, &paths::cache_dir()) {
+        format!("use cached image {base}")
+    } else {
+        format!("download {base}")
+    }
+}
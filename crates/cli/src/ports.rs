@@ -0,0 +1,122 @@
+use ecsdk::prelude::*;
+use ecsdk::tasks::SpawnTask;
+use machine::config::PortForward;
+use machine::driver::LibvirtDriver;
+use orchestrator::{HealthMonitor, ManagedInstance};
+use orchestrator::instance::instance_phase::{Crashed, Running, ShuttingDown};
+use tokio::task::JoinHandle;
+
+/// Server-side plugin that keeps the configured `[[ports]]` forwarded while
+/// the managed instance is running.
+pub struct PortForwardPlugin;
+
+impl Plugin for PortForwardPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ActiveForwards>();
+        app.add_observer(start_on_running);
+        app.add_observer(stop_on_shutdown);
+        app.add_observer(stop_on_crash);
+    }
+}
+
+/// One listening forward and the task accepting its connections.
+pub struct ActiveForward {
+    pub spec: PortForward,
+    handle: JoinHandle<()>,
+}
+
+/// Port forwards the daemon currently listens on.
+#[derive(Resource, Default)]
+pub struct ActiveForwards(pub Vec<ActiveForward>);
+
+impl ActiveForwards {
+    /// Stop every forward matching `spec`.
+    pub fn stop(&mut self, spec: &PortForward) {
+        self.0.retain(|forward| {
+            let keep = !same_forward(&forward.spec, spec);
+            if !keep {
+                forward.handle.abort();
+            }
+            keep
+        });
+    }
+
+    pub fn stop_all(&mut self) {
+        for forward in self.0.drain(..) {
+            forward.handle.abort();
+        }
+    }
+}
+
+/// Whether two forwards listen on the same address and target the same
+/// guest port.
+pub fn same_forward(a: &PortForward, b: &PortForward) -> bool {
+    a.host == b.host && a.guest == b.guest && a.bind_addr() == b.bind_addr()
+}
+
+/// Start listening for each of `ports`. Forwards that fail to bind are
+/// reported in the returned errors instead of stopping the others.
+pub async fn start(
+    driver: &LibvirtDriver,
+    ports: Vec<PortForward>,
+) -> (Vec<ActiveForward>, Vec<String>) {
+    let mut started = Vec::new();
+    let mut errors = Vec::new();
+    if ports.is_empty() {
+        return (started, errors);
+    }
+    let cid = match driver.get_vsock_cid() {
+        Ok(cid) => cid,
+        Err(error) => return (started, vec![error.to_string()]),
+    };
+    for spec in ports {
+        match machine::guest::start_port_forwards(cid, std::slice::from_ref(&spec)).await {
+            Ok(handles) => {
+                tracing::info!(
+                    bind = spec.bind_addr(),
+                    host = spec.host,
+                    guest = spec.guest,
+                    "forwarding port"
+                );
+                started.extend(handles.into_iter().map(|handle| ActiveForward {
+                    spec: spec.clone(),
+                    handle,
+                }));
+            }
+            Err(error) => errors.push(error.to_string()),
+        }
+    }
+    (started, errors)
+}
+
+fn start_on_running(
+    _trigger: On<Add, Running>,
+    // Back from Unhealthy, the health monitor is already running and so
+    // are the forwards.
+    instances: Query<&ManagedInstance<LibvirtDriver>, Without<HealthMonitor>>,
+    mut commands: Commands,
+) {
+    let Some(instance) = instances.iter().next() else {
+        return;
+    };
+    let driver = instance.driver();
+    commands.spawn_empty().spawn_task(move |task| async move {
+        let ports = driver.system().config.ports.clone();
+        let (started, errors) = start(&driver, ports).await;
+        for error in errors {
+            tracing::warn!(%error, "failed to start port forward");
+        }
+        task.queue_cmd_wake(move |world: &mut World| {
+            world.resource_mut::<ActiveForwards>().0.extend(started);
+        });
+    });
+}
+
+fn stop_on_shutdown(_trigger: On<Add, ShuttingDown>, mut forwards: ResMut<ActiveForwards>) {
+    forwards.stop_all();
+}
+
+/// A crashed guest has nothing left to forward to.
+fn stop_on_crash(_trigger: On<Add, Crashed>, mut forwards: ResMut<ActiveForwards>) {
+    forwards.stop_all();
+}
@@ -2,13 +2,17 @@ use std::path::PathBuf;
 
 use ecsdk::prelude::*;
 use machine::instance::InstanceState;
-use orchestrator::InstancePhase;
+use machine::test_run::TestOutcome;
+use orchestrator::{EntityError, InstancePhase};
 use serde::{Deserialize, Serialize};
 
 /// Client requests that the daemon shut down the managed machine.
-#[derive(Default, Event, ClientRequest, Serialize, Deserialize)]
+#[derive(Default, Clone, Event, ClientRequest, Serialize, Deserialize)]
 #[request(response = "DownResponse")]
-pub struct DownRequest;
+pub struct DownRequest {
+    /// Abandon the lifecycle work in flight instead of waiting for it.
+    pub force: bool,
+}
 
 /// Server acknowledges a shutdown request.
 #[derive(Event, Serialize, Deserialize)]
@@ -16,6 +20,31 @@ pub struct DownResponse {
     pub accepted: bool,
 }
 
+/// Client requests that the daemon abandon the boot or provisioning run in
+/// flight and stop the managed machine.
+#[derive(Default, Clone, Event, ClientRequest, Serialize, Deserialize)]
+#[request(response = "CancelWorkResponse")]
+pub struct CancelWorkRequest;
+
+/// Server answers a cancel request; `message` says why nothing was cancelled.
+#[derive(Event, Serialize, Deserialize)]
+pub struct CancelWorkResponse {
+    pub cancelled: bool,
+    pub message: Option<String>,
+}
+
+/// Client requests that the daemon reboot the managed machine in place.
+#[derive(Default, Event, ClientRequest, Serialize, Deserialize)]
+#[request(response = "RebootResponse")]
+pub struct RebootRequest;
+
+/// Server acknowledges a reboot request.
+#[derive(Event, Serialize, Deserialize)]
+pub struct RebootResponse {
+    pub accepted: bool,
+    pub message: Option<String>,
+}
+
 /// Client requests that the daemon destroy the managed machine and purge its
 /// persisted state directory.
 #[derive(Default, Event, ClientRequest, Serialize, Deserialize)]
@@ -40,6 +69,8 @@ pub enum CopySpec {
 #[request(response = "CopyResponse")]
 pub struct CopyRequest {
     pub spec: Option<CopySpec>,
+    /// zstd-compress the chunks sent over vsock.
+    pub compress: bool,
 }
 
 /// Result of a file-copy request handled by the daemon.
@@ -49,12 +80,38 @@ pub struct CopyResponse {
     pub message: String,
 }
 
+/// Client requests that the daemon sync a host directory into the guest,
+/// transferring only files whose content changed.
+#[derive(Default, Clone, Event, ClientRequest, Serialize, Deserialize)]
+#[request(response = "SyncResponse")]
+pub struct SyncRequest {
+    pub local: Option<PathBuf>,
+    pub guest: String,
+}
+
+/// Result of a sync request handled by the daemon.
+#[derive(Event, Serialize, Deserialize)]
+pub struct SyncResponse {
+    pub success: bool,
+    pub message: String,
+}
+
 /// Client requests that the daemon execute a shell command in the managed
 /// guest and stream its output through the replicated log pipeline.
 #[derive(Default, Clone, Event, ClientRequest, Serialize, Deserialize)]
 #[request(response = "ExecResponse")]
 pub struct ExecRequest {
     pub command: Option<String>,
+    /// Guest user to run the command as. Defaults to root.
+    pub user: Option<String>,
+    /// Guest working directory for the command.
+    pub workdir: Option<String>,
+    /// Extra environment variables as `(name, value)` pairs.
+    pub env: Vec<(String, String)>,
+    /// Kill the command after this many seconds.
+    pub timeout_s: Option<u64>,
+    /// Id a [`CancelRequest`] stops the command by.
+    pub execution_id: String,
 }
 
 /// Final result of a guest exec request handled by the daemon.
@@ -65,6 +122,67 @@ pub struct ExecResponse {
     pub message: Option<String>,
 }
 
+/// Client requests that the daemon run the provisioning plan again on the
+/// running guest, streaming its output like an exec request.
+#[derive(Default, Clone, Event, ClientRequest, Serialize, Deserialize)]
+#[request(response = "ProvisionResponse")]
+pub struct ProvisionRequest {
+    /// Id a [`CancelRequest`] stops the run by.
+    pub execution_id: String,
+    /// Run only the plan step with this name.
+    pub only: Option<String>,
+    /// Include the steps that are unchanged since their last run.
+    pub force: bool,
+    /// Run this script instead of the plan.
+    pub script: Option<AdHocScript>,
+}
+
+/// A host script `rum provision --script` runs once in the guest.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct AdHocScript {
+    /// Name its logs are kept under.
+    pub name: String,
+    pub content: String,
+}
+
+/// Final result of a provisioning request handled by the daemon.
+#[derive(Event, Serialize, Deserialize)]
+pub struct ProvisionResponse {
+    pub success: bool,
+    pub message: Option<String>,
+}
+
+/// Client requests that the daemon run the `[[tests]]` checks in the guest,
+/// streaming their results like an exec request.
+#[derive(Default, Clone, Event, ClientRequest, Serialize, Deserialize)]
+#[request(response = "TestResponse")]
+pub struct TestRequest {
+    /// Run only the tests with these names; every test when empty.
+    pub names: Vec<String>,
+}
+
+/// How each requested test ended, or why none ran.
+#[derive(Event, Serialize, Deserialize)]
+pub struct TestResponse {
+    pub outcomes: Vec<TestOutcome>,
+    pub message: Option<String>,
+}
+
+/// Client requests that the daemon stop the guest command it started for an
+/// exec or provisioning request.
+#[derive(Default, Clone, Event, ClientRequest, Serialize, Deserialize)]
+#[request(response = "CancelResponse")]
+pub struct CancelRequest {
+    pub execution_id: String,
+}
+
+/// Result of a cancel request handled by the daemon.
+#[derive(Event, Serialize, Deserialize)]
+pub struct CancelResponse {
+    /// Whether the command was still running in the guest.
+    pub cancelled: bool,
+}
+
 /// Client requests a one-shot status snapshot from the daemon.
 #[derive(Default, Event, ClientRequest, Serialize, Deserialize)]
 #[request(response = "StatusResponse")]
@@ -77,5 +195,24 @@ pub struct StatusResponse {
     pub label: Option<String>,
     pub recovered_state: Option<InstanceState>,
     pub phase: Option<InstancePhase>,
-    pub error: Option<String>,
+    pub error: Option<EntityError>,
+    /// `DOCKER_HOST` of the `[docker]` engine, when enabled.
+    pub docker_host: Option<String>,
+}
+
+/// Client requests that the daemon re-read the config file and apply the
+/// changes that do not need a reboot.
+#[derive(Default, Clone, Event, ClientRequest, Serialize, Deserialize)]
+#[request(response = "ReloadResponse")]
+pub struct ReloadRequest {
+    /// Absolute path of the config file to reload.
+    pub config: Option<PathBuf>,
+}
+
+/// Result of a reload handled by the daemon.
+#[derive(Event, Serialize, Deserialize)]
+pub struct ReloadResponse {
+    pub success: bool,
+    /// One line per applied change or problem.
+    pub changes: Vec<String>,
 }
@@ -0,0 +1,367 @@
+use ecsdk::app::AsyncApp;
+use ecsdk::network::{InitialConnection, IsomorphicPlugin};
+use ecsdk::prelude::*;
+use ecsdk::tasks::SpawnTask;
+use machine::driver::LibvirtDriver;
+use orchestrator::{
+    InstancePhase, LogBuffer, ManagedInstance, OrchestratorMessage, ProvisionLogView,
+};
+
+use anyhow::Context;
+use guest::agent::{ProvisionMode, ProvisionScript, RunOn};
+
+use crate::agent::SharedAgent;
+use crate::protocol::{AdHocScript, ProvisionRequest, ProvisionResponse};
+use crate::render::RenderStyle;
+
+/// Shared request feature for re-running provisioning through the daemon.
+pub struct ProvisionFeature;
+
+impl IsomorphicPlugin for ProvisionFeature {
+    fn build_shared(&self, app: &mut App) {
+        ProvisionRequest::register(app);
+    }
+
+    fn build_server(&self, app: &mut App) {
+        app.init_resource::<SharedAgent>();
+        app.add_observer(handle_provision_request);
+    }
+
+    fn build_client(&self, app: &mut App) {
+        app.add_observer(handle_provision_response);
+        app.add_systems(Update, crate::exit::on_server_disconnect);
+    }
+}
+
+/// The request for `rum provision`, reading the `--script` file on the host.
+pub fn prepare_request(
+    only: Option<String>,
+    force: bool,
+    script: Option<&std::path::Path>,
+) -> anyhow::Result<ProvisionRequest> {
+    let script = script
+        .map(|path| {
+            let content = std::fs::read_to_string(path)
+                .with_context(|| format!("reading {}", path.display()))?;
+            let name = script_name(
+                &path
+                    .file_stem()
+                    .map(|stem| stem.to_string_lossy().into_owned())
+                    .unwrap_or_default(),
+            );
+            anyhow::Ok(AdHocScript { name, content })
+        })
+        .transpose()?;
+    Ok(ProvisionRequest {
+        execution_id: crate::cancel::new_execution_id(),
+        only,
+        force,
+        script,
+    })
+}
+
+/// The name an ad-hoc script runs under. The guest names its log after it,
+/// so anything outside `[a-zA-Z0-9._-]` becomes `-` and it starts with a
+/// letter or digit.
+fn script_name(stem: &str) -> String {
+    let name: String = stem
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-') {
+                c
+            } else {
+                '-'
+            }
+        })
+        .collect();
+    let name = name.trim_start_matches(|c: char| !c.is_ascii_alphanumeric());
+    if name.is_empty() {
+        "script".into()
+    } else {
+        name.into()
+    }
+}
+
+/// Client request state used to send the provisioning request on the
+/// initial daemon connection.
+#[derive(Resource, Clone)]
+struct PendingProvisionRequest(ProvisionRequest);
+
+/// Build the client app used by `rum provision`.
+pub fn build_provision_client(
+    mut app: AsyncApp<OrchestratorMessage>,
+    request: ProvisionRequest,
+) -> AsyncApp<OrchestratorMessage> {
+    crate::cancel::cancel_on_ctrl_c(&mut app, request.execution_id.clone());
+    app.insert_resource(PendingProvisionRequest(request));
+    app.add_observer(send_provision_request_on_connect);
+    app
+}
+
+fn send_provision_request_on_connect(
+    _trigger: On<Add, InitialConnection>,
+    request: Res<PendingProvisionRequest>,
+    mut commands: Commands,
+) {
+    commands.client_trigger(request.0.clone());
+}
+
+fn handle_provision_request(
+    trigger: On<FromClient<ProvisionRequest>>,
+    instances: Query<(Entity, &ManagedInstance<LibvirtDriver>, &InstancePhase)>,
+    views: Query<&ProvisionLogView>,
+    mut buffers: Query<&mut LogBuffer>,
+    agent: Res<SharedAgent>,
+    mut commands: Commands,
+) {
+    let client_id = trigger.event().client_id;
+    let reject = |commands: &mut Commands, message: String| {
+        ProvisionRequest::reply(
+            commands,
+            client_id,
+            ProvisionResponse {
+                success: false,
+                message: Some(message),
+            },
+        );
+    };
+
+    let Some((instance_entity, instance, phase)) = instances.iter().next() else {
+        reject(&mut commands, "no managed instance was found".into());
+        return;
+    };
+    if *phase != InstancePhase::Running {
+        reject(
+            &mut commands,
+            format!("instance is {}, not running", phase.label()),
+        );
+        return;
+    }
+    let request = trigger.event().message.clone();
+
+    if let Ok(mut buffer) = buffers.get_mut(instance_entity) {
+        buffer.lines.clear();
+    }
+    if let Ok(entries) = views.get(instance_entity) {
+        for entry in entries.iter() {
+            commands.entity(entry).despawn();
+        }
+    }
+
+    let driver = instance.driver();
+    let agent = agent.clone();
+    commands.spawn_empty().spawn_task(move |task| async move {
+        let log_task = task.clone();
+        let on_output = move |line: String| {
+            log_task.queue_cmd_tick(move |world: &mut World| {
+                if let Some(mut buffer) = world.get_mut::<LogBuffer>(instance_entity) {
+                    buffer.push(line);
+                }
+            });
+        };
+
+        let response = match run_provision(&agent, &driver, request, on_output).await {
+            Ok(message) => ProvisionResponse {
+                success: true,
+                message,
+            },
+            Err(message) => ProvisionResponse {
+                success: false,
+                message: Some(message),
+            },
+        };
+
+        task.queue_cmd_wake(move |world: &mut World| {
+            let mut commands = world.commands();
+            ProvisionRequest::reply(&mut commands, client_id, response);
+        });
+    });
+}
+
+async fn run_provision<F>(
+    agent: &SharedAgent,
+    driver: &LibvirtDriver,
+    request: ProvisionRequest,
+    on_output: F,
+) -> Result<Option<String>, String>
+where
+    F: Fn(String) + Send + Sync + Clone,
+{
+    let (scripts, mode) = select_scripts(driver, &request).await?;
+    if scripts.is_empty() {
+        let message = if request.force {
+            "no provisioning scripts are configured"
+        } else {
+            "nothing to provision: no step changed since its last run (--all runs every step)"
+        };
+        return Ok(Some(message.into()));
+    }
+
+    let client = agent.client(driver).await?;
+    client
+        .provision_in_mode(
+            scripts.clone(),
+            &driver.layout().logs_dir,
+            driver.system().config.logging.max_files,
+            Some(request.execution_id),
+            mode,
+            on_output,
+        )
+        .await
+        .map_err(|error| error.to_string())?;
+    if request.script.is_none() {
+        driver
+            .record_provisioned_scripts(&scripts)
+            .and_then(|()| driver.freeze_after_provision(&scripts))
+            .map_err(|error| error.to_string())?;
+        if request.only.as_deref().is_none_or(|only| only == "docker") {
+            record_provisioned_docker(driver.system());
+        }
+    }
+    Ok(None)
+}
+
+/// The engine matches `[docker]` once its step ran or was unchanged, so
+/// `rum diff` stops listing it.
+fn record_provisioned_docker(system: &machine::config::SystemConfig) {
+    let path = machine::paths::applied_config_path(&system.id, system.name.as_deref());
+    let Some(mut applied) = machine::config::applied_config(&path) else {
+        return;
+    };
+    applied.docker = system.config.docker.clone();
+    if let Err(error) = machine::config::record_applied(&path, &applied) {
+        tracing::warn!(%error, "failed to record the provisioned config");
+    }
+}
+
+/// The scripts `request` asks for: the ad-hoc script, one step of the plan,
+/// or the plan itself, built from the config as it is now. Unless forced,
+/// the plan leaves out system steps that are unchanged since their last run.
+async fn select_scripts(
+    driver: &LibvirtDriver,
+    request: &ProvisionRequest,
+) -> Result<(Vec<ProvisionScript>, ProvisionMode), String> {
+    if let Some(script) = &request.script {
+        return Ok((vec![adhoc_script(script)], ProvisionMode::Script));
+    }
+
+    // A step skipped as unchanged is still there to pick with `--only`.
+    let system = driver.system();
+    let sshfs_key = crate::server::sshfs_key(system)
+        .await
+        .map_err(|error| error.to_string())?;
+    let force = request.force || request.only.is_some();
+    let full = crate::server::build_provision_plan(system, sshfs_key.as_deref(), force)
+        .map_err(|error| error.to_string())?;
+    match &request.only {
+        Some(only) => Ok((vec![pick_step(&full, only)?], ProvisionMode::OneOff)),
+        None => Ok((full, ProvisionMode::Plan)),
+    }
+}
+
+/// The `--script` file as a system step of its own. The name is checked
+/// again here, as the request may come from any client.
+fn adhoc_script(script: &AdHocScript) -> ProvisionScript {
+    let name = script_name(&script.name);
+    ProvisionScript::shell(name.clone(), name, script.content.clone(), 0, RunOn::System)
+}
+
+/// The step of `full` that `--only` names.
+fn pick_step(full: &[ProvisionScript], only: &str) -> Result<ProvisionScript, String> {
+    match full.iter().find(|script| script.name == only) {
+        Some(step) => Ok(step.clone()),
+        None => {
+            let names: Vec<&str> = full.iter().map(|script| script.name.as_str()).collect();
+            Err(format!(
+                "no provisioning step named '{only}'; the plan has: {}",
+                names.join(", ")
+            ))
+        }
+    }
+}
+
+fn handle_provision_response(
+    trigger: On<ProvisionResponse>,
+    style: Option<Res<RenderStyle>>,
+    mut exit: MessageWriter<AppExit>,
+) {
+    let response = trigger.event();
+    let style = style.as_deref().copied().unwrap_or_default();
+    if response.success {
+        if let Some(message) = response.message.as_deref() {
+            println!("{}", style.text(message));
+        }
+        exit.write(AppExit::Success);
+    } else {
+        let message = response.message.as_deref().unwrap_or("provisioning failed");
+        eprintln!("{}", style.error(&style.text(message)));
+        exit.write(AppExit::from_code(1));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn script_names_are_safe_to_use_as_file_names() {
+        assert_eq!(script_name("setup-db_2.v1"), "setup-db_2.v1");
+        assert_eq!(script_name("my script"), "my-script");
+        assert_eq!(script_name("../etc/passwd"), "etc-passwd");
+        assert_eq!(script_name(".hidden"), "hidden");
+        assert_eq!(script_name(""), "script");
+        assert_eq!(script_name("..."), "script");
+    }
+
+    #[test]
+    fn prepare_request_reads_the_script_file() {
+        let dir = std::env::temp_dir().join(format!("rum-provision-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("seed data.sh");
+        std::fs::write(&path, "echo seeded\n").unwrap();
+
+        let request = prepare_request(None, false, Some(&path)).unwrap();
+        let script = request.script.unwrap();
+        assert_eq!(script.name, "seed-data");
+        assert_eq!(script.content, "echo seeded\n");
+
+        let missing = prepare_request(None, false, Some(&dir.join("missing.sh")));
+        assert!(missing.is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn prepare_request_without_script_keeps_the_step_selection() {
+        let request = prepare_request(Some("packages".into()), true, None).unwrap();
+        assert_eq!(request.only.as_deref(), Some("packages"));
+        assert!(request.force);
+        assert!(request.script.is_none());
+    }
+
+    #[test]
+    fn adhoc_scripts_run_once_as_a_system_step() {
+        let script = adhoc_script(&AdHocScript {
+            name: "a/b".into(),
+            content: "true".into(),
+        });
+        assert_eq!(script.name, "a-b");
+        assert_eq!(script.title, "a-b");
+        assert_eq!(script.content, "true");
+        assert_eq!(script.order, 0);
+        assert!(matches!(script.run_on, RunOn::System));
+    }
+
+    #[test]
+    fn pick_step_finds_the_named_step_or_lists_the_plan() {
+        let full = vec![
+            ProvisionScript::shell("packages", "Packages", "true".into(), 1, RunOn::System),
+            ProvisionScript::shell("boot", "Boot", "true".into(), 2, RunOn::Boot),
+        ];
+        assert_eq!(pick_step(&full, "boot").unwrap().order, 2);
+        let error = pick_step(&full, "nope").unwrap_err();
+        assert_eq!(
+            error,
+            "no provisioning step named 'nope'; the plan has: packages, boot"
+        );
+    }
+}
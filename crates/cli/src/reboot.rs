@@ -0,0 +1,107 @@
+use ecsdk::app::AsyncApp;
+use ecsdk::prelude::*;
+use orchestrator::instance::instance_phase::{Running, ShuttingDown};
+use orchestrator::{InstancePhase, OrchestratorMessage};
+
+use crate::exit;
+use crate::protocol::{RebootRequest, RebootResponse};
+use crate::render::RenderStyle;
+
+/// Isomorphic request feature that lets a client ask the daemon to shut the
+/// managed machine down and boot it again, re-running boot provisioning.
+pub struct RebootFeature;
+
+impl RequestPlugin for RebootFeature {
+    type Request = RebootRequest;
+    type Trigger = ecsdk::network::InitialConnection;
+
+    fn auto_register_client() -> bool {
+        false
+    }
+
+    fn build_server(app: &mut App) {
+        app.add_observer(handle_reboot_request);
+    }
+
+    fn build_client(app: &mut App) {
+        app.init_resource::<RebootProgress>();
+        app.add_observer(handle_reboot_response);
+        app.add_observer(track_shutdown);
+        app.add_observer(exit_when_running_again);
+        app.add_observer(exit::on_failed);
+        app.add_systems(Update, exit::on_server_disconnect);
+    }
+}
+
+/// Whether the client has seen the instance go down since it connected, so
+/// the initial replicated `Running` phase does not end the client early.
+#[derive(Resource, Default)]
+struct RebootProgress {
+    went_down: bool,
+}
+
+/// Build the client app used by `rum restart`.
+pub fn build_reboot_client(
+    mut app: AsyncApp<OrchestratorMessage>,
+) -> AsyncApp<OrchestratorMessage> {
+    RebootFeature::register_client(&mut app);
+    app
+}
+
+fn handle_reboot_request(
+    trigger: On<FromClient<RebootRequest>>,
+    phases: Query<&InstancePhase>,
+    mut commands: Commands,
+) {
+    let client_id = trigger.event().client_id;
+    let response = match phases.iter().next() {
+        Some(InstancePhase::Running | InstancePhase::Unhealthy | InstancePhase::Crashed) => {
+            commands.send_msg(OrchestratorMessage::RequestReboot);
+            RebootResponse {
+                accepted: true,
+                message: None,
+            }
+        }
+        Some(phase) => RebootResponse {
+            accepted: false,
+            message: Some(format!("instance is {}, not running", phase.label())),
+        },
+        None => RebootResponse {
+            accepted: false,
+            message: Some("no managed instance was found".into()),
+        },
+    };
+    RebootRequest::reply(&mut commands, client_id, response);
+}
+
+fn handle_reboot_response(
+    trigger: On<RebootResponse>,
+    style: Option<Res<RenderStyle>>,
+    mut exit: MessageWriter<AppExit>,
+) {
+    let response = trigger.event();
+    let style = style.as_deref().copied().unwrap_or_default();
+    if response.accepted {
+        tracing::info!("restart request accepted");
+        return;
+    }
+    if let Some(message) = response.message.as_deref() {
+        eprintln!("restart rejected: {}", style.text(message));
+    }
+    exit.write(AppExit::from_code(1));
+}
+
+fn track_shutdown(_trigger: On<Add, ShuttingDown>, mut progress: ResMut<RebootProgress>) {
+    progress.went_down = true;
+}
+
+fn exit_when_running_again(
+    _trigger: On<Add, Running>,
+    progress: Res<RebootProgress>,
+    mut exit: MessageWriter<AppExit>,
+) {
+    if progress.went_down {
+        tracing::info!("managed instance is running again");
+        exit.write(AppExit::Success);
+    }
+}
@@ -0,0 +1,359 @@
+use std::path::PathBuf;
+
+use ecsdk::app::AsyncApp;
+use ecsdk::network::{InitialConnection, IsomorphicPlugin};
+use ecsdk::prelude::*;
+use ecsdk::tasks::SpawnTask;
+use machine::config::{
+    PortForward, ResolvedMount, SystemConfig, applied_config, load_config, record_applied,
+};
+use machine::driver::LibvirtDriver;
+use machine::instance::Instance;
+use orchestrator::{InstancePhase, ManagedInstance, OrchestratorMessage};
+
+use crate::ports::{self, ActiveForwards, same_forward};
+use crate::protocol::{ReloadRequest, ReloadResponse};
+use crate::render::RenderStyle;
+
+/// Shared request feature for applying config changes to the running machine.
+pub struct ReloadFeature;
+
+impl IsomorphicPlugin for ReloadFeature {
+    fn build_shared(&self, app: &mut App) {
+        ReloadRequest::register(app);
+    }
+
+    fn build_server(&self, app: &mut App) {
+        app.add_observer(handle_reload_request);
+    }
+
+    fn build_client(&self, app: &mut App) {
+        app.add_observer(handle_reload_response);
+        app.add_systems(Update, crate::exit::on_server_disconnect);
+    }
+}
+
+/// Client request state used to send the reload request on the initial
+/// daemon connection.
+#[derive(Resource, Clone)]
+struct PendingReloadRequest(ReloadRequest);
+
+/// Build the client app used by `rum reload`.
+pub fn build_reload_client(
+    mut app: AsyncApp<OrchestratorMessage>,
+    config: PathBuf,
+) -> AsyncApp<OrchestratorMessage> {
+    app.insert_resource(PendingReloadRequest(ReloadRequest {
+        config: Some(config),
+    }));
+    app.add_observer(send_reload_request_on_connect);
+    app
+}
+
+fn send_reload_request_on_connect(
+    _trigger: On<Add, InitialConnection>,
+    request: Res<PendingReloadRequest>,
+    mut commands: Commands,
+) {
+    commands.client_trigger(request.0.clone());
+}
+
+/// Changes between the running config and the reloaded one.
+struct ReloadPlan {
+    stop_ports: Vec<PortForward>,
+    start_ports: Vec<PortForward>,
+    detach: Vec<ResolvedMount>,
+    attach: Vec<ResolvedMount>,
+    ready_changed: bool,
+    notify_changed: bool,
+    mdns_changed: bool,
+    docker_changed: bool,
+    /// Something besides ports, mounts, `[ready]`, `[notify]`,
+    /// `network.mdns` and `[docker]` changed.
+    needs_restart: bool,
+}
+
+fn plan_reload(old: &SystemConfig, new: &SystemConfig) -> Result<ReloadPlan, String> {
+    let (old_ports, new_ports) = (&old.config.ports, &new.config.ports);
+    let old_mounts = old.resolve_mounts().map_err(|e| e.to_string())?;
+    let new_mounts = new.resolve_mounts().map_err(|e| e.to_string())?;
+
+    Ok(ReloadPlan {
+        stop_ports: only_in(old_ports, new_ports, same_forward),
+        start_ports: only_in(new_ports, old_ports, same_forward),
+        detach: only_in(&old_mounts, &new_mounts, same_mount),
+        attach: only_in(&new_mounts, &old_mounts, same_mount),
+        ready_changed: facet_json::to_string(&old.config.ready)
+            != facet_json::to_string(&new.config.ready),
+        notify_changed: facet_json::to_string(&old.config.notify)
+            != facet_json::to_string(&new.config.notify),
+        mdns_changed: old.config.network.mdns != new.config.network.mdns,
+        docker_changed: facet_json::to_string(&old.config.docker)
+            != facet_json::to_string(&new.config.docker),
+        needs_restart: without_reloadable(old) != without_reloadable(new),
+    })
+}
+
+/// Items of `items` with no equal entry in `other`.
+fn only_in<T: Clone>(items: &[T], other: &[T], same: fn(&T, &T) -> bool) -> Vec<T> {
+    items
+        .iter()
+        .filter(|item| !other.iter().any(|o| same(item, o)))
+        .cloned()
+        .collect()
+}
+
+fn same_mount(a: &ResolvedMount, b: &ResolvedMount) -> bool {
+    a.tag == b.tag
+        && a.source == b.source
+        && a.target == b.target
+        && a.readonly == b.readonly
+        && a.driver == b.driver
+}
+
+/// The config with every reloadable section cleared, for spotting changes
+/// that need a restart.
+fn without_reloadable(system: &SystemConfig) -> String {
+    let mut config = system.config.clone();
+    config.ports.clear();
+    config.mounts.clear();
+    config.ready = Default::default();
+    config.notify = Default::default();
+    config.network.mdns = false;
+    config.docker = Default::default();
+    facet_json::to_string(&config)
+}
+
+fn describe_forward(spec: &PortForward) -> String {
+    match spec.socket_addr() {
+        Some(addr) => format!("{addr} -> guest:{}", spec.guest),
+        None => format!("{}:{} -> guest:{}", spec.bind, spec.host, spec.guest),
+    }
+}
+
+fn handle_reload_request(
+    trigger: On<FromClient<ReloadRequest>>,
+    instances: Query<(Entity, &ManagedInstance<LibvirtDriver>, &InstancePhase)>,
+    mut forwards: ResMut<ActiveForwards>,
+    mut commands: Commands,
+) {
+    let client_id = trigger.event().client_id;
+    let reject = |commands: &mut Commands, message: String| {
+        ReloadRequest::reply(
+            commands,
+            client_id,
+            ReloadResponse {
+                success: false,
+                changes: vec![message],
+            },
+        );
+    };
+
+    let Some((entity, instance, phase)) = instances.iter().next() else {
+        reject(&mut commands, "no managed instance was found".into());
+        return;
+    };
+    if *phase != InstancePhase::Running {
+        reject(
+            &mut commands,
+            format!("instance is {}, not running", phase.label()),
+        );
+        return;
+    }
+    let Some(path) = trigger.event().message.config.clone() else {
+        reject(&mut commands, "missing reload request payload".into());
+        return;
+    };
+    let loaded = load_config(&path).and_then(|system| {
+        system.check_agent_transport(machine::guest::host_has_vsock())?;
+        Ok(system)
+    });
+    let system = match loaded {
+        Ok(mut system) => {
+            crate::ci::bound_timeouts(&mut system);
+            system
+        }
+        Err(error) => {
+            reject(&mut commands, error.to_string());
+            return;
+        }
+    };
+    let current = instance.driver();
+    if system.id != current.system().id {
+        reject(
+            &mut commands,
+            format!("{} belongs to a different machine", path.display()),
+        );
+        return;
+    }
+    // `rum mount` attaches shares without the daemon; count them as running
+    // so they are not attached a second time.
+    let mut running = current.system().clone();
+    let live_mounts = machine::paths::live_mounts_path(&running.id, running.name.as_deref());
+    machine::live_mount::merge(&mut running.config, &live_mounts);
+    let plan = match plan_reload(&running, &system) {
+        Ok(plan) => plan,
+        Err(message) => {
+            reject(&mut commands, message);
+            return;
+        }
+    };
+
+    let mut changes = Vec::new();
+    for spec in &plan.stop_ports {
+        forwards.stop(spec);
+        changes.push(format!("stopped forward {}", describe_forward(spec)));
+    }
+
+    let driver = LibvirtDriver::new(system.clone());
+    commands.spawn_empty().spawn_task(move |task| async move {
+        let mut success = true;
+        // What is actually in effect: the reloaded config, minus the
+        // changes that failed to apply.
+        let mut system = system;
+        for mount in &plan.detach {
+            match driver.detach_mount(mount).await {
+                Ok(()) => changes.push(format!("unmounted {}", mount.target)),
+                Err(error) => {
+                    success = false;
+                    changes.push(format!("failed to unmount {}: {error}", mount.target));
+                    let mounts = &mut system.config.mounts;
+                    mounts.retain(|m| m.target != mount.target);
+                    mounts.extend(
+                        running
+                            .config
+                            .mounts
+                            .iter()
+                            .filter(|m| m.target == mount.target)
+                            .cloned(),
+                    );
+                }
+            }
+        }
+        for mount in &plan.attach {
+            match driver.attach_mount(mount).await {
+                Ok(()) => changes.push(format!(
+                    "mounted {} at {}",
+                    mount.source.display(),
+                    mount.target
+                )),
+                Err(error) => {
+                    success = false;
+                    changes.push(format!("failed to mount {}: {error}", mount.target));
+                    system.config.mounts.retain(|m| m.target != mount.target);
+                }
+            }
+        }
+
+        let (started, errors) = ports::start(&driver, plan.start_ports.clone()).await;
+        for forward in &started {
+            changes.push(format!(
+                "started forward {}",
+                describe_forward(&forward.spec)
+            ));
+        }
+        if !errors.is_empty() {
+            success = false;
+            changes.extend(errors);
+            let failed: Vec<&PortForward> = plan
+                .start_ports
+                .iter()
+                .filter(|spec| !started.iter().any(|f| same_forward(&f.spec, spec)))
+                .collect();
+            system
+                .config
+                .ports
+                .retain(|port| !failed.iter().any(|spec| same_forward(port, spec)));
+        }
+
+        if plan.ready_changed {
+            match driver.wait_ready().await {
+                Ok(()) => changes.push("readiness probes pass".into()),
+                Err(error) => {
+                    success = false;
+                    changes.push(error.to_string());
+                }
+            }
+        }
+        if plan.notify_changed {
+            changes.push("updated notifications".into());
+        }
+        if plan.mdns_changed {
+            changes.push(if system.config.network.mdns {
+                "started advertising over mDNS".into()
+            } else {
+                "stopped advertising over mDNS".into()
+            });
+        }
+        if plan.docker_changed {
+            changes.push(
+                "updated the docker socket forward; run `rum provision` to set up the engine"
+                    .into(),
+            );
+        }
+        if plan.needs_restart {
+            changes.push("other settings changed; run `rum restart` to apply them".into());
+        }
+        if changes.is_empty() {
+            changes.push("nothing to reload".into());
+        }
+        record_reloaded(&system);
+
+        task.queue_cmd_wake(move |world: &mut World| {
+            world.resource_mut::<ActiveForwards>().0.extend(started);
+            crate::notify::reconfigure(world, &system);
+            if plan.mdns_changed {
+                crate::mdns::reconfigure(world, &system);
+            }
+            if plan.docker_changed {
+                crate::docker::reconfigure(world, &system);
+            }
+            if let Ok(mut entity) = world.get_entity_mut(entity) {
+                entity.insert(ManagedInstance(Instance::new(system)));
+            }
+            let mut commands = world.commands();
+            ReloadRequest::reply(
+                &mut commands,
+                client_id,
+                ReloadResponse { success, changes },
+            );
+        });
+    });
+}
+
+/// Fold the reloaded sections that took effect into the config recorded for
+/// `rum diff`.
+fn record_reloaded(system: &SystemConfig) {
+    let path = machine::paths::applied_config_path(&system.id, system.name.as_deref());
+    let Some(mut applied) = applied_config(&path) else {
+        return;
+    };
+    applied.ports = system.config.ports.clone();
+    applied.mounts = system.config.mounts.clone();
+    applied.ready = system.config.ready.clone();
+    applied.notify = system.config.notify.clone();
+    applied.network.mdns = system.config.network.mdns;
+    if let Err(error) = record_applied(&path, &applied) {
+        tracing::warn!(%error, "failed to record the reloaded config");
+    }
+}
+
+fn handle_reload_response(
+    trigger: On<ReloadResponse>,
+    style: Option<Res<RenderStyle>>,
+    mut exit: MessageWriter<AppExit>,
+) {
+    let response = trigger.event();
+    let style = style.as_deref().copied().unwrap_or_default();
+    if response.success {
+        for change in &response.changes {
+            println!("{}", style.text(change));
+        }
+        exit.write(AppExit::Success);
+    } else {
+        for change in &response.changes {
+            eprintln!("{}", style.error(&style.text(change)));
+        }
+        exit.write(AppExit::from_code(1));
+    }
+}
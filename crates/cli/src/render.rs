@@ -1,8 +1,11 @@
 mod plain;
+mod style;
 
 use clap::ValueEnum;
 use ecsdk::prelude::*;
 
+pub use style::{ColorChoice, RenderStyle};
+
 /// Output mode for the first CLI renderer.
 #[derive(Clone, Copy, PartialEq, Eq, Debug, ValueEnum)]
 pub enum RenderMode {
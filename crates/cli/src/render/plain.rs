@@ -1,16 +1,33 @@
 use std::collections::HashMap;
 
+use bevy::app::AppExit;
 use bevy::ecs::prelude::*;
 use orchestrator::{
     EntityError, InstanceLabel, InstancePhase, ProvisionLogEntry, ProvisionLogView, RecoveredState,
 };
 
+use super::RenderStyle;
+use crate::ci::CiMode;
+
 #[derive(Default)]
 pub(super) struct PlainRenderState {
     last_phase: HashMap<Entity, InstancePhase>,
     last_log_count: HashMap<Entity, usize>,
     last_recovered: HashMap<Entity, machine::instance::InstanceState>,
-    printed_failure: HashMap<Entity, String>,
+    printed_failure: HashMap<Entity, EntityError>,
+    /// Id of the CI group the output is in.
+    open_group: Option<String>,
+}
+
+impl PlainRenderState {
+    /// Print the marker that ends the open CI group, if any.
+    fn close_group(&mut self, ci: &CiMode) {
+        if let Some(id) = self.open_group.take()
+            && let Some(marker) = ci.end_group(&id)
+        {
+            println!("{marker}");
+        }
+    }
 }
 
 #[allow(clippy::type_complexity)]
@@ -27,8 +44,12 @@ pub(super) fn render_plain(
         Without<ecsdk::network::InitialConnection>,
     >,
     log_entries: Query<&ProvisionLogEntry>,
+    ci: Option<Res<CiMode>>,
+    style: Option<Res<RenderStyle>>,
+    mut exits: MessageReader<AppExit>,
     mut state: Local<PlainRenderState>,
 ) {
+    let style = style.as_deref().copied().unwrap_or_default();
     let mut entities: Vec<_> = query.iter().collect();
     entities.sort_by(|a, b| {
         let label_a = a.1.map(|label| label.0.as_str()).unwrap_or("instance");
@@ -49,26 +70,54 @@ pub(super) fn render_plain(
 
         let phase = *phase;
         if state.last_phase.get(&entity) != Some(&phase) {
-            println!("{label}: {}", phase.label());
+            let title = format!("{label}: {}", phase.label());
+            // In CI each phase folds into a group of its own.
+            if let Some(ci) = ci.as_deref() {
+                state.close_group(ci);
+                let id = format!("rum_{}", phase.label().replace(' ', "_"));
+                if let Some(marker) = ci.start_group(&id, &title) {
+                    println!("{marker}");
+                    state.open_group = Some(id);
+                }
+            }
+            println!("{label}: {}", style.phase(phase));
             state.last_phase.insert(entity, phase);
         }
 
-        if phase == InstancePhase::Failed
+        if matches!(phase, InstancePhase::Failed | InstancePhase::Crashed)
             && let Some(error) = error
-            && state.printed_failure.get(&entity) != Some(&error.0)
+            && state.printed_failure.get(&entity) != Some(error)
         {
-            eprintln!("{label}: {}", error.0);
-            state.printed_failure.insert(entity, error.0.clone());
+            // Failures stay visible outside the collapsed groups.
+            if let Some(ci) = ci.as_deref() {
+                state.close_group(ci);
+            }
+            eprintln!("{label}: {}", style.error(&style.text(&error.to_string())));
+            if let Some(hint) = &error.hint {
+                eprintln!("  hint: {}", style.text(hint));
+            }
+            state.printed_failure.insert(entity, error.clone());
         }
 
         if let Some(log_view) = log_view {
             let seen = state.last_log_count.get(&entity).copied().unwrap_or_default();
             for entry_entity in log_view.iter().skip(seen) {
                 if let Ok(entry) = log_entries.get(entry_entity) {
-                    println!("  {} | {}", entry.label, entry.message);
+                    println!(
+                        "  {} | {}",
+                        style.text(&entry.label),
+                        style.text(&entry.message)
+                    );
                 }
             }
             state.last_log_count.insert(entity, log_view.iter().len());
         }
     }
+
+    // A group left open would swallow whatever the job prints after rum.
+    if let Some(ci) = ci.as_deref()
+        && exits.read().next().is_some()
+    {
+        state.close_group(ci);
+    }
 }
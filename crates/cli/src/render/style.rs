@@ -0,0 +1,199 @@
+use std::borrow::Cow;
+use std::ffi::OsStr;
+use std::io::IsTerminal;
+
+use clap::ValueEnum;
+use ecsdk::prelude::*;
+use orchestrator::InstancePhase;
+
+/// When output gets ANSI colors.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, ValueEnum)]
+pub enum ColorChoice {
+    /// Only on a terminal, and only without `NO_COLOR`.
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    /// Whether a stream that `is_terminal` or not gets colors.
+    pub fn enabled(self, is_terminal: bool) -> bool {
+        self.enabled_with(is_terminal, std::env::var_os("NO_COLOR").as_deref())
+    }
+
+    /// `enabled` with `NO_COLOR` set to `no_color`, if at all.
+    fn enabled_with(self, is_terminal: bool, no_color: Option<&OsStr>) -> bool {
+        match self {
+            Self::Always => true,
+            Self::Never => false,
+            Self::Auto => is_terminal && no_color.is_none_or(|v| v.is_empty()),
+        }
+    }
+}
+
+/// How the renderers print: with or without colors, and whether to keep
+/// output to plain ASCII.
+#[derive(Resource, Clone, Copy, Debug, Default)]
+pub struct RenderStyle {
+    pub color: bool,
+    pub ascii: bool,
+}
+
+impl RenderStyle {
+    /// The style for stdout under `color`, plain ASCII when `ascii` is set.
+    pub fn new(color: ColorChoice, ascii: bool) -> Self {
+        Self {
+            color: color.enabled(std::io::stdout().is_terminal()),
+            ascii,
+        }
+    }
+
+    /// `label` of `phase`, colored by how the phase went.
+    pub fn phase(&self, phase: InstancePhase) -> Cow<'static, str> {
+        let code = match phase {
+            InstancePhase::Running | InstancePhase::Stopped => "32",
+            InstancePhase::Unhealthy => "33",
+            InstancePhase::Failed | InstancePhase::Crashed => "31",
+            _ => "36",
+        };
+        self.paint(code, phase.label())
+    }
+
+    /// `text` in red, for errors.
+    pub fn error<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        self.paint("31", text)
+    }
+
+    /// Output from elsewhere, like a guest command, made safe to print: its
+    /// own escape sequences dropped without colors, and anything beyond
+    /// ASCII replaced in ASCII mode.
+    pub fn text<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        let text = if self.color {
+            Cow::Borrowed(text)
+        } else {
+            strip_ansi(text)
+        };
+        if self.ascii && !text.is_ascii() {
+            let ascii = text
+                .chars()
+                .map(|c| if c.is_ascii() { c } else { '?' })
+                .collect();
+            return Cow::Owned(ascii);
+        }
+        text
+    }
+
+    fn paint<'a>(&self, code: &str, text: &'a str) -> Cow<'a, str> {
+        if self.color {
+            Cow::Owned(format!("\x1b[{code}m{text}\x1b[0m"))
+        } else {
+            Cow::Borrowed(text)
+        }
+    }
+}
+
+/// `text` without ANSI escape sequences: CSI (colors, cursor movement) and
+/// OSC (titles, links) sequences, and two-character escapes.
+fn strip_ansi(text: &str) -> Cow<'_, str> {
+    if !text.contains('\x1b') {
+        return Cow::Borrowed(text);
+    }
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\x1b' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('[') => {
+                // Parameters, then one final byte in `@`..=`~`.
+                for c in chars.by_ref() {
+                    if ('@'..='~').contains(&c) {
+                        break;
+                    }
+                }
+            }
+            Some(']') => {
+                // Ends with BEL or ESC `\`.
+                while let Some(c) = chars.next() {
+                    if c == '\x07' || (c == '\x1b' && chars.next_if_eq(&'\\').is_some()) {
+                        break;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    Cow::Owned(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn auto_colors_only_a_terminal_without_no_color() {
+        let auto = ColorChoice::Auto;
+        assert!(auto.enabled_with(true, None));
+        assert!(auto.enabled_with(true, Some(OsStr::new(""))));
+        assert!(!auto.enabled_with(true, Some(OsStr::new("1"))));
+        assert!(!auto.enabled_with(false, None));
+        assert!(ColorChoice::Always.enabled_with(false, Some(OsStr::new("1"))));
+        assert!(!ColorChoice::Never.enabled_with(true, None));
+    }
+
+    #[test]
+    fn strip_ansi_borrows_plain_text() {
+        assert!(matches!(
+            strip_ansi("plain text"),
+            Cow::Borrowed("plain text")
+        ));
+    }
+
+    #[test]
+    fn strip_ansi_drops_csi_and_two_character_escapes() {
+        assert_eq!(strip_ansi("\x1b[1;31mred\x1b[0m ok"), "red ok");
+        assert_eq!(strip_ansi("a\x1b[2Kb"), "ab");
+        assert_eq!(strip_ansi("a\x1bcb\x1b7c"), "abc");
+    }
+
+    #[test]
+    fn strip_ansi_drops_osc_ended_by_bel_or_st() {
+        assert_eq!(strip_ansi("\x1b]0;title\x07text"), "text");
+        assert_eq!(
+            strip_ansi("\x1b]8;;http://x\x1b\\link\x1b]8;;\x1b\\"),
+            "link"
+        );
+    }
+
+    #[test]
+    fn strip_ansi_drops_unterminated_sequences() {
+        assert_eq!(strip_ansi("ok\x1b[31"), "ok");
+        assert_eq!(strip_ansi("ok\x1b]0;title"), "ok");
+        assert_eq!(strip_ansi("ok\x1b"), "ok");
+    }
+
+    #[test]
+    fn text_strips_escapes_only_without_color() {
+        let colored = "\x1b[32mok\x1b[0m";
+        let plain = RenderStyle::default();
+        assert_eq!(plain.text(colored), "ok");
+        let color = RenderStyle {
+            color: true,
+            ascii: false,
+        };
+        assert!(matches!(color.text(colored), Cow::Borrowed(s) if s == colored));
+    }
+
+    #[test]
+    fn text_replaces_non_ascii_in_ascii_mode() {
+        let ascii = RenderStyle {
+            color: false,
+            ascii: true,
+        };
+        assert_eq!(ascii.text("done \u{2713} \u{1b}[1mx"), "done ? x");
+        assert_eq!(RenderStyle::default().text("\u{2713}"), "\u{2713}");
+    }
+}
@@ -0,0 +1,26 @@
+use anyhow::bail;
+use machine::config::SystemConfig;
+use machine::driver::LibvirtDriver;
+
+/// Hot-resize the running machine's vCPUs and/or memory.
+///
+/// `memory` accepts sizes like `4G` or `2048M`.
+pub fn run(system: &SystemConfig, cpus: Option<u32>, memory: Option<&str>) -> anyhow::Result<()> {
+    if cpus.is_none() && memory.is_none() {
+        bail!("nothing to resize; pass --cpus and/or --memory");
+    }
+    let memory_mb = memory
+        .map(|size| machine::util::parse_size(size).map(|bytes| bytes / (1024 * 1024)))
+        .transpose()?;
+
+    LibvirtDriver::new(system.clone()).resize(cpus, memory_mb)?;
+
+    if let Some(cpus) = cpus {
+        println!("vcpus:  {cpus}");
+    }
+    if let Some(memory_mb) = memory_mb {
+        println!("memory: {memory_mb} MiB");
+    }
+    println!("live change only; update [resources] in the config to keep it across boots");
+    Ok(())
+}
@@ -6,6 +6,8 @@ use ecsdk::bevy_replicon::shared::protocol::ProtocolMismatch;
 use ecsdk::network::IsomorphicPlugin;
 use ecsdk::prelude::*;
 
+use crate::ci::CiMode;
+
 /// Shared flag toggled when the client wants the daemon to be restarted after
 /// a protocol mismatch.
 #[derive(Resource, Clone)]
@@ -32,8 +34,17 @@ impl IsomorphicPlugin for ProtocolRestartPlugin {
 fn on_protocol_mismatch(
     _trigger: On<ProtocolMismatch>,
     requested: Res<RestartRequested>,
+    ci: Option<Res<CiMode>>,
     mut exit: MessageWriter<AppExit>,
 ) {
+    // Nobody answers a prompt in CI, where a new client is the usual cause.
+    if ci.is_some() {
+        eprintln!("Daemon version differs from client. Restarting daemon to update.");
+        requested.0.store(true, Ordering::SeqCst);
+        exit.write(AppExit::Success);
+        return;
+    }
+
     eprintln!("Daemon version differs from client. Restart daemon to update? [y/N]");
     eprint!("> ");
     let _ = io::stderr().flush();
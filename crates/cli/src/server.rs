@@ -3,16 +3,18 @@ use ecsdk::app::AsyncApp;
 use ecsdk::network::IsomorphicAppExt;
 use ecsdk::prelude::*;
 use ecsdk::tasks::SpawnTask;
-use machine::config::{SystemConfig, load_config};
+use guest::agent::{ProvisionScript, RunOn, ScriptKind};
+use machine::config::{MountDriver, SystemConfig, load_config};
 use machine::driver::Driver;
 use machine::driver::LibvirtDriver;
-use machine::image::ensure_base_image;
+use machine::image::{baked_image, ensure_base_image};
 use machine::instance::Instance;
+use machine::retry::{RetryPolicy, retry};
 use machine::{error::Error, paths};
 use orchestrator::instance::instance_phase::{Failed, Stopped};
 use orchestrator::{
-    ManagedInstanceSpec, OrchestratorMessage, OrchestratorPlugin, ShutdownRequested,
-    spawn_managed_instance,
+    ManagedInstanceSpec, OrchestratorMessage, OrchestratorPlugin, RebootRequested,
+    ShutdownRequested, spawn_managed_instance,
 };
 
 /// Server bootstrap inputs resolved before the daemon starts.
@@ -27,13 +29,44 @@ pub struct ServerSpec {
 }
 
 /// Resolve config and startup inputs for a single `rum up` daemon.
-pub async fn load_server_spec(config_path: &Path) -> Result<ServerSpec, Error> {
-    let system = load_config(config_path)?;
+///
+/// With `resume`, the base image journaled by an interrupted previous run is
+/// reused instead of being resolved again; recovery then picks the lifecycle
+/// up from whatever state that run left the machine in.
+pub async fn load_server_spec(config_path: &Path, resume: bool) -> Result<ServerSpec, Error> {
+    let mut system = load_config(config_path)?;
+    system.check_agent_transport(machine::guest::host_has_vsock())?;
+    crate::ci::bound_timeouts(&mut system);
     let display_name = system.display_name().to_string();
     let instance = Instance::new(system.clone());
-    let base_image = ensure_base_image(&system.config.image.base, &paths::cache_dir()).await?;
+    let baked = paths::baked_image_path(&system.id, system.name.as_deref());
+    let resumed = resume
+        .then(|| crate::journal::resumable_base_image(&system))
+        .flatten();
+    let base_image = match (resumed, baked_image(&baked, &system.config.image.base)) {
+        (Some(path), _) => {
+            tracing::info!(path = %path.display(), "resuming with journaled base image");
+            path
+        }
+        (None, Some(path)) => {
+            tracing::info!(path = %path.display(), "using baked base image");
+            path
+        }
+        (None, None) => {
+            let timeouts = &system.config.timeouts;
+            retry(
+                RetryPolicy::new(timeouts.image_download_s, timeouts.retries),
+                "image download",
+                |attempt| tracing::warn!("{attempt}"),
+                || ensure_base_image(&system.config.image, &paths::cache_dir()),
+            )
+            .await?
+        }
+    };
+    machine::journal::reset(&paths::journal_path(&system.id, system.name.as_deref()))?;
     let socket_path = crate::ipc::socket_path(&system);
-    let provision_plan = build_provision_plan(&system);
+    let sshfs_key = sshfs_key(&system).await?;
+    let provision_plan = build_provision_plan(&system, sshfs_key.as_deref(), false)?;
 
     Ok(ServerSpec {
         system,
@@ -45,10 +78,12 @@ pub async fn load_server_spec(config_path: &Path) -> Result<ServerSpec, Error> {
     })
 }
 
-/// Build the first server-side daemon app for `rum up`.
+/// Build the first server-side daemon app for `rum up`. Notifications the
+/// app sends are tracked in `notifications`.
 pub fn build_up_server(
     iso: ecsdk::network::IsomorphicApp<OrchestratorMessage>,
     spec: ServerSpec,
+    notifications: crate::notify::PendingNotifications,
 ) -> AsyncApp<OrchestratorMessage> {
     let mut app = iso.build_server();
     app.add_isomorphic_plugin(
@@ -56,6 +91,13 @@ pub fn build_up_server(
         OrchestratorPlugin::<LibvirtDriver>::default(),
     );
     app.add_plugins(RumServerPlugin);
+    if let Some(http) = crate::http::HttpApiPlugin::from_system(&spec.system) {
+        app.add_plugins(http);
+    }
+    app.add_plugins(crate::notify::NotifyPlugin::from_system(
+        &spec.system,
+        notifications,
+    ));
     spawn_managed_instance(app.world_mut(), spec.managed_instance);
     app
 }
@@ -65,6 +107,12 @@ struct RumServerPlugin;
 
 impl Plugin for RumServerPlugin {
     fn build(&self, app: &mut App) {
+        app.add_plugins(crate::ports::PortForwardPlugin);
+        app.add_plugins(crate::mount_sync::MountSyncPlugin);
+        app.add_plugins(crate::peers::PeerHostsPlugin);
+        app.add_plugins(crate::mdns::MdnsPlugin);
+        app.add_plugins(crate::docker::DockerSocketPlugin);
+        app.add_plugins(crate::journal::JournalPlugin);
         app.init_resource::<DestroyRequested>();
         app.add_observer(exit_on_stopped_after_shutdown);
         app.add_observer(destroy_after_stop);
@@ -80,10 +128,11 @@ pub struct DestroyRequested(pub bool);
 fn exit_on_stopped_after_shutdown(
     _trigger: On<Add, Stopped>,
     shutdown: Res<ShutdownRequested>,
+    reboot: Res<RebootRequested>,
     destroy: Res<DestroyRequested>,
     mut exit: MessageWriter<AppExit>,
 ) {
-    if shutdown.0 && !destroy.0 {
+    if shutdown.0 && !reboot.0 && !destroy.0 {
         tracing::info!("managed instance stopped after shutdown request; exiting daemon");
         exit.write(AppExit::Success);
     }
@@ -130,28 +179,243 @@ fn exit_on_failed(_trigger: On<Add, Failed>, mut exit: MessageWriter<AppExit>) {
     exit.write(AppExit::Success);
 }
 
-fn build_provision_plan(system: &SystemConfig) -> Vec<guest::agent::ProvisionScript> {
+/// The key the guest mounts sshfs shares with, if the machine has any.
+pub(crate) async fn sshfs_key(system: &SystemConfig) -> Result<Option<String>, Error> {
+    let has_sshfs = system
+        .resolve_mounts()?
+        .iter()
+        .any(|m| m.driver == MountDriver::Sshfs);
+    if !has_sshfs {
+        return Ok(None);
+    }
+    LibvirtDriver::new(system.clone())
+        .sshfs_key()
+        .await
+        .map(Some)
+}
+
+/// The provisioning scripts of `system` in run order. Unless `force` is set,
+/// system scripts whose content did not change since they last ran are left
+/// out.
+pub(crate) fn build_provision_plan(
+    system: &SystemConfig,
+    sshfs_key: Option<&str>,
+    force: bool,
+) -> Result<Vec<ProvisionScript>, Error> {
     let mut scripts = Vec::new();
 
-    if let Some(provision) = &system.config.provision.system {
-        scripts.push(guest::agent::ProvisionScript {
-            name: "system".into(),
-            title: "System provisioning".into(),
-            content: provision.script.clone(),
-            order: 0,
-            run_on: guest::agent::RunOn::System,
+    // Format and mount [drives]/[fs] before the user's system script runs.
+    // Drives already formatted on the host are skipped by the script itself.
+    let drives = system.resolve_drives()?;
+    let filesystems = system.resolve_fs(&drives)?;
+    let luks = system.resolve_luks(&drives)?;
+
+    // NixOS declares users, mounts and drive tooling in a module that has
+    // to be switched to before the drive script can find its tools.
+    if system.is_nixos() {
+        let mounts = system.resolve_mounts()?;
+        let config = &system.config;
+        let docker = &config.docker;
+        let engine = docker.enable.then_some(docker.engine.as_str());
+        let content = machine::nixos::build_module(&machine::nixos::ModuleConfig {
+            host_id: &system.id,
+            user_name: &config.user.name,
+            user_groups: &config.user.groups,
+            autologin: config.advanced.autologin,
+            packages: &config.provision.packages,
+            mounts: &mounts,
+            filesystems: &filesystems,
+            luks: &luks,
+            docker: engine,
+        });
+        scripts.push(ProvisionScript {
+            kind: ScriptKind::NixosModule,
+            ..ProvisionScript::shell("nixos", "NixOS configuration", content, 0, RunOn::System)
         });
     }
 
-    if let Some(provision) = &system.config.provision.boot {
-        scripts.push(guest::agent::ProvisionScript {
-            name: "boot".into(),
-            title: "Boot provisioning".into(),
-            content: provision.script.clone(),
-            order: 100,
-            run_on: guest::agent::RunOn::Boot,
+    if !filesystems.is_empty() || !luks.is_empty() {
+        let content = machine::cloudinit::build_drive_script(&filesystems, &luks);
+        scripts.push(ProvisionScript {
+            env: machine::cloudinit::luks_key_env(&luks),
+            ..ProvisionScript::shell("drives", "Drive setup", content, 0, RunOn::System)
         });
     }
+    // Encrypted drives stay locked after a reboot until the host has the agent
+    // reopen them; their keys are never stored in the guest.
+    if !luks.is_empty() {
+        let content = machine::cloudinit::build_unlock_script(&luks);
+        scripts.push(ProvisionScript {
+            env: machine::cloudinit::luks_key_env(&luks),
+            ..ProvisionScript::shell(
+                "drive-unlock",
+                "Unlock encrypted drives",
+                content,
+                0,
+                RunOn::Boot,
+            )
+        });
+    }
+    // Drives enlarged on the host are only grown in the guest on the next boot.
+    if !filesystems.is_empty() {
+        let content = machine::cloudinit::build_grow_script(&filesystems, &luks);
+        scripts.push(ProvisionScript {
+            env: machine::cloudinit::luks_key_env(&luks),
+            ..ProvisionScript::shell(
+                "drive-grow",
+                "Grow drive filesystems",
+                content,
+                1,
+                RunOn::Boot,
+            )
+        });
+    }
+
+    // NixOS gets its packages from the module instead.
+    let packages = &system.config.provision.packages;
+    if !packages.is_empty() && !system.is_nixos() {
+        scripts.push(ProvisionScript::shell(
+            "packages",
+            "Package installation",
+            machine::cloudinit::build_packages_script(packages),
+            0,
+            RunOn::System,
+        ));
+    }
+
+    // NixOS enables the engine in its module instead.
+    let docker = &system.config.docker;
+    if docker.enable && !system.is_nixos() {
+        scripts.push(ProvisionScript::shell(
+            "docker",
+            "Container engine",
+            machine::cloudinit::build_docker_script(&docker.engine, &system.config.user.name),
+            0,
+            RunOn::System,
+        ));
+    }
+
+    if let Some(content) = system.system_script()? {
+        scripts.push(ProvisionScript::shell(
+            "system",
+            "System provisioning",
+            content,
+            0,
+            RunOn::System,
+        ));
+    }
 
-    scripts
+    // Steps with explicit dependencies still wait for the built-in setup.
+    let setup: Vec<String> = scripts
+        .iter()
+        .filter(|s| matches!(s.name.as_str(), "nixos" | "drives" | "packages" | "docker"))
+        .map(|s| s.name.clone())
+        .collect();
+    for step in system.resolve_steps()? {
+        let content = machine::cloudinit::build_step_script(&step.script, step.when.as_deref());
+        let run_on = if step.boot {
+            RunOn::Boot
+        } else {
+            RunOn::System
+        };
+        scripts.push(ProvisionScript {
+            depends_on: step.depends_on.map(|deps| [setup.clone(), deps].concat()),
+            user: step.user,
+            interpreter: step.interpreter,
+            ..ProvisionScript::shell(step.name.clone(), step.name, content, step.order, run_on)
+        });
+    }
+
+    // sshfs shares need the network, so they are mounted on every `up`
+    // instead of from fstab. The key only travels with the plan.
+    if let Some(key) = sshfs_key {
+        let mounts: Vec<_> = system
+            .resolve_mounts()?
+            .into_iter()
+            .filter(|m| m.driver == MountDriver::Sshfs)
+            .collect();
+        let host_user = std::env::var("USER").unwrap_or_else(|_| "root".into());
+        let content =
+            machine::cloudinit::build_sshfs_script(&mounts, &system.config.user.name, &host_user);
+        scripts.push(ProvisionScript {
+            env: machine::cloudinit::sshfs_key_env(key),
+            ..ProvisionScript::shell("sshfs", "Mount sshfs shares", content, 2, RunOn::Boot)
+        });
+    }
+
+    if let Some(content) = system.boot_script()? {
+        scripts.push(ProvisionScript::shell(
+            "boot",
+            "Boot provisioning",
+            content,
+            100,
+            RunOn::Boot,
+        ));
+    }
+
+    // System scripts only run again once their content changes, or when
+    // forced. Boot scripts always go along, since the agent replaces its
+    // cached copies with the ones of every plan.
+    let recorded = machine::provision::recorded_hashes(&paths::provision_hashes_path(
+        &system.id,
+        system.name.as_deref(),
+    ));
+    machine::provision::skip_unchanged(&mut scripts, &recorded, force);
+
+    // The agent runs scripts by order and the client attributes output in
+    // plan order, so both must agree.
+    scripts.sort_by_key(|s| s.order);
+    Ok(scripts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn system_with_drive() -> SystemConfig {
+        let dir = std::env::temp_dir().join(format!("rum-server-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("rum.toml");
+        std::fs::write(
+            &path,
+            r#"
+[image]
+base = "ubuntu.qcow2"
+
+[resources]
+cpus = 1
+memory_mb = 512
+
+[drives.data]
+size = "20G"
+
+[[fs.ext4]]
+drive = "data"
+target = "/mnt/data"
+"#,
+        )
+        .unwrap();
+        let system = load_config(&path).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        system
+    }
+
+    #[test]
+    fn drive_filesystems_get_a_setup_step_before_the_system_script() {
+        let plan = build_provision_plan(&system_with_drive(), None, true).unwrap();
+        let names: Vec<&str> = plan.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, ["drives", "drive-grow"]);
+        assert!(plan[0].content.contains("mkfs.ext4"));
+    }
+
+    #[test]
+    fn filesystem_on_an_unknown_drive_fails_the_plan() {
+        let mut system = system_with_drive();
+        system.config.fs.get_mut("ext4").unwrap()[0].drive = "logs".into();
+        let error = build_provision_plan(&system, None, false).unwrap_err();
+        assert!(
+            error.to_string().contains("unknown drive 'logs'"),
+            "{error}"
+        );
+    }
 }
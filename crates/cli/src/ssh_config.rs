@@ -0,0 +1,29 @@
+use machine::config::SystemConfig;
+use machine::driver::LibvirtDriver;
+
+/// Print an OpenSSH `Host` entry for the running machine, or with `install`
+/// write it into `~/.ssh/config` so `ssh <name>` works. `rum destroy`
+/// removes the installed entry again.
+pub async fn run(system: &SystemConfig, install: bool) -> anyhow::Result<()> {
+    let driver = LibvirtDriver::new(system.clone());
+    let ip = driver.guest_ip(None, false).await?;
+    let block = machine::ssh_config::host_block(
+        system.display_name(),
+        &ip,
+        &system.config.ssh,
+        &driver.layout().ssh_key_path,
+    );
+    if !install {
+        print!("{block}");
+        return Ok(());
+    }
+
+    let path = machine::paths::user_ssh_config_path();
+    machine::ssh_config::install(&path, &system.id, &block)?;
+    println!(
+        "added {} to {}; run `rum ssh-config --install` again if its IP changes",
+        system.display_name(),
+        path.display()
+    );
+    Ok(())
+}
@@ -1,6 +1,9 @@
 use ecsdk::app::AsyncApp;
 use ecsdk::prelude::*;
-use orchestrator::{EntityError, InstanceLabel, InstancePhase, OrchestratorMessage, RecoveredState};
+use machine::driver::LibvirtDriver;
+use orchestrator::{
+    EntityError, InstanceLabel, InstancePhase, ManagedInstance, OrchestratorMessage, RecoveredState,
+};
 
 use crate::exit;
 use crate::protocol::{StatusRequest, StatusResponse};
@@ -73,18 +76,20 @@ fn handle_status_request(
             Option<&RecoveredState>,
             &InstancePhase,
             Option<&EntityError>,
+            Option<&ManagedInstance<LibvirtDriver>>,
         ),
         Without<ecsdk::network::InitialConnection>,
     >,
     mut commands: Commands,
 ) {
-    let response = if let Some((label, recovered, phase, error)) = query.iter().next() {
+    let response = if let Some((label, recovered, phase, error, instance)) = query.iter().next() {
         StatusResponse {
             found: true,
             label: label.map(|label| label.0.clone()),
             recovered_state: recovered.map(|recovered| recovered.0),
             phase: Some(*phase),
-            error: error.map(|error| error.0.clone()),
+            error: error.cloned(),
+            docker_host: instance.and_then(|instance| instance.driver_ref().system().docker_host()),
         }
     } else {
         StatusResponse {
@@ -93,6 +98,7 @@ fn handle_status_request(
             recovered_state: None,
             phase: None,
             error: None,
+            docker_host: None,
         }
     };
 
@@ -121,8 +127,14 @@ fn handle_status_response(
     if let Some(phase) = status.phase {
         println!("  phase: {}", phase.label());
     }
-    if let Some(error) = status.error.as_deref() {
+    if let Some(docker_host) = &status.docker_host {
+        println!("  docker: {docker_host}");
+    }
+    if let Some(error) = &status.error {
         println!("  error: {error}");
+        if let Some(hint) = &error.hint {
+            println!("  hint: {hint}");
+        }
     }
 
     if mode.0 == StatusMode::Snapshot {
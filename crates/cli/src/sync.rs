@@ -0,0 +1,173 @@
+use std::path::PathBuf;
+
+use ecsdk::app::AsyncApp;
+use ecsdk::network::{InitialConnection, IsomorphicPlugin};
+use ecsdk::prelude::*;
+use ecsdk::tasks::SpawnTask;
+use machine::driver::LibvirtDriver;
+use orchestrator::ManagedInstance;
+use orchestrator::OrchestratorMessage;
+
+use crate::agent::SharedAgent;
+use crate::protocol::{SyncRequest, SyncResponse};
+use crate::render::RenderStyle;
+
+/// Shared request feature for daemon-backed delta syncs into the guest.
+pub struct SyncFeature;
+
+impl IsomorphicPlugin for SyncFeature {
+    fn build_shared(&self, app: &mut App) {
+        SyncRequest::register(app);
+    }
+
+    fn build_server(&self, app: &mut App) {
+        app.init_resource::<SharedAgent>();
+        app.add_observer(handle_sync_request);
+    }
+
+    fn build_client(&self, app: &mut App) {
+        app.add_observer(handle_sync_response);
+        app.add_systems(Update, crate::exit::on_server_disconnect);
+    }
+}
+
+/// Client request state used to send one concrete sync request on the initial
+/// daemon connection.
+#[derive(Resource, Clone)]
+struct PendingSyncRequest(SyncRequest);
+
+/// Parse the user-facing `rum sync` arguments and resolve the local directory
+/// to an absolute path before handing control to the daemon.
+pub fn prepare_request(src: &str, dst: &str) -> anyhow::Result<SyncRequest> {
+    if src.starts_with(':') {
+        anyhow::bail!("rum sync only copies host to guest: use `rum sync <local> :<guest>`")
+    }
+    let Some(guest) = dst.strip_prefix(':') else {
+        anyhow::bail!("destination must be a guest path prefixed with `:`")
+    };
+
+    let local = PathBuf::from(src);
+    let local = if local.is_absolute() {
+        local
+    } else {
+        std::env::current_dir()?.join(local)
+    };
+    if !local.is_dir() {
+        anyhow::bail!("{} is not a directory", local.display());
+    }
+
+    Ok(SyncRequest {
+        local: Some(local),
+        guest: guest.to_string(),
+    })
+}
+
+/// Build the client app used by `rum sync`.
+pub fn build_sync_client(
+    mut app: AsyncApp<OrchestratorMessage>,
+    request: SyncRequest,
+) -> AsyncApp<OrchestratorMessage> {
+    app.insert_resource(PendingSyncRequest(request));
+    app.add_observer(send_sync_request_on_connect);
+    app
+}
+
+fn send_sync_request_on_connect(
+    _trigger: On<Add, InitialConnection>,
+    request: Res<PendingSyncRequest>,
+    mut commands: Commands,
+) {
+    commands.client_trigger(request.0.clone());
+}
+
+fn handle_sync_request(
+    trigger: On<FromClient<SyncRequest>>,
+    instances: Query<&ManagedInstance<LibvirtDriver>>,
+    agent: Res<SharedAgent>,
+    mut commands: Commands,
+) {
+    let Some(instance) = instances.iter().next() else {
+        SyncRequest::reply(
+            &mut commands,
+            trigger.event().client_id,
+            SyncResponse {
+                success: false,
+                message: "no managed instance was found".into(),
+            },
+        );
+        return;
+    };
+
+    let request = &trigger.event().message;
+    let Some(local) = request.local.clone() else {
+        SyncRequest::reply(
+            &mut commands,
+            trigger.event().client_id,
+            SyncResponse {
+                success: false,
+                message: "missing sync request payload".into(),
+            },
+        );
+        return;
+    };
+    let guest = request.guest.clone();
+
+    let driver = instance.driver();
+    let agent = agent.clone();
+    let client_id = trigger.event().client_id;
+    commands.spawn_empty().spawn_task(move |task| async move {
+        let response = match run_sync(&agent, driver, local, guest).await {
+            Ok(message) => SyncResponse {
+                success: true,
+                message,
+            },
+            Err(message) => SyncResponse {
+                success: false,
+                message,
+            },
+        };
+
+        task.queue_cmd_wake(move |world: &mut World| {
+            let mut commands = world.commands();
+            SyncRequest::reply(&mut commands, client_id, response);
+        });
+    });
+}
+
+async fn run_sync(
+    agent: &SharedAgent,
+    driver: LibvirtDriver,
+    local: PathBuf,
+    guest: String,
+) -> Result<String, String> {
+    let client = agent.client(&driver).await?;
+
+    let stats = client
+        .sync_to_guest(&local, &guest)
+        .await
+        .map_err(|error| error.to_string())?;
+    Ok(format!(
+        "synced {} to guest:{}: {} files transferred ({} bytes), {} unchanged",
+        local.display(),
+        guest,
+        stats.transferred,
+        stats.bytes,
+        stats.unchanged
+    ))
+}
+
+fn handle_sync_response(
+    trigger: On<SyncResponse>,
+    style: Option<Res<RenderStyle>>,
+    mut exit: MessageWriter<AppExit>,
+) {
+    let response = trigger.event();
+    let style = style.as_deref().copied().unwrap_or_default();
+    if response.success {
+        println!("{}", style.text(&response.message));
+        exit.write(AppExit::Success);
+    } else {
+        eprintln!("{}", style.error(&style.text(&response.message)));
+        exit.write(AppExit::from_code(1));
+    }
+}
@@ -0,0 +1,373 @@
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use anyhow::Context;
+use ecsdk::app::AsyncApp;
+use ecsdk::network::{InitialConnection, IsomorphicPlugin};
+use ecsdk::prelude::*;
+use ecsdk::tasks::SpawnTask;
+use guest::agent::{ExecOptions, LogStream};
+use machine::config::TestConfig;
+use machine::driver::LibvirtDriver;
+use machine::test_run::{self, OUTPUT_LINES, TestCheck, TestOutcome};
+use orchestrator::{
+    InstancePhase, LogBuffer, ManagedInstance, OrchestratorMessage, ProvisionLogView,
+};
+
+use crate::agent::SharedAgent;
+use crate::protocol::{TestRequest, TestResponse};
+use crate::render::RenderStyle;
+
+/// Shared request feature for running the `[[tests]]` checks through the
+/// daemon.
+pub struct TestFeature;
+
+impl IsomorphicPlugin for TestFeature {
+    fn build_shared(&self, app: &mut App) {
+        TestRequest::register(app);
+    }
+
+    fn build_server(&self, app: &mut App) {
+        app.init_resource::<SharedAgent>();
+        app.add_observer(handle_test_request);
+    }
+
+    fn build_client(&self, app: &mut App) {
+        app.add_observer(handle_test_response);
+        app.add_systems(Update, crate::exit::on_server_disconnect);
+    }
+}
+
+/// Format of the report `rum test --report` writes.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default)]
+pub enum ReportFormat {
+    /// One JUnit `<testsuite>`, as most CI systems read it.
+    #[default]
+    Junit,
+    /// A JSON array with one object per test.
+    Json,
+}
+
+/// Where the client writes the report, and the suite name it carries.
+#[derive(Resource, Clone)]
+pub struct TestReport {
+    pub path: PathBuf,
+    pub format: ReportFormat,
+    pub suite: String,
+}
+
+/// Shared slot the test client fills with the exit code of `rum test`.
+#[derive(Resource, Clone, Default)]
+pub struct TestExit(pub Arc<Mutex<Option<i32>>>);
+
+impl TestExit {
+    pub fn exit_code(&self) -> Option<i32> {
+        *self.0.lock().expect("test exit lock poisoned")
+    }
+}
+
+#[derive(Resource, Clone)]
+struct PendingTestRequest(TestRequest);
+
+/// Build the client app used by `rum test`.
+pub fn build_test_client(
+    mut app: AsyncApp<OrchestratorMessage>,
+    request: TestRequest,
+    report: Option<TestReport>,
+    exit: TestExit,
+) -> AsyncApp<OrchestratorMessage> {
+    app.insert_resource(PendingTestRequest(request));
+    app.insert_resource(exit);
+    if let Some(report) = report {
+        app.insert_resource(report);
+    }
+    app.add_observer(send_test_request_on_connect);
+    app
+}
+
+fn send_test_request_on_connect(
+    _trigger: On<Add, InitialConnection>,
+    request: Res<PendingTestRequest>,
+    mut commands: Commands,
+) {
+    commands.client_trigger(request.0.clone());
+}
+
+fn handle_test_request(
+    trigger: On<FromClient<TestRequest>>,
+    instances: Query<(Entity, &ManagedInstance<LibvirtDriver>, &InstancePhase)>,
+    views: Query<&ProvisionLogView>,
+    mut buffers: Query<&mut LogBuffer>,
+    agent: Res<SharedAgent>,
+    mut commands: Commands,
+) {
+    let client_id = trigger.event().client_id;
+    let reject = |commands: &mut Commands, message: String| {
+        TestRequest::reply(
+            commands,
+            client_id,
+            TestResponse {
+                outcomes: Vec::new(),
+                message: Some(message),
+            },
+        );
+    };
+
+    let Some((instance_entity, instance, phase)) = instances.iter().next() else {
+        reject(&mut commands, "no managed instance was found".into());
+        return;
+    };
+    if *phase != InstancePhase::Running {
+        reject(
+            &mut commands,
+            format!("instance is {}, not running", phase.label()),
+        );
+        return;
+    }
+    let tests = match select_tests(
+        &instance.driver().system().config.tests,
+        &trigger.event().message.names,
+    ) {
+        Ok(tests) => tests,
+        Err(message) => {
+            reject(&mut commands, message);
+            return;
+        }
+    };
+
+    if let Ok(mut buffer) = buffers.get_mut(instance_entity) {
+        buffer.lines.clear();
+    }
+    if let Ok(entries) = views.get(instance_entity) {
+        for entry in entries.iter() {
+            commands.entity(entry).despawn();
+        }
+    }
+
+    let driver = instance.driver();
+    let agent = agent.clone();
+    commands.spawn_empty().spawn_task(move |task| async move {
+        let log_task = task.clone();
+        let log = move |line: String| {
+            log_task.queue_cmd_tick(move |world: &mut World| {
+                if let Some(mut buffer) = world.get_mut::<LogBuffer>(instance_entity) {
+                    buffer.push(line);
+                }
+            });
+        };
+
+        let response = match run_tests(&agent, &driver, &tests, log).await {
+            Ok(outcomes) => TestResponse {
+                outcomes,
+                message: None,
+            },
+            Err(message) => TestResponse {
+                outcomes: Vec::new(),
+                message: Some(message),
+            },
+        };
+
+        task.queue_cmd_wake(move |world: &mut World| {
+            let mut commands = world.commands();
+            TestRequest::reply(&mut commands, client_id, response);
+        });
+    });
+}
+
+/// The configured tests `names` picks, in config order; all of them when
+/// `names` is empty.
+fn select_tests(tests: &[TestConfig], names: &[String]) -> Result<Vec<TestCheck>, String> {
+    if tests.is_empty() {
+        return Err("no [[tests]] are configured".into());
+    }
+    if let Some(unknown) = names
+        .iter()
+        .find(|name| !tests.iter().any(|test| &test.name == *name))
+    {
+        let known: Vec<&str> = tests.iter().map(|test| test.name.as_str()).collect();
+        return Err(format!(
+            "no test named '{unknown}'; the config has: {}",
+            known.join(", ")
+        ));
+    }
+    tests
+        .iter()
+        .filter(|test| names.is_empty() || names.contains(&test.name))
+        .map(|test| TestCheck::new(test.clone()).map_err(|e| e.to_string()))
+        .collect()
+}
+
+/// Run `tests` one after another, logging a line per result and the output
+/// tail of each failure.
+async fn run_tests<F>(
+    agent: &SharedAgent,
+    driver: &LibvirtDriver,
+    tests: &[TestCheck],
+    log: F,
+) -> Result<Vec<TestOutcome>, String>
+where
+    F: Fn(String) + Send + Sync,
+{
+    let client = agent.client(driver).await?;
+    let mut outcomes = Vec::with_capacity(tests.len());
+    for check in tests {
+        let test = &check.config;
+        log(format!("RUN  {}", test.name));
+        let stdout = Arc::new(Mutex::new(String::new()));
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let options = ExecOptions {
+            user: test.user.clone(),
+            cwd: None,
+            env: Vec::new(),
+            timeout_s: Some(test.timeout_s),
+            execution_id: None,
+        };
+        let started = Instant::now();
+        let result = {
+            let (stdout, output) = (stdout.clone(), output.clone());
+            client
+                .exec_with_result(test.command.clone(), options, move |event| {
+                    if matches!(event.stream, LogStream::Stdout) {
+                        let mut stdout = stdout.lock().expect("test stdout lock poisoned");
+                        stdout.push_str(&event.message);
+                        stdout.push('\n');
+                    }
+                    output
+                        .lock()
+                        .expect("test output lock poisoned")
+                        .push(event.message);
+                })
+                .await
+        };
+        let elapsed = started.elapsed();
+
+        let failure = match result {
+            Err(error) => Some(error.to_string()),
+            Ok(result) if result.timed_out => Some(format!("timed out after {}s", test.timeout_s)),
+            Ok(result) => {
+                let stdout = stdout.lock().expect("test stdout lock poisoned");
+                check.failure(result.exit_code.unwrap_or(1), &stdout)
+            }
+        };
+        let mut output = std::mem::take(&mut *output.lock().expect("test output lock poisoned"));
+        let outcome = TestOutcome {
+            name: test.name.clone(),
+            passed: failure.is_none(),
+            duration_ms: elapsed.as_millis() as u64,
+            output: match failure {
+                Some(_) => output.split_off(output.len().saturating_sub(OUTPUT_LINES)),
+                None => Vec::new(),
+            },
+            failure,
+        };
+
+        let secs = elapsed.as_secs_f64();
+        match &outcome.failure {
+            None => log(format!("PASS {} ({secs:.1}s)", test.name)),
+            Some(failure) => {
+                log(format!("FAIL {}: {failure}", test.name));
+                for line in &outcome.output {
+                    log(format!("     {line}"));
+                }
+            }
+        }
+        outcomes.push(outcome);
+    }
+    Ok(outcomes)
+}
+
+fn handle_test_response(
+    trigger: On<TestResponse>,
+    report: Option<Res<TestReport>>,
+    outcome: Res<TestExit>,
+    style: Option<Res<RenderStyle>>,
+    mut exit: MessageWriter<AppExit>,
+) {
+    let response = trigger.event();
+    let style = style.as_deref().copied().unwrap_or_default();
+    let exit_code = match response.message.as_deref() {
+        Some(message) => {
+            eprintln!("{}", style.error(&style.text(message)));
+            1
+        }
+        None => {
+            let failed: Vec<&TestOutcome> =
+                response.outcomes.iter().filter(|o| !o.passed).collect();
+            for test in &failed {
+                eprintln!(
+                    "{} {}: {}",
+                    style.error("FAIL"),
+                    style.text(&test.name),
+                    style.text(test.failure.as_deref().unwrap_or_default())
+                );
+            }
+            println!(
+                "{} passed, {} failed",
+                response.outcomes.len() - failed.len(),
+                failed.len()
+            );
+            let written = report.map_or(Ok(()), |report| write_report(&report, response));
+            if let Err(error) = &written {
+                eprintln!("{}", style.error(&format!("{error:#}")));
+            }
+            i32::from(!failed.is_empty() || written.is_err())
+        }
+    };
+
+    *outcome.0.lock().expect("test exit lock poisoned") = Some(exit_code);
+    exit.write(AppExit::from_code(exit_code as u8));
+}
+
+fn write_report(report: &TestReport, response: &TestResponse) -> anyhow::Result<()> {
+    let content = match report.format {
+        ReportFormat::Junit => test_run::junit_xml(&report.suite, &response.outcomes),
+        ReportFormat::Json => test_run::json_report(&response.outcomes),
+    };
+    std::fs::write(&report.path, content)
+        .with_context(|| format!("writing test report {}", report.path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn configured() -> Vec<TestConfig> {
+        ["nginx", "api", "db"]
+            .into_iter()
+            .map(|name| TestConfig {
+                name: name.into(),
+                command: "true".into(),
+                ..TestConfig::default()
+            })
+            .collect()
+    }
+
+    fn names(checks: &[TestCheck]) -> Vec<&str> {
+        checks
+            .iter()
+            .map(|check| check.config.name.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn select_tests_keeps_config_order() {
+        let tests = configured();
+        assert_eq!(
+            names(&select_tests(&tests, &[]).unwrap()),
+            ["nginx", "api", "db"]
+        );
+        let picked = select_tests(&tests, &["db".into(), "nginx".into()]).unwrap();
+        assert_eq!(names(&picked), ["nginx", "db"]);
+    }
+
+    #[test]
+    fn select_tests_rejects_unknown_names_and_empty_configs() {
+        let error = select_tests(&configured(), &["web".into()]).unwrap_err();
+        assert_eq!(error, "no test named 'web'; the config has: nginx, api, db");
+        assert_eq!(
+            select_tests(&[], &[]).unwrap_err(),
+            "no [[tests]] are configured"
+        );
+    }
+}
@@ -0,0 +1,22 @@
+use anyhow::Context;
+use machine::config::SystemConfig;
+use machine::driver::LibvirtDriver;
+use tokio::net::TcpListener;
+
+/// Run a SOCKS5 proxy on `bind:port` that opens every connection from
+/// inside the running guest, until Ctrl+C.
+pub async fn run(system: &SystemConfig, bind: &str, port: u16) -> anyhow::Result<()> {
+    let cid = LibvirtDriver::new(system.clone()).get_vsock_cid()?;
+    let listener = TcpListener::bind((bind, port))
+        .await
+        .with_context(|| format!("failed to listen on {bind}:{port}"))?;
+    let addr = listener.local_addr()?;
+    println!("SOCKS5 proxy on {addr} via '{}'", system.display_name());
+    println!("press Ctrl+C to stop");
+
+    tokio::select! {
+        result = machine::socks::serve(listener, cid) => result?,
+        result = tokio::signal::ctrl_c() => result.context("failed to wait for Ctrl+C")?,
+    }
+    Ok(())
+}
@@ -0,0 +1,39 @@
+use anyhow::bail;
+use machine::config::SystemConfig;
+use machine::driver::LibvirtDriver;
+
+/// Open the machine's graphical console in `virt-viewer`, or print its URI.
+///
+/// Falls back to printing the URI when `virt-viewer` is not installed.
+pub fn run(system: &SystemConfig, print: bool) -> anyhow::Result<()> {
+    if system.config.display.protocol == "none" {
+        bail!("machine is headless; set `[display] protocol` to \"spice\" or \"vnc\"");
+    }
+
+    let driver = LibvirtDriver::new(system.clone());
+    let Some(uri) = driver.display_uri()? else {
+        bail!(
+            "libvirt has not assigned a display port to '{}'",
+            system.display_name()
+        );
+    };
+
+    if print {
+        println!("{uri}");
+        return Ok(());
+    }
+
+    let status = std::process::Command::new("virt-viewer")
+        .args(["--connect", system.libvirt_uri(), system.display_name()])
+        .status();
+    match status {
+        Ok(status) if status.success() => Ok(()),
+        Ok(status) => bail!("virt-viewer exited with {status}"),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            eprintln!("virt-viewer not found; connect with any SPICE/VNC client:");
+            println!("{uri}");
+            Ok(())
+        }
+        Err(e) => Err(e.into()),
+    }
+}
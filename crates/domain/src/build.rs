@@ -2,11 +2,76 @@
 
 use std::path::Path;
 
-use crate::{DomainConfig, ResolvedDrive, ResolvedMount, prefixed_name};
+use crate::{
+    AGENT_CHANNEL, DiskTuning, DomainConfig, METADATA_NAMESPACE, ResolvedDrive, ResolvedMount,
+    prefixed_name, shared_name,
+};
 
 use super::model::*;
 use super::support::generate_mac;
 
+/// Generate the `<filesystem>` device XML of one share, for hot-plugging
+/// it into a running domain.
+pub fn generate_filesystem_xml(mount: &ResolvedMount) -> String {
+    facet_xml::to_string(&filesystem(mount)).expect("filesystem XML serialization should not fail")
+}
+
+/// Generate the `<domainsnapshot>` XML of a disk-only external snapshot
+/// that moves the writes of the root disk to `working_path`.
+///
+/// The writable `drives` (by target dev) are left out of the snapshot;
+/// libvirt skips read-only disks on its own.
+pub fn generate_root_snapshot_xml(working_path: &Path, drives: &[&str]) -> String {
+    let mut disk = vec![SnapshotDisk {
+        name: "vda".into(),
+        snapshot: "external".into(),
+        driver: Some(SnapshotDriver {
+            driver_type: "qcow2".into(),
+        }),
+        source: Some(DiskSource {
+            file: Some(working_path.display().to_string()),
+            dev: None,
+        }),
+    }];
+    disk.extend(drives.iter().map(|dev| SnapshotDisk {
+        name: dev.to_string(),
+        snapshot: "no".into(),
+        driver: None,
+        source: None,
+    }));
+    let snapshot = DomainSnapshot {
+        disks: SnapshotDisks { disk },
+    };
+    facet_xml::to_string(&snapshot).expect("snapshot XML serialization should not fail")
+}
+
+/// virtiofs runs as its own daemon and passes ownership through; 9p runs
+/// inside QEMU, which cannot change file owners, so it stores them mapped.
+fn filesystem(mount: &ResolvedMount) -> Filesystem {
+    let (accessmode, driver) = if mount.nine_p {
+        ("mapped", None)
+    } else {
+        (
+            "passthrough",
+            Some(FsDriver {
+                driver_type: "virtiofs".into(),
+            }),
+        )
+    };
+    Filesystem {
+        fs_type: "mount".into(),
+        accessmode: accessmode.into(),
+        driver,
+        source: FsSource {
+            dir: mount.source.display().to_string(),
+        },
+        target: FsTarget {
+            dir: mount.tag.clone(),
+        },
+        readonly: if mount.readonly { Some(Empty {}) } else { None },
+    }
+}
+
 /// Generate libvirt domain XML from config.
 ///
 /// Uses compact (single-line) output because facet-xml's pretty-printer
@@ -18,7 +83,8 @@ pub fn generate_domain_xml(
     mounts: &[ResolvedMount],
     drives: &[ResolvedDrive],
 ) -> String {
-    let memory_backing = if mounts.is_empty() {
+    // Only virtiofsd needs guest memory shared with it.
+    let memory_backing = if mounts.iter().all(|m| m.nine_p) {
         None
     } else {
         Some(MemoryBacking {
@@ -31,32 +97,17 @@ pub fn generate_domain_xml(
         })
     };
 
-    let filesystems: Vec<Filesystem> = mounts
-        .iter()
-        .map(|m| Filesystem {
-            fs_type: "mount".into(),
-            accessmode: "passthrough".into(),
-            driver: FsDriver {
-                driver_type: "virtiofs".into(),
-            },
-            source: FsSource {
-                dir: m.source.display().to_string(),
-            },
-            target: FsTarget { dir: m.tag.clone() },
-            readonly: if m.readonly { Some(Empty {}) } else { None },
-        })
-        .collect();
+    let filesystems: Vec<Filesystem> = mounts.iter().map(filesystem).collect();
 
+    let mut iothreads = 0;
     let mut disks = vec![
         Disk {
             disk_type: "file".into(),
             device: "disk".into(),
-            driver: DiskDriver {
-                name: "qemu".into(),
-                driver_type: "qcow2".into(),
-            },
+            driver: disk_driver("qcow2", &config.root_disk, &mut iothreads),
             source: DiskSource {
-                file: overlay_path.display().to_string(),
+                file: Some(overlay_path.display().to_string()),
+                dev: None,
             },
             target: DiskTarget {
                 dev: "vda".into(),
@@ -67,12 +118,10 @@ pub fn generate_domain_xml(
         Disk {
             disk_type: "file".into(),
             device: "cdrom".into(),
-            driver: DiskDriver {
-                name: "qemu".into(),
-                driver_type: "raw".into(),
-            },
+            driver: disk_driver("raw", &DiskTuning::default(), &mut iothreads),
             source: DiskSource {
-                file: seed_path.display().to_string(),
+                file: Some(seed_path.display().to_string()),
+                dev: None,
             },
             target: DiskTarget {
                 dev: "sda".into(),
@@ -82,23 +131,47 @@ pub fn generate_domain_xml(
         },
     ];
 
+    // Extra CD-ROMs (sdb, sdc, ...) from [[cdroms]] config
+    for (i, path) in config.cdroms.iter().enumerate() {
+        disks.push(Disk {
+            disk_type: "file".into(),
+            device: "cdrom".into(),
+            driver: disk_driver("raw", &DiskTuning::default(), &mut iothreads),
+            source: DiskSource {
+                file: Some(path.display().to_string()),
+                dev: None,
+            },
+            target: DiskTarget {
+                dev: format!("sd{}", (b'b' + i as u8) as char),
+                bus: "sata".into(),
+            },
+            readonly: Some(Empty {}),
+        });
+    }
+
     // Extra drives (vdb, vdc, ...) from [drives] config
     for drive in drives {
+        let path = drive.path.display().to_string();
         disks.push(Disk {
-            disk_type: "file".into(),
+            disk_type: if drive.block { "block" } else { "file" }.into(),
             device: "disk".into(),
-            driver: DiskDriver {
-                name: "qemu".into(),
-                driver_type: "qcow2".into(),
-            },
-            source: DiskSource {
-                file: drive.path.display().to_string(),
+            driver: disk_driver(&drive.format, &drive.tuning, &mut iothreads),
+            source: if drive.block {
+                DiskSource {
+                    file: None,
+                    dev: Some(path),
+                }
+            } else {
+                DiskSource {
+                    file: Some(path),
+                    dev: None,
+                }
             },
             target: DiskTarget {
                 dev: drive.dev.clone(),
                 bus: "virtio".into(),
             },
-            readonly: None,
+            readonly: drive.readonly.then_some(Empty {}),
         });
     }
 
@@ -108,7 +181,10 @@ pub fn generate_domain_xml(
     if config.nat {
         interfaces.push(Interface {
             iface_type: "network".into(),
-            mac: None,
+            mac: config
+                .nat_mac
+                .clone()
+                .map(|address| InterfaceMac { address }),
             source: InterfaceSource {
                 network: "default".into(),
             },
@@ -120,7 +196,11 @@ pub fn generate_domain_xml(
 
     let display = &config.name;
     for (i, iface_cfg) in config.interfaces.iter().enumerate() {
-        let libvirt_name = prefixed_name(&config.id, &iface_cfg.network);
+        let libvirt_name = if iface_cfg.shared {
+            shared_name(&iface_cfg.network)
+        } else {
+            prefixed_name(&config.id, &iface_cfg.network)
+        };
         interfaces.push(Interface {
             iface_type: "network".into(),
             mac: Some(InterfaceMac {
@@ -135,27 +215,74 @@ pub fn generate_domain_xml(
         });
     }
 
+    let aarch64 = config.arch == "aarch64";
     let domain = Domain {
         domain_type: config.domain_type.clone(),
         name: config.name.clone(),
+        metadata: Metadata {
+            instance: RumInstance {
+                xmlns: METADATA_NAMESPACE.into(),
+                id: config.id.clone(),
+                profile: config.profile.clone(),
+                work_dir: config
+                    .work_dir
+                    .as_ref()
+                    .map(|dir| dir.display().to_string()),
+                label: config
+                    .labels
+                    .iter()
+                    .map(|(name, value)| RumLabel {
+                        name: name.clone(),
+                        value: value.clone(),
+                    })
+                    .collect(),
+            },
+        },
         memory: Memory {
+            unit: "KiB".into(),
+            value: config.memory_max_mb.unwrap_or(config.memory_mb) * 1024,
+        },
+        current_memory: config.memory_max_mb.map(|_| Memory {
             unit: "KiB".into(),
             value: config.memory_mb * 1024,
+        }),
+        vcpu: Vcpu {
+            current: config.cpus_max.map(|_| config.cpus),
+            value: config.cpus_max.unwrap_or(config.cpus),
         },
-        vcpu: config.cpus,
+        iothreads: (iothreads > 0).then_some(iothreads),
         os: Os {
+            firmware: config.uefi.as_ref().map(|_| "efi".into()),
             os_type: OsType {
-                arch: "x86_64".into(),
+                arch: config.arch.clone(),
                 machine: config.machine.clone(),
                 value: "hvm".into(),
             },
+            loader: config.uefi.as_ref().and_then(|uefi| {
+                uefi.loader.as_ref().map(|loader| Loader {
+                    readonly: "yes".into(),
+                    loader_type: "pflash".into(),
+                    value: loader.display().to_string(),
+                })
+            }),
+            nvram: config.uefi.as_ref().map(|uefi| Nvram {
+                template: uefi
+                    .nvram_template
+                    .as_ref()
+                    .map(|template| template.display().to_string()),
+                value: uefi.nvram.display().to_string(),
+            }),
             boot: Boot { dev: "hd".into() },
         },
         memory_backing,
         features: Features {
             acpi: Empty {},
-            apic: Empty {},
+            apic: (!aarch64).then_some(Empty {}),
+            gic: aarch64.then(|| Gic {
+                version: "3".into(),
+            }),
         },
+        cpu: build_cpu(config, aarch64),
         devices: Devices {
             disk: disks,
             filesystem: filesystems,
@@ -171,14 +298,104 @@ pub fn generate_domain_xml(
                     port: "0".into(),
                 },
             },
-            vsock: Vsock {
+            channel: config.agent_channel.as_ref().map(|path| Channel {
+                channel_type: "unix".into(),
+                source: ChannelSource {
+                    mode: "bind".into(),
+                    path: path.display().to_string(),
+                },
+                target: ChannelTarget {
+                    target_type: "virtio".into(),
+                    name: AGENT_CHANNEL.into(),
+                },
+            }),
+            vsock: config.vsock.then(|| Vsock {
                 model: "virtio".into(),
                 cid: VsockCid {
                     auto: "yes".into(),
                 },
-            },
+            }),
+            memballoon: config.memory_max_mb.map(|_| MemBalloon {
+                model: "virtio".into(),
+            }),
+            graphics: config.display.as_ref().map(|display| Graphics {
+                graphics_type: display.protocol.clone(),
+                autoport: if display.port.is_some() { "no" } else { "yes" }.into(),
+                port: display.port.map(|port| port.to_string()),
+                listen: display.listen.clone(),
+            }),
+            video: config.display.as_ref().map(|_| Video {
+                model: VideoModel {
+                    model_type: "virtio".into(),
+                },
+            }),
+            input: config.display.as_ref().map(|_| Input {
+                input_type: "tablet".into(),
+                bus: "usb".into(),
+            }),
+            // The ISA pvpanic device only exists on x86.
+            panic: (!aarch64).then(|| Panic {
+                model: "isa".into(),
+            }),
         },
     };
 
     facet_xml::to_string(&domain).expect("domain XML serialization should not fail")
 }
+
+/// `<driver>` for a disk, allocating the next I/O thread id when requested.
+fn disk_driver(format: &str, tuning: &DiskTuning, iothreads: &mut u32) -> DiskDriver {
+    let iothread = tuning.iothread.then(|| {
+        *iothreads += 1;
+        *iothreads
+    });
+    DiskDriver {
+        name: "qemu".into(),
+        driver_type: format.into(),
+        cache: tuning.cache.clone(),
+        io: tuning.io.clone(),
+        discard: tuning.discard.then(|| "unmap".into()),
+        iothread,
+    }
+}
+
+/// `<cpu>` from the configured model and topology.
+///
+/// QEMU's default `virt` CPU is a 32-bit cortex-a15, so aarch64 guests always
+/// need an explicit CPU: the host's under KVM, an emulated 64-bit core under TCG.
+fn build_cpu(config: &DomainConfig, aarch64: bool) -> Option<Cpu> {
+    let model = match config.cpu.model.as_deref() {
+        Some(model) => Some(model),
+        None if aarch64 && config.domain_type == "kvm" => Some("host-passthrough"),
+        None if aarch64 => Some("cortex-a57"),
+        None => None,
+    };
+    let topology = config
+        .cpu
+        .topology
+        .map(|(sockets, cores, threads)| CpuTopology {
+            sockets,
+            cores,
+            threads,
+        });
+    if model.is_none() && topology.is_none() {
+        return None;
+    }
+
+    let (mode, model) = match model {
+        Some(mode @ ("host-passthrough" | "host-model")) => (Some(mode.to_string()), None),
+        Some(name) => (
+            Some("custom".to_string()),
+            Some(CpuModel {
+                fallback: "allow".into(),
+                value: name.to_string(),
+            }),
+        ),
+        None => (None, None),
+    };
+    Some(Cpu {
+        mode,
+        model,
+        topology,
+    })
+}
@@ -3,25 +3,83 @@ mod model;
 mod network_xml;
 mod support;
 
+use std::collections::BTreeMap;
 use std::path::PathBuf;
 
+/// XML namespace for the `<rum:instance>` element inside domain `<metadata>`.
+pub const METADATA_NAMESPACE: &str = "urn:rum:instance:1";
+
+/// Name of the virtio-serial channel the guest agent listens on besides
+/// vsock.
+pub const AGENT_CHANNEL: &str = "org.rum.agent.0";
+
 #[derive(Debug, Clone)]
 pub struct ResolvedMount {
     pub source: PathBuf,
     pub target: String,
     pub readonly: bool,
     pub tag: String,
+    /// Share over virtio-9p instead of virtiofs.
+    pub nine_p: bool,
 }
 
 #[derive(Debug, Clone)]
 pub struct ResolvedDrive {
     pub path: PathBuf,
     pub dev: String,
+    /// `path` is a host block device (`<disk type="block">`).
+    pub block: bool,
+    pub readonly: bool,
+    /// Image format for `<driver type>`: `qcow2` or `raw`.
+    pub format: String,
+    pub tuning: DiskTuning,
+}
+
+/// `<driver>` I/O attributes of a disk; unset fields keep QEMU defaults.
+#[derive(Debug, Clone, Default)]
+pub struct DiskTuning {
+    pub cache: Option<String>,
+    pub io: Option<String>,
+    pub discard: bool,
+    /// Give the disk its own I/O thread.
+    pub iothread: bool,
+}
+
+/// UEFI firmware for a domain.
+#[derive(Debug, Clone)]
+pub struct UefiConfig {
+    /// Read-only firmware code image. `None` lets libvirt pick one.
+    pub loader: Option<PathBuf>,
+    /// Per-VM variable store, created from `nvram_template` on first boot.
+    pub nvram: PathBuf,
+    pub nvram_template: Option<PathBuf>,
+}
+
+/// Guest CPU model and topology.
+#[derive(Debug, Clone, Default)]
+pub struct CpuConfig {
+    /// `host-passthrough`, `host-model`, or a named QEMU model.
+    pub model: Option<String>,
+    /// `(sockets, cores, threads)`.
+    pub topology: Option<(u32, u32, u32)>,
+}
+
+/// Graphical console exposed by the domain.
+#[derive(Debug, Clone)]
+pub struct DisplayConfig {
+    /// `spice` or `vnc`.
+    pub protocol: String,
+    pub listen: String,
+    /// Fixed port; `None` lets libvirt pick a free one.
+    pub port: Option<u16>,
 }
 
 #[derive(Debug, Clone)]
 pub struct InterfaceConfig {
     pub network: String,
+    /// Attach to the shared `rum-net-<network>` network instead of the
+    /// VM's own `rum-<id>-<network>`.
+    pub shared: bool,
 }
 
 #[derive(Debug, Clone)]
@@ -30,15 +88,65 @@ pub struct DomainConfig {
     pub name: String,
     pub domain_type: String,
     pub machine: String,
+    /// Guest CPU architecture: `x86_64` or `aarch64`. Libvirt picks the
+    /// matching `qemu-system-*` emulator from its capabilities.
+    pub arch: String,
+    /// Boot through UEFI instead of SeaBIOS.
+    pub uefi: Option<UefiConfig>,
+    /// Headless when `None`.
+    pub display: Option<DisplayConfig>,
     pub memory_mb: u64,
+    /// Memory ceiling for live balloon resizing.
+    pub memory_max_mb: Option<u64>,
     pub cpus: u32,
+    /// vCPU ceiling for live hotplug.
+    pub cpus_max: Option<u32>,
+    pub cpu: CpuConfig,
+    /// I/O tuning of the root overlay disk.
+    pub root_disk: DiskTuning,
     pub nat: bool,
+    /// Fixed MAC of the NAT interface, so the guest can tell it apart from
+    /// the extra ones. Libvirt picks one when unset.
+    pub nat_mac: Option<String>,
     pub interfaces: Vec<InterfaceConfig>,
+    /// Extra read-only ISO images, attached after the seed as `sdb`, `sdc`, ...
+    pub cdroms: Vec<PathBuf>,
+    pub labels: BTreeMap<String, String>,
+    /// Work dir holding the machine's state. Kept in the metadata so
+    /// `rum clean` only touches domains under the caller's own data dir.
+    pub work_dir: Option<PathBuf>,
+    /// Config profile the machine was defined with. Kept in the metadata so
+    /// switching profiles changes the XML and redefines the domain.
+    pub profile: Option<String>,
+    /// Host socket of a virtio-serial channel to the guest agent, for
+    /// guests or hosts without vsock.
+    pub agent_channel: Option<PathBuf>,
+    /// Whether to give the domain a vsock device; needs `vhost-vsock` on
+    /// the host.
+    pub vsock: bool,
+}
+
+/// rum-owned metadata recovered from a live domain definition.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InstanceMetadata {
+    pub id: String,
+    pub labels: BTreeMap<String, String>,
+    pub profile: Option<String>,
+    /// Work dir of the machine; `None` for domains defined before rum
+    /// recorded it.
+    pub work_dir: Option<PathBuf>,
 }
 
 #[cfg(test)]
 mod tests;
 
-pub use build::generate_domain_xml;
-pub use support::{generate_mac, parse_vsock_cid, xml_has_changed};
-pub use network_xml::{derive_subnet, generate_network_xml, prefixed_name};
+pub use build::{generate_domain_xml, generate_filesystem_xml, generate_root_snapshot_xml};
+pub use support::{
+    DomainChange, attaches_to_network, domain_changes, generate_mac, generate_nat_mac,
+    parse_display_uri, parse_filesystem_tags, parse_instance_metadata, parse_interface_macs,
+    parse_network_macs, parse_vsock_cid, xml_changes,
+};
+pub use network_xml::{
+    NetworkOptions, derive_free_subnet, derive_ipv6_prefix, derive_subnet, generate_network_xml,
+    parse_ip_index, parse_network_subnet, prefixed_name, shared_name, subnet_collision,
+};
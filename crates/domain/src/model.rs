@@ -7,22 +7,62 @@ use facet_xml as xml;
 // `#[facet(xml::attribute)]`, text content uses `#[facet(xml::text)]`,
 // and child elements are nested structs.
 
-#[derive(Debug, Facet)]
+#[derive(Debug, PartialEq, Facet)]
 #[facet(rename = "domain")]
 pub(super) struct Domain {
     #[facet(xml::attribute, rename = "type")]
     pub(super) domain_type: String,
     pub(super) name: String,
+    pub(super) metadata: Metadata,
+    /// Maximum memory; equals the boot allocation unless hotplug headroom is set.
     pub(super) memory: Memory,
-    pub(super) vcpu: u32,
+    #[facet(default, rename = "currentMemory")]
+    pub(super) current_memory: Option<Memory>,
+    pub(super) vcpu: Vcpu,
+    #[facet(default)]
+    pub(super) iothreads: Option<u32>,
     pub(super) os: Os,
     #[facet(default, rename = "memoryBacking")]
     pub(super) memory_backing: Option<MemoryBacking>,
     pub(super) features: Features,
+    #[facet(default)]
+    pub(super) cpu: Option<Cpu>,
     pub(super) devices: Devices,
 }
 
-#[derive(Debug, Facet)]
+// ── metadata (rum instance identity and labels) ────────────
+
+#[derive(Debug, PartialEq, Facet)]
+pub(super) struct Metadata {
+    #[facet(rename = "rum:instance")]
+    pub(super) instance: RumInstance,
+}
+
+#[derive(Debug, PartialEq, Facet)]
+#[facet(rename = "rum:instance")]
+pub(super) struct RumInstance {
+    #[facet(xml::attribute, rename = "xmlns:rum")]
+    pub(super) xmlns: String,
+    #[facet(xml::attribute)]
+    pub(super) id: String,
+    #[facet(xml::attribute)]
+    pub(super) profile: Option<String>,
+    #[facet(xml::attribute, rename = "workdir")]
+    pub(super) work_dir: Option<String>,
+    #[facet(default, rename = "rum:label")]
+    pub(super) label: Vec<RumLabel>,
+}
+
+#[derive(Debug, PartialEq, Facet)]
+#[facet(rename = "rum:label")]
+pub(super) struct RumLabel {
+    #[facet(xml::attribute)]
+    pub(super) name: String,
+    #[facet(xml::attribute)]
+    pub(super) value: String,
+}
+
+#[derive(Debug, PartialEq, Facet)]
 pub(super) struct Memory {
     #[facet(xml::attribute)]
     pub(super) unit: String,
@@ -30,16 +70,51 @@ pub(super) struct Memory {
     pub(super) value: u64,
 }
 
+/// Maximum vCPUs, with `current` online at boot when hotplug headroom is set.
+#[derive(Debug, PartialEq, Facet)]
+pub(super) struct Vcpu {
+    #[facet(xml::attribute, default)]
+    pub(super) current: Option<u32>,
+    #[facet(xml::text)]
+    pub(super) value: u32,
+}
+
 // ── OS ─────────────────────────────────────────────────────
 
-#[derive(Debug, Facet)]
+#[derive(Debug, PartialEq, Facet)]
 pub(super) struct Os {
+    /// `efi` for UEFI guests; libvirt's firmware autoselection fills in
+    /// whatever loader/nvram we leave out.
+    #[facet(xml::attribute, default)]
+    pub(super) firmware: Option<String>,
     #[facet(rename = "type")]
     pub(super) os_type: OsType,
+    #[facet(default)]
+    pub(super) loader: Option<Loader>,
+    #[facet(default)]
+    pub(super) nvram: Option<Nvram>,
     pub(super) boot: Boot,
 }
 
-#[derive(Debug, Facet)]
+#[derive(Debug, PartialEq, Facet)]
+pub(super) struct Loader {
+    #[facet(xml::attribute)]
+    pub(super) readonly: String,
+    #[facet(xml::attribute, rename = "type")]
+    pub(super) loader_type: String,
+    #[facet(xml::text)]
+    pub(super) value: String,
+}
+
+#[derive(Debug, PartialEq, Facet)]
+pub(super) struct Nvram {
+    #[facet(xml::attribute, default)]
+    pub(super) template: Option<String>,
+    #[facet(xml::text)]
+    pub(super) value: String,
+}
+
+#[derive(Debug, PartialEq, Facet)]
 #[facet(rename = "type")]
 pub(super) struct OsType {
     #[facet(xml::attribute)]
@@ -50,7 +125,7 @@ pub(super) struct OsType {
     pub(super) value: String,
 }
 
-#[derive(Debug, Facet)]
+#[derive(Debug, PartialEq, Facet)]
 pub(super) struct Boot {
     #[facet(xml::attribute)]
     pub(super) dev: String,
@@ -58,19 +133,19 @@ pub(super) struct Boot {
 
 // ── memoryBacking (required for virtiofs) ──────────────────
 
-#[derive(Debug, Facet)]
+#[derive(Debug, PartialEq, Facet)]
 pub(super) struct MemoryBacking {
     pub(super) source: MemoryBackingSource,
     pub(super) access: MemoryBackingAccess,
 }
 
-#[derive(Debug, Facet)]
+#[derive(Debug, PartialEq, Facet)]
 pub(super) struct MemoryBackingSource {
     #[facet(xml::attribute, rename = "type")]
     pub(super) source_type: String,
 }
 
-#[derive(Debug, Facet)]
+#[derive(Debug, PartialEq, Facet)]
 pub(super) struct MemoryBackingAccess {
     #[facet(xml::attribute)]
     pub(super) mode: String,
@@ -78,29 +153,83 @@ pub(super) struct MemoryBackingAccess {
 
 // ── features ───────────────────────────────────────────────
 
-#[derive(Debug, Facet)]
+#[derive(Debug, PartialEq, Facet)]
 pub(super) struct Features {
     pub(super) acpi: Empty,
-    pub(super) apic: Empty,
+    /// x86 only.
+    #[facet(default)]
+    pub(super) apic: Option<Empty>,
+    /// aarch64 only.
+    #[facet(default)]
+    pub(super) gic: Option<Gic>,
 }
 
-#[derive(Debug, Default, Facet)]
+#[derive(Debug, PartialEq, Facet)]
+pub(super) struct Gic {
+    #[facet(xml::attribute)]
+    pub(super) version: String,
+}
+
+// ── cpu ────────────────────────────────────────────────────
+
+#[derive(Debug, PartialEq, Facet)]
+pub(super) struct Cpu {
+    #[facet(xml::attribute, default)]
+    pub(super) mode: Option<String>,
+    #[facet(default)]
+    pub(super) model: Option<CpuModel>,
+    #[facet(default)]
+    pub(super) topology: Option<CpuTopology>,
+}
+
+#[derive(Debug, PartialEq, Facet)]
+pub(super) struct CpuTopology {
+    #[facet(xml::attribute)]
+    pub(super) sockets: u32,
+    #[facet(xml::attribute)]
+    pub(super) cores: u32,
+    #[facet(xml::attribute)]
+    pub(super) threads: u32,
+}
+
+#[derive(Debug, PartialEq, Facet)]
+pub(super) struct CpuModel {
+    #[facet(xml::attribute)]
+    pub(super) fallback: String,
+    #[facet(xml::text)]
+    pub(super) value: String,
+}
+
+#[derive(Debug, Default, PartialEq, Facet)]
 #[facet(default)]
 pub(super) struct Empty {}
 
 // ── devices ────────────────────────────────────────────────
 
-#[derive(Debug, Facet)]
+#[derive(Debug, PartialEq, Facet)]
 pub(super) struct Devices {
     pub(super) disk: Vec<Disk>,
     pub(super) filesystem: Vec<Filesystem>,
     pub(super) interface: Vec<Interface>,
     pub(super) serial: Serial,
     pub(super) console: Console,
-    pub(super) vsock: Vsock,
+    #[facet(default)]
+    pub(super) channel: Option<Channel>,
+    #[facet(default)]
+    pub(super) vsock: Option<Vsock>,
+    #[facet(default)]
+    pub(super) memballoon: Option<MemBalloon>,
+    #[facet(default)]
+    pub(super) graphics: Option<Graphics>,
+    #[facet(default)]
+    pub(super) video: Option<Video>,
+    #[facet(default)]
+    pub(super) input: Option<Input>,
+    #[facet(default)]
+    pub(super) panic: Option<Panic>,
 }
 
-#[derive(Debug, Facet)]
+#[derive(Debug, PartialEq, Facet)]
 pub(super) struct Disk {
     #[facet(xml::attribute, rename = "type")]
     pub(super) disk_type: String,
@@ -113,21 +242,32 @@ pub(super) struct Disk {
     pub(super) readonly: Option<Empty>,
 }
 
-#[derive(Debug, Facet)]
+#[derive(Debug, PartialEq, Facet)]
 pub(super) struct DiskDriver {
     #[facet(xml::attribute)]
     pub(super) name: String,
     #[facet(xml::attribute, rename = "type")]
     pub(super) driver_type: String,
+    #[facet(xml::attribute, default)]
+    pub(super) cache: Option<String>,
+    #[facet(xml::attribute, default)]
+    pub(super) io: Option<String>,
+    #[facet(xml::attribute, default)]
+    pub(super) discard: Option<String>,
+    #[facet(xml::attribute, default)]
+    pub(super) iothread: Option<u32>,
 }
 
-#[derive(Debug, Facet)]
+#[derive(Debug, PartialEq, Facet)]
 pub(super) struct DiskSource {
-    #[facet(xml::attribute)]
-    pub(super) file: String,
+    #[facet(xml::attribute, default)]
+    pub(super) file: Option<String>,
+    /// Block device path for `type="block"` disks.
+    #[facet(xml::attribute, default)]
+    pub(super) dev: Option<String>,
 }
 
-#[derive(Debug, Facet)]
+#[derive(Debug, PartialEq, Facet)]
 pub(super) struct DiskTarget {
     #[facet(xml::attribute)]
     pub(super) dev: String,
@@ -135,34 +275,70 @@ pub(super) struct DiskTarget {
     pub(super) bus: String,
 }
 
+// ── disk-only snapshot ─────────────────────────────────────
+
+#[derive(Debug, PartialEq, Facet)]
+#[facet(rename = "domainsnapshot")]
+pub(super) struct DomainSnapshot {
+    pub(super) disks: SnapshotDisks,
+}
+
+#[derive(Debug, PartialEq, Facet)]
+pub(super) struct SnapshotDisks {
+    pub(super) disk: Vec<SnapshotDisk>,
+}
+
+#[derive(Debug, PartialEq, Facet)]
+#[facet(rename = "disk")]
+pub(super) struct SnapshotDisk {
+    #[facet(xml::attribute)]
+    pub(super) name: String,
+    /// `external` to move writes to `source`, `no` to leave the disk alone.
+    #[facet(xml::attribute)]
+    pub(super) snapshot: String,
+    #[facet(default)]
+    pub(super) driver: Option<SnapshotDriver>,
+    #[facet(default)]
+    pub(super) source: Option<DiskSource>,
+}
+
+#[derive(Debug, PartialEq, Facet)]
+#[facet(rename = "driver")]
+pub(super) struct SnapshotDriver {
+    #[facet(xml::attribute, rename = "type")]
+    pub(super) driver_type: String,
+}
+
 // ── virtiofs filesystem ────────────────────────────────────
 
-#[derive(Debug, Facet)]
+#[derive(Debug, PartialEq, Facet)]
+#[facet(rename = "filesystem")]
 pub(super) struct Filesystem {
     #[facet(xml::attribute, rename = "type")]
     pub(super) fs_type: String,
     #[facet(xml::attribute)]
     pub(super) accessmode: String,
-    pub(super) driver: FsDriver,
+    #[facet(default)]
+    pub(super) driver: Option<FsDriver>,
     pub(super) source: FsSource,
     pub(super) target: FsTarget,
     #[facet(default)]
     pub(super) readonly: Option<Empty>,
 }
 
-#[derive(Debug, Facet)]
+#[derive(Debug, PartialEq, Facet)]
 pub(super) struct FsDriver {
     #[facet(xml::attribute, rename = "type")]
     pub(super) driver_type: String,
 }
 
-#[derive(Debug, Facet)]
+#[derive(Debug, PartialEq, Facet)]
 pub(super) struct FsSource {
     #[facet(xml::attribute)]
     pub(super) dir: String,
 }
 
-#[derive(Debug, Facet)]
+#[derive(Debug, PartialEq, Facet)]
 pub(super) struct FsTarget {
     #[facet(xml::attribute)]
     pub(super) dir: String,
@@ -170,7 +346,7 @@ pub(super) struct FsTarget {
 
 // ── network ────────────────────────────────────────────────
 
-#[derive(Debug, Facet)]
+#[derive(Debug, PartialEq, Facet)]
 pub(super) struct Interface {
     #[facet(xml::attribute, rename = "type")]
     pub(super) iface_type: String,
@@ -180,19 +356,19 @@ pub(super) struct Interface {
     pub(super) model: InterfaceModel,
 }
 
-#[derive(Debug, Facet)]
+#[derive(Debug, PartialEq, Facet)]
 pub(super) struct InterfaceMac {
     #[facet(xml::attribute)]
     pub(super) address: String,
 }
 
-#[derive(Debug, Facet)]
+#[derive(Debug, PartialEq, Facet)]
 pub(super) struct InterfaceSource {
     #[facet(xml::attribute)]
     pub(super) network: String,
 }
 
-#[derive(Debug, Facet)]
+#[derive(Debug, PartialEq, Facet)]
 pub(super) struct InterfaceModel {
     #[facet(xml::attribute, rename = "type")]
     pub(super) model_type: String,
@@ -200,14 +376,14 @@ pub(super) struct InterfaceModel {
 
 // ── vsock ─────────────────────────────────────────────────
 
-#[derive(Debug, Facet)]
+#[derive(Debug, PartialEq, Facet)]
 pub(super) struct Vsock {
     #[facet(xml::attribute)]
     pub(super) model: String,
     pub(super) cid: VsockCid,
 }
 
-#[derive(Debug, Facet)]
+#[derive(Debug, PartialEq, Facet)]
 pub(super) struct VsockCid {
     #[facet(xml::attribute)]
     pub(super) auto: String,
@@ -219,7 +395,7 @@ pub(super) struct VsockCid {
 ///
 /// Live XML includes an `address` attribute on `<cid>` that is not present
 /// in the generation struct (since libvirt auto-assigns the CID).
-#[derive(Debug, Facet)]
+#[derive(Debug, PartialEq, Facet)]
 #[facet(rename = "vsock")]
 pub(super) struct LiveVsock {
     #[facet(xml::attribute)]
@@ -227,7 +403,7 @@ pub(super) struct LiveVsock {
     pub(super) cid: LiveVsockCid,
 }
 
-#[derive(Debug, Default, Facet)]
+#[derive(Debug, Default, PartialEq, Facet)]
 #[facet(default)]
 pub(super) struct LiveVsockCid {
     #[facet(xml::attribute)]
@@ -238,28 +414,28 @@ pub(super) struct LiveVsockCid {
 
 // ── serial / console ───────────────────────────────────────
 
-#[derive(Debug, Facet)]
+#[derive(Debug, PartialEq, Facet)]
 pub(super) struct Serial {
     #[facet(xml::attribute, rename = "type")]
     pub(super) serial_type: String,
     pub(super) target: SerialTarget,
 }
 
-#[derive(Debug, Facet)]
+#[derive(Debug, PartialEq, Facet)]
 #[facet(rename = "target")]
 pub(super) struct SerialTarget {
     #[facet(xml::attribute)]
     pub(super) port: String,
 }
 
-#[derive(Debug, Facet)]
+#[derive(Debug, PartialEq, Facet)]
 pub(super) struct Console {
     #[facet(xml::attribute, rename = "type")]
     pub(super) console_type: String,
     pub(super) target: ConsoleTarget,
 }
 
-#[derive(Debug, Facet)]
+#[derive(Debug, PartialEq, Facet)]
 #[facet(rename = "target")]
 pub(super) struct ConsoleTarget {
     #[facet(xml::attribute, rename = "type")]
@@ -267,3 +443,95 @@ pub(super) struct ConsoleTarget {
     #[facet(xml::attribute)]
     pub(super) port: String,
 }
+
+// ── channel ────────────────────────────────────────────────
+
+#[derive(Debug, PartialEq, Facet)]
+pub(super) struct Channel {
+    #[facet(xml::attribute, rename = "type")]
+    pub(super) channel_type: String,
+    pub(super) source: ChannelSource,
+    pub(super) target: ChannelTarget,
+}
+
+#[derive(Debug, PartialEq, Facet)]
+#[facet(rename = "source")]
+pub(super) struct ChannelSource {
+    #[facet(xml::attribute)]
+    pub(super) mode: String,
+    #[facet(xml::attribute)]
+    pub(super) path: String,
+}
+
+#[derive(Debug, PartialEq, Facet)]
+#[facet(rename = "target")]
+pub(super) struct ChannelTarget {
+    #[facet(xml::attribute, rename = "type")]
+    pub(super) target_type: String,
+    #[facet(xml::attribute)]
+    pub(super) name: String,
+}
+
+// ── memballoon ─────────────────────────────────────────────
+
+#[derive(Debug, PartialEq, Facet)]
+pub(super) struct MemBalloon {
+    #[facet(xml::attribute)]
+    pub(super) model: String,
+}
+
+// ── graphics ───────────────────────────────────────────────
+
+#[derive(Debug, PartialEq, Facet)]
+pub(super) struct Graphics {
+    #[facet(xml::attribute, rename = "type")]
+    pub(super) graphics_type: String,
+    #[facet(xml::attribute)]
+    pub(super) autoport: String,
+    #[facet(xml::attribute, default)]
+    pub(super) port: Option<String>,
+    #[facet(xml::attribute)]
+    pub(super) listen: String,
+}
+
+#[derive(Debug, PartialEq, Facet)]
+pub(super) struct Video {
+    pub(super) model: VideoModel,
+}
+
+#[derive(Debug, PartialEq, Facet)]
+#[facet(rename = "model")]
+pub(super) struct VideoModel {
+    #[facet(xml::attribute, rename = "type")]
+    pub(super) model_type: String,
+}
+
+/// Absolute-pointer tablet so the viewer cursor tracks the guest's.
+#[derive(Debug, PartialEq, Facet)]
+pub(super) struct Input {
+    #[facet(xml::attribute, rename = "type")]
+    pub(super) input_type: String,
+    #[facet(xml::attribute)]
+    pub(super) bus: String,
+}
+
+/// pvpanic device, so a guest kernel panic shuts the domain off with a
+/// "crashed" reason instead of leaving it spinning.
+#[derive(Debug, PartialEq, Facet)]
+pub(super) struct Panic {
+    #[facet(xml::attribute)]
+    pub(super) model: String,
+}
+
+// ── graphics deserialization (live XML) ────────────────────
+
+#[derive(Debug, Default, PartialEq, Facet)]
+#[facet(rename = "graphics", default)]
+pub(super) struct LiveGraphics {
+    #[facet(xml::attribute, rename = "type")]
+    pub(super) graphics_type: String,
+    #[facet(xml::attribute, default)]
+    pub(super) port: Option<String>,
+    #[facet(xml::attribute, default)]
+    pub(super) listen: Option<String>,
+}
@@ -9,15 +9,42 @@ use facet_xml as xml;
 #[facet(rename = "network")]
 struct NetworkDef {
     name: String,
-    ip: NetworkIp,
+    domain: Option<NetworkDomain>,
+    dns: Option<NetworkDns>,
+    ip: Vec<NetworkIp>,
+}
+
+#[derive(Debug, Facet)]
+struct NetworkDomain {
+    #[facet(xml::attribute)]
+    name: String,
+    /// Answer queries for the domain locally instead of forwarding them.
+    #[facet(xml::attribute, rename = "localOnly")]
+    local_only: String,
+}
+
+#[derive(Debug, Facet)]
+struct NetworkDns {
+    host: Vec<DnsHost>,
+}
+
+#[derive(Debug, Facet)]
+struct DnsHost {
+    #[facet(xml::attribute)]
+    ip: String,
+    hostname: Vec<String>,
 }
 
 #[derive(Debug, Facet)]
 struct NetworkIp {
+    #[facet(xml::attribute)]
+    family: Option<String>,
     #[facet(xml::attribute)]
     address: String,
     #[facet(xml::attribute)]
-    netmask: String,
+    netmask: Option<String>,
+    #[facet(xml::attribute)]
+    prefix: Option<u8>,
     dhcp: NetworkDhcp,
 }
 
@@ -42,23 +69,78 @@ pub fn prefixed_name(id: &str, config_network: &str) -> String {
     format!("rum-{id}-{config_network}")
 }
 
+/// Libvirt name of a network shared between VMs, e.g. `rum-net-lab`.
+///
+/// Used for interfaces with an explicit subnet, so every VM naming the same
+/// network joins it. The name carries no instance id, so `rum destroy` and
+/// `rum clean` only remove it once no defined domain attaches to it.
+pub fn shared_name(config_network: &str) -> String {
+    format!("rum-net-{config_network}")
+}
+
 // ── public API ─────────────────────────────────────────────
 
+/// Optional DHCP and DNS settings for [`generate_network_xml`].
+#[derive(Debug, Clone, Default)]
+pub struct NetworkOptions {
+    /// DHCP range as full addresses; defaults to `.100`–`.254` of the subnet.
+    pub dhcp_range: Option<(String, String)>,
+    /// DNS domain resolved locally by the network's dnsmasq.
+    pub domain: Option<String>,
+    /// Static DNS records as `(ip, hostnames)`.
+    pub hosts: Vec<(String, Vec<String>)>,
+    /// IPv6 /64 prefix (four groups, e.g. `fd12:3456:789a:0`) to add next
+    /// to the IPv4 subnet. dnsmasq then sends router advertisements and
+    /// serves DHCPv6 from it.
+    pub ipv6_prefix: Option<String>,
+}
+
 /// Generate libvirt network XML for a host-only network with DHCP.
-pub fn generate_network_xml(name: &str, subnet: &str) -> String {
-    let net = NetworkDef {
+pub fn generate_network_xml(name: &str, subnet: &str, options: &NetworkOptions) -> String {
+    let (start, end) = options
+        .dhcp_range
+        .clone()
+        .unwrap_or_else(|| (format!("{subnet}.100"), format!("{subnet}.254")));
+    let mut net = NetworkDef {
         name: name.into(),
-        ip: NetworkIp {
+        domain: options.domain.as_ref().map(|domain| NetworkDomain {
+            name: domain.clone(),
+            local_only: "yes".into(),
+        }),
+        dns: (!options.hosts.is_empty()).then(|| NetworkDns {
+            host: options
+                .hosts
+                .iter()
+                .map(|(ip, names)| DnsHost {
+                    ip: ip.clone(),
+                    hostname: names.clone(),
+                })
+                .collect(),
+        }),
+        ip: vec![NetworkIp {
+            family: None,
             address: format!("{subnet}.1"),
-            netmask: "255.255.255.0".into(),
+            netmask: Some("255.255.255.0".into()),
+            prefix: None,
+            dhcp: NetworkDhcp {
+                range: DhcpRange { start, end },
+            },
+        }],
+    };
+    if let Some(prefix) = &options.ipv6_prefix {
+        net.ip.push(NetworkIp {
+            family: Some("ipv6".into()),
+            address: format!("{prefix}::1"),
+            netmask: None,
+            prefix: Some(64),
             dhcp: NetworkDhcp {
                 range: DhcpRange {
-                    start: format!("{subnet}.100"),
-                    end: format!("{subnet}.254"),
+                    start: format!("{prefix}::100"),
+                    end: format!("{prefix}::1ff"),
                 },
             },
-        },
-    };
+        });
+    }
 
     facet_xml::to_string(&net).expect("network XML serialization should not fail")
 }
@@ -66,8 +148,8 @@ pub fn generate_network_xml(name: &str, subnet: &str) -> String {
 /// Derive a /24 subnet prefix (first 3 octets) for a host-only network.
 ///
 /// If an IP hint is provided (e.g. "192.168.50.10"), uses its first 3 octets.
-/// Otherwise, generates `192.168.<hash>` from the network name.
-/// TODO: Use OS specific lookup to find something free
+/// Otherwise, generates `192.168.<hash>` from the network name; use
+/// [`derive_free_subnet`] to move off subnets the host already uses.
 pub fn derive_subnet(name: &str, ip_hint: &str) -> String {
     if !ip_hint.is_empty()
         && let Some((prefix, _)) = ip_hint.rsplit_once('.')
@@ -83,17 +165,180 @@ pub fn derive_subnet(name: &str, ip_hint: &str) -> String {
     format!("192.168.{octet}")
 }
 
+/// Derive the IPv6 /64 prefix of a network as four `:`-separated groups.
+///
+/// An IPv6 hint (e.g. "fd00:1:2:3::10") keeps its first four groups.
+/// Otherwise a unique local (`fd00::/8`) prefix is generated whose 40-bit
+/// global id is a hash of the network name, so it is stable across runs.
+pub fn derive_ipv6_prefix(name: &str, ip_hint: &str) -> String {
+    if let Ok(hint) = ip_hint.parse::<std::net::Ipv6Addr>() {
+        let g = hint.segments();
+        return format!("{:x}:{:x}:{:x}:{:x}", g[0], g[1], g[2], g[3]);
+    }
+    // FNV-1a keeps the 40 bits well mixed for short, similar names.
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for b in name.bytes() {
+        hash = (hash ^ b as u64).wrapping_mul(0x0100_0000_01b3);
+    }
+    let id = hash.to_be_bytes();
+    format!(
+        "fd{:02x}:{:02x}{:02x}:{:02x}{:02x}:0",
+        id[0], id[1], id[2], id[3], id[4]
+    )
+}
+
+/// Extract the /24 prefix (first 3 octets) from a libvirt network XML's
+/// `<ip address="...">` element.
+pub fn parse_network_subnet(network_xml: &str) -> Option<String> {
+    let ip_start = network_xml.find("<ip ")?;
+    let rest = &network_xml[ip_start..];
+    let attr = rest.find("address=")? + "address=".len();
+    let quote = rest[attr..].chars().next()?;
+    let value_start = attr + 1;
+    let value_end = value_start + rest[value_start..].find(quote)?;
+    let (prefix, _) = rest[value_start..value_end].rsplit_once('.')?;
+    Some(prefix.to_string())
+}
+
+/// Position of the first `<ip>` element of a libvirt network XML with the
+/// given address family, as `virNetworkUpdate` takes its parent index.
+pub fn parse_ip_index(network_xml: &str, ipv6: bool) -> Option<i32> {
+    let is_ipv6 = |element: &str| -> Option<bool> {
+        let attr = element.find("address=")? + "address=".len();
+        let quote = element[attr..].chars().next()?;
+        let value = &element[attr + 1..];
+        Some(value[..value.find(quote)?].contains(':'))
+    };
+    let position = network_xml
+        .split("<ip ")
+        .skip(1)
+        .position(|element| is_ipv6(element) == Some(ipv6))?;
+    i32::try_from(position).ok()
+}
+
+/// Find an existing libvirt network or host route already using `subnet`.
+///
+/// `existing` holds `(owner, subnet)` pairs, where the subnet is either the
+/// /24 prefix of a libvirt network or an `address/len` host route. The
+/// network called `name` itself is ignored so re-checking a defined network
+/// is not a collision.
+pub fn subnet_collision<'a>(
+    name: &str,
+    subnet: &str,
+    existing: &'a [(String, String)],
+) -> Option<&'a str> {
+    existing
+        .iter()
+        .find(|(owner, used)| owner != name && subnet_overlaps(used, subnet))
+        .map(|(owner, _)| owner.as_str())
+}
+
+/// Whether `used` (a /24 prefix or an `address/len` route) overlaps the /24
+/// with prefix `subnet`.
+fn subnet_overlaps(used: &str, subnet: &str) -> bool {
+    let Some((address, len)) = used.split_once('/') else {
+        return used == subnet;
+    };
+    let (Ok(address), Ok(len), Ok(subnet)) = (
+        address.parse::<std::net::Ipv4Addr>(),
+        len.parse::<u32>(),
+        format!("{subnet}.0").parse::<std::net::Ipv4Addr>(),
+    ) else {
+        return false;
+    };
+    // Two prefixes overlap when they agree on the bits of the shorter one.
+    let mask = u32::MAX.checked_shl(32 - len.min(24)).unwrap_or(0);
+    u32::from(address) & mask == u32::from(subnet) & mask
+}
+
+/// Derive a subnet like [`derive_subnet`], skipping prefixes already used by
+/// other libvirt networks or host routes.
+///
+/// An explicit IP hint is never moved: if its subnet is taken, the name of
+/// the conflicting network is returned as the error. Hash-derived subnets
+/// probe forward through the third octet (still deterministic for a given
+/// host state) until a free one is found.
+pub fn derive_free_subnet(
+    name: &str,
+    ip_hint: &str,
+    existing: &[(String, String)],
+) -> Result<String, String> {
+    let subnet = derive_subnet(name, ip_hint);
+    let Some(conflict) = subnet_collision(name, &subnet, existing) else {
+        return Ok(subnet);
+    };
+    if !ip_hint.is_empty() {
+        return Err(conflict.to_string());
+    }
+
+    let start: u32 = subnet
+        .rsplit_once('.')
+        .and_then(|(_, octet)| octet.parse().ok())
+        .unwrap_or(2);
+    (0..253)
+        .map(|step| format!("192.168.{}", (start - 2 + step) % 253 + 2))
+        .find(|candidate| subnet_collision(name, candidate, existing).is_none())
+        .ok_or_else(|| conflict.to_string())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     #[test]
     fn network_xml_has_name_and_dhcp() {
-        let xml = generate_network_xml("rum-hostonly", "192.168.50");
+        let xml = generate_network_xml("rum-hostonly", "192.168.50", &NetworkOptions::default());
         assert!(xml.contains("<name>rum-hostonly</name>"));
         assert!(xml.contains(r#"address="192.168.50.1""#));
         assert!(xml.contains(r#"start="192.168.50.100""#));
         assert!(xml.contains(r#"end="192.168.50.254""#));
+        assert!(!xml.contains("<dns"));
+        assert!(!xml.contains("<domain"));
+    }
+
+    #[test]
+    fn network_xml_with_dhcp_range_and_dns() {
+        let options = NetworkOptions {
+            dhcp_range: Some(("192.168.77.50".into(), "192.168.77.99".into())),
+            domain: Some("lab.internal".into()),
+            hosts: vec![(
+                "192.168.77.5".into(),
+                vec!["db".into(), "db.lab.internal".into()],
+            )],
+            ..Default::default()
+        };
+        let xml = generate_network_xml("rum-net-lab", "192.168.77", &options);
+        assert!(xml.contains(r#"start="192.168.77.50""#), "got:\n{xml}");
+        assert!(xml.contains(r#"end="192.168.77.99""#));
+        assert!(xml.contains(r#"<domain name="lab.internal" localOnly="yes""#));
+        assert!(xml.contains(r#"<host ip="192.168.77.5">"#));
+        assert!(xml.contains("<hostname>db</hostname><hostname>db.lab.internal</hostname>"));
+        assert_eq!(parse_network_subnet(&xml), Some("192.168.77".into()));
+    }
+
+    #[test]
+    fn network_xml_with_ipv6_prefix() {
+        let options = NetworkOptions {
+            ipv6_prefix: Some(derive_ipv6_prefix("rum-net-lab", "")),
+            ..Default::default()
+        };
+        let prefix = options.ipv6_prefix.clone().unwrap();
+        assert!(prefix.starts_with("fd"));
+        let xml = generate_network_xml("rum-net-lab", "192.168.77", &options);
+        assert!(
+            xml.contains(&format!(
+                r#"family="ipv6" address="{prefix}::1" prefix="64""#
+            )),
+            "got:\n{xml}"
+        );
+        assert!(xml.contains(&format!(r#"start="{prefix}::100""#)));
+        assert_eq!(parse_network_subnet(&xml), Some("192.168.77".into()));
+
+        assert_eq!(derive_ipv6_prefix("net", "fd00:1:2:3::10"), "fd00:1:2:3");
+        assert_ne!(
+            derive_ipv6_prefix("net-a", ""),
+            derive_ipv6_prefix("net-b", "")
+        );
     }
 
     #[test]
@@ -116,4 +361,95 @@ mod tests {
         let s2 = derive_subnet("net-b", "");
         assert_ne!(s1, s2);
     }
+
+    #[test]
+    fn parse_network_subnet_from_live_xml() {
+        let xml = r#"<network>
+  <name>default</name>
+  <ip address='192.168.122.1' netmask='255.255.255.0'>
+    <dhcp><range start='192.168.122.2' end='192.168.122.254'/></dhcp>
+  </ip>
+</network>"#;
+        assert_eq!(parse_network_subnet(xml), Some("192.168.122".into()));
+        let generated = generate_network_xml("rum-net", "192.168.50", &NetworkOptions::default());
+        assert_eq!(parse_network_subnet(&generated), Some("192.168.50".into()));
+    }
+
+    #[test]
+    fn parse_ip_index_by_family() {
+        let dual = generate_network_xml(
+            "rum-net-lab",
+            "192.168.77",
+            &NetworkOptions {
+                ipv6_prefix: Some("fd00:1:2:3".into()),
+                ..Default::default()
+            },
+        );
+        assert_eq!(parse_ip_index(&dual, false), Some(0));
+        assert_eq!(parse_ip_index(&dual, true), Some(1));
+
+        let v6_only = r#"<network>
+  <name>v6</name>
+  <ip family='ipv6' address='fd00::1' prefix='64'/>
+</network>"#;
+        assert_eq!(parse_ip_index(v6_only, true), Some(0));
+        assert_eq!(parse_ip_index(v6_only, false), None);
+    }
+
+    #[test]
+    fn derive_free_subnet_skips_taken_prefix() {
+        let derived = derive_subnet("rum-hostonly", "");
+        let existing = vec![("other".to_string(), derived.clone())];
+        let free = derive_free_subnet("rum-hostonly", "", &existing).unwrap();
+        assert_ne!(free, derived);
+        assert_eq!(
+            derive_free_subnet("rum-hostonly", "", &existing).unwrap(),
+            free,
+            "probing must stay deterministic"
+        );
+    }
+
+    #[test]
+    fn derive_free_subnet_ignores_own_network() {
+        let derived = derive_subnet("rum-hostonly", "");
+        let existing = vec![("rum-hostonly".to_string(), derived.clone())];
+        assert_eq!(
+            derive_free_subnet("rum-hostonly", "", &existing).unwrap(),
+            derived
+        );
+    }
+
+    #[test]
+    fn host_routes_collide_with_the_subnets_they_cover() {
+        let existing = vec![
+            ("route via wg0".to_string(), "10.8.0.0/16".to_string()),
+            ("route via eth0".to_string(), "192.168.50.0/25".to_string()),
+        ];
+        assert_eq!(
+            subnet_collision("rum-net", "10.8.3", &existing),
+            Some("route via wg0")
+        );
+        assert_eq!(
+            subnet_collision("rum-net", "192.168.50", &existing),
+            Some("route via eth0")
+        );
+        assert_eq!(subnet_collision("rum-net", "10.9.3", &existing), None);
+        assert_eq!(subnet_collision("rum-net", "192.168.51", &existing), None);
+
+        let derived = derive_subnet("rum-hostonly", "");
+        let covering = vec![("route via eth0".to_string(), format!("{derived}.0/24"))];
+        assert_ne!(
+            derive_free_subnet("rum-hostonly", "", &covering).unwrap(),
+            derived
+        );
+    }
+
+    #[test]
+    fn derive_free_subnet_reports_hint_collision() {
+        let existing = vec![("default".to_string(), "192.168.122".to_string())];
+        assert_eq!(
+            derive_free_subnet("rum-net", "192.168.122.10", &existing),
+            Err("default".to_string())
+        );
+    }
 }
@@ -1,13 +1,13 @@
 //! Helper functions for domain XML processing.
 
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 use facet_xml as xml;
 
-use crate::{DomainConfig, ResolvedDrive, ResolvedMount};
+use crate::{DomainConfig, InstanceMetadata, ResolvedDrive, ResolvedMount};
 
 use super::build::generate_domain_xml;
-use super::model::LiveVsock;
+use super::model::{Domain, LiveGraphics, LiveVsock, RumInstance};
 
 /// Generate a deterministic MAC address from VM name and interface index.
 ///
@@ -30,6 +30,12 @@ pub fn generate_mac(vm_name: &str, index: usize) -> String {
     )
 }
 
+/// Deterministic MAC for the NAT interface, distinct from every
+/// [`generate_mac`] index used for extra interfaces.
+pub fn generate_nat_mac(vm_name: &str) -> String {
+    generate_mac(vm_name, usize::MAX)
+}
+
 /// Extract the auto-assigned vsock CID from a full domain XML string.
 ///
 /// Locates the `<vsock>...</vsock>` section in the XML, deserializes it
@@ -46,18 +52,231 @@ pub fn parse_vsock_cid(domain_xml: &str) -> Option<u32> {
     live.cid.address.as_deref()?.parse::<u32>().ok()
 }
 
-/// Check if the generated XML differs from the saved XML on disk.
-pub fn xml_has_changed(
+/// MAC addresses of every `<interface>` in a live domain XML string, in
+/// document order and lowercased.
+///
+/// Libvirt assigns a MAC to interfaces defined without one, so the live XML
+/// is the only place the default NIC's address is known.
+pub fn parse_interface_macs(domain_xml: &str) -> Vec<String> {
+    interfaces(domain_xml)
+        .filter_map(|iface| quoted_value(iface, "<mac address="))
+        .map(str::to_lowercase)
+        .collect()
+}
+
+/// MAC addresses of the `<interface>`s of a domain XML string that attach to
+/// the libvirt network `network`, lowercased.
+pub fn parse_network_macs(domain_xml: &str, network: &str) -> Vec<String> {
+    interfaces(domain_xml)
+        .filter(|iface| quoted_value(iface, "<source network=") == Some(network))
+        .filter_map(|iface| quoted_value(iface, "<mac address="))
+        .map(str::to_lowercase)
+        .collect()
+}
+
+/// Whether any `<interface>` of a domain XML string attaches to the libvirt
+/// network `network`.
+pub fn attaches_to_network(domain_xml: &str, network: &str) -> bool {
+    interfaces(domain_xml).any(|iface| quoted_value(iface, "<source network=") == Some(network))
+}
+
+/// Target tags of the `<filesystem>` shares of a live domain XML string.
+pub fn parse_filesystem_tags(domain_xml: &str) -> Vec<String> {
+    elements(domain_xml, "filesystem")
+        .filter_map(|fs| quoted_value(fs, "<target dir="))
+        .map(str::to_string)
+        .collect()
+}
+
+/// Every `<interface>...</interface>` section of a domain XML string.
+fn interfaces(domain_xml: &str) -> impl Iterator<Item = &str> {
+    elements(domain_xml, "interface")
+}
+
+/// Every `<name>...</name>` section of a domain XML string.
+fn elements<'a>(domain_xml: &'a str, name: &str) -> impl Iterator<Item = &'a str> {
+    let (open, close) = (format!("<{name}"), format!("</{name}>"));
+    let mut rest = domain_xml;
+    std::iter::from_fn(move || {
+        let start = rest.find(&open)?;
+        rest = &rest[start..];
+        let end = rest.find(&close).unwrap_or(rest.len());
+        let element = &rest[..end];
+        rest = &rest[end..];
+        Some(element)
+    })
+}
+
+/// The quoted value right after `prefix` (e.g. `<mac address=`) in `xml`,
+/// with either quote style.
+fn quoted_value<'a>(xml: &'a str, prefix: &str) -> Option<&'a str> {
+    let value = &xml[xml.find(prefix)? + prefix.len()..];
+    let quote = value.chars().next()?;
+    let len = value[1..].find(quote)?;
+    Some(&value[1..1 + len])
+}
+
+/// Build a `spice://` or `vnc://` URI from the `<graphics>` element of a live
+/// domain XML string.
+///
+/// Only the opening tag is parsed; its `<listen>` children duplicate the
+/// `listen` attribute. Returns `None` for headless domains and for domains
+/// that are not running (no port assigned yet).
+pub fn parse_display_uri(domain_xml: &str) -> Option<String> {
+    let start = domain_xml.find("<graphics")?;
+    let end = start + domain_xml[start..].find('>')?;
+    let tag = &domain_xml[start..end];
+    let tag = format!("{}/>", tag.strip_suffix('/').unwrap_or(tag));
+
+    let live: LiveGraphics = xml::from_str(&tag).ok()?;
+    let port = live
+        .port
+        .as_deref()?
+        .parse::<i32>()
+        .ok()
+        .filter(|p| *p > 0)?;
+    let host = match live.listen.as_deref() {
+        None | Some("" | "0.0.0.0" | "::") => "127.0.0.1",
+        Some(host) => host,
+    };
+    Some(format!("{}://{host}:{port}", live.graphics_type))
+}
+
+/// Extract rum's instance metadata from a full domain XML string.
+///
+/// Returns `None` for domains that were not defined by rum (or by a rum
+/// version predating the metadata block).
+pub fn parse_instance_metadata(domain_xml: &str) -> Option<InstanceMetadata> {
+    let start = domain_xml.find("<rum:instance")?;
+    let rest = &domain_xml[start..];
+    let end = match rest.find("</rum:instance>") {
+        Some(i) => i + "</rum:instance>".len(),
+        None => rest.find("/>")? + "/>".len(),
+    };
+
+    let instance: RumInstance = xml::from_str(&rest[..end]).ok()?;
+    Some(InstanceMetadata {
+        id: instance.id,
+        labels: instance
+            .label
+            .into_iter()
+            .map(|l| (l.name, l.value))
+            .collect(),
+        profile: instance.profile,
+        work_dir: instance.work_dir.map(PathBuf::from),
+    })
+}
+
+/// One difference between a saved domain definition and the one generated
+/// from the current config.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DomainChange {
+    /// Boot memory changed below an unchanged maximum, so the balloon can
+    /// apply it.
+    Memory { mb: u64 },
+    /// Online vCPUs changed below an unchanged maximum, so hotplug can
+    /// apply it.
+    Vcpus { count: u32 },
+    /// rum's labels or profile in `<metadata>`.
+    Metadata,
+    /// A part of the definition that only takes effect on the next boot.
+    Restart { part: &'static str },
+}
+
+impl DomainChange {
+    /// Whether the change applies without rebooting the domain.
+    pub fn is_live(&self) -> bool {
+        !matches!(self, Self::Restart { .. })
+    }
+}
+
+/// Differences between the saved XML at `existing_xml_path` and the XML
+/// generated from config. A missing saved definition is one restart change.
+pub fn xml_changes(
     config: &DomainConfig,
     overlay_path: &Path,
     seed_path: &Path,
     mounts: &[ResolvedMount],
     drives: &[ResolvedDrive],
     existing_xml_path: &Path,
-) -> bool {
+) -> Vec<DomainChange> {
     let new_xml = generate_domain_xml(config, overlay_path, seed_path, mounts, drives);
     match std::fs::read_to_string(existing_xml_path) {
-        Ok(existing) => existing != new_xml,
-        Err(_) => true,
+        Ok(existing) => domain_changes(&existing, &new_xml),
+        Err(_) => vec![DomainChange::Restart { part: "definition" }],
     }
 }
+
+/// Compare two domain XML strings element by element.
+///
+/// XML that does not parse as a rum domain is treated as entirely changed.
+pub fn domain_changes(old_xml: &str, new_xml: &str) -> Vec<DomainChange> {
+    if old_xml == new_xml {
+        return Vec::new();
+    }
+    let (Ok(old), Ok(new)) = (
+        xml::from_str::<Domain>(old_xml),
+        xml::from_str::<Domain>(new_xml),
+    ) else {
+        return vec![DomainChange::Restart { part: "definition" }];
+    };
+
+    let restart = |part| DomainChange::Restart { part };
+    let mut changes = Vec::new();
+    if old.memory != new.memory {
+        changes.push(restart("memory"));
+    } else if old.current_memory != new.current_memory {
+        changes.push(match &new.current_memory {
+            Some(current) => DomainChange::Memory {
+                mb: current.value / 1024,
+            },
+            None => restart("memory"),
+        });
+    }
+    if old.vcpu.value != new.vcpu.value {
+        changes.push(restart("vcpus"));
+    } else if old.vcpu.current != new.vcpu.current {
+        changes.push(match new.vcpu.current {
+            Some(count) => DomainChange::Vcpus { count },
+            None => restart("vcpus"),
+        });
+    }
+    if old.metadata != new.metadata {
+        changes.push(DomainChange::Metadata);
+    }
+
+    let (old_dev, new_dev) = (&old.devices, &new.devices);
+    let parts = [
+        ("name", old.name != new.name),
+        ("domain type", old.domain_type != new.domain_type),
+        ("os", old.os != new.os),
+        ("features", old.features != new.features),
+        ("cpu", old.cpu != new.cpu),
+        ("iothreads", old.iothreads != new.iothreads),
+        ("memory backing", old.memory_backing != new.memory_backing),
+        ("disks", old_dev.disk != new_dev.disk),
+        ("filesystems", old_dev.filesystem != new_dev.filesystem),
+        ("interfaces", old_dev.interface != new_dev.interface),
+        ("memory balloon", old_dev.memballoon != new_dev.memballoon),
+        (
+            "display",
+            old_dev.graphics != new_dev.graphics
+                || old_dev.video != new_dev.video
+                || old_dev.input != new_dev.input,
+        ),
+        (
+            "devices",
+            old_dev.serial != new_dev.serial
+                || old_dev.console != new_dev.console
+                || old_dev.channel != new_dev.channel
+                || old_dev.vsock != new_dev.vsock,
+        ),
+    ];
+    changes.extend(
+        parts
+            .into_iter()
+            .filter(|(_, changed)| *changed)
+            .map(|(part, _)| restart(part)),
+    );
+    changes
+}
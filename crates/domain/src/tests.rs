@@ -1,9 +1,13 @@
 #[cfg(test)]
 mod tests {
     use crate::{
-        DomainConfig, InterfaceConfig, ResolvedDrive, ResolvedMount, network_xml,
-        generate_domain_xml, generate_mac, parse_vsock_cid,
+        CpuConfig, DiskTuning, DisplayConfig, DomainChange, DomainConfig, InterfaceConfig,
+        ResolvedDrive, ResolvedMount, UefiConfig, attaches_to_network, domain_changes,
+        generate_domain_xml, generate_filesystem_xml, generate_mac, generate_nat_mac,
+        generate_root_snapshot_xml, network_xml, parse_display_uri, parse_filesystem_tags,
+        parse_instance_metadata, parse_interface_macs, parse_network_macs, parse_vsock_cid,
     };
+    use std::collections::BTreeMap;
     use std::path::PathBuf;
 
     fn test_domain_config() -> DomainConfig {
@@ -12,10 +16,24 @@ mod tests {
             name: "test-vm".into(),
             domain_type: "kvm".into(),
             machine: "q35".into(),
+            arch: "x86_64".into(),
+            uefi: None,
+            display: None,
             memory_mb: 512,
+            memory_max_mb: None,
             cpus: 1,
+            cpus_max: None,
+            cpu: Default::default(),
+            root_disk: Default::default(),
             nat: true,
+            nat_mac: None,
             interfaces: Vec::new(),
+            cdroms: Vec::new(),
+            labels: BTreeMap::new(),
+            work_dir: None,
+            profile: None,
+            agent_channel: None,
+            vsock: true,
         }
     }
 
@@ -50,6 +68,173 @@ mod tests {
         );
     }
 
+    #[test]
+    fn xml_with_agent_channel_keeps_vsock_where_available() {
+        let mut config = test_domain_config();
+        config.agent_channel = Some(PathBuf::from("/tmp/agent.sock"));
+        let xml = make_xml(&config, &[], &[]);
+        assert!(xml.contains(r#"<channel type="unix">"#), "got:\n{xml}");
+        assert!(
+            xml.contains(r#"<source mode="bind" path="/tmp/agent.sock">"#),
+            "got:\n{xml}"
+        );
+        assert!(
+            xml.contains(r#"<target type="virtio" name="org.rum.agent.0">"#),
+            "got:\n{xml}"
+        );
+        assert!(xml.contains("<vsock"), "got:\n{xml}");
+
+        let changes = domain_changes(&make_xml(&test_domain_config(), &[], &[]), &xml);
+        assert_eq!(changes, [DomainChange::Restart { part: "devices" }]);
+
+        config.vsock = false;
+        let xml = make_xml(&config, &[], &[]);
+        assert!(xml.contains(r#"<channel type="unix">"#), "got:\n{xml}");
+        assert!(!xml.contains("<vsock"), "got:\n{xml}");
+        assert_eq!(parse_vsock_cid(&xml), None);
+    }
+
+    #[test]
+    fn xml_for_aarch64_uses_efi_and_gic() {
+        let mut config = test_domain_config();
+        config.arch = "aarch64".into();
+        config.uefi = Some(UefiConfig {
+            loader: None,
+            nvram: PathBuf::from("/tmp/nvram.fd"),
+            nvram_template: None,
+        });
+        config.machine = "virt".into();
+        config.domain_type = "qemu".into();
+        let xml = make_xml(&config, &[], &[]);
+        assert!(xml.contains(r#"firmware="efi""#), "got:\n{xml}");
+        assert!(xml.contains(r#"arch="aarch64""#), "got:\n{xml}");
+        assert!(xml.contains(r#"<gic version="3">"#), "got:\n{xml}");
+        assert!(!xml.contains("<apic"), "apic is x86-only, got:\n{xml}");
+        assert!(!xml.contains("<panic"), "pvpanic is x86-only, got:\n{xml}");
+        assert!(
+            xml.contains("cortex-a57"),
+            "TCG needs a 64-bit CPU model, got:\n{xml}"
+        );
+
+        let xml = make_xml(&test_domain_config(), &[], &[]);
+        assert!(xml.contains("<apic"));
+        assert!(xml.contains(r#"<panic model="isa">"#), "got:\n{xml}");
+        assert!(!xml.contains("firmware="));
+        assert!(!xml.contains("<cpu"));
+    }
+
+    #[test]
+    fn xml_with_uefi_has_loader_and_nvram() {
+        let mut config = test_domain_config();
+        config.uefi = Some(UefiConfig {
+            loader: Some(PathBuf::from("/usr/share/OVMF/OVMF_CODE.fd")),
+            nvram: PathBuf::from("/tmp/nvram.fd"),
+            nvram_template: Some(PathBuf::from("/usr/share/OVMF/OVMF_VARS.fd")),
+        });
+        let xml = make_xml(&config, &[], &[]);
+        assert!(xml.contains(r#"<os firmware="efi">"#), "got:\n{xml}");
+        assert!(
+            xml.contains(
+                r#"<loader readonly="yes" type="pflash">/usr/share/OVMF/OVMF_CODE.fd</loader>"#
+            ),
+            "got:\n{xml}"
+        );
+        assert!(
+            xml.contains(r#"<nvram template="/usr/share/OVMF/OVMF_VARS.fd">/tmp/nvram.fd</nvram>"#),
+            "got:\n{xml}"
+        );
+    }
+
+    #[test]
+    fn xml_with_display_has_graphics_and_video() {
+        let mut config = test_domain_config();
+        config.display = Some(DisplayConfig {
+            protocol: "spice".into(),
+            listen: "127.0.0.1".into(),
+            port: None,
+        });
+        let xml = make_xml(&config, &[], &[]);
+        assert!(
+            xml.contains(r#"<graphics type="spice" autoport="yes" listen="127.0.0.1">"#),
+            "got:\n{xml}"
+        );
+        assert!(xml.contains(r#"<model type="virtio">"#), "got:\n{xml}");
+
+        let xml = make_xml(&test_domain_config(), &[], &[]);
+        assert!(
+            !xml.contains("<graphics"),
+            "headless by default, got:\n{xml}"
+        );
+    }
+
+    #[test]
+    fn display_uri_from_live_xml() {
+        let live = concat!(
+            "<domain><devices>",
+            "<graphics type='vnc' port='5901' autoport='yes' listen='127.0.0.1'>",
+            "<listen type='address' address='127.0.0.1'/></graphics>",
+            "</devices></domain>"
+        );
+        assert_eq!(
+            parse_display_uri(live).as_deref(),
+            Some("vnc://127.0.0.1:5901")
+        );
+
+        let stopped = "<domain><devices><graphics type='spice' autoport='yes'/></devices></domain>";
+        assert_eq!(parse_display_uri(stopped), None);
+        assert_eq!(parse_display_uri("<domain><devices/></domain>"), None);
+    }
+
+    #[test]
+    fn xml_with_cpu_model_and_topology() {
+        let mut config = test_domain_config();
+        config.cpus = 4;
+        config.cpu = CpuConfig {
+            model: Some("host-passthrough".into()),
+            topology: Some((1, 2, 2)),
+        };
+        let xml = make_xml(&config, &[], &[]);
+        assert!(
+            xml.contains(
+                r#"<cpu mode="host-passthrough"><topology sockets="1" cores="2" threads="2">"#
+            ),
+            "got:\n{xml}"
+        );
+
+        config.cpu.model = Some("Skylake-Client".into());
+        config.cpu.topology = None;
+        let xml = make_xml(&config, &[], &[]);
+        assert!(
+            xml.contains(r#"<cpu mode="custom"><model fallback="allow">Skylake-Client</model>"#),
+            "got:\n{xml}"
+        );
+    }
+
+    #[test]
+    fn xml_with_hotplug_headroom() {
+        let mut config = test_domain_config();
+        config.memory_max_mb = Some(2048);
+        config.cpus_max = Some(4);
+        let xml = make_xml(&config, &[], &[]);
+        assert!(
+            xml.contains(r#"<memory unit="KiB">2097152</memory>"#),
+            "got:\n{xml}"
+        );
+        assert!(
+            xml.contains(r#"<currentMemory unit="KiB">524288</currentMemory>"#),
+            "got:\n{xml}"
+        );
+        assert!(xml.contains(r#"<vcpu current="1">4</vcpu>"#), "got:\n{xml}");
+        assert!(
+            xml.contains(r#"<memballoon model="virtio">"#),
+            "got:\n{xml}"
+        );
+
+        let xml = make_xml(&test_domain_config(), &[], &[]);
+        assert!(xml.contains("<vcpu>1</vcpu>"), "got:\n{xml}");
+        assert!(!xml.contains("currentMemory"), "got:\n{xml}");
+    }
+
     #[test]
     fn xml_with_mounts_has_virtiofs() {
         let mounts = vec![
@@ -58,12 +243,14 @@ mod tests {
                 target: "/mnt/project".into(),
                 readonly: false,
                 tag: "mnt_project".into(),
+                nine_p: false,
             },
             ResolvedMount {
                 source: PathBuf::from("/data"),
                 target: "/mnt/data".into(),
                 readonly: true,
                 tag: "mnt_data".into(),
+                nine_p: false,
             },
         ];
         let xml = make_xml(&test_domain_config(), &mounts, &[]);
@@ -74,6 +261,54 @@ mod tests {
         assert!(xml.contains("<readonly>"));
     }
 
+    #[test]
+    fn root_snapshot_leaves_other_drives_alone() {
+        let xml =
+            generate_root_snapshot_xml(&PathBuf::from("/work/working-1.qcow2"), &["vdb", "vdc"]);
+        assert!(
+            xml.contains(r#"<disk name="vda" snapshot="external">"#),
+            "got:\n{xml}"
+        );
+        assert!(xml.contains(r#"<source file="/work/working-1.qcow2""#));
+        assert!(xml.contains(r#"<disk name="vdb" snapshot="no""#));
+        assert!(xml.contains(r#"<disk name="vdc" snapshot="no""#));
+    }
+
+    #[test]
+    fn filesystem_xml_for_hotplug() {
+        let xml = generate_filesystem_xml(&ResolvedMount {
+            source: PathBuf::from("/data"),
+            target: "/mnt/data".into(),
+            readonly: true,
+            tag: "mnt_data".into(),
+            nine_p: false,
+        });
+        assert!(
+            xml.starts_with(r#"<filesystem type="mount" accessmode="passthrough">"#),
+            "got:\n{xml}"
+        );
+        assert!(xml.contains(r#"<target dir="mnt_data">"#));
+        assert!(xml.contains("<readonly>"));
+    }
+
+    #[test]
+    fn xml_with_9p_mount_skips_memory_backing() {
+        let mounts = vec![ResolvedMount {
+            source: PathBuf::from("/data"),
+            target: "/mnt/data".into(),
+            readonly: false,
+            tag: "mnt_data".into(),
+            nine_p: true,
+        }];
+        let xml = make_xml(&test_domain_config(), &mounts, &[]);
+        assert!(
+            xml.contains(r#"<filesystem type="mount" accessmode="mapped">"#),
+            "got:\n{xml}"
+        );
+        assert!(!xml.contains("memoryBacking"));
+        assert!(!xml.contains("virtiofs"));
+    }
+
     #[test]
     fn xml_without_mounts_no_memory_backing() {
         let xml = make_xml(&test_domain_config(), &[], &[]);
@@ -87,10 +322,18 @@ mod tests {
             ResolvedDrive {
                 path: PathBuf::from("/home/user/.local/share/rum/test-vm/drive-data.qcow2"),
                 dev: "vdb".into(),
+                block: false,
+                readonly: false,
+                format: "qcow2".into(),
+                tuning: Default::default(),
             },
             ResolvedDrive {
                 path: PathBuf::from("/home/user/.local/share/rum/test-vm/drive-scratch.qcow2"),
                 dev: "vdc".into(),
+                block: false,
+                readonly: false,
+                format: "qcow2".into(),
+                tuning: Default::default(),
             },
         ];
         let xml = make_xml(&test_domain_config(), &[], &drives);
@@ -100,6 +343,100 @@ mod tests {
         assert!(xml.contains("drive-scratch.qcow2"));
     }
 
+    #[test]
+    fn xml_with_raw_drive_uses_raw_driver() {
+        let drives = vec![ResolvedDrive {
+            path: PathBuf::from("/home/user/.local/share/rum/test-vm/drive-scratch.raw"),
+            dev: "vdb".into(),
+            block: false,
+            readonly: false,
+            format: "raw".into(),
+            tuning: Default::default(),
+        }];
+        let xml = make_xml(&test_domain_config(), &[], &drives);
+        assert!(xml.contains("drive-scratch.raw"));
+        assert!(
+            xml.contains(r#"<driver name="qemu" type="raw">"#),
+            "got:\n{xml}"
+        );
+    }
+
+    #[test]
+    fn xml_with_disk_tuning_assigns_iothreads() {
+        let mut config = test_domain_config();
+        config.root_disk = DiskTuning {
+            cache: Some("none".into()),
+            io: Some("native".into()),
+            discard: true,
+            iothread: true,
+        };
+        let drives = vec![ResolvedDrive {
+            path: PathBuf::from("/tmp/drive-db.qcow2"),
+            dev: "vdb".into(),
+            block: false,
+            readonly: false,
+            format: "qcow2".into(),
+            tuning: DiskTuning {
+                io: Some("io_uring".into()),
+                iothread: true,
+                ..Default::default()
+            },
+        }];
+        let xml = make_xml(&config, &[], &drives);
+        assert!(xml.contains("<iothreads>2</iothreads>"), "got:\n{xml}");
+        let root_driver = concat!(
+            r#"<driver name="qemu" type="qcow2" cache="none" io="native" "#,
+            r#"discard="unmap" iothread="1">"#
+        );
+        assert!(xml.contains(root_driver), "got:\n{xml}");
+        assert!(
+            xml.contains(r#"<driver name="qemu" type="qcow2" io="io_uring" iothread="2">"#),
+            "got:\n{xml}"
+        );
+
+        let xml = make_xml(&test_domain_config(), &[], &[]);
+        assert!(!xml.contains("iothread"), "got:\n{xml}");
+    }
+
+    #[test]
+    fn xml_with_host_block_device() {
+        let drives = vec![ResolvedDrive {
+            path: PathBuf::from("/dev/disk/by-id/nvme-ssd-part1"),
+            dev: "vdb".into(),
+            block: true,
+            readonly: true,
+            format: "raw".into(),
+            tuning: Default::default(),
+        }];
+        let xml = make_xml(&test_domain_config(), &[], &drives);
+        assert!(
+            xml.contains(r#"<disk type="block" device="disk"><driver name="qemu" type="raw">"#),
+            "got:\n{xml}"
+        );
+        assert!(
+            xml.contains(r#"<source dev="/dev/disk/by-id/nvme-ssd-part1">"#),
+            "got:\n{xml}"
+        );
+        // The seed ISO is read-only too.
+        assert_eq!(xml.matches("<readonly").count(), 2, "got:\n{xml}");
+    }
+
+    #[test]
+    fn xml_attaches_extra_cdroms_after_seed() {
+        let mut config = test_domain_config();
+        config.cdroms = vec![
+            PathBuf::from("/isos/virtio-win.iso"),
+            PathBuf::from("/isos/tools.iso"),
+        ];
+        let xml = make_xml(&config, &[], &[]);
+        assert_eq!(xml.matches(r#"device="cdrom""#).count(), 3, "got:\n{xml}");
+        assert!(xml.contains(r#"file="/isos/virtio-win.iso""#));
+        assert!(xml.contains(r#"dev="sdb""#));
+        assert!(xml.contains(r#"dev="sdc""#));
+        // The seed and both ISOs are read-only, the root disk is not.
+        assert_eq!(xml.matches("<readonly").count(), 3, "got:\n{xml}");
+    }
+
     #[test]
     fn xml_default_config_has_single_nat_interface() {
         let xml = make_xml(&test_domain_config(), &[], &[]);
@@ -113,6 +450,7 @@ mod tests {
         let mut config = test_domain_config();
         config.interfaces = vec![InterfaceConfig {
             network: "hostonly".into(),
+            shared: false,
         }];
         let xml = make_xml(&config, &[], &[]);
         let expected_net = network_xml::prefixed_name(&config.id, "hostonly");
@@ -127,12 +465,25 @@ mod tests {
         assert!(xml.contains("52:54:00:"));
     }
 
+    #[test]
+    fn xml_nat_interface_uses_configured_mac() {
+        let mut config = test_domain_config();
+        config.nat_mac = Some(generate_nat_mac("test-vm"));
+        let xml = make_xml(&config, &[], &[]);
+        assert!(
+            xml.contains(&format!(r#"address="{}""#, generate_nat_mac("test-vm"))),
+            "got:\n{xml}"
+        );
+        assert_ne!(generate_nat_mac("test-vm"), generate_mac("test-vm", 0));
+    }
+
     #[test]
     fn xml_no_nat_with_extra_nic() {
         let mut config = test_domain_config();
         config.nat = false;
         config.interfaces = vec![InterfaceConfig {
             network: "isolated".into(),
+            shared: false,
         }];
         let xml = make_xml(&config, &[], &[]);
         let expected_net = network_xml::prefixed_name(&config.id, "isolated");
@@ -143,6 +494,20 @@ mod tests {
         );
     }
 
+    #[test]
+    fn xml_shared_nic_uses_shared_network() {
+        let mut config = test_domain_config();
+        config.interfaces = vec![InterfaceConfig {
+            network: "lab".into(),
+            shared: true,
+        }];
+        let xml = make_xml(&config, &[], &[]);
+        assert!(
+            xml.contains(r#"<source network="rum-net-lab">"#),
+            "got:\n{xml}"
+        );
+    }
+
     #[test]
     fn xml_no_networking() {
         let mut config = test_domain_config();
@@ -167,6 +532,74 @@ mod tests {
         assert_ne!(mac0, mac1);
     }
 
+    #[test]
+    fn parse_interface_macs_from_live_xml() {
+        let xml = r#"<domain type="kvm">
+  <devices>
+    <interface type="network">
+      <mac address="52:54:00:AA:BB:CC"/>
+      <source network="default"/>
+    </interface>
+    <interface type='network'>
+      <mac address='52:54:00:11:22:33'/>
+    </interface>
+  </devices>
+</domain>"#;
+        assert_eq!(
+            parse_interface_macs(xml),
+            ["52:54:00:aa:bb:cc", "52:54:00:11:22:33"]
+        );
+    }
+
+    #[test]
+    fn parse_network_macs_from_live_xml() {
+        let xml = r#"<domain type="kvm">
+  <devices>
+    <interface type="network">
+      <mac address="52:54:00:AA:BB:CC"/>
+      <source network="default"/>
+    </interface>
+    <interface type='network'>
+      <mac address='52:54:00:11:22:33'/>
+      <source network='rum-lab'/>
+    </interface>
+  </devices>
+</domain>"#;
+        assert_eq!(parse_network_macs(xml, "rum-lab"), ["52:54:00:11:22:33"]);
+        assert!(parse_network_macs(xml, "rum-other").is_empty());
+    }
+
+    #[test]
+    fn attaches_to_network_matches_source_exactly() {
+        let xml = r#"<domain type="kvm">
+  <devices>
+    <interface type='network'>
+      <source network='rum-net-lab'/>
+    </interface>
+  </devices>
+</domain>"#;
+        assert!(attaches_to_network(xml, "rum-net-lab"));
+        assert!(!attaches_to_network(xml, "rum-net-la"));
+        assert!(!attaches_to_network("<domain/>", "rum-net-lab"));
+    }
+
+    #[test]
+    fn parse_filesystem_tags_from_live_xml() {
+        let mount = ResolvedMount {
+            source: PathBuf::from("/home/user/project"),
+            target: "/mnt/project".into(),
+            readonly: false,
+            tag: "mnt_project".into(),
+            nine_p: false,
+        };
+        let xml = format!(
+            "<domain><devices>{}<interface type='network'/></devices></domain>",
+            generate_filesystem_xml(&mount)
+        );
+        assert_eq!(parse_filesystem_tags(&xml), ["mnt_project"]);
+        assert!(parse_filesystem_tags("<domain/>").is_empty());
+    }
+
     #[test]
     fn parse_vsock_cid_from_live_xml() {
         let xml = r#"<domain type="kvm">
@@ -198,4 +631,97 @@ mod tests {
         let xml = r#"<domain type="kvm"><name>test</name></domain>"#;
         assert_eq!(parse_vsock_cid(xml), None);
     }
+
+    #[test]
+    fn xml_metadata_round_trips_labels() {
+        let mut config = test_domain_config();
+        config.labels.insert("team".into(), "platform".into());
+        config.labels.insert("env".into(), "dev".into());
+        let xml = make_xml(&config, &[], &[]);
+        assert!(
+            xml.contains("<metadata>"),
+            "should have metadata, got:\n{xml}"
+        );
+
+        let metadata = parse_instance_metadata(&xml).expect("metadata should parse");
+        assert_eq!(metadata.id, "aabbccdd");
+        assert_eq!(metadata.labels, config.labels);
+    }
+
+    #[test]
+    fn parse_instance_metadata_without_labels() {
+        let xml = make_xml(&test_domain_config(), &[], &[]);
+        let metadata = parse_instance_metadata(&xml).expect("metadata should parse");
+        assert_eq!(metadata.id, "aabbccdd");
+        assert!(metadata.labels.is_empty());
+        assert_eq!(metadata.profile, None);
+        assert_eq!(metadata.work_dir, None);
+    }
+
+    #[test]
+    fn xml_metadata_records_profile() {
+        let mut config = test_domain_config();
+        config.profile = Some("ci".into());
+        let xml = make_xml(&config, &[], &[]);
+        assert!(xml.contains(r#"profile="ci""#), "got:\n{xml}");
+
+        let metadata = parse_instance_metadata(&xml).expect("metadata should parse");
+        assert_eq!(metadata.profile.as_deref(), Some("ci"));
+    }
+
+    #[test]
+    fn xml_metadata_records_work_dir() {
+        let mut config = test_domain_config();
+        config.work_dir = Some(PathBuf::from("/home/alice/rum/aabbccdd-web"));
+        let xml = make_xml(&config, &[], &[]);
+
+        let metadata = parse_instance_metadata(&xml).expect("metadata should parse");
+        assert_eq!(metadata.work_dir, config.work_dir);
+    }
+
+    #[test]
+    fn parse_instance_metadata_foreign_domain() {
+        let xml = r#"<domain type="kvm"><name>other</name></domain>"#;
+        assert_eq!(parse_instance_metadata(xml), None);
+    }
+
+    #[test]
+    fn domain_changes_separate_live_fields_from_restarts() {
+        let mut config = test_domain_config();
+        config.memory_max_mb = Some(2048);
+        config.cpus_max = Some(4);
+        let old = make_xml(&config, &[], &[]);
+        assert!(domain_changes(&old, &old).is_empty());
+
+        config.memory_mb = 1024;
+        config.cpus = 2;
+        config.labels.insert("team".into(), "infra".into());
+        let changes = domain_changes(&old, &make_xml(&config, &[], &[]));
+        assert_eq!(
+            changes,
+            [
+                DomainChange::Memory { mb: 1024 },
+                DomainChange::Vcpus { count: 2 },
+                DomainChange::Metadata,
+            ]
+        );
+        assert!(changes.iter().all(DomainChange::is_live));
+
+        config.memory_max_mb = Some(4096);
+        let drive = ResolvedDrive {
+            path: PathBuf::from("/tmp/data.qcow2"),
+            dev: "vdb".into(),
+            block: false,
+            readonly: false,
+            format: "qcow2".into(),
+            tuning: DiskTuning::default(),
+        };
+        let changes = domain_changes(&old, &make_xml(&config, &[], &[drive]));
+        assert!(changes.contains(&DomainChange::Restart { part: "memory" }));
+        assert!(changes.contains(&DomainChange::Restart { part: "disks" }));
+        assert_eq!(
+            domain_changes(&old, "<domain/>"),
+            [DomainChange::Restart { part: "definition" }]
+        );
+    }
 }
@@ -1,10 +1,42 @@
 use facet::Facet;
 use roam::{Rx, Tx};
 
+/// Version of the [`Agent`] RPC interface. Bumped whenever a method or a
+/// type it carries changes shape, so the host can tell an incompatible agent
+/// apart before its calls fail to decode.
+pub const PROTOCOL_VERSION: u32 = 15;
+
+/// First protocol whose agents serve [`Agent::set_peer_hosts`].
+pub const PEER_HOSTS_PROTOCOL: u32 = 13;
+
+/// Answer to `ping`. Agents before [`Agent::protocol_version`] only report
+/// their protocol here, so this keeps its shape.
 #[derive(Debug, Clone, Facet)]
 pub struct ReadyResponse {
     pub version: String,
     pub hostname: String,
+    /// [`PROTOCOL_VERSION`] of the agent; 0 for agents that predate it.
+    #[facet(default)]
+    pub protocol: u32,
+}
+
+/// Answer to the host's periodic liveness check.
+#[derive(Debug, Clone, Facet)]
+pub struct Heartbeat {
+    /// Time since the guest kernel booted. Going backwards between two
+    /// heartbeats means the guest rebooted underneath the host.
+    pub uptime_ms: u64,
+}
+
+/// How cloud-init's first-boot run ended.
+#[derive(Debug, Clone, Facet)]
+pub struct CloudInitReport {
+    /// `false` when the guest has no cloud-init, or it did not run.
+    pub ran: bool,
+    /// Errors cloud-init recorded; empty when it succeeded.
+    pub errors: Vec<String>,
+    /// The end of the cloud-init output log when it failed.
+    pub log_excerpt: Vec<String>,
 }
 
 #[derive(Debug, Clone, Facet)]
@@ -34,9 +66,33 @@ pub struct LogEvent {
     pub stream: LogStream,
 }
 
+/// One environment variable passed to a guest command.
+#[derive(Debug, Clone, Facet)]
+pub struct EnvVar {
+    pub name: String,
+    pub value: String,
+}
+
+/// Execution context for a guest command.
+///
+/// Every field is optional so the default keeps the historical behavior:
+/// run as the agent user (root) in the agent's working directory with no
+/// time limit.
+#[derive(Debug, Clone, Default, Facet)]
+pub struct ExecOptions {
+    pub user: Option<String>,
+    pub cwd: Option<String>,
+    pub env: Vec<EnvVar>,
+    pub timeout_s: Option<u64>,
+    /// Id [`Agent::cancel`] stops the command by, chosen by the caller.
+    pub execution_id: Option<String>,
+}
+
 #[derive(Debug, Clone, Facet)]
 pub struct ExecResult {
     pub exit_code: Option<i32>,
+    /// The command ran past [`ExecOptions::timeout_s`] and was killed.
+    pub timed_out: bool,
 }
 
 #[derive(Debug, Clone, Facet)]
@@ -54,6 +110,31 @@ pub enum ProvisionEvent {
     Done(i32),
 }
 
+/// How the agent applies a provisioning entry.
+#[derive(Debug, Clone, Facet)]
+#[repr(u8)]
+pub enum ScriptKind {
+    /// Run `content` with `sh`.
+    Shell,
+    /// Install `content` as `/etc/nixos/rum.nix` and run `nixos-rebuild switch`.
+    NixosModule,
+}
+
+/// What a provisioning call does with the state earlier runs left behind.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Facet)]
+#[repr(u8)]
+pub enum ProvisionMode {
+    /// The whole plan: replaces the cached boot scripts and marks the guest
+    /// provisioned once it succeeds.
+    Plan,
+    /// Scripts run outside the plan, such as `rum provision --only`; the
+    /// cached boot scripts are left alone.
+    OneOff,
+    /// A host script run with `rum provision --script`. Runs like
+    /// [`Self::OneOff`], but its marker is kept apart from the plan's steps.
+    Script,
+}
+
 #[derive(Debug, Clone, Facet)]
 pub struct ProvisionScript {
     pub name: String,
@@ -61,6 +142,61 @@ pub struct ProvisionScript {
     pub content: String,
     pub order: u32,
     pub run_on: RunOn,
+    pub kind: ScriptKind,
+    /// Scripts that must finish first. `None` waits for every script that
+    /// sorts before this one; `Some` lets it run alongside anything else.
+    pub depends_on: Option<Vec<String>>,
+    /// Guest user a shell script runs as; root when `None`.
+    pub user: Option<String>,
+    /// Program a shell script is handed to as a file, e.g. `python3`;
+    /// `sh -c` when `None`.
+    pub interpreter: Option<String>,
+    /// Environment the script runs with, such as secrets from the host. It
+    /// is never written to the guest disk, so a script that needs it is not
+    /// cached for boot replay and only runs when the host sends it.
+    pub env: Vec<EnvVar>,
+}
+
+impl ProvisionScript {
+    /// A shell script run as root by `sh`, after every script ordered
+    /// before it, with no extra environment.
+    pub fn shell(
+        name: impl Into<String>,
+        title: impl Into<String>,
+        content: String,
+        order: u32,
+        run_on: RunOn,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            title: title.into(),
+            content,
+            order,
+            run_on,
+            kind: ScriptKind::Shell,
+            depends_on: None,
+            user: None,
+            interpreter: None,
+            env: Vec::new(),
+        }
+    }
+}
+
+/// A boot script the agent replayed on reboot, before the host connected.
+#[derive(Debug, Clone, Facet)]
+pub struct BootScriptResult {
+    pub name: String,
+    /// `None` when the script could not be started or was killed by a
+    /// signal.
+    pub exit_code: Option<i32>,
+    /// The end of its combined stdout and stderr.
+    pub output: Vec<String>,
+}
+
+impl BootScriptResult {
+    pub fn success(&self) -> bool {
+        self.exit_code == Some(0)
+    }
 }
 
 #[derive(Debug, Clone, Facet)]
@@ -69,9 +205,86 @@ pub struct ProvisionResult {
     pub failed_script: String,
 }
 
+/// A virtiofs mount the host expects to be active in the guest.
+#[derive(Debug, Clone, Facet)]
+pub struct MountCheck {
+    pub tag: String,
+    pub target: String,
+}
+
+/// Outcome of a mount verification pass.
+#[derive(Debug, Clone, Facet)]
+pub struct MountReport {
+    /// Mounts that were still missing after the agent retried `mount -a`.
+    pub missing: Vec<MountCheck>,
+    /// Whether the agent had to run `mount -a` to recover a mount.
+    pub remounted: bool,
+}
+
+/// A guest network interface and the addresses configured on it.
+#[derive(Debug, Clone, Facet)]
+pub struct GuestInterface {
+    pub name: String,
+    /// Lowercase colon-separated MAC address.
+    pub mac: String,
+    /// IPv4 and IPv6 addresses without prefix length.
+    pub addrs: Vec<String>,
+}
+
+/// Another machine on a network this guest shares, as it goes into
+/// `/etc/hosts`.
+#[derive(Debug, Clone, PartialEq, Eq, Facet)]
+pub struct PeerHost {
+    pub ip: String,
+    pub names: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Facet)]
+#[repr(u8)]
+pub enum FsEventKind {
+    Created,
+    Modified,
+    Removed,
+}
+
+/// A filesystem change observed inside the guest.
+#[derive(Debug, Clone, Facet)]
+pub struct FsEvent {
+    pub timestamp_us: u64,
+    /// Absolute guest path that changed.
+    pub path: String,
+    pub kind: FsEventKind,
+    pub is_dir: bool,
+}
+
+/// Filtering and batching for a `subscribe_fs_events` stream.
+#[derive(Debug, Clone, Default, Facet)]
+pub struct FsWatchOptions {
+    /// Gitignore-style patterns relative to each watched root. Ignored
+    /// directories get no watches at all.
+    pub ignore: Vec<String>,
+    /// Also honor the `.gitignore` file at each watched root.
+    pub gitignore: bool,
+    /// Collect events for this long and send each changed path once.
+    /// `0` sends every event as it happens.
+    pub debounce_ms: u64,
+}
+
+/// One entry of a directory tree listing, relative to the listed root.
+#[derive(Debug, Clone, Facet)]
+pub struct TreeEntry {
+    pub path: String,
+    pub size: u64,
+    pub mode: u32,
+    pub is_dir: bool,
+}
+
 #[derive(Debug, Clone, Facet)]
 pub struct FileChunk {
     pub data: Vec<u8>,
+    /// `data` is a zstd frame. Senders leave chunks that do not shrink raw.
+    #[facet(default)]
+    pub compressed: bool,
 }
 
 #[derive(Debug, Clone, Facet)]
@@ -79,38 +292,184 @@ pub struct WriteFileInfo {
     pub path: String,
     pub filename: String,
     pub mode: u32,
+    /// Size of the whole file, including the part already transferred.
     pub size: u64,
+    /// Where the data starts: bytes before it are kept from the partial
+    /// file of an interrupted transfer, see [`Agent::partial_upload`].
+    pub offset: u64,
+    /// Hex SHA-256 of the whole file. The file only replaces the
+    /// destination once its content matches.
+    pub sha256: Option<String>,
 }
 
 #[derive(Debug, Clone, Facet)]
 pub struct WriteFileResult {
     pub bytes_written: u64,
+    /// Hex SHA-256 of the file as written.
+    pub sha256: String,
+}
+
+/// What is left of an interrupted upload to a destination.
+#[derive(Debug, Clone, Facet)]
+pub struct ResumePoint {
+    /// Bytes already in the partial file; 0 when there is none.
+    pub offset: u64,
+    /// Hex SHA-256 of those bytes.
+    pub sha256: String,
 }
 
 #[derive(Debug, Clone, Facet)]
 pub struct ReadFileResult {
     pub mode: u32,
     pub size: u64,
+    /// Hex SHA-256 of the whole file.
+    pub sha256: String,
+}
+
+/// What `service_action` asks `systemctl` to do with a guest unit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Facet)]
+#[repr(u8)]
+pub enum ServiceAction {
+    Start,
+    Stop,
+    Restart,
+    Reload,
+    Enable,
+    Disable,
+    Status,
+}
+
+impl ServiceAction {
+    /// The `systemctl` verb for this action.
+    pub fn verb(self) -> &'static str {
+        match self {
+            Self::Start => "start",
+            Self::Stop => "stop",
+            Self::Restart => "restart",
+            Self::Reload => "reload",
+            Self::Enable => "enable",
+            Self::Disable => "disable",
+            Self::Status => "status",
+        }
+    }
+}
+
+/// Outcome of one `systemctl` call made by `service_action`.
+#[derive(Debug, Clone, Facet)]
+pub struct ServiceActionResult {
+    pub exit_code: i32,
+    /// Combined stdout and stderr of `systemctl`.
+    pub output: String,
+}
+
+/// A guest service unit as `systemctl list-units` reports it.
+#[derive(Debug, Clone, Facet)]
+pub struct ServiceInfo {
+    pub name: String,
+    /// Load state, e.g. `loaded` or `not-found`.
+    pub load: String,
+    /// High-level state, e.g. `active` or `failed`.
+    pub active: String,
+    /// Unit-type specific state, e.g. `running` or `exited`.
+    pub sub: String,
+    pub description: String,
+}
+
+/// Which guest log lines `stream_journal` sends.
+#[derive(Debug, Clone, Default, Facet)]
+pub struct JournalFilter {
+    /// Only lines of this systemd unit. Needs journald.
+    pub unit: Option<String>,
+    /// Lines of history sent first.
+    pub lines: u32,
+    /// Keep sending new lines until the stream is dropped.
+    pub follow: bool,
 }
 
 #[roam::service]
 pub trait Agent {
     async fn ping(&self) -> Result<ReadyResponse, String>;
+    /// The agent's [`PROTOCOL_VERSION`]. Never changes shape, so the host
+    /// can read it before anything else that might not decode.
+    async fn protocol_version(&self) -> u32;
+    /// Cheap liveness check the host sends while the machine runs.
+    async fn heartbeat(&self) -> Result<Heartbeat, String>;
+    /// Wait until cloud-init finished its run and report how it ended.
+    async fn cloud_init_status(&self) -> Result<CloudInitReport, String>;
+    /// The boot scripts run since the agent started, in the order they ran.
+    /// Scripts after a failed one are skipped and not listed.
+    async fn boot_report(&self) -> Vec<BootScriptResult>;
     async fn subscribe_logs(&self, output: Tx<LogEvent>);
-    async fn exec(&self, command: String, output: Tx<LogEvent>) -> ExecResult;
+    async fn exec(
+        &self,
+        command: String,
+        options: ExecOptions,
+        output: Tx<LogEvent>,
+    ) -> ExecResult;
+    /// Run `scripts`; `execution_id` lets [`Agent::cancel`] stop them.
     async fn provision(
         &self,
         scripts: Vec<ProvisionScript>,
+        execution_id: Option<String>,
+        mode: ProvisionMode,
         output: Tx<ProvisionEvent>,
     ) -> ProvisionResult;
+    async fn verify_mounts(&self, mounts: Vec<MountCheck>) -> MountReport;
+    async fn interfaces(&self) -> Vec<GuestInterface>;
+    /// Replace the block of peer records rum keeps in `/etc/hosts` with
+    /// `peers`; an empty list removes the block.
+    async fn set_peer_hosts(&self, peers: Vec<PeerHost>) -> Result<(), String>;
+    /// Write `data` to a partial file next to the destination and move it
+    /// into place once all `info.size` bytes arrived. An interrupted
+    /// transfer leaves the partial file for a later call to resume.
     async fn write_file(
         &self,
         info: WriteFileInfo,
         data: Rx<FileChunk>,
     ) -> Result<WriteFileResult, String>;
+    /// The partial file an interrupted `write_file` to `path` left.
+    async fn partial_upload(&self, path: String, filename: String) -> Result<ResumePoint, String>;
+    async fn subscribe_fs_events(
+        &self,
+        paths: Vec<String>,
+        options: FsWatchOptions,
+        output: Tx<FsEvent>,
+    );
+    async fn stat_tree(&self, root: String) -> Result<Vec<TreeEntry>, String>;
+    async fn hash_file(&self, path: String) -> Result<String, String>;
+    /// Hex SHA-256 of the first `len` bytes of `path`.
+    async fn hash_file_prefix(&self, path: String, len: u64) -> Result<String, String>;
+    /// Remove a file or directory tree; a missing path is not an error.
+    async fn remove_path(&self, path: String) -> Result<(), String>;
+    /// Stream `path` from byte `offset`, zstd-compressing chunks when
+    /// `compress` is set.
     async fn read_file(
         &self,
         path: String,
+        offset: u64,
+        compress: bool,
         output: Tx<FileChunk>,
     ) -> Result<ReadFileResult, String>;
+    /// Run `systemctl <action> <name>` on a guest unit.
+    async fn service_action(
+        &self,
+        name: String,
+        action: ServiceAction,
+    ) -> Result<ServiceActionResult, String>;
+    /// Every service unit systemd knows about, loaded or not.
+    async fn list_services(&self) -> Result<Vec<ServiceInfo>, String>;
+    /// Tail the guest journal, or `/var/log/syslog` and `/var/log/messages`
+    /// on guests without journald.
+    async fn stream_journal(
+        &self,
+        filter: JournalFilter,
+        output: Tx<LogEvent>,
+    ) -> Result<(), String>;
+    /// Replace the agent binary with `data`, checked against its hex
+    /// SHA-256, and re-exec into it once the reply is sent.
+    async fn update_agent(&self, sha256: String, data: Rx<FileChunk>) -> Result<(), String>;
+    /// Stop the exec or provisioning run started with `execution_id`:
+    /// SIGTERM to its processes, then SIGKILL to whatever is left after a
+    /// grace period. Returns whether anything was running under that id.
+    async fn cancel(&self, execution_id: String) -> bool;
 }
@@ -0,0 +1,190 @@
+//! Replaying cached boot scripts when the guest comes back up.
+//!
+//! [`crate::provision`] caches the plan's boot scripts; they run again on
+//! the first agent start after each reboot, and what happened is kept for
+//! [`Agent::boot_report`](guest::agent::Agent::boot_report).
+
+use std::path::Path;
+
+use tokio::io::{AsyncBufReadExt, BufReader};
+
+use guest::agent::BootScriptResult;
+
+use crate::provision::SCRIPTS_DIR;
+
+/// Created by the first agent start of a boot. `/run` is a tmpfs, so it is
+/// gone after a reboot but survives self-update re-execs and restarts.
+pub const BOOTED_MARKER: &str = "/run/rum/booted";
+/// Output lines of a boot script kept for the report.
+const BOOT_OUTPUT_LINES: usize = 50;
+
+/// Replay the cached boot scripts in order, stopping at the first that
+/// fails, and keep what happened for the host to ask about.
+pub async fn run_cached_boot_scripts() -> Vec<BootScriptResult> {
+    let mut results = Vec::new();
+    let scripts_dir = Path::new(SCRIPTS_DIR);
+    let mut entries = match tokio::fs::read_dir(scripts_dir).await {
+        Ok(e) => e,
+        Err(e) => {
+            tracing::error!(error = %e, "failed to read scripts dir");
+            return results;
+        }
+    };
+
+    let mut boot_scripts = Vec::new();
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let name = entry.file_name();
+        let name_str = name.to_string_lossy();
+        if name_str.ends_with(".boot.sh") {
+            boot_scripts.push(entry.path());
+        }
+    }
+    boot_scripts.sort();
+
+    if boot_scripts.is_empty() {
+        return results;
+    }
+
+    tracing::info!(count = boot_scripts.len(), "running cached boot scripts");
+    for path in &boot_scripts {
+        let filename = path.file_name().unwrap_or_default().to_string_lossy();
+        tracing::info!(script = %filename, "executing boot script");
+        let result = run_boot_script(path, &filename).await;
+        let success = result.success();
+        results.push(result);
+        if !success {
+            break;
+        }
+    }
+    results
+}
+
+async fn run_boot_script(path: &Path, filename: &str) -> BootScriptResult {
+    let failed = |line: String| BootScriptResult {
+        name: filename.to_string(),
+        exit_code: None,
+        output: vec![line],
+    };
+
+    let content = match tokio::fs::read_to_string(path).await {
+        Ok(c) => c,
+        Err(e) => {
+            tracing::error!(script = %filename, error = %e, "failed to read script");
+            return failed(format!("failed to read script: {e}"));
+        }
+    };
+
+    // One pipe for both streams keeps their lines in order.
+    let child = tokio::process::Command::new("sh")
+        .arg("-c")
+        .arg(format!("exec 2>&1\n{content}"))
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::piped())
+        .spawn();
+    let mut child = match child {
+        Ok(child) => child,
+        Err(e) => {
+            tracing::error!(script = %filename, error = %e, "failed to spawn boot script");
+            return failed(format!("failed to spawn boot script: {e}"));
+        }
+    };
+
+    // Every line goes to the journal as it comes; only the end is kept for
+    // the report, however much the script prints.
+    let mut tail = std::collections::VecDeque::with_capacity(BOOT_OUTPUT_LINES);
+    let mut lines = BufReader::new(child.stdout.take().unwrap()).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        tracing::info!(script = %filename, "{line}");
+        if tail.len() == BOOT_OUTPUT_LINES {
+            tail.pop_front();
+        }
+        tail.push_back(line);
+    }
+
+    let exit_code = match child.wait().await {
+        Ok(status) => status.code(),
+        Err(e) => {
+            tracing::error!(script = %filename, error = %e, "failed to wait for boot script");
+            None
+        }
+    };
+    if exit_code == Some(0) {
+        tracing::info!(script = %filename, "boot script completed");
+    } else {
+        tracing::error!(script = %filename, ?exit_code, "boot script failed");
+    }
+    BootScriptResult {
+        name: filename.to_string(),
+        exit_code,
+        output: tail.into(),
+    }
+}
+
+/// Whether this is the agent's first start since the guest booted, marking
+/// it so at `marker`. Without a writable marker every start counts as the
+/// first, as before the marker existed.
+pub fn first_start_since_boot(marker: &Path) -> bool {
+    if let Some(dir) = marker.parent() {
+        let _ = std::fs::create_dir_all(dir);
+    }
+    match std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(marker)
+    {
+        Ok(_) => true,
+        Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+            tracing::info!("agent restarted since boot; skipping boot scripts");
+            false
+        }
+        Err(e) => {
+            tracing::warn!(path = %marker.display(), error = %e, "failed to mark the boot");
+            true
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("rum-agent-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[tokio::test]
+    async fn boot_scripts_report_the_end_of_their_output() {
+        let dir = temp_dir("boot-script");
+        let script = dir.join("10-noisy.boot.sh");
+        std::fs::write(
+            &script,
+            "for i in $(seq 1 200); do echo \"line $i\"; done\necho oops >&2\nexit 3\n",
+        )
+        .unwrap();
+
+        let result = run_boot_script(&script, "10-noisy.boot.sh").await;
+        assert_eq!(result.name, "10-noisy.boot.sh");
+        assert_eq!(result.exit_code, Some(3));
+        assert_eq!(result.output.len(), BOOT_OUTPUT_LINES);
+        assert_eq!(result.output.last().unwrap(), "oops");
+        assert_eq!(
+            result.output[0],
+            format!("line {}", 200 - BOOT_OUTPUT_LINES + 2)
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn only_the_first_start_after_boot_runs_boot_scripts() {
+        let dir = temp_dir("booted");
+        let marker = dir.join("run/rum/booted");
+        assert!(first_start_since_boot(&marker));
+        // A re-exec after `rum agent update`, or a restart by systemd.
+        assert!(!first_start_since_boot(&marker));
+        assert!(!first_start_since_boot(&marker));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,32 @@
+use super::{Client, ClientError};
+
+impl<C> Client<C>
+where
+    C: roam_stream::Connector,
+{
+    /// Wait for cloud-init to finish in the guest.
+    ///
+    /// Fails with the errors cloud-init recorded and the end of its output
+    /// log. Guests without cloud-init pass right away.
+    pub async fn wait_for_cloud_init(&self) -> Result<(), ClientError> {
+        let report = self
+            .rpc()
+            .cloud_init_status()
+            .await
+            .map_err(|message| ClientError::Rpc {
+                context: "cloud_init_status RPC failed".into(),
+                message: message.to_string(),
+            })?;
+
+        if !report.ran {
+            tracing::debug!("no cloud-init run to wait for");
+        }
+        if report.errors.is_empty() {
+            return Ok(());
+        }
+        Err(ClientError::CloudInitFailed {
+            reason: report.errors.join("; "),
+            log_excerpt: report.log_excerpt,
+        })
+    }
+}
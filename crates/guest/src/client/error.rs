@@ -8,10 +8,19 @@ pub enum ClientError {
     },
     #[error("agent did not respond within {timeout_secs}s: {message}")]
     AgentTimeout { timeout_secs: u64, message: String },
+    #[error("{message}")]
+    IncompatibleAgent { message: String },
     #[error("{context}: {message}")]
     Rpc { context: String, message: String },
     #[error("copy failed: {message}")]
     CopyFailed { message: String },
     #[error("provision failed: {script}")]
     ProvisionFailed { script: String },
+    #[error("mount '{tag}' is not active at {target}")]
+    MountMissing { tag: String, target: String },
+    #[error("cloud-init failed: {reason}")]
+    CloudInitFailed {
+        reason: String,
+        log_excerpt: Vec<String>,
+    },
 }
@@ -1,4 +1,4 @@
-use crate::agent::{LogEvent, LogStream};
+use crate::agent::{ExecOptions, ExecResult, LogEvent, LogStream};
 
 use super::{Client, ClientError};
 
@@ -6,10 +6,10 @@ impl<C> Client<C>
 where
     C: roam_stream::Connector,
 {
-    pub async fn exec(&self, command: String) -> Result<i32, ClientError> {
+    pub async fn exec(&self, command: String, options: ExecOptions) -> Result<i32, ClientError> {
         use std::io::Write;
 
-        self.exec_with_output(command, |event| match event.stream {
+        self.exec_with_output(command, options, |event| match event.stream {
             LogStream::Stdout => {
                 let mut stdout = std::io::stdout().lock();
                 let _ = writeln!(stdout, "{}", event.message);
@@ -27,20 +27,36 @@ where
     pub async fn exec_with_output<F>(
         &self,
         command: String,
+        options: ExecOptions,
         on_output: F,
     ) -> Result<i32, ClientError>
+    where
+        F: Fn(LogEvent) + Send + Sync,
+    {
+        let result = self.exec_with_result(command, options, on_output).await?;
+        Ok(result.exit_code.unwrap_or(1))
+    }
+
+    /// Like [`Self::exec_with_output`], but returns the agent's
+    /// [`ExecResult`] so callers can tell a timeout from a failed exit.
+    pub async fn exec_with_result<F>(
+        &self,
+        command: String,
+        options: ExecOptions,
+        on_output: F,
+    ) -> Result<ExecResult, ClientError>
     where
         F: Fn(LogEvent) + Send + Sync,
     {
         let (tx, mut rx) = roam::channel::<LogEvent>();
         let agent = self.rpc().clone();
-        let exec_task = tokio::spawn(async move { agent.exec(command, tx).await });
+        let exec_task = tokio::spawn(async move { agent.exec(command, options, tx).await });
 
         while let Ok(Some(event)) = rx.recv().await {
             on_output(event);
         }
 
-        let result = exec_task
+        exec_task
             .await
             .map_err(|e| ClientError::Io {
                 context: format!("exec task panicked: {e}"),
@@ -49,7 +65,18 @@ where
             .map_err(|message| ClientError::Rpc {
                 context: "exec RPC failed".into(),
                 message: message.to_string(),
-            })?;
-        Ok(result.exit_code.unwrap_or(1))
+            })
+    }
+
+    /// Stop the exec or provisioning run started with `execution_id`.
+    /// Returns whether the agent had anything running under it.
+    pub async fn cancel(&self, execution_id: String) -> Result<bool, ClientError> {
+        self.rpc()
+            .cancel(execution_id)
+            .await
+            .map_err(|message| ClientError::Rpc {
+                context: "cancel RPC failed".into(),
+                message: message.to_string(),
+            })
     }
 }
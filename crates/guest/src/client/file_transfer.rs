@@ -1,8 +1,9 @@
 use std::path::{Path, PathBuf};
 
-use tokio::io::{AsyncReadExt, AsyncWriteExt, BufReader, BufWriter};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt, BufReader, BufWriter};
 
 use crate::agent::{FileChunk, WriteFileInfo};
+use crate::transfer;
 
 use super::{Client, ClientError};
 
@@ -33,18 +34,59 @@ pub fn parse_copy_args(src: &str, dst: &str) -> Result<CopyDirection, ClientErro
     }
 }
 
+/// How a copy moves its bytes.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TransferOptions {
+    /// zstd-compress chunks on the wire. Pays off for text and build
+    /// artifacts, costs CPU for already-compressed files.
+    pub compress: bool,
+}
+
+fn copy_failed(message: impl std::fmt::Display) -> ClientError {
+    ClientError::CopyFailed {
+        message: message.to_string(),
+    }
+}
+
+async fn hash_local(path: &Path) -> Result<String, ClientError> {
+    let path = path.to_path_buf();
+    tokio::task::spawn_blocking(move || crate::tree::hash_file(&path))
+        .await
+        .map_err(|e| copy_failed(format!("hash task: {e}")))?
+        .map_err(|e| copy_failed(format!("hash: {e}")))
+}
+
+async fn resume_offset(path: &Path, offset: u64, sha256: String) -> Result<u64, ClientError> {
+    let path = path.to_path_buf();
+    tokio::task::spawn_blocking(move || transfer::resume_offset(&path, offset, &sha256))
+        .await
+        .map_err(|e| copy_failed(format!("hash task: {e}")))?
+        .map_err(|e| copy_failed(format!("hash: {e}")))
+}
+
 impl<C> Client<C>
 where
     C: roam_stream::Connector,
 {
     pub async fn copy_to_guest(&self, local: &Path, guest_path: &str) -> Result<u64, ClientError> {
+        self.copy_to_guest_with(local, guest_path, TransferOptions::default())
+            .await
+    }
+
+    /// Upload `local` to `guest_path`, continuing a previous upload that
+    /// left a partial file with the same leading bytes behind. Returns the
+    /// bytes sent by this call.
+    pub async fn copy_to_guest_with(
+        &self,
+        local: &Path,
+        guest_path: &str,
+        options: TransferOptions,
+    ) -> Result<u64, ClientError> {
         use std::os::unix::fs::PermissionsExt;
 
         let metadata = tokio::fs::metadata(local)
             .await
-            .map_err(|e| ClientError::CopyFailed {
-                message: format!("{}: {e}", local.display()),
-            })?;
+            .map_err(|e| copy_failed(format!("{}: {e}", local.display())))?;
         let mode = metadata.permissions().mode();
         let size = metadata.len();
         let filename = local
@@ -53,22 +95,29 @@ where
             .to_string_lossy()
             .to_string();
 
+        let partial = self
+            .rpc()
+            .partial_upload(guest_path.to_string(), filename.clone())
+            .await
+            .map_err(|message| copy_failed(format!("partial_upload RPC: {message}")))?;
+        let offset = resume_offset(local, partial.offset, partial.sha256).await?;
+        let sha256 = hash_local(local).await?;
+
         let (tx, rx) = roam::channel::<FileChunk>();
         let local_owned = local.to_path_buf();
+        let compress = options.compress;
         let send_task = tokio::spawn(async move {
-            let file = tokio::fs::File::open(&local_owned).await?;
+            let mut file = tokio::fs::File::open(&local_owned).await?;
+            file.seek(std::io::SeekFrom::Start(offset)).await?;
             let mut reader = BufReader::new(file);
-            const CHUNK_SIZE: usize = 10 * 1024 * 1024;
-            let mut buf = vec![0u8; CHUNK_SIZE];
+            let mut buf = vec![0u8; transfer::CHUNK_SIZE];
 
             loop {
                 let n = reader.read(&mut buf).await?;
                 if n == 0 {
                     break;
                 }
-                let chunk = FileChunk {
-                    data: buf[..n].to_vec(),
-                };
+                let chunk = transfer::encode_chunk(&buf[..n], compress);
                 if tx.send(&chunk).await.is_err() {
                     break;
                 }
@@ -82,24 +131,27 @@ where
             filename,
             mode,
             size,
+            offset,
+            sha256: Some(sha256.clone()),
         };
 
         let result = self
             .rpc()
             .write_file(info, rx)
             .await
-            .map_err(|message| ClientError::CopyFailed {
-                message: format!("write_file RPC: {message}"),
-            })?;
+            .map_err(|message| copy_failed(format!("write_file RPC: {message}")))?;
 
         send_task
             .await
-            .map_err(|e| ClientError::CopyFailed {
-                message: format!("send task: {e}"),
-            })?
-            .map_err(|e| ClientError::CopyFailed {
-                message: format!("send: {e}"),
-            })?;
+            .map_err(|e| copy_failed(format!("send task: {e}")))?
+            .map_err(|e| copy_failed(format!("send: {e}")))?;
+
+        if result.sha256 != sha256 {
+            return Err(copy_failed(format!(
+                "checksum mismatch: guest has {}, expected {sha256}",
+                result.sha256
+            )));
+        }
 
         Ok(result.bytes_written)
     }
@@ -109,68 +161,107 @@ where
         guest_path: &str,
         local: &Path,
     ) -> Result<u64, ClientError> {
-        use std::os::unix::fs::PermissionsExt;
+        self.copy_from_guest_with(guest_path, local, TransferOptions::default())
+            .await
+    }
 
-        let (tx, mut rx) = roam::channel::<FileChunk>();
-        let guest_owned = guest_path.to_string();
-        let agent = self.rpc().clone();
-        let read_task = tokio::spawn(async move { agent.read_file(guest_owned, tx).await });
+    /// Download `guest_path` to `local`, continuing from a partial file a
+    /// previous download left behind if the guest file still starts with
+    /// its bytes. Returns the bytes received by this call.
+    pub async fn copy_from_guest_with(
+        &self,
+        guest_path: &str,
+        local: &Path,
+        options: TransferOptions,
+    ) -> Result<u64, ClientError> {
+        use std::os::unix::fs::PermissionsExt;
 
         let guest_filename = Path::new(guest_path)
             .file_name()
             .unwrap_or_default()
             .to_string_lossy()
             .to_string();
-        let final_path = if local.is_dir() {
-            local.join(&guest_filename)
-        } else {
-            local.to_path_buf()
-        };
+        let final_path = transfer::destination(local, &guest_filename);
+        let part = transfer::partial_path(&final_path);
 
         if let Some(parent) = final_path.parent() {
             tokio::fs::create_dir_all(parent)
                 .await
-                .map_err(|e| ClientError::CopyFailed {
-                    message: format!("create dirs: {e}"),
-                })?;
+                .map_err(|e| copy_failed(format!("create dirs: {e}")))?;
         }
 
-        let file = tokio::fs::File::create(&final_path)
+        let partial_len = tokio::fs::metadata(&part)
             .await
-            .map_err(|e| ClientError::CopyFailed {
-                message: format!("{}: {e}", final_path.display()),
-            })?;
+            .map(|metadata| metadata.len())
+            .unwrap_or(0);
+        let mut offset = 0;
+        if partial_len > 0 {
+            // The guest file may have changed or shrunk since; start over then.
+            let remote = self
+                .rpc()
+                .hash_file_prefix(guest_path.to_string(), partial_len)
+                .await;
+            if let Ok(remote) = remote {
+                offset = resume_offset(&part, partial_len, remote).await?;
+            }
+        }
+
+        let (tx, mut rx) = roam::channel::<FileChunk>();
+        let guest_owned = guest_path.to_string();
+        let agent = self.rpc().clone();
+        let compress = options.compress;
+        let read_task =
+            tokio::spawn(async move { agent.read_file(guest_owned, offset, compress, tx).await });
+
+        let file = transfer::open_partial(&part, offset)
+            .await
+            .map_err(|e| copy_failed(format!("{}: {e}", part.display())))?;
         let mut writer = BufWriter::new(file);
         let mut bytes_written = 0_u64;
 
         while let Ok(Some(chunk)) = rx.recv().await {
+            let bytes = transfer::decode_chunk(chunk)
+                .map_err(|e| copy_failed(format!("decompress: {e}")))?;
             writer
-                .write_all(&chunk.data)
+                .write_all(&bytes)
                 .await
-                .map_err(|e| ClientError::CopyFailed {
-                    message: format!("write: {e}"),
-                })?;
-            bytes_written += chunk.data.len() as u64;
+                .map_err(|e| copy_failed(format!("write: {e}")))?;
+            bytes_written += bytes.len() as u64;
         }
 
-        writer.flush().await.map_err(|e| ClientError::CopyFailed {
-            message: format!("flush: {e}"),
-        })?;
+        writer
+            .flush()
+            .await
+            .map_err(|e| copy_failed(format!("flush: {e}")))?;
 
         let result = read_task
             .await
-            .map_err(|e| ClientError::CopyFailed {
-                message: format!("read task: {e}"),
-            })?
-            .map_err(|message| ClientError::CopyFailed {
-                message: format!("read_file RPC: {message}"),
-            })?;
-
-        tokio::fs::set_permissions(&final_path, std::fs::Permissions::from_mode(result.mode))
+            .map_err(|e| copy_failed(format!("read task: {e}")))?
+            .map_err(|message| copy_failed(format!("read_file RPC: {message}")))?;
+
+        // A short file stays behind as the partial for a resumed download.
+        let total = offset + bytes_written;
+        if total != result.size {
+            return Err(copy_failed(format!(
+                "transfer interrupted after {total} of {} bytes",
+                result.size
+            )));
+        }
+        let sha256 = hash_local(&part).await?;
+        if sha256 != result.sha256 {
+            let _ = tokio::fs::remove_file(&part).await;
+            return Err(copy_failed(format!(
+                "checksum mismatch: got {sha256}, expected {}",
+                result.sha256
+            )));
+        }
+
+        tokio::fs::set_permissions(&part, std::fs::Permissions::from_mode(result.mode))
+            .await
+            .map_err(|e| copy_failed(format!("chmod: {e}")))?;
+        tokio::fs::rename(&part, &final_path)
             .await
-            .map_err(|e| ClientError::CopyFailed {
-                message: format!("chmod: {e}"),
-            })?;
+            .map_err(|e| copy_failed(format!("{}: {e}", final_path.display())))?;
 
         Ok(bytes_written)
     }
@@ -191,3 +282,23 @@ pub async fn copy_from_guest<C: roam_stream::Connector>(
 ) -> Result<u64, ClientError> {
     client.copy_from_guest(guest_path, local).await
 }
+
+pub async fn copy_to_guest_with<C: roam_stream::Connector>(
+    client: &Client<C>,
+    local: &Path,
+    guest_path: &str,
+    options: TransferOptions,
+) -> Result<u64, ClientError> {
+    client.copy_to_guest_with(local, guest_path, options).await
+}
+
+pub async fn copy_from_guest_with<C: roam_stream::Connector>(
+    client: &Client<C>,
+    guest_path: &str,
+    local: &Path,
+    options: TransferOptions,
+) -> Result<u64, ClientError> {
+    client
+        .copy_from_guest_with(guest_path, local, options)
+        .await
+}
@@ -0,0 +1,40 @@
+use crate::agent::{FsEvent, FsWatchOptions};
+
+use super::{Client, ClientError};
+
+impl<C> Client<C>
+where
+    C: roam_stream::Connector,
+{
+    /// Watch guest `paths` recursively and call `on_event` for every change
+    /// until the agent closes the stream or the connection drops.
+    pub async fn subscribe_fs_events<F>(
+        &self,
+        paths: Vec<String>,
+        options: FsWatchOptions,
+        on_event: F,
+    ) -> Result<(), ClientError>
+    where
+        F: Fn(FsEvent) + Send + Sync,
+    {
+        let (tx, mut rx) = roam::channel::<FsEvent>();
+        let agent = self.rpc().clone();
+        let watch_task =
+            tokio::spawn(async move { agent.subscribe_fs_events(paths, options, tx).await });
+
+        while let Ok(Some(event)) = rx.recv().await {
+            on_event(event);
+        }
+
+        watch_task
+            .await
+            .map_err(|e| ClientError::Io {
+                context: format!("fs watch task panicked: {e}"),
+                source: std::io::Error::other(e.to_string()),
+            })?
+            .map_err(|message| ClientError::Rpc {
+                context: "subscribe_fs_events RPC failed".into(),
+                message: message.to_string(),
+            })
+    }
+}
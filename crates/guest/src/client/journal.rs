@@ -0,0 +1,38 @@
+use crate::agent::{JournalFilter, LogEvent};
+
+use super::{Client, ClientError};
+
+impl<C> Client<C>
+where
+    C: roam_stream::Connector,
+{
+    /// Pass guest log lines matching `filter` to `on_line` until the guest
+    /// stops sending them, which with `filter.follow` is never.
+    pub async fn stream_journal<F>(
+        &self,
+        filter: JournalFilter,
+        on_line: F,
+    ) -> Result<(), ClientError>
+    where
+        F: Fn(LogEvent) + Send + Sync,
+    {
+        let (tx, mut rx) = roam::channel::<LogEvent>();
+        let agent = self.rpc().clone();
+        let journal_task = tokio::spawn(async move { agent.stream_journal(filter, tx).await });
+
+        while let Ok(Some(event)) = rx.recv().await {
+            on_line(event);
+        }
+
+        journal_task
+            .await
+            .map_err(|e| ClientError::Io {
+                context: format!("journal task panicked: {e}"),
+                source: std::io::Error::other(e.to_string()),
+            })?
+            .map_err(|message| ClientError::Rpc {
+                context: "stream_journal RPC failed".into(),
+                message: message.to_string(),
+            })
+    }
+}
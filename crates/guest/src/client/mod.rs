@@ -1,9 +1,22 @@
+mod cloud_init;
 mod error;
 mod exec;
 mod file_transfer;
+mod fs_events;
+mod journal;
+mod mount;
+mod net;
 mod provision;
+mod service;
+mod sync;
 mod transport;
+mod update;
 
 pub use error::ClientError;
-pub use file_transfer::{CopyDirection, copy_from_guest, copy_to_guest, parse_copy_args};
-pub use transport::{Client, wait_for_agent};
+pub use file_transfer::{
+    CopyDirection, TransferOptions, copy_from_guest, copy_from_guest_with, copy_to_guest,
+    copy_to_guest_with, parse_copy_args,
+};
+pub use provision::{LogStamp, stamps_path};
+pub use sync::SyncStats;
+pub use transport::{Client, wait_for_agent, wait_for_agent_within};
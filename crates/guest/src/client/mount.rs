@@ -0,0 +1,81 @@
+use crate::agent::{MountCheck, MountReport};
+
+use super::{Client, ClientError};
+
+impl<C> Client<C>
+where
+    C: roam_stream::Connector,
+{
+    /// Ask the agent to confirm every mount is active, remounting if needed.
+    ///
+    /// Fails with the first mount that is still missing afterwards.
+    pub async fn verify_mounts(&self, mounts: Vec<MountCheck>) -> Result<(), ClientError> {
+        if mounts.is_empty() {
+            return Ok(());
+        }
+
+        let report =
+            self.rpc()
+                .verify_mounts(mounts)
+                .await
+                .map_err(|message| ClientError::Rpc {
+                    context: "verify_mounts RPC failed".into(),
+                    message: message.to_string(),
+                })?;
+
+        check_report(report)
+    }
+}
+
+/// Turn the agent's report into the first mount still missing, if any.
+fn check_report(report: MountReport) -> Result<(), ClientError> {
+    match report.missing.into_iter().next() {
+        Some(m) => Err(ClientError::MountMissing {
+            tag: m.tag,
+            target: m.target,
+        }),
+        None => {
+            if report.remounted {
+                tracing::warn!("guest mounts were missing after boot, recovered with mount -a");
+            }
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(tag: &str, target: &str) -> MountCheck {
+        MountCheck {
+            tag: tag.into(),
+            target: target.into(),
+        }
+    }
+
+    #[test]
+    fn recovered_mounts_pass() {
+        for remounted in [false, true] {
+            let report = MountReport {
+                missing: Vec::new(),
+                remounted,
+            };
+            assert!(check_report(report).is_ok());
+        }
+    }
+
+    #[test]
+    fn first_mount_still_missing_fails_the_check() {
+        let report = MountReport {
+            missing: vec![check("project", "/mnt/project"), check("data", "/mnt/data")],
+            remounted: true,
+        };
+        match check_report(report) {
+            Err(ClientError::MountMissing { tag, target }) => {
+                assert_eq!((tag.as_str(), target.as_str()), ("project", "/mnt/project"));
+            }
+            other => panic!("expected a missing mount, got {other:?}"),
+        }
+    }
+}
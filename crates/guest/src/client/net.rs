@@ -0,0 +1,30 @@
+use crate::agent::{GuestInterface, PeerHost};
+
+use super::{Client, ClientError};
+
+impl<C> Client<C>
+where
+    C: roam_stream::Connector,
+{
+    /// List the guest's network interfaces as the agent sees them.
+    pub async fn interfaces(&self) -> Result<Vec<GuestInterface>, ClientError> {
+        self.rpc()
+            .interfaces()
+            .await
+            .map_err(|message| ClientError::Rpc {
+                context: "interfaces RPC failed".into(),
+                message: message.to_string(),
+            })
+    }
+
+    /// Point the guest's `/etc/hosts` records for other machines at `peers`.
+    pub async fn set_peer_hosts(&self, peers: Vec<PeerHost>) -> Result<(), ClientError> {
+        self.rpc()
+            .set_peer_hosts(peers)
+            .await
+            .map_err(|message| ClientError::Rpc {
+                context: "set_peer_hosts RPC failed".into(),
+                message: message.to_string(),
+            })
+    }
+}
@@ -1,8 +1,8 @@
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::time::SystemTime;
 
-use crate::agent::{ProvisionEvent, ProvisionScript};
+use crate::agent::{BootScriptResult, ProvisionEvent, ProvisionMode, ProvisionScript};
 
 use super::{Client, ClientError};
 
@@ -10,18 +10,52 @@ impl<C> Client<C>
 where
     C: roam_stream::Connector,
 {
+    /// Run `scripts`, logging each one's output to `logs_dir`, where the
+    /// newest `keep_logs` finished logs per script are kept.
     pub async fn provision(
         &self,
         scripts: Vec<ProvisionScript>,
         logs_dir: &Path,
+        keep_logs: usize,
     ) -> Result<(), ClientError> {
-        self.provision_with_output(scripts, logs_dir, |_| ()).await
+        self.provision_with_output(scripts, logs_dir, keep_logs, None, |_| ())
+            .await
     }
 
+    /// Like [`Client::provision`], passing each output line to `on_output`.
+    /// `execution_id` lets [`Client::cancel`] stop the run.
     pub async fn provision_with_output<F>(
         &self,
         scripts: Vec<ProvisionScript>,
         logs_dir: &Path,
+        keep_logs: usize,
+        execution_id: Option<String>,
+        on_output: F,
+    ) -> Result<(), ClientError>
+    where
+        F: Fn(String) + Send + Sync + Clone,
+    {
+        self.provision_in_mode(
+            scripts,
+            logs_dir,
+            keep_logs,
+            execution_id,
+            ProvisionMode::Plan,
+            on_output,
+        )
+        .await
+    }
+
+    /// Like [`Client::provision_with_output`], for scripts that are only a
+    /// part of the plan or not in it at all. See [`ProvisionMode::OneOff`] and
+    /// [`ProvisionMode::Script`].
+    pub async fn provision_in_mode<F>(
+        &self,
+        scripts: Vec<ProvisionScript>,
+        logs_dir: &Path,
+        keep_logs: usize,
+        execution_id: Option<String>,
+        mode: ProvisionMode,
         on_output: F,
     ) -> Result<(), ClientError>
     where
@@ -31,7 +65,8 @@ where
 
         let (tx, rx) = roam::channel::<ProvisionEvent>();
         let agent = self.rpc().clone();
-        let task = tokio::spawn(async move { agent.provision(scripts, tx).await });
+        let task =
+            tokio::spawn(async move { agent.provision(scripts, execution_id, mode, tx).await });
 
         let rx = Arc::new(tokio::sync::Mutex::new(rx));
         let mut failed = false;
@@ -52,7 +87,11 @@ where
                         }
                         ProvisionEvent::Stdout(ref line) | ProvisionEvent::Stderr(ref line) => {
                             if let Some(ref mut lg) = logger {
-                                lg.write_line(line);
+                                let stream = match event {
+                                    ProvisionEvent::Stderr(_) => "stderr",
+                                    _ => "stdout",
+                                };
+                                lg.write_line(stream, line);
                             }
                             on_output(line.clone());
                         }
@@ -72,7 +111,7 @@ where
         }
 
         for name in &script_names {
-            rotate_logs(logs_dir, name, 10);
+            rotate_logs(logs_dir, name, keep_logs);
         }
 
         let result = task
@@ -94,11 +133,47 @@ where
 
         Ok(())
     }
+
+    /// The boot scripts the agent replayed when the guest last started.
+    pub async fn boot_report(&self) -> Result<Vec<BootScriptResult>, ClientError> {
+        self.rpc()
+            .boot_report()
+            .await
+            .map_err(|message| ClientError::Rpc {
+                context: "boot_report RPC failed".into(),
+                message: message.to_string(),
+            })
+    }
+}
+
+/// Sidecar of a script log holding one `<unix ms>\t<stdout|stderr>` stamp
+/// per line of the log, which itself keeps the plain output lines. Logs of
+/// older versions have none.
+pub fn stamps_path(log: &Path) -> PathBuf {
+    log.with_extension("stamps")
+}
+
+/// When a script log line was written and to which stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LogStamp<'a> {
+    pub at_ms: u64,
+    pub stream: &'a str,
+}
+
+impl<'a> LogStamp<'a> {
+    pub fn parse(line: &'a str) -> Option<Self> {
+        let (at, stream) = line.trim_end().split_once('\t')?;
+        Some(Self {
+            at_ms: at.parse().ok()?,
+            stream: matches!(stream, "stdout" | "stderr").then_some(stream)?,
+        })
+    }
 }
 
 struct ScriptLogger {
     file: std::fs::File,
-    path: std::path::PathBuf,
+    stamps: Option<std::fs::File>,
+    path: PathBuf,
 }
 
 impl ScriptLogger {
@@ -107,12 +182,21 @@ impl ScriptLogger {
         let filename = format!("{}_{}_running.log", utc_timestamp(), script_name);
         let path = logs_dir.join(filename);
         let file = std::fs::File::create(&path)?;
-        Ok(Self { file, path })
+        let stamps = std::fs::File::create(stamps_path(&path)).ok();
+        Ok(Self { file, stamps, path })
     }
 
-    fn write_line(&mut self, line: &str) {
+    fn write_line(&mut self, stream: &str, line: &str) {
         use std::io::Write;
 
+        // The stamp goes first, so a reader that sees the line finds it.
+        if let Some(stamps) = &mut self.stamps {
+            let at_ms = SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .map(|d| d.as_millis())
+                .unwrap_or_default();
+            let _ = writeln!(stamps, "{at_ms}\t{stream}");
+        }
         let _ = writeln!(self.file, "{line}");
     }
 
@@ -124,6 +208,9 @@ impl ScriptLogger {
         let new_path = self
             .path
             .with_file_name(name.replace("_running.log", &format!("_{suffix}.log")));
+        if self.stamps.is_some() {
+            let _ = std::fs::rename(stamps_path(&self.path), stamps_path(&new_path));
+        }
         let _ = std::fs::rename(&self.path, new_path);
     }
 }
@@ -148,6 +235,7 @@ fn rotate_logs(logs_dir: &Path, script_name: &str, keep: usize) {
     if matching.len() > keep {
         for old in &matching[..matching.len() - keep] {
             let _ = std::fs::remove_file(old);
+            let _ = std::fs::remove_file(stamps_path(old));
         }
     }
 }
@@ -177,3 +265,58 @@ fn utc_timestamp() -> String {
 
     format!("{year:04}-{month:02}-{day:02}T{hours:02}-{minutes:02}-{seconds:02}")
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn script_logs_keep_plain_lines_and_stamp_them_aside() {
+        let dir = std::env::temp_dir().join(format!("rum-script-log-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let mut logger = ScriptLogger::new(&dir, "setup").unwrap();
+        logger.write_line("stdout", "installing\tgit");
+        logger.write_line("stderr", "warning: slow mirror");
+        let running = logger.path.clone();
+        logger.finish(false);
+
+        let finished = running.with_file_name(
+            running
+                .file_name()
+                .unwrap()
+                .to_str()
+                .unwrap()
+                .replace("_running.log", "_failed.log"),
+        );
+        assert!(!running.exists() && !stamps_path(&running).exists());
+        let content = std::fs::read_to_string(&finished).unwrap();
+        assert_eq!(content, "installing\tgit\nwarning: slow mirror\n");
+
+        let stamps = std::fs::read_to_string(stamps_path(&finished)).unwrap();
+        let streams: Vec<_> = stamps
+            .lines()
+            .map(|line| LogStamp::parse(line).unwrap().stream)
+            .collect();
+        assert_eq!(streams, ["stdout", "stderr"]);
+
+        rotate_logs(&dir, "setup", 0);
+        assert!(!finished.exists() && !stamps_path(&finished).exists());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn log_stamps_parse_time_and_stream() {
+        assert_eq!(
+            LogStamp::parse("1700000000000\tstderr\n"),
+            Some(LogStamp {
+                at_ms: 1_700_000_000_000,
+                stream: "stderr",
+            })
+        );
+        assert_eq!(LogStamp::parse("1700000000000\tstdin"), None);
+        assert_eq!(LogStamp::parse("soon\tstdout"), None);
+        assert_eq!(LogStamp::parse("plain old line"), None);
+        assert_eq!(LogStamp::parse(""), None);
+    }
+}
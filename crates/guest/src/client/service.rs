@@ -0,0 +1,34 @@
+use crate::agent::{ServiceAction, ServiceActionResult, ServiceInfo};
+
+use super::{Client, ClientError};
+
+impl<C> Client<C>
+where
+    C: roam_stream::Connector,
+{
+    /// Run `systemctl <action> <name>` in the guest.
+    pub async fn service_action(
+        &self,
+        name: &str,
+        action: ServiceAction,
+    ) -> Result<ServiceActionResult, ClientError> {
+        self.rpc()
+            .service_action(name.to_string(), action)
+            .await
+            .map_err(|message| ClientError::Rpc {
+                context: format!("systemctl {} {name}", action.verb()),
+                message: message.to_string(),
+            })
+    }
+
+    /// Every service unit known to the guest's systemd.
+    pub async fn list_services(&self) -> Result<Vec<ServiceInfo>, ClientError> {
+        self.rpc()
+            .list_services()
+            .await
+            .map_err(|message| ClientError::Rpc {
+                context: "list_services RPC failed".into(),
+                message: message.to_string(),
+            })
+    }
+}
@@ -0,0 +1,120 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::agent::TreeEntry;
+
+use super::{Client, ClientError};
+
+/// Summary of one `sync_to_guest` pass.
+#[derive(Debug, Clone, Default)]
+pub struct SyncStats {
+    pub transferred: usize,
+    pub unchanged: usize,
+    pub bytes: u64,
+}
+
+impl<C> Client<C>
+where
+    C: roam_stream::Connector,
+{
+    /// Upload only the files under `local_root` whose content differs from
+    /// the matching path under `guest_root`.
+    ///
+    /// Size mismatches are copied straight away; equal sizes fall back to a
+    /// SHA-256 comparison since guest and host clocks cannot be trusted to
+    /// agree on mtimes. Extra guest files are left in place.
+    pub async fn sync_to_guest(
+        &self,
+        local_root: &Path,
+        guest_root: &str,
+    ) -> Result<SyncStats, ClientError> {
+        let local_owned = local_root.to_path_buf();
+        let local_entries = tokio::task::spawn_blocking(move || crate::tree::walk(&local_owned))
+            .await
+            .map_err(|e| sync_failed(format!("walk task: {e}")))?
+            .map_err(|e| sync_failed(format!("{}: {e}", local_root.display())))?;
+
+        let guest_entries = self
+            .rpc()
+            .stat_tree(guest_root.to_string())
+            .await
+            .map_err(|message| sync_failed(format!("stat_tree RPC: {message}")))?;
+        let guest_sizes: HashMap<String, u64> = guest_entries
+            .into_iter()
+            .filter(|entry| !entry.is_dir)
+            .map(|entry| (entry.path, entry.size))
+            .collect();
+
+        let mut stats = SyncStats::default();
+        for entry in local_entries.iter().filter(|entry| !entry.is_dir) {
+            let local_path = local_root.join(&entry.path);
+            let guest_path = join_guest(guest_root, &entry.path);
+
+            let changed = match guest_sizes.get(&entry.path) {
+                None => true,
+                Some(size) if *size != entry.size => true,
+                Some(_) => self.content_differs(&local_path, &guest_path).await?,
+            };
+
+            if changed {
+                stats.bytes += self.copy_to_guest(&local_path, &guest_path).await?;
+                stats.transferred += 1;
+            } else {
+                stats.unchanged += 1;
+            }
+        }
+
+        Ok(stats)
+    }
+
+    /// List every file and directory below `root` in the guest.
+    pub async fn stat_tree(&self, root: &str) -> Result<Vec<TreeEntry>, ClientError> {
+        self.rpc()
+            .stat_tree(root.to_string())
+            .await
+            .map_err(|message| sync_failed(format!("stat_tree RPC: {message}")))
+    }
+
+    /// SHA-256 of a guest file, or `None` when nothing exists at `path`.
+    pub async fn file_hash(&self, path: &str) -> Result<Option<String>, ClientError> {
+        match self.rpc().hash_file(path.to_string()).await {
+            Ok(hash) => Ok(Some(hash)),
+            // Listing a missing path yields nothing, while listing a file
+            // fails, so an empty listing tells "gone" apart from other errors.
+            Err(message) => match self.rpc().stat_tree(path.to_string()).await {
+                Ok(entries) if entries.is_empty() => Ok(None),
+                _ => Err(sync_failed(format!("hash_file RPC: {message}"))),
+            },
+        }
+    }
+
+    /// Remove a guest file or directory tree.
+    pub async fn remove_path(&self, path: &str) -> Result<(), ClientError> {
+        self.rpc()
+            .remove_path(path.to_string())
+            .await
+            .map_err(|message| sync_failed(format!("remove_path RPC: {message}")))
+    }
+
+    async fn content_differs(&self, local: &Path, guest_path: &str) -> Result<bool, ClientError> {
+        let local_owned: PathBuf = local.to_path_buf();
+        let local_hash = tokio::task::spawn_blocking(move || crate::tree::hash_file(&local_owned))
+            .await
+            .map_err(|e| sync_failed(format!("hash task: {e}")))?
+            .map_err(|e| sync_failed(format!("{}: {e}", local.display())))?;
+        let guest_hash = self
+            .rpc()
+            .hash_file(guest_path.to_string())
+            .await
+            .map_err(|message| sync_failed(format!("hash_file RPC: {message}")))?;
+        Ok(local_hash != guest_hash)
+    }
+}
+
+fn join_guest(root: &str, relative: &str) -> String {
+    format!("{}/{relative}", root.trim_end_matches('/'))
+}
+
+fn sync_failed(message: String) -> ClientError {
+    ClientError::CopyFailed { message }
+}
@@ -2,7 +2,7 @@ use std::time::Duration;
 
 use roam_stream::{Client as StreamClient, Connector, HandshakeConfig, NoDispatcher, connect};
 
-use crate::agent::{AgentClient as RpcAgentClient, ReadyResponse};
+use crate::agent::{AgentClient as RpcAgentClient, Heartbeat, PROTOCOL_VERSION, ReadyResponse};
 
 use super::ClientError;
 
@@ -28,31 +28,90 @@ impl<C: Connector> Client<C> {
         &self.rpc
     }
 
+    /// Ping the agent until it answers, then check that it speaks the same
+    /// protocol as this host.
     pub async fn wait_ready(&self) -> Result<ReadyResponse, ClientError> {
-        let deadline = tokio::time::Instant::now() + Duration::from_secs(AGENT_TIMEOUT_SECS);
+        self.wait_ready_within(Some(Duration::from_secs(AGENT_TIMEOUT_SECS)))
+            .await
+    }
+
+    /// Like [`Client::wait_ready`], giving up after `timeout`, or never
+    /// with `None`.
+    pub async fn wait_ready_within(
+        &self,
+        timeout: Option<Duration>,
+    ) -> Result<ReadyResponse, ClientError> {
+        let deadline = timeout.map(|timeout| tokio::time::Instant::now() + timeout);
 
         loop {
-            match self.rpc.ping().await {
-                Ok(resp) => {
-                    tracing::debug!(
-                        version = %resp.version,
-                        hostname = %resp.hostname,
-                        "agent ready"
-                    );
-                    return Ok(resp);
-                }
-                Err(_) if tokio::time::Instant::now() < deadline => {
-                    tokio::time::sleep(Duration::from_millis(AGENT_RETRY_INTERVAL_MS)).await;
-                }
-                Err(e) => {
-                    return Err(ClientError::AgentTimeout {
-                        timeout_secs: AGENT_TIMEOUT_SECS,
-                        message: e.to_string(),
-                    });
+            let error = match self.rpc.protocol_version().await {
+                Ok(protocol) => {
+                    // The rest of the agent's answers may not decode when
+                    // its protocol differs, so it is checked first.
+                    check_protocol(protocol, None)?;
+                    match self.rpc.ping().await {
+                        Ok(resp) => return Ok(ready(resp)),
+                        Err(e) => e.to_string(),
+                    }
                 }
+                // Agents before `protocol_version` report it in `ping`.
+                Err(e) => match self.rpc.ping().await {
+                    Ok(resp) => {
+                        check_protocol(resp.protocol, Some(&resp.version))?;
+                        return Ok(ready(resp));
+                    }
+                    Err(_) => e.to_string(),
+                },
+            };
+            if deadline.is_some_and(|d| tokio::time::Instant::now() >= d) {
+                return Err(ClientError::AgentTimeout {
+                    timeout_secs: timeout.unwrap_or_default().as_secs(),
+                    message: error,
+                });
             }
+            tokio::time::sleep(Duration::from_millis(AGENT_RETRY_INTERVAL_MS)).await;
         }
     }
+
+    /// Ask the agent whether it is still alive, without retrying.
+    pub async fn heartbeat(&self) -> Result<Heartbeat, ClientError> {
+        self.rpc
+            .heartbeat()
+            .await
+            .map_err(|message| ClientError::Rpc {
+                context: "heartbeat RPC failed".into(),
+                message: message.to_string(),
+            })
+    }
+}
+
+fn ready(resp: ReadyResponse) -> ReadyResponse {
+    tracing::debug!(
+        version = %resp.version,
+        hostname = %resp.hostname,
+        protocol = resp.protocol,
+        "agent ready"
+    );
+    resp
+}
+
+/// Compare the agent's `protocol` with this host's; `version` is the
+/// agent's release where known.
+fn check_protocol(protocol: u32, version: Option<&str>) -> Result<(), ClientError> {
+    let host = env!("CARGO_PKG_VERSION");
+    let agent = version.map_or_else(|| "agent".to_string(), |v| format!("agent v{v}"));
+    let message = match protocol.cmp(&PROTOCOL_VERSION) {
+        std::cmp::Ordering::Equal => return Ok(()),
+        std::cmp::Ordering::Less => format!(
+            "{agent} in guest is older than host rum v{host} (protocol {protocol} < \
+             {PROTOCOL_VERSION}) — run `rum agent update`"
+        ),
+        std::cmp::Ordering::Greater => format!(
+            "{agent} in guest is newer than host rum v{host} (protocol {protocol} > \
+             {PROTOCOL_VERSION}) — update rum on the host"
+        ),
+    };
+    Err(ClientError::IncompatibleAgent { message })
 }
 
 pub async fn wait_for_agent<C: Connector>(connector: C) -> Result<Client<C>, ClientError> {
@@ -60,3 +119,51 @@ pub async fn wait_for_agent<C: Connector>(connector: C) -> Result<Client<C>, Cli
     client.wait_ready().await?;
     Ok(client)
 }
+
+/// Like [`wait_for_agent`], giving up after `timeout`, or never with `None`.
+pub async fn wait_for_agent_within<C: Connector>(
+    connector: C,
+    timeout: Option<Duration>,
+) -> Result<Client<C>, ClientError> {
+    let client = Client::connect(connector);
+    client.wait_ready_within(timeout).await?;
+    Ok(client)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mismatch(protocol: u32, version: Option<&str>) -> String {
+        match check_protocol(protocol, version) {
+            Err(ClientError::IncompatibleAgent { message }) => message,
+            other => panic!("expected a protocol mismatch, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn matching_protocols_pass() {
+        check_protocol(PROTOCOL_VERSION, None).unwrap();
+        check_protocol(PROTOCOL_VERSION, Some("0.1.0")).unwrap();
+    }
+
+    #[test]
+    fn an_old_agent_is_told_to_update() {
+        // Old agents only answer `ping`, so their release is known.
+        let message = mismatch(PROTOCOL_VERSION - 1, Some("0.0.9"));
+        assert!(
+            message.starts_with("agent v0.0.9 in guest is older"),
+            "{message}"
+        );
+        assert!(message.contains("rum agent update"), "{message}");
+        let message = mismatch(0, Some("0.0.1"));
+        assert!(message.contains("protocol 0 <"), "{message}");
+    }
+
+    #[test]
+    fn a_newer_agent_is_rejected_from_its_protocol_alone() {
+        let message = mismatch(PROTOCOL_VERSION + 1, None);
+        assert!(message.starts_with("agent in guest is newer"), "{message}");
+        assert!(message.contains("update rum on the host"), "{message}");
+    }
+}
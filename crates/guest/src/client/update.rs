@@ -0,0 +1,42 @@
+use crate::agent::FileChunk;
+
+use super::{Client, ClientError};
+
+const CHUNK_SIZE: usize = 1024 * 1024;
+
+impl<C> Client<C>
+where
+    C: roam_stream::Connector,
+{
+    /// Replace the guest's agent with `binary`. The agent re-execs into it
+    /// right after answering, which ends this client's connection.
+    pub async fn update_agent(&self, binary: &[u8]) -> Result<(), ClientError> {
+        let sha256 = crate::tree::hash_bytes(binary);
+        let chunks: Vec<Vec<u8>> = binary.chunks(CHUNK_SIZE).map(<[u8]>::to_vec).collect();
+        let (tx, rx) = roam::channel::<FileChunk>();
+        let send_task = tokio::spawn(async move {
+            for data in chunks {
+                let chunk = FileChunk {
+                    data,
+                    compressed: false,
+                };
+                if tx.send(&chunk).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        let result = self
+            .rpc()
+            .update_agent(sha256, rx)
+            .await
+            .map_err(|message| ClientError::Rpc {
+                context: "update_agent RPC failed (agents from before self-update cannot \
+                          replace themselves; recreate the machine instead)"
+                    .into(),
+                message: message.to_string(),
+            });
+        let _ = send_task.await;
+        result
+    }
+}
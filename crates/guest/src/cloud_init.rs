@@ -0,0 +1,98 @@
+//! How cloud-init's first-boot run ended, read from the files it leaves.
+
+use facet::Facet;
+
+/// Written by cloud-init once its last stage finished.
+pub const RESULT_PATH: &str = "/run/cloud-init/result.json";
+
+/// Output of the user-data modules, where failing `runcmd` lines end up.
+pub const OUTPUT_LOG_PATH: &str = "/var/log/cloud-init-output.log";
+
+/// Lines of [`OUTPUT_LOG_PATH`] sent along with a failure.
+pub const EXCERPT_LINES: usize = 20;
+
+#[derive(Facet)]
+struct ResultFile {
+    v1: ResultV1,
+}
+
+#[derive(Facet)]
+struct ResultV1 {
+    #[facet(default)]
+    errors: Vec<String>,
+}
+
+/// The errors recorded in the contents of [`RESULT_PATH`].
+pub fn result_errors(json: &str) -> Result<Vec<String>, String> {
+    facet_json::from_str::<ResultFile>(json)
+        .map(|result| result.v1.errors)
+        .map_err(|e| format!("parse {RESULT_PATH}: {e}"))
+}
+
+/// The last `lines` lines of `log`.
+pub fn excerpt(log: &str, lines: usize) -> Vec<String> {
+    let all: Vec<&str> = log.lines().collect();
+    let skip = all.len().saturating_sub(lines);
+    all[skip..].iter().map(|line| line.to_string()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_clean_run_has_no_errors() {
+        let json = r#"{
+ "v1": {
+  "datasource": "DataSourceNoCloud [seed=/dev/sr0][dsmode=net]",
+  "errors": [],
+  "recoverable_errors": {
+   "WARNING": ["Running scripts-user is deprecated"]
+  }
+ }
+}"#;
+        assert_eq!(result_errors(json).unwrap(), Vec::<String>::new());
+    }
+
+    #[test]
+    fn failed_modules_are_reported() {
+        let json = r#"{
+ "v1": {
+  "datasource": null,
+  "errors": [
+   "('scripts_user', RuntimeError('Runparts: 1 failures (runcmd) in 1 attempted commands'))",
+   "('package_update_upgrade_install', ...)"
+  ]
+ }
+}"#;
+        assert_eq!(
+            result_errors(json).unwrap(),
+            [
+                "('scripts_user', RuntimeError('Runparts: 1 failures (runcmd) in 1 attempted commands'))",
+                "('package_update_upgrade_install', ...)",
+            ]
+        );
+    }
+
+    #[test]
+    fn results_without_an_errors_list_count_as_clean() {
+        assert!(result_errors(r#"{"v1": {}}"#).unwrap().is_empty());
+    }
+
+    #[test]
+    fn unreadable_results_name_the_file() {
+        for json in ["", "{\"v1\": ", "{\"errors\": []}"] {
+            let err = result_errors(json).unwrap_err();
+            assert!(err.starts_with(&format!("parse {RESULT_PATH}")), "{err}");
+        }
+    }
+
+    #[test]
+    fn excerpt_keeps_the_last_lines() {
+        let log = "one\ntwo\nthree\nfour\n";
+        assert_eq!(excerpt(log, 2), ["three", "four"]);
+        assert_eq!(excerpt(log, 10), ["one", "two", "three", "four"]);
+        assert!(excerpt(log, 0).is_empty());
+        assert!(excerpt("", 5).is_empty());
+    }
+}
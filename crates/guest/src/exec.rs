@@ -0,0 +1,159 @@
+//! Guest-side end of the `exec` RPC: one shell command, its output streamed
+//! to the host line by line.
+
+use roam::Tx;
+use tokio::io::{AsyncBufReadExt, BufReader};
+
+use guest::agent::{ExecOptions, ExecResult, LogEvent, LogLevel, LogStream};
+
+use crate::executions::Scope;
+use crate::now_us;
+
+/// Build the `sh -c` command for `content`, wrapped in `runuser` when the
+/// caller asked for a specific user.
+fn shell_command(content: &str, options: &ExecOptions) -> tokio::process::Command {
+    let mut cmd = match &options.user {
+        Some(user) => {
+            let mut cmd = tokio::process::Command::new("runuser");
+            cmd.args(["-u", user, "--", "sh", "-c", content]);
+            cmd
+        }
+        None => {
+            let mut cmd = tokio::process::Command::new("sh");
+            cmd.arg("-c").arg(content);
+            cmd
+        }
+    };
+    if let Some(cwd) = &options.cwd {
+        cmd.current_dir(cwd);
+    }
+    cmd.envs(options.env.iter().map(|v| (&v.name, &v.value)));
+    // Own process group so a timeout can take down everything the shell spawned.
+    cmd.process_group(0);
+    cmd
+}
+
+/// Run `content` as `options` ask, streaming its output to `output` as
+/// `name`.
+pub async fn run_script(
+    content: &str,
+    name: &str,
+    options: &ExecOptions,
+    output: &Tx<LogEvent>,
+    scope: &Scope,
+) -> ExecResult {
+    let child = shell_command(content, options)
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn();
+
+    let mut child = match child {
+        Ok(c) => c,
+        Err(e) => {
+            let _ = output
+                .send(&LogEvent {
+                    timestamp_us: now_us(),
+                    level: LogLevel::Error,
+                    target: name.into(),
+                    message: format!("failed to spawn: {e}"),
+                    stream: LogStream::Stderr,
+                })
+                .await;
+            return ExecResult {
+                exit_code: None,
+                timed_out: false,
+            };
+        }
+    };
+
+    let stdout = child.stdout.take().unwrap();
+    let stderr = child.stderr.take().unwrap();
+    let process = scope.spawned(child);
+
+    let mut stdout_lines = BufReader::new(stdout).lines();
+    let mut stderr_lines = BufReader::new(stderr).lines();
+
+    let stream_output = async {
+        loop {
+            tokio::select! {
+                line = stdout_lines.next_line() => {
+                    match line {
+                        Ok(Some(text)) => {
+                            let _ = output.send(&LogEvent {
+                                timestamp_us: now_us(),
+                                level: LogLevel::Info,
+                                target: name.into(),
+                                message: text,
+                                stream: LogStream::Stdout,
+                            }).await;
+                        }
+                        Ok(None) => break,
+                        Err(_) => break,
+                    }
+                }
+                line = stderr_lines.next_line() => {
+                    match line {
+                        Ok(Some(text)) => {
+                            let _ = output.send(&LogEvent {
+                                timestamp_us: now_us(),
+                                level: LogLevel::Warn,
+                                target: name.into(),
+                                message: text,
+                                stream: LogStream::Stderr,
+                            }).await;
+                        }
+                        Ok(None) => break,
+                        Err(_) => break,
+                    }
+                }
+            }
+        }
+        process.wait().await
+    };
+
+    let status = match options.timeout_s {
+        Some(secs) => {
+            let outcome =
+                tokio::time::timeout(std::time::Duration::from_secs(secs), stream_output).await;
+            match outcome {
+                Ok(status) => status,
+                Err(_) => {
+                    process.signal("KILL").await;
+                    let _ = process.wait().await;
+                    scope.exited(&process);
+                    let _ = output
+                        .send(&LogEvent {
+                            timestamp_us: now_us(),
+                            level: LogLevel::Error,
+                            target: name.into(),
+                            message: format!("command timed out after {secs}s"),
+                            stream: LogStream::Stderr,
+                        })
+                        .await;
+                    return ExecResult {
+                        exit_code: None,
+                        timed_out: true,
+                    };
+                }
+            }
+        }
+        None => stream_output.await,
+    };
+    scope.exited(&process);
+
+    if scope.is_cancelled() {
+        let _ = output
+            .send(&LogEvent {
+                timestamp_us: now_us(),
+                level: LogLevel::Error,
+                target: name.into(),
+                message: "command cancelled".into(),
+                stream: LogStream::Stderr,
+            })
+            .await;
+    }
+    ExecResult {
+        exit_code: status.and_then(|s| s.code()),
+        timed_out: false,
+    }
+}
@@ -0,0 +1,221 @@
+//! Commands the host may cancel, by the execution id it picked.
+//!
+//! Every exec or provisioning call that carries an id opens a [`Scope`] for
+//! as long as it runs, and each command it spawns is tracked by the handle
+//! of the process group leader. `cancel` signals those groups, so children
+//! the command forked go down with it.
+
+use std::collections::HashMap;
+use std::process::ExitStatus;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// How long cancelled commands get to exit after SIGTERM before they are
+/// killed.
+const CANCEL_GRACE: Duration = Duration::from_secs(5);
+
+/// How often [`Process::wait`] checks whether the child has exited.
+const EXIT_POLL: Duration = Duration::from_millis(50);
+
+#[derive(Default)]
+struct Execution {
+    /// Leaders of the process groups still running.
+    groups: Vec<Process>,
+    cancelled: bool,
+}
+
+/// A spawned process group leader, shared by the call running it and
+/// `cancel`. It is only signalled through this handle while the child is
+/// unreaped, so its pid cannot have been reused by another process.
+#[derive(Clone)]
+pub struct Process(Arc<tokio::sync::Mutex<tokio::process::Child>>);
+
+impl Process {
+    /// Send `signal` to the group, unless the leader was already reaped.
+    pub async fn signal(&self, signal: &str) {
+        let child = self.0.lock().await;
+        if let Some(pid) = child.id() {
+            signal_process_group(pid, signal).await;
+        }
+    }
+
+    /// Wait for the leader to exit. The handle is only locked between polls,
+    /// so it can be signalled in the meantime.
+    pub async fn wait(&self) -> Option<ExitStatus> {
+        loop {
+            match self.0.lock().await.try_wait() {
+                Ok(Some(status)) => return Some(status),
+                Ok(None) => {}
+                Err(_) => return None,
+            }
+            tokio::time::sleep(EXIT_POLL).await;
+        }
+    }
+
+    fn is(&self, other: &Process) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+/// Executions in flight, shared by every RPC session.
+#[derive(Clone, Default)]
+pub struct Executions(Arc<Mutex<HashMap<String, Execution>>>);
+
+impl Executions {
+    /// Open the scope of one exec or provisioning call. Without an id the
+    /// call cannot be cancelled and the scope tracks nothing.
+    pub fn scope(&self, id: Option<String>) -> Scope {
+        if let Some(id) = &id {
+            self.lock().insert(id.clone(), Execution::default());
+        }
+        Scope {
+            executions: self.clone(),
+            id,
+        }
+    }
+
+    /// Stop everything running under `id`. Returns `false` if nothing is.
+    pub async fn cancel(&self, id: &str) -> bool {
+        let groups = {
+            let mut executions = self.lock();
+            let Some(execution) = executions.get_mut(id) else {
+                return false;
+            };
+            execution.cancelled = true;
+            execution.groups.clone()
+        };
+        for process in groups {
+            process.signal("TERM").await;
+        }
+
+        let deadline = tokio::time::Instant::now() + CANCEL_GRACE;
+        while tokio::time::Instant::now() < deadline && !self.groups(id).is_empty() {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        for process in self.groups(id) {
+            process.signal("KILL").await;
+        }
+        true
+    }
+
+    fn groups(&self, id: &str) -> Vec<Process> {
+        self.lock()
+            .get(id)
+            .map(|execution| execution.groups.clone())
+            .unwrap_or_default()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, Execution>> {
+        self.0.lock().expect("executions lock poisoned")
+    }
+}
+
+/// One exec or provisioning call, registered until dropped.
+pub struct Scope {
+    executions: Executions,
+    id: Option<String>,
+}
+
+impl Scope {
+    /// Whether the host cancelled this call.
+    pub fn is_cancelled(&self) -> bool {
+        self.with_execution(|execution| execution.cancelled)
+            .unwrap_or(false)
+    }
+
+    /// Take over `child`, which leads its own process group, and track it
+    /// until [`Scope::exited`].
+    pub fn spawned(&self, child: tokio::process::Child) -> Process {
+        let process = Process(Arc::new(tokio::sync::Mutex::new(child)));
+        self.with_execution(|execution| execution.groups.push(process.clone()));
+        process
+    }
+
+    pub fn exited(&self, process: &Process) {
+        self.with_execution(|execution| execution.groups.retain(|group| !group.is(process)));
+    }
+
+    fn with_execution<T>(&self, f: impl FnOnce(&mut Execution) -> T) -> Option<T> {
+        let id = self.id.as_ref()?;
+        self.executions.lock().get_mut(id).map(f)
+    }
+}
+
+impl Drop for Scope {
+    fn drop(&mut self) {
+        if let Some(id) = &self.id {
+            self.executions.lock().remove(id);
+        }
+    }
+}
+
+/// Send `signal` (e.g. `KILL`) to every process in the group led by `pid`.
+async fn signal_process_group(pid: u32, signal: &str) {
+    let _ = tokio::process::Command::new("kill")
+        .args([&format!("-{signal}"), "--", &format!("-{pid}")])
+        .status()
+        .await;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::process::ExitStatusExt;
+
+    use super::*;
+
+    fn spawn(script: &str) -> tokio::process::Child {
+        tokio::process::Command::new("sh")
+            .args(["-c", script])
+            .process_group(0)
+            .spawn()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn cancel_terminates_the_running_group() {
+        let executions = Executions::default();
+        let scope = executions.scope(Some("exec-1".into()));
+        let process = scope.spawned(spawn("sleep 30"));
+
+        let waiter = tokio::spawn({
+            let process = process.clone();
+            async move { process.wait().await }
+        });
+        assert!(executions.cancel("exec-1").await);
+        let status = waiter.await.unwrap().unwrap();
+        assert_eq!(status.signal(), Some(15));
+        assert!(scope.is_cancelled());
+    }
+
+    #[tokio::test]
+    async fn reaped_processes_are_neither_tracked_nor_signalled() {
+        let executions = Executions::default();
+        let scope = executions.scope(Some("exec-1".into()));
+        let process = scope.spawned(spawn("exit 3"));
+
+        assert_eq!(process.wait().await.unwrap().code(), Some(3));
+        assert!(process.0.lock().await.id().is_none());
+        scope.exited(&process);
+        assert!(executions.groups("exec-1").is_empty());
+        // Nothing left to signal, so this returns without waiting out the grace.
+        let started = std::time::Instant::now();
+        assert!(executions.cancel("exec-1").await);
+        assert!(started.elapsed() < CANCEL_GRACE);
+    }
+
+    #[tokio::test]
+    async fn only_open_scopes_can_be_cancelled() {
+        let executions = Executions::default();
+        assert!(!executions.cancel("missing").await);
+
+        let anonymous = executions.scope(None);
+        let process = anonymous.spawned(spawn("exit 0"));
+        assert!(executions.lock().is_empty());
+        assert!(!anonymous.is_cancelled());
+        process.wait().await;
+
+        let scope = executions.scope(Some("exec-1".into()));
+        drop(scope);
+        assert!(!executions.cancel("exec-1").await);
+    }
+}
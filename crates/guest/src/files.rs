@@ -0,0 +1,189 @@
+//! Guest-side end of the file transfer RPCs used by `rum cp` and sync.
+//!
+//! Uploads land in a partial file next to their destination and are only
+//! renamed into place once complete and checked, so an interrupted upload
+//! can resume where it stopped.
+
+use std::path::Path;
+
+use roam::{Rx, Tx};
+use tokio::io::{AsyncReadExt, AsyncWriteExt, BufReader};
+
+use guest::agent::{FileChunk, ReadFileResult, ResumePoint, WriteFileInfo, WriteFileResult};
+use guest::transfer;
+
+/// Receive `data` into the file `info` describes.
+pub async fn write(
+    info: WriteFileInfo,
+    mut data: Rx<FileChunk>,
+) -> Result<WriteFileResult, String> {
+    use std::os::unix::fs::PermissionsExt;
+    use tokio::io::BufWriter;
+
+    let final_path = transfer::destination(Path::new(&info.path), &info.filename);
+    let part = transfer::partial_path(&final_path);
+
+    // Create parent directories
+    if let Some(parent) = final_path.parent() {
+        tokio::fs::create_dir_all(parent)
+            .await
+            .map_err(|e| format!("create dirs: {e}"))?;
+    }
+
+    let file = transfer::open_partial(&part, info.offset)
+        .await
+        .map_err(|e| format!("open {}: {e}", part.display()))?;
+    let mut writer = BufWriter::new(file);
+    let mut bytes_written: u64 = 0;
+
+    while let Ok(Some(chunk)) = data.recv().await {
+        let bytes = transfer::decode_chunk(chunk).map_err(|e| format!("decompress: {e}"))?;
+        writer
+            .write_all(&bytes)
+            .await
+            .map_err(|e| format!("write: {e}"))?;
+        bytes_written += bytes.len() as u64;
+    }
+
+    writer.flush().await.map_err(|e| format!("flush: {e}"))?;
+
+    // A short file stays behind as the partial for a resumed transfer.
+    let total = info.offset + bytes_written;
+    if total != info.size {
+        return Err(format!(
+            "transfer interrupted after {total} of {} bytes",
+            info.size
+        ));
+    }
+
+    let hashed = part.clone();
+    let sha256 = tokio::task::spawn_blocking(move || guest::tree::hash_file(&hashed))
+        .await
+        .map_err(|e| format!("hash task: {e}"))?
+        .map_err(|e| format!("hash {}: {e}", part.display()))?;
+    if let Some(expected) = &info.sha256
+        && *expected != sha256
+    {
+        let _ = tokio::fs::remove_file(&part).await;
+        return Err(format!(
+            "checksum mismatch: got {sha256}, expected {expected}"
+        ));
+    }
+
+    // Set permissions
+    tokio::fs::set_permissions(&part, std::fs::Permissions::from_mode(info.mode))
+        .await
+        .map_err(|e| format!("chmod: {e}"))?;
+    tokio::fs::rename(&part, &final_path)
+        .await
+        .map_err(|e| format!("replace {}: {e}", final_path.display()))?;
+
+    tracing::info!(
+        path = %final_path.display(),
+        bytes = bytes_written,
+        offset = info.offset,
+        "write_file complete"
+    );
+
+    Ok(WriteFileResult {
+        bytes_written,
+        sha256,
+    })
+}
+
+/// Where an interrupted upload of `filename` to `path` can resume.
+pub async fn partial_upload(path: &str, filename: &str) -> Result<ResumePoint, String> {
+    let part = transfer::partial_path(&transfer::destination(Path::new(path), filename));
+    let offset = match tokio::fs::metadata(&part).await {
+        Ok(metadata) => metadata.len(),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => 0,
+        Err(e) => return Err(format!("metadata {}: {e}", part.display())),
+    };
+    if offset == 0 {
+        return Ok(ResumePoint {
+            offset,
+            sha256: String::new(),
+        });
+    }
+    let sha256 = tokio::task::spawn_blocking(move || guest::tree::hash_file(&part))
+        .await
+        .map_err(|e| format!("hash task: {e}"))?
+        .map_err(|e| format!("hash partial upload: {e}"))?;
+    Ok(ResumePoint { offset, sha256 })
+}
+
+/// Send `path` to the host from `offset` on.
+pub async fn read(
+    path: &str,
+    offset: u64,
+    compress: bool,
+    output: Tx<FileChunk>,
+) -> Result<ReadFileResult, String> {
+    use std::os::unix::fs::PermissionsExt;
+    use tokio::io::AsyncSeekExt;
+
+    let file_path = Path::new(path);
+
+    if !file_path.exists() {
+        return Err(format!("file not found: {path}"));
+    }
+    if file_path.is_dir() {
+        return Err(format!("path is a directory: {path}"));
+    }
+
+    let metadata = tokio::fs::metadata(file_path)
+        .await
+        .map_err(|e| format!("metadata: {e}"))?;
+    let mode = metadata.permissions().mode();
+    let size = metadata.len();
+    if offset > size {
+        return Err(format!("cannot resume at byte {offset}: {path} has {size}"));
+    }
+
+    let mut file = tokio::fs::File::open(file_path)
+        .await
+        .map_err(|e| format!("open: {e}"))?;
+    file.seek(std::io::SeekFrom::Start(offset))
+        .await
+        .map_err(|e| format!("seek: {e}"))?;
+    let mut reader = BufReader::new(file);
+    let mut buf = vec![0u8; transfer::CHUNK_SIZE];
+
+    loop {
+        let n = reader
+            .read(&mut buf)
+            .await
+            .map_err(|e| format!("read: {e}"))?;
+        if n == 0 {
+            break;
+        }
+        let chunk = transfer::encode_chunk(&buf[..n], compress);
+        if output.send(&chunk).await.is_err() {
+            break; // client disconnected
+        }
+    }
+
+    let hashed = file_path.to_path_buf();
+    let sha256 = tokio::task::spawn_blocking(move || guest::tree::hash_file(&hashed))
+        .await
+        .map_err(|e| format!("hash task: {e}"))?
+        .map_err(|e| format!("hash {path}: {e}"))?;
+
+    tracing::info!(path, size, offset, "read_file complete");
+
+    Ok(ReadFileResult { mode, size, sha256 })
+}
+
+/// Remove the file or directory tree at `path`; a missing one is fine.
+pub async fn remove(path: &str) -> Result<(), String> {
+    let result = match tokio::fs::symlink_metadata(path).await {
+        Ok(metadata) if metadata.is_dir() => tokio::fs::remove_dir_all(path).await,
+        Ok(_) => tokio::fs::remove_file(path).await,
+        Err(e) => Err(e),
+    };
+    match result {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(format!("remove {path}: {e}")),
+    }
+}
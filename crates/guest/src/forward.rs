@@ -0,0 +1,87 @@
+//! Port forwarding and dialing over the vsock forward port.
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio_vsock::VsockStream;
+
+/// Handle a single port-forwarding connection over vsock.
+///
+/// Protocol: the first 2 bytes are a big-endian u16 target port.
+/// After that, bidirectional byte proxying to 127.0.0.1:port.
+///
+/// Target port 0 asks to dial an arbitrary host instead: a u16 length, the
+/// host name, and the u16 port follow, and one status byte (0 = connected)
+/// is written back before proxying starts. A host starting with `/` is the
+/// path of a unix socket, and its port is ignored.
+pub async fn handle_forward(mut vsock: VsockStream) {
+    let target_port = match vsock.read_u16().await {
+        Ok(p) => p,
+        Err(e) => {
+            tracing::error!(error = %e, "forward: failed to read target port");
+            return;
+        }
+    };
+    if target_port == 0 {
+        handle_dial(vsock).await;
+        return;
+    }
+
+    let mut tcp = match TcpStream::connect(("127.0.0.1", target_port)).await {
+        Ok(s) => s,
+        Err(e) => {
+            tracing::debug!(port = target_port, error = %e, "forward: failed to connect");
+            return;
+        }
+    };
+
+    if let Err(e) = tokio::io::copy_bidirectional(&mut vsock, &mut tcp).await {
+        tracing::debug!(port = target_port, error = %e, "forward: proxy error");
+    }
+}
+
+async fn handle_dial(mut vsock: VsockStream) {
+    let target = async {
+        let len = vsock.read_u16().await?;
+        let mut host = vec![0u8; len as usize];
+        vsock.read_exact(&mut host).await?;
+        let port = vsock.read_u16().await?;
+        Ok::<_, std::io::Error>((String::from_utf8_lossy(&host).into_owned(), port))
+    };
+    let (host, port) = match target.await {
+        Ok(target) => target,
+        Err(e) => {
+            tracing::error!(error = %e, "dial: failed to read target");
+            return;
+        }
+    };
+
+    if host.starts_with('/') {
+        let unix = tokio::net::UnixStream::connect(&host).await;
+        proxy_dialed(vsock, unix, &host).await;
+    } else {
+        let tcp = TcpStream::connect((host.as_str(), port)).await;
+        proxy_dialed(vsock, tcp, &format!("{host}:{port}")).await;
+    }
+}
+
+/// Answer a dial with its status byte, then proxy the stream it opened.
+async fn proxy_dialed<S>(mut vsock: VsockStream, stream: std::io::Result<S>, dest: &str)
+where
+    S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin,
+{
+    let mut stream = match stream {
+        Ok(s) => s,
+        Err(e) => {
+            tracing::debug!(dest, error = %e, "dial: failed to connect");
+            let _ = vsock.write_u8(1).await;
+            return;
+        }
+    };
+    if vsock.write_u8(0).await.is_err() {
+        return;
+    }
+
+    if let Err(e) = tokio::io::copy_bidirectional(&mut vsock, &mut stream).await {
+        tracing::debug!(dest, error = %e, "dial: proxy error");
+    }
+}
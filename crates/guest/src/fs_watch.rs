@@ -0,0 +1,28 @@
+//! Guest-side end of the `subscribe_fs_events` RPC.
+
+use roam::Tx;
+use tokio::sync::mpsc;
+
+use guest::agent::{FsEvent, FsWatchOptions};
+
+/// Stream filesystem events below `paths` until the subscriber disconnects.
+pub async fn watch(
+    paths: Vec<String>,
+    options: FsWatchOptions,
+    output: Tx<FsEvent>,
+) -> std::io::Result<()> {
+    let (events, mut received) = mpsc::unbounded_channel();
+    let watcher = guest::watch::watch(paths, options, events);
+    tokio::pin!(watcher);
+
+    loop {
+        tokio::select! {
+            result = &mut watcher => return result,
+            Some(event) = received.recv() => {
+                if output.send(&event).await.is_err() {
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,112 @@
+//! The block of peer records rum keeps in `/etc/hosts`.
+//!
+//! Everything between the two markers belongs to rum and is rewritten as a
+//! whole; the rest of the file is left as it is.
+
+use crate::agent::PeerHost;
+
+pub const HOSTS_PATH: &str = "/etc/hosts";
+
+const BEGIN_MARKER: &str = "# BEGIN rum peers";
+const END_MARKER: &str = "# END rum peers";
+
+/// `hosts` with its rum block replaced by one listing `peers`, appended at
+/// the end if it had none. An empty `peers` drops the block.
+pub fn with_peers(hosts: &str, peers: &[PeerHost]) -> String {
+    let mut lines = Vec::new();
+    let mut in_block = false;
+    let mut block_at = None;
+    for line in hosts.lines() {
+        match line.trim() {
+            BEGIN_MARKER => {
+                in_block = true;
+                block_at.get_or_insert(lines.len());
+            }
+            END_MARKER if in_block => in_block = false,
+            _ if in_block => {}
+            _ => lines.push(line.to_string()),
+        }
+    }
+
+    if !peers.is_empty() {
+        let mut block = vec![BEGIN_MARKER.to_string()];
+        block.extend(
+            peers
+                .iter()
+                .map(|peer| format!("{} {}", peer.ip, peer.names.join(" "))),
+        );
+        block.push(END_MARKER.to_string());
+        let at = block_at.unwrap_or(lines.len());
+        lines.splice(at..at, block);
+    }
+
+    let mut out = lines.join("\n");
+    out.push('\n');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn peer(ip: &str, names: &[&str]) -> PeerHost {
+        PeerHost {
+            ip: ip.into(),
+            names: names.iter().map(|name| name.to_string()).collect(),
+        }
+    }
+
+    const HOSTS: &str = "127.0.0.1 localhost\n::1 localhost\n";
+
+    #[test]
+    fn appends_a_block_when_there_is_none() {
+        let hosts = with_peers(HOSTS, &[peer("192.168.50.10", &["db.lab", "db"])]);
+        assert_eq!(
+            hosts,
+            "127.0.0.1 localhost\n::1 localhost\n\
+             # BEGIN rum peers\n192.168.50.10 db.lab db\n# END rum peers\n"
+        );
+    }
+
+    #[test]
+    fn replaces_the_block_in_place() {
+        let hosts = "127.0.0.1 localhost\n\
+                     # BEGIN rum peers\n192.168.50.10 old\n# END rum peers\n\
+                     10.0.0.1 gateway\n";
+        let hosts = with_peers(
+            hosts,
+            &[
+                peer("192.168.50.11", &["web"]),
+                peer("192.168.50.12", &["api"]),
+            ],
+        );
+        assert_eq!(
+            hosts,
+            "127.0.0.1 localhost\n\
+             # BEGIN rum peers\n192.168.50.11 web\n192.168.50.12 api\n# END rum peers\n\
+             10.0.0.1 gateway\n"
+        );
+        assert_eq!(
+            with_peers(&hosts, &[peer("192.168.50.11", &["web"])])
+                .matches(BEGIN_MARKER)
+                .count(),
+            1
+        );
+    }
+
+    #[test]
+    fn no_peers_drop_the_block() {
+        let hosts = with_peers(HOSTS, &[peer("192.168.50.10", &["db"])]);
+        assert_eq!(with_peers(&hosts, &[]), HOSTS);
+        assert_eq!(with_peers(HOSTS, &[]), HOSTS);
+    }
+
+    #[test]
+    fn an_unterminated_block_runs_to_the_end() {
+        let hosts = "127.0.0.1 localhost\n# BEGIN rum peers\n192.168.50.10 stale\n";
+        assert_eq!(
+            with_peers(hosts, &[peer("192.168.50.11", &["web"])]),
+            "127.0.0.1 localhost\n# BEGIN rum peers\n192.168.50.11 web\n# END rum peers\n"
+        );
+    }
+}
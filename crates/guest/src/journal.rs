@@ -0,0 +1,148 @@
+//! Guest-side end of the `stream_journal` RPC.
+//!
+//! Guests with systemd are read through `journalctl`. Minimal images without
+//! journald fall back to tailing the syslog file, which cannot be filtered
+//! by unit.
+
+use std::path::Path;
+
+use roam::Tx;
+use tokio::io::{AsyncBufReadExt, BufReader};
+
+use guest::agent::{JournalFilter, LogEvent, LogLevel, LogStream};
+
+/// Present while journald runs.
+const JOURNALD_RUNTIME_DIR: &str = "/run/systemd/journal";
+/// Where syslog daemons write, Debian's name first.
+const SYSLOG_FILES: [&str; 2] = ["/var/log/syslog", "/var/log/messages"];
+
+/// Send the guest log lines `filter` asks for to `output`, following it
+/// until the host disconnects when `filter.follow` is set.
+pub async fn stream(filter: &JournalFilter, output: &Tx<LogEvent>) -> Result<(), String> {
+    let journald = Path::new(JOURNALD_RUNTIME_DIR).exists();
+    let syslog = SYSLOG_FILES
+        .into_iter()
+        .find(|path| Path::new(path).exists());
+    let mut command = journal_command(filter, journald, syslog)?;
+    let mut child = command
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::null())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| format!("failed to read the guest log: {e}"))?;
+
+    let mut lines = BufReader::new(child.stdout.take().unwrap()).lines();
+    while let Ok(Some(text)) = lines.next_line().await {
+        let event = LogEvent {
+            timestamp_us: crate::now_us(),
+            level: LogLevel::Info,
+            target: "journal".into(),
+            message: text,
+            stream: LogStream::Log,
+        };
+        if output.send(&event).await.is_err() {
+            // The host went away; dropping the child stops the tail.
+            return Ok(());
+        }
+    }
+
+    match child.wait().await {
+        Ok(status) if status.success() => Ok(()),
+        Ok(status) => Err(format!("reading the guest log exited with {status}")),
+        Err(e) => Err(format!("reading the guest log: {e}")),
+    }
+}
+
+/// `journalctl` for `filter` when the guest runs `journald`, or `tail` on
+/// the `syslog` file otherwise.
+fn journal_command(
+    filter: &JournalFilter,
+    journald: bool,
+    syslog: Option<&str>,
+) -> Result<tokio::process::Command, String> {
+    let lines = filter.lines.to_string();
+    if journald {
+        let mut command = tokio::process::Command::new("journalctl");
+        command.args(["--no-pager", "--output=short-iso", "--lines", &lines]);
+        if let Some(unit) = &filter.unit {
+            command.arg(format!("--unit={unit}"));
+        }
+        if filter.follow {
+            command.arg("--follow");
+        }
+        return Ok(command);
+    }
+
+    if filter.unit.is_some() {
+        return Err("the guest has no journald, so logs cannot be filtered by unit".into());
+    }
+    let file =
+        syslog.ok_or("the guest has neither journald nor /var/log/syslog or /var/log/messages")?;
+    let mut command = tokio::process::Command::new("tail");
+    command.args(["-n", &lines]);
+    if filter.follow {
+        command.arg("-F");
+    }
+    command.arg(file);
+    Ok(command)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(unit: Option<&str>, follow: bool) -> JournalFilter {
+        JournalFilter {
+            unit: unit.map(Into::into),
+            lines: 20,
+            follow,
+        }
+    }
+
+    fn argv(command: &tokio::process::Command) -> Vec<String> {
+        let command = command.as_std();
+        std::iter::once(command.get_program())
+            .chain(command.get_args())
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn journald_is_read_through_journalctl() {
+        let command = journal_command(&filter(Some("nginx.service"), true), true, None).unwrap();
+        assert_eq!(
+            argv(&command),
+            [
+                "journalctl",
+                "--no-pager",
+                "--output=short-iso",
+                "--lines",
+                "20",
+                "--unit=nginx.service",
+                "--follow"
+            ]
+        );
+    }
+
+    #[test]
+    fn without_journald_the_syslog_file_is_tailed() {
+        let command = journal_command(&filter(None, true), false, Some("/var/log/messages"));
+        assert_eq!(
+            argv(&command.unwrap()),
+            ["tail", "-n", "20", "-F", "/var/log/messages"]
+        );
+    }
+
+    #[test]
+    fn unit_filter_needs_journald() {
+        let unit = filter(Some("nginx.service"), false);
+        let err = journal_command(&unit, false, Some("/var/log/syslog")).unwrap_err();
+        assert!(err.contains("cannot be filtered by unit"), "{err}");
+    }
+
+    #[test]
+    fn guest_without_any_log_is_an_error() {
+        let err = journal_command(&filter(None, false), false, None).unwrap_err();
+        assert!(err.contains("neither journald"), "{err}");
+    }
+}
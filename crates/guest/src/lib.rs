@@ -1,4 +1,10 @@
 pub mod agent;
 pub mod client;
+pub mod cloud_init;
+pub mod hosts;
+pub mod schedule;
+pub mod transfer;
+pub mod tree;
+pub mod watch;
 
 pub use agent::*;
@@ -1,33 +1,45 @@
+mod boot;
+mod exec;
+mod executions;
+mod files;
+mod forward;
+mod fs_watch;
+mod journal;
 mod log_layer;
+mod mounts;
+mod network;
+mod nixos;
+mod provision;
+mod service;
+mod transport;
+mod update;
 
+use std::path::Path;
 use std::time::{SystemTime, UNIX_EPOCH};
 
 use roam::{Rx, Tx};
-use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
-use tokio::net::TcpStream;
+use roam_stream::{HandshakeConfig, accept};
 use tokio::signal::unix::{SignalKind, signal};
 use tokio::sync::broadcast;
-use tokio_vsock::{VMADDR_CID_ANY, VsockAddr, VsockListener};
 use tracing_subscriber::layer::SubscriberExt;
 use tracing_subscriber::util::SubscriberInitExt;
 
-use roam_stream::{HandshakeConfig, accept};
+use executions::Executions;
 use guest::agent::{
-    ExecResult, FileChunk, LogEvent, LogLevel, LogStream, ProvisionEvent, ProvisionResult,
-    ProvisionScript, ReadFileResult, RunOn, Agent, AgentDispatcher, WriteFileInfo,
+    Agent, AgentDispatcher, BootScriptResult, CloudInitReport, ExecOptions, ExecResult, FileChunk,
+    FsEvent, FsWatchOptions, GuestInterface, JournalFilter, LogEvent, MountCheck, MountReport,
+    PeerHost, ProvisionEvent, ProvisionMode, ProvisionResult, ProvisionScript, ReadFileResult,
+    ResumePoint, ServiceAction, ServiceActionResult, ServiceInfo, TreeEntry, WriteFileInfo,
     WriteFileResult,
 };
-
-use std::path::Path;
-
-const RPC_PORT: u32 = 2222;
-const FORWARD_PORT: u32 = 2223;
-const SCRIPTS_DIR: &str = "/var/lib/rum/scripts";
-const SENTINEL_PATH: &str = "/var/lib/rum/.system-provisioned";
+use guest::cloud_init;
+use transport::{FORWARD_PORT, RPC_PORT};
 
 #[derive(Clone)]
 struct AgentService {
     log_tx: broadcast::Sender<LogEvent>,
+    executions: Executions,
+    boot_report: std::sync::Arc<Vec<BootScriptResult>>,
 }
 
 impl Agent for AgentService {
@@ -40,9 +52,71 @@ impl Agent for AgentService {
         Ok(guest::agent::ReadyResponse {
             version: env!("CARGO_PKG_VERSION").into(),
             hostname,
+            protocol: guest::agent::PROTOCOL_VERSION,
+        })
+    }
+
+    async fn protocol_version(&self, _cx: &roam::Context) -> u32 {
+        guest::agent::PROTOCOL_VERSION
+    }
+
+    async fn heartbeat(&self, _cx: &roam::Context) -> Result<guest::agent::Heartbeat, String> {
+        let uptime = tokio::fs::read_to_string("/proc/uptime")
+            .await
+            .map_err(|e| format!("read /proc/uptime: {e}"))?;
+        let seconds: f64 = uptime
+            .split_whitespace()
+            .next()
+            .and_then(|field| field.parse().ok())
+            .ok_or_else(|| format!("unexpected /proc/uptime: {uptime}"))?;
+        Ok(guest::agent::Heartbeat {
+            uptime_ms: (seconds * 1000.0) as u64,
+        })
+    }
+
+    async fn cloud_init_status(&self, _cx: &roam::Context) -> Result<CloudInitReport, String> {
+        let not_run = CloudInitReport {
+            ran: false,
+            errors: Vec::new(),
+            log_excerpt: Vec::new(),
+        };
+        // The exit code of `--wait` differs between cloud-init releases, so
+        // only the result file it waited for counts.
+        let waited = tokio::process::Command::new("cloud-init")
+            .args(["status", "--wait"])
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::null())
+            .status()
+            .await;
+        if waited.is_err() {
+            return Ok(not_run);
+        }
+
+        let json = match tokio::fs::read_to_string(cloud_init::RESULT_PATH).await {
+            Ok(json) => json,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(not_run),
+            Err(e) => return Err(format!("read {}: {e}", cloud_init::RESULT_PATH)),
+        };
+        let errors = cloud_init::result_errors(&json)?;
+        let log_excerpt = if errors.is_empty() {
+            Vec::new()
+        } else {
+            tokio::fs::read_to_string(cloud_init::OUTPUT_LOG_PATH)
+                .await
+                .map(|log| cloud_init::excerpt(&log, cloud_init::EXCERPT_LINES))
+                .unwrap_or_default()
+        };
+        Ok(CloudInitReport {
+            ran: true,
+            errors,
+            log_excerpt,
         })
     }
 
+    async fn boot_report(&self, _cx: &roam::Context) -> Vec<BootScriptResult> {
+        self.boot_report.to_vec()
+    }
+
     async fn subscribe_logs(&self, _cx: &roam::Context, output: Tx<LogEvent>) {
         let mut rx = self.log_tx.subscribe();
         loop {
@@ -64,398 +138,161 @@ impl Agent for AgentService {
         &self,
         _cx: &roam::Context,
         command: String,
+        options: ExecOptions,
         output: Tx<LogEvent>,
     ) -> ExecResult {
-        tracing::info!(command, "exec");
-        run_script(&command, "exec", &output).await
+        tracing::info!(command, user = ?options.user, cwd = ?options.cwd, "exec");
+        let scope = self.executions.scope(options.execution_id.clone());
+        exec::run_script(&command, "exec", &options, &output, &scope).await
     }
 
     async fn provision(
         &self,
         _cx: &roam::Context,
         scripts: Vec<ProvisionScript>,
+        execution_id: Option<String>,
+        mode: ProvisionMode,
         output: Tx<ProvisionEvent>,
     ) -> ProvisionResult {
-        tracing::info!(count = scripts.len(), "provision");
-
-        // Create scripts dir, clear old scripts
-        let scripts_dir = Path::new(SCRIPTS_DIR);
-        if let Err(e) = tokio::fs::create_dir_all(scripts_dir).await {
-            tracing::error!(error = %e, "failed to create scripts dir");
-            return ProvisionResult {
-                success: false,
-                failed_script: "(setup)".into(),
-            };
-        }
-        if let Ok(mut entries) = tokio::fs::read_dir(scripts_dir).await {
-            while let Ok(Some(entry)) = entries.next_entry().await {
-                let _ = tokio::fs::remove_file(entry.path()).await;
-            }
-        }
-
-        // Write all scripts to disk
-        for s in &scripts {
-            let suffix = match s.run_on {
-                RunOn::System => "system",
-                RunOn::Boot => "boot",
-            };
-            let filename = format!("{:03}-{}.{suffix}.sh", s.order, s.name);
-            let path = scripts_dir.join(&filename);
-            if let Err(e) = tokio::fs::write(&path, &s.content).await {
-                tracing::error!(error = %e, filename, "failed to write script");
-                return ProvisionResult {
-                    success: false,
-                    failed_script: s.name.clone(),
-                };
-            }
-        }
-
-        // Run all received scripts in order — the host controls what to send
-        let mut sorted: Vec<&ProvisionScript> = scripts.iter().collect();
-        sorted.sort_by_key(|s| s.order);
-
-        for s in &sorted {
-            tracing::info!(script = %s.name, "running provision script");
+        tracing::info!(count = scripts.len(), ?mode, "provision");
+        let scope = self.executions.scope(execution_id);
+        provision::run(scripts, mode, &output, &scope).await
+    }
 
-            let exit_code = run_provision_script(&s.content, &output)
-                .await
-                .unwrap_or(-1);
-            let _ = output.send(&ProvisionEvent::Done(exit_code)).await;
-
-            if exit_code != 0 {
-                tracing::error!(script = %s.name, exit_code, "script failed");
-                return ProvisionResult {
-                    success: false,
-                    failed_script: s.name.clone(),
-                };
-            }
-        }
+    async fn verify_mounts(&self, _cx: &roam::Context, mounts: Vec<MountCheck>) -> MountReport {
+        tracing::info!(count = mounts.len(), "verify_mounts");
+        mounts::verify(mounts).await
+    }
 
-        // Create sentinel on success so auto-boot scripts know system was provisioned
-        if let Some(parent) = Path::new(SENTINEL_PATH).parent() {
-            let _ = tokio::fs::create_dir_all(parent).await;
-        }
-        if let Err(e) = tokio::fs::write(SENTINEL_PATH, "").await {
-            tracing::error!(error = %e, "failed to create sentinel");
-        }
+    async fn interfaces(&self, _cx: &roam::Context) -> Vec<GuestInterface> {
+        network::interfaces().await
+    }
 
-        ProvisionResult {
-            success: true,
-            failed_script: String::new(),
-        }
+    async fn set_peer_hosts(
+        &self,
+        _cx: &roam::Context,
+        peers: Vec<PeerHost>,
+    ) -> Result<(), String> {
+        network::set_peer_hosts(&peers).await
     }
 
     async fn write_file(
         &self,
         _cx: &roam::Context,
         info: WriteFileInfo,
-        mut data: Rx<FileChunk>,
+        data: Rx<FileChunk>,
     ) -> Result<WriteFileResult, String> {
-        use std::os::unix::fs::PermissionsExt;
-        use tokio::io::BufWriter;
+        files::write(info, data).await
+    }
 
-        let dest = Path::new(&info.path);
-        let final_path = if dest.is_dir() {
-            dest.join(&info.filename)
-        } else {
-            dest.to_path_buf()
-        };
+    async fn partial_upload(
+        &self,
+        _cx: &roam::Context,
+        path: String,
+        filename: String,
+    ) -> Result<ResumePoint, String> {
+        files::partial_upload(&path, &filename).await
+    }
 
-        // Create parent directories
-        if let Some(parent) = final_path.parent() {
-            tokio::fs::create_dir_all(parent)
-                .await
-                .map_err(|e| format!("create dirs: {e}"))?;
+    async fn subscribe_fs_events(
+        &self,
+        _cx: &roam::Context,
+        paths: Vec<String>,
+        options: FsWatchOptions,
+        output: Tx<FsEvent>,
+    ) {
+        tracing::info!(?paths, ?options, "subscribe_fs_events");
+        if let Err(e) = fs_watch::watch(paths, options, output).await {
+            tracing::error!(error = %e, "fs watch failed");
         }
+    }
 
-        let file = tokio::fs::File::create(&final_path)
+    async fn stat_tree(
+        &self,
+        _cx: &roam::Context,
+        root: String,
+    ) -> Result<Vec<TreeEntry>, String> {
+        let root_path = std::path::PathBuf::from(&root);
+        let entries = tokio::task::spawn_blocking(move || guest::tree::walk(&root_path))
             .await
-            .map_err(|e| format!("create file: {e}"))?;
-        let mut writer = BufWriter::new(file);
-        let mut bytes_written: u64 = 0;
+            .map_err(|e| format!("walk task: {e}"))?
+            .map_err(|e| format!("walk {root}: {e}"))?;
 
-        while let Ok(Some(chunk)) = data.recv().await {
-            writer
-                .write_all(&chunk.data)
-                .await
-                .map_err(|e| format!("write: {e}"))?;
-            bytes_written += chunk.data.len() as u64;
-        }
-
-        writer.flush().await.map_err(|e| format!("flush: {e}"))?;
-
-        // Set permissions
-        tokio::fs::set_permissions(
-            &final_path,
-            std::fs::Permissions::from_mode(info.mode),
-        )
-        .await
-        .map_err(|e| format!("chmod: {e}"))?;
-
-        tracing::info!(
-            path = %final_path.display(),
-            bytes = bytes_written,
-            expected = info.size,
-            "write_file complete"
-        );
+        tracing::info!(root, count = entries.len(), "stat_tree complete");
+        Ok(entries)
+    }
 
-        Ok(WriteFileResult { bytes_written })
+    async fn hash_file(&self, _cx: &roam::Context, path: String) -> Result<String, String> {
+        let file_path = std::path::PathBuf::from(&path);
+        tokio::task::spawn_blocking(move || guest::tree::hash_file(&file_path))
+            .await
+            .map_err(|e| format!("hash task: {e}"))?
+            .map_err(|e| format!("hash {path}: {e}"))
     }
 
-    async fn read_file(
+    async fn hash_file_prefix(
         &self,
         _cx: &roam::Context,
         path: String,
-        output: Tx<FileChunk>,
-    ) -> Result<ReadFileResult, String> {
-        use std::os::unix::fs::PermissionsExt;
-
-        let file_path = Path::new(&path);
-
-        if !file_path.exists() {
-            return Err(format!("file not found: {path}"));
-        }
-        if file_path.is_dir() {
-            return Err(format!("path is a directory: {path}"));
-        }
-
-        let metadata = tokio::fs::metadata(file_path)
-            .await
-            .map_err(|e| format!("metadata: {e}"))?;
-        let mode = metadata.permissions().mode();
-        let size = metadata.len();
-
-        let file = tokio::fs::File::open(file_path)
+        len: u64,
+    ) -> Result<String, String> {
+        let file_path = std::path::PathBuf::from(&path);
+        tokio::task::spawn_blocking(move || guest::tree::hash_file_prefix(&file_path, len))
             .await
-            .map_err(|e| format!("open: {e}"))?;
-        let mut reader = BufReader::new(file);
-
-        const CHUNK_SIZE: usize = 10 * 1024 * 1024; // 10 MB
-        let mut buf = vec![0u8; CHUNK_SIZE];
-
-        loop {
-            let n = reader.read(&mut buf).await.map_err(|e| format!("read: {e}"))?;
-            if n == 0 {
-                break;
-            }
-            let chunk = FileChunk {
-                data: buf[..n].to_vec(),
-            };
-            if output.send(&chunk).await.is_err() {
-                break; // client disconnected
-            }
-        }
-
-        tracing::info!(path, size, "read_file complete");
-
-        Ok(ReadFileResult { mode, size })
+            .map_err(|e| format!("hash task: {e}"))?
+            .map_err(|e| format!("hash {path}: {e}"))
     }
-}
-
-async fn run_script(content: &str, name: &str, output: &Tx<LogEvent>) -> ExecResult {
-    let child = tokio::process::Command::new("sh")
-        .arg("-c")
-        .arg(content)
-        .stdout(std::process::Stdio::piped())
-        .stderr(std::process::Stdio::piped())
-        .spawn();
-
-    let mut child = match child {
-        Ok(c) => c,
-        Err(e) => {
-            let _ = output
-                .send(&LogEvent {
-                    timestamp_us: now_us(),
-                    level: LogLevel::Error,
-                    target: name.into(),
-                    message: format!("failed to spawn: {e}"),
-                    stream: LogStream::Stderr,
-                })
-                .await;
-            return ExecResult { exit_code: None };
-        }
-    };
-
-    let stdout = child.stdout.take().unwrap();
-    let stderr = child.stderr.take().unwrap();
 
-    let mut stdout_lines = BufReader::new(stdout).lines();
-    let mut stderr_lines = BufReader::new(stderr).lines();
-
-    loop {
-        tokio::select! {
-            line = stdout_lines.next_line() => {
-                match line {
-                    Ok(Some(text)) => {
-                        let _ = output.send(&LogEvent {
-                            timestamp_us: now_us(),
-                            level: LogLevel::Info,
-                            target: name.into(),
-                            message: text,
-                            stream: LogStream::Stdout,
-                        }).await;
-                    }
-                    Ok(None) => break,
-                    Err(_) => break,
-                }
-            }
-            line = stderr_lines.next_line() => {
-                match line {
-                    Ok(Some(text)) => {
-                        let _ = output.send(&LogEvent {
-                            timestamp_us: now_us(),
-                            level: LogLevel::Warn,
-                            target: name.into(),
-                            message: text,
-                            stream: LogStream::Stderr,
-                        }).await;
-                    }
-                    Ok(None) => break,
-                    Err(_) => break,
-                }
-            }
-        }
+    async fn remove_path(&self, _cx: &roam::Context, path: String) -> Result<(), String> {
+        files::remove(&path).await
     }
 
-    let status = child.wait().await.ok();
-    ExecResult {
-        exit_code: status.and_then(|s| s.code()),
+    async fn read_file(
+        &self,
+        _cx: &roam::Context,
+        path: String,
+        offset: u64,
+        compress: bool,
+        output: Tx<FileChunk>,
+    ) -> Result<ReadFileResult, String> {
+        files::read(&path, offset, compress, output).await
     }
-}
-
-async fn run_provision_script(content: &str, output: &Tx<ProvisionEvent>) -> Option<i32> {
-    let child = tokio::process::Command::new("sh")
-        .arg("-c")
-        .arg(content)
-        .stdout(std::process::Stdio::piped())
-        .stderr(std::process::Stdio::piped())
-        .spawn();
-
-    let mut child = match child {
-        Ok(c) => c,
-        Err(e) => {
-            let _ = output
-                .send(&ProvisionEvent::Stderr(format!("failed to spawn: {e}")))
-                .await;
-            return None;
-        }
-    };
 
-    let stdout = child.stdout.take().unwrap();
-    let stderr = child.stderr.take().unwrap();
-
-    let mut stdout_lines = BufReader::new(stdout).lines();
-    let mut stderr_lines = BufReader::new(stderr).lines();
-
-    loop {
-        tokio::select! {
-            line = stdout_lines.next_line() => {
-                match line {
-                    Ok(Some(text)) => {
-                        let _ = output.send(&ProvisionEvent::Stdout(text)).await;
-                    }
-                    Ok(None) => break,
-                    Err(_) => break,
-                }
-            }
-            line = stderr_lines.next_line() => {
-                match line {
-                    Ok(Some(text)) => {
-                        let _ = output.send(&ProvisionEvent::Stderr(text)).await;
-                    }
-                    Ok(None) => break,
-                    Err(_) => break,
-                }
-            }
-        }
+    async fn service_action(
+        &self,
+        _cx: &roam::Context,
+        name: String,
+        action: ServiceAction,
+    ) -> Result<ServiceActionResult, String> {
+        service::run(&name, action).await
     }
 
-    let status = child.wait().await.ok()?;
-    status.code()
-}
-
-/// Handle a single port-forwarding connection over vsock.
-///
-/// Protocol: the first 2 bytes are a big-endian u16 target port.
-/// After that, bidirectional byte proxying to 127.0.0.1:port.
-async fn handle_forward(mut vsock: tokio_vsock::VsockStream) {
-    let target_port = match vsock.read_u16().await {
-        Ok(p) => p,
-        Err(e) => {
-            tracing::error!(error = %e, "forward: failed to read target port");
-            return;
-        }
-    };
-
-    let mut tcp = match TcpStream::connect(("127.0.0.1", target_port)).await {
-        Ok(s) => s,
-        Err(e) => {
-            tracing::debug!(port = target_port, error = %e, "forward: failed to connect");
-            return;
-        }
-    };
-
-    if let Err(e) = tokio::io::copy_bidirectional(&mut vsock, &mut tcp).await {
-        tracing::debug!(port = target_port, error = %e, "forward: proxy error");
+    async fn list_services(&self, _cx: &roam::Context) -> Result<Vec<ServiceInfo>, String> {
+        service::list().await
     }
-}
-
-async fn run_cached_boot_scripts() {
-    let scripts_dir = Path::new(SCRIPTS_DIR);
-    let mut entries = match tokio::fs::read_dir(scripts_dir).await {
-        Ok(e) => e,
-        Err(e) => {
-            tracing::error!(error = %e, "failed to read scripts dir");
-            return;
-        }
-    };
 
-    let mut boot_scripts = Vec::new();
-    while let Ok(Some(entry)) = entries.next_entry().await {
-        let name = entry.file_name();
-        let name_str = name.to_string_lossy();
-        if name_str.ends_with(".boot.sh") {
-            boot_scripts.push(entry.path());
-        }
+    async fn stream_journal(
+        &self,
+        _cx: &roam::Context,
+        filter: JournalFilter,
+        output: Tx<LogEvent>,
+    ) -> Result<(), String> {
+        tracing::info!(?filter, "stream_journal");
+        journal::stream(&filter, &output).await
     }
-    boot_scripts.sort();
 
-    if boot_scripts.is_empty() {
-        return;
+    async fn update_agent(
+        &self,
+        _cx: &roam::Context,
+        sha256: String,
+        data: Rx<FileChunk>,
+    ) -> Result<(), String> {
+        update::update_agent(sha256, data).await
     }
 
-    tracing::info!(count = boot_scripts.len(), "running cached boot scripts");
-    for path in &boot_scripts {
-        let filename = path.file_name().unwrap_or_default().to_string_lossy();
-        tracing::info!(script = %filename, "executing boot script");
-
-        let content = match tokio::fs::read_to_string(path).await {
-            Ok(c) => c,
-            Err(e) => {
-                tracing::error!(script = %filename, error = %e, "failed to read script");
-                break;
-            }
-        };
-
-        let status = tokio::process::Command::new("sh")
-            .arg("-c")
-            .arg(&content)
-            .status()
-            .await;
-
-        match status {
-            Ok(s) if s.success() => {
-                tracing::info!(script = %filename, "boot script completed");
-            }
-            Ok(s) => {
-                tracing::error!(script = %filename, exit_code = ?s.code(), "boot script failed");
-                break;
-            }
-            Err(e) => {
-                tracing::error!(script = %filename, error = %e, "failed to spawn boot script");
-                break;
-            }
-        }
+    async fn cancel(&self, _cx: &roam::Context, execution_id: String) -> bool {
+        tracing::info!(execution_id, "cancel");
+        self.executions.cancel(&execution_id).await
     }
 }
 
@@ -478,26 +315,37 @@ async fn main() {
     let version = env!("CARGO_PKG_VERSION");
     tracing::info!(version, "rum-agent starting");
 
-    // Run cached boot scripts on reboot (sentinel exists = not first boot)
-    if Path::new(SENTINEL_PATH).exists() && Path::new(SCRIPTS_DIR).exists() {
-        run_cached_boot_scripts().await;
+    // Run cached boot scripts on reboot (sentinel exists = not first boot),
+    // but not again when the agent restarts or re-execs after an update.
+    let first_start = boot::first_start_since_boot(Path::new(boot::BOOTED_MARKER));
+    let mut boot_report = Vec::new();
+    if first_start
+        && Path::new(provision::SENTINEL_PATH).exists()
+        && Path::new(provision::SCRIPTS_DIR).exists()
+    {
+        boot_report = boot::run_cached_boot_scripts().await;
     }
 
-    let rpc_listener = VsockListener::bind(VsockAddr::new(VMADDR_CID_ANY, RPC_PORT))
-        .expect("failed to bind vsock RPC listener");
-    let fwd_listener = VsockListener::bind(VsockAddr::new(VMADDR_CID_ANY, FORWARD_PORT))
-        .expect("failed to bind vsock forward listener");
-
-    tracing::info!(rpc_port = RPC_PORT, fwd_port = FORWARD_PORT, "listening");
+    let rpc_listener = transport::bind_vsock(RPC_PORT);
+    let fwd_listener = transport::bind_vsock(FORWARD_PORT);
+    if rpc_listener.is_some() {
+        tracing::info!(rpc_port = RPC_PORT, fwd_port = FORWARD_PORT, "listening");
+    }
 
     let mut sigterm = signal(SignalKind::terminate()).expect("failed to register SIGTERM handler");
     let mut sigint = signal(SignalKind::interrupt()).expect("failed to register SIGINT handler");
 
-    let agent = AgentService { log_tx };
+    let agent = AgentService {
+        log_tx,
+        executions: Executions::default(),
+        boot_report: std::sync::Arc::new(boot_report),
+    };
+
+    tokio::spawn(transport::serve_agent_channel(agent.clone()));
 
     loop {
         tokio::select! {
-            result = rpc_listener.accept() => {
+            result = transport::accept_vsock(&rpc_listener) => {
                 match result {
                     Ok((stream, addr)) => {
                         tracing::info!(?addr, "RPC connection");
@@ -516,11 +364,11 @@ async fn main() {
                     Err(e) => tracing::error!(error = %e, "RPC accept error"),
                 }
             }
-            result = fwd_listener.accept() => {
+            result = transport::accept_vsock(&fwd_listener) => {
                 match result {
                     Ok((stream, addr)) => {
                         tracing::debug!(?addr, "forward connection");
-                        tokio::spawn(handle_forward(stream));
+                        tokio::spawn(forward::handle_forward(stream));
                     }
                     Err(e) => tracing::error!(error = %e, "forward accept error"),
                 }
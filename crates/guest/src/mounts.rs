@@ -0,0 +1,117 @@
+//! Guest-side end of the `verify_mounts` RPC.
+
+use guest::agent::{MountCheck, MountReport};
+
+/// Check that every expected mount is active, retrying `mount -a` once for
+/// those that are not.
+pub async fn verify(mounts: Vec<MountCheck>) -> MountReport {
+    let missing = missing_mounts(&mounts).await;
+    if missing.is_empty() {
+        return MountReport {
+            missing,
+            remounted: false,
+        };
+    }
+
+    // cloud-init writes the virtiofs entries to fstab, so a plain
+    // `mount -a` recovers mounts that raced the device showing up.
+    for m in &missing {
+        tracing::warn!(tag = %m.tag, target = %m.target, "mount missing, retrying mount -a");
+    }
+    match tokio::process::Command::new("mount")
+        .arg("-a")
+        .status()
+        .await
+    {
+        Ok(s) if !s.success() => tracing::warn!(exit_code = ?s.code(), "mount -a failed"),
+        Err(e) => tracing::warn!(error = %e, "failed to spawn mount -a"),
+        Ok(_) => {}
+    }
+
+    MountReport {
+        missing: missing_mounts(&mounts).await,
+        remounted: true,
+    }
+}
+
+/// Return the expected mounts that do not appear in `/proc/self/mounts`.
+async fn missing_mounts(mounts: &[MountCheck]) -> Vec<MountCheck> {
+    let table = tokio::fs::read_to_string("/proc/self/mounts")
+        .await
+        .unwrap_or_default();
+    missing_from(&table, mounts)
+}
+
+/// Return the expected mounts that have no entry in the mount `table`.
+///
+/// An entry only counts when both its source matches the virtiofs tag and it
+/// is mounted at the expected target.
+fn missing_from(table: &str, mounts: &[MountCheck]) -> Vec<MountCheck> {
+    let active: Vec<(&str, &str)> = table
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            Some((fields.next()?, fields.next()?))
+        })
+        .collect();
+
+    mounts
+        .iter()
+        .filter(|m| {
+            !active
+                .iter()
+                .any(|(source, target)| *source == m.tag && *target == m.target)
+        })
+        .cloned()
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TABLE: &str = "\
+proc /proc proc rw,nosuid,nodev,noexec,relatime 0 0
+/dev/vda1 / ext4 rw,relatime 0 0
+project /mnt/project virtiofs rw,relatime 0 0
+cache /var/cache/app virtiofs rw,relatime 0 0
+";
+
+    fn check(tag: &str, target: &str) -> MountCheck {
+        MountCheck {
+            tag: tag.into(),
+            target: target.into(),
+        }
+    }
+
+    fn tags(missing: &[MountCheck]) -> Vec<&str> {
+        missing.iter().map(|m| m.tag.as_str()).collect()
+    }
+
+    #[test]
+    fn active_mounts_are_not_missing() {
+        let mounts = [
+            check("project", "/mnt/project"),
+            check("cache", "/var/cache/app"),
+        ];
+        assert!(missing_from(TABLE, &mounts).is_empty());
+    }
+
+    #[test]
+    fn unknown_tag_is_missing() {
+        let mounts = [check("project", "/mnt/project"), check("data", "/mnt/data")];
+        assert_eq!(tags(&missing_from(TABLE, &mounts)), ["data"]);
+    }
+
+    #[test]
+    fn tag_mounted_elsewhere_is_missing() {
+        let mounts = [check("project", "/srv/project")];
+        assert_eq!(tags(&missing_from(TABLE, &mounts)), ["project"]);
+    }
+
+    #[test]
+    fn unreadable_table_reports_every_mount() {
+        let mounts = [check("project", "/mnt/project")];
+        assert_eq!(tags(&missing_from("", &mounts)), ["project"]);
+    }
+}
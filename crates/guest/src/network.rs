@@ -0,0 +1,72 @@
+//! The guest's view of the network: its interfaces for `rum status`, and
+//! the peer records the host keeps in `/etc/hosts`.
+
+use guest::agent::{GuestInterface, PeerHost};
+use guest::hosts;
+
+/// Non-loopback interfaces with their addresses, from `ip -o addr show`.
+pub async fn interfaces() -> Vec<GuestInterface> {
+    let output = match tokio::process::Command::new("ip")
+        .args(["-o", "addr", "show"])
+        .output()
+        .await
+    {
+        Ok(output) => String::from_utf8_lossy(&output.stdout).into_owned(),
+        Err(e) => {
+            tracing::warn!(error = %e, "failed to run ip addr");
+            return Vec::new();
+        }
+    };
+
+    // Lines look like `2: eth0    inet 10.0.2.15/24 brd ... scope global eth0`.
+    let mut interfaces: Vec<GuestInterface> = Vec::new();
+    for line in output.lines() {
+        let mut fields = line.split_whitespace().skip(1);
+        let (Some(name), Some(family), Some(addr)) = (fields.next(), fields.next(), fields.next())
+        else {
+            continue;
+        };
+        let name = name.split('@').next().unwrap_or(name);
+        if name == "lo" || !matches!(family, "inet" | "inet6") {
+            continue;
+        }
+        let addr = addr.split('/').next().unwrap_or(addr).to_string();
+        match interfaces.iter_mut().find(|i| i.name == name) {
+            Some(iface) => iface.addrs.push(addr),
+            None => {
+                let mac = tokio::fs::read_to_string(format!("/sys/class/net/{name}/address"))
+                    .await
+                    .unwrap_or_default();
+                interfaces.push(GuestInterface {
+                    name: name.to_string(),
+                    mac: mac.trim().to_lowercase(),
+                    addrs: vec![addr],
+                });
+            }
+        }
+    }
+    interfaces
+}
+
+/// Rewrite the rum block of `/etc/hosts` to list `peers`, leaving the file
+/// alone when nothing changed.
+pub async fn set_peer_hosts(peers: &[PeerHost]) -> Result<(), String> {
+    let current = match tokio::fs::read_to_string(hosts::HOSTS_PATH).await {
+        Ok(current) => current,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(format!("read {}: {e}", hosts::HOSTS_PATH)),
+    };
+    let updated = hosts::with_peers(&current, peers);
+    if updated == current {
+        return Ok(());
+    }
+    tracing::info!(peers = peers.len(), "updating peer hosts");
+    // Rename over the file so resolvers never read it half-written.
+    let tmp = format!("{}.rum-tmp", hosts::HOSTS_PATH);
+    tokio::fs::write(&tmp, updated)
+        .await
+        .map_err(|e| format!("write {tmp}: {e}"))?;
+    tokio::fs::rename(&tmp, hosts::HOSTS_PATH)
+        .await
+        .map_err(|e| format!("replace {}: {e}", hosts::HOSTS_PATH))
+}
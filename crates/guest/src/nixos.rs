@@ -0,0 +1,68 @@
+//! NixOS module steps: the host renders the module, the guest installs it
+//! and switches to the new generation.
+
+use std::path::Path;
+
+use guest::agent::ProvisionEvent;
+use guest::schedule::Events;
+
+use crate::executions::Scope;
+use crate::provision::stream_command;
+
+const MODULE_PATH: &str = "/etc/nixos/rum.nix";
+const CONFIG_PATH: &str = "/etc/nixos/configuration.nix";
+
+/// Install the host-generated module, import it from `configuration.nix` and
+/// switch to the new system generation.
+pub async fn apply_module(content: &str, output: &Events, scope: &Scope) -> Option<i32> {
+    if !Path::new("/etc/NIXOS").exists() {
+        let message = "guest is not NixOS; set provision.nixos = false".to_string();
+        let _ = output.send(ProvisionEvent::Stderr(message));
+        return Some(1);
+    }
+    if let Err(e) = install_module(content).await {
+        let message = format!("failed to install {MODULE_PATH}: {e}");
+        let _ = output.send(ProvisionEvent::Stderr(message));
+        return Some(1);
+    }
+
+    // Units started by systemd-run do not get the NixOS profile on PATH.
+    let path = std::env::var("PATH").unwrap_or_default();
+    let mut command = tokio::process::Command::new("nixos-rebuild");
+    command
+        .arg("switch")
+        .env("PATH", format!("/run/current-system/sw/bin:{path}"));
+    stream_command(command, output, scope).await
+}
+
+async fn install_module(content: &str) -> std::io::Result<()> {
+    tokio::fs::write(MODULE_PATH, content).await?;
+    let config = tokio::fs::read_to_string(CONFIG_PATH).await?;
+    if let Some(updated) = import_rum_module(&config) {
+        tokio::fs::write(CONFIG_PATH, updated).await?;
+    }
+    Ok(())
+}
+
+/// Add `./rum.nix` to the `imports` of `configuration.nix`. Returns `None`
+/// when it is already imported or the file has no attribute set to extend.
+fn import_rum_module(config: &str) -> Option<String> {
+    if config.contains("./rum.nix") {
+        return None;
+    }
+    if let Some(start) = config.find("imports")
+        && let Some(open) = config[start..].find('[')
+    {
+        let at = start + open + 1;
+        return Some(format!("{} ./rum.nix{}", &config[..at], &config[at..]));
+    }
+    // No imports yet: open a list at the top of the module body, which
+    // follows the `{ config, pkgs, ... }:` argument pattern.
+    let args_end = config.find("}:").map_or(0, |i| i + 2);
+    let body = args_end + config[args_end..].find('{')? + 1;
+    Some(format!(
+        "{}\n  imports = [ ./rum.nix ];{}",
+        &config[..body],
+        &config[body..]
+    ))
+}
@@ -0,0 +1,339 @@
+//! Guest-side end of the `provision` RPC.
+//!
+//! A plan's scripts are kept in [`SCRIPTS_DIR`], private to root, so boot
+//! scripts can be replayed by [`crate::boot`] after a reboot. Steps run in
+//! the order [`guest::schedule`] works out, and each system step that ran
+//! leaves a marker with the hash of its content.
+
+use std::path::Path;
+
+use roam::Tx;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+use guest::agent::{
+    EnvVar, ProvisionEvent, ProvisionMode, ProvisionResult, ProvisionScript, RunOn, ScriptKind,
+};
+use guest::schedule::{Events, schedule_plan};
+
+use crate::executions::Scope;
+use crate::nixos;
+
+pub const SCRIPTS_DIR: &str = "/var/lib/rum/scripts";
+/// Created once a full plan succeeded, so boot scripts know the system steps
+/// already ran.
+pub const SENTINEL_PATH: &str = "/var/lib/rum/.system-provisioned";
+/// One file per system step that ran, holding the hash of its content.
+const STEP_MARKERS_DIR: &str = "/var/lib/rum/steps";
+/// The same for ad-hoc scripts, which may share a name with a step.
+const SCRIPT_MARKERS_DIR: &str = "/var/lib/rum/adhoc";
+
+/// Run `scripts` as `mode` asks: write them out, run them in plan order and
+/// record which steps ran. A full plan is also cached for boot and marks the
+/// guest as provisioned.
+pub async fn run(
+    mut scripts: Vec<ProvisionScript>,
+    mode: ProvisionMode,
+    output: &Tx<ProvisionEvent>,
+    scope: &Scope,
+) -> ProvisionResult {
+    let plan = mode == ProvisionMode::Plan;
+    // Hashed before launchers replace the content, to match the host's.
+    let step_hashes: Vec<(String, String)> = scripts
+        .iter()
+        .filter(|s| matches!(s.run_on, RunOn::System))
+        .map(|s| {
+            let hash = guest::tree::hash_bytes(s.content.as_bytes());
+            (s.name.clone(), hash)
+        })
+        .collect();
+
+    // Create scripts dir, clear old scripts
+    let scripts_dir = Path::new(SCRIPTS_DIR);
+    if let Err(e) = create_private_dir(scripts_dir).await {
+        tracing::error!(error = %e, "failed to create scripts dir");
+        return ProvisionResult {
+            success: false,
+            failed_script: "(setup)".into(),
+        };
+    }
+    if plan && let Ok(mut entries) = tokio::fs::read_dir(scripts_dir).await {
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            let _ = tokio::fs::remove_file(entry.path()).await;
+        }
+    }
+
+    // Scripts for another user or interpreter are kept as a body file
+    // started by a shell launcher, so cached boot scripts replay the
+    // same way. One-off runs get bodies of their own, next to the plan's
+    // and removed once they finished.
+    let mut one_off_bodies = Vec::new();
+    for s in scripts.iter_mut().filter(|s| {
+        matches!(s.kind, ScriptKind::Shell) && (s.user.is_some() || s.interpreter.is_some())
+    }) {
+        let body = if plan {
+            scripts_dir.join(format!("{:03}-{}.body", s.order, s.name))
+        } else {
+            scripts_dir.join(format!("{:03}-{}.oneoff.body", s.order, s.name))
+        };
+        if !plan {
+            one_off_bodies.push(body.clone());
+        }
+        if let Err(e) = write_private(&body, &s.content).await {
+            tracing::error!(error = %e, script = %s.name, "failed to write script body");
+            remove_files(&one_off_bodies).await;
+            return ProvisionResult {
+                success: false,
+                failed_script: s.name.clone(),
+            };
+        }
+        s.content = script_launcher(s, &body);
+    }
+
+    // Write all scripts of the plan to disk. NixOS modules persist in
+    // /etc/nixos instead, and scripts that need the host's environment
+    // cannot run without it, so neither is replayed on boot.
+    for s in scripts
+        .iter()
+        .filter(|s| plan && matches!(s.kind, ScriptKind::Shell) && s.env.is_empty())
+    {
+        let suffix = match s.run_on {
+            RunOn::System => "system",
+            RunOn::Boot => "boot",
+        };
+        let filename = format!("{:03}-{}.{suffix}.sh", s.order, s.name);
+        let path = scripts_dir.join(&filename);
+        if let Err(e) = write_private(&path, &s.content).await {
+            tracing::error!(error = %e, filename, "failed to write script");
+            return ProvisionResult {
+                success: false,
+                failed_script: s.name.clone(),
+            };
+        }
+    }
+
+    // Run all received scripts — the host controls what to send
+    let mut sorted: Vec<&ProvisionScript> = scripts.iter().collect();
+    sorted.sort_by_key(|s| s.order);
+    let failed = run_plan(&sorted, output, scope).await;
+    remove_files(&one_off_bodies).await;
+    if let Some(failed) = failed {
+        tracing::error!(script = %failed, "script failed");
+        return ProvisionResult {
+            success: false,
+            failed_script: failed,
+        };
+    }
+
+    let markers_dir = if mode == ProvisionMode::Script {
+        SCRIPT_MARKERS_DIR
+    } else {
+        STEP_MARKERS_DIR
+    };
+    write_step_markers(Path::new(markers_dir), &step_hashes).await;
+    if !plan {
+        return ProvisionResult {
+            success: true,
+            failed_script: String::new(),
+        };
+    }
+
+    // Create sentinel on success so auto-boot scripts know system was provisioned
+    if let Some(parent) = Path::new(SENTINEL_PATH).parent() {
+        let _ = tokio::fs::create_dir_all(parent).await;
+    }
+    if let Err(e) = tokio::fs::write(SENTINEL_PATH, "").await {
+        tracing::error!(error = %e, "failed to create sentinel");
+    }
+
+    ProvisionResult {
+        success: true,
+        failed_script: String::new(),
+    }
+}
+
+/// Create `dir` readable by root only, tightening it if it already exists.
+async fn create_private_dir(dir: &Path) -> std::io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    tokio::fs::create_dir_all(dir).await?;
+    tokio::fs::set_permissions(dir, std::fs::Permissions::from_mode(0o700)).await
+}
+
+/// Write `contents` to `path` readable by root only. Scripts can hold
+/// secrets, and guest users must not read or change what root runs.
+async fn write_private(path: &Path, contents: &str) -> std::io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    let mut file = tokio::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)
+        .await?;
+    // `mode` only applies to new files.
+    file.set_permissions(std::fs::Permissions::from_mode(0o600))
+        .await?;
+    file.write_all(contents.as_bytes()).await?;
+    file.flush().await
+}
+
+/// Shell command running the script saved at `body` as its user and with its
+/// interpreter.
+///
+/// Root reads the body and hands it over on stdin, so the scripts dir stays
+/// private to root.
+fn script_launcher(script: &ProvisionScript, body: &Path) -> String {
+    let interpreter = script.interpreter.as_deref().unwrap_or("sh");
+    match &script.user {
+        Some(user) => format!(
+            "exec runuser -u {user} -- {interpreter} /dev/stdin < {}\n",
+            body.display()
+        ),
+        None => format!("exec {interpreter} {}\n", body.display()),
+    }
+}
+
+/// Run `sorted` and return the name of the first script (in plan order) that
+/// failed or was skipped because a dependency failed.
+async fn run_plan(
+    sorted: &[&ProvisionScript],
+    output: &Tx<ProvisionEvent>,
+    scope: &Scope,
+) -> Option<String> {
+    let forward = async |event: ProvisionEvent| {
+        let _ = output.send(&event).await;
+    };
+    schedule_plan(sorted, |s, events| run_plan_step(s, events, scope), forward).await
+}
+
+/// Run one script of the plan, or skip it once provisioning was cancelled.
+async fn run_plan_step(s: &ProvisionScript, events: Events, scope: &Scope) -> Option<i32> {
+    if scope.is_cancelled() {
+        tracing::warn!(script = %s.name, "skipped: provisioning was cancelled");
+        return None;
+    }
+    tracing::info!(script = %s.name, "running provision script");
+    let exit_code = match s.kind {
+        ScriptKind::Shell => run_provision_script(&s.content, &s.env, &events, scope).await,
+        ScriptKind::NixosModule => nixos::apply_module(&s.content, &events, scope).await,
+    };
+    Some(exit_code.unwrap_or(-1))
+}
+
+async fn run_provision_script(
+    content: &str,
+    env: &[EnvVar],
+    output: &Events,
+    scope: &Scope,
+) -> Option<i32> {
+    let mut command = tokio::process::Command::new("sh");
+    command
+        .arg("-c")
+        .arg(content)
+        .envs(env.iter().map(|v| (&v.name, &v.value)));
+    stream_command(command, output, scope).await
+}
+
+/// Run `command` in a process group of its own, sending its output to
+/// `output` line by line, and return its exit code.
+pub async fn stream_command(
+    mut command: tokio::process::Command,
+    output: &Events,
+    scope: &Scope,
+) -> Option<i32> {
+    // Own process group so a cancel takes down everything the script spawned.
+    let child = command
+        .process_group(0)
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn();
+
+    let mut child = match child {
+        Ok(c) => c,
+        Err(e) => {
+            let _ = output.send(ProvisionEvent::Stderr(format!("failed to spawn: {e}")));
+            return None;
+        }
+    };
+
+    let stdout = child.stdout.take().unwrap();
+    let stderr = child.stderr.take().unwrap();
+    let process = scope.spawned(child);
+
+    let mut stdout_lines = BufReader::new(stdout).lines();
+    let mut stderr_lines = BufReader::new(stderr).lines();
+
+    loop {
+        tokio::select! {
+            line = stdout_lines.next_line() => {
+                match line {
+                    Ok(Some(text)) => {
+                        let _ = output.send(ProvisionEvent::Stdout(text));
+                    }
+                    Ok(None) => break,
+                    Err(_) => break,
+                }
+            }
+            line = stderr_lines.next_line() => {
+                match line {
+                    Ok(Some(text)) => {
+                        let _ = output.send(ProvisionEvent::Stderr(text));
+                    }
+                    Ok(None) => break,
+                    Err(_) => break,
+                }
+            }
+        }
+    }
+
+    let status = process.wait().await;
+    scope.exited(&process);
+    status?.code()
+}
+
+/// Record which content every system step last ran with. Markers only
+/// help when inspecting a guest, so failing to write them is not an error.
+async fn write_step_markers(dir: &Path, hashes: &[(String, String)]) {
+    if let Err(e) = tokio::fs::create_dir_all(dir).await {
+        tracing::warn!(error = %e, "failed to create step markers dir");
+        return;
+    }
+    for (name, hash) in hashes {
+        let path = dir.join(name);
+        if let Err(e) = tokio::fs::write(&path, format!("{hash}\n")).await {
+            tracing::warn!(error = %e, step = %name, "failed to write step marker");
+        }
+    }
+}
+
+async fn remove_files(paths: &[std::path::PathBuf]) {
+    for path in paths {
+        if let Err(e) = tokio::fs::remove_file(path).await {
+            tracing::warn!(path = %path.display(), error = %e, "failed to remove file");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn launcher_feeds_other_users_the_body_on_stdin() {
+        let body = Path::new("/var/lib/rum/scripts/050-seed.body");
+        let step = ProvisionScript {
+            user: Some("app".into()),
+            interpreter: Some("python3".into()),
+            ..ProvisionScript::shell("seed", "seed", String::new(), 50, RunOn::System)
+        };
+        assert_eq!(
+            script_launcher(&step, body),
+            "exec runuser -u app -- python3 /dev/stdin < /var/lib/rum/scripts/050-seed.body\n"
+        );
+
+        let root = ProvisionScript { user: None, ..step };
+        assert_eq!(
+            script_launcher(&root, body),
+            "exec python3 /var/lib/rum/scripts/050-seed.body\n"
+        );
+    }
+}
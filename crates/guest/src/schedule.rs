@@ -0,0 +1,196 @@
+//! Ordering and concurrency of a provisioning plan.
+//!
+//! The agent runs the steps of a plan as their dependencies allow, while the
+//! host reads their output as if they ran one after another. This module
+//! owns that scheduling and leaves running a single step to the caller.
+
+use crate::agent::{ProvisionEvent, ProvisionScript};
+
+/// Per-script provisioning output, forwarded to the host in plan order.
+pub type Events = tokio::sync::mpsc::UnboundedSender<ProvisionEvent>;
+
+/// Run `sorted` with `run`, which returns the exit code of a script or
+/// `None` when it skipped it, and return the name of the first script (in
+/// plan order) that failed or was skipped.
+///
+/// A script without `depends_on` waits for every script before it; one with
+/// `depends_on` only waits for those, so independent steps run concurrently.
+/// Output is buffered per script and handed to `output` in plan order, so
+/// the host still sees one script at a time.
+pub async fn schedule_plan<'a, Run, Fut>(
+    sorted: &[&'a ProvisionScript],
+    run: Run,
+    mut output: impl AsyncFnMut(ProvisionEvent),
+) -> Option<String>
+where
+    Run: Fn(&'a ProvisionScript, Events) -> Fut,
+    Fut: Future<Output = Option<i32>>,
+{
+    use futures_util::future::join_all;
+    use tokio::sync::{mpsc, watch};
+
+    let finished: Vec<_> = sorted
+        .iter()
+        .map(|_| watch::channel(None::<bool>))
+        .collect();
+    let mut receivers = Vec::new();
+
+    let runs = sorted.iter().enumerate().map(|(i, s)| {
+        let (events_tx, events_rx) = mpsc::unbounded_channel();
+        receivers.push(events_rx);
+        let deps: Vec<watch::Receiver<Option<bool>>> = finished[..i]
+            .iter()
+            .zip(sorted)
+            .filter(|(_, dep)| {
+                s.depends_on
+                    .as_ref()
+                    .is_none_or(|names| names.contains(&dep.name))
+            })
+            .map(|((tx, _), _)| tx.subscribe())
+            .collect();
+        let done = &finished[i].0;
+        let run = &run;
+        async move {
+            let mut deps_ok = true;
+            for mut dep in deps {
+                let ok = dep
+                    .wait_for(Option::is_some)
+                    .await
+                    .is_ok_and(|v| *v == Some(true));
+                deps_ok &= ok;
+            }
+            if !deps_ok {
+                tracing::warn!(script = %s.name, "skipped: a dependency failed");
+                done.send_replace(Some(false));
+                return;
+            }
+
+            let Some(exit_code) = run(*s, events_tx.clone()).await else {
+                done.send_replace(Some(false));
+                return;
+            };
+            let _ = events_tx.send(ProvisionEvent::Done(exit_code));
+            done.send_replace(Some(exit_code == 0));
+        }
+    });
+    let runs = join_all(runs.collect::<Vec<_>>());
+
+    let forward = async {
+        for (s, mut events) in sorted.iter().zip(receivers) {
+            let mut success = false;
+            while let Some(event) = events.recv().await {
+                success = matches!(event, ProvisionEvent::Done(0));
+                output(event).await;
+            }
+            if !success {
+                return Some(s.name.clone());
+            }
+        }
+        None
+    };
+
+    let (_, failed) = tokio::join!(runs, forward);
+    failed
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    use super::*;
+    use crate::agent::RunOn;
+
+    fn script(name: &str, depends_on: Option<&[&str]>) -> ProvisionScript {
+        ProvisionScript {
+            depends_on: depends_on.map(|deps| deps.iter().map(|d| d.to_string()).collect()),
+            ..ProvisionScript::shell(name, name, String::new(), 0, RunOn::System)
+        }
+    }
+
+    fn label(event: &ProvisionEvent) -> String {
+        match event {
+            ProvisionEvent::Stdout(line) | ProvisionEvent::Stderr(line) => line.clone(),
+            ProvisionEvent::Done(code) => format!("done {code}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn independent_steps_run_concurrently() {
+        let (a, b) = (script("a", Some(&[])), script("b", Some(&[])));
+        // Each step waits for the other to start, so running them one after
+        // the other never finishes.
+        let barrier = &tokio::sync::Barrier::new(2);
+        let run = move |_: &ProvisionScript, _| async move {
+            barrier.wait().await;
+            Some(0)
+        };
+        let plan = schedule_plan(&[&a, &b], run, async |_| {});
+        let failed = tokio::time::timeout(Duration::from_secs(5), plan).await;
+        assert_eq!(failed.expect("steps ran one after the other"), None);
+    }
+
+    #[tokio::test]
+    async fn output_follows_plan_order_and_default_steps_wait_for_all() {
+        let (slow, fast) = (script("slow", Some(&[])), script("fast", Some(&[])));
+        let last = script("last", None);
+        let log = &Mutex::new(Vec::new());
+        let run = move |s: &ProvisionScript, events: Events| {
+            let name = s.name.clone();
+            async move {
+                log.lock().unwrap().push(format!("start {name}"));
+                if name == "slow" {
+                    tokio::time::sleep(Duration::from_millis(50)).await;
+                }
+                log.lock().unwrap().push(format!("end {name}"));
+                let _ = events.send(ProvisionEvent::Stdout(name));
+                Some(0)
+            }
+        };
+        let mut output = Vec::new();
+        let failed = schedule_plan(&[&slow, &fast, &last], run, async |event| {
+            output.push(label(&event));
+        })
+        .await;
+
+        assert_eq!(failed, None);
+        assert_eq!(
+            output,
+            ["slow", "done 0", "fast", "done 0", "last", "done 0"]
+        );
+        let log = log.lock().unwrap();
+        let at = |entry: &str| log.iter().position(|e| e == entry).unwrap();
+        assert!(at("end fast") < at("end slow"), "got {log:?}");
+        assert!(at("end slow") < at("start last"), "got {log:?}");
+    }
+
+    #[tokio::test]
+    async fn failed_dependency_skips_its_dependents_only() {
+        let broken = script("broken", Some(&[]));
+        let dependent = script("dependent", Some(&["broken"]));
+        let unrelated = script("unrelated", Some(&[]));
+        let ran = &Mutex::new(Vec::new());
+        let run = move |s: &ProvisionScript, _| {
+            ran.lock().unwrap().push(s.name.clone());
+            let exit_code = if s.name == "broken" { 1 } else { 0 };
+            async move { Some(exit_code) }
+        };
+        let failed = schedule_plan(&[&broken, &dependent, &unrelated], run, async |_| {}).await;
+
+        assert_eq!(failed.as_deref(), Some("broken"));
+        let mut ran = ran.lock().unwrap().clone();
+        ran.sort();
+        assert_eq!(ran, ["broken", "unrelated"]);
+    }
+
+    #[tokio::test]
+    async fn skipped_step_is_reported_as_failed() {
+        let (first, second) = (script("first", None), script("second", None));
+        let run = |s: &ProvisionScript, _| {
+            let skip = s.name == "second";
+            async move { (!skip).then_some(0) }
+        };
+        let failed = schedule_plan(&[&first, &second], run, async |_| {}).await;
+        assert_eq!(failed.as_deref(), Some("second"));
+    }
+}
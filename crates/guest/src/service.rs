@@ -0,0 +1,111 @@
+//! Guest-side end of the `service_action` and `list_services` RPCs.
+
+use guest::agent::{ServiceAction, ServiceActionResult, ServiceInfo};
+
+/// Run `systemctl <action> <name>` and hand back its exit code and output.
+pub async fn run(name: &str, action: ServiceAction) -> Result<ServiceActionResult, String> {
+    // A leading dash would be taken as a systemctl option.
+    if name.is_empty() || name.starts_with('-') {
+        return Err(format!("invalid service name '{name}'"));
+    }
+    let output = tokio::process::Command::new("systemctl")
+        .args(["--no-pager", action.verb(), "--", name])
+        .output()
+        .await
+        .map_err(|e| format!("systemctl: {e}"))?;
+
+    let mut text = String::from_utf8_lossy(&output.stdout).into_owned();
+    text.push_str(&String::from_utf8_lossy(&output.stderr));
+    let exit_code = output.status.code().unwrap_or(-1);
+    tracing::info!(
+        name,
+        action = action.verb(),
+        exit_code,
+        "service_action complete"
+    );
+    Ok(ServiceActionResult {
+        exit_code,
+        output: text,
+    })
+}
+
+/// Every service unit systemd knows about, loaded or not.
+pub async fn list() -> Result<Vec<ServiceInfo>, String> {
+    let output = tokio::process::Command::new("systemctl")
+        .args([
+            "list-units",
+            "--type=service",
+            "--all",
+            "--plain",
+            "--no-legend",
+            "--no-pager",
+        ])
+        .output()
+        .await
+        .map_err(|e| format!("systemctl: {e}"))?;
+    if !output.status.success() {
+        return Err(format!(
+            "systemctl list-units failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    let listing = String::from_utf8_lossy(&output.stdout);
+    Ok(parse_units(&listing))
+}
+
+/// Parse `systemctl list-units --plain --no-legend` lines, which look like
+/// `nginx.service loaded active running A high performance web server`.
+fn parse_units(listing: &str) -> Vec<ServiceInfo> {
+    listing
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let name = fields.next()?.to_string();
+            let load = fields.next()?.to_string();
+            let active = fields.next()?.to_string();
+            let sub = fields.next()?.to_string();
+            let description = fields.collect::<Vec<_>>().join(" ");
+            Some(ServiceInfo {
+                name,
+                load,
+                active,
+                sub,
+                description,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unit_line_splits_into_its_columns() {
+        let units =
+            parse_units("nginx.service loaded active running A high performance web server\n");
+        assert_eq!(units.len(), 1);
+        let nginx = &units[0];
+        assert_eq!(nginx.name, "nginx.service");
+        assert_eq!(nginx.load, "loaded");
+        assert_eq!(nginx.active, "active");
+        assert_eq!(nginx.sub, "running");
+        assert_eq!(nginx.description, "A high performance web server");
+    }
+
+    #[test]
+    fn unit_without_description_keeps_an_empty_one() {
+        let units = parse_units("ghost.service not-found inactive dead\n");
+        assert_eq!(units.len(), 1);
+        assert_eq!(units[0].name, "ghost.service");
+        assert_eq!(units[0].sub, "dead");
+        assert_eq!(units[0].description, "");
+    }
+
+    #[test]
+    fn short_and_blank_lines_are_skipped() {
+        let listing = "\nbroken.service loaded\nsshd.service loaded active running OpenSSH\n";
+        let names: Vec<_> = parse_units(listing).into_iter().map(|u| u.name).collect();
+        assert_eq!(names, ["sshd.service"]);
+    }
+}
@@ -0,0 +1,192 @@
+//! Chunk encoding and partial files shared by both ends of a file transfer.
+//!
+//! A transfer writes into `<destination>.rum-part` and renames it once the
+//! whole file arrived and its checksum matched, so an interrupted copy can
+//! pick up where the partial file ends.
+
+use std::path::{Path, PathBuf};
+
+use crate::agent::FileChunk;
+
+/// Bytes per [`FileChunk`] of a file transfer.
+pub const CHUNK_SIZE: usize = 10 * 1024 * 1024;
+
+const PARTIAL_SUFFIX: &str = ".rum-part";
+
+/// zstd level for compressed chunks: fast enough to keep up with vsock.
+const ZSTD_LEVEL: i32 = 3;
+
+/// Where a transfer to `path` lands: `filename` inside it when `path` is a
+/// directory, `path` itself otherwise.
+pub fn destination(path: &Path, filename: &str) -> PathBuf {
+    if path.is_dir() {
+        path.join(filename)
+    } else {
+        path.to_path_buf()
+    }
+}
+
+/// The partial file a transfer to `destination` writes into.
+pub fn partial_path(destination: &Path) -> PathBuf {
+    let mut name = destination.file_name().unwrap_or_default().to_os_string();
+    name.push(PARTIAL_SUFFIX);
+    destination.with_file_name(name)
+}
+
+/// How many leading bytes of a transfer can be skipped: `offset` when `path`
+/// holds at least that many and they hash to `sha256`, 0 otherwise.
+pub fn resume_offset(path: &Path, offset: u64, sha256: &str) -> std::io::Result<u64> {
+    if offset == 0 || std::fs::metadata(path)?.len() < offset {
+        return Ok(0);
+    }
+    let prefix = crate::tree::hash_file_prefix(path, offset)?;
+    Ok(if prefix == sha256 { offset } else { 0 })
+}
+
+/// Open the partial file `part` to continue a transfer at `offset`, dropping
+/// whatever it holds past that, or start it afresh when `offset` is 0.
+pub async fn open_partial(part: &Path, offset: u64) -> std::io::Result<tokio::fs::File> {
+    use tokio::io::AsyncSeekExt;
+
+    if offset == 0 {
+        return tokio::fs::File::create(part).await;
+    }
+    let mut file = tokio::fs::OpenOptions::new().write(true).open(part).await?;
+    let len = file.metadata().await?.len();
+    if len < offset {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("cannot resume at byte {offset} of a {len}-byte file"),
+        ));
+    }
+    file.set_len(offset).await?;
+    file.seek(std::io::SeekFrom::End(0)).await?;
+    Ok(file)
+}
+
+/// `data` as a chunk, zstd-compressed when `compress` is set and that makes
+/// it smaller.
+pub fn encode_chunk(data: &[u8], compress: bool) -> FileChunk {
+    if compress
+        && let Ok(compressed) = zstd::bulk::compress(data, ZSTD_LEVEL)
+        && compressed.len() < data.len()
+    {
+        return FileChunk {
+            data: compressed,
+            compressed: true,
+        };
+    }
+    FileChunk {
+        data: data.to_vec(),
+        compressed: false,
+    }
+}
+
+/// The bytes `chunk` carries.
+pub fn decode_chunk(chunk: FileChunk) -> std::io::Result<Vec<u8>> {
+    if chunk.compressed {
+        zstd::stream::decode_all(chunk.data.as_slice())
+    } else {
+        Ok(chunk.data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::AsyncWriteExt;
+
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("rum-transfer-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn partial_files_sit_next_to_the_destination() {
+        assert_eq!(
+            partial_path(Path::new("/srv/data/image.qcow2")),
+            Path::new("/srv/data/image.qcow2.rum-part")
+        );
+        assert_eq!(
+            partial_path(Path::new("notes")),
+            Path::new("notes.rum-part")
+        );
+    }
+
+    #[test]
+    fn chunks_round_trip_compressed_or_not() {
+        let text = "the same line over and over\n".repeat(1000).into_bytes();
+        let chunk = encode_chunk(&text, true);
+        assert!(chunk.compressed);
+        assert!(chunk.data.len() < text.len());
+        assert_eq!(decode_chunk(chunk).unwrap(), text);
+
+        let chunk = encode_chunk(&text, false);
+        assert!(!chunk.compressed);
+        assert_eq!(decode_chunk(chunk).unwrap(), text);
+    }
+
+    #[test]
+    fn incompressible_chunks_are_sent_as_is() {
+        let mut state = 0x2545_f491_4f6c_dd1d_u64;
+        let noise: Vec<u8> = (0..4096)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                (state >> 56) as u8
+            })
+            .collect();
+        let chunk = encode_chunk(&noise, true);
+        assert!(!chunk.compressed);
+        assert_eq!(decode_chunk(chunk).unwrap(), noise);
+        assert_eq!(
+            decode_chunk(encode_chunk(&[], true)).unwrap(),
+            Vec::<u8>::new()
+        );
+    }
+
+    #[test]
+    fn resumes_only_from_a_matching_prefix() {
+        let dir = temp_dir("offset");
+        let file = dir.join("source");
+        std::fs::write(&file, b"hello world").unwrap();
+        let prefix = crate::tree::hash_bytes(b"hello ");
+
+        assert_eq!(resume_offset(&file, 6, &prefix).unwrap(), 6);
+        // The file changed since the partial was written.
+        assert_eq!(
+            resume_offset(&file, 6, &crate::tree::hash_bytes(b"howdy ")).unwrap(),
+            0
+        );
+        // The file shrank below the partial.
+        assert_eq!(resume_offset(&file, 64, &prefix).unwrap(), 0);
+        assert_eq!(resume_offset(&file, 0, "").unwrap(), 0);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn partial_files_continue_at_the_offset() {
+        let dir = temp_dir("partial");
+        let part = partial_path(&dir.join("out"));
+        std::fs::write(&part, b"hello wor").unwrap();
+
+        // Bytes past the offset were never confirmed and are dropped.
+        let mut file = open_partial(&part, 6).await.unwrap();
+        file.write_all(b"world").await.unwrap();
+        file.flush().await.unwrap();
+        assert_eq!(std::fs::read(&part).unwrap(), b"hello world");
+
+        let err = open_partial(&part, 64).await.unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+
+        let mut file = open_partial(&part, 0).await.unwrap();
+        file.write_all(b"fresh").await.unwrap();
+        file.flush().await.unwrap();
+        assert_eq!(std::fs::read(&part).unwrap(), b"fresh");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,95 @@
+//! How the host reaches the agent: vsock where the host has `vhost-vsock`,
+//! and the virtio-serial agent channel otherwise.
+
+use std::path::Path;
+
+use roam_stream::{HandshakeConfig, accept};
+use tokio_vsock::{VMADDR_CID_ANY, VsockAddr, VsockListener, VsockStream};
+
+use guest::agent::AgentDispatcher;
+
+use crate::AgentService;
+
+pub const RPC_PORT: u32 = 2222;
+pub const FORWARD_PORT: u32 = 2223;
+/// virtio-serial port the host gives the domain next to its vsock device,
+/// or instead of it on hosts without `vhost-vsock`.
+const AGENT_CHANNEL_PATH: &str = "/dev/virtio-ports/org.rum.agent.0";
+/// How long udev gets to create the agent channel's device node before it
+/// is looked for less often.
+const AGENT_CHANNEL_SETTLE: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// Listen on vsock `port`, or `None` when the guest has no vsock device.
+pub fn bind_vsock(port: u32) -> Option<VsockListener> {
+    match VsockListener::bind(VsockAddr::new(VMADDR_CID_ANY, port)) {
+        Ok(listener) => Some(listener),
+        Err(e) => {
+            tracing::warn!(port, error = %e, "vsock unavailable");
+            None
+        }
+    }
+}
+
+/// Accept on `listener`, or wait forever without one.
+pub async fn accept_vsock(
+    listener: &Option<VsockListener>,
+) -> std::io::Result<(VsockStream, VsockAddr)> {
+    match listener {
+        Some(listener) => listener.accept().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Wait until the agent channel's device node exists. udev may create it
+/// after the agent started, and a domain defined without the channel never
+/// gets one, so after [`AGENT_CHANNEL_SETTLE`] it is only checked now and
+/// then.
+async fn wait_for_agent_channel() {
+    let started = std::time::Instant::now();
+    let mut waiting = false;
+    while !Path::new(AGENT_CHANNEL_PATH).exists() {
+        if !waiting {
+            tracing::debug!(path = AGENT_CHANNEL_PATH, "waiting for the agent channel");
+            waiting = true;
+        }
+        let interval = if started.elapsed() < AGENT_CHANNEL_SETTLE {
+            std::time::Duration::from_secs(1)
+        } else {
+            std::time::Duration::from_secs(30)
+        };
+        tokio::time::sleep(interval).await;
+    }
+}
+
+/// Serve RPC over the virtio-serial agent channel. The channel is a single
+/// byte stream, so host connections are served one after another.
+pub async fn serve_agent_channel(agent: AgentService) {
+    wait_for_agent_channel().await;
+    tracing::info!(path = AGENT_CHANNEL_PATH, "listening on agent channel");
+    loop {
+        wait_for_agent_channel().await;
+        let opened = async {
+            let reader = tokio::fs::File::open(AGENT_CHANNEL_PATH).await?;
+            let writer = tokio::fs::OpenOptions::new()
+                .write(true)
+                .open(AGENT_CHANNEL_PATH)
+                .await?;
+            Ok::<_, std::io::Error>(tokio::io::join(reader, writer))
+        };
+        match opened.await {
+            Ok(stream) => {
+                let dispatcher = AgentDispatcher::new(agent.clone());
+                match accept(stream, HandshakeConfig::default(), dispatcher).await {
+                    Ok((_handle, _incoming, driver)) => {
+                        if let Err(e) = driver.run().await {
+                            tracing::debug!(error = %e, "agent channel session ended");
+                        }
+                    }
+                    Err(e) => tracing::debug!(error = %e, "agent channel handshake failed"),
+                }
+            }
+            Err(e) => tracing::error!(error = %e, "failed to open agent channel"),
+        }
+        tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+    }
+}
@@ -0,0 +1,136 @@
+//! Directory walking and content hashing shared by the agent and host-side
+//! sync so both ends compare trees the same way.
+
+use std::io::Read;
+use std::path::Path;
+
+use sha2::{Digest, Sha256};
+
+use crate::agent::TreeEntry;
+
+/// List every file and directory below `root`, sorted by relative path.
+///
+/// Symlinks are skipped. A missing root yields an empty listing so a first
+/// sync into a fresh guest directory needs no special casing.
+pub fn walk(root: &Path) -> std::io::Result<Vec<TreeEntry>> {
+    let mut entries = Vec::new();
+    if !root.exists() {
+        return Ok(entries);
+    }
+    walk_dir(root, root, &mut entries)?;
+    entries.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(entries)
+}
+
+fn walk_dir(root: &Path, dir: &Path, out: &mut Vec<TreeEntry>) -> std::io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        if file_type.is_symlink() {
+            continue;
+        }
+
+        let path = entry.path();
+        let metadata = entry.metadata()?;
+        let relative = path
+            .strip_prefix(root)
+            .unwrap_or(&path)
+            .to_string_lossy()
+            .into_owned();
+        out.push(TreeEntry {
+            path: relative,
+            size: if file_type.is_dir() {
+                0
+            } else {
+                metadata.len()
+            },
+            mode: metadata.permissions().mode(),
+            is_dir: file_type.is_dir(),
+        });
+
+        if file_type.is_dir() {
+            walk_dir(root, &path, out)?;
+        }
+    }
+    Ok(())
+}
+
+/// Hex SHA-256 of `data`, as [`hash_file`] reports it.
+pub fn hash_bytes(data: &[u8]) -> String {
+    Sha256::digest(data)
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+/// Hex SHA-256 of the first `len` bytes of `path`. Fails if the file is
+/// shorter.
+pub fn hash_file_prefix(path: &Path, len: u64) -> std::io::Result<String> {
+    let mut file = std::fs::File::open(path)?.take(len);
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; 1024 * 1024];
+    let mut read = 0_u64;
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+        read += n as u64;
+    }
+    if read < len {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::UnexpectedEof,
+            format!("{} is only {read} bytes", path.display()),
+        ));
+    }
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect())
+}
+
+/// Hex-encoded SHA-256 of a file's contents.
+pub fn hash_file(path: &Path) -> std::io::Result<String> {
+    let mut file = std::fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; 1024 * 1024];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prefix_hashes_match_the_hash_of_the_leading_bytes() {
+        let dir = std::env::temp_dir().join(format!("rum-tree-prefix-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("data");
+        std::fs::write(&file, b"hello world").unwrap();
+
+        assert_eq!(hash_file_prefix(&file, 5).unwrap(), hash_bytes(b"hello"));
+        assert_eq!(
+            hash_file_prefix(&file, 11).unwrap(),
+            hash_file(&file).unwrap()
+        );
+        assert_eq!(hash_file_prefix(&file, 0).unwrap(), hash_bytes(b""));
+        let err = hash_file_prefix(&file, 12).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
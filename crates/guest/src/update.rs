@@ -0,0 +1,99 @@
+//! Guest-side end of the `update_agent` RPC: the agent replaces its own
+//! binary and re-execs into it.
+
+use std::path::Path;
+
+use roam::Rx;
+use tokio::io::AsyncWriteExt;
+
+use guest::agent::FileChunk;
+use guest::transfer;
+
+/// Stage the binary streamed in `data`, install it once its hash matches
+/// `sha256`, and restart into it after replying.
+pub async fn update_agent(sha256: String, mut data: Rx<FileChunk>) -> Result<(), String> {
+    let exe = std::env::current_exe().map_err(|e| format!("locate agent binary: {e}"))?;
+    let staged = exe.with_extension("new");
+    let mut file = tokio::fs::File::create(&staged)
+        .await
+        .map_err(|e| format!("create {}: {e}", staged.display()))?;
+    while let Ok(Some(chunk)) = data.recv().await {
+        let bytes = transfer::decode_chunk(chunk).map_err(|e| format!("decompress: {e}"))?;
+        file.write_all(&bytes)
+            .await
+            .map_err(|e| format!("write: {e}"))?;
+    }
+    file.sync_all().await.map_err(|e| format!("sync: {e}"))?;
+    drop(file);
+
+    let target = exe.clone();
+    tokio::task::spawn_blocking(move || install_staged_agent(&staged, &target, &sha256))
+        .await
+        .map_err(|e| format!("install task: {e}"))??;
+
+    tracing::info!(path = %exe.display(), "agent binary updated, restarting");
+    tokio::spawn(async move {
+        // Let the reply reach the host before the process is replaced.
+        tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+        let error = std::os::unix::process::CommandExt::exec(
+            std::process::Command::new(&exe).args(std::env::args_os().skip(1)),
+        );
+        tracing::error!(error = %error, "failed to re-exec the agent");
+        // systemd starts the new binary instead.
+        std::process::exit(1);
+    });
+    Ok(())
+}
+
+/// Move the agent binary `staged` over `exe` once its SHA-256 matches
+/// `sha256`; a mismatching one is removed and `exe` left as it is.
+fn install_staged_agent(staged: &Path, exe: &Path, sha256: &str) -> Result<(), String> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let hash =
+        guest::tree::hash_file(staged).map_err(|e| format!("hash {}: {e}", staged.display()))?;
+    if hash != sha256 {
+        let _ = std::fs::remove_file(staged);
+        return Err(format!("checksum mismatch: got {hash}, expected {sha256}"));
+    }
+    std::fs::set_permissions(staged, std::fs::Permissions::from_mode(0o755))
+        .map_err(|e| format!("chmod: {e}"))?;
+    std::fs::rename(staged, exe).map_err(|e| format!("replace {}: {e}", exe.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("rum-agent-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn updated_agent_replaces_the_binary_only_when_its_hash_matches() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = temp_dir("update");
+        let exe = dir.join("rum-agent");
+        let staged = exe.with_extension("new");
+        std::fs::write(&exe, b"old agent").unwrap();
+
+        std::fs::write(&staged, b"new agent").unwrap();
+        let err =
+            install_staged_agent(&staged, &exe, &guest::tree::hash_bytes(b"other")).unwrap_err();
+        assert!(err.starts_with("checksum mismatch"), "{err}");
+        assert!(!staged.exists());
+        assert_eq!(std::fs::read(&exe).unwrap(), b"old agent");
+
+        std::fs::write(&staged, b"new agent").unwrap();
+        install_staged_agent(&staged, &exe, &guest::tree::hash_bytes(b"new agent")).unwrap();
+        assert!(!staged.exists());
+        assert_eq!(std::fs::read(&exe).unwrap(), b"new agent");
+        let mode = std::fs::metadata(&exe).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o755);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
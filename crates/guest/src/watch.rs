@@ -0,0 +1,379 @@
+//! Recursive inotify watching with filtering and batching.
+//!
+//! Backs the agent's fs event bridge and the host side of mount sync.
+//! inotify is not recursive, so every directory below the requested roots
+//! gets its own watch, and directories created later are added as they
+//! appear. Build output and dependency trees churn through thousands of
+//! files, so ignored directories are skipped entirely and bursts of events
+//! are coalesced per path.
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use futures_util::StreamExt;
+use inotify::{EventMask, Inotify, WatchDescriptor, WatchMask, Watches};
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::time::Instant;
+
+use crate::agent::{FsEvent, FsEventKind, FsWatchOptions};
+
+const WATCH_MASK: WatchMask = WatchMask::CREATE
+    .union(WatchMask::CLOSE_WRITE)
+    .union(WatchMask::DELETE)
+    .union(WatchMask::MOVED_FROM)
+    .union(WatchMask::MOVED_TO);
+
+/// A watched root and the patterns that apply below it.
+struct Root {
+    path: PathBuf,
+    ignore: IgnoreSet,
+}
+
+/// Watched directories, each with the index of the root it belongs to.
+type Dirs = HashMap<WatchDescriptor, (PathBuf, usize)>;
+
+/// Watch `paths` recursively and send their events to `events` until the
+/// receiver is dropped.
+///
+/// Ignored directories are never watched, and events are batched per path
+/// when `options` asks for a debounce window.
+pub async fn watch(
+    paths: Vec<String>,
+    options: FsWatchOptions,
+    events: UnboundedSender<FsEvent>,
+) -> std::io::Result<()> {
+    let inotify = Inotify::init()?;
+    let mut buffer = vec![0u8; 4096];
+    let mut stream = inotify.into_event_stream(&mut buffer[..])?;
+    let mut watches = stream.watches();
+    let mut dirs: Dirs = HashMap::new();
+
+    let roots: Vec<Root> = paths
+        .iter()
+        .map(|path| Root {
+            ignore: IgnoreSet::new(Path::new(path), &options.ignore, options.gitignore),
+            path: PathBuf::from(path),
+        })
+        .collect();
+    for (index, root) in roots.iter().enumerate() {
+        add_recursive(&mut watches, &mut dirs, &roots, index, &root.path);
+    }
+
+    let debounce = Duration::from_millis(options.debounce_ms);
+    let mut batch = Batch::default();
+    let mut flush_at: Option<Instant> = None;
+
+    loop {
+        let event = tokio::select! {
+            event = stream.next() => match event {
+                Some(event) => event?,
+                None => break,
+            },
+            _ = tokio::time::sleep_until(flush_at.unwrap_or_else(Instant::now)),
+                if flush_at.is_some() =>
+            {
+                flush_at = None;
+                for fs_event in batch.drain() {
+                    if events.send(fs_event).is_err() {
+                        return Ok(());
+                    }
+                }
+                continue;
+            }
+        };
+
+        if event.mask.contains(EventMask::Q_OVERFLOW) {
+            tracing::warn!("inotify queue overflowed, some guest fs events were dropped");
+            continue;
+        }
+        let (Some((dir, index)), Some(name)) = (dirs.get(&event.wd).cloned(), event.name) else {
+            continue;
+        };
+        let path = dir.join(name);
+        let is_dir = event.mask.contains(EventMask::ISDIR);
+        if is_ignored(&roots[index], &path, is_dir) {
+            continue;
+        }
+
+        let kind = if event
+            .mask
+            .intersects(EventMask::CREATE | EventMask::MOVED_TO)
+        {
+            if is_dir {
+                add_recursive(&mut watches, &mut dirs, &roots, index, &path);
+            }
+            FsEventKind::Created
+        } else if event
+            .mask
+            .intersects(EventMask::DELETE | EventMask::MOVED_FROM)
+        {
+            FsEventKind::Removed
+        } else {
+            FsEventKind::Modified
+        };
+
+        let fs_event = FsEvent {
+            timestamp_us: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_micros() as u64,
+            path: path.to_string_lossy().into_owned(),
+            kind,
+            is_dir,
+        };
+        if debounce.is_zero() {
+            if events.send(fs_event).is_err() {
+                break;
+            }
+        } else {
+            batch.push(fs_event);
+            if batch.is_empty() {
+                flush_at = None;
+            } else {
+                flush_at.get_or_insert_with(|| Instant::now() + debounce);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn is_ignored(root: &Root, path: &Path, is_dir: bool) -> bool {
+    path.strip_prefix(&root.path)
+        .is_ok_and(|relative| root.ignore.is_ignored(relative, is_dir))
+}
+
+fn add_recursive(watches: &mut Watches, dirs: &mut Dirs, roots: &[Root], index: usize, dir: &Path) {
+    match watches.add(dir, WATCH_MASK) {
+        Ok(wd) => {
+            dirs.insert(wd, (dir.to_path_buf(), index));
+        }
+        Err(e) => {
+            tracing::warn!(path = %dir.display(), error = %e, "failed to add inotify watch");
+            return;
+        }
+    }
+
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if entry.file_type().is_ok_and(|t| t.is_dir()) && !is_ignored(&roots[index], &path, true) {
+            add_recursive(watches, dirs, roots, index, &path);
+        }
+    }
+}
+
+/// Gitignore-style patterns for one watched root.
+///
+/// Supports `*` and `?` wildcards, trailing `/` for directories only, and
+/// patterns anchored by a leading or inner `/`. Negations are not
+/// supported and are skipped.
+#[derive(Debug, Clone, Default)]
+pub struct IgnoreSet {
+    patterns: Vec<Pattern>,
+}
+
+#[derive(Debug, Clone)]
+struct Pattern {
+    glob: String,
+    dir_only: bool,
+    /// Match against the whole relative path instead of any one name.
+    anchored: bool,
+}
+
+impl IgnoreSet {
+    /// Combine `patterns` with the root's `.gitignore` when `gitignore` is set.
+    pub fn new(root: &Path, patterns: &[String], gitignore: bool) -> Self {
+        let mut set = Self::default();
+        for pattern in patterns {
+            set.add(pattern);
+        }
+        if gitignore && let Ok(contents) = std::fs::read_to_string(root.join(".gitignore")) {
+            for line in contents.lines() {
+                set.add(line);
+            }
+        }
+        set
+    }
+
+    fn add(&mut self, line: &str) {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with('!') {
+            return;
+        }
+        let dir_only = line.ends_with('/');
+        let line = line.trim_end_matches('/');
+        let anchored = line.contains('/');
+        self.patterns.push(Pattern {
+            glob: line.trim_start_matches('/').to_string(),
+            dir_only,
+            anchored,
+        });
+    }
+
+    /// Whether `relative` (a path below the root) or one of its parent
+    /// directories is ignored.
+    pub fn is_ignored(&self, relative: &Path, is_dir: bool) -> bool {
+        let components: Vec<String> = relative
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy().into_owned())
+            .collect();
+        (0..components.len()).any(|end| {
+            let name_is_dir = is_dir || end + 1 < components.len();
+            let prefix = components[..=end].join("/");
+            self.patterns.iter().any(|p| {
+                (name_is_dir || !p.dir_only)
+                    && if p.anchored {
+                        glob_match(&p.glob, &prefix)
+                    } else {
+                        glob_match(&p.glob, &components[end])
+                    }
+            })
+        })
+    }
+}
+
+/// Match `text` against `glob`, where `*` never crosses a `/`.
+fn glob_match(glob: &str, text: &str) -> bool {
+    let (glob, text) = (glob.as_bytes(), text.as_bytes());
+    let (mut g, mut t) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        match glob.get(g) {
+            Some(b'*') => {
+                star = Some((g, t));
+                g += 1;
+            }
+            Some(&c) if c == text[t] || (c == b'?' && text[t] != b'/') => {
+                g += 1;
+                t += 1;
+            }
+            _ => match star {
+                Some((star_g, star_t)) if text[star_t] != b'/' => {
+                    star = Some((star_g, star_t + 1));
+                    g = star_g + 1;
+                    t = star_t + 1;
+                }
+                _ => return false,
+            },
+        }
+    }
+    glob[g..].iter().all(|&c| c == b'*')
+}
+
+/// Events collected during one debounce window, one per path.
+#[derive(Debug, Default)]
+struct Batch {
+    pending: BTreeMap<String, FsEvent>,
+}
+
+impl Batch {
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    /// Fold `event` into the entry for its path. A file created and removed
+    /// within the window produces no event at all.
+    pub fn push(&mut self, event: FsEvent) {
+        use FsEventKind::*;
+
+        let Some(previous) = self.pending.remove(&event.path) else {
+            self.pending.insert(event.path.clone(), event);
+            return;
+        };
+        let kind = match (previous.kind, event.kind) {
+            (Created, Removed) => return,
+            (Created, _) => Created,
+            (Removed, Created) => Modified,
+            (_, kind) => kind,
+        };
+        self.pending
+            .insert(event.path.clone(), FsEvent { kind, ..event });
+    }
+
+    /// Take the collected events, ordered by path.
+    pub fn drain(&mut self) -> Vec<FsEvent> {
+        std::mem::take(&mut self.pending).into_values().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(path: &str, kind: FsEventKind) -> FsEvent {
+        FsEvent {
+            timestamp_us: 0,
+            path: path.into(),
+            kind,
+            is_dir: false,
+        }
+    }
+
+    #[test]
+    fn ignore_patterns_follow_gitignore_rules() {
+        let patterns = ["target/", "*.log", "/dist", "docs/*.tmp"].map(String::from);
+        let set = IgnoreSet::new(Path::new("/nonexistent"), &patterns, false);
+        let ignored = |path: &str, is_dir| set.is_ignored(Path::new(path), is_dir);
+
+        assert!(ignored("target", true));
+        assert!(ignored("crates/a/target/debug/app", false));
+        assert!(
+            !ignored("target", false),
+            "target/ only matches directories"
+        );
+        assert!(ignored("build.log", false));
+        assert!(ignored("logs/today.log", false));
+        assert!(ignored("dist/app.js", false));
+        assert!(!ignored("web/dist", true), "/dist is anchored to the root");
+        assert!(ignored("docs/draft.tmp", false));
+        assert!(!ignored("docs/sub/draft.tmp", false), "* never crosses a /");
+        assert!(!ignored("src/main.rs", false));
+    }
+
+    #[test]
+    fn gitignore_is_read_only_when_asked() {
+        let root = std::env::temp_dir().join(format!("rum-watch-{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(root.join(".gitignore"), "# build output\nout/\n!keep\n").unwrap();
+
+        let with = IgnoreSet::new(&root, &[], true);
+        let without = IgnoreSet::new(&root, &[], false);
+        std::fs::remove_dir_all(&root).unwrap();
+        assert!(with.is_ignored(Path::new("out/bin"), false));
+        assert!(!with.is_ignored(Path::new("keep"), false));
+        assert!(!without.is_ignored(Path::new("out/bin"), false));
+    }
+
+    #[test]
+    fn batch_keeps_one_event_per_path() {
+        use FsEventKind::*;
+
+        let mut batch = Batch::default();
+        batch.push(event("b", Modified));
+        batch.push(event("a", Created));
+        batch.push(event("a", Modified));
+        batch.push(event("tmp", Created));
+        batch.push(event("tmp", Removed));
+        batch.push(event("c", Removed));
+        batch.push(event("c", Created));
+
+        let kinds: Vec<_> = batch
+            .drain()
+            .into_iter()
+            .map(|e| (e.path, e.kind))
+            .collect();
+        assert_eq!(
+            kinds,
+            [
+                ("a".to_string(), Created),
+                ("b".to_string(), Modified),
+                ("c".to_string(), Modified),
+            ]
+        );
+        assert!(batch.is_empty());
+    }
+}
@@ -0,0 +1,149 @@
+//! Audit log of the commands run on a machine.
+//!
+//! Every command run against a machine's config appends one JSON line to
+//! `audit.jsonl` in the work dir once it finishes: who ran it, when, with
+//! which config, and the state the machine was left in. Unlike the
+//! [journal](crate::journal) the log is never reset, so `rum history` can
+//! answer who destroyed or restarted a machine on a shared host.
+
+use std::io::Write;
+use std::path::Path;
+
+use facet::Facet;
+use sha2::Digest;
+
+use crate::error::Error;
+
+/// One finished command.
+#[derive(Debug, Clone, PartialEq, Eq, Facet)]
+pub struct AuditEntry {
+    /// Seconds since the Unix epoch when the command started.
+    pub at: u64,
+    /// Account that ran the command, e.g. `alice` or `alice (sudo)`.
+    pub user: String,
+    pub pid: u32,
+    /// The command line as typed, e.g. `rum down --force`.
+    pub command: String,
+    /// Subcommand that ran: `up`, `down`, `exec`, ...
+    pub flow: String,
+    /// SHA-256 of the config file the command ran with.
+    pub config_hash: String,
+    pub duration_ms: u64,
+    pub success: bool,
+    /// State of the machine once the command finished, e.g. `Running`.
+    pub state: String,
+    pub error: Option<String>,
+}
+
+/// Append `entry` to the audit log at `path`.
+pub fn append(path: &Path, entry: &AuditEntry) -> Result<(), Error> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).map_err(|e| audit_error(path, e))?;
+    }
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|e| audit_error(path, e))?;
+    writeln!(file, "{}", facet_json::to_string(entry)).map_err(|e| audit_error(path, e))
+}
+
+/// Every entry of the audit log at `path`, oldest first. A missing log is
+/// empty, and a line cut short by a crash is skipped.
+pub fn read(path: &Path) -> Vec<AuditEntry> {
+    let Ok(contents) = std::fs::read_to_string(path) else {
+        return Vec::new();
+    };
+    contents
+        .lines()
+        .filter_map(|line| facet_json::from_str(line).ok())
+        .collect()
+}
+
+/// Hex SHA-256 of the config file at `path`, or an empty string if it
+/// cannot be read.
+pub fn config_hash(path: &Path) -> String {
+    std::fs::read(path)
+        .map(|contents| {
+            sha2::Sha256::digest(contents)
+                .iter()
+                .map(|byte| format!("{byte:02x}"))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// `at` as a UTC `YYYY-MM-DD HH:MM:SS` timestamp.
+pub fn format_utc(at: u64) -> String {
+    let days = (at / 86_400) as i64;
+    let time_of_day = at % 86_400;
+    let hours = time_of_day / 3_600;
+    let minutes = (time_of_day % 3_600) / 60;
+    let seconds = time_of_day % 60;
+
+    let z = days + 719_468;
+    let era = if z >= 0 { z } else { z - 146_096 } / 146_097;
+    let doe = (z - era * 146_097) as u64;
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let y = yoe as i64 + era * 400;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = if month <= 2 { y + 1 } else { y };
+
+    format!("{year:04}-{month:02}-{day:02} {hours:02}:{minutes:02}:{seconds:02}")
+}
+
+fn audit_error(path: &Path, source: std::io::Error) -> Error {
+    Error::Io {
+        context: format!("writing audit log {}", path.display()),
+        source,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(flow: &str, success: bool) -> AuditEntry {
+        AuditEntry {
+            at: 0,
+            user: "alice".into(),
+            pid: 7,
+            command: format!("rum {flow}"),
+            flow: flow.into(),
+            config_hash: "abc".into(),
+            duration_ms: 1200,
+            success,
+            state: "Running".into(),
+            error: None,
+        }
+    }
+
+    #[test]
+    fn audit_log_appends_and_skips_torn_lines() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("work/audit.jsonl");
+        append(&path, &entry("up", true)).unwrap();
+        append(&path, &entry("destroy", false)).unwrap();
+        std::fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap()
+            .write_all(b"{\"at\":1")
+            .unwrap();
+
+        assert_eq!(
+            read(&path),
+            vec![entry("up", true), entry("destroy", false)]
+        );
+        assert!(read(&dir.path().join("missing.jsonl")).is_empty());
+    }
+
+    #[test]
+    fn timestamps_format_as_utc() {
+        assert_eq!(format_utc(0), "1970-01-01 00:00:00");
+        assert_eq!(format_utc(1_709_210_096), "2024-02-29 12:34:56");
+    }
+}
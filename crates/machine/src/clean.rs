@@ -0,0 +1,477 @@
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use domain::InstanceMetadata;
+use virt::connect::Connect;
+use virt::domain::Domain;
+use virt::error as virt_error;
+use virt::network::Network;
+
+use crate::error::Error;
+use crate::paths;
+
+/// Kind of leftover state found by [`plan`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum CleanKind {
+    WorkDir,
+    Domain,
+    Network,
+    SeedIso,
+    CacheEntry,
+}
+
+impl CleanKind {
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::WorkDir => "workdir",
+            Self::Domain => "domain",
+            Self::Network => "network",
+            Self::SeedIso => "seed",
+            Self::CacheEntry => "cache",
+        }
+    }
+}
+
+/// One piece of state `rum clean` would remove.
+#[derive(Debug, Clone)]
+pub struct CleanItem {
+    pub kind: CleanKind,
+    /// Libvirt object name for domains/networks, filesystem path otherwise.
+    pub target: String,
+    pub reason: String,
+    /// A running domain, which only `--force` stops.
+    pub running: bool,
+}
+
+/// Collect everything that is safe to remove.
+///
+/// A work dir is stale when the config file recorded in it no longer exists,
+/// or when `destroy` left nothing in it but the audit log. Domains are
+/// orphaned when their metadata puts them under this user's data dir and no
+/// live work dir carries their id; the libvirt URI is shared between users,
+/// so domains of other users (or of `sudo rum`) are never touched. Networks
+/// are orphaned when neither a live work dir nor a kept domain carries their
+/// id. Libvirt being unreachable only skips the libvirt part of the scan.
+pub fn plan(libvirt_uri: &str, cache_ttl: Duration) -> Result<Vec<CleanItem>, Error> {
+    let mut items = Vec::new();
+    let mut live_ids = BTreeSet::new();
+    let mut backing_files = BTreeSet::new();
+    let data_dir = paths::data_dir();
+
+    for dir in list_dirs(&data_dir)? {
+        let Some(dir_name) = dir.file_name().and_then(|n| n.to_str()) else {
+            continue;
+        };
+        let id = dir_name.split('-').next().unwrap_or(dir_name).to_string();
+
+        if destroyed(&dir) {
+            items.push(CleanItem {
+                kind: CleanKind::WorkDir,
+                target: dir.display().to_string(),
+                reason: "machine was destroyed".into(),
+                running: false,
+            });
+            continue;
+        }
+        // Work dirs without a recorded config are mid-creation; leave them alone.
+        let Ok(config_path) = std::fs::read_to_string(dir.join("config_path")) else {
+            backing_files.extend(backing_chain(&dir));
+            live_ids.insert(id);
+            continue;
+        };
+        let config_path = config_path.trim();
+        if !Path::new(config_path).exists() {
+            items.push(CleanItem {
+                kind: CleanKind::WorkDir,
+                target: dir.display().to_string(),
+                reason: format!("config {config_path} no longer exists"),
+                running: false,
+            });
+            continue;
+        }
+
+        items.extend(stale_seeds(&dir));
+        backing_files.extend(backing_chain(&dir));
+        live_ids.insert(id);
+    }
+
+    match connect(libvirt_uri) {
+        Ok(conn) => items.extend(orphaned_libvirt(&conn, &live_ids, &data_dir)),
+        Err(error) => tracing::warn!(%error, "skipping libvirt cleanup"),
+    }
+
+    items.extend(expired_cache_entries(
+        &paths::cache_dir(),
+        cache_ttl,
+        &backing_files,
+    )?);
+    Ok(items)
+}
+
+/// Remove every item returned by [`plan`]. Running domains are only
+/// stopped with `force`; without it they are left alone, even when they
+/// started after the scan.
+pub fn apply(libvirt_uri: &str, items: &[CleanItem], force: bool) -> Result<(), Error> {
+    let needs_libvirt = items
+        .iter()
+        .any(|item| matches!(item.kind, CleanKind::Domain | CleanKind::Network));
+    let conn = if needs_libvirt {
+        Some(connect(libvirt_uri)?)
+    } else {
+        None
+    };
+
+    for item in items {
+        match item.kind {
+            CleanKind::WorkDir => remove_path(Path::new(&item.target), true)?,
+            CleanKind::SeedIso | CleanKind::CacheEntry => {
+                remove_path(Path::new(&item.target), false)?
+            }
+            CleanKind::Domain => {
+                let conn = conn
+                    .as_ref()
+                    .expect("libvirt connection for domain cleanup");
+                if let Ok(dom) = Domain::lookup_by_name(conn, &item.target) {
+                    if dom.is_active().unwrap_or(false) {
+                        if !force {
+                            tracing::warn!(
+                                domain = %item.target,
+                                "leaving running domain without --force"
+                            );
+                            continue;
+                        }
+                        let _ = dom.destroy();
+                    }
+                    dom.undefine_flags(virt::sys::VIR_DOMAIN_UNDEFINE_NVRAM)
+                        .map_err(|e| Error::Libvirt {
+                            message: format!("failed to undefine domain '{}': {e}", item.target),
+                            hint: "check libvirt permissions".into(),
+                        })?;
+                }
+            }
+            CleanKind::Network => {
+                let conn = conn
+                    .as_ref()
+                    .expect("libvirt connection for network cleanup");
+                if let Ok(net) = Network::lookup_by_name(conn, &item.target) {
+                    if net.is_active().unwrap_or(false) {
+                        let _ = net.destroy();
+                    }
+                    net.undefine().map_err(|e| Error::Libvirt {
+                        message: format!("failed to undefine network '{}': {e}", item.target),
+                        hint: "check libvirt permissions".into(),
+                    })?;
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn connect(libvirt_uri: &str) -> Result<Connect, Error> {
+    virt_error::clear_error_callback();
+    Connect::open(Some(libvirt_uri)).map_err(|e| Error::Libvirt {
+        message: format!("failed to connect to libvirt: {e}"),
+        hint: format!("ensure libvirtd is running and you have access to {libvirt_uri}"),
+    })
+}
+
+fn list_dirs(root: &Path) -> Result<Vec<PathBuf>, Error> {
+    let entries = match std::fs::read_dir(root) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => {
+            return Err(Error::Io {
+                context: format!("reading {}", root.display()),
+                source: e,
+            });
+        }
+    };
+    let mut dirs: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.is_dir())
+        .collect();
+    dirs.sort();
+    Ok(dirs)
+}
+
+/// Whether `work_dir` holds nothing but the audit log `destroy` keeps.
+fn destroyed(work_dir: &Path) -> bool {
+    let Ok(entries) = std::fs::read_dir(work_dir) else {
+        return false;
+    };
+    let mut names = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.file_name());
+    matches!(names.next(), Some(name) if name == paths::AUDIT_LOG) && names.next().is_none()
+}
+
+/// Seed ISOs in a live work dir that the saved domain XML no longer points at.
+fn stale_seeds(work_dir: &Path) -> Vec<CleanItem> {
+    let Ok(domain_xml) = std::fs::read_to_string(work_dir.join("domain.xml")) else {
+        return Vec::new();
+    };
+    let Ok(entries) = std::fs::read_dir(work_dir) else {
+        return Vec::new();
+    };
+
+    entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let name = entry.file_name().to_str()?.to_string();
+            let is_seed = name.starts_with("seed-") && name.ends_with(".iso");
+            (is_seed && !domain_xml.contains(&name)).then(|| CleanItem {
+                kind: CleanKind::SeedIso,
+                target: entry.path().display().to_string(),
+                reason: "not referenced by the current domain".into(),
+                running: false,
+            })
+        })
+        .collect()
+}
+
+fn orphaned_libvirt(
+    conn: &Connect,
+    live_ids: &BTreeSet<String>,
+    data_dir: &Path,
+) -> Vec<CleanItem> {
+    let mut items = Vec::new();
+    // XML of the domains that stay, to tell whether a shared network is
+    // still attached to anything, and their ids, to keep their own networks.
+    let mut kept = Vec::new();
+    let mut kept_ids = BTreeSet::new();
+
+    for dom in conn.list_all_domains(0).unwrap_or_default() {
+        let (Ok(name), Ok(xml)) = (dom.get_name(), dom.get_xml_desc(0)) else {
+            continue;
+        };
+        match domain::parse_instance_metadata(&xml) {
+            Some(metadata) if orphaned(&metadata, live_ids, data_dir) => {
+                let running = dom.is_active().unwrap_or(false);
+                let reason = format!("no work dir for instance {}", metadata.id);
+                items.push(CleanItem {
+                    kind: CleanKind::Domain,
+                    target: name,
+                    reason: if running {
+                        format!("{reason}; running, needs --force")
+                    } else {
+                        reason
+                    },
+                    running,
+                });
+            }
+            metadata => {
+                kept_ids.extend(metadata.map(|metadata| metadata.id));
+                kept.push(xml);
+            }
+        }
+    }
+
+    for net in conn.list_all_networks(0).unwrap_or_default() {
+        let Ok(name) = net.get_name() else {
+            continue;
+        };
+        if name.starts_with("rum-net-") {
+            if !kept
+                .iter()
+                .any(|xml| domain::attaches_to_network(xml, &name))
+            {
+                items.push(CleanItem {
+                    kind: CleanKind::Network,
+                    reason: "shared network no machine attaches to".into(),
+                    target: name,
+                    running: false,
+                });
+            }
+            continue;
+        }
+        let Some(id) = network_owner(&name) else {
+            continue;
+        };
+        if !live_ids.contains(id) && !kept_ids.contains(id) {
+            items.push(CleanItem {
+                kind: CleanKind::Network,
+                reason: format!("no work dir for instance {id}"),
+                target: name,
+                running: false,
+            });
+        }
+    }
+
+    items
+}
+
+/// Whether the domain described by `metadata` belongs to this user and has
+/// lost its work dir. Domains recorded under another data dir, or defined
+/// before rum recorded the work dir, are never ours to remove.
+fn orphaned(metadata: &InstanceMetadata, live_ids: &BTreeSet<String>, data_dir: &Path) -> bool {
+    let owned = metadata
+        .work_dir
+        .as_deref()
+        .is_some_and(|dir| dir.starts_with(data_dir));
+    owned && !live_ids.contains(&metadata.id)
+}
+
+/// Extract the instance id from a `rum-<id>-<network>` name.
+fn network_owner(name: &str) -> Option<&str> {
+    let (id, network) = name.strip_prefix("rum-")?.split_once('-')?;
+    let is_id = id.len() == 8 && id.bytes().all(|b| b.is_ascii_hexdigit());
+    (is_id && !network.is_empty()).then_some(id)
+}
+
+/// Every image the qcow2 images in `work_dir` are backed by, directly or
+/// through another backing image, canonicalized.
+fn backing_chain(work_dir: &Path) -> BTreeSet<PathBuf> {
+    let mut chain = BTreeSet::new();
+    let Ok(entries) = std::fs::read_dir(work_dir) else {
+        return chain;
+    };
+    let mut pending: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "qcow2"))
+        .collect();
+    while let Some(image) = pending.pop() {
+        let Ok(Some(backing)) = crate::qcow2::backing_file(&image) else {
+            continue;
+        };
+        let backing = std::fs::canonicalize(&backing).unwrap_or(backing);
+        if chain.insert(backing.clone()) {
+            pending.push(backing);
+        }
+    }
+    chain
+}
+
+/// Cache entries older than `ttl`, except images a machine still boots
+/// from through its overlay (`in_use` holds canonical paths).
+fn expired_cache_entries(
+    cache_dir: &Path,
+    ttl: Duration,
+    in_use: &BTreeSet<PathBuf>,
+) -> Result<Vec<CleanItem>, Error> {
+    let entries = match std::fs::read_dir(cache_dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => {
+            return Err(Error::Io {
+                context: format!("reading {}", cache_dir.display()),
+                source: e,
+            });
+        }
+    };
+
+    let now = SystemTime::now();
+    Ok(entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| {
+            let path = std::fs::canonicalize(entry.path()).unwrap_or_else(|_| entry.path());
+            !in_use.contains(&path)
+        })
+        .filter_map(|entry| {
+            let modified = entry.metadata().ok()?.modified().ok()?;
+            let age = now.duration_since(modified).ok()?;
+            (age > ttl).then(|| CleanItem {
+                kind: CleanKind::CacheEntry,
+                target: entry.path().display().to_string(),
+                reason: format!("unused for {} days", age.as_secs() / 86_400),
+                running: false,
+            })
+        })
+        .collect())
+}
+
+fn remove_path(path: &Path, dir: bool) -> Result<(), Error> {
+    let result = if dir {
+        std::fs::remove_dir_all(path)
+    } else {
+        std::fs::remove_file(path)
+    };
+    match result {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(Error::Io {
+            context: format!("removing {}", path.display()),
+            source: e,
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn network_owner_extracts_instance_id() {
+        assert_eq!(network_owner("rum-a1b2c3d4-hostonly"), Some("a1b2c3d4"));
+        assert_eq!(network_owner("rum-a1b2c3d4-"), None);
+        assert_eq!(network_owner("rum-notanid-hostonly"), None);
+        assert_eq!(network_owner("default"), None);
+    }
+
+    #[test]
+    fn only_domains_under_our_data_dir_are_orphaned() {
+        let data_dir = Path::new("/home/alice/rum");
+        let live_ids = BTreeSet::from(["a1b2c3d4".to_string()]);
+        let metadata = |id: &str, work_dir: Option<&str>| InstanceMetadata {
+            id: id.into(),
+            labels: Default::default(),
+            profile: None,
+            work_dir: work_dir.map(PathBuf::from),
+        };
+
+        let ours = metadata("deadbeef", Some("/home/alice/rum/deadbeef-web"));
+        assert!(orphaned(&ours, &live_ids, data_dir));
+
+        let live = metadata("a1b2c3d4", Some("/home/alice/rum/a1b2c3d4"));
+        assert!(!orphaned(&live, &live_ids, data_dir));
+
+        let other_user = metadata("deadbeef", Some("/home/bob/rum/deadbeef"));
+        assert!(!orphaned(&other_user, &live_ids, data_dir));
+
+        let sibling = metadata("deadbeef", Some("/home/alice/rum-old/deadbeef"));
+        assert!(!orphaned(&sibling, &live_ids, data_dir));
+
+        let unrecorded = metadata("deadbeef", None);
+        assert!(!orphaned(&unrecorded, &live_ids, data_dir));
+    }
+
+    #[test]
+    fn only_work_dirs_left_with_just_the_audit_log_are_destroyed() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(!destroyed(dir.path()));
+
+        std::fs::write(dir.path().join(paths::AUDIT_LOG), "{}\n").unwrap();
+        assert!(destroyed(dir.path()));
+
+        // Mid-creation: the overlay is there, the config path not yet.
+        std::fs::write(dir.path().join("overlay.qcow2"), "").unwrap();
+        assert!(!destroyed(dir.path()));
+    }
+
+    #[test]
+    fn expired_cache_keeps_images_backing_an_overlay() {
+        let cache = tempfile::tempdir().unwrap();
+        let work = tempfile::tempdir().unwrap();
+        let base = cache.path().join("base.qcow2");
+        let unused = cache.path().join("unused.qcow2");
+        crate::qcow2::create_qcow2(&base, "1G").unwrap();
+        crate::qcow2::create_qcow2(&unused, "1G").unwrap();
+        crate::qcow2::create_qcow2_overlay(&work.path().join("overlay.qcow2"), &base, None)
+            .unwrap();
+        let old = SystemTime::now() - Duration::from_secs(30 * 86_400);
+        for path in [&base, &unused] {
+            let file = std::fs::File::options().write(true).open(path).unwrap();
+            file.set_modified(old).unwrap();
+        }
+
+        let in_use = backing_chain(work.path());
+        let expired =
+            expired_cache_entries(cache.path(), Duration::from_secs(86_400), &in_use).unwrap();
+        let targets: Vec<&str> = expired.iter().map(|item| item.target.as_str()).collect();
+        assert_eq!(targets, [unused.display().to_string()]);
+    }
+}
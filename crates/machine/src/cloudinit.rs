@@ -1,36 +1,54 @@
+use std::fmt::Write;
 use std::hash::{DefaultHasher, Hash, Hasher};
 use std::path::Path;
 
 use facet_value::{VArray, Value, value};
+use guest::agent::EnvVar;
 
-use crate::config::{BtrfsFs, ResolvedFs, ResolvedMount, SimpleFs, ZfsFs};
+use crate::config::{
+    BtrfsFs, DotFile, GuestConfig, LuksDrive, ResolvedFs, ResolvedMount, SimpleFs, UserConfig,
+    ZfsFs,
+};
 use crate::error::Error;
 use crate::iso9660::{self, IsoFile};
 
 /// Configuration for cloud-init seed ISO generation.
 pub struct SeedConfig<'a> {
     pub hostname: &'a str,
-    pub user_name: &'a str,
-    pub user_groups: &'a [String],
+    /// The main user first, then the extra `[[users]]`.
+    pub users: &'a [UserConfig],
+    /// Files from the users' `dotfiles` directories.
+    pub dotfiles: &'a [DotFile],
+    pub guest: &'a GuestConfig,
+    /// Guest NICs in domain order; every `en*` NIC uses DHCP when empty.
+    pub nics: &'a [NicConfig<'a>],
     pub mounts: &'a [ResolvedMount],
     pub autologin: bool,
     pub ssh_keys: &'a [String],
     pub agent_binary: Option<&'a [u8]>,
+    /// Contents of file-backed provisioning scripts. Not part of the seed;
+    /// hashed so that editing a script file is picked up as a config change.
+    pub provision_files: &'a [String],
 }
 
 /// Compute a short hash of the cloud-init inputs for cache-busting the seed ISO filename.
 pub fn seed_hash(config: &SeedConfig) -> String {
     let mut hasher = DefaultHasher::new();
     config.hostname.hash(&mut hasher);
-    config.user_name.hash(&mut hasher);
-    for g in config.user_groups {
-        g.hash(&mut hasher);
+    for user in config.users {
+        facet_json::to_string(user).hash(&mut hasher);
     }
+    for file in config.dotfiles {
+        (&file.user, &file.path, file.mode, &file.content).hash(&mut hasher);
+    }
+    facet_json::to_string(config.guest).hash(&mut hasher);
+    build_network_config(config.nics).hash(&mut hasher);
     for m in config.mounts {
         m.tag.hash(&mut hasher);
         m.target.hash(&mut hasher);
         m.readonly.hash(&mut hasher);
         m.default.hash(&mut hasher);
+        m.driver.label().hash(&mut hasher);
     }
     config.autologin.hash(&mut hasher);
     for k in config.ssh_keys {
@@ -39,13 +57,82 @@ pub fn seed_hash(config: &SeedConfig) -> String {
     if let Some(agent) = config.agent_binary {
         agent.hash(&mut hasher);
     }
+    config.provision_files.hash(&mut hasher);
     format!("{:016x}", hasher.finish())
 }
 
+/// A guest NIC in the generated `network-config`.
+pub struct NicConfig<'a> {
+    /// Lowercase MAC the NIC is matched by; `None` matches every `en*` NIC.
+    pub mac: Option<String>,
+    /// Static CIDR addresses; the NIC uses DHCP when empty.
+    pub addresses: &'a [String],
+    pub gateway: &'a str,
+    pub nameservers: &'a [String],
+    pub search: &'a [String],
+    pub mtu: Option<u32>,
+    /// Also configure IPv6 through DHCPv6 and router advertisements.
+    pub dhcp6: bool,
+}
+
+fn yaml_list(items: &[String]) -> String {
+    let quoted: Vec<String> = items.iter().map(facet_json::to_string).collect();
+    format!("[{}]", quoted.join(", "))
+}
+
+/// Render network config v2 with one `ethernets` entry per NIC.
+///
+/// There is no outer `network:` wrapper; the NoCloud file is the network
+/// config itself.
+pub fn build_network_config(nics: &[NicConfig]) -> String {
+    let mut yaml = String::from("version: 2\nethernets:\n");
+    if nics.is_empty() {
+        yaml.push_str("  id0:\n    match:\n      name: \"en*\"\n    dhcp4: true\n");
+        return yaml;
+    }
+    for (i, nic) in nics.iter().enumerate() {
+        writeln!(yaml, "  id{i}:\n    match:").unwrap();
+        match &nic.mac {
+            Some(mac) => writeln!(yaml, "      macaddress: \"{mac}\"").unwrap(),
+            None => yaml.push_str("      name: \"en*\"\n"),
+        }
+        writeln!(yaml, "    dhcp4: {}", nic.addresses.is_empty()).unwrap();
+        if nic.dhcp6 {
+            yaml.push_str("    dhcp6: true\n");
+        }
+        if !nic.addresses.is_empty() {
+            writeln!(yaml, "    addresses: {}", yaml_list(nic.addresses)).unwrap();
+        }
+        if !nic.gateway.is_empty() {
+            let default = if nic.gateway.contains(':') {
+                "::/0"
+            } else {
+                "0.0.0.0/0"
+            };
+            writeln!(yaml, "    routes:\n      - to: \"{default}\"").unwrap();
+            writeln!(yaml, "        via: \"{}\"", nic.gateway).unwrap();
+        }
+        if !nic.nameservers.is_empty() || !nic.search.is_empty() {
+            yaml.push_str("    nameservers:\n");
+            if !nic.nameservers.is_empty() {
+                writeln!(yaml, "      addresses: {}", yaml_list(nic.nameservers)).unwrap();
+            }
+            if !nic.search.is_empty() {
+                writeln!(yaml, "      search: {}", yaml_list(nic.search)).unwrap();
+            }
+        }
+        if let Some(mtu) = nic.mtu {
+            writeln!(yaml, "    mtu: {mtu}").unwrap();
+        }
+    }
+    yaml
+}
+
 /// Generate a cloud-init NoCloud seed ISO (ISO 9660 with volume label "CIDATA").
 ///
 /// If `agent_binary` is provided, the agent binary and its systemd service are
-/// included in the ISO and installed via cloud-init runcmd on first boot.
+/// included in the ISO and installed via cloud-init runcmd on first boot, or
+/// written from user-data with `guest.inline_agent`.
 pub async fn generate_seed_iso(
     seed_path: &Path,
     config: &SeedConfig<'_>,
@@ -62,30 +149,31 @@ pub async fn generate_seed_iso(
     let hostname = config.hostname;
     let meta_data = format!("instance-id: {hostname}\nlocal-hostname: {hostname}\n");
     let user_data = build_user_data(config);
-    // Network config v2 for cloud-init NoCloud datasource.
-    // Note: no outer "network:" wrapper — the file IS the network config directly.
-    let network_config =
-        "version: 2\nethernets:\n  id0:\n    match:\n      name: \"en*\"\n    dhcp4: true\n";
+    let network_config = build_network_config(config.nics);
 
     let mut iso_files = vec![
         IsoFile {
             name: "meta-data",
             data: meta_data.as_bytes(),
+            symlink: None,
         },
         IsoFile {
             name: "user-data",
             data: user_data.as_bytes(),
+            symlink: None,
         },
         IsoFile {
             name: "network-config",
             data: network_config.as_bytes(),
+            symlink: None,
         },
     ];
 
-    if let Some(agent) = config.agent_binary {
+    if let Some(agent) = config.agent_binary.filter(|_| !config.guest.inline_agent) {
         iso_files.push(IsoFile {
             name: "rum-agent",
             data: agent,
+            symlink: None,
         });
     }
 
@@ -110,34 +198,96 @@ fn autologin_dropin(user_name: &str) -> String {
     )
 }
 
+/// The `users` entry for one account. `extra_keys` are authorized on top of
+/// the user's own `ssh_keys`.
+fn user_entry(user: &UserConfig, extra_keys: &[String], sudo: bool) -> Value {
+    let locked = user.hashed_passwd.is_none() && user.password.is_none();
+    let mut entry = value!({
+        "name": (user.name.as_str()),
+        "lock_passwd": locked,
+        "shell": (user.shell.as_deref().unwrap_or("/bin/bash")),
+    });
+    let Some(obj) = entry.as_object_mut() else {
+        return entry;
+    };
+    match (&user.hashed_passwd, &user.password) {
+        (Some(hash), _) => {
+            obj.insert("hashed_passwd", Value::from(hash.as_str()));
+        }
+        (None, Some(password)) => {
+            obj.insert("plain_text_passwd", Value::from(password.as_str()));
+        }
+        (None, None) => {}
+    }
+    if sudo {
+        obj.insert("sudo", Value::from("ALL=(ALL) NOPASSWD:ALL"));
+    }
+    if let Some(uid) = user.uid {
+        obj.insert("uid", Value::from(uid.to_string().as_str()));
+    }
+    if !user.groups.is_empty() {
+        obj.insert("groups", Value::from(user.groups.join(",").as_str()));
+    }
+
+    let keys: Vec<&String> = extra_keys.iter().chain(&user.ssh_keys).collect();
+    if !keys.is_empty() {
+        let keys_array = VArray::from_iter(keys.iter().map(|k| Value::from(k.as_str())));
+        obj.insert("ssh_authorized_keys", Value::from(keys_array));
+    }
+    entry
+}
+
+/// Shell command that clones `repo` to `~/.dotfiles` as `user` and installs
+/// it: by running its `install.sh` if there is one, otherwise by copying
+/// the tracked files into the home directory.
+fn dotfiles_clone(user: &str, repo: &str) -> String {
+    let script = format!(
+        "git clone --depth 1 -- {repo} ~/.dotfiles && cd ~/.dotfiles && \
+         if [ -x install.sh ]; then ./install.sh; \
+         else git ls-files -z | xargs -0 cp --parents -t ~; fi",
+        repo = sh_quote(repo),
+    );
+    format!("su - {} -c {}", sh_quote(user), sh_quote(&script))
+}
+
+/// Standard padded base64 of `data`, for `encoding: b64` in `write_files`.
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bytes = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let n = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
 fn build_user_data(config: &SeedConfig) -> String {
     let mounts = config.mounts;
     let autologin = config.autologin;
     let ssh_keys = config.ssh_keys;
     let agent_binary = config.agent_binary;
-    let user_name = config.user_name;
-    let user_groups = config.user_groups;
-    let mut user = value!({
-        "name": (user_name),
-        "plain_text_passwd": (user_name),
-        "lock_passwd": false,
-        "shell": "/bin/bash",
-        "sudo": "ALL=(ALL) NOPASSWD:ALL",
-    });
+    let guest = config.guest;
+    let user_name = config.users.first().map_or("rum", |u| u.name.as_str());
 
-    if !user_groups.is_empty() {
-        let groups_str = user_groups.join(",");
-        if let Some(obj) = user.as_object_mut() {
-            obj.insert("groups", Value::from(groups_str.as_str()));
+    // Only the main user gets sudo and rum's own keys.
+    let users = VArray::from_iter(config.users.iter().enumerate().map(|(i, user)| {
+        if i == 0 {
+            user_entry(user, ssh_keys, true)
+        } else {
+            user_entry(user, &[], false)
         }
-    }
-
-    if !ssh_keys.is_empty() {
-        let keys_array = VArray::from_iter(ssh_keys.iter().map(|k| Value::from(k.as_str())));
-        if let Some(obj) = user.as_object_mut() {
-            obj.insert("ssh_authorized_keys", Value::from(keys_array));
-        }
-    }
+    }));
 
     let mut write_files = VArray::new();
 
@@ -147,6 +297,14 @@ fn build_user_data(config: &SeedConfig) -> String {
             "content": (crate::guest::AGENT_SERVICE),
         }));
     }
+    if let Some(agent) = agent_binary.filter(|_| guest.inline_agent) {
+        write_files.push(value!({
+            "path": "/usr/local/bin/rum-agent",
+            "encoding": "b64",
+            "content": (base64(agent).as_str()),
+            "permissions": "0755",
+        }));
+    }
 
     if autologin {
         let dropin = autologin_dropin(user_name);
@@ -156,6 +314,17 @@ fn build_user_data(config: &SeedConfig) -> String {
         }));
     }
 
+    // Dotfiles are written once the users exist, so their owner resolves.
+    for file in config.dotfiles {
+        write_files.push(value!({
+            "path": (format!("/home/{}/{}", file.user, file.path).as_str()),
+            "content": (file.content.as_str()),
+            "owner": (format!("{0}:{0}", file.user).as_str()),
+            "permissions": (format!("{:04o}", file.mode).as_str()),
+            "defer": true,
+        }));
+    }
+
     // If a mount is marked as default workdir, write a profile.d script to cd into it
     if let Some(default_mount) = mounts.iter().find(|m| m.default) {
         write_files.push(value!({
@@ -175,14 +344,41 @@ fn build_user_data(config: &SeedConfig) -> String {
         ])));
     }
 
-    if agent_binary.is_some() {
+    if agent_binary.is_some() && !guest.inline_agent {
         runcmd.push(value!(["mkdir", "-p", "/mnt/cidata"]));
         runcmd.push(value!(["mount", "-L", "CIDATA", "/mnt/cidata"]));
-        runcmd.push(value!(["install", "-m", "755", "/mnt/cidata/rum-agent", "/usr/local/bin/rum-agent"]));
+        runcmd.push(value!([
+            "install",
+            "-D",
+            "-m",
+            "755",
+            "/mnt/cidata/rum-agent",
+            "/usr/local/bin/rum-agent"
+        ]));
         runcmd.push(value!(["umount", "/mnt/cidata"]));
         runcmd.push(value!(["rmdir", "/mnt/cidata"]));
+    }
+    if agent_binary.is_some() {
         runcmd.push(value!(["systemctl", "daemon-reload"]));
-        runcmd.push(value!(["systemctl", "enable", "--now", "rum-agent.service"]));
+        // NixOS units live in the read-only store; run the agent as a
+        // transient unit until the generated module declares it.
+        runcmd.push(value!([
+            "sh",
+            "-c",
+            (concat!(
+                "systemctl enable --now rum-agent.service || ",
+                "systemd-run --unit=rum-agent /usr/local/bin/rum-agent"
+            ))
+        ]));
+    }
+
+    let repos: Vec<(&str, &str)> = config
+        .users
+        .iter()
+        .filter_map(|user| Some((user.name.as_str(), user.dotfiles_repo()?)))
+        .collect();
+    for (user, repo) in &repos {
+        runcmd.push(value!(["sh", "-c", (dotfiles_clone(user, repo).as_str())]));
     }
 
     if autologin {
@@ -195,25 +391,51 @@ fn build_user_data(config: &SeedConfig) -> String {
     }
 
     let mut config = value!({
-        "users": [user],
+        "users": (Value::from(users)),
         "write_files": (Value::from(write_files)),
         "runcmd": (Value::from(runcmd)),
     });
-
-    // Add virtiofs mount entries
-    if !mounts.is_empty() {
-        let mut mount_entries = VArray::new();
-        for m in mounts {
-            let entry = VArray::from_iter([
-                Value::from(m.tag.as_str()),
-                Value::from(m.target.as_str()),
-                Value::from("virtiofs"),
-                Value::from("defaults,nofail"),
-                Value::from("0"),
-                Value::from("0"),
-            ]);
-            mount_entries.push(Value::from(entry));
+    if let Some(obj) = config.as_object_mut() {
+        if !repos.is_empty() {
+            obj.insert("packages", value!(["git"]));
+        }
+        if let Some(timezone) = &guest.timezone {
+            obj.insert("timezone", Value::from(timezone.as_str()));
+        }
+        if let Some(locale) = &guest.locale {
+            obj.insert("locale", Value::from(locale.as_str()));
         }
+        if !guest.ntp_servers.is_empty() {
+            let servers =
+                VArray::from_iter(guest.ntp_servers.iter().map(|s| Value::from(s.as_str())));
+            obj.insert(
+                "ntp",
+                value!({
+                    "enabled": true,
+                    "servers": (Value::from(servers)),
+                }),
+            );
+        }
+    }
+
+    // Add virtiofs and 9p mount entries; sshfs shares are mounted by their
+    // boot script once the network is up.
+    let mut mount_entries = VArray::new();
+    for m in mounts {
+        let Some((fs_type, options)) = m.driver.fs_options() else {
+            continue;
+        };
+        let entry = VArray::from_iter([
+            Value::from(m.tag.as_str()),
+            Value::from(m.target.as_str()),
+            Value::from(fs_type),
+            Value::from(options),
+            Value::from("0"),
+            Value::from("0"),
+        ]);
+        mount_entries.push(Value::from(entry));
+    }
+    if !mount_entries.is_empty() {
         if let Some(obj) = config.as_object_mut() {
             obj.insert("mounts", Value::from(mount_entries));
         }
@@ -226,59 +448,327 @@ fn build_user_data(config: &SeedConfig) -> String {
     format!("#cloud-config\n{yaml}")
 }
 
-pub fn build_drive_script(fs: &[ResolvedFs]) -> String {
-    use std::collections::BTreeSet;
+/// Shell line that makes sure `mkfs.<fs_type>` exists, installing the
+/// providing package where we know it.
+fn mkfs_tool_check(fs_type: &str) -> String {
+    let package = match fs_type {
+        "ext4" | "ext3" | "ext2" => "e2fsprogs",
+        "xfs" => "xfsprogs",
+        "ntfs" => "ntfs-3g",
+        "vfat" => "dosfstools",
+        _ => {
+            return format!(
+                "command -v mkfs.{fs_type} >/dev/null 2>&1 || echo \"rum: mkfs.{fs_type} not found\" >&2"
+            );
+        }
+    };
+    format!("command -v mkfs.{fs_type} >/dev/null 2>&1 || install_pkg {package}")
+}
+
+/// Single-quote `value` for `sh`.
+pub(crate) fn sh_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+/// Variable the passphrase of the `index`th encrypted drive reaches the
+/// guest scripts in.
+fn luks_key_var(index: usize) -> String {
+    format!("RUM_LUKS_KEY_{index}")
+}
+
+/// Environment carrying the passphrases of `luks` to the scripts built for
+/// them. The agent hands it to the script without writing it to the guest
+/// disk, so scripts that need it are not replayed on boot.
+pub fn luks_key_env(luks: &[LuksDrive]) -> Vec<EnvVar> {
+    luks.iter()
+        .enumerate()
+        .map(|(index, drive)| EnvVar {
+            name: luks_key_var(index),
+            value: drive.key.clone(),
+        })
+        .collect()
+}
+
+/// Shell lines that open the `index`th LUKS mapping if it is not open yet.
+/// The passphrase is piped through stdin so it never touches the guest disk.
+fn luks_open(index: usize, drive: &LuksDrive) -> String {
+    format!(
+        "[ -e \"/dev/mapper/{mapper}\" ] || printf '%s' \"${var}\" | \
+         cryptsetup open --key-file=- \"{dev}\" \"{mapper}\"\n",
+        mapper = drive.mapper,
+        dev = drive.dev,
+        var = luks_key_var(index),
+    )
+}
+
+/// Script that reopens encrypted drives and mounts their filesystems. The
+/// key is not stored in the guest, so nothing is unlocked until the host
+/// has the agent run this with [`luks_key_env`] after every boot.
+pub fn build_unlock_script(luks: &[LuksDrive]) -> String {
+    let mut script = String::from("#!/usr/bin/env sh\nset -eu\n\n");
+    for (index, drive) in luks.iter().enumerate() {
+        script.push_str(&luks_open(index, drive));
+    }
+    script.push_str("mount -a\n");
+    script
+}
+
+/// Boot-time script that grows LUKS mappings and filesystems to fill their
+/// drives after the host enlarged the images. Every step is a no-op when the
+/// size did not change, so it is safe to run on each boot.
+pub fn build_grow_script(fs: &[ResolvedFs], luks: &[LuksDrive]) -> String {
+    use std::fmt::Write;
+
+    let mut script = String::from("#!/usr/bin/env sh\nset -eu\n\n");
+    for (index, drive) in luks.iter().enumerate() {
+        writeln!(
+            script,
+            "printf '%s' \"${}\" | cryptsetup resize --key-file=- \"{}\"",
+            luks_key_var(index),
+            drive.mapper
+        )
+        .unwrap();
+    }
+
+    for entry in fs {
+        match entry {
+            ResolvedFs::Simple(s) => match s.filesystem.as_str() {
+                "ext2" | "ext3" | "ext4" => {
+                    writeln!(script, "resize2fs \"{}\"", s.dev).unwrap();
+                }
+                "xfs" => writeln!(script, "xfs_growfs \"{}\"", s.target).unwrap(),
+                "btrfs" => {
+                    writeln!(script, "btrfs filesystem resize max \"{}\"", s.target).unwrap();
+                }
+                other => {
+                    writeln!(script, "echo \"rum: cannot grow {other} on {}\" >&2", s.dev)
+                        .unwrap();
+                }
+            },
+            ResolvedFs::Zfs(z) => {
+                for dev in &z.devs {
+                    writeln!(script, "zpool online -e \"{}\" \"{dev}\"", z.pool).unwrap();
+                }
+            }
+            ResolvedFs::Btrfs(b) => {
+                writeln!(
+                    script,
+                    "for id in $(btrfs filesystem show --raw \"{0}\" | \
+                     awk '/devid/ {{print $2}}'); do \
+                     btrfs filesystem resize \"$id:max\" \"{0}\"; done",
+                    b.target
+                )
+                .unwrap();
+            }
+        }
+    }
+    script
+}
+
+/// Script header defining `install_pkg`, which maps onto the guest's
+/// package manager based on `/etc/os-release`.
+const INSTALL_PKG_PRELUDE: &str = "#!/usr/bin/env sh\nset -eu\n\n\
+     . /etc/os-release\n\
+     install_pkg() {\n\
+     \x20 case \"$ID\" in\n\
+     \x20   ubuntu|debian) DEBIAN_FRONTEND=noninteractive apt-get install -y \"$@\" ;;\n\
+     \x20   arch)          pacman -S --noconfirm \"$@\" ;;\n\
+     \x20   fedora)        dnf install -y \"$@\" ;;\n\
+     \x20   alpine)        apk add \"$@\" ;;\n\
+     \x20   nixos) echo \"rum: $* must come from the NixOS module\" >&2; exit 1 ;;\n\
+     \x20   *) echo \"rum: unsupported OS '$ID' for package install\" >&2; exit 1 ;;\n\
+     \x20 esac\n\
+     }\n\n";
+
+/// Install `[provision] packages` with the guest's package manager.
+pub fn build_packages_script(packages: &[String]) -> String {
+    let mut script = String::from(INSTALL_PKG_PRELUDE);
+    // Cloud images ship without package lists (apt) or with stale ones.
+    script.push_str(
+        "case \"$ID\" in\n\
+         \x20 ubuntu|debian) apt-get update ;;\n\
+         \x20 arch)          pacman -Sy ;;\n\
+         \x20 alpine)        apk update ;;\n\
+         esac\n",
+    );
+    let quoted: Vec<String> = packages.iter().map(|p| sh_quote(p)).collect();
+    script.push_str(&format!("install_pkg {}\n", quoted.join(" ")));
+    script
+}
+
+/// Install the `[docker]` engine with its API on the socket the host forwards,
+/// [`DOCKER_SOCKET`](crate::config::DOCKER_SOCKET) or
+/// [`PODMAN_SOCKET`](crate::config::PODMAN_SOCKET), with `user_name` allowed
+/// to use docker's socket inside the guest. Nothing listens on TCP.
+pub fn build_docker_script(engine: &str, user_name: &str) -> String {
+    let mut script = String::from(INSTALL_PKG_PRELUDE);
+    script.push_str(
+        "if ! command -v systemctl >/dev/null 2>&1; then\n\
+         \x20 echo \"rum: [docker] needs a guest with systemd\" >&2\n\
+         \x20 exit 1\n\
+         fi\n\n",
+    );
+    if engine == "podman" {
+        script.push_str(
+            "if ! command -v podman >/dev/null 2>&1; then\n\
+             \x20 case \"$ID\" in\n\
+             \x20   ubuntu|debian) apt-get update ;;\n\
+             \x20 esac\n\
+             \x20 install_pkg podman\n\
+             fi\n\n",
+        );
+        // Earlier versions served the API on TCP from a unit of their own.
+        script.push_str(
+            "if [ -e /etc/systemd/system/rum-podman.service ]; then\n\
+             \x20 systemctl disable --now rum-podman.service\n\
+             \x20 rm -f /etc/systemd/system/rum-podman.service\n\
+             fi\n\
+             systemctl daemon-reload\n\
+             systemctl enable --now podman.socket\n",
+        );
+        return script;
+    }
+
+    script.push_str(
+        "if ! command -v dockerd >/dev/null 2>&1; then\n\
+         \x20 case \"$ID\" in\n\
+         \x20   ubuntu|debian) apt-get update; install_pkg docker.io ;;\n\
+         \x20   fedora)        install_pkg moby-engine ;;\n\
+         \x20   *)             install_pkg docker ;;\n\
+         \x20 esac\n\
+         fi\n\n",
+    );
+    // The packaged units start dockerd through docker.socket. Earlier
+    // versions added a TCP listener in a drop-in.
+    writeln!(
+        script,
+        "rm -f /etc/systemd/system/docker.service.d/rum.conf\n\
+         getent group docker >/dev/null || groupadd docker\n\
+         usermod -aG docker {user}\n\
+         systemctl daemon-reload\n\
+         systemctl enable docker.socket docker.service\n\
+         systemctl restart docker.service",
+        user = sh_quote(user_name),
+    )
+    .unwrap();
+    script
+}
+
+/// Variable [`build_sshfs_script`] reads the private key from.
+const SSHFS_KEY_VAR: &str = "RUM_SSHFS_KEY";
+
+/// Environment carrying the sshfs private `key` to [`build_sshfs_script`].
+/// Like the LUKS passphrases, it never reaches the guest disk.
+pub fn sshfs_key_env(key: &str) -> Vec<EnvVar> {
+    vec![EnvVar {
+        name: SSHFS_KEY_VAR.into(),
+        value: key.trim_end().to_string(),
+    }]
+}
+
+/// The `authorized_keys` line for the sshfs `public_key`: the key may only
+/// speak SFTP, with no shell, forwarding or pty.
+pub fn sshfs_authorized_key(public_key: &str) -> String {
+    format!("restrict,command=\"internal-sftp\" {}", public_key.trim())
+}
+
+/// Mount `sshfs` shares from the host, reached as the guest's default
+/// gateway. Shares that are already mounted are left alone.
+///
+/// The private key comes from [`sshfs_key_env`] and is kept on the guest's
+/// tmpfs only while the machine runs.
+pub fn build_sshfs_script(mounts: &[ResolvedMount], user_name: &str, host_user: &str) -> String {
     use std::fmt::Write;
 
-    let mut script = String::from(
-        "#!/usr/bin/env sh\nset -eu\n\n\
-         . /etc/os-release\n\
-         install_pkg() {\n\
+    let mut script = String::from(INSTALL_PKG_PRELUDE);
+    script.push_str(
+        "if ! command -v sshfs >/dev/null 2>&1; then\n\
          \x20 case \"$ID\" in\n\
-         \x20   ubuntu|debian) DEBIAN_FRONTEND=noninteractive apt-get install -y \"$@\" ;;\n\
-         \x20   arch)          pacman -S --noconfirm \"$@\" ;;\n\
-         \x20   fedora)        dnf install -y \"$@\" ;;\n\
-         \x20   alpine)        apk add \"$@\" ;;\n\
-         \x20   *) echo \"rum: unsupported OS '$ID' for package install\" >&2; exit 1 ;;\n\
+         \x20   ubuntu|debian) apt-get update; install_pkg sshfs ;;\n\
+         \x20   fedora)        install_pkg fuse-sshfs ;;\n\
+         \x20   *)             install_pkg sshfs ;;\n\
          \x20 esac\n\
-         }\n\n",
+         fi\n\n",
     );
+    writeln!(
+        script,
+        "rm -f /root/.ssh/rum_sshfs\n\
+         key=/run/rum/sshfs-key\n\
+         mkdir -p /run/rum\n\
+         (umask 077 && printf '%s\\n' \"${SSHFS_KEY_VAR}\" >\"$key\")\n\
+         host=$(ip route show default | awk '{{ print $3; exit }}')\n\
+         uid=$(id -u {user})\n\
+         gid=$(id -g {user})\n\
+         mount_sshfs() {{\n\
+         \x20 mountpoint -q \"$2\" && return 0\n\
+         \x20 mkdir -p \"$2\"\n\
+         \x20 sshfs {host_user}@\"$host:$1\" \"$2\" -o \"IdentityFile=$key,\
+         StrictHostKeyChecking=accept-new,reconnect,ServerAliveInterval=15,\
+         allow_other,uid=$uid,gid=$gid$3\"\n\
+         }}",
+        user = sh_quote(user_name),
+        host_user = sh_quote(host_user),
+    )
+    .unwrap();
+    for m in mounts {
+        writeln!(
+            script,
+            "mount_sshfs {} {} {}",
+            sh_quote(&m.source.display().to_string()),
+            sh_quote(&m.target),
+            if m.readonly { ",ro" } else { "''" },
+        )
+        .unwrap();
+    }
+    script
+}
+
+/// Guard a provision step's script with its `when` condition, so the step
+/// exits successfully without running when the condition fails.
+pub fn build_step_script(script: &str, when: Option<&str>) -> String {
+    match when {
+        Some(condition) => format!(
+            "if ! {{ {condition}\n}} >/dev/null 2>&1; then\n\
+             \x20 echo {}\n\
+             \x20 exit 0\nfi\n{script}",
+            sh_quote(&format!("rum: skipped, condition not met: {condition}")),
+        ),
+        None => script.to_string(),
+    }
+}
+
+pub fn build_drive_script(fs: &[ResolvedFs], luks: &[LuksDrive]) -> String {
+    use std::fmt::Write;
+
+    let mut script = String::from(INSTALL_PKG_PRELUDE);
 
-    // Collect needed filesystem types for tool checks
-    let mut need_simple: BTreeSet<&str> = BTreeSet::new();
+    // Collect needed filesystem types for tool checks. Simple filesystems
+    // check for their mkfs tool inside the per-device block instead, so a
+    // drive that was already formatted on the host never installs packages.
     let mut need_zfs = false;
     let mut need_btrfs = false;
 
     for entry in fs {
         match entry {
-            ResolvedFs::Simple(s) => {
-                need_simple.insert(&s.filesystem);
-            }
+            ResolvedFs::Simple(_) => {}
             ResolvedFs::Zfs(_) => need_zfs = true,
             ResolvedFs::Btrfs(_) => need_btrfs = true,
         }
     }
 
-    // Emit tool checks
-    for fs_type in &need_simple {
-        match *fs_type {
-            "ext4" | "ext3" | "ext2" => {
-                writeln!(
-                    script,
-                    "command -v mkfs.{fs_type} >/dev/null 2>&1 || install_pkg e2fsprogs"
-                )
-                .unwrap();
-            }
-            "xfs" => script.push_str("command -v mkfs.xfs >/dev/null 2>&1 || install_pkg xfsprogs\n"),
-            "ntfs" => script.push_str("command -v mkfs.ntfs >/dev/null 2>&1 || install_pkg ntfs-3g\n"),
-            "vfat" => script.push_str("command -v mkfs.vfat >/dev/null 2>&1 || install_pkg dosfstools\n"),
-            _ => {
-                writeln!(
-                    script,
-                    "command -v mkfs.{fs_type} >/dev/null 2>&1 || echo \"rum: mkfs.{fs_type} not found\" >&2"
-                )
-                .unwrap();
-            }
+    if !luks.is_empty() {
+        script.push_str("command -v cryptsetup >/dev/null 2>&1 || install_pkg cryptsetup\n");
+        for (index, drive) in luks.iter().enumerate() {
+            writeln!(script, "if ! cryptsetup isLuks \"{}\"; then", drive.dev).unwrap();
+            writeln!(
+                script,
+                "  printf '%s' \"${}\" | cryptsetup luksFormat --batch-mode --key-file=- \"{}\"",
+                luks_key_var(index),
+                drive.dev
+            )
+            .unwrap();
+            script.push_str("fi\n");
+            script.push_str(&luks_open(index, drive));
         }
     }
 
@@ -311,12 +801,14 @@ pub fn build_drive_script(fs: &[ResolvedFs]) -> String {
             s.dev
         )
         .unwrap();
+        writeln!(script, "  {}", mkfs_tool_check(&s.filesystem)).unwrap();
         writeln!(script, "  mkfs.{} \"{}\"", s.filesystem, s.dev).unwrap();
         script.push_str("fi\n");
         writeln!(script, "mkdir -p \"{}\"", s.target).unwrap();
         writeln!(
             script,
-            "grep -q \"{}\" /etc/fstab || echo \"{} {} {} defaults,nofail 0 2\" >> /etc/fstab",
+            "[ -e /etc/NIXOS ] || grep -q \"{}\" /etc/fstab || \
+             echo \"{} {} {} defaults,nofail 0 2\" >> /etc/fstab",
             s.dev, s.dev, s.target, s.filesystem
         )
         .unwrap();
@@ -367,7 +859,8 @@ pub fn build_drive_script(fs: &[ResolvedFs]) -> String {
         writeln!(script, "mkdir -p \"{}\"", b.target).unwrap();
         writeln!(
             script,
-            "grep -q \"{}\" /etc/fstab || echo \"{} {} btrfs defaults,nofail 0 0\" >> /etc/fstab",
+            "[ -e /etc/NIXOS ] || grep -q \"{}\" /etc/fstab || \
+             echo \"{} {} btrfs defaults,nofail 0 0\" >> /etc/fstab",
             first_dev, first_dev, b.target
         )
         .unwrap();
@@ -388,16 +881,38 @@ pub fn build_drive_script(fs: &[ResolvedFs]) -> String {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::config::MountDriver;
+
+    static DEFAULT_USERS: std::sync::LazyLock<[UserConfig; 1]> =
+        std::sync::LazyLock::new(|| [UserConfig::default()]);
+
+    static DEFAULT_GUEST: GuestConfig = GuestConfig {
+        timezone: None,
+        locale: None,
+        ntp_servers: Vec::new(),
+        inline_agent: false,
+    };
+
+    fn user(name: &str, groups: &[&str]) -> UserConfig {
+        UserConfig {
+            name: name.into(),
+            groups: groups.iter().map(|g| g.to_string()).collect(),
+            ..Default::default()
+        }
+    }
 
     fn default_seed_config() -> SeedConfig<'static> {
         SeedConfig {
             hostname: "",
-            user_name: "rum",
-            user_groups: &[],
+            users: &*DEFAULT_USERS,
+            dotfiles: &[],
+            guest: &DEFAULT_GUEST,
+            nics: &[],
             mounts: &[],
             autologin: false,
             ssh_keys: &[],
             agent_binary: None,
+            provision_files: &[],
         }
     }
 
@@ -413,7 +928,23 @@ mod tests {
         let config = default_seed_config();
         let ud = build_user_data(&config);
         assert!(ud.contains("name: rum"));
-        assert!(ud.contains("lock_passwd: false"));
+        assert!(ud.contains("lock_passwd: true"), "no password means key-only login: {ud}");
+        assert!(!ud.contains("plain_text_passwd"), "got: {ud}");
+    }
+
+    #[test]
+    fn user_data_sets_a_configured_password() {
+        let users = [UserConfig {
+            password: Some("secret".into()),
+            ..user("myuser", &[])
+        }];
+        let config = SeedConfig {
+            users: &users,
+            ..default_seed_config()
+        };
+        let ud = build_user_data(&config);
+        assert!(ud.contains("lock_passwd: false"), "got: {ud}");
+        assert!(ud.contains("plain_text_passwd: secret"), "got: {ud}");
     }
 
     #[test]
@@ -454,6 +985,7 @@ mod tests {
             readonly: false,
             tag: "mnt_project".into(),
             default: false,
+            driver: MountDriver::Virtiofs,
         }];
         let config = SeedConfig { mounts: &mounts, ..default_seed_config() };
         let ud = build_user_data(&config);
@@ -473,7 +1005,7 @@ mod tests {
             dev: "/dev/vdb".into(),
             target: "/mnt/data".into(),
         })];
-        let script = build_drive_script(&fs);
+        let script = build_drive_script(&fs, &[]);
         assert!(script.starts_with("#!/usr/bin/env sh"));
         assert!(script.contains("install_pkg"));
         assert!(script.contains("e2fsprogs"));
@@ -483,6 +1015,42 @@ mod tests {
         assert!(script.contains("blkid")); // idempotency guard
     }
 
+    #[test]
+    fn drive_script_luks_formats_and_opens_before_mkfs() {
+        let luks = vec![LuksDrive {
+            name: "data".into(),
+            dev: "/dev/vdb".into(),
+            mapper: "rum-data".into(),
+            key: "it's secret".into(),
+        }];
+        let fs = vec![ResolvedFs::Simple(SimpleFs {
+            filesystem: "ext4".into(),
+            dev: "/dev/mapper/rum-data".into(),
+            target: "/mnt/data".into(),
+        })];
+        let script = build_drive_script(&fs, &luks);
+        let format = script.find("cryptsetup luksFormat").unwrap();
+        let open = script.find("cryptsetup open").unwrap();
+        let mkfs = script.find("mkfs.ext4 \"/dev/mapper/rum-data\"").unwrap();
+        assert!(format < open && open < mkfs, "got:\n{script}");
+        assert!(script.contains("cryptsetup isLuks \"/dev/vdb\""));
+        assert!(
+            script.contains(r#"printf '%s' "$RUM_LUKS_KEY_0""#),
+            "got:\n{script}"
+        );
+        assert!(!script.contains("secret"));
+        let env = luks_key_env(&luks);
+        assert_eq!(env.len(), 1);
+        assert_eq!(
+            (env[0].name.as_str(), env[0].value.as_str()),
+            ("RUM_LUKS_KEY_0", "it's secret")
+        );
+
+        let unlock = build_unlock_script(&luks);
+        assert!(unlock.contains("cryptsetup open --key-file=- \"/dev/vdb\" \"rum-data\""));
+        assert!(!unlock.contains("luksFormat") && !unlock.contains("secret"));
+    }
+
     #[test]
     fn drive_script_zfs_mirror() {
         let fs = vec![ResolvedFs::Zfs(ZfsFs {
@@ -491,7 +1059,7 @@ mod tests {
             target: "/mnt/logs".into(),
             mode: Some("mirror".into()),
         })];
-        let script = build_drive_script(&fs);
+        let script = build_drive_script(&fs, &[]);
         assert!(script.contains("zfsutils-linux")); // ubuntu/debian package
         assert!(script.contains("modprobe zfs"));
         assert!(script.contains("zpool list \"logspool\"")); // idempotency guard
@@ -500,6 +1068,79 @@ mod tests {
         assert!(script.contains("mirror \"/dev/vdc\" \"/dev/vdd\""));
     }
 
+    #[test]
+    fn packages_script_installs_through_shim() {
+        let packages = vec!["git".to_string(), "build-essential".to_string()];
+        let script = build_packages_script(&packages);
+        assert!(script.contains("install_pkg() {"));
+        assert!(script.contains("apt-get update"));
+        assert!(
+            script.ends_with("install_pkg 'git' 'build-essential'\n"),
+            "got:\n{script}"
+        );
+    }
+
+    #[test]
+    fn docker_script_serves_api_on_a_socket_only() {
+        let script = build_docker_script("docker", "rum");
+        assert!(script.contains("install_pkg docker.io"));
+        assert!(script.contains("systemctl enable docker.socket docker.service"));
+        assert!(script.contains("usermod -aG docker 'rum'"));
+        assert!(!script.contains("tcp://"), "got:\n{script}");
+
+        let script = build_docker_script("podman", "rum");
+        assert!(script.contains("install_pkg podman"));
+        assert!(script.contains("systemctl enable --now podman.socket"));
+        assert!(!script.contains("dockerd"));
+        assert!(!script.contains("tcp:"), "got:\n{script}");
+    }
+
+    #[test]
+    fn step_script_guards_on_condition() {
+        assert_eq!(build_step_script("make\n", None), "make\n");
+
+        let script = build_step_script("make\n", Some("command -v make"));
+        assert!(script.starts_with("if ! { command -v make\n} >/dev/null 2>&1; then\n"));
+        assert!(script.contains("exit 0\nfi\nmake\n"), "got:\n{script}");
+    }
+
+    #[test]
+    fn grow_script_resizes_each_filesystem() {
+        let luks = vec![LuksDrive {
+            name: "data".into(),
+            dev: "/dev/vdb".into(),
+            mapper: "rum-data".into(),
+            key: "secret".into(),
+        }];
+        let fs = vec![
+            ResolvedFs::Simple(SimpleFs {
+                filesystem: "ext4".into(),
+                dev: "/dev/mapper/rum-data".into(),
+                target: "/mnt/data".into(),
+            }),
+            ResolvedFs::Simple(SimpleFs {
+                filesystem: "xfs".into(),
+                dev: "/dev/vdc".into(),
+                target: "/mnt/xfs".into(),
+            }),
+            ResolvedFs::Zfs(ZfsFs {
+                pool: "tank".into(),
+                devs: vec!["/dev/vdd".into()],
+                target: "/mnt/tank".into(),
+                mode: None,
+            }),
+        ];
+        let script = build_grow_script(&fs, &luks);
+        let crypt = script
+            .find("cryptsetup resize --key-file=- \"rum-data\"")
+            .unwrap();
+        let ext = script.find("resize2fs \"/dev/mapper/rum-data\"").unwrap();
+        assert!(crypt < ext, "got:\n{script}");
+        assert!(script.contains("xfs_growfs \"/mnt/xfs\""));
+        assert!(script.contains("zpool online -e \"tank\" \"/dev/vdd\""));
+        assert!(!script.contains("mkfs"));
+    }
+
     #[test]
     fn drive_script_btrfs_raid1() {
         let fs = vec![ResolvedFs::Btrfs(BtrfsFs {
@@ -507,7 +1148,7 @@ mod tests {
             target: "/mnt/fast".into(),
             mode: Some("raid1".into()),
         })];
-        let script = build_drive_script(&fs);
+        let script = build_drive_script(&fs, &[]);
         assert!(script.contains("btrfs-progs"));
         assert!(script.contains("mkfs.btrfs -d raid1 \"/dev/vde\" \"/dev/vdf\""));
         assert!(script.contains("mkdir -p \"/mnt/fast\""));
@@ -535,7 +1176,7 @@ mod tests {
                 mode: None,
             }),
         ];
-        let script = build_drive_script(&fs);
+        let script = build_drive_script(&fs, &[]);
 
         // ext4: all paths must be double-quoted
         assert!(script.contains("mkdir -p \"/mnt/my data\""));
@@ -568,6 +1209,43 @@ mod tests {
         assert!(ud.contains("ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAITest extra-key"));
     }
 
+    #[test]
+    fn user_data_mounts_9p_and_leaves_sshfs_to_boot_script() {
+        let mounts = vec![
+            ResolvedMount {
+                source: std::path::PathBuf::from("/data"),
+                target: "/mnt/data".into(),
+                readonly: false,
+                tag: "mnt_data".into(),
+                default: false,
+                driver: MountDriver::NineP,
+            },
+            ResolvedMount {
+                source: std::path::PathBuf::from("/home/user/notes"),
+                target: "/mnt/notes".into(),
+                readonly: true,
+                tag: "mnt_notes".into(),
+                default: false,
+                driver: MountDriver::Sshfs,
+            },
+        ];
+        let config = SeedConfig { mounts: &mounts, ..default_seed_config() };
+        let ud = build_user_data(&config);
+        assert!(ud.contains("trans=virtio"), "got:\n{ud}");
+        assert!(!ud.contains("mnt_notes"));
+
+        let script = build_sshfs_script(&mounts[1..], "rum", "alice");
+        assert!(!script.contains("KEY\n"));
+        assert!(script.contains("printf '%s\\n' \"$RUM_SSHFS_KEY\" >\"$key\""));
+        assert_eq!(sshfs_key_env("KEY\n")[0].value, "KEY");
+        assert_eq!(
+            sshfs_authorized_key("ssh-ed25519 AAAA rum-sshfs\n"),
+            "restrict,command=\"internal-sftp\" ssh-ed25519 AAAA rum-sshfs"
+        );
+        assert!(script.contains("sshfs 'alice'@\"$host:$1\""));
+        assert!(script.contains("mount_sshfs '/home/user/notes' '/mnt/notes' ,ro"));
+    }
+
     #[test]
     fn user_data_without_ssh_keys_omits_authorized_keys() {
         let config = default_seed_config();
@@ -583,6 +1261,7 @@ mod tests {
             readonly: false,
             tag: "mnt_project".into(),
             default: true,
+            driver: MountDriver::Virtiofs,
         }];
         let config = SeedConfig { mounts: &mounts, ..default_seed_config() };
         let ud = build_user_data(&config);
@@ -592,8 +1271,11 @@ mod tests {
 
     #[test]
     fn user_data_with_groups() {
-        let groups = vec!["docker".to_string(), "video".to_string()];
-        let config = SeedConfig { user_groups: &groups, ..default_seed_config() };
+        let users = [user("rum", &["docker", "video"])];
+        let config = SeedConfig {
+            users: &users,
+            ..default_seed_config()
+        };
         let ud = build_user_data(&config);
         assert!(ud.contains("groups: docker,video"), "user-data should contain groups: {ud}");
     }
@@ -607,16 +1289,21 @@ mod tests {
 
     #[test]
     fn user_data_custom_user_name() {
-        let config = SeedConfig { user_name: "myuser", ..default_seed_config() };
+        let users = [user("myuser", &[])];
+        let config = SeedConfig {
+            users: &users,
+            ..default_seed_config()
+        };
         let ud = build_user_data(&config);
         assert!(ud.contains("name: myuser"), "user-data should use custom user name: {ud}");
-        assert!(ud.contains("plain_text_passwd: myuser"), "password should match user name: {ud}");
+        assert!(!ud.contains("plain_text_passwd"), "no default password: {ud}");
     }
 
     #[test]
     fn user_data_custom_user_autologin() {
+        let users = [user("myuser", &[])];
         let config = SeedConfig {
-            user_name: "myuser",
+            users: &users,
             autologin: true,
             ..default_seed_config()
         };
@@ -627,15 +1314,191 @@ mod tests {
     #[test]
     fn seed_hash_changes_with_user_name() {
         let config1 = default_seed_config();
-        let config2 = SeedConfig { user_name: "other", ..default_seed_config() };
+        let users = [user("other", &[])];
+        let config2 = SeedConfig {
+            users: &users,
+            ..default_seed_config()
+        };
+        assert_ne!(seed_hash(&config1), seed_hash(&config2));
+    }
+
+    #[test]
+    fn seed_hash_changes_with_provision_files() {
+        let files = vec!["apt-get install -y git\n".to_string()];
+        let config1 = default_seed_config();
+        let config2 = SeedConfig {
+            provision_files: &files,
+            ..default_seed_config()
+        };
         assert_ne!(seed_hash(&config1), seed_hash(&config2));
     }
 
     #[test]
     fn seed_hash_changes_with_groups() {
-        let groups = vec!["docker".to_string()];
+        let users = [user("rum", &["docker"])];
         let config1 = default_seed_config();
-        let config2 = SeedConfig { user_groups: &groups, ..default_seed_config() };
+        let config2 = SeedConfig {
+            users: &users,
+            ..default_seed_config()
+        };
         assert_ne!(seed_hash(&config1), seed_hash(&config2));
     }
+
+    #[test]
+    fn user_data_creates_extra_users_with_their_settings() {
+        let users = [
+            UserConfig {
+                uid: Some(1500),
+                shell: Some("/usr/bin/zsh".into()),
+                ssh_keys: vec!["ssh-ed25519 AAAA main".into()],
+                ..user("rum", &[])
+            },
+            UserConfig {
+                hashed_passwd: Some("$6$salt$hash".into()),
+                dotfiles: Some("https://example.com/dots.git".into()),
+                ..user("alice", &["video"])
+            },
+        ];
+        let dotfiles = [DotFile {
+            user: "rum".into(),
+            path: ".vimrc".into(),
+            mode: 0o644,
+            content: "set number\n".into(),
+        }];
+        let keys = ["ssh-ed25519 AAAA rum".to_string()];
+        let config = SeedConfig {
+            users: &users,
+            dotfiles: &dotfiles,
+            ssh_keys: &keys,
+            ..default_seed_config()
+        };
+        let ud = build_user_data(&config);
+
+        let main = &ud[ud.find("name: rum").unwrap()..ud.find("name: alice").unwrap()];
+        assert!(
+            main.contains("uid: '1500'") || main.contains("uid: \"1500\""),
+            "got: {ud}"
+        );
+        assert!(main.contains("shell: /usr/bin/zsh"));
+        assert!(main.contains("AAAA rum") && main.contains("AAAA main"));
+        assert!(main.contains("NOPASSWD"));
+
+        let extra = &ud[ud.find("name: alice").unwrap()..ud.find("write_files:").unwrap()];
+        assert!(extra.contains("hashed_passwd:"), "got: {ud}");
+        assert!(extra.contains("lock_passwd: false"));
+        assert!(!extra.contains("plain_text_passwd"));
+        assert!(!extra.contains("NOPASSWD"));
+        assert!(!extra.contains("AAAA rum"));
+        assert!(extra.contains("groups: video"));
+
+        assert!(ud.contains("path: /home/rum/.vimrc"));
+        assert!(ud.contains("owner: rum:rum"));
+        assert!(ud.contains("defer: true"));
+        assert!(ud.contains("https://example.com/dots.git"));
+        assert!(ud.contains("- git"));
+    }
+
+    #[test]
+    fn user_data_sets_timezone_locale_and_ntp() {
+        let ud = build_user_data(&default_seed_config());
+        assert!(!ud.contains("timezone:") && !ud.contains("ntp:"));
+
+        let guest = GuestConfig {
+            timezone: Some("Europe/Oslo".into()),
+            locale: Some("nb_NO.UTF-8".into()),
+            ntp_servers: vec!["ntp.example.com".into()],
+            inline_agent: false,
+        };
+        let config = SeedConfig {
+            guest: &guest,
+            ..default_seed_config()
+        };
+        let ud = build_user_data(&config);
+        assert!(ud.contains("timezone: Europe/Oslo"), "got: {ud}");
+        assert!(ud.contains("locale: nb_NO.UTF-8"));
+        assert!(ud.contains("ntp.example.com"));
+        assert_ne!(seed_hash(&default_seed_config()), seed_hash(&config));
+    }
+
+    #[test]
+    fn user_data_inlines_the_agent_instead_of_mounting_the_seed() {
+        let agent = b"\x7fELF agent";
+        let config = SeedConfig {
+            agent_binary: Some(agent),
+            ..default_seed_config()
+        };
+        let ud = build_user_data(&config);
+        assert!(ud.contains("/mnt/cidata/rum-agent"));
+        assert!(!ud.contains("encoding: b64"));
+
+        let guest = GuestConfig {
+            inline_agent: true,
+            ..GuestConfig::default()
+        };
+        let inline = SeedConfig {
+            guest: &guest,
+            ..config
+        };
+        let ud = build_user_data(&inline);
+        assert!(!ud.contains("CIDATA"), "got: {ud}");
+        assert!(ud.contains("encoding: b64"));
+        assert!(ud.contains(&base64(agent)));
+        assert!(ud.contains("rum-agent.service"));
+        assert!(ud.contains("daemon-reload"));
+    }
+
+    #[test]
+    fn base64_pads_partial_groups() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo"), "Zm9v");
+        assert_eq!(base64(b"foobar"), "Zm9vYmFy");
+    }
+
+    #[test]
+    fn network_config_defaults_to_dhcp_on_every_nic() {
+        let yaml = build_network_config(&[]);
+        assert!(yaml.contains("name: \"en*\""));
+        assert!(yaml.contains("dhcp4: true"));
+    }
+
+    #[test]
+    fn network_config_matches_nics_by_mac() {
+        let addresses = vec!["192.168.50.10/24".to_string()];
+        let nameservers = vec!["192.168.50.1".to_string()];
+        let search = vec!["lab.internal".to_string()];
+        let nics = [
+            NicConfig {
+                mac: Some("52:54:00:aa:bb:cc".into()),
+                addresses: &[],
+                gateway: "",
+                nameservers: &[],
+                search: &[],
+                mtu: None,
+                dhcp6: false,
+            },
+            NicConfig {
+                mac: Some("52:54:00:11:22:33".into()),
+                addresses: &addresses,
+                gateway: "192.168.50.1",
+                nameservers: &nameservers,
+                search: &search,
+                mtu: Some(9000),
+                dhcp6: false,
+            },
+        ];
+        let yaml = build_network_config(&nics);
+        assert!(!yaml.contains("en*"), "got:\n{yaml}");
+
+        let (nat, extra) = yaml.split_at(yaml.find("id1:").unwrap());
+        assert!(nat.contains("macaddress: \"52:54:00:aa:bb:cc\""));
+        assert!(nat.contains("dhcp4: true"));
+        assert!(extra.contains("macaddress: \"52:54:00:11:22:33\""));
+        assert!(extra.contains("dhcp4: false"));
+        assert!(extra.contains("addresses: [\"192.168.50.10/24\"]"));
+        assert!(extra.contains("via: \"192.168.50.1\""));
+        assert!(extra.contains("search: [\"lab.internal\"]"));
+        assert!(extra.contains("mtu: 9000"));
+    }
 }
@@ -0,0 +1,131 @@
+use std::path::{Path, PathBuf};
+
+use miette::{Diagnostic, GraphicalReportHandler, NamedSource, SourceSpan};
+
+use crate::error::Error;
+
+use super::identity::derive_name;
+use super::load::parse_config;
+use super::validate::{config_problems, validate_name};
+
+/// One problem found by [`check_config`].
+#[derive(Debug, Clone)]
+pub struct ConfigProblem {
+    /// Top-level TOML key of the section the problem is in, when known.
+    pub key: Option<&'static str>,
+    pub message: String,
+}
+
+/// Every problem found in a config file, for `rum config validate`.
+#[derive(Debug, Clone)]
+pub struct ConfigReport {
+    pub path: PathBuf,
+    source: String,
+    pub problems: Vec<ConfigProblem>,
+}
+
+#[derive(Debug, thiserror::Error, Diagnostic)]
+#[error("{message}")]
+struct ProblemDiagnostic {
+    message: String,
+    #[source_code]
+    source: NamedSource<String>,
+    #[label("in this section")]
+    span: Option<SourceSpan>,
+}
+
+/// Parse and validate `path` with `profile` applied, collecting the first
+/// problem of every section instead of stopping at the first one like
+/// [`super::load_config`].
+///
+/// Only a file that cannot be read is an error.
+pub fn check_config(path: &Path, profile: Option<&str>) -> Result<ConfigReport, Error> {
+    let source = std::fs::read_to_string(path).map_err(|source| Error::ConfigLoad {
+        path: path.display().to_string(),
+        source,
+    })?;
+
+    let mut problems = Vec::new();
+    match parse_config(path, &source, profile) {
+        Ok(config) => {
+            problems.extend(config_problems(&config).into_iter().map(|(key, error)| {
+                ConfigProblem {
+                    key: Some(key),
+                    message: problem_message(error),
+                }
+            }));
+        }
+        Err(error) => problems.push(ConfigProblem {
+            key: None,
+            message: problem_message(error),
+        }),
+    }
+    if let Some(name) = derive_name(path)
+        && let Err(error) = validate_name(&name)
+    {
+        problems.push(ConfigProblem {
+            key: None,
+            message: problem_message(error),
+        });
+    }
+
+    Ok(ConfigReport {
+        path: path.to_path_buf(),
+        source,
+        problems,
+    })
+}
+
+impl ConfigReport {
+    pub fn is_valid(&self) -> bool {
+        self.problems.is_empty()
+    }
+
+    /// Render every problem with the config excerpt of its section.
+    pub fn render(&self) -> String {
+        let handler = GraphicalReportHandler::new();
+        let mut out = String::new();
+        for problem in &self.problems {
+            let diagnostic = ProblemDiagnostic {
+                message: problem.message.clone(),
+                source: NamedSource::new(self.path.display().to_string(), self.source.clone()),
+                span: problem.key.and_then(|key| key_span(&self.source, key)),
+            };
+            if handler.render_report(&mut out, &diagnostic).is_err() {
+                out.push_str(&problem.message);
+                out.push('\n');
+            }
+        }
+        out
+    }
+}
+
+fn problem_message(error: Error) -> String {
+    match error {
+        Error::Validation { message } => message,
+        other => other.to_string(),
+    }
+}
+
+/// Span of the first line that opens or assigns the top-level `key`: a
+/// `[key]`/`[[key]]` header, a dotted `[key.sub]` header, or `key = ...`.
+fn key_span(source: &str, key: &str) -> Option<SourceSpan> {
+    let mut offset = 0;
+    for line in source.split_inclusive('\n') {
+        let trimmed = line.trim();
+        let name = trimmed.trim_start_matches('[');
+        let opens = trimmed.starts_with('[')
+            && name
+                .strip_prefix(key)
+                .is_some_and(|rest| rest.starts_with([']', '.']));
+        let assigns = trimmed
+            .strip_prefix(key)
+            .is_some_and(|rest| rest.trim_start().starts_with(['=', '.']));
+        if opens || assigns {
+            let start = offset + (line.len() - line.trim_start().len());
+            return Some((start, trimmed.len()).into());
+        }
+        offset += line.len();
+    }
+    None
+}
@@ -0,0 +1,281 @@
+use std::path::Path;
+
+use facet::Facet;
+
+use crate::error::Error;
+
+use super::schema::{Config, MountConfig, PortForward};
+
+/// How a config change reaches the machine.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Apply {
+    /// `rum reload` applies it to the running machine.
+    Reload,
+    /// `rum resize` applies it to the running machine.
+    Resize,
+    /// A provisioning step applies it, with `rum provision` once the
+    /// daemon reloaded the config.
+    Provision,
+    /// Takes effect the next time the machine boots.
+    Restart,
+    /// Only read when the machine is first created.
+    Recreate,
+}
+
+impl Apply {
+    pub fn hint(self) -> &'static str {
+        match self {
+            Self::Reload => "applies live with `rum reload`",
+            Self::Resize => "applies live with `rum resize`",
+            Self::Provision => "applies with `rum reload`, then `rum provision`",
+            Self::Restart => "needs a restart: `rum down`, then `rum up`",
+            Self::Recreate => "needs a new machine: `rum destroy`, then `rum up`",
+        }
+    }
+}
+
+/// One difference found by [`diff_configs`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigChange {
+    /// Top-level TOML key of the changed section.
+    pub key: &'static str,
+    pub detail: String,
+    pub apply: Apply,
+}
+
+/// Save `config` as the one the machine was last started with.
+pub fn record_applied(path: &Path, config: &Config) -> Result<(), Error> {
+    std::fs::write(path, facet_json::to_string(config)).map_err(|e| Error::Io {
+        context: format!("saving applied config to {}", path.display()),
+        source: e,
+    })
+}
+
+/// The config recorded by [`record_applied`], if any could be read.
+pub fn applied_config(path: &Path) -> Option<Config> {
+    let contents = std::fs::read_to_string(path).ok()?;
+    facet_json::from_str(&contents).ok()
+}
+
+/// Every change from `applied`, the config the machine was started with, to
+/// `current`, along with how each one is applied.
+pub fn diff_configs(applied: &Config, current: &Config) -> Vec<ConfigChange> {
+    let mut changes = Vec::new();
+    let mut push = |key, detail: String, apply| {
+        changes.push(ConfigChange { key, detail, apply });
+    };
+
+    if applied.image.base != current.image.base {
+        push(
+            "image",
+            format!(
+                "base image {} -> {}",
+                applied.image.base, current.image.base
+            ),
+            Apply::Recreate,
+        );
+    } else if differs(&applied.image, &current.image) {
+        push("image", "image settings changed".into(), Apply::Recreate);
+    }
+
+    let (old, new) = (&applied.resources, &current.resources);
+    if old.cpus != new.cpus {
+        let live = old.cpus_max.is_some_and(|max| new.cpus <= max);
+        push(
+            "resources",
+            format!("cpus {} -> {}", old.cpus, new.cpus),
+            if live { Apply::Resize } else { Apply::Restart },
+        );
+    }
+    if old.memory_mb != new.memory_mb {
+        let live = old.memory_max_mb.is_some_and(|max| new.memory_mb <= max);
+        push(
+            "resources",
+            format!("memory {} MiB -> {} MiB", old.memory_mb, new.memory_mb),
+            if live { Apply::Resize } else { Apply::Restart },
+        );
+    }
+    if old.disk != new.disk {
+        push(
+            "resources",
+            format!("disk {} -> {}", old.disk, new.disk),
+            Apply::Restart,
+        );
+    }
+    let mut rest = old.clone();
+    (rest.cpus, rest.memory_mb, rest.disk) = (new.cpus, new.memory_mb, new.disk.clone());
+    if differs(&rest, new) {
+        push(
+            "resources",
+            "other resource settings changed".into(),
+            Apply::Restart,
+        );
+    }
+
+    for port in only_in(&current.ports, &applied.ports, same_forward) {
+        push(
+            "ports",
+            format!("forward {} added", describe_forward(port)),
+            Apply::Reload,
+        );
+    }
+    for port in only_in(&applied.ports, &current.ports, same_forward) {
+        push(
+            "ports",
+            format!("forward {} removed", describe_forward(port)),
+            Apply::Reload,
+        );
+    }
+
+    for mount in &current.mounts {
+        match applied.mounts.iter().find(|m| m.target == mount.target) {
+            None => push(
+                "mounts",
+                format!("mount {} at {} added", mount.source, mount.target),
+                Apply::Reload,
+            ),
+            Some(old) if differs(old, mount) => push(
+                "mounts",
+                format!("mount at {} changed", mount.target),
+                Apply::Reload,
+            ),
+            Some(_) => {}
+        }
+    }
+    for mount in only_in(&applied.mounts, &current.mounts, same_target) {
+        push(
+            "mounts",
+            format!("mount at {} removed", mount.target),
+            Apply::Reload,
+        );
+    }
+
+    let (old, new) = (&applied.provision, &current.provision);
+    if differs(&old.system, &new.system) {
+        push("provision", "system script changed".into(), Apply::Restart);
+    }
+    if differs(&old.boot, &new.boot) {
+        push("provision", "boot script changed".into(), Apply::Restart);
+    }
+    if old.packages != new.packages {
+        push("provision", "packages changed".into(), Apply::Restart);
+    }
+    for step in &new.steps {
+        match old.steps.iter().find(|s| s.name == step.name) {
+            None => push(
+                "provision",
+                format!("step '{}' added", step.name),
+                Apply::Restart,
+            ),
+            Some(old) if differs(old, step) => push(
+                "provision",
+                format!("step '{}' changed", step.name),
+                Apply::Restart,
+            ),
+            Some(_) => {}
+        }
+    }
+    for step in old
+        .steps
+        .iter()
+        .filter(|s| !new.steps.iter().any(|n| n.name == s.name))
+    {
+        push(
+            "provision",
+            format!("step '{}' removed", step.name),
+            Apply::Restart,
+        );
+    }
+    if old.nixos != new.nixos {
+        push("provision", "nixos changed".into(), Apply::Restart);
+    }
+
+    // The engine is installed by its own provisioning step; the daemon
+    // forwards its socket.
+    if differs(&applied.docker, &current.docker) {
+        push("docker", "[docker] changed".into(), Apply::Provision);
+    }
+    if differs(&applied.ready, &current.ready) {
+        push("ready", "readiness probes changed".into(), Apply::Reload);
+    }
+    // Only the daemon advertises the machine over mDNS.
+    if applied.network.mdns != current.network.mdns {
+        push(
+            "network",
+            format!("mdns {} -> {}", applied.network.mdns, current.network.mdns),
+            Apply::Reload,
+        );
+    }
+    // Only the daemon sends notifications.
+    if differs(&applied.notify, &current.notify) {
+        push("notify", "[notify] changed".into(), Apply::Reload);
+    }
+
+    // The ssh client options are read by every `rum ssh`; only the rest
+    // of [ssh] goes into the seed.
+    let mut seed_ssh = applied.ssh.clone();
+    seed_ssh.forward_agent = current.ssh.forward_agent;
+    seed_ssh.proxy_jump = current.ssh.proxy_jump.clone();
+    seed_ssh.extra_options = current.ssh.extra_options.clone();
+    seed_ssh.multiplex = current.ssh.multiplex;
+    seed_ssh.control_path = current.ssh.control_path.clone();
+    let mut boot_network = applied.network.clone();
+    boot_network.mdns = current.network.mdns;
+
+    // Cloud-init only reads the seed on the first boot.
+    let seed_sections = [
+        ("user", differs(&applied.user, &current.user)),
+        ("users", differs(&applied.users, &current.users)),
+        ("ssh", differs(&seed_ssh, &current.ssh)),
+    ];
+    let restart_sections = [
+        ("network", differs(&boot_network, &current.network)),
+        ("advanced", differs(&applied.advanced, &current.advanced)),
+        ("guest", differs(&applied.guest, &current.guest)),
+        ("root_disk", differs(&applied.root_disk, &current.root_disk)),
+        ("drives", differs(&applied.drives, &current.drives)),
+        ("cdroms", differs(&applied.cdroms, &current.cdroms)),
+        ("fs", differs(&applied.fs, &current.fs)),
+        ("metadata", differs(&applied.metadata, &current.metadata)),
+        ("display", differs(&applied.display, &current.display)),
+        ("timeouts", differs(&applied.timeouts, &current.timeouts)),
+        ("serve", differs(&applied.serve, &current.serve)),
+        ("logging", differs(&applied.logging, &current.logging)),
+    ];
+    for (key, changed) in seed_sections {
+        if changed {
+            push(key, format!("[{key}] changed"), Apply::Recreate);
+        }
+    }
+    for (key, changed) in restart_sections {
+        if changed {
+            push(key, format!("[{key}] changed"), Apply::Restart);
+        }
+    }
+
+    changes
+}
+
+fn differs<'a, T: Facet<'a>>(a: &T, b: &T) -> bool {
+    facet_json::to_string(a) != facet_json::to_string(b)
+}
+
+/// Items of `items` with no matching entry in `other`.
+fn only_in<'a, T>(items: &'a [T], other: &[T], same: fn(&T, &T) -> bool) -> Vec<&'a T> {
+    items
+        .iter()
+        .filter(|item| !other.iter().any(|o| same(item, o)))
+        .collect()
+}
+
+fn same_forward(a: &PortForward, b: &PortForward) -> bool {
+    a.host == b.host && a.guest == b.guest && a.bind_addr() == b.bind_addr()
+}
+
+fn same_target(a: &MountConfig, b: &MountConfig) -> bool {
+    a.target == b.target
+}
+
+fn describe_forward(port: &PortForward) -> String {
+    format!("{}:{} -> guest:{}", port.bind_addr(), port.host, port.guest)
+}
@@ -1,4 +1,29 @@
-use std::path::Path;
+//! Config loading, including `extends`.
+//!
+//! A config can build on other configs with a top-level
+//! `extends = "../base.rum.toml"`, or a list of paths that are applied in
+//! order. Paths are relative to the file that names them, and included
+//! files may extend further files. The layers merge before the result is
+//! parsed and validated as one config:
+//!
+//! - tables merge key by key, recursively, so an override only has to name
+//!   the keys it changes;
+//! - arrays, including arrays of tables such as `[[mounts]]` and
+//!   `[[ports]]`, are concatenated with the base entries first;
+//! - any other value in the extending file replaces the base's.
+//!
+//! Relative paths inside any layer (images, drives, scripts) are resolved
+//! against the directory of the config rum was pointed at.
+//!
+//! A config can also carry profiles, `[profile.<name>]` tables holding the
+//! same keys as the top level. Selecting one (`--profile` or
+//! [`PROFILE_ENV`]) applies it after `extends`: tables again merge key by
+//! key, but arrays and other values in the profile replace the config's, so
+//! a profile can swap out `[[ports]]` or `provision.packages` entirely.
+
+use std::path::{Path, PathBuf};
+
+use facet_value::Value;
 
 use crate::error::Error;
 
@@ -7,16 +32,88 @@ use super::runtime::SystemConfig;
 use super::schema::Config;
 use super::validate::{validate_config, validate_name};
 
+/// Environment variable naming the config file when `--config` is not given.
+pub const CONFIG_ENV: &str = "RUM_CONFIG";
+
+/// Environment variable selecting a profile when `--profile` is not given.
+pub const PROFILE_ENV: &str = "RUM_PROFILE";
+
+/// The profile named by [`PROFILE_ENV`], if set and not empty.
+pub fn profile_from_env() -> Option<String> {
+    std::env::var(PROFILE_ENV).ok().filter(|p| !p.is_empty())
+}
+
+/// Find the config for a command run in `start`, searching it and then its
+/// parents like git and cargo do. The nearest directory holding `rum.toml`
+/// wins; one holding a single `*.rum.toml` instead uses that file, and one
+/// holding several is ambiguous.
+pub fn find_config(start: &Path) -> Result<Option<PathBuf>, Error> {
+    find_config_below(start, None)
+}
+
+/// [`find_config`], searching no higher than `ceiling` when it is set.
+pub(crate) fn find_config_below(
+    start: &Path,
+    ceiling: Option<&Path>,
+) -> Result<Option<PathBuf>, Error> {
+    let dirs = start
+        .ancestors()
+        .take_while(|dir| ceiling.is_none_or(|ceiling| dir.starts_with(ceiling)));
+    for dir in dirs {
+        let default = dir.join("rum.toml");
+        if default.is_file() {
+            return Ok(Some(default));
+        }
+        let Ok(entries) = std::fs::read_dir(dir) else {
+            continue;
+        };
+        let mut named: Vec<PathBuf> = entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| {
+                path.is_file()
+                    && path
+                        .file_name()
+                        .and_then(|name| name.to_str())
+                        .is_some_and(|name| name.ends_with(".rum.toml"))
+            })
+            .collect();
+        match named.len() {
+            0 => {}
+            1 => return Ok(named.pop()),
+            _ => {
+                named.sort();
+                let names: Vec<String> = named
+                    .iter()
+                    .filter_map(|path| path.file_name())
+                    .map(|name| name.to_string_lossy().into_owned())
+                    .collect();
+                return Err(Error::Validation {
+                    message: format!(
+                        "{} has several configs ({}); pick one with --config",
+                        dir.display(),
+                        names.join(", ")
+                    ),
+                });
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// Load the config at `path` with the profile from [`PROFILE_ENV`].
 pub fn load_config(path: &Path) -> Result<SystemConfig, Error> {
+    load_config_with_profile(path, profile_from_env().as_deref())
+}
+
+/// Load the config at `path` with `[profile.<profile>]` applied.
+pub fn load_config_with_profile(path: &Path, profile: Option<&str>) -> Result<SystemConfig, Error> {
     let contents = std::fs::read_to_string(path).map_err(|source| Error::ConfigLoad {
         path: path.display().to_string(),
         source,
     })?;
 
-    let config: Config = facet_toml::from_str(&contents).map_err(|e| Error::ConfigParse {
-        path: path.display().to_string(),
-        message: e.to_string(),
-    })?;
+    let mut config = parse_config(path, &contents, profile)?;
 
     validate_config(&config)?;
 
@@ -32,10 +129,231 @@ pub fn load_config(path: &Path) -> Result<SystemConfig, Error> {
 
     let id = config_id(&canonical, name.as_deref());
 
+    // Shares added with `rum mount` stay until they are unmounted again.
+    let live_mounts = crate::paths::live_mounts_path(&id, name.as_deref());
+    crate::live_mount::merge(&mut config, &live_mounts);
+
+    // Relative local images are relative to the config file, not the cwd.
+    if let Some(local) = crate::image::local_path(&config.image.base)
+        && local.is_relative()
+        && let Some(dir) = canonical.parent()
+    {
+        config.image.base = dir.join(local).display().to_string();
+    }
+    if let Some(dir) = canonical.parent() {
+        for drive in config.drives.values_mut() {
+            for path in [&mut drive.path, &mut drive.key_file].into_iter().flatten() {
+                if Path::new(path.as_str()).is_relative() {
+                    *path = dir.join(&path).display().to_string();
+                }
+            }
+        }
+        for cdrom in &mut config.cdroms {
+            if Path::new(&cdrom.path).is_relative() {
+                cdrom.path = dir.join(&cdrom.path).display().to_string();
+            }
+        }
+        let provision = &mut config.provision;
+        let mut files: Vec<&mut String> = provision
+            .steps
+            .iter_mut()
+            .filter_map(|step| step.file.as_mut())
+            .collect();
+        files.extend(
+            provision
+                .system
+                .as_mut()
+                .and_then(|s| s.script_file.as_mut()),
+        );
+        files.extend(provision.boot.as_mut().and_then(|b| b.script_file.as_mut()));
+        files.extend(
+            std::iter::once(&mut config.user)
+                .chain(&mut config.users)
+                .filter(|user| user.dotfiles_dir().is_some())
+                .filter_map(|user| user.dotfiles.as_mut()),
+        );
+        for file in files {
+            if Path::new(file.as_str()).is_relative() {
+                *file = dir.join(&file).display().to_string();
+            }
+        }
+    }
+
     Ok(SystemConfig {
         id,
         name,
         config_path: canonical,
+        profile: profile.map(str::to_string),
         config,
     })
 }
+
+/// Parse the TOML of the config at `path`, merging in the files it extends
+/// and then the selected profile.
+pub(super) fn parse_config(
+    path: &Path,
+    contents: &str,
+    profile: Option<&str>,
+) -> Result<Config, Error> {
+    let parse_error = |message: String| Error::ConfigParse {
+        path: path.display().to_string(),
+        message,
+    };
+    if !has_extends(contents) && !has_profiles(contents) && profile.is_none() {
+        return facet_toml::from_str(contents).map_err(|e| parse_error(e.to_string()));
+    }
+
+    let mut merged = layered_value(path, contents, &mut Vec::new())?;
+    let profiles = merged.as_object_mut().and_then(|obj| obj.remove("profile"));
+    if let Some(name) = profile {
+        let selected = profiles
+            .as_ref()
+            .and_then(|profiles| profiles.as_object())
+            .and_then(|profiles| profiles.get(name))
+            .ok_or_else(|| Error::Validation {
+                message: format!(
+                    "profile '{name}' is not defined in {}{}",
+                    path.display(),
+                    available_profiles(profiles.as_ref())
+                ),
+            })?;
+        merge_value(&mut merged, selected.clone(), false);
+    }
+    facet_json::from_str(&facet_json::to_string(&merged)).map_err(|e| parse_error(e.to_string()))
+}
+
+fn available_profiles(profiles: Option<&Value>) -> String {
+    let names: Vec<&str> = profiles
+        .and_then(|profiles| profiles.as_object())
+        .map(|profiles| profiles.iter().map(|(name, _)| name.as_str()).collect())
+        .unwrap_or_default();
+    if names.is_empty() {
+        String::new()
+    } else {
+        format!(" (available: {})", names.join(", "))
+    }
+}
+
+/// Whether the config declares any `[profile.<name>]` tables.
+fn has_profiles(contents: &str) -> bool {
+    contents
+        .lines()
+        .any(|line| line.trim().trim_start_matches('[').starts_with("profile"))
+}
+
+/// Whether a top-level `extends` key appears before the first table header.
+fn has_extends(contents: &str) -> bool {
+    contents
+        .lines()
+        .map(str::trim)
+        .take_while(|line| !line.starts_with('['))
+        .any(|line| {
+            line.strip_prefix("extends")
+                .is_some_and(|rest| rest.trim_start().starts_with('='))
+        })
+}
+
+/// The config at `path` merged over everything it extends. `chain` holds
+/// the files being loaded, to catch include cycles.
+fn layered_value(path: &Path, contents: &str, chain: &mut Vec<PathBuf>) -> Result<Value, Error> {
+    let canonical = path.canonicalize().map_err(|source| Error::ConfigLoad {
+        path: path.display().to_string(),
+        source,
+    })?;
+    if chain.contains(&canonical) {
+        let cycle: Vec<String> = chain
+            .iter()
+            .chain([&canonical])
+            .map(|p| p.display().to_string())
+            .collect();
+        return Err(Error::Validation {
+            message: format!("config extends itself: {}", cycle.join(" -> ")),
+        });
+    }
+
+    let mut value: Value = facet_toml::from_str(contents).map_err(|e| Error::ConfigParse {
+        path: path.display().to_string(),
+        message: e.to_string(),
+    })?;
+    let extends = value
+        .as_object_mut()
+        .and_then(|obj| obj.remove("extends"))
+        .map(|extends| extends_paths(path, &extends))
+        .transpose()?
+        .unwrap_or_default();
+
+    chain.push(canonical);
+    let dir = path.parent().unwrap_or(Path::new("."));
+    let mut merged: Option<Value> = None;
+    for base in extends {
+        let base_path = dir.join(base);
+        let base_contents =
+            std::fs::read_to_string(&base_path).map_err(|source| Error::ConfigLoad {
+                path: base_path.display().to_string(),
+                source,
+            })?;
+        let base = layered_value(&base_path, &base_contents, chain)?;
+        match &mut merged {
+            Some(merged) => merge_value(merged, base, true),
+            None => merged = Some(base),
+        }
+    }
+    chain.pop();
+
+    Ok(match merged {
+        Some(mut merged) => {
+            merge_value(&mut merged, value, true);
+            merged
+        }
+        None => value,
+    })
+}
+
+/// `extends` is either one path or a list of paths.
+fn extends_paths(path: &Path, extends: &Value) -> Result<Vec<String>, Error> {
+    let invalid = || Error::Validation {
+        message: format!(
+            "{}: extends must be a path or a list of paths",
+            path.display()
+        ),
+    };
+    if let Some(single) = extends.as_string() {
+        return Ok(vec![single.as_str().to_string()]);
+    }
+    extends
+        .as_array()
+        .ok_or_else(invalid)?
+        .iter()
+        .map(|entry| {
+            entry
+                .as_string()
+                .map(|s| s.as_str().to_string())
+                .ok_or_else(invalid)
+        })
+        .collect()
+}
+
+/// Merge `overlay` into `base`: tables key by key, arrays by appending when
+/// `append` is set, anything else by replacing.
+fn merge_value(base: &mut Value, overlay: Value, append: bool) {
+    if let (Some(base_obj), Some(overlay_obj)) = (base.as_object_mut(), overlay.as_object()) {
+        for (key, value) in overlay_obj.iter() {
+            match base_obj.get_mut(key.as_str()) {
+                Some(existing) => merge_value(existing, value.clone(), append),
+                None => {
+                    base_obj.insert(key.as_str(), value.clone());
+                }
+            }
+        }
+        return;
+    }
+    if append
+        && let (Some(base_items), Some(overlay_items)) = (base.as_array_mut(), overlay.as_array())
+    {
+        for item in overlay_items.iter() {
+            base_items.push(item.clone());
+        }
+        return;
+    }
+    *base = overlay;
+}
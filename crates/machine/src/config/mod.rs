@@ -1,3 +1,5 @@
+mod check;
+mod diff;
 mod identity;
 mod load;
 mod runtime;
@@ -7,6 +9,10 @@ mod validate;
 #[cfg(test)]
 pub mod tests;
 
-pub use load::load_config;
+pub use check::{ConfigProblem, ConfigReport, check_config};
+pub use diff::{Apply, ConfigChange, applied_config, diff_configs, record_applied};
+pub use load::{
+    CONFIG_ENV, PROFILE_ENV, find_config, load_config, load_config_with_profile, profile_from_env,
+};
 pub use runtime::*;
 pub use schema::*;
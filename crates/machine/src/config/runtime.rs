@@ -1,3 +1,4 @@
+use std::os::unix::fs::FileTypeExt;
 use std::path::{Path, PathBuf};
 
 use crate::error::Error;
@@ -6,6 +7,104 @@ use crate::paths;
 use super::identity::sanitize_tag;
 use super::schema::*;
 
+/// How a host directory is shared with the guest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MountDriver {
+    Virtiofs,
+    NineP,
+    /// Mounted from inside the guest over SSH to the host; no domain device.
+    Sshfs,
+    /// `mode = "sync"`: a guest copy kept in sync by [`crate::sync`]; no
+    /// domain device and nothing mounted.
+    Sync,
+}
+
+impl MountDriver {
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Virtiofs => "virtiofs",
+            Self::NineP => "9p",
+            Self::Sshfs => "sshfs",
+            Self::Sync => "sync",
+        }
+    }
+
+    /// Guest `mount -t` type and options, for the drivers backed by a
+    /// domain device.
+    pub fn fs_options(self) -> Option<(&'static str, &'static str)> {
+        match self {
+            Self::Virtiofs => Some(("virtiofs", "defaults,nofail")),
+            Self::NineP => Some(("9p", "trans=virtio,version=9p2000.L,msize=262144,nofail")),
+            Self::Sshfs | Self::Sync => None,
+        }
+    }
+}
+
+/// Patterns a sync mount skips when it sets no `inotify_ignore`.
+pub const DEFAULT_INOTIFY_IGNORE: &[&str] = &["target/", "node_modules/", ".git/"];
+
+/// Debounce window used when a mount sets no `inotify_debounce_ms`.
+pub const DEFAULT_INOTIFY_DEBOUNCE_MS: u64 = 200;
+
+/// Guest socket the `[docker]` engine serves its API on.
+pub const DOCKER_SOCKET: &str = "/run/docker.sock";
+
+/// Guest socket of podman's Docker-compatible API.
+pub const PODMAN_SOCKET: &str = "/run/podman/podman.sock";
+
+/// Longest each lifecycle step may take in CI mode, in seconds. Tighter
+/// than the defaults, since a hung CI job only shows up when it times out.
+pub const CI_IMAGE_DOWNLOAD_S: u64 = 900;
+pub const CI_BOOT_S: u64 = 60;
+pub const CI_AGENT_CONNECT_S: u64 = 120;
+pub const CI_CLOUD_INIT_S: u64 = 300;
+pub const CI_SCRIPT_S: u64 = 1800;
+pub const CI_SHUTDOWN_S: u64 = 10;
+pub const CI_HEARTBEAT_S: u64 = 5;
+
+impl TimeoutsConfig {
+    /// These timeouts for an unattended CI run, where nobody is around to
+    /// notice a hang: each step waits at most its `CI_*` bound, including
+    /// the ones set to wait indefinitely.
+    pub fn for_ci(&self) -> Self {
+        let bound = |secs: u64, max: u64| match secs {
+            0 => max,
+            secs => secs.min(max),
+        };
+        Self {
+            image_download_s: bound(self.image_download_s, CI_IMAGE_DOWNLOAD_S),
+            boot_s: bound(self.boot_s, CI_BOOT_S),
+            agent_connect_s: bound(self.agent_connect_s, CI_AGENT_CONNECT_S),
+            cloud_init_s: bound(self.cloud_init_s, CI_CLOUD_INIT_S),
+            script_s: bound(self.script_s, CI_SCRIPT_S),
+            shutdown_s: bound(self.shutdown_s, CI_SHUTDOWN_S),
+            heartbeat_interval_s: self.heartbeat_interval_s,
+            heartbeat_s: bound(self.heartbeat_s, CI_HEARTBEAT_S),
+            missed_heartbeats: self.missed_heartbeats,
+            retries: self.retries,
+        }
+    }
+}
+
+impl MountConfig {
+    /// Options for watching both sides of this sync mount.
+    pub fn fs_watch_options(&self) -> guest::agent::FsWatchOptions {
+        guest::agent::FsWatchOptions {
+            ignore: match &self.inotify_ignore {
+                Some(patterns) => patterns.clone(),
+                None => DEFAULT_INOTIFY_IGNORE
+                    .iter()
+                    .map(|p| p.to_string())
+                    .collect(),
+            },
+            gitignore: self.inotify_gitignore,
+            debounce_ms: self
+                .inotify_debounce_ms
+                .unwrap_or(DEFAULT_INOTIFY_DEBOUNCE_MS),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct ResolvedMount {
     pub source: PathBuf,
@@ -13,6 +112,7 @@ pub struct ResolvedMount {
     pub readonly: bool,
     pub tag: String,
     pub default: bool,
+    pub driver: MountDriver,
 }
 
 #[derive(Debug, Clone)]
@@ -21,6 +121,62 @@ pub struct ResolvedDrive {
     pub size: String,
     pub path: PathBuf,
     pub dev: String,
+    /// Set for `path` drives: the image or device already exists and is
+    /// never created or deleted by rum.
+    pub external: bool,
+    /// The path is a host block device rather than an image file.
+    pub block: bool,
+    pub readonly: bool,
+    pub encrypted: bool,
+    /// `qcow2` or `raw`.
+    pub format: String,
+    /// `off`, `metadata`, or `full`.
+    pub preallocation: String,
+    pub io: DiskIoConfig,
+}
+
+/// A unix socket on the host forwarded to one in the guest.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SocketForward {
+    pub host: PathBuf,
+    pub guest: String,
+}
+
+/// A `[[provision.step]]` with its script loaded.
+#[derive(Debug, Clone)]
+pub struct ResolvedStep {
+    pub name: String,
+    pub script: String,
+    /// The script was read from a file rather than given inline.
+    pub from_file: bool,
+    /// Runs on every boot rather than once.
+    pub boot: bool,
+    pub order: u32,
+    pub when: Option<String>,
+    pub depends_on: Option<Vec<String>>,
+    pub user: Option<String>,
+    pub interpreter: Option<String>,
+}
+
+/// An encrypted drive with its passphrase loaded from the host.
+#[derive(Clone)]
+pub struct LuksDrive {
+    pub name: String,
+    /// Raw guest device, e.g. `/dev/vdb`.
+    pub dev: String,
+    /// Name of the opened mapping under `/dev/mapper`.
+    pub mapper: String,
+    pub key: String,
+}
+
+impl std::fmt::Debug for LuksDrive {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LuksDrive")
+            .field("name", &self.name)
+            .field("dev", &self.dev)
+            .field("mapper", &self.mapper)
+            .finish_non_exhaustive()
+    }
 }
 
 #[derive(Debug, Clone, Hash)]
@@ -61,10 +217,42 @@ pub struct SystemConfig {
     pub name: Option<String>,
     /// Canonicalized path to the config file.
     pub config_path: PathBuf,
+    /// `[profile.<name>]` applied on top of the config, if any.
+    pub profile: Option<String>,
     /// Parsed TOML config.
     pub config: Config,
 }
 
+/// A file from a host `dotfiles` directory, to be written into a user's
+/// home directory.
+#[derive(Debug, Clone)]
+pub struct DotFile {
+    pub user: String,
+    /// Relative to the home directory.
+    pub path: String,
+    pub mode: u32,
+    pub content: String,
+}
+
+impl UserConfig {
+    /// `dotfiles` when it names a git repository.
+    pub fn dotfiles_repo(&self) -> Option<&str> {
+        self.dotfiles.as_deref().filter(|d| is_git_url(d))
+    }
+
+    /// `dotfiles` when it names a host directory.
+    pub fn dotfiles_dir(&self) -> Option<&Path> {
+        self.dotfiles
+            .as_deref()
+            .filter(|d| !is_git_url(d))
+            .map(Path::new)
+    }
+}
+
+fn is_git_url(value: &str) -> bool {
+    value.contains("://") || value.starts_with("git@")
+}
+
 impl SystemConfig {
     /// User-facing display name: the derived name if present, otherwise the id.
     pub fn display_name(&self) -> &str {
@@ -80,11 +268,168 @@ impl SystemConfig {
         }
     }
 
+    /// Name the machine is advertised under with `network.mdns`: the first
+    /// label of its hostname in the `.local` domain.
+    pub fn mdns_name(&self) -> String {
+        let host = self.hostname().split('.').next().unwrap_or_default();
+        format!("{host}.local")
+    }
+
+    /// Fail when the config needs a stream the guest agent only serves on
+    /// vsock and `vsock` says the host has none. The virtio-serial fallback
+    /// carries agent RPC only, so `[[ports]]`, the `[docker]` socket and the
+    /// tcp/http readiness probes would otherwise only fail once the machine
+    /// is running.
+    pub fn check_agent_transport(&self, vsock: bool) -> Result<(), Error> {
+        if vsock {
+            return Ok(());
+        }
+        let config = &self.config;
+        let needs_vsock: Vec<&str> = [
+            (!config.ports.is_empty(), "[[ports]]"),
+            (config.docker.enable, "[docker]"),
+            (config.ready.tcp.is_some(), "ready.tcp"),
+            (config.ready.http.is_some(), "ready.http"),
+        ]
+        .into_iter()
+        .filter_map(|(used, feature)| used.then_some(feature))
+        .collect();
+        if needs_vsock.is_empty() {
+            return Ok(());
+        }
+        Err(Error::Validation {
+            message: format!(
+                "{} need vsock, but this host has no /dev/vhost-vsock and the guest agent \
+                 falls back to a serial channel; run `sudo modprobe vhost_vsock` or remove them",
+                needs_vsock.join(", ")
+            ),
+        })
+    }
+
+    /// Forward of the `[docker]` engine's API socket, when it is enabled.
+    /// The engine's API is root in the guest, so the host end is a socket
+    /// only the user running rum can open.
+    pub fn docker_forward(&self) -> Option<SocketForward> {
+        let docker = &self.config.docker;
+        let guest = match docker.engine.as_str() {
+            "podman" => PODMAN_SOCKET,
+            _ => DOCKER_SOCKET,
+        };
+        docker.enable.then(|| SocketForward {
+            host: paths::docker_socket_path(&self.id, self.name.as_deref()),
+            guest: guest.into(),
+        })
+    }
+
+    /// `DOCKER_HOST` that reaches the `[docker]` engine from the host.
+    pub fn docker_host(&self) -> Option<String> {
+        self.docker_forward()
+            .map(|forward| format!("unix://{}", forward.host.display()))
+    }
+
+    /// The main `[user]` followed by the `[[users]]` entries.
+    pub fn users(&self) -> impl Iterator<Item = &UserConfig> {
+        std::iter::once(&self.config.user).chain(&self.config.users)
+    }
+
+    /// Read the files of every user's `dotfiles` directory.
+    ///
+    /// `.git` is left out, and files that are not UTF-8 are skipped with a
+    /// warning since cloud-init writes them as text.
+    pub fn dotfile_contents(&self) -> Result<Vec<DotFile>, Error> {
+        let mut files = Vec::new();
+        for user in self.users() {
+            let Some(dir) = user.dotfiles_dir() else {
+                continue;
+            };
+            let entries = guest::tree::walk(dir).map_err(|e| Error::Io {
+                context: format!("reading dotfiles from {}", dir.display()),
+                source: e,
+            })?;
+            for entry in entries {
+                if entry.is_dir || entry.path == ".git" || entry.path.starts_with(".git/") {
+                    continue;
+                }
+                let path = dir.join(&entry.path);
+                let bytes = std::fs::read(&path).map_err(|e| Error::Io {
+                    context: format!("reading {}", path.display()),
+                    source: e,
+                })?;
+                match String::from_utf8(bytes) {
+                    Ok(content) => files.push(DotFile {
+                        user: user.name.clone(),
+                        path: entry.path,
+                        mode: entry.mode & 0o7777,
+                        content,
+                    }),
+                    Err(_) => tracing::warn!(path = %path.display(), "skipping binary dotfile"),
+                }
+            }
+        }
+        Ok(files)
+    }
+
+    /// Libvirt network an extra interface attaches to.
+    ///
+    /// Interfaces with an explicit subnet share one network across VMs.
+    pub fn network_name(&self, iface: &InterfaceConfig) -> String {
+        if iface.subnet.is_empty() {
+            domain::prefixed_name(&self.id, &iface.network)
+        } else {
+            domain::shared_name(&iface.network)
+        }
+    }
+
     /// Resolved libvirt URI.
     pub fn libvirt_uri(&self) -> &str {
         &self.config.advanced.libvirt_uri
     }
 
+    /// Guest CPU architecture — falls back to the host's.
+    pub fn guest_arch(&self) -> &str {
+        self.config
+            .image
+            .arch
+            .as_deref()
+            .unwrap_or(std::env::consts::ARCH)
+    }
+
+    /// Libvirt domain type. KVM cannot run a foreign-arch guest, so those
+    /// fall back to TCG emulation (`qemu`).
+    pub fn domain_type(&self) -> &str {
+        let domain_type = &self.config.advanced.domain_type;
+        if domain_type == "kvm" && self.guest_arch() != std::env::consts::ARCH {
+            "qemu"
+        } else {
+            domain_type
+        }
+    }
+
+    /// Whether the guest is provisioned as NixOS.
+    pub fn is_nixos(&self) -> bool {
+        self.config.provision.nixos.unwrap_or_else(|| {
+            let base = &self.config.image.base;
+            let file = base.rsplit('/').next().unwrap_or(base);
+            file.to_ascii_lowercase().contains("nixos")
+        })
+    }
+
+    /// Whether the guest boots through UEFI.
+    pub fn uefi(&self) -> bool {
+        self.config.advanced.firmware == "uefi" || self.guest_arch() == "aarch64"
+    }
+
+    /// Libvirt machine type. `q35` is x86-only, so aarch64 guests get `virt`
+    /// unless a different machine was configured explicitly.
+    pub fn machine_type(&self) -> &str {
+        let machine = &self.config.advanced.machine;
+        if machine == "q35" && self.guest_arch() == "aarch64" {
+            "virt"
+        } else {
+            machine
+        }
+    }
+
     /// Resolve drive configs into paths and device names.
     ///
     /// BTreeMap iteration is sorted by key, so device names are assigned
@@ -94,11 +439,28 @@ impl SystemConfig {
         let mut resolved = Vec::new();
         for (i, (name, drive)) in self.config.drives.iter().enumerate() {
             let dev = format!("vd{}", (b'b' + i as u8) as char);
+            let path = match &drive.path {
+                Some(path) => PathBuf::from(path),
+                None => paths::drive_path(&self.id, self.name.as_deref(), name, &drive.format),
+            };
+            let block = drive.path.is_some()
+                && std::fs::metadata(&path).is_ok_and(|m| m.file_type().is_block_device());
             resolved.push(ResolvedDrive {
                 name: name.clone(),
                 size: drive.size.clone(),
-                path: paths::drive_path(&self.id, self.name.as_deref(), name),
+                path,
                 dev,
+                external: drive.path.is_some(),
+                block,
+                readonly: drive.readonly,
+                encrypted: drive.encrypted,
+                format: if block {
+                    "raw".into()
+                } else {
+                    drive.format.clone()
+                },
+                preallocation: drive.preallocation.clone(),
+                io: drive.io.clone(),
             });
         }
         Ok(resolved)
@@ -126,6 +488,7 @@ impl SystemConfig {
 
         let mut resolved = Vec::new();
         let mut seen_tags = std::collections::HashSet::new();
+        let mut auto_driver = None;
 
         for m in &self.config.mounts {
             let source = match m.source.as_str() {
@@ -173,26 +536,159 @@ impl SystemConfig {
                 });
             }
 
+            let driver = if m.mode.as_deref() == Some("sync") {
+                MountDriver::Sync
+            } else {
+                match m.driver.as_deref() {
+                    Some("9p") => MountDriver::NineP,
+                    Some("sshfs") => MountDriver::Sshfs,
+                    Some(_) => MountDriver::Virtiofs,
+                    None => *auto_driver.get_or_insert_with(|| {
+                        if crate::util::virtiofsd_available() {
+                            MountDriver::Virtiofs
+                        } else {
+                            tracing::info!("virtiofsd not found; sharing mounts over 9p");
+                            MountDriver::NineP
+                        }
+                    }),
+                }
+            };
+
             resolved.push(ResolvedMount {
                 source,
                 target: m.target.clone(),
                 readonly: m.readonly,
                 tag,
                 default: m.default,
+                driver,
             });
         }
 
         Ok(resolved)
     }
 
+    /// Load passphrases for encrypted drives from `key_env` / `key_file`.
+    pub fn resolve_luks(&self, drives: &[ResolvedDrive]) -> Result<Vec<LuksDrive>, Error> {
+        let mut resolved = Vec::new();
+        for drive in drives.iter().filter(|d| d.encrypted) {
+            let Some(config) = self.config.drives.get(&drive.name) else {
+                continue;
+            };
+            let key = match (&config.key_env, &config.key_file) {
+                (Some(var), _) => std::env::var(var).map_err(|_| Error::Validation {
+                    message: format!("drive '{}': {var} is not set", drive.name),
+                })?,
+                (None, Some(path)) => std::fs::read_to_string(path).map_err(|e| Error::Io {
+                    context: format!("reading key file for drive '{}'", drive.name),
+                    source: e,
+                })?,
+                (None, None) => unreachable!("validated: encrypted drives have a key source"),
+            };
+            let key = key.trim_end_matches(['\r', '\n']).to_string();
+            if key.is_empty() {
+                return Err(Error::Validation {
+                    message: format!("drive '{}': passphrase is empty", drive.name),
+                });
+            }
+            resolved.push(LuksDrive {
+                name: drive.name.clone(),
+                dev: format!("/dev/{}", drive.dev),
+                mapper: luks_mapper(&drive.name),
+                key,
+            });
+        }
+        Ok(resolved)
+    }
+
+    /// `[provision.system]` script, read from `script_file` when set.
+    pub fn system_script(&self) -> Result<Option<String>, Error> {
+        let Some(system) = &self.config.provision.system else {
+            return Ok(None);
+        };
+        read_script("system", &system.script, system.script_file.as_deref()).map(Some)
+    }
+
+    /// `[provision.boot]` script, read from `script_file` when set.
+    pub fn boot_script(&self) -> Result<Option<String>, Error> {
+        let Some(boot) = &self.config.provision.boot else {
+            return Ok(None);
+        };
+        read_script("boot", &boot.script, boot.script_file.as_deref()).map(Some)
+    }
+
+    /// Contents of every file-backed provisioning script, in config order.
+    pub fn provision_file_contents(&self) -> Result<Vec<String>, Error> {
+        let provision = &self.config.provision;
+        let mut contents = Vec::new();
+        if provision
+            .system
+            .as_ref()
+            .is_some_and(|s| s.script_file.is_some())
+        {
+            contents.extend(self.system_script()?);
+        }
+        if provision
+            .boot
+            .as_ref()
+            .is_some_and(|b| b.script_file.is_some())
+        {
+            contents.extend(self.boot_script()?);
+        }
+        for step in self.resolve_steps()? {
+            if step.from_file {
+                contents.push(step.script);
+            }
+        }
+        Ok(contents)
+    }
+
+    /// Resolve `[[provision.step]]` entries into runnable scripts, reading
+    /// `file` steps from disk.
+    pub fn resolve_steps(&self) -> Result<Vec<ResolvedStep>, Error> {
+        self.config
+            .provision
+            .steps
+            .iter()
+            .map(|step| {
+                let script = match (&step.script, &step.file) {
+                    (Some(script), _) => script.clone(),
+                    (None, Some(file)) => std::fs::read_to_string(file).map_err(|e| Error::Io {
+                        context: format!("reading script for provision step '{}'", step.name),
+                        source: e,
+                    })?,
+                    (None, None) => unreachable!("validated: steps have a script or file"),
+                };
+                Ok(ResolvedStep {
+                    name: step.name.clone(),
+                    script,
+                    from_file: step.file.is_some(),
+                    boot: step.run_on == "boot",
+                    order: step.order,
+                    when: step.when.clone(),
+                    depends_on: step.depends_on.clone(),
+                    user: step.user.clone(),
+                    interpreter: step.interpreter.clone(),
+                })
+            })
+            .collect()
+    }
+
     /// Resolve filesystem entries by mapping drive names to device paths.
     ///
     /// Must be called after `resolve_drives()` — uses the resolved drives
     /// to look up device names (vdb, vdc, ...).
     pub fn resolve_fs(&self, drives: &[ResolvedDrive]) -> Result<Vec<ResolvedFs>, Error> {
-        let drive_map: std::collections::HashMap<&str, &str> = drives
+        // Filesystems on encrypted drives live on the opened LUKS mapping.
+        let drive_map: std::collections::HashMap<&str, String> = drives
             .iter()
-            .map(|d| (d.name.as_str(), d.dev.as_str()))
+            .map(|d| {
+                let dev = if d.encrypted {
+                    format!("mapper/{}", luks_mapper(&d.name))
+                } else {
+                    d.dev.clone()
+                };
+                (d.name.as_str(), dev)
+            })
             .collect();
 
         let mut resolved = Vec::new();
@@ -264,3 +760,17 @@ impl SystemConfig {
         Ok(resolved)
     }
 }
+
+fn read_script(label: &str, script: &str, script_file: Option<&str>) -> Result<String, Error> {
+    match script_file {
+        Some(path) => std::fs::read_to_string(path).map_err(|e| Error::Io {
+            context: format!("reading provision.{label} script_file {path}"),
+            source: e,
+        }),
+        None => Ok(script.to_string()),
+    }
+}
+
+fn luks_mapper(drive_name: &str) -> String {
+    format!("rum-{drive_name}")
+}
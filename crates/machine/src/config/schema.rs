@@ -13,12 +13,93 @@ pub struct MountConfig {
     pub tag: String,
     #[facet(default)]
     pub default: bool,
+    /// `mount` (default) shares the host directory; `sync` keeps a
+    /// separate copy in the guest that rum syncs both ways.
+    pub mode: Option<String>,
+    /// `virtiofs`, `9p` or `sshfs`. Unset uses virtiofs, or 9p when the
+    /// host has no virtiofsd.
+    pub driver: Option<String>,
+    /// Gitignore-style patterns a `sync` mount neither watches nor copies.
+    /// Unset skips `target/`, `node_modules/` and `.git/`.
+    pub inotify_ignore: Option<Vec<String>>,
+    /// Also skip what the `sync` mount's `.gitignore` ignores.
+    #[facet(default)]
+    pub inotify_gitignore: bool,
+    /// Batch a `sync` mount's fs events per path for this long; `0` syncs
+    /// each event.
+    pub inotify_debounce_ms: Option<u64>,
 }
 
+/// An extra ISO image attached read-only as a CD-ROM, e.g. virtio drivers
+/// for Windows guests.
 #[derive(Debug, Clone, Default, Facet)]
 #[facet(default)]
+pub struct CdromConfig {
+    /// ISO file, relative to the config file.
+    pub path: String,
+}
+
+#[derive(Debug, Clone, Facet)]
+#[facet(default)]
 pub struct DriveConfig {
     pub size: String,
+    /// Attach an existing block device or image instead of creating one.
+    /// Block devices are always raw; image files use `format`.
+    pub path: Option<String>,
+    #[facet(default)]
+    pub readonly: bool,
+    /// Format the drive as LUKS inside the guest. The passphrase comes from
+    /// `key_env` or `key_file` on the host and reaches the guest only over
+    /// the agent channel.
+    #[facet(default)]
+    pub encrypted: bool,
+    /// Host environment variable holding the passphrase.
+    pub key_env: Option<String>,
+    /// Host file holding the passphrase (trailing newline ignored).
+    pub key_file: Option<String>,
+    /// Image format: `qcow2` (sparse, default) or `raw`.
+    #[facet(default = "qcow2")]
+    pub format: String,
+    /// `off`, `metadata`, or `full`. Preallocated images trade disk space
+    /// and creation time for steadier write throughput.
+    #[facet(default = "off")]
+    pub preallocation: String,
+    /// I/O tuning, set in the drive table itself like in `[root_disk]`.
+    #[facet(flatten)]
+    pub io: DiskIoConfig,
+}
+
+impl Default for DriveConfig {
+    fn default() -> Self {
+        Self {
+            size: String::new(),
+            path: None,
+            readonly: false,
+            encrypted: false,
+            key_env: None,
+            key_file: None,
+            format: "qcow2".into(),
+            preallocation: "off".into(),
+            io: DiskIoConfig::default(),
+        }
+    }
+}
+
+/// I/O tuning for the root disk (`[root_disk]`) and extra drives.
+/// Unset options keep the libvirt/QEMU defaults.
+#[derive(Debug, Clone, Default, Facet)]
+#[facet(default)]
+pub struct DiskIoConfig {
+    /// `none`, `writeback`, `writethrough`, `directsync`, or `unsafe`.
+    pub cache: Option<String>,
+    /// `native`, `threads`, or `io_uring`.
+    pub io: Option<String>,
+    /// Pass guest TRIM through to the image so freed blocks shrink it.
+    #[facet(default)]
+    pub discard: bool,
+    /// Run the disk on a dedicated QEMU I/O thread.
+    #[facet(default)]
+    pub iothread: bool,
 }
 
 #[derive(Debug, Clone, Default, Facet)]
@@ -45,13 +126,21 @@ pub struct PortForward {
 }
 
 impl PortForward {
+    /// Host address to listen on, with `localhost` taken as `127.0.0.1`.
     pub fn bind_addr(&self) -> &str {
-        if self.bind.is_empty() {
+        if self.bind.is_empty() || self.bind.eq_ignore_ascii_case("localhost") {
             "127.0.0.1"
         } else {
             &self.bind
         }
     }
+
+    /// Host socket address to listen on, or `None` if `bind` is neither an IP
+    /// nor `localhost`.
+    pub fn socket_addr(&self) -> Option<std::net::SocketAddr> {
+        let ip: std::net::IpAddr = self.bind_addr().parse().ok()?;
+        Some(std::net::SocketAddr::new(ip, self.host))
+    }
 }
 
 #[derive(Debug, Clone, Facet)]
@@ -69,18 +158,299 @@ pub struct Config {
     #[facet(default)]
     pub user: UserConfig,
     #[facet(default)]
+    pub guest: GuestConfig,
+    /// Extra login accounts created next to `[user]`.
+    #[facet(default)]
+    pub users: Vec<UserConfig>,
+    #[facet(default)]
     pub mounts: Vec<MountConfig>,
     #[facet(default)]
+    pub root_disk: DiskIoConfig,
+    #[facet(default)]
     pub drives: BTreeMap<String, DriveConfig>,
     #[facet(default)]
+    pub cdroms: Vec<CdromConfig>,
+    #[facet(default)]
     pub fs: BTreeMap<String, Vec<FsEntryConfig>>,
     #[facet(default)]
     pub ports: Vec<PortForward>,
+    #[facet(default)]
+    pub metadata: MetadataConfig,
+    #[facet(default)]
+    pub display: DisplayConfig,
+    #[facet(default)]
+    pub ready: ReadyConfig,
+    #[facet(default)]
+    pub timeouts: TimeoutsConfig,
+    #[facet(default)]
+    pub serve: ServeConfig,
+    #[facet(default)]
+    pub logging: LoggingConfig,
+    #[facet(default)]
+    pub notify: NotifyConfig,
+    #[facet(default)]
+    pub docker: DockerConfig,
+    #[facet(default)]
+    pub tests: Vec<TestConfig>,
+}
+
+/// Graphical console. Machines are headless unless a protocol is chosen.
+#[derive(Debug, Clone, Facet)]
+#[facet(default)]
+pub struct DisplayConfig {
+    /// `none`, `spice`, or `vnc`.
+    #[facet(default = "none")]
+    pub protocol: String,
+    #[facet(default = "127.0.0.1")]
+    pub listen: String,
+    /// Fixed port; libvirt picks a free one when unset.
+    pub port: Option<u16>,
+}
+
+impl Default for DisplayConfig {
+    fn default() -> Self {
+        Self {
+            protocol: "none".into(),
+            listen: "127.0.0.1".into(),
+            port: None,
+        }
+    }
+}
+
+/// Probes that must pass before a machine is reported as running.
+///
+/// All probes run inside the guest through the agent, so they see the
+/// guest's own `localhost`.
+#[derive(Debug, Clone, Facet)]
+#[facet(default)]
+pub struct ReadyConfig {
+    /// Guest TCP port that must accept connections.
+    pub tcp: Option<u16>,
+    /// `http://localhost[:port]/path` that must answer with a 2xx or 3xx status.
+    pub http: Option<String>,
+    /// Guest shell command that must exit 0.
+    pub command: Option<String>,
+    /// How long to keep retrying before `rum up` fails.
+    #[facet(default = 120)]
+    pub timeout_s: u64,
+}
+
+impl Default for ReadyConfig {
+    fn default() -> Self {
+        Self {
+            tcp: None,
+            http: None,
+            command: None,
+            timeout_s: 120,
+        }
+    }
+}
+
+impl ReadyConfig {
+    pub fn is_empty(&self) -> bool {
+        self.tcp.is_none() && self.http.is_none() && self.command.is_none()
+    }
+
+    /// Guest port and request path of the `http` probe, or `None` when the
+    /// URL is not a plain `http://` URL on the guest's loopback.
+    pub fn http_target(&self) -> Option<(u16, String)> {
+        let rest = self.http.as_deref()?.strip_prefix("http://")?;
+        let (authority, path) = match rest.find('/') {
+            Some(i) => rest.split_at(i),
+            None => (rest, "/"),
+        };
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) => (host, port.parse().ok().filter(|&p: &u16| p > 0)?),
+            None => (authority, 80),
+        };
+        matches!(host, "localhost" | "127.0.0.1").then(|| (port, path.to_string()))
+    }
+}
+
+/// Limits on the slow lifecycle steps, so a flaky network or a guest that
+/// never comes up fails `rum up` instead of hanging it. `0` waits
+/// indefinitely.
+#[derive(Debug, Clone, Facet)]
+#[facet(default)]
+pub struct TimeoutsConfig {
+    /// Per attempt of the base image download.
+    #[facet(default = 1800)]
+    pub image_download_s: u64,
+    /// Starting the domain.
+    #[facet(default = 120)]
+    pub boot_s: u64,
+    /// Per attempt of connecting to the guest agent after boot.
+    #[facet(default = 300)]
+    pub agent_connect_s: u64,
+    /// Waiting for cloud-init to finish its first-boot run, once the agent
+    /// answers.
+    #[facet(default = 600)]
+    pub cloud_init_s: u64,
+    /// All provisioning scripts of one run together. Scripts are never
+    /// retried.
+    #[facet(default = 0)]
+    pub script_s: u64,
+    /// How long a guest gets to power off before it is forced off.
+    #[facet(default = 10)]
+    pub shutdown_s: u64,
+    /// How often the agent of a running machine gets a heartbeat. Must be
+    /// at least 1.
+    #[facet(default = 2)]
+    pub heartbeat_interval_s: u64,
+    /// How long the agent gets to answer one heartbeat.
+    #[facet(default = 5)]
+    pub heartbeat_s: u64,
+    /// Heartbeats missed in a row before the machine counts as unhealthy.
+    /// Must be at least 1.
+    #[facet(default = 3)]
+    pub missed_heartbeats: u32,
+    /// Extra attempts after a failed or timed-out download or agent
+    /// connection.
+    #[facet(default = 2)]
+    pub retries: u32,
+}
+
+impl Default for TimeoutsConfig {
+    fn default() -> Self {
+        Self {
+            image_download_s: 1800,
+            boot_s: 120,
+            agent_connect_s: 300,
+            cloud_init_s: 600,
+            script_s: 0,
+            shutdown_s: 10,
+            heartbeat_interval_s: 2,
+            heartbeat_s: 5,
+            missed_heartbeats: 3,
+            retries: 2,
+        }
+    }
+}
+
+/// The daemon's `rum.log` in the work dir and the provisioning script logs.
+#[derive(Debug, Clone, Facet)]
+#[facet(default)]
+pub struct LoggingConfig {
+    /// `text`, or `json` for one object per line.
+    #[facet(default = "text")]
+    pub format: String,
+    /// Start a new `rum.log` once it grows past this size; `0` never does.
+    #[facet(default = 10)]
+    pub max_size_mb: u64,
+    /// Also start a new `rum.log` every day.
+    #[facet(default)]
+    pub rotate_daily: bool,
+    /// Old `rum.log` files, and finished logs per provisioning script, to
+    /// keep.
+    #[facet(default = 10)]
+    pub max_files: usize,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self {
+            format: "text".into(),
+            max_size_mb: 10,
+            rotate_daily: false,
+            max_files: 10,
+        }
+    }
+}
+
+/// Extra control surfaces of the daemon next to its Unix socket.
+#[derive(Debug, Clone, Default, Facet)]
+#[facet(default)]
+pub struct ServeConfig {
+    /// Address of the HTTP control API, e.g. `127.0.0.1:7878`. Requests
+    /// need the bearer token the daemon writes to its work dir.
+    pub http: Option<String>,
+    /// Allow `http` to bind a non-loopback address. The API is plain HTTP,
+    /// so the token, which allows exec in the guest, crosses the network in
+    /// the clear.
+    pub allow_remote: bool,
+}
+
+/// Notifications the daemon sends when the machine is up, stops or fails.
+#[derive(Debug, Clone, Default, Facet)]
+#[facet(default)]
+pub struct NotifyConfig {
+    /// Show a desktop notification when provisioning finishes or fails.
+    pub desktop: bool,
+    /// URLs to POST a Slack-compatible `{"text": ...}` message to when the
+    /// machine is up, has stopped or has failed.
+    #[facet(default)]
+    pub webhooks: Vec<String>,
+}
+
+/// A container engine installed in the guest, with its Docker API socket
+/// forwarded to a host socket so local `docker` clients can use it.
+#[derive(Debug, Clone, Facet)]
+#[facet(default)]
+pub struct DockerConfig {
+    pub enable: bool,
+    /// `docker` or `podman`; podman serves the Docker API as well.
+    #[facet(default = "docker")]
+    pub engine: String,
+}
+
+impl Default for DockerConfig {
+    fn default() -> Self {
+        Self {
+            enable: false,
+            engine: "docker".into(),
+        }
+    }
+}
+
+/// One `[[tests]]` entry: a guest command `rum test` runs and checks.
+#[derive(Debug, Clone, Facet)]
+#[facet(default)]
+pub struct TestConfig {
+    pub name: String,
+    /// Shell command run in the guest.
+    pub command: String,
+    /// Exit code the command has to end with.
+    pub exit_code: i32,
+    /// Regex the command's stdout has to match somewhere.
+    pub stdout: Option<String>,
+    /// Fail the test and kill the command after this many seconds.
+    #[facet(default = 300)]
+    pub timeout_s: u64,
+    /// Guest user to run the command as instead of root.
+    pub user: Option<String>,
+}
+
+impl Default for TestConfig {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            command: String::new(),
+            exit_code: 0,
+            stdout: None,
+            timeout_s: 300,
+            user: None,
+        }
+    }
+}
+
+/// Free-form labels written into the libvirt domain metadata so hosts with
+/// many rum VMs can filter them with `rum list --filter label=...`.
+#[derive(Debug, Clone, Default, Facet)]
+#[facet(default)]
+pub struct MetadataConfig {
+    #[facet(default)]
+    pub labels: BTreeMap<String, String>,
 }
 
 #[derive(Debug, Clone, Facet)]
 pub struct ImageConfig {
     pub base: String,
+    /// Expected hex SHA-256 of the base image.
+    pub sha256: Option<String>,
+    /// Expected hex SHA-512 of the base image.
+    pub sha512: Option<String>,
+    /// Guest CPU architecture (`x86_64` or `aarch64`). Defaults to the host's.
+    pub arch: Option<String>,
 }
 
 #[derive(Debug, Clone, Facet)]
@@ -89,6 +459,44 @@ pub struct ResourcesConfig {
     pub memory_mb: u64,
     #[facet(default = "20G")]
     pub disk: String,
+    /// Ceiling for `rum resize --memory`; the domain boots with `memory_mb`.
+    pub memory_max_mb: Option<u64>,
+    /// Ceiling for `rum resize --cpus`; the domain boots with `cpus` online.
+    pub cpus_max: Option<u32>,
+    /// `host-passthrough`, `host-model`, or a named QEMU model such as
+    /// `Skylake-Client`. Left to libvirt when unset.
+    pub cpu_model: Option<String>,
+    /// CPU topology. Missing values default to 1; the product must equal
+    /// `cpus_max` (or `cpus` without hotplug headroom).
+    pub sockets: Option<u32>,
+    pub cores: Option<u32>,
+    pub threads: Option<u32>,
+    /// Expose hardware virtualization to the guest. Implies
+    /// `cpu_model = "host-passthrough"` unless a host model is set.
+    #[facet(default)]
+    pub nested: bool,
+}
+
+impl ResourcesConfig {
+    /// `(sockets, cores, threads)` when any of them is set.
+    pub fn topology(&self) -> Option<(u32, u32, u32)> {
+        if self.sockets.is_none() && self.cores.is_none() && self.threads.is_none() {
+            return None;
+        }
+        Some((
+            self.sockets.unwrap_or(1),
+            self.cores.unwrap_or(1),
+            self.threads.unwrap_or(1),
+        ))
+    }
+
+    /// Effective CPU model, with `nested` defaulting to host-passthrough.
+    pub fn effective_cpu_model(&self) -> Option<&str> {
+        match self.cpu_model.as_deref() {
+            None if self.nested => Some("host-passthrough"),
+            model => model,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Default, Facet)]
@@ -97,6 +505,77 @@ pub struct InterfaceConfig {
     pub network: String,
     #[facet(default)]
     pub ip: String,
+    /// Fixed subnet such as `192.168.77.0/24`. Interfaces that set one join a
+    /// network shared by every VM using the same network name, instead of a
+    /// network private to this VM.
+    #[facet(default)]
+    pub subnet: String,
+    /// DHCP range; defaults to `.100`–`.254` of the subnet.
+    pub dhcp: Option<DhcpRangeConfig>,
+    /// DNS domain served by the network's resolver.
+    #[facet(default)]
+    pub domain: String,
+    /// Static DNS records served by the network's resolver.
+    #[facet(default)]
+    pub hosts: Vec<DnsHostConfig>,
+    /// Add an IPv6 ULA /64 with router advertisements and DHCPv6 to the
+    /// network. Implied by an IPv6 `ip`.
+    #[facet(default)]
+    pub ipv6: bool,
+    /// Static guest addresses in CIDR form, such as `192.168.50.10/24`.
+    /// The guest skips DHCP on this interface when set.
+    #[facet(default)]
+    pub addresses: Vec<String>,
+    /// Default route for a static interface.
+    #[facet(default)]
+    pub gateway: String,
+    /// DNS servers the guest uses on this interface.
+    #[facet(default)]
+    pub nameservers: Vec<String>,
+    /// DNS search domains the guest uses on this interface.
+    #[facet(default)]
+    pub search: Vec<String>,
+    pub mtu: Option<u32>,
+}
+
+impl InterfaceConfig {
+    /// First three octets of `subnet`, or `None` when it is not a valid
+    /// `a.b.c.0/24` subnet.
+    pub fn subnet_prefix(&self) -> Option<&str> {
+        let prefix = self.subnet.strip_suffix(".0/24")?;
+        let octets: Vec<&str> = prefix.split('.').collect();
+        let valid = octets.len() == 3 && octets.iter().all(|o| o.parse::<u8>().is_ok());
+        valid.then_some(prefix)
+    }
+
+    /// Whether `ip` is an IPv6 address.
+    pub fn has_ipv6_ip(&self) -> bool {
+        self.ip.parse::<std::net::Ipv6Addr>().is_ok()
+    }
+
+    pub fn ipv6_enabled(&self) -> bool {
+        self.ipv6 || self.has_ipv6_ip()
+    }
+
+    /// `ip` when it can seed the IPv4 subnet, empty otherwise.
+    pub fn ipv4_hint(&self) -> &str {
+        if self.has_ipv6_ip() { "" } else { &self.ip }
+    }
+}
+
+#[derive(Debug, Clone, Default, Facet)]
+#[facet(default)]
+pub struct DhcpRangeConfig {
+    pub start: String,
+    pub end: String,
+}
+
+/// A static DNS record: `names` resolve to `ip` on the network.
+#[derive(Debug, Clone, Default, Facet)]
+#[facet(default)]
+pub struct DnsHostConfig {
+    pub ip: String,
+    pub names: Vec<String>,
 }
 
 #[derive(Debug, Clone, Facet)]
@@ -112,6 +591,21 @@ pub struct NetworkConfig {
     pub ip_wait_timeout_s: u64,
     #[facet(default)]
     pub interfaces: Vec<InterfaceConfig>,
+    /// DNS servers the guest uses on the NAT interface instead of the ones
+    /// handed out by DHCP.
+    #[facet(default)]
+    pub nameservers: Vec<String>,
+    /// DNS search domains for the NAT interface.
+    #[facet(default)]
+    pub search: Vec<String>,
+    /// Keep `/etc/hosts` entries for the other rum machines on this
+    /// machine's shared networks, so they resolve each other by name.
+    #[facet(default = true)]
+    pub peer_hosts: bool,
+    /// Advertise `<hostname>.local` for the guest over mDNS on the host
+    /// while the machine runs, through the host's avahi daemon.
+    #[facet(default)]
+    pub mdns: bool,
 }
 
 impl Default for NetworkConfig {
@@ -122,6 +616,10 @@ impl Default for NetworkConfig {
             wait_for_ip: true,
             ip_wait_timeout_s: 120,
             interfaces: Vec::new(),
+            nameservers: Vec::new(),
+            search: Vec::new(),
+            peer_hosts: true,
+            mdns: false,
         }
     }
 }
@@ -131,16 +629,77 @@ impl Default for NetworkConfig {
 pub struct ProvisionConfig {
     pub system: Option<ProvisionSystemConfig>,
     pub boot: Option<ProvisionBootConfig>,
+    /// Distro packages installed before the system script runs.
+    #[facet(default)]
+    pub packages: Vec<String>,
+    /// Named `[[provision.step]]` entries, run alongside `system`/`boot`.
+    #[facet(default, rename = "step")]
+    pub steps: Vec<ProvisionStep>,
+    /// Provision the guest as NixOS: users, mounts and drive tooling are
+    /// declared in a generated module applied with `nixos-rebuild`. Unset
+    /// detects NixOS from the base image name.
+    pub nixos: Option<bool>,
 }
 
 #[derive(Debug, Clone, Facet)]
 pub struct ProvisionSystemConfig {
+    #[facet(default)]
     pub script: String,
+    /// Script file, relative to the config file. Replaces `script`.
+    pub script_file: Option<String>,
 }
 
 #[derive(Debug, Clone, Facet)]
 pub struct ProvisionBootConfig {
+    #[facet(default)]
     pub script: String,
+    /// Script file, relative to the config file. Replaces `script`.
+    pub script_file: Option<String>,
+}
+
+/// One named provisioning step.
+#[derive(Debug, Clone, Facet)]
+#[facet(default)]
+pub struct ProvisionStep {
+    pub name: String,
+    /// Inline script; exactly one of `script` and `file` is set.
+    pub script: Option<String>,
+    /// Script file, relative to the config file.
+    pub file: Option<String>,
+    /// Guest user the step runs as; root when unset.
+    pub user: Option<String>,
+    /// Program the script is handed to as a file, e.g. `bash` or
+    /// `python3 -u`. `sh` when unset.
+    pub interpreter: Option<String>,
+    /// `system` (once, on first provision) or `boot` (every boot).
+    #[facet(default = "system")]
+    pub run_on: String,
+    /// Steps run in ascending order; built-in steps use 0 (system) and 100 (boot).
+    #[facet(default = 50)]
+    pub order: u32,
+    /// Shell condition evaluated in the guest; the step is skipped unless it
+    /// exits 0.
+    pub when: Option<String>,
+    /// Steps (or `system`) that must finish first. When set, the step runs
+    /// concurrently with everything it does not depend on; when unset it
+    /// waits for all steps ordered before it.
+    pub depends_on: Option<Vec<String>>,
+}
+
+impl Default for ProvisionStep {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            script: None,
+            file: None,
+            user: None,
+            interpreter: None,
+            run_on: "system".into(),
+            order: 50,
+            when: None,
+            depends_on: None,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Facet)]
@@ -152,8 +711,15 @@ pub struct AdvancedConfig {
     pub domain_type: String,
     #[facet(default = "q35")]
     pub machine: String,
+    /// `bios` or `uefi`. aarch64 guests always boot through UEFI.
+    #[facet(default = "bios")]
+    pub firmware: String,
     #[facet(default)]
     pub autologin: bool,
+    /// Format ext4/xfs drives on the host before first boot. Requires a
+    /// build with the `host-mkfs` feature; ignored otherwise.
+    #[facet(default)]
+    pub host_mkfs: bool,
 }
 
 impl Default for AdvancedConfig {
@@ -162,7 +728,9 @@ impl Default for AdvancedConfig {
             libvirt_uri: "qemu:///system".into(),
             domain_type: "kvm".into(),
             machine: "q35".into(),
+            firmware: "bios".into(),
             autologin: false,
+            host_mkfs: false,
         }
     }
 }
@@ -178,6 +746,21 @@ pub struct SshConfig {
     pub interface: String,
     #[facet(default)]
     pub authorized_keys: Vec<String>,
+    /// Forward the host's ssh-agent, e.g. to clone private git repos.
+    #[facet(default)]
+    pub forward_agent: bool,
+    /// Reach the machine through this jump host (`ssh -J`).
+    pub proxy_jump: Option<String>,
+    /// Extra `Key=Value` ssh options, e.g. `ServerAliveInterval=30`.
+    #[facet(default)]
+    pub extra_options: Vec<String>,
+    /// Share one connection between `rum ssh` invocations through a
+    /// ControlMaster socket, kept open for ten minutes after the last one.
+    #[facet(default = true)]
+    pub multiplex: bool,
+    /// Socket of the shared connection; `ssh.sock` in the work dir when
+    /// unset.
+    pub control_path: Option<String>,
 }
 
 impl Default for SshConfig {
@@ -187,10 +770,35 @@ impl Default for SshConfig {
             command: "ssh".into(),
             interface: String::new(),
             authorized_keys: Vec::new(),
+            forward_agent: false,
+            proxy_jump: None,
+            extra_options: Vec::new(),
+            multiplex: true,
+            control_path: None,
         }
     }
 }
 
+/// Guest OS settings applied by cloud-init on first boot.
+#[derive(Debug, Clone, Default, Facet)]
+#[facet(default)]
+pub struct GuestConfig {
+    /// tz database name, e.g. `Europe/Oslo`. The image default (usually
+    /// UTC) when unset.
+    pub timezone: Option<String>,
+    /// System locale, e.g. `en_US.UTF-8`.
+    pub locale: Option<String>,
+    /// NTP servers replacing the image's defaults.
+    #[facet(default)]
+    pub ntp_servers: Vec<String>,
+    /// Ship the agent inside user-data instead of as a file on the seed
+    /// ISO, for images whose cloud-init reads user-data from elsewhere or
+    /// cannot mount the seed.
+    #[facet(default)]
+    pub inline_agent: bool,
+}
+
+/// A guest login account: the main `[user]` or an extra `[[users]]` entry.
 #[derive(Debug, Clone, Facet)]
 #[facet(default)]
 pub struct UserConfig {
@@ -198,6 +806,23 @@ pub struct UserConfig {
     pub name: String,
     #[facet(default)]
     pub groups: Vec<String>,
+    /// Fixed UID; the guest picks the next free one when unset.
+    pub uid: Option<u32>,
+    /// Login shell, `/bin/bash` by default.
+    pub shell: Option<String>,
+    /// Plain-text password. Without one (or `hashed_passwd`) the password
+    /// is locked and the user logs in with SSH keys only.
+    pub password: Option<String>,
+    /// Password hash in crypt(3) format, e.g. from `mkpasswd`. Replaces
+    /// `password`.
+    pub hashed_passwd: Option<String>,
+    /// Public keys authorized for this user only. The main user also gets
+    /// rum's key and `[ssh] authorized_keys`.
+    #[facet(default)]
+    pub ssh_keys: Vec<String>,
+    /// Git repository cloned to `~/.dotfiles`, or a host directory (relative
+    /// to the config file) whose files are copied into the home directory.
+    pub dotfiles: Option<String>,
 }
 
 impl Default for UserConfig {
@@ -205,6 +830,12 @@ impl Default for UserConfig {
         Self {
             name: "rum".into(),
             groups: Vec::new(),
+            uid: None,
+            shell: None,
+            password: None,
+            hashed_passwd: None,
+            ssh_keys: Vec::new(),
+            dotfiles: None,
         }
     }
 }
@@ -6,25 +6,55 @@ use super::runtime::*;
 use super::schema::*;
 use super::validate::{validate_config, validate_name};
 
-fn valid_config() -> Config {
+pub(crate) fn valid_config() -> Config {
     Config {
         image: ImageConfig {
             base: "https://example.com/image.qcow2".into(),
+            sha256: None,
+            sha512: None,
+            arch: None,
         },
         resources: ResourcesConfig {
             cpus: 1,
             memory_mb: 512,
             disk: "20G".into(),
+            memory_max_mb: None,
+            cpus_max: None,
+            cpu_model: None,
+            sockets: None,
+            cores: None,
+            threads: None,
+            nested: false,
         },
         network: NetworkConfig::default(),
         provision: ProvisionConfig::default(),
         advanced: AdvancedConfig::default(),
         ssh: SshConfig::default(),
         user: UserConfig::default(),
+        users: vec![],
+        guest: GuestConfig::default(),
         mounts: vec![],
+        root_disk: DiskIoConfig::default(),
         drives: BTreeMap::new(),
+        cdroms: vec![],
         fs: BTreeMap::new(),
         ports: vec![],
+        metadata: MetadataConfig::default(),
+        display: DisplayConfig::default(),
+        ready: ReadyConfig::default(),
+        timeouts: TimeoutsConfig::default(),
+        serve: ServeConfig::default(),
+        logging: LoggingConfig::default(),
+        notify: NotifyConfig::default(),
+        docker: DockerConfig::default(),
+        tests: vec![],
+    }
+}
+
+fn drive(size: &str) -> DriveConfig {
+    DriveConfig {
+        size: size.into(),
+        ..Default::default()
     }
 }
 
@@ -34,6 +64,7 @@ pub fn test_system_config() -> SystemConfig {
         id: "deadbeef".into(),
         name: Some("test-vm".into()),
         config_path: PathBuf::from("/tmp/test-vm.rum.toml"),
+        profile: None,
         config: valid_config(),
     }
 }
@@ -115,6 +146,7 @@ fn empty_interface_network_rejected() {
     config.network.interfaces = vec![InterfaceConfig {
         network: String::new(),
         ip: String::new(),
+        ..Default::default()
     }];
     assert!(validate_config(&config).is_err());
 }
@@ -125,6 +157,7 @@ fn valid_interface_config() {
     config.network.interfaces = vec![InterfaceConfig {
         network: "rum-hostonly".into(),
         ip: "192.168.50.10".into(),
+        ..Default::default()
     }];
     validate_config(&config).unwrap();
 }
@@ -184,6 +217,56 @@ fn hostname_uses_explicit_value() {
     assert_eq!(sc.hostname(), "custom-host");
 }
 
+#[test]
+fn mdns_name_uses_first_hostname_label() {
+    let mut sc = test_system_config();
+    assert_eq!(sc.mdns_name(), "test-vm.local");
+    sc.config.network.hostname = "web.example.com".into();
+    assert_eq!(sc.mdns_name(), "web.local");
+}
+
+#[test]
+fn interface_with_subnet_dhcp_and_dns() {
+    let toml = r#"
+[image]
+base = "ubuntu.img"
+
+[resources]
+cpus = 1
+memory_mb = 512
+
+[[network.interfaces]]
+network = "lab"
+ip = "192.168.77.10"
+subnet = "192.168.77.0/24"
+dhcp = { start = "192.168.77.50", end = "192.168.77.99" }
+domain = "lab.internal"
+hosts = [{ ip = "192.168.77.5", names = ["db", "db.lab.internal"] }]
+"#;
+    let config: Config = facet_toml::from_str(toml).unwrap();
+    validate_config(&config).unwrap();
+    let iface = &config.network.interfaces[0];
+    assert_eq!(iface.subnet_prefix(), Some("192.168.77"));
+    assert_eq!(iface.dhcp.as_ref().unwrap().end, "192.168.77.99");
+    assert_eq!(iface.hosts[0].names, ["db", "db.lab.internal"]);
+
+    let mut bad = config.clone();
+    bad.network.interfaces[0].ip = "192.168.78.10".into();
+    assert!(validate_config(&bad).is_err(), "ip outside the subnet");
+    let mut bad = config.clone();
+    bad.network.interfaces[0].subnet = "192.168.77.0/16".into();
+    assert!(validate_config(&bad).is_err(), "only /24 subnets");
+    let mut bad = config.clone();
+    bad.network.interfaces[0].dhcp = Some(DhcpRangeConfig {
+        start: "192.168.77.99".into(),
+        end: "192.168.77.50".into(),
+    });
+    assert!(validate_config(&bad).is_err(), "reversed range");
+    let mut bad = config;
+    bad.network.interfaces[0].subnet.clear();
+    assert!(validate_config(&bad).is_err(), "dns without a subnet");
+}
+
 #[test]
 fn parse_config_with_fs_ext4() {
     let toml = r#"
@@ -208,6 +291,30 @@ target = "/mnt/data"
     assert_eq!(config.fs["ext4"][0].target, "/mnt/data");
 }
 
+#[test]
+fn parse_drive_io_options_inline() {
+    let toml = r#"
+[image]
+base = "ubuntu.img"
+
+[resources]
+cpus = 1
+memory_mb = 512
+
+[drives.data]
+size = "20G"
+cache = "none"
+io = "native"
+discard = true
+"#;
+    let config: Config = facet_toml::from_str(toml).unwrap();
+    validate_config(&config).unwrap();
+    let io = &config.drives["data"].io;
+    assert_eq!(io.cache.as_deref(), Some("none"));
+    assert_eq!(io.io.as_deref(), Some("native"));
+    assert!(io.discard && !io.iothread);
+}
+
 #[test]
 fn parse_config_with_fs_zfs() {
     let toml = r#"
@@ -240,7 +347,7 @@ pool = "logspool"
 #[test]
 fn fs_missing_target_rejected() {
     let mut config = valid_config();
-    config.drives.insert("d".into(), DriveConfig { size: "10G".into() });
+    config.drives.insert("d".into(), drive("10G"));
     config.fs.insert(
         "ext4".into(),
         vec![FsEntryConfig {
@@ -269,7 +376,7 @@ fn fs_nonexistent_drive_rejected() {
 #[test]
 fn fs_duplicate_drive_rejected() {
     let mut config = valid_config();
-    config.drives.insert("d".into(), DriveConfig { size: "10G".into() });
+    config.drives.insert("d".into(), drive("10G"));
     config.fs.insert(
         "ext4".into(),
         vec![
@@ -291,7 +398,7 @@ fn fs_duplicate_drive_rejected() {
 #[test]
 fn fs_simple_with_drives_rejected() {
     let mut config = valid_config();
-    config.drives.insert("d".into(), DriveConfig { size: "10G".into() });
+    config.drives.insert("d".into(), drive("10G"));
     config.fs.insert(
         "ext4".into(),
         vec![FsEntryConfig {
@@ -306,7 +413,7 @@ fn fs_simple_with_drives_rejected() {
 #[test]
 fn fs_zfs_with_drive_rejected() {
     let mut config = valid_config();
-    config.drives.insert("d".into(), DriveConfig { size: "10G".into() });
+    config.drives.insert("d".into(), drive("10G"));
     config.fs.insert(
         "zfs".into(),
         vec![FsEntryConfig {
@@ -321,7 +428,7 @@ fn fs_zfs_with_drive_rejected() {
 #[test]
 fn resolve_fs_simple() {
     let mut sc = test_system_config();
-    sc.config.drives.insert("data".into(), DriveConfig { size: "20G".into() });
+    sc.config.drives.insert("data".into(), drive("20G"));
     sc.config.fs.insert(
         "ext4".into(),
         vec![FsEntryConfig {
@@ -346,12 +453,8 @@ fn resolve_fs_simple() {
 #[test]
 fn resolve_fs_zfs() {
     let mut sc = test_system_config();
-    sc.config
-        .drives
-        .insert("logs1".into(), DriveConfig { size: "50G".into() });
-    sc.config
-        .drives
-        .insert("logs2".into(), DriveConfig { size: "50G".into() });
+    sc.config.drives.insert("logs1".into(), drive("50G"));
+    sc.config.drives.insert("logs2".into(), drive("50G"));
     sc.config.fs.insert(
         "zfs".into(),
         vec![FsEntryConfig {
@@ -398,6 +501,93 @@ script = "echo boot"
     assert_eq!(boot.script, "echo boot");
 }
 
+#[test]
+fn cdrom_paths_resolve_relative_to_config() {
+    let dir = tempfile::tempdir().unwrap();
+    let config_path = dir.path().join("rum.toml");
+    std::fs::write(
+        &config_path,
+        r#"
+[image]
+base = "ubuntu.img"
+
+[resources]
+cpus = 1
+memory_mb = 512
+
+[[cdroms]]
+path = "isos/virtio-win.iso"
+
+[[cdroms]]
+path = "/srv/tools.iso"
+"#,
+    )
+    .unwrap();
+
+    let system = super::load_config(&config_path).unwrap();
+    let dir = dir.path().canonicalize().unwrap();
+    let paths: Vec<&str> = system
+        .config
+        .cdroms
+        .iter()
+        .map(|c| c.path.as_str())
+        .collect();
+    assert_eq!(
+        paths,
+        [
+            dir.join("isos/virtio-win.iso")
+                .display()
+                .to_string()
+                .as_str(),
+            "/srv/tools.iso"
+        ]
+    );
+
+    let mut config = system.config;
+    config.cdroms = vec![CdromConfig::default(); 6];
+    let msg = validate_config(&config).unwrap_err().to_string();
+    assert!(msg.contains("too many cdroms"), "got: {msg}");
+}
+
+#[test]
+fn provision_script_file_replaces_inline_script() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("setup.sh"), "echo from file\n").unwrap();
+    let config_path = dir.path().join("rum.toml");
+    std::fs::write(
+        &config_path,
+        r#"
+[image]
+base = "ubuntu.img"
+
+[resources]
+cpus = 1
+memory_mb = 512
+
+[provision.system]
+script_file = "setup.sh"
+"#,
+    )
+    .unwrap();
+
+    let system = super::load_config(&config_path).unwrap();
+    assert_eq!(
+        system.system_script().unwrap().as_deref(),
+        Some("echo from file\n")
+    );
+    assert_eq!(
+        system.provision_file_contents().unwrap(),
+        ["echo from file\n"]
+    );
+
+    let mut config = valid_config();
+    config.provision.system = Some(ProvisionSystemConfig {
+        script: "echo inline".into(),
+        script_file: Some("setup.sh".into()),
+    });
+    assert!(validate_config(&config).is_err());
+}
+
 #[test]
 fn parse_config_provision_absent_is_none() {
     let toml = r#"
@@ -413,6 +603,135 @@ memory_mb = 512
     assert!(config.provision.boot.is_none());
 }
 
+#[test]
+fn provision_packages_must_be_package_names() {
+    let mut config = valid_config();
+    config.provision.packages = vec!["git".into(), "g++".into(), "python3.12".into()];
+    validate_config(&config).unwrap();
+
+    for bad in ["", "--force-yes", "git; rm -rf /"] {
+        config.provision.packages = vec![bad.into()];
+        assert!(
+            validate_config(&config).is_err(),
+            "{bad:?} should be rejected"
+        );
+    }
+}
+
+#[test]
+fn parse_config_with_provision_steps() {
+    let toml = r#"
+[image]
+base = "ubuntu.img"
+
+[resources]
+cpus = 1
+memory_mb = 512
+
+[[provision.step]]
+name = "toolchain"
+script = "curl -sSf https://sh.rustup.rs | sh -s -- -y"
+order = 10
+
+[[provision.step]]
+name = "services"
+file = "provision/services.sh"
+run_on = "boot"
+when = "test -d /srv"
+"#;
+    let config: Config = facet_toml::from_str(toml).unwrap();
+    validate_config(&config).unwrap();
+    let steps = &config.provision.steps;
+    assert_eq!(steps.len(), 2);
+    assert_eq!(steps[0].order, 10);
+    assert_eq!(steps[0].run_on, "system");
+    assert_eq!(steps[1].file.as_deref(), Some("provision/services.sh"));
+    assert_eq!(steps[1].order, 50);
+    assert_eq!(steps[1].when.as_deref(), Some("test -d /srv"));
+}
+
+#[test]
+fn provision_step_validation() {
+    let step = |name: &str| ProvisionStep {
+        name: name.into(),
+        script: Some("true".into()),
+        ..Default::default()
+    };
+    let mut config = valid_config();
+    config.provision.steps = vec![step("setup"), step("setup")];
+    assert!(validate_config(&config).is_err());
+
+    config.provision.steps = vec![step("system")];
+    assert!(validate_config(&config).is_err());
+
+    let mut both = step("setup");
+    both.file = Some("setup.sh".into());
+    config.provision.steps = vec![both];
+    assert!(validate_config(&config).is_err());
+
+    let mut bad_run_on = step("setup");
+    bad_run_on.run_on = "shutdown".into();
+    config.provision.steps = vec![bad_run_on];
+    assert!(validate_config(&config).is_err());
+    let mut python = step("setup");
+    python.user = Some("postgres".into());
+    python.interpreter = Some("python3 -u".into());
+    config.provision.steps = vec![python.clone()];
+    validate_config(&config).unwrap();
+
+    python.when = Some("test -d /srv".into());
+    config.provision.steps = vec![python];
+    assert!(validate_config(&config).is_err());
+
+    let mut bad_user = step("setup");
+    bad_user.user = Some("root; reboot".into());
+    config.provision.steps = vec![bad_user];
+    assert!(validate_config(&config).is_err());
+}
+
+#[test]
+fn provision_step_depends_on_earlier_steps_only() {
+    let step = |name: &str, depends_on: &[&str]| ProvisionStep {
+        name: name.into(),
+        script: Some("true".into()),
+        depends_on: Some(depends_on.iter().map(|d| d.to_string()).collect()),
+        ..Default::default()
+    };
+    let mut config = valid_config();
+    config.provision.steps = vec![
+        step("db", &[]),
+        step("cache", &[]),
+        step("app", &["db", "cache"]),
+    ];
+    validate_config(&config).unwrap();
+
+    // Forward references would deadlock the scheduler.
+    config.provision.steps = vec![step("app", &["db"]), step("db", &[])];
+    assert!(validate_config(&config).is_err());
+
+    config.provision.steps = vec![step("app", &["missing"])];
+    assert!(validate_config(&config).is_err());
+
+    let mut boot = step("boot-app", &["db"]);
+    boot.run_on = "boot".into();
+    config.provision.steps = vec![step("db", &[]), boot];
+    assert!(validate_config(&config).is_err());
+}
+
+#[test]
+fn nixos_detected_from_image_name() {
+    let mut system = test_system_config();
+    system.config.image.base = "https://example.com/nixos-24.05-x86_64.qcow2".into();
+    assert!(system.is_nixos());
+
+    // Only the file name counts, not directories along the way.
+    system.config.image.base = "/srv/nixos/ubuntu-24.04.img".into();
+    assert!(!system.is_nixos());
+
+    system.config.provision.nixos = Some(true);
+    assert!(system.is_nixos());
+}
+
 #[test]
 fn mount_target_exact_overlap_rejected() {
     let mut config = valid_config();
@@ -421,7 +740,7 @@ fn mount_target_exact_overlap_rejected() {
         target: "/mnt/data".into(),
         ..Default::default()
     }];
-    config.drives.insert("d".into(), DriveConfig { size: "10G".into() });
+    config.drives.insert("d".into(), drive("10G"));
     config.fs.insert(
         "ext4".into(),
         vec![FsEntryConfig {
@@ -445,7 +764,7 @@ fn mount_target_prefix_overlap_rejected() {
         target: "/mnt/data".into(),
         ..Default::default()
     }];
-    config.drives.insert("d".into(), DriveConfig { size: "10G".into() });
+    config.drives.insert("d".into(), drive("10G"));
     config.fs.insert(
         "ext4".into(),
         vec![FsEntryConfig {
@@ -470,7 +789,7 @@ fn mount_target_no_false_prefix_overlap() {
         target: "/mnt/data".into(),
         ..Default::default()
     }];
-    config.drives.insert("d".into(), DriveConfig { size: "10G".into() });
+    config.drives.insert("d".into(), drive("10G"));
     config.fs.insert(
         "ext4".into(),
         vec![FsEntryConfig {
@@ -490,7 +809,7 @@ fn mount_target_non_overlapping_passes() {
         target: "/mnt/shared".into(),
         ..Default::default()
     }];
-    config.drives.insert("d".into(), DriveConfig { size: "10G".into() });
+    config.drives.insert("d".into(), drive("10G"));
     config.fs.insert(
         "ext4".into(),
         vec![FsEntryConfig {
@@ -502,13 +821,144 @@ fn mount_target_non_overlapping_passes() {
     validate_config(&config).unwrap();
 }
 
+#[test]
+fn mount_driver_must_be_known() {
+    let mut config = valid_config();
+    config.mounts = vec![MountConfig {
+        source: "/tmp".into(),
+        target: "/mnt/shared".into(),
+        driver: Some("9p".into()),
+        ..Default::default()
+    }];
+    validate_config(&config).unwrap();
+
+    config.mounts[0].driver = Some("nfs".into());
+    let msg = validate_config(&config).unwrap_err().to_string();
+    assert!(msg.contains("virtiofs, 9p or sshfs"), "got: {msg}");
+}
+
+#[test]
+fn extra_users_are_validated() {
+    let mut config = valid_config();
+    config.users = vec![UserConfig {
+        name: "alice".into(),
+        uid: Some(1500),
+        shell: Some("/usr/bin/zsh".into()),
+        ..Default::default()
+    }];
+    validate_config(&config).unwrap();
+
+    config.users[0].name = "rum".into();
+    let msg = validate_config(&config).unwrap_err().to_string();
+    assert!(msg.contains("duplicate user 'rum'"), "got: {msg}");
+
+    config.users[0].name = "Alice".into();
+    assert!(validate_config(&config).is_err());
+
+    config.users[0].name = "alice".into();
+    config.users[0].shell = Some("zsh".into());
+    let msg = validate_config(&config).unwrap_err().to_string();
+    assert!(msg.contains("absolute path"), "got: {msg}");
+
+    config.users[0].shell = None;
+    config.users[0].password = Some("secret".into());
+    config.users[0].hashed_passwd = Some("$6$salt$hash".into());
+    let msg = validate_config(&config).unwrap_err().to_string();
+    assert!(
+        msg.contains("only one of password or hashed_passwd"),
+        "got: {msg}"
+    );
+}
+
+#[test]
+fn guest_timezone_must_be_tz_name() {
+    let toml = r#"
+[image]
+base = "ubuntu.qcow2"
+
+[resources]
+cpus = 1
+memory_mb = 512
+
+[guest]
+timezone = "America/New_York"
+ntp_servers = ["time.example.com"]
+"#;
+    let mut config: Config = facet_toml::from_str(toml).unwrap();
+    validate_config(&config).unwrap();
+    assert_eq!(config.guest.ntp_servers, ["time.example.com"]);
+
+    config.guest.timezone = Some("../etc/passwd".into());
+    let msg = validate_config(&config).unwrap_err().to_string();
+    assert!(msg.contains("tz database name"), "got: {msg}");
+}
+
+#[test]
+fn sync_mode_rejects_driver() {
+    let mut config = valid_config();
+    config.mounts = vec![MountConfig {
+        source: "/tmp".into(),
+        target: "/mnt/shared".into(),
+        mode: Some("sync".into()),
+        ..Default::default()
+    }];
+    validate_config(&config).unwrap();
+
+    config.mounts[0].driver = Some("9p".into());
+    let msg = validate_config(&config).unwrap_err().to_string();
+    assert!(msg.contains("does not use a driver"), "got: {msg}");
+
+    config.mounts[0].mode = Some("copy".into());
+    let msg = validate_config(&config).unwrap_err().to_string();
+    assert!(msg.contains("mode must be mount or sync"), "got: {msg}");
+}
+
+#[test]
+fn mount_fs_watch_options_default_and_override() {
+    let toml = r#"
+[image]
+base = "ubuntu.qcow2"
+
+[resources]
+cpus = 1
+memory_mb = 512
+
+[[mounts]]
+source = "."
+target = "/mnt/project"
+
+[[mounts]]
+source = "."
+target = "/mnt/web"
+mode = "sync"
+inotify_ignore = ["dist/", "*.log"]
+inotify_gitignore = true
+inotify_debounce_ms = 0
+"#;
+    let mut config: Config = facet_toml::from_str(toml).unwrap();
+    validate_config(&config).unwrap();
+
+    let defaults = config.mounts[0].fs_watch_options();
+    assert_eq!(defaults.ignore, DEFAULT_INOTIFY_IGNORE);
+    assert_eq!(defaults.debounce_ms, DEFAULT_INOTIFY_DEBOUNCE_MS);
+    assert!(!defaults.gitignore);
+
+    let custom = config.mounts[1].fs_watch_options();
+    assert_eq!(custom.ignore, ["dist/", "*.log"]);
+    assert_eq!(custom.debounce_ms, 0);
+    assert!(custom.gitignore);
+
+    // Shared mounts have no watcher the options could apply to.
+    config.mounts[1].mode = None;
+    let msg = validate_config(&config).unwrap_err().to_string();
+    assert!(msg.contains("only apply to mode = \"sync\""), "got: {msg}");
+}
+
 #[test]
 fn drive_count_exceeding_24_rejected() {
     let mut config = valid_config();
     for i in 0..25 {
-        config
-            .drives
-            .insert(format!("d{i}"), DriveConfig { size: "1G".into() });
+        config.drives.insert(format!("d{i}"), drive("1G"));
     }
     let err = validate_config(&config).unwrap_err();
     let msg = err.to_string();
@@ -519,9 +969,7 @@ fn drive_count_exceeding_24_rejected() {
 #[test]
 fn invalid_drive_size_format_rejected() {
     let mut config = valid_config();
-    config
-        .drives
-        .insert("bad".into(), DriveConfig { size: "20X".into() });
+    config.drives.insert("bad".into(), drive("20X"));
     assert!(validate_config(&config).is_err());
 }
 
@@ -578,59 +1026,993 @@ bind = "0.0.0.0"
 }
 
 #[test]
-fn port_forward_zero_host_rejected() {
-    let mut config = valid_config();
-    config.ports = vec![PortForward {
-        host: 0,
-        guest: 80,
-        ..Default::default()
-    }];
-    assert!(validate_config(&config).is_err());
-}
-
-#[test]
-fn port_forward_zero_guest_rejected() {
-    let mut config = valid_config();
-    config.ports = vec![PortForward {
-        host: 8080,
-        guest: 0,
-        ..Default::default()
-    }];
-    assert!(validate_config(&config).is_err());
-}
-
-#[test]
-fn port_forward_duplicate_host_rejected() {
+fn ipv6_port_binds_and_interface_address() {
     let mut config = valid_config();
     config.ports = vec![
         PortForward {
             host: 8080,
             guest: 80,
-            ..Default::default()
+            bind: "::1".into(),
         },
         PortForward {
-            host: 8080,
+            host: 8443,
             guest: 443,
-            ..Default::default()
+            bind: "::".into(),
         },
     ];
-    assert!(validate_config(&config).is_err());
+    config.network.interfaces = vec![InterfaceConfig {
+        network: "lab".into(),
+        ip: "fd00:1:2:3::10".into(),
+        ..Default::default()
+    }];
+    validate_config(&config).unwrap();
+    assert_eq!(
+        config.ports[0].socket_addr().unwrap().to_string(),
+        "[::1]:8080"
+    );
+    let iface = &config.network.interfaces[0];
+    assert!(iface.ipv6_enabled());
+    assert_eq!(iface.ipv4_hint(), "");
+
+    let mut local = config.clone();
+    local.ports[0].bind = "localhost".into();
+    validate_config(&local).unwrap();
+    assert_eq!(
+        local.ports[0].socket_addr().unwrap().to_string(),
+        "127.0.0.1:8080"
+    );
+    let mut bad = config.clone();
+    bad.ports[0].bind = "example.com".into();
+    assert!(validate_config(&bad).is_err(), "bind must be an IP");
+    let mut bad = config;
+    bad.network.interfaces[0].ip = "fd00::zz".into();
+    assert!(validate_config(&bad).is_err(), "ip must parse");
 }
 
 #[test]
-fn port_forward_same_host_different_bind_ok() {
+fn static_interface_addressing_is_validated() {
     let mut config = valid_config();
-    config.ports = vec![
-        PortForward {
-            host: 8080,
-            guest: 80,
-            bind: "127.0.0.1".into(),
-        },
-        PortForward {
-            host: 8080,
-            guest: 443,
-            bind: "0.0.0.0".into(),
-        },
-    ];
+    config.network.nameservers = vec!["1.1.1.1".into()];
+    config.network.interfaces = vec![InterfaceConfig {
+        network: "lab".into(),
+        addresses: vec!["192.168.50.10/24".into(), "fd00::10/64".into()],
+        gateway: "192.168.50.1".into(),
+        nameservers: vec!["192.168.50.1".into()],
+        mtu: Some(9000),
+        ..Default::default()
+    }];
     validate_config(&config).unwrap();
+
+    let mut bad = config.clone();
+    bad.network.interfaces[0].addresses = vec!["192.168.50.10".into()];
+    let msg = validate_config(&bad).unwrap_err().to_string();
+    assert!(msg.contains("CIDR"), "got: {msg}");
+
+    let mut bad = config.clone();
+    bad.network.interfaces[0].ip = "192.168.50.20".into();
+    assert!(
+        validate_config(&bad).is_err(),
+        "ip and addresses are exclusive"
+    );
+
+    let mut bad = config.clone();
+    bad.network.nameservers = vec!["dns.example.com".into()];
+    assert!(validate_config(&bad).is_err(), "nameservers must be IPs");
+
+    let mut bad = config;
+    bad.network.interfaces[0].mtu = Some(10);
+    assert!(validate_config(&bad).is_err(), "mtu must be in range");
+}
+
+#[test]
+fn docker_forwards_the_engine_socket_and_validates() {
+    let mut sc = test_system_config();
+    assert!(sc.docker_forward().is_none());
+    assert!(sc.docker_host().is_none());
+
+    sc.config.docker.enable = true;
+    let forward = sc.docker_forward().unwrap();
+    assert_eq!(forward.guest, DOCKER_SOCKET);
+    assert_eq!(
+        forward.host,
+        crate::paths::docker_socket_path(&sc.id, sc.name.as_deref())
+    );
+    assert_eq!(
+        sc.docker_host(),
+        Some(format!("unix://{}", forward.host.display()))
+    );
+    validate_config(&sc.config).unwrap();
+
+    sc.config.docker.engine = "podman".into();
+    assert_eq!(sc.docker_forward().unwrap().guest, PODMAN_SOCKET);
+    validate_config(&sc.config).unwrap();
+
+    let mut bad = sc.config.clone();
+    bad.docker.engine = "containerd".into();
+    assert!(validate_config(&bad).is_err(), "unknown engine");
+}
+
+#[test]
+fn tests_parse_with_defaults_and_validate() {
+    let toml = r#"
+[image]
+base = "ubuntu.img"
+
+[resources]
+cpus = 1
+memory_mb = 512
+
+[[tests]]
+name = "nginx"
+command = "curl -s localhost"
+stdout = "^Welcome"
+
+[[tests]]
+name = "no-root-login"
+command = "grep -q '^PermitRootLogin no' /etc/ssh/sshd_config"
+exit_code = 0
+timeout_s = 10
+"#;
+    let config: Config = facet_toml::from_str(toml).unwrap();
+    assert_eq!(config.tests.len(), 2);
+    assert_eq!(config.tests[0].exit_code, 0);
+    assert_eq!(config.tests[0].timeout_s, 300);
+    assert_eq!(config.tests[0].stdout.as_deref(), Some("^Welcome"));
+    assert_eq!(config.tests[1].timeout_s, 10);
+    validate_config(&config).unwrap();
+
+    let mut bad = config.clone();
+    bad.tests[1].name = "nginx".into();
+    assert!(validate_config(&bad).is_err(), "duplicate name");
+
+    let mut bad = config.clone();
+    bad.tests[0].stdout = Some("(unclosed".into());
+    assert!(validate_config(&bad).is_err(), "invalid regex");
+
+    let mut bad = config;
+    bad.tests[0].timeout_s = 0;
+    assert!(validate_config(&bad).is_err(), "zero timeout");
+}
+
+#[test]
+fn ci_timeouts_bound_indefinite_waits() {
+    let timeouts = TimeoutsConfig {
+        image_download_s: 7200,
+        boot_s: 0,
+        shutdown_s: 5,
+        ..TimeoutsConfig::default()
+    };
+    let ci = timeouts.for_ci();
+    assert_eq!(ci.image_download_s, CI_IMAGE_DOWNLOAD_S);
+    assert_eq!(ci.boot_s, CI_BOOT_S);
+    assert_eq!(ci.agent_connect_s, CI_AGENT_CONNECT_S);
+    assert_eq!(ci.cloud_init_s, CI_CLOUD_INIT_S);
+    assert_eq!(ci.script_s, CI_SCRIPT_S, "indefinite scripts are bounded");
+    assert_eq!(ci.shutdown_s, 5, "shorter timeouts are kept");
+    assert_eq!(ci.heartbeat_s, CI_HEARTBEAT_S);
+    assert_eq!(ci.heartbeat_interval_s, timeouts.heartbeat_interval_s);
+    assert_eq!(ci.retries, timeouts.retries);
+}
+
+#[test]
+fn heartbeats_need_an_interval_and_a_miss_limit() {
+    let mut config = valid_config();
+    config.timeouts.heartbeat_s = 0;
+    assert!(
+        validate_config(&config).is_ok(),
+        "heartbeats may wait indefinitely"
+    );
+
+    let mut bad = config.clone();
+    bad.timeouts.heartbeat_interval_s = 0;
+    assert!(validate_config(&bad).is_err(), "zero interval");
+
+    let mut bad = config;
+    bad.timeouts.missed_heartbeats = 0;
+    assert!(validate_config(&bad).is_err(), "zero missed heartbeats");
+}
+
+#[test]
+fn peer_hosts_default_on() {
+    assert!(valid_config().network.peer_hosts);
+
+    let toml = r#"
+[image]
+base = "ubuntu.img"
+
+[resources]
+cpus = 1
+memory_mb = 512
+
+[network]
+peer_hosts = false
+"#;
+    let config: Config = facet_toml::from_str(toml).unwrap();
+    assert!(!config.network.peer_hosts);
+}
+
+#[test]
+fn port_forward_zero_host_rejected() {
+    let mut config = valid_config();
+    config.ports = vec![PortForward {
+        host: 0,
+        guest: 80,
+        ..Default::default()
+    }];
+    assert!(validate_config(&config).is_err());
+}
+
+#[test]
+fn port_forward_zero_guest_rejected() {
+    let mut config = valid_config();
+    config.ports = vec![PortForward {
+        host: 8080,
+        guest: 0,
+        ..Default::default()
+    }];
+    assert!(validate_config(&config).is_err());
+}
+
+#[test]
+fn port_forward_duplicate_host_rejected() {
+    let mut config = valid_config();
+    config.ports = vec![
+        PortForward {
+            host: 8080,
+            guest: 80,
+            ..Default::default()
+        },
+        PortForward {
+            host: 8080,
+            guest: 443,
+            ..Default::default()
+        },
+    ];
+    assert!(validate_config(&config).is_err());
+}
+
+#[test]
+fn port_forward_same_host_different_bind_ok() {
+    let mut config = valid_config();
+    config.ports = vec![
+        PortForward {
+            host: 8080,
+            guest: 80,
+            bind: "127.0.0.1".into(),
+        },
+        PortForward {
+            host: 8080,
+            guest: 443,
+            bind: "0.0.0.0".into(),
+        },
+    ];
+    validate_config(&config).unwrap();
+}
+
+#[test]
+fn parse_config_with_metadata_labels() {
+    let toml = r#"
+[image]
+base = "ubuntu.img"
+
+[resources]
+cpus = 1
+memory_mb = 512
+
+[metadata]
+labels = { team = "platform", env = "dev" }
+"#;
+    let config: Config = facet_toml::from_str(toml).unwrap();
+    validate_config(&config).unwrap();
+    assert_eq!(config.metadata.labels.len(), 2);
+    assert_eq!(config.metadata.labels["team"], "platform");
+    assert_eq!(config.metadata.labels["env"], "dev");
+}
+
+#[test]
+fn invalid_metadata_label_key_rejected() {
+    let mut config = valid_config();
+    config
+        .metadata
+        .labels
+        .insert("bad key".into(), "value".into());
+    assert!(validate_config(&config).is_err());
+}
+
+#[test]
+fn image_checksum_must_be_hex_of_digest_length() {
+    let mut config = valid_config();
+    config.image.sha256 = Some("a".repeat(64));
+    validate_config(&config).unwrap();
+
+    config.image.sha256 = Some("abc".into());
+    assert!(validate_config(&config).is_err());
+
+    config.image.sha256 = None;
+    config.image.sha512 = Some("z".repeat(128));
+    assert!(validate_config(&config).is_err());
+}
+
+#[test]
+fn image_base_accepts_local_paths_and_file_urls() {
+    let mut config = valid_config();
+    for base in [
+        "ubuntu.img",
+        "./images/ubuntu.img",
+        "/srv/ubuntu.img",
+        "file:///srv/ubuntu.img",
+    ] {
+        config.image.base = base.into();
+        validate_config(&config).unwrap();
+    }
+
+    config.image.base = "ftp://example.com/ubuntu.img".into();
+    assert!(validate_config(&config).is_err());
+}
+
+#[test]
+fn image_arch_must_be_supported() {
+    let mut config = valid_config();
+    for arch in ["x86_64", "aarch64"] {
+        config.image.arch = Some(arch.into());
+        validate_config(&config).unwrap();
+    }
+
+    config.image.arch = Some("arm64".into());
+    assert!(validate_config(&config).is_err());
+}
+
+#[test]
+fn display_protocol_must_be_known() {
+    let mut config = valid_config();
+    for protocol in ["none", "spice", "vnc"] {
+        config.display.protocol = protocol.into();
+        validate_config(&config).unwrap();
+    }
+
+    config.display.protocol = "rdp".into();
+    assert!(validate_config(&config).is_err());
+}
+
+#[test]
+fn cpu_topology_must_match_cpus() {
+    let mut config = valid_config();
+    config.resources.cpus = 4;
+    config.resources.sockets = Some(1);
+    config.resources.cores = Some(2);
+    config.resources.threads = Some(2);
+    validate_config(&config).unwrap();
+
+    config.resources.threads = None;
+    assert!(validate_config(&config).is_err());
+
+    config.resources.sockets = Some(u32::MAX);
+    config.resources.threads = Some(u32::MAX);
+    assert!(validate_config(&config).is_err());
+}
+
+#[test]
+fn nested_requires_host_cpu_model() {
+    let mut config = valid_config();
+    config.resources.nested = true;
+    validate_config(&config).unwrap();
+
+    config.resources.cpu_model = Some("host-model".into());
+    validate_config(&config).unwrap();
+
+    config.resources.cpu_model = Some("Skylake-Client".into());
+    assert!(validate_config(&config).is_err());
+}
+
+#[test]
+fn hotplug_ceilings_must_cover_boot_values() {
+    let mut config = valid_config();
+    config.resources.memory_max_mb = Some(1024);
+    config.resources.cpus_max = Some(4);
+    validate_config(&config).unwrap();
+
+    config.resources.sockets = Some(1);
+    config.resources.cores = Some(4);
+    validate_config(&config).unwrap();
+
+    config.resources.memory_max_mb = Some(256);
+    assert!(validate_config(&config).is_err());
+
+    config.resources.memory_max_mb = None;
+    config.resources.cpus_max = Some(0);
+    assert!(validate_config(&config).is_err());
+}
+
+#[test]
+fn disk_io_options_are_validated() {
+    let mut config = valid_config();
+    config.root_disk.cache = Some("none".into());
+    config.root_disk.io = Some("native".into());
+    validate_config(&config).unwrap();
+
+    config.root_disk.cache = Some("writeback".into());
+    assert!(
+        validate_config(&config).is_err(),
+        "io=native needs an O_DIRECT cache mode"
+    );
+
+    config.root_disk = DiskIoConfig::default();
+    config.drives.insert(
+        "data".into(),
+        DriveConfig {
+            size: "1G".into(),
+            io: DiskIoConfig {
+                io: Some("aio".into()),
+                ..Default::default()
+            },
+            ..Default::default()
+        },
+    );
+    assert!(validate_config(&config).is_err());
+}
+
+#[test]
+fn drive_format_and_preallocation_are_validated() {
+    let mut config = valid_config();
+    let mut drive = DriveConfig {
+        size: "1G".into(),
+        format: "raw".into(),
+        preallocation: "full".into(),
+        ..Default::default()
+    };
+    config.drives.insert("db".into(), drive.clone());
+    validate_config(&config).unwrap();
+
+    drive.format = "vmdk".into();
+    config.drives.insert("db".into(), drive.clone());
+    assert!(validate_config(&config).is_err());
+
+    drive.format = "qcow2".into();
+    drive.preallocation = "falloc".into();
+    config.drives.insert("db".into(), drive);
+    assert!(validate_config(&config).is_err());
+}
+
+#[test]
+fn drive_path_replaces_size() {
+    let mut config = valid_config();
+    let mut drive = DriveConfig {
+        path: Some("/dev/disk/by-id/nvme-ssd-part1".into()),
+        readonly: true,
+        ..Default::default()
+    };
+    config.drives.insert("ssd".into(), drive.clone());
+    validate_config(&config).unwrap();
+
+    drive.size = "10G".into();
+    config.drives.insert("ssd".into(), drive);
+    assert!(validate_config(&config).is_err());
+}
+
+#[test]
+fn encrypted_drive_needs_one_key_source() {
+    let mut config = valid_config();
+    let mut drive = DriveConfig {
+        size: "1G".into(),
+        encrypted: true,
+        key_env: Some("RUM_DATA_KEY".into()),
+        ..Default::default()
+    };
+    config.drives.insert("data".into(), drive.clone());
+    validate_config(&config).unwrap();
+
+    drive.key_file = Some("/etc/rum/data.key".into());
+    config.drives.insert("data".into(), drive.clone());
+    assert!(validate_config(&config).is_err());
+
+    drive.key_env = None;
+    drive.key_file = None;
+    config.drives.insert("data".into(), drive);
+    assert!(validate_config(&config).is_err());
+}
+
+#[test]
+fn encrypted_drive_resolves_to_mapper_device() {
+    let mut sc = test_system_config();
+    let dir = tempfile::tempdir().unwrap();
+    let key_path = dir.path().join("data.key");
+    std::fs::write(&key_path, "s3cret\n").unwrap();
+    sc.config.drives.insert(
+        "data".into(),
+        DriveConfig {
+            size: "1G".into(),
+            encrypted: true,
+            key_file: Some(key_path.display().to_string()),
+            ..Default::default()
+        },
+    );
+    sc.config.fs.insert(
+        "ext4".into(),
+        vec![FsEntryConfig {
+            drive: "data".into(),
+            target: "/mnt/data".into(),
+            ..Default::default()
+        }],
+    );
+
+    let drives = sc.resolve_drives().unwrap();
+    let luks = sc.resolve_luks(&drives).unwrap();
+    assert_eq!(luks.len(), 1);
+    assert_eq!(luks[0].dev, "/dev/vdb");
+    assert_eq!(luks[0].key, "s3cret");
+    assert!(!format!("{:?}", luks[0]).contains("s3cret"));
+
+    match &sc.resolve_fs(&drives).unwrap()[0] {
+        ResolvedFs::Simple(s) => assert_eq!(s.dev, "/dev/mapper/rum-data"),
+        _ => panic!("expected Simple"),
+    }
+}
+
+#[test]
+fn ready_http_probe_targets_guest_loopback() {
+    let toml = r#"
+[image]
+base = "ubuntu.img"
+
+[resources]
+cpus = 1
+memory_mb = 512
+
+[ready]
+tcp = 5432
+http = "http://localhost:8080/health"
+command = "systemctl is-active postgres"
+"#;
+    let config: Config = facet_toml::from_str(toml).unwrap();
+    validate_config(&config).unwrap();
+    assert_eq!(config.ready.tcp, Some(5432));
+    assert_eq!(config.ready.timeout_s, 120);
+    assert_eq!(config.ready.http_target(), Some((8080, "/health".into())));
+
+    let mut config = valid_config();
+    assert!(config.ready.is_empty());
+    config.ready.http = Some("http://127.0.0.1".into());
+    assert_eq!(config.ready.http_target(), Some((80, "/".into())));
+    for url in [
+        "https://localhost/",
+        "http://example.com/",
+        "http://localhost:0/",
+    ] {
+        config.ready.http = Some(url.into());
+        assert!(
+            validate_config(&config).is_err(),
+            "{url} should be rejected"
+        );
+    }
+}
+
+#[test]
+fn timeouts_fill_unset_fields_with_defaults() {
+    let toml = r#"
+[image]
+base = "ubuntu.img"
+
+[resources]
+cpus = 1
+memory_mb = 512
+
+[timeouts]
+agent_connect_s = 60
+retries = 0
+"#;
+    let config: Config = facet_toml::from_str(toml).unwrap();
+    assert_eq!(config.timeouts.agent_connect_s, 60);
+    assert_eq!(config.timeouts.retries, 0);
+    assert_eq!(config.timeouts.image_download_s, 1800);
+    assert_eq!(config.timeouts.shutdown_s, 10);
+    assert_eq!(config.timeouts.cloud_init_s, 600);
+    assert_eq!(config.timeouts.boot_s, 120);
+    assert_eq!(config.timeouts.heartbeat_interval_s, 2);
+    assert_eq!(config.timeouts.heartbeat_s, 5);
+    assert_eq!(config.timeouts.missed_heartbeats, 3);
+    assert_eq!(valid_config().timeouts.script_s, 0);
+}
+
+#[test]
+fn serve_http_must_be_a_socket_address() {
+    let mut config = valid_config();
+    assert!(config.serve.http.is_none());
+    config.serve.http = Some("127.0.0.1:7878".into());
+    validate_config(&config).unwrap();
+
+    config.serve.http = Some("localhost".into());
+    assert!(validate_config(&config).is_err());
+}
+
+#[test]
+fn serve_http_stays_on_loopback_unless_allowed() {
+    let mut config = valid_config();
+    config.serve.http = Some("[::1]:7878".into());
+    validate_config(&config).unwrap();
+
+    config.serve.http = Some("0.0.0.0:7878".into());
+    let err = validate_config(&config).unwrap_err().to_string();
+    assert!(err.contains("serve.allow_remote"), "{err}");
+
+    config.serve.allow_remote = true;
+    validate_config(&config).unwrap();
+}
+
+#[test]
+fn logging_defaults_and_validation() {
+    let toml = r#"
+[image]
+base = "ubuntu.img"
+
+[resources]
+cpus = 1
+memory_mb = 512
+
+[logging]
+format = "json"
+"#;
+    let mut config: Config = facet_toml::from_str(toml).unwrap();
+    validate_config(&config).unwrap();
+    assert_eq!(config.logging.format, "json");
+    assert_eq!(config.logging.max_size_mb, 10);
+    assert_eq!(config.logging.max_files, 10);
+
+    config.logging.format = "xml".into();
+    assert!(validate_config(&config).is_err());
+    config.logging.format = "text".into();
+    config.logging.max_files = 0;
+    assert!(validate_config(&config).is_err());
+}
+
+#[test]
+fn notify_webhooks_must_be_http_urls() {
+    let toml = r#"
+[image]
+base = "ubuntu.img"
+
+[resources]
+cpus = 1
+memory_mb = 512
+
+[notify]
+desktop = true
+webhooks = ["https://hooks.example.com/T000/B000"]
+"#;
+    let mut config: Config = facet_toml::from_str(toml).unwrap();
+    validate_config(&config).unwrap();
+    assert!(config.notify.desktop);
+    assert!(!valid_config().notify.desktop);
+
+    config.notify.webhooks = vec!["hooks.example.com".into()];
+    assert!(validate_config(&config).is_err());
+}
+
+#[test]
+fn ssh_client_options_validate_and_apply_without_recreate() {
+    let toml = r#"
+[image]
+base = "ubuntu.img"
+
+[resources]
+cpus = 1
+memory_mb = 512
+
+[ssh]
+forward_agent = true
+proxy_jump = "bastion.example.com"
+extra_options = ["ServerAliveInterval=30"]
+"#;
+    let mut config: Config = facet_toml::from_str(toml).unwrap();
+    validate_config(&config).unwrap();
+    assert!(config.ssh.forward_agent);
+    assert!(config.ssh.multiplex);
+    assert!(super::diff_configs(&valid_config(), &config).is_empty());
+    config.ssh.multiplex = false;
+    config.ssh.control_path = Some("/tmp/rum-%C".into());
+    assert!(super::diff_configs(&valid_config(), &config).is_empty());
+
+    for option in [
+        "ServerAliveInterval 30",
+        "ServerAliveInterval=",
+        "ServerAliveInterval=  ",
+        "ServerAliveInterval=30\nProxyCommand sh -c x",
+        "Proxy\nCommand=sh",
+    ] {
+        config.ssh.extra_options = vec![option.into()];
+        assert!(validate_config(&config).is_err(), "{option:?}");
+    }
+    config.ssh.extra_options = vec!["RemoteCommand=tmux attach".into()];
+    validate_config(&config).unwrap();
+    config.ssh.extra_options.clear();
+    for jump in ["", " ", "bastion\nMatch all", "bastion\tother"] {
+        config.ssh.proxy_jump = Some(jump.into());
+        assert!(validate_config(&config).is_err(), "{jump:?}");
+    }
+    config.ssh.proxy_jump = Some("user@bastion:2222,inner".into());
+    validate_config(&config).unwrap();
+    config.ssh.proxy_jump = None;
+    config.ssh.control_path = Some(String::new());
+    assert!(validate_config(&config).is_err());
+}
+
+#[test]
+fn check_reports_every_invalid_section() {
+    let dir = tempfile::tempdir().unwrap();
+    let config_path = dir.path().join("rum.toml");
+    std::fs::write(
+        &config_path,
+        r#"
+[image]
+base = "ubuntu.img"
+
+[resources]
+cpus = 0
+memory_mb = 512
+
+[[ports]]
+host = 0
+guest = 80
+"#,
+    )
+    .unwrap();
+
+    let report = super::check_config(&config_path, None).unwrap();
+    let keys: Vec<_> = report.problems.iter().map(|p| p.key).collect();
+    assert_eq!(keys, [Some("resources"), Some("ports")]);
+    assert_eq!(report.problems[0].message, "cpus must be at least 1");
+    assert!(!report.is_valid());
+    let rendered = report.render();
+    assert!(
+        rendered.contains("ports[0]: host port must be > 0"),
+        "got:\n{rendered}"
+    );
+}
+
+#[test]
+fn extends_merges_tables_and_appends_arrays() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(
+        dir.path().join("base.rum.toml"),
+        r#"
+[image]
+base = "https://example.com/image.qcow2"
+
+[resources]
+cpus = 2
+memory_mb = 2048
+
+[provision]
+packages = ["git"]
+
+[[ports]]
+host = 8080
+guest = 80
+"#,
+    )
+    .unwrap();
+    let config_path = dir.path().join("rum.toml");
+    std::fs::write(
+        &config_path,
+        r#"
+extends = "base.rum.toml"
+
+[resources]
+cpus = 4
+
+[provision]
+packages = ["htop"]
+
+[[ports]]
+host = 5432
+guest = 5432
+"#,
+    )
+    .unwrap();
+
+    let config = super::load_config(&config_path).unwrap().config;
+    assert_eq!(config.image.base, "https://example.com/image.qcow2");
+    assert_eq!(config.resources.cpus, 4);
+    assert_eq!(config.resources.memory_mb, 2048);
+    assert_eq!(config.provision.packages, ["git", "htop"]);
+    let hosts: Vec<u16> = config.ports.iter().map(|p| p.host).collect();
+    assert_eq!(hosts, [8080, 5432]);
+}
+
+#[test]
+fn extends_cycles_are_rejected() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(
+        dir.path().join("a.rum.toml"),
+        "extends = [\"b.rum.toml\"]\n",
+    )
+    .unwrap();
+    std::fs::write(dir.path().join("b.rum.toml"), "extends = \"a.rum.toml\"\n").unwrap();
+
+    let err = super::load_config(&dir.path().join("a.rum.toml")).unwrap_err();
+    assert!(err.to_string().contains("extends itself"), "got: {err}");
+}
+
+#[test]
+fn profile_replaces_arrays_and_merges_tables() {
+    let dir = tempfile::tempdir().unwrap();
+    let config_path = dir.path().join("rum.toml");
+    std::fs::write(
+        &config_path,
+        r#"
+[image]
+base = "https://example.com/image.qcow2"
+
+[resources]
+cpus = 2
+memory_mb = 2048
+
+[[ports]]
+host = 8080
+guest = 80
+
+[profile.ci.resources]
+cpus = 8
+
+[[profile.ci.ports]]
+host = 9090
+guest = 90
+"#,
+    )
+    .unwrap();
+
+    let plain = super::load_config_with_profile(&config_path, None).unwrap();
+    assert_eq!(plain.config.resources.cpus, 2);
+    assert_eq!(plain.profile, None);
+
+    let ci = super::load_config_with_profile(&config_path, Some("ci")).unwrap();
+    assert_eq!(ci.config.resources.cpus, 8);
+    assert_eq!(ci.config.resources.memory_mb, 2048);
+    let hosts: Vec<u16> = ci.config.ports.iter().map(|p| p.host).collect();
+    assert_eq!(hosts, [9090]);
+    assert_eq!(ci.profile.as_deref(), Some("ci"));
+    assert_eq!(ci.id, plain.id);
+
+    let err = super::load_config_with_profile(&config_path, Some("prod")).unwrap_err();
+    assert!(err.to_string().contains("available: ci"), "got: {err}");
+}
+
+#[test]
+fn find_config_searches_parent_directories() {
+    let dir = tempfile::tempdir().unwrap();
+    let nested = dir.path().join("src/deep");
+    std::fs::create_dir_all(&nested).unwrap();
+    // Stop at the temp dir so configs above it cannot leak in.
+    let find = |start: &Path| super::load::find_config_below(start, Some(dir.path()));
+    assert_eq!(find(&nested).unwrap(), None);
+
+    std::fs::write(dir.path().join("dev.rum.toml"), "").unwrap();
+    assert_eq!(
+        find(&nested).unwrap(),
+        Some(dir.path().join("dev.rum.toml"))
+    );
+
+    std::fs::write(dir.path().join("ci.rum.toml"), "").unwrap();
+    let err = find(&nested).unwrap_err();
+    assert!(
+        err.to_string().contains("ci.rum.toml, dev.rum.toml"),
+        "got: {err}"
+    );
+
+    std::fs::write(dir.path().join("rum.toml"), "").unwrap();
+    assert_eq!(
+        find(&nested).unwrap(),
+        Some(dir.path().join("rum.toml"))
+    );
+}
+
+#[test]
+fn config_diff_says_how_each_change_is_applied() {
+    let mut applied = valid_config();
+    applied.resources.memory_max_mb = Some(4096);
+    let mut current = applied.clone();
+    current.resources.memory_mb = 2048;
+    current.resources.cpus = 2;
+    current.ports.push(PortForward {
+        host: 8080,
+        guest: 80,
+        bind: String::new(),
+    });
+    current.user.name = "dev".into();
+
+    let changes: Vec<_> = super::diff_configs(&applied, &current)
+        .into_iter()
+        .map(|change| (change.key, change.detail, change.apply))
+        .collect();
+    assert_eq!(
+        changes,
+        [
+            (
+                "resources",
+                "cpus 1 -> 2".to_string(),
+                super::Apply::Restart
+            ),
+            (
+                "resources",
+                "memory 512 MiB -> 2048 MiB".to_string(),
+                super::Apply::Resize
+            ),
+            (
+                "ports",
+                "forward 127.0.0.1:8080 -> guest:80 added".to_string(),
+                super::Apply::Reload
+            ),
+            ("user", "[user] changed".to_string(), super::Apply::Recreate),
+        ]
+    );
+    assert!(super::diff_configs(&applied, &applied.clone()).is_empty());
+}
+
+#[test]
+fn daemon_only_sections_reload_without_a_restart() {
+    let applied = valid_config();
+    let mut current = applied.clone();
+    current.notify.webhooks.push("http://localhost/hook".into());
+    current.network.mdns = true;
+
+    let changes: Vec<_> = super::diff_configs(&applied, &current)
+        .into_iter()
+        .map(|change| (change.key, change.apply))
+        .collect();
+    assert_eq!(
+        changes,
+        [
+            ("network", super::Apply::Reload),
+            ("notify", super::Apply::Reload)
+        ]
+    );
+}
+
+#[test]
+fn docker_changes_apply_by_provisioning() {
+    let applied = valid_config();
+    let mut current = applied.clone();
+    current.docker.enable = true;
+
+    let changes: Vec<_> = super::diff_configs(&applied, &current)
+        .into_iter()
+        .map(|change| (change.key, change.apply))
+        .collect();
+    assert_eq!(changes, [("docker", super::Apply::Provision)]);
+}
+
+#[test]
+fn applied_config_round_trips() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("applied-config.json");
+    assert!(super::applied_config(&path).is_none());
+
+    let mut config = valid_config();
+    config.mounts.push(MountConfig {
+        source: ".".into(),
+        target: "/work".into(),
+        ..Default::default()
+    });
+    super::record_applied(&path, &config).unwrap();
+    let recorded = super::applied_config(&path).unwrap();
+    assert!(super::diff_configs(&config, &recorded).is_empty());
+}
+
+#[test]
+fn vsock_only_features_are_rejected_without_vsock() {
+    let mut system = test_system_config();
+    assert!(system.check_agent_transport(false).is_ok());
+
+    system.config.ports.push(PortForward {
+        host: 8080,
+        guest: 80,
+        bind: "127.0.0.1".into(),
+    });
+    system.config.docker.enable = true;
+    system.config.ready.tcp = Some(5432);
+    assert!(system.check_agent_transport(true).is_ok());
+    let error = system.check_agent_transport(false).unwrap_err().to_string();
+    assert!(
+        error.contains("[[ports]], [docker], ready.tcp need vsock"),
+        "got: {error}"
+    );
 }
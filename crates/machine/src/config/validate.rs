@@ -2,7 +2,57 @@ use crate::error::Error;
 
 use super::schema::*;
 
+/// Validation for one top-level section of the config.
+type SectionValidator = fn(&Config) -> Result<(), Error>;
+
+/// Section validators in the order they run, keyed by the TOML key of the
+/// section they check.
+const SECTIONS: &[(&str, SectionValidator)] = &[
+    ("resources", validate_resources),
+    ("mounts", validate_mounts),
+    ("user", validate_users),
+    ("ssh", validate_ssh),
+    ("guest", validate_guest),
+    ("cdroms", validate_cdroms),
+    ("drives", validate_drives),
+    ("root_disk", |config| {
+        validate_disk_io("root_disk", &config.root_disk)
+    }),
+    ("fs", validate_filesystems),
+    ("mounts", validate_mount_targets),
+    ("network", validate_network),
+    ("ports", validate_ports),
+    ("image", validate_image),
+    ("advanced", validate_firmware),
+    ("display", validate_display),
+    ("ready", validate_ready),
+    ("provision", validate_provision),
+    ("metadata", validate_metadata),
+    ("serve", validate_serve),
+    ("logging", validate_logging),
+    ("timeouts", validate_timeouts),
+    ("notify", validate_notify),
+    ("docker", validate_docker),
+    ("tests", validate_tests),
+];
+
 pub(super) fn validate_config(config: &Config) -> Result<(), Error> {
+    for (_, validate) in SECTIONS {
+        validate(config)?;
+    }
+    Ok(())
+}
+
+/// The first problem in every section, with the key of the section it was
+/// found in.
+pub(super) fn config_problems(config: &Config) -> Vec<(&'static str, Error)> {
+    SECTIONS
+        .iter()
+        .filter_map(|(key, validate)| validate(config).err().map(|error| (*key, error)))
+        .collect()
+}
+
+fn validate_resources(config: &Config) -> Result<(), Error> {
     if config.resources.cpus < 1 {
         return Err(Error::Validation {
             message: "cpus must be at least 1".into(),
@@ -16,14 +66,94 @@ pub(super) fn validate_config(config: &Config) -> Result<(), Error> {
     if !config.resources.disk.is_empty() {
         crate::util::parse_size(&config.resources.disk)?;
     }
+    if let Some(memory_max_mb) = config.resources.memory_max_mb
+        && memory_max_mb < config.resources.memory_mb
+    {
+        return Err(Error::Validation {
+            message: "memory_max_mb must be at least memory_mb".into(),
+        });
+    }
+    if let Some(cpus_max) = config.resources.cpus_max
+        && cpus_max < config.resources.cpus
+    {
+        return Err(Error::Validation {
+            message: "cpus_max must be at least cpus".into(),
+        });
+    }
+    let max_cpus = config.resources.cpus_max.unwrap_or(config.resources.cpus);
+    if let Some((sockets, cores, threads)) = config.resources.topology() {
+        let total = sockets
+            .checked_mul(cores)
+            .and_then(|n| n.checked_mul(threads));
+        if total != Some(max_cpus) {
+            let total = total.map_or_else(|| "overflows".into(), |n| n.to_string());
+            return Err(Error::Validation {
+                message: format!(
+                    "sockets * cores * threads ({total}) must equal cpus_max or cpus ({max_cpus})"
+                ),
+            });
+        }
+    }
+    if config.resources.nested
+        && let Some(model) = &config.resources.cpu_model
+        && !matches!(model.as_str(), "host-passthrough" | "host-model")
+    {
+        return Err(Error::Validation {
+            message: format!(
+                "nested requires cpu_model 'host-passthrough' or 'host-model', got '{model}'"
+            ),
+        });
+    }
+    Ok(())
+}
 
-    // Validate mounts
+fn validate_mounts(config: &Config) -> Result<(), Error> {
     for m in &config.mounts {
         if !m.target.starts_with('/') {
             return Err(Error::Validation {
                 message: format!("mount target must be absolute (got '{}')", m.target),
             });
         }
+        match m.mode.as_deref().unwrap_or("mount") {
+            "mount"
+                if m.inotify_ignore.is_some()
+                    || m.inotify_gitignore
+                    || m.inotify_debounce_ms.is_some() =>
+            {
+                return Err(Error::Validation {
+                    message: format!(
+                        "mount '{}': inotify_ignore, inotify_gitignore and inotify_debounce_ms \
+                         only apply to mode = \"sync\"",
+                        m.target
+                    ),
+                });
+            }
+            "mount" => {}
+            "sync" if m.driver.is_none() => {}
+            "sync" => {
+                return Err(Error::Validation {
+                    message: format!("mount '{}': sync mode does not use a driver", m.target),
+                });
+            }
+            other => {
+                return Err(Error::Validation {
+                    message: format!(
+                        "mount '{}': mode must be mount or sync (got '{other}')",
+                        m.target
+                    ),
+                });
+            }
+        }
+        if let Some(driver) = &m.driver
+            && !matches!(driver.as_str(), "virtiofs" | "9p" | "sshfs")
+        {
+            return Err(Error::Validation {
+                message: format!(
+                    "mount '{}': driver must be virtiofs, 9p or sshfs (got '{driver}')",
+                    m.target
+                ),
+            });
+        }
     }
 
     // Check for duplicate tags
@@ -40,23 +170,194 @@ pub(super) fn validate_config(config: &Config) -> Result<(), Error> {
             });
         }
     }
+    Ok(())
+}
+
+fn validate_users(config: &Config) -> Result<(), Error> {
+    let users: Vec<&UserConfig> = std::iter::once(&config.user).chain(&config.users).collect();
+    for (i, user) in users.iter().enumerate() {
+        let name = user.name.as_str();
+        let valid_name = name.starts_with(|c: char| c.is_ascii_lowercase() || c == '_')
+            && name
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || matches!(c, '_' | '-'));
+        if !valid_name || name.len() > 32 {
+            return Err(Error::Validation {
+                message: format!(
+                    "user name '{name}' must start with a lowercase letter or '_' and use only \
+                     lowercase letters, digits, '_' and '-' (max 32 chars)"
+                ),
+            });
+        }
+        if users[..i].iter().any(|other| other.name == name) {
+            return Err(Error::Validation {
+                message: format!("duplicate user '{name}'"),
+            });
+        }
+        if user.uid == Some(0) {
+            return Err(Error::Validation {
+                message: format!("user '{name}': uid 0 is reserved for root"),
+            });
+        }
+        if let Some(shell) = &user.shell
+            && !shell.starts_with('/')
+        {
+            return Err(Error::Validation {
+                message: format!("user '{name}': shell must be an absolute path (got '{shell}')"),
+            });
+        }
+        if user.password.is_some() && user.hashed_passwd.is_some() {
+            return Err(Error::Validation {
+                message: format!("user '{name}': set only one of password or hashed_passwd"),
+            });
+        }
+        if user.dotfiles.as_deref() == Some("") {
+            return Err(Error::Validation {
+                message: format!("user '{name}': dotfiles must not be empty"),
+            });
+        }
+    }
+    Ok(())
+}
+
+fn validate_ssh(config: &Config) -> Result<(), Error> {
+    // Both end up in `~/.ssh/config` via `rum ssh-config --install`, where a
+    // newline would start a new directive outside the machine's block.
+    for option in &config.ssh.extra_options {
+        let valid = option.split_once('=').is_some_and(|(key, value)| {
+            !key.is_empty()
+                && key.chars().all(|c| c.is_ascii_alphanumeric())
+                && !value.trim().is_empty()
+                && !value.contains(char::is_control)
+        });
+        if !valid {
+            return Err(Error::Validation {
+                message: format!("ssh.extra_options entry {option:?} must look like Key=Value"),
+            });
+        }
+    }
+    if let Some(jump) = &config.ssh.proxy_jump
+        && (jump.is_empty() || jump.contains(|c: char| c.is_whitespace() || c.is_control()))
+    {
+        return Err(Error::Validation {
+            message: format!(
+                "ssh.proxy_jump must be a non-empty host list without whitespace (got {jump:?})"
+            ),
+        });
+    }
+    if config
+        .ssh
+        .control_path
+        .as_deref()
+        .is_some_and(str::is_empty)
+    {
+        return Err(Error::Validation {
+            message: "ssh.control_path must not be empty".into(),
+        });
+    }
+
+    Ok(())
+}
+
+fn validate_guest(config: &Config) -> Result<(), Error> {
+    if let Some(timezone) = &config.guest.timezone
+        && (timezone.is_empty()
+            || timezone.starts_with('/')
+            || timezone
+                .split('/')
+                .any(|part| part.is_empty() || part == "..")
+            || timezone.contains(char::is_whitespace))
+    {
+        return Err(Error::Validation {
+            message: format!("guest timezone '{timezone}' is not a tz database name"),
+        });
+    }
+    if config.guest.locale.as_deref().is_some_and(|l| l.is_empty()) {
+        return Err(Error::Validation {
+            message: "guest locale must not be empty".into(),
+        });
+    }
+    if config.guest.ntp_servers.iter().any(|s| s.trim().is_empty()) {
+        return Err(Error::Validation {
+            message: "guest ntp_servers must not contain empty entries".into(),
+        });
+    }
+    Ok(())
+}
+
+fn validate_cdroms(config: &Config) -> Result<(), Error> {
+    // The seed takes the first of the six SATA ports.
+    if config.cdroms.len() > 5 {
+        return Err(Error::Validation {
+            message: format!("too many cdroms (max 5, got {})", config.cdroms.len()),
+        });
+    }
+    if config.cdroms.iter().any(|c| c.path.trim().is_empty()) {
+        return Err(Error::Validation {
+            message: "cdrom path must not be empty".into(),
+        });
+    }
+    Ok(())
+}
 
-    // Validate drives
+fn validate_drives(config: &Config) -> Result<(), Error> {
     if config.drives.len() > 24 {
         return Err(Error::Validation {
             message: format!("too many drives (max 24, got {})", config.drives.len()),
         });
     }
     for (name, drive) in &config.drives {
-        if drive.size.is_empty() {
+        if let Some(path) = &drive.path {
+            if path.is_empty() {
+                return Err(Error::Validation {
+                    message: format!("drive '{name}': path must not be empty"),
+                });
+            }
+            if !drive.size.is_empty() || drive.preallocation != "off" {
+                return Err(Error::Validation {
+                    message: format!(
+                        "drive '{name}': size and preallocation do not apply to `path` drives"
+                    ),
+                });
+            }
+        } else if drive.size.is_empty() {
             return Err(Error::Validation {
-                message: format!("drive '{name}' must have a size"),
+                message: format!("drive '{name}' must have a size or a path"),
             });
+        } else {
+            crate::util::parse_size(&drive.size)?;
         }
-        crate::util::parse_size(&drive.size)?;
+        match (drive.encrypted, &drive.key_env, &drive.key_file) {
+            (true, Some(_), None) | (true, None, Some(_)) | (false, None, None) => {}
+            (true, _, _) => {
+                return Err(Error::Validation {
+                    message: format!(
+                        "drive '{name}': encrypted drives need exactly one of key_env or key_file"
+                    ),
+                });
+            }
+            (false, _, _) => {
+                return Err(Error::Validation {
+                    message: format!("drive '{name}': key_env/key_file require encrypted = true"),
+                });
+            }
+        }
+        if !matches!(drive.format.as_str(), "qcow2" | "raw") {
+            return Err(Error::Validation {
+                message: format!("drive '{name}': format must be qcow2 or raw"),
+            });
+        }
+        if !matches!(drive.preallocation.as_str(), "off" | "metadata" | "full") {
+            return Err(Error::Validation {
+                message: format!("drive '{name}': preallocation must be off, metadata, or full"),
+            });
+        }
+        validate_disk_io(&format!("drive '{name}'"), &drive.io)?;
     }
+    Ok(())
+}
 
-    // Validate filesystem entries
+fn validate_filesystems(config: &Config) -> Result<(), Error> {
     let mut used_drives = std::collections::HashSet::new();
     for (fs_type, entries) in &config.fs {
         for (idx, entry) in entries.iter().enumerate() {
@@ -176,59 +477,62 @@ pub(super) fn validate_config(config: &Config) -> Result<(), Error> {
             }
         }
     }
+    Ok(())
+}
 
-    // Validate mount target overlap between [[mounts]] and [[fs.*]] entries
-    {
-        // Collect all (target, source_label) pairs
-        let mut targets: Vec<(&str, String)> = Vec::new();
-        for m in &config.mounts {
-            targets.push((&m.target, "[[mounts]]".into()));
-        }
-        for (fs_type, entries) in &config.fs {
-            for entry in entries {
-                targets.push((&entry.target, format!("[[fs.{fs_type}]]")));
-            }
+/// Mount targets of `[[mounts]]` and `[[fs.*]]` entries must not collide or nest.
+fn validate_mount_targets(config: &Config) -> Result<(), Error> {
+    // Collect all (target, source_label) pairs
+    let mut targets: Vec<(&str, String)> = Vec::new();
+    for m in &config.mounts {
+        targets.push((&m.target, "[[mounts]]".into()));
+    }
+    for (fs_type, entries) in &config.fs {
+        for entry in entries {
+            targets.push((&entry.target, format!("[[fs.{fs_type}]]")));
         }
+    }
 
-        // Check for exact duplicates
-        for i in 0..targets.len() {
-            for j in (i + 1)..targets.len() {
-                if targets[i].0 == targets[j].0 {
-                    return Err(Error::Validation {
-                        message: format!(
-                            "mount target '{}' is used by both {} and {}",
-                            targets[i].0, targets[i].1, targets[j].1
-                        ),
-                    });
-                }
+    // Check for exact duplicates
+    for i in 0..targets.len() {
+        for j in (i + 1)..targets.len() {
+            if targets[i].0 == targets[j].0 {
+                return Err(Error::Validation {
+                    message: format!(
+                        "mount target '{}' is used by both {} and {}",
+                        targets[i].0, targets[i].1, targets[j].1
+                    ),
+                });
             }
         }
+    }
 
-        // Check for prefix overlap (parent/child mount points)
-        for i in 0..targets.len() {
-            for j in 0..targets.len() {
-                if i == j {
-                    continue;
-                }
-                let parent = targets[i].0;
-                let child = targets[j].0;
-                // Check if parent is a prefix of child with a '/' boundary
-                if child.len() > parent.len()
-                    && child.starts_with(parent)
-                    && child.as_bytes()[parent.len()] == b'/'
-                {
-                    return Err(Error::Validation {
-                        message: format!(
-                            "mount target '{}' overlaps with '{}' (from {})",
-                            child, parent, targets[i].1
-                        ),
-                    });
-                }
+    // Check for prefix overlap (parent/child mount points)
+    for i in 0..targets.len() {
+        for j in 0..targets.len() {
+            if i == j {
+                continue;
+            }
+            let parent = targets[i].0;
+            let child = targets[j].0;
+            // Check if parent is a prefix of child with a '/' boundary
+            if child.len() > parent.len()
+                && child.starts_with(parent)
+                && child.as_bytes()[parent.len()] == b'/'
+            {
+                return Err(Error::Validation {
+                    message: format!(
+                        "mount target '{}' overlaps with '{}' (from {})",
+                        child, parent, targets[i].1
+                    ),
+                });
             }
         }
     }
+    Ok(())
+}
 
-    // Validate hostname
+fn validate_network(config: &Config) -> Result<(), Error> {
     if !config.network.hostname.is_empty() {
         let h = &config.network.hostname;
         if h.len() > 253 {
@@ -263,9 +567,22 @@ pub(super) fn validate_config(config: &Config) -> Result<(), Error> {
                 message: "network interface must have a non-empty network name".into(),
             });
         }
+        if !iface.ip.is_empty() && iface.ip.parse::<std::net::IpAddr>().is_err() {
+            return Err(Error::Validation {
+                message: format!(
+                    "network '{}': ip '{}' is not an IPv4 or IPv6 address",
+                    iface.network, iface.ip
+                ),
+            });
+        }
+        validate_interface_network(iface)?;
+        validate_interface_addressing(iface)?;
     }
+    validate_nameservers("network", &config.network.nameservers)?;
+    Ok(())
+}
 
-    // Validate port forwards
+fn validate_ports(config: &Config) -> Result<(), Error> {
     for (i, pf) in config.ports.iter().enumerate() {
         if pf.host == 0 {
             return Err(Error::Validation {
@@ -277,6 +594,14 @@ pub(super) fn validate_config(config: &Config) -> Result<(), Error> {
                 message: format!("ports[{i}]: guest port must be > 0"),
             });
         }
+        if pf.socket_addr().is_none() {
+            return Err(Error::Validation {
+                message: format!(
+                    "ports[{i}]: bind must be localhost or an IP address such as ::1, got '{}'",
+                    pf.bind
+                ),
+            });
+        }
         // Check for duplicate host port + bind combinations
         for j in (i + 1)..config.ports.len() {
             if pf.host == config.ports[j].host && pf.bind_addr() == config.ports[j].bind_addr() {
@@ -290,7 +615,519 @@ pub(super) fn validate_config(config: &Config) -> Result<(), Error> {
             }
         }
     }
+    Ok(())
+}
+
+fn validate_image(config: &Config) -> Result<(), Error> {
+    // HTTP(S) URL, file:// URL, or local path
+    let base = &config.image.base;
+    if base.trim().is_empty() {
+        return Err(Error::Validation {
+            message: "image.base must not be empty".into(),
+        });
+    }
+    if let Some((scheme, _)) = base.split_once("://")
+        && !matches!(scheme, "http" | "https" | "file")
+    {
+        return Err(Error::Validation {
+            message: format!("image.base scheme '{scheme}://' is not supported"),
+        });
+    }
+
+    // Validate image checksums
+    for (algorithm, digest, len) in [
+        ("sha256", &config.image.sha256, 64),
+        ("sha512", &config.image.sha512, 128),
+    ] {
+        if let Some(digest) = digest
+            && (digest.len() != len || !digest.chars().all(|c| c.is_ascii_hexdigit()))
+        {
+            return Err(Error::Validation {
+                message: format!("image.{algorithm} must be {len} hex characters"),
+            });
+        }
+    }
+
+    // Validate guest architecture
+    if let Some(arch) = &config.image.arch
+        && !matches!(arch.as_str(), "x86_64" | "aarch64")
+    {
+        return Err(Error::Validation {
+            message: format!("image.arch must be 'x86_64' or 'aarch64', got '{arch}'"),
+        });
+    }
+    Ok(())
+}
+
+fn validate_firmware(config: &Config) -> Result<(), Error> {
+    if !matches!(config.advanced.firmware.as_str(), "bios" | "uefi") {
+        return Err(Error::Validation {
+            message: format!(
+                "advanced.firmware must be 'bios' or 'uefi', got '{}'",
+                config.advanced.firmware
+            ),
+        });
+    }
+    Ok(())
+}
 
+fn validate_display(config: &Config) -> Result<(), Error> {
+    if !matches!(config.display.protocol.as_str(), "none" | "spice" | "vnc") {
+        return Err(Error::Validation {
+            message: format!(
+                "display.protocol must be 'none', 'spice', or 'vnc', got '{}'",
+                config.display.protocol
+            ),
+        });
+    }
+    Ok(())
+}
+
+fn validate_ready(config: &Config) -> Result<(), Error> {
+    if config.ready.tcp == Some(0) {
+        return Err(Error::Validation {
+            message: "ready.tcp must be > 0".into(),
+        });
+    }
+    if let Some(url) = &config.ready.http
+        && config.ready.http_target().is_none()
+    {
+        return Err(Error::Validation {
+            message: format!("ready.http must be an http://localhost[:port]/path URL, got '{url}'"),
+        });
+    }
+    if config
+        .ready
+        .command
+        .as_deref()
+        .is_some_and(|c| c.trim().is_empty())
+    {
+        return Err(Error::Validation {
+            message: "ready.command must not be empty".into(),
+        });
+    }
+    if config.ready.timeout_s == 0 {
+        return Err(Error::Validation {
+            message: "ready.timeout_s must be > 0".into(),
+        });
+    }
+    Ok(())
+}
+
+fn validate_provision(config: &Config) -> Result<(), Error> {
+    let scripts = [
+        (
+            "system",
+            config
+                .provision
+                .system
+                .as_ref()
+                .map(|s| (&s.script, &s.script_file)),
+        ),
+        (
+            "boot",
+            config
+                .provision
+                .boot
+                .as_ref()
+                .map(|b| (&b.script, &b.script_file)),
+        ),
+    ];
+    for (label, script) in scripts {
+        if let Some((script, script_file)) = script
+            && script.is_empty() == script_file.is_none()
+        {
+            return Err(Error::Validation {
+                message: format!("provision.{label} needs exactly one of script or script_file"),
+            });
+        }
+    }
+
+    // Validate provision steps
+    let mut step_names = std::collections::HashSet::new();
+    for step in &config.provision.steps {
+        let name = &step.name;
+        let valid = !name.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || matches!(c, '-' | '_'));
+        if !valid {
+            return Err(Error::Validation {
+                message: format!("provision step name '{name}' must match [a-z0-9_-]+"),
+            });
+        }
+        const RESERVED: &[&str] = &[
+            "nixos",
+            "drives",
+            "drive-unlock",
+            "drive-grow",
+            "packages",
+            "docker",
+            "system",
+            "boot",
+        ];
+        if RESERVED.contains(&name.as_str()) || !step_names.insert(name) {
+            return Err(Error::Validation {
+                message: format!("provision step name '{name}' is reserved or used twice"),
+            });
+        }
+        if step.script.is_some() == step.file.is_some() {
+            return Err(Error::Validation {
+                message: format!("provision step '{name}' needs exactly one of script or file"),
+            });
+        }
+        if !matches!(step.run_on.as_str(), "system" | "boot") {
+            return Err(Error::Validation {
+                message: format!("provision step '{name}': run_on must be 'system' or 'boot'"),
+            });
+        }
+        if let Some(user) = &step.user {
+            let valid = user.starts_with(|c: char| c.is_ascii_lowercase() || c == '_')
+                && user.chars().all(|c| {
+                    c.is_ascii_lowercase() || c.is_ascii_digit() || matches!(c, '_' | '-')
+                });
+            if !valid {
+                return Err(Error::Validation {
+                    message: format!("provision step '{name}': '{user}' is not a user name"),
+                });
+            }
+        }
+        if let Some(interpreter) = &step.interpreter {
+            if interpreter.trim().is_empty() || interpreter.contains('\n') {
+                return Err(Error::Validation {
+                    message: format!("provision step '{name}': interpreter must be one line"),
+                });
+            }
+            // `when` is a shell snippet wrapped around the script itself.
+            if step.when.is_some() {
+                return Err(Error::Validation {
+                    message: format!(
+                        "provision step '{name}': `when` only works with the default sh \
+                         interpreter"
+                    ),
+                });
+            }
+        }
+    }
+    // Dependencies must run earlier in the same phase, which also rules out
+    // cycles: steps run by `order`, ties in config order, after `system`.
+    let steps = &config.provision.steps;
+    for (idx, step) in steps.iter().enumerate() {
+        for dep in step.depends_on.iter().flatten() {
+            let earlier = if dep == "system" {
+                config.provision.system.is_some() && step.run_on == "system"
+            } else {
+                steps.iter().enumerate().any(|(i, other)| {
+                    other.name == *dep
+                        && other.run_on == step.run_on
+                        && (other.order, i) < (step.order, idx)
+                })
+            };
+            if !earlier {
+                return Err(Error::Validation {
+                    message: format!(
+                        "provision step '{}' depends on '{dep}', which is not an earlier step \
+                         with the same run_on",
+                        step.name
+                    ),
+                });
+            }
+        }
+    }
+
+    // Validate packages — they are passed to the package manager verbatim
+    for package in &config.provision.packages {
+        let valid = !package.is_empty()
+            && !package.starts_with('-')
+            && package.chars().all(|c| {
+                c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-' | '+' | ':' | '@')
+            });
+        if !valid {
+            return Err(Error::Validation {
+                message: format!("provision.packages entry '{package}' is not a package name"),
+            });
+        }
+    }
+    Ok(())
+}
+
+fn validate_metadata(config: &Config) -> Result<(), Error> {
+    for key in config.metadata.labels.keys() {
+        let valid = !key.is_empty()
+            && key
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-' | '/'));
+        if !valid {
+            return Err(Error::Validation {
+                message: format!("metadata label '{key}' must match [a-zA-Z0-9._/-]+"),
+            });
+        }
+    }
+
+    Ok(())
+}
+
+fn validate_serve(config: &Config) -> Result<(), Error> {
+    let Some(addr) = &config.serve.http else {
+        return Ok(());
+    };
+    let Ok(parsed) = addr.parse::<std::net::SocketAddr>() else {
+        return Err(Error::Validation {
+            message: format!("serve.http '{addr}' must be an address like 127.0.0.1:7878"),
+        });
+    };
+    if !parsed.ip().is_loopback() && !config.serve.allow_remote {
+        return Err(Error::Validation {
+            message: format!(
+                "serve.http '{addr}' is not a loopback address; the API is plain HTTP, \
+                 set serve.allow_remote = true to expose it anyway"
+            ),
+        });
+    }
+
+    Ok(())
+}
+
+fn validate_logging(config: &Config) -> Result<(), Error> {
+    if !matches!(config.logging.format.as_str(), "text" | "json") {
+        return Err(Error::Validation {
+            message: format!(
+                "logging.format must be 'text' or 'json', got '{}'",
+                config.logging.format
+            ),
+        });
+    }
+    if config.logging.max_files == 0 {
+        return Err(Error::Validation {
+            message: "logging.max_files must be at least 1".into(),
+        });
+    }
+    Ok(())
+}
+
+fn validate_timeouts(config: &Config) -> Result<(), Error> {
+    if config.timeouts.heartbeat_interval_s == 0 {
+        return Err(Error::Validation {
+            message: "timeouts.heartbeat_interval_s must be at least 1".into(),
+        });
+    }
+    if config.timeouts.missed_heartbeats == 0 {
+        return Err(Error::Validation {
+            message: "timeouts.missed_heartbeats must be at least 1".into(),
+        });
+    }
+    Ok(())
+}
+
+fn validate_notify(config: &Config) -> Result<(), Error> {
+    for url in &config.notify.webhooks {
+        if !url.starts_with("http://") && !url.starts_with("https://") {
+            return Err(Error::Validation {
+                message: format!("notify.webhooks entry '{url}' must be an http(s) URL"),
+            });
+        }
+    }
+
+    Ok(())
+}
+
+fn validate_docker(config: &Config) -> Result<(), Error> {
+    let docker = &config.docker;
+    if !matches!(docker.engine.as_str(), "docker" | "podman") {
+        return Err(Error::Validation {
+            message: format!(
+                "docker.engine must be 'docker' or 'podman', got '{}'",
+                docker.engine
+            ),
+        });
+    }
+    Ok(())
+}
+
+fn validate_tests(config: &Config) -> Result<(), Error> {
+    let mut names = std::collections::HashSet::new();
+    for test in &config.tests {
+        let name = &test.name;
+        if name.trim().is_empty() || !names.insert(name) {
+            return Err(Error::Validation {
+                message: format!("tests: name '{name}' is empty or used twice"),
+            });
+        }
+        if test.command.trim().is_empty() {
+            return Err(Error::Validation {
+                message: format!("test '{name}': command must not be empty"),
+            });
+        }
+        if test.timeout_s == 0 {
+            return Err(Error::Validation {
+                message: format!("test '{name}': timeout_s must be > 0"),
+            });
+        }
+        crate::test_run::TestCheck::new(test.clone())?;
+    }
+
+    Ok(())
+}
+
+/// Check the static addressing and DNS settings written to the guest's
+/// `network-config`.
+fn validate_interface_addressing(iface: &InterfaceConfig) -> Result<(), Error> {
+    let label = format!("network '{}'", iface.network);
+    if !iface.addresses.is_empty() && !iface.ip.is_empty() {
+        return Err(Error::Validation {
+            message: format!("{label}: set either ip (DHCP reservation) or static addresses"),
+        });
+    }
+    for address in &iface.addresses {
+        let valid = address.split_once('/').is_some_and(|(ip, prefix)| {
+            match (ip.parse::<std::net::IpAddr>(), prefix.parse::<u8>()) {
+                (Ok(ip), Ok(prefix)) => prefix <= if ip.is_ipv4() { 32 } else { 128 },
+                _ => false,
+            }
+        });
+        if !valid {
+            return Err(Error::Validation {
+                message: format!("{label}: address '{address}' must be in CIDR form (a.b.c.d/nn)"),
+            });
+        }
+    }
+    if !iface.gateway.is_empty() {
+        if iface.addresses.is_empty() {
+            return Err(Error::Validation {
+                message: format!("{label}: gateway requires static addresses"),
+            });
+        }
+        if iface.gateway.parse::<std::net::IpAddr>().is_err() {
+            return Err(Error::Validation {
+                message: format!("{label}: gateway '{}' is not an IP address", iface.gateway),
+            });
+        }
+    }
+    if let Some(mtu) = iface.mtu
+        && !(68..=65535).contains(&mtu)
+    {
+        return Err(Error::Validation {
+            message: format!("{label}: mtu must be between 68 and 65535 (got {mtu})"),
+        });
+    }
+    validate_nameservers(&label, &iface.nameservers)
+}
+
+fn validate_nameservers(label: &str, nameservers: &[String]) -> Result<(), Error> {
+    match nameservers
+        .iter()
+        .find(|ns| ns.parse::<std::net::IpAddr>().is_err())
+    {
+        Some(ns) => Err(Error::Validation {
+            message: format!("{label}: nameserver '{ns}' is not an IP address"),
+        }),
+        None => Ok(()),
+    }
+}
+
+/// Check the subnet, DHCP range and DNS records of an interface's network.
+fn validate_interface_network(iface: &InterfaceConfig) -> Result<(), Error> {
+    let label = format!("network '{}'", iface.network);
+    if iface.subnet.is_empty() {
+        if iface.dhcp.is_some() || !iface.domain.is_empty() || !iface.hosts.is_empty() {
+            return Err(Error::Validation {
+                message: format!("{label}: dhcp, domain and hosts require a subnet"),
+            });
+        }
+        return Ok(());
+    }
+    let subnet = iface.subnet_prefix().ok_or_else(|| Error::Validation {
+        message: format!(
+            "{label}: subnet must look like 192.168.77.0/24, got '{}'",
+            iface.subnet
+        ),
+    })?;
+
+    // Host part of `addr` within the subnet, if it is one.
+    let host = |addr: &str| {
+        addr.strip_prefix(subnet)
+            .and_then(|rest| rest.strip_prefix('.'))
+            .and_then(|octet| octet.parse::<u8>().ok())
+    };
+    let in_subnet = |field: &str, addr: &str| match host(addr) {
+        Some(1..=254) => Ok(()),
+        _ => Err(Error::Validation {
+            message: format!(
+                "{label}: {field} '{addr}' is not a host in {}",
+                iface.subnet
+            ),
+        }),
+    };
+
+    // The subnet is IPv4; IPv6 addresses come from the network's ULA prefix.
+    if !iface.ip.is_empty() && !iface.has_ipv6_ip() {
+        in_subnet("ip", &iface.ip)?;
+    }
+    if let Some(dhcp) = &iface.dhcp {
+        in_subnet("dhcp.start", &dhcp.start)?;
+        in_subnet("dhcp.end", &dhcp.end)?;
+        if host(&dhcp.start) > host(&dhcp.end) {
+            return Err(Error::Validation {
+                message: format!("{label}: dhcp.start must not be after dhcp.end"),
+            });
+        }
+        if host(&dhcp.start) == Some(1) {
+            return Err(Error::Validation {
+                message: format!("{label}: the DHCP range must not include the gateway {subnet}.1"),
+            });
+        }
+    }
+
+    let is_dns_name = |name: &str| {
+        !name.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.')
+    };
+    if !iface.domain.is_empty() && !is_dns_name(&iface.domain) {
+        return Err(Error::Validation {
+            message: format!("{label}: invalid domain '{}'", iface.domain),
+        });
+    }
+    for record in &iface.hosts {
+        in_subnet("hosts.ip", &record.ip)?;
+        if record.names.is_empty() || !record.names.iter().all(|n| is_dns_name(n)) {
+            return Err(Error::Validation {
+                message: format!("{label}: hosts entry for {} needs valid names", record.ip),
+            });
+        }
+    }
+    Ok(())
+}
+
+fn validate_disk_io(label: &str, io: &DiskIoConfig) -> Result<(), Error> {
+    if let Some(cache) = &io.cache
+        && !matches!(
+            cache.as_str(),
+            "none" | "writeback" | "writethrough" | "directsync" | "unsafe"
+        )
+    {
+        return Err(Error::Validation {
+            message: format!(
+                "{label}: cache must be none, writeback, writethrough, directsync, or unsafe"
+            ),
+        });
+    }
+    match io.io.as_deref() {
+        None | Some("threads" | "io_uring") => {}
+        // QEMU refuses native AIO without O_DIRECT.
+        Some("native") if matches!(io.cache.as_deref(), Some("none" | "directsync")) => {}
+        Some("native") => {
+            return Err(Error::Validation {
+                message: format!("{label}: io = \"native\" requires cache none or directsync"),
+            });
+        }
+        Some(_) => {
+            return Err(Error::Validation {
+                message: format!("{label}: io must be native, threads, or io_uring"),
+            });
+        }
+    }
     Ok(())
 }
 
@@ -0,0 +1,315 @@
+//! Host environment checks for `rum doctor`.
+//!
+//! Every check reports a [`CheckStatus`] plus a hint on how to fix it. Only
+//! [`CheckStatus::Fail`] marks something that stops machines from booting;
+//! warnings cover optional features such as virtiofs mounts or TPM emulation.
+
+use std::path::{Path, PathBuf};
+
+use virt::connect::Connect;
+use virt::error as virt_error;
+
+use crate::{paths, util};
+
+/// Free space below which a directory check fails.
+const MIN_FREE_BYTES: u64 = 2 << 30;
+/// Free space below which a directory check warns.
+const LOW_FREE_BYTES: u64 = 10 << 30;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckStatus {
+    Pass,
+    Warn,
+    Fail,
+}
+
+impl CheckStatus {
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Pass => "ok",
+            Self::Warn => "warn",
+            Self::Fail => "FAIL",
+        }
+    }
+}
+
+/// Outcome of one environment check.
+#[derive(Debug, Clone)]
+pub struct Check {
+    pub name: String,
+    pub status: CheckStatus,
+    pub detail: String,
+    /// How to fix a warning or failure.
+    pub hint: Option<String>,
+}
+
+impl Check {
+    fn pass(name: impl Into<String>, detail: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            status: CheckStatus::Pass,
+            detail: detail.into(),
+            hint: None,
+        }
+    }
+
+    fn problem(
+        status: CheckStatus,
+        name: impl Into<String>,
+        detail: impl Into<String>,
+        hint: impl Into<String>,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            status,
+            detail: detail.into(),
+            hint: Some(hint.into()),
+        }
+    }
+}
+
+/// Run every check against the host and the libvirt daemon at `libvirt_uri`.
+pub fn run_checks(libvirt_uri: &str) -> Vec<Check> {
+    vec![
+        check_kvm(),
+        check_libvirt(libvirt_uri),
+        check_libvirt_group(libvirt_uri),
+        check_virtiofsd(),
+        check_swtpm(),
+        check_avahi(),
+        check_free_space("cache dir", &paths::cache_dir()),
+        check_free_space("work dir", &paths::data_dir()),
+        check_nested(),
+        check_vsock(),
+    ]
+}
+
+fn check_kvm() -> Check {
+    let name = "kvm";
+    if !Path::new("/dev/kvm").exists() {
+        return Check::problem(
+            CheckStatus::Fail,
+            name,
+            "/dev/kvm does not exist",
+            "enable virtualization in the firmware settings and load kvm_intel or kvm_amd",
+        );
+    }
+    match std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/kvm")
+    {
+        Ok(_) => Check::pass(name, "/dev/kvm is accessible"),
+        Err(e) => Check::problem(
+            CheckStatus::Fail,
+            name,
+            format!("cannot open /dev/kvm: {e}"),
+            "add your user to the kvm group, then log in again",
+        ),
+    }
+}
+
+fn check_libvirt(libvirt_uri: &str) -> Check {
+    let name = "libvirt";
+    virt_error::clear_error_callback();
+    match Connect::open(Some(libvirt_uri)) {
+        Ok(mut conn) => {
+            let _ = conn.close();
+            Check::pass(name, format!("connected to {libvirt_uri}"))
+        }
+        Err(e) => Check::problem(
+            CheckStatus::Fail,
+            name,
+            format!("cannot connect to {libvirt_uri}: {e}"),
+            "start libvirtd (`systemctl enable --now libvirtd`) and check the URI",
+        ),
+    }
+}
+
+/// Only the system daemon is guarded by group membership.
+fn check_libvirt_group(libvirt_uri: &str) -> Check {
+    let name = "libvirt group";
+    if !libvirt_uri.ends_with("/system") {
+        return Check::pass(name, format!("not needed for {libvirt_uri}"));
+    }
+    let groups = match std::process::Command::new("id").arg("-nG").output() {
+        Ok(output) if output.status.success() => {
+            String::from_utf8_lossy(&output.stdout).into_owned()
+        }
+        _ => {
+            return Check::problem(
+                CheckStatus::Warn,
+                name,
+                "could not list your groups",
+                "check that `id -nG` works",
+            );
+        }
+    };
+    if groups.split_whitespace().any(|g| g == "libvirt") {
+        Check::pass(name, "you are in the libvirt group")
+    } else {
+        Check::problem(
+            CheckStatus::Warn,
+            name,
+            "you are not in the libvirt group",
+            "run `sudo usermod -aG libvirt $USER`, then log in again",
+        )
+    }
+}
+
+fn check_virtiofsd() -> Check {
+    if util::virtiofsd_available() {
+        Check::pass("virtiofsd", "found")
+    } else {
+        Check::problem(
+            CheckStatus::Warn,
+            "virtiofsd",
+            "not found; virtiofs mounts will not work",
+            "install virtiofsd, or use `driver = \"9p\"` for mounts",
+        )
+    }
+}
+
+fn check_swtpm() -> Check {
+    if find_in_path("swtpm").is_some() {
+        Check::pass("swtpm", "found")
+    } else {
+        Check::problem(
+            CheckStatus::Warn,
+            "swtpm",
+            "not found; TPM emulation is unavailable",
+            "install swtpm",
+        )
+    }
+}
+
+fn check_avahi() -> Check {
+    if find_in_path("avahi-publish").is_some() {
+        Check::pass("avahi", "found")
+    } else {
+        Check::problem(
+            CheckStatus::Warn,
+            "avahi",
+            "avahi-publish not found; `network.mdns` cannot advertise machines",
+            "install avahi-utils and start avahi-daemon",
+        )
+    }
+}
+
+fn check_free_space(label: &str, dir: &Path) -> Check {
+    let name = format!("{label} space");
+    // The directory may not exist before the first `rum up`.
+    let existing = dir.ancestors().find(|d| d.exists()).unwrap_or(dir);
+    let available = std::process::Command::new("df")
+        .args(["--output=avail", "-B1"])
+        .arg(existing)
+        .output()
+        .ok()
+        .and_then(|output| parse_df_avail(&String::from_utf8_lossy(&output.stdout)));
+    let Some(available) = available else {
+        return Check::problem(
+            CheckStatus::Warn,
+            name,
+            format!("could not determine free space in {}", dir.display()),
+            "check that `df` is installed",
+        );
+    };
+
+    let detail = format!("{} free in {}", format_gib(available), dir.display());
+    match space_status(available) {
+        CheckStatus::Pass => Check::pass(name, detail),
+        status => Check::problem(
+            status,
+            name,
+            detail,
+            "free up space, e.g. with `rum clean` or `rum image delete`",
+        ),
+    }
+}
+
+fn check_nested() -> Check {
+    let name = "nested virt";
+    let value = ["kvm_intel", "kvm_amd"].iter().find_map(|module| {
+        let path = format!("/sys/module/{module}/parameters/nested");
+        std::fs::read_to_string(path).ok().map(|v| (*module, v))
+    });
+    match value {
+        None => Check::pass(name, "no KVM module parameters to check"),
+        Some((module, value)) if nested_enabled(&value) => {
+            Check::pass(name, format!("enabled in {module}"))
+        }
+        Some((module, _)) => Check::problem(
+            CheckStatus::Warn,
+            name,
+            format!("disabled in {module}; `resources.nested` has no effect"),
+            format!("add `options {module} nested=1` to /etc/modprobe.d/kvm.conf and reload it"),
+        ),
+    }
+}
+
+fn check_vsock() -> Check {
+    let name = "vsock";
+    if Path::new("/dev/vhost-vsock").exists() || Path::new("/sys/module/vhost_vsock").exists() {
+        Check::pass(name, "vhost_vsock is available")
+    } else {
+        Check::problem(
+            CheckStatus::Warn,
+            name,
+            "vhost_vsock is not loaded; the guest agent falls back to a serial channel \
+             that only the daemon can use, without port forwards or tcp/http readiness \
+             probes",
+            "run `sudo modprobe vhost_vsock`",
+        )
+    }
+}
+
+fn find_in_path(binary: &str) -> Option<PathBuf> {
+    let path = std::env::var_os("PATH").unwrap_or_default();
+    std::env::split_paths(&path)
+        .map(|dir| dir.join(binary))
+        .find(|candidate| candidate.is_file())
+}
+
+/// Available bytes from `df --output=avail -B1`, which prints a header line
+/// followed by the number.
+fn parse_df_avail(output: &str) -> Option<u64> {
+    output.lines().nth(1)?.trim().parse().ok()
+}
+
+fn space_status(available: u64) -> CheckStatus {
+    if available < MIN_FREE_BYTES {
+        CheckStatus::Fail
+    } else if available < LOW_FREE_BYTES {
+        CheckStatus::Warn
+    } else {
+        CheckStatus::Pass
+    }
+}
+
+fn nested_enabled(value: &str) -> bool {
+    matches!(value.trim(), "Y" | "y" | "1")
+}
+
+fn format_gib(bytes: u64) -> String {
+    format!("{:.1} GiB", bytes as f64 / (1u64 << 30) as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn df_output_is_parsed() {
+        assert_eq!(parse_df_avail("   Avail\n12345678\n"), Some(12345678));
+        assert_eq!(parse_df_avail("Avail\n"), None);
+    }
+
+    #[test]
+    fn free_space_thresholds() {
+        assert_eq!(space_status(1 << 30), CheckStatus::Fail);
+        assert_eq!(space_status(5 << 30), CheckStatus::Warn);
+        assert_eq!(space_status(50 << 30), CheckStatus::Pass);
+        assert!(nested_enabled("Y\n"));
+        assert!(!nested_enabled("N\n"));
+    }
+}
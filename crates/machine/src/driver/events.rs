@@ -0,0 +1,317 @@
+//! Lifecycle events of a libvirt domain, pushed by libvirt instead of polled.
+//!
+//! libvirt delivers events from its default event loop, which one dedicated
+//! thread runs for the whole process. Each [`DomainEvents`] keeps its own
+//! connection with a lifecycle callback registered on it; the callback only
+//! forwards the events of its domain into a channel, so nothing async ever
+//! runs on the event-loop thread.
+
+use std::ffi::{CStr, c_int, c_void};
+use std::sync::{Arc, Mutex, Once};
+use std::time::Duration;
+
+use tokio::sync::mpsc;
+use virt::connect::Connect;
+use virt::domain::Domain;
+use virt::error as virt_error;
+use virt::sys;
+
+use crate::error::Error;
+
+/// Seconds between keepalive probes, and probes missed before libvirt closes
+/// the connection, so a restarted libvirtd ends the subscription.
+const KEEPALIVE_INTERVAL_S: c_int = 5;
+const KEEPALIVE_COUNT: u32 = 3;
+
+/// Health of a domain the daemon expects to be running.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DomainHealth {
+    Running,
+    /// Powered off without a crash, e.g. `poweroff` inside the guest.
+    Stopped,
+    Crashed {
+        reason: String,
+    },
+}
+
+/// Lifecycle changes of one domain. Dropping it deregisters the callback and
+/// closes the connection it was registered on.
+pub struct DomainEvents {
+    rx: mpsc::UnboundedReceiver<DomainHealth>,
+    _subscription: Option<Subscription>,
+    /// Keeps `rx` open for [`DomainEvents::silent`].
+    _idle: Option<mpsc::UnboundedSender<DomainHealth>>,
+}
+
+impl DomainEvents {
+    /// Events of a backend that cannot report any: [`DomainEvents::next`]
+    /// never resolves.
+    pub fn silent() -> Self {
+        let (tx, rx) = mpsc::unbounded_channel();
+        Self {
+            rx,
+            _subscription: None,
+            _idle: Some(tx),
+        }
+    }
+
+    /// The next change, or `None` once the connection to libvirt is lost and
+    /// the caller has to subscribe again.
+    pub async fn next(&mut self) -> Option<DomainHealth> {
+        self.rx.recv().await
+    }
+}
+
+/// Subscribe to the lifecycle events of domain `name` on `uri`.
+///
+/// The current state is reported first if the domain is not running, so a
+/// crash between the last check and the subscription is not missed.
+pub(super) fn watch(uri: &str, name: &str) -> Result<DomainEvents, Error> {
+    start_event_loop();
+    virt_error::clear_error_callback();
+
+    let conn = Connect::open(Some(uri)).map_err(|e| Error::Libvirt {
+        message: format!("failed to connect to libvirt: {e}"),
+        hint: format!("ensure libvirtd is running and you have access to {uri}"),
+    })?;
+    let (tx, rx) = mpsc::unbounded_channel();
+    let listener = Arc::new(Listener {
+        name: name.to_string(),
+        tx: Mutex::new(Some(tx)),
+    });
+
+    // SAFETY: `conn` is open. Each registration gets its own strong count of
+    // `listener`, which libvirt hands back to `release` once it drops the
+    // callback, so the pointer stays valid for as long as the callback can
+    // run. A failed registration keeps nothing, so its count is dropped here.
+    let close_opaque = Arc::into_raw(listener.clone()) as *mut c_void;
+    let registered = unsafe {
+        sys::virConnectSetKeepAlive(conn.as_ptr(), KEEPALIVE_INTERVAL_S, KEEPALIVE_COUNT);
+        sys::virConnectRegisterCloseCallback(
+            conn.as_ptr(),
+            Some(on_close),
+            close_opaque,
+            Some(release),
+        )
+    };
+    if registered < 0 {
+        drop(unsafe { Arc::from_raw(close_opaque as *const Listener) });
+        return Err(Error::Libvirt {
+            message: format!("failed to watch the libvirt connection for domain '{name}'"),
+            hint: "check libvirt permissions".into(),
+        });
+    }
+
+    // SAFETY: as above. The lifecycle callback is cast to the generic
+    // signature exactly like the `VIR_DOMAIN_EVENT_CALLBACK` macro does in C.
+    let lifecycle_opaque = Arc::into_raw(listener.clone()) as *mut c_void;
+    let callback_id = unsafe {
+        let lifecycle: LifecycleCallback = on_lifecycle;
+        sys::virConnectDomainEventRegisterAny(
+            conn.as_ptr(),
+            std::ptr::null_mut(),
+            sys::VIR_DOMAIN_EVENT_ID_LIFECYCLE as c_int,
+            Some(std::mem::transmute::<LifecycleCallback, GenericCallback>(
+                lifecycle,
+            )),
+            lifecycle_opaque,
+            Some(release),
+        )
+    };
+    if callback_id < 0 {
+        // SAFETY: as above; unregistering hands the close callback's count
+        // back to `release`.
+        unsafe {
+            drop(Arc::from_raw(lifecycle_opaque as *const Listener));
+            sys::virConnectUnregisterCloseCallback(conn.as_ptr(), Some(on_close));
+        }
+        return Err(Error::Libvirt {
+            message: format!("failed to register for events of domain '{name}'"),
+            hint: "check libvirt permissions".into(),
+        });
+    }
+
+    if let Ok(dom) = Domain::lookup_by_name(&conn, name)
+        && let Ok((state, reason)) = dom.get_state()
+    {
+        let health = state_health(state as u32, reason as u32);
+        if health != DomainHealth::Running {
+            listener.send(health);
+        }
+    }
+
+    Ok(DomainEvents {
+        rx,
+        _subscription: Some(Subscription { conn, callback_id }),
+        _idle: None,
+    })
+}
+
+/// Register libvirt's default event loop and run it on a thread of its own,
+/// once per process. Connections only get events if they are opened after.
+fn start_event_loop() {
+    static EVENT_LOOP: Once = Once::new();
+    EVENT_LOOP.call_once(|| {
+        // SAFETY: no preconditions; `Once` keeps it to a single call.
+        if unsafe { sys::virEventRegisterDefaultImpl() } < 0 {
+            tracing::warn!("failed to register the libvirt event loop");
+            return;
+        }
+        let spawned = std::thread::Builder::new()
+            .name("libvirt-events".into())
+            .spawn(|| {
+                loop {
+                    // SAFETY: the default implementation was registered above
+                    // and this is the only thread running it.
+                    if unsafe { sys::virEventRunDefaultImpl() } < 0 {
+                        tracing::warn!("libvirt event loop iteration failed");
+                        std::thread::sleep(Duration::from_secs(1));
+                    }
+                }
+            });
+        if let Err(error) = spawned {
+            tracing::warn!(%error, "failed to start the libvirt event loop");
+        }
+    });
+}
+
+/// The connection and callback behind a [`DomainEvents`].
+struct Subscription {
+    conn: Connect,
+    callback_id: c_int,
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        // SAFETY: `conn` is still open and `callback_id` was registered on it.
+        // Failures only mean libvirt already dropped the callbacks with the
+        // connection.
+        unsafe {
+            sys::virConnectDomainEventDeregisterAny(self.conn.as_ptr(), self.callback_id);
+            sys::virConnectUnregisterCloseCallback(self.conn.as_ptr(), Some(on_close));
+        }
+    }
+}
+
+/// State shared by the callbacks of one subscription.
+struct Listener {
+    name: String,
+    /// Taken when the connection closes, which ends the receiver.
+    tx: Mutex<Option<mpsc::UnboundedSender<DomainHealth>>>,
+}
+
+impl Listener {
+    fn send(&self, health: DomainHealth) {
+        if let Some(tx) = self.tx.lock().expect("listener lock poisoned").as_ref() {
+            let _ = tx.send(health);
+        }
+    }
+}
+
+type LifecycleCallback =
+    unsafe extern "C" fn(sys::virConnectPtr, sys::virDomainPtr, c_int, c_int, *mut c_void) -> c_int;
+type GenericCallback = unsafe extern "C" fn(sys::virConnectPtr, sys::virDomainPtr, *mut c_void);
+
+unsafe extern "C" fn on_lifecycle(
+    _conn: sys::virConnectPtr,
+    dom: sys::virDomainPtr,
+    event: c_int,
+    detail: c_int,
+    opaque: *mut c_void,
+) -> c_int {
+    // SAFETY: `opaque` is a `Listener` count that lives until `release`, and
+    // libvirt passes a domain that is valid for the duration of the call.
+    let listener = unsafe { &*(opaque as *const Listener) };
+    let name = unsafe { sys::virDomainGetName(dom) };
+    if name.is_null() || unsafe { CStr::from_ptr(name) }.to_bytes() != listener.name.as_bytes() {
+        return 0;
+    }
+    if let Some(health) = lifecycle_health(event as u32, detail as u32) {
+        listener.send(health);
+    }
+    0
+}
+
+unsafe extern "C" fn on_close(_conn: sys::virConnectPtr, _reason: c_int, opaque: *mut c_void) {
+    // SAFETY: see `on_lifecycle`.
+    let listener = unsafe { &*(opaque as *const Listener) };
+    listener.tx.lock().expect("listener lock poisoned").take();
+}
+
+unsafe extern "C" fn release(opaque: *mut c_void) {
+    // SAFETY: every registration passes a count from `Arc::into_raw`, and
+    // libvirt calls this once per registration.
+    drop(unsafe { Arc::from_raw(opaque as *const Listener) });
+}
+
+/// What a lifecycle event says about the domain; `None` for events that do
+/// not change whether it is usable, such as a reboot or a resume.
+fn lifecycle_health(event: u32, detail: u32) -> Option<DomainHealth> {
+    if event == sys::VIR_DOMAIN_EVENT_CRASHED as u32 {
+        return Some(DomainHealth::Crashed {
+            reason: "the guest kernel panicked".into(),
+        });
+    }
+    if event != sys::VIR_DOMAIN_EVENT_STOPPED as u32 {
+        return None;
+    }
+    Some(if detail == sys::VIR_DOMAIN_EVENT_STOPPED_CRASHED as u32 {
+        DomainHealth::Crashed {
+            reason: "the domain crashed".into(),
+        }
+    } else if detail == sys::VIR_DOMAIN_EVENT_STOPPED_FAILED as u32 {
+        DomainHealth::Crashed {
+            reason: "QEMU exited unexpectedly".into(),
+        }
+    } else {
+        DomainHealth::Stopped
+    })
+}
+
+/// The same for a state read with `virDomainGetState`.
+fn state_health(state: u32, reason: u32) -> DomainHealth {
+    if state == sys::VIR_DOMAIN_CRASHED as u32
+        || (state == sys::VIR_DOMAIN_PAUSED as u32
+            && reason == sys::VIR_DOMAIN_PAUSED_CRASHED as u32)
+    {
+        DomainHealth::Crashed {
+            reason: "the guest kernel panicked".into(),
+        }
+    } else if state == sys::VIR_DOMAIN_SHUTOFF as u32 {
+        if reason == sys::VIR_DOMAIN_SHUTOFF_CRASHED as u32 {
+            DomainHealth::Crashed {
+                reason: "the domain crashed".into(),
+            }
+        } else {
+            DomainHealth::Stopped
+        }
+    } else {
+        DomainHealth::Running
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stop_events_tell_crashes_from_shutdowns() {
+        let stopped = sys::VIR_DOMAIN_EVENT_STOPPED as u32;
+        assert_eq!(
+            lifecycle_health(stopped, sys::VIR_DOMAIN_EVENT_STOPPED_SHUTDOWN as u32),
+            Some(DomainHealth::Stopped)
+        );
+        assert!(matches!(
+            lifecycle_health(stopped, sys::VIR_DOMAIN_EVENT_STOPPED_CRASHED as u32),
+            Some(DomainHealth::Crashed { .. })
+        ));
+        assert!(matches!(
+            lifecycle_health(sys::VIR_DOMAIN_EVENT_CRASHED as u32, 0),
+            Some(DomainHealth::Crashed { .. })
+        ));
+        assert_eq!(
+            lifecycle_health(sys::VIR_DOMAIN_EVENT_RESUMED as u32, 0),
+            None
+        );
+    }
+}
@@ -0,0 +1,149 @@
+//! Reaching the guest agent of a running machine: which transports the
+//! domain offers, which one the agent answered on, and the `[ready]`
+//! probes that run through it.
+
+use virt::domain::Domain;
+
+use crate::driver::Driver;
+use crate::driver::events::{self, DomainEvents};
+use crate::error::Error;
+use crate::guest::{AgentConnector, AgentEndpoints};
+
+use super::lifecycle::LibvirtDriver;
+
+impl LibvirtDriver {
+    /// Retry the `[ready]` probes until they all pass at once.
+    ///
+    /// Each probe runs in the guest, so no host port forward is needed.
+    pub async fn wait_ready(&self) -> Result<(), Error> {
+        let ready = &self.system.config.ready;
+        if ready.is_empty() {
+            return Ok(());
+        }
+
+        let connector = self.agent_connector()?;
+        // The tcp and http probes go through the agent's vsock forward port,
+        // so they cannot pass over the agent channel.
+        let cid = match &connector {
+            AgentConnector::Vsock(cid) => *cid,
+            AgentConnector::Serial(_) if ready.tcp.is_some() || ready.http.is_some() => {
+                return Err(Error::Validation {
+                    message: "ready.tcp and ready.http probe through vsock, but the guest \
+                              agent is only reachable over its serial channel; use \
+                              ready.command instead"
+                        .into(),
+                });
+            }
+            AgentConnector::Serial(_) => 0,
+        };
+        let tcp_up = async |port| crate::guest::probe_tcp(cid, port).await;
+        let http_up = async |port, path: &str| crate::guest::probe_http(cid, port, path).await;
+        let client = crate::guest::agent_client(connector);
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(ready.timeout_s);
+        loop {
+            let mut failing = Vec::new();
+            if let Some(port) = ready.tcp
+                && !tcp_up(port).await
+            {
+                failing.push(format!("tcp {port}"));
+            }
+            if let Some((port, path)) = ready.http_target()
+                && !http_up(port, &path).await
+            {
+                failing.push(format!(
+                    "http {}",
+                    ready.http.as_deref().unwrap_or_default()
+                ));
+            }
+            if let Some(command) = &ready.command
+                && !client
+                    .exec_with_output(command.clone(), Default::default(), |event| {
+                        tracing::trace!(message = %event.message, "ready probe output");
+                    })
+                    .await
+                    .is_ok_and(|code| code == 0)
+            {
+                failing.push(format!("command `{command}`"));
+            }
+
+            if failing.is_empty() {
+                return Ok(());
+            }
+            if std::time::Instant::now() >= deadline {
+                return Err(Error::NotReady {
+                    name: self.name().to_string(),
+                    failing: failing.join(", "),
+                    timeout_s: ready.timeout_s,
+                });
+            }
+            tracing::debug!(failing = ?failing, "waiting for readiness probes");
+            tokio::time::sleep(std::time::Duration::from_secs(2)).await;
+        }
+    }
+
+    /// The guest's vsock CID, for port forwards and other streams the agent
+    /// serves on vsock only.
+    pub fn get_vsock_cid(&self) -> Result<u32, Error> {
+        match self.agent_connector()? {
+            AgentConnector::Vsock(cid) => Ok(cid),
+            AgentConnector::Serial(_) => Err(Error::ExecNotReady {
+                name: self.name().to_string(),
+                reason: "the guest agent is only reachable over its serial channel, which \
+                         carries no port or socket forwards; load vhost_vsock on the host \
+                         (`sudo modprobe vhost_vsock`) and restart the machine"
+                    .into(),
+            }),
+        }
+    }
+
+    /// The transports the running machine offers its guest agent on.
+    pub fn agent_endpoints(&self) -> Result<AgentEndpoints, Error> {
+        let dom = self.running_domain()?;
+        let endpoints = self.agent_endpoints_of(&dom);
+        if endpoints == AgentEndpoints::default() {
+            return Err(Error::ExecNotReady {
+                name: self.name().to_string(),
+                reason: "domain XML has neither a vsock CID nor an agent channel".into(),
+            });
+        }
+        Ok(endpoints)
+    }
+
+    /// How to reach the guest agent of the running machine: over the
+    /// transport negotiated since boot, else over vsock where the domain
+    /// has it.
+    pub fn agent_connector(&self) -> Result<AgentConnector, Error> {
+        let negotiated = std::fs::read_to_string(&self.layout.agent_transport).ok();
+        let endpoints = self.agent_endpoints()?;
+        Ok(endpoints
+            .connector(negotiated.as_deref().map(str::trim))
+            .expect("agent_endpoints offers a transport"))
+    }
+
+    /// Remember `connector` as the transport the guest agent answered on,
+    /// for every caller until the next boot.
+    pub fn record_agent_transport(&self, connector: &AgentConnector) -> Result<(), Error> {
+        crate::util::write_atomic(
+            &self.layout.agent_transport,
+            connector.transport().as_bytes(),
+        )
+    }
+
+    pub(super) fn agent_endpoints_of(&self, dom: &Domain) -> AgentEndpoints {
+        let Ok(xml) = dom.get_xml_desc(0) else {
+            return AgentEndpoints::default();
+        };
+        AgentEndpoints {
+            vsock_cid: domain::parse_vsock_cid(&xml),
+            channel: xml
+                .contains(domain::AGENT_CHANNEL)
+                .then(|| self.layout.agent_channel.clone()),
+        }
+    }
+
+    /// Subscribe to the lifecycle events of the domain, to notice a guest
+    /// that crashed or powered off while the daemon thought it was running.
+    pub fn watch_domain(&self) -> Result<DomainEvents, Error> {
+        events::watch(self.system.libvirt_uri(), self.name())
+    }
+}
@@ -0,0 +1,236 @@
+//! Disk images of the machine: the overlay and its provisioned layer, and
+//! the extra drives.
+//!
+//! After provisioning, the disk is frozen into a provisioned layer while
+//! the machine runs, and the frozen files are folded into that layer once
+//! it has stopped. `rum reset` drops the working layer on top of it.
+
+use std::path::{Path, PathBuf};
+
+use guest::agent::ProvisionScript;
+use virt::domain::Domain;
+use virt::domain_snapshot::DomainSnapshot;
+
+use crate::driver::Driver;
+use crate::error::Error;
+use crate::qcow2;
+
+use super::lifecycle::LibvirtDriver;
+
+impl LibvirtDriver {
+    /// Freeze the provisioned layer after provisioning succeeded: the first
+    /// time, and again whenever scripts ran on top of an existing layer.
+    pub fn freeze_after_provision(&self, ran: &[ProvisionScript]) -> Result<(), Error> {
+        let frozen = self.layout.provisioned_layer.exists() || !self.working_files().is_empty();
+        if ran.is_empty() && frozen {
+            return Ok(());
+        }
+        self.freeze_provisioned_layer()
+    }
+
+    /// Freeze the disk as it is now into the provisioned layer.
+    ///
+    /// Runs while the machine does: a disk-only external snapshot moves the
+    /// guest's writes to a new working file and leaves the file below it
+    /// read-only. [`Self::settle_provisioned_layer`] folds the frozen files
+    /// into the layer once the machine has stopped.
+    pub fn freeze_provisioned_layer(&self) -> Result<(), Error> {
+        let dom = self.running_domain()?;
+        let working = self
+            .layout
+            .work_dir
+            .join(format!("working-{}.qcow2", self.working_files().len() + 1));
+        let drives = self.system.resolve_drives()?;
+        let writable: Vec<&str> = drives
+            .iter()
+            .filter(|drive| !drive.readonly)
+            .map(|drive| drive.dev.as_str())
+            .collect();
+        let xml = domain::generate_root_snapshot_xml(&working, &writable);
+        let flags = virt::sys::VIR_DOMAIN_SNAPSHOT_CREATE_DISK_ONLY
+            | virt::sys::VIR_DOMAIN_SNAPSHOT_CREATE_NO_METADATA
+            | virt::sys::VIR_DOMAIN_SNAPSHOT_CREATE_ATOMIC;
+        DomainSnapshot::create_xml(&dom, &xml, flags).map_err(|e| Error::Libvirt {
+            message: format!("failed to freeze the provisioned layer: {e}"),
+            hint: "the hypervisor has to support external disk snapshots".into(),
+        })?;
+        tracing::info!(path = %working.display(), "froze provisioned layer");
+        crate::provision::copy_hashes(
+            &self.layout.provision_hashes,
+            &self.layout.provisioned_hashes,
+        )
+    }
+
+    /// The files [`Self::freeze_provisioned_layer`] stacked on the overlay,
+    /// oldest first. The last one takes the guest's writes.
+    fn working_files(&self) -> Vec<PathBuf> {
+        (1..)
+            .map(|n| self.layout.work_dir.join(format!("working-{n}.qcow2")))
+            .take_while(|path| path.exists())
+            .collect()
+    }
+
+    /// Fold the files frozen since the last stop into the provisioned layer.
+    ///
+    /// The overlay and every working file but the newest are merged into the
+    /// layer in order (the first freeze turns the overlay into the layer),
+    /// and the newest working file moves back to the overlay path. That
+    /// leaves base → provisioned layer → working layer under the original
+    /// overlay path, so the domain XML does not change. The domain must be
+    /// stopped.
+    pub(super) fn settle_provisioned_layer(&self) -> Result<(), Error> {
+        let working = self.working_files();
+        let Some((top, frozen)) = working.split_last() else {
+            return Ok(());
+        };
+        let layer = &self.layout.provisioned_layer;
+        let overlay = &self.layout.overlay_path;
+
+        for (i, file) in std::iter::once(overlay).chain(frozen).enumerate() {
+            // Its backing file was just merged into the layer.
+            if i > 0 {
+                qcow2::rebase_unsafe(file, layer)?;
+            }
+            if layer.exists() {
+                qcow2::commit(file)?;
+                std::fs::remove_file(file).map_err(|e| Error::Io {
+                    context: format!("removing {}", file.display()),
+                    source: e,
+                })?;
+            } else {
+                std::fs::rename(file, layer).map_err(|e| Error::Io {
+                    context: format!("moving {} to {}", file.display(), layer.display()),
+                    source: e,
+                })?;
+            }
+        }
+        qcow2::rebase_unsafe(top, layer)?;
+        std::fs::rename(top, overlay).map_err(|e| Error::Io {
+            context: format!("moving {} to {}", top.display(), overlay.display()),
+            source: e,
+        })?;
+
+        // The snapshot pointed the persistent definition at the working file.
+        if let Ok(xml) = std::fs::read_to_string(&self.layout.xml_path) {
+            self.define_domain(&self.connect()?, &xml)?;
+        }
+        tracing::info!(path = %layer.display(), "saved provisioned layer");
+        Ok(())
+    }
+
+    /// Discard the working layer and start over from the provisioned layer.
+    ///
+    /// Returns `false` when no provisioned layer exists yet, in which case
+    /// the caller has to fall back to a full destroy. The domain must be
+    /// stopped.
+    pub fn reset_working_layer(&self) -> Result<bool, Error> {
+        self.settle_provisioned_layer()?;
+        let layer = &self.layout.provisioned_layer;
+        if !layer.exists() {
+            return Ok(false);
+        }
+
+        if self.layout.overlay_path.exists() {
+            std::fs::remove_file(&self.layout.overlay_path).map_err(|e| Error::Io {
+                context: format!("removing {}", self.layout.overlay_path.display()),
+                source: e,
+            })?;
+        }
+        qcow2::create_qcow2_overlay(&self.layout.overlay_path, layer, None)?;
+        crate::sync::forget_bases(&self.layout.work_dir)?;
+        // Steps that ran after the freeze are gone with the working layer.
+        crate::provision::copy_hashes(
+            &self.layout.provisioned_hashes,
+            &self.layout.provision_hashes,
+        )?;
+        Ok(true)
+    }
+
+    /// Remember which system scripts ran, so unchanged ones are skipped next
+    /// time.
+    pub fn record_provisioned_scripts(&self, scripts: &[ProvisionScript]) -> Result<(), Error> {
+        crate::provision::record_hashes(&self.layout.provision_hashes, scripts)
+    }
+
+    /// Create the backing image for one extra drive.
+    ///
+    /// With `advanced.host_mkfs` and the `host-mkfs` feature, ext4/xfs drives
+    /// are formatted on the host; any failure falls back to an empty image
+    /// that the guest drive script formats on first boot.
+    pub(super) fn create_drive(
+        &self,
+        drive: &crate::config::ResolvedDrive,
+        filesystems: &[crate::config::ResolvedFs],
+    ) -> Result<(), Error> {
+        let sparse_qcow2 = drive.format == "qcow2" && drive.preallocation == "off";
+
+        #[cfg(feature = "host-mkfs")]
+        if self.system.config.advanced.host_mkfs && sparse_qcow2 {
+            let dev = format!("/dev/{}", drive.dev);
+            let filesystem = filesystems.iter().find_map(|fs| match fs {
+                crate::config::ResolvedFs::Simple(s) if s.dev == dev => Some(&s.filesystem),
+                _ => None,
+            });
+            if let Some(filesystem) = filesystem.filter(|f| crate::mkfs::supports(f)) {
+                match crate::mkfs::create_formatted_qcow2(&drive.path, &drive.size, filesystem) {
+                    Ok(()) => return Ok(()),
+                    Err(error) => tracing::warn!(
+                        drive = %drive.name,
+                        error = %error,
+                        "host-side mkfs failed, falling back to guest formatting"
+                    ),
+                }
+            }
+        }
+        #[cfg(not(feature = "host-mkfs"))]
+        {
+            let _ = filesystems;
+            if self.system.config.advanced.host_mkfs {
+                tracing::debug!("advanced.host_mkfs is set but rum was built without host-mkfs");
+            }
+        }
+
+        if sparse_qcow2 {
+            qcow2::create_qcow2(&drive.path, &drive.size)
+        } else {
+            qcow2::create_preallocated(
+                &drive.path,
+                &drive.size,
+                &drive.format,
+                &drive.preallocation,
+            )
+        }
+    }
+
+    /// Grow an existing image to `size` when the configured size increased.
+    ///
+    /// QEMU holds a write lock on images of a running domain, so growing is
+    /// deferred to the next cold start instead of failing `prepare`.
+    pub(super) fn grow_image(&self, path: &Path, format: &str, size: u64) -> Result<(), Error> {
+        if qcow2::virtual_size(path, format)? >= size {
+            return qcow2::grow(path, format, size).map(|_| ());
+        }
+        let running = self
+            .connect()
+            .ok()
+            .and_then(|conn| Domain::lookup_by_name(&conn, self.name()).ok())
+            .is_some_and(|dom| self.is_running(&dom));
+        if running {
+            tracing::warn!(
+                path = %path.display(),
+                "disk size increased; restart the VM to grow the image"
+            );
+            return Ok(());
+        }
+        qcow2::grow(path, format, size).map(|_| ())
+    }
+}
+
+pub(super) fn disk_tuning(io: &crate::config::DiskIoConfig) -> domain::DiskTuning {
+    domain::DiskTuning {
+        cache: io.cache.clone(),
+        io: io.io.clone(),
+        discard: io.discard,
+        iothread: io.iothread,
+    }
+}
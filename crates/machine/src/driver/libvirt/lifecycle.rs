@@ -0,0 +1,803 @@
+//! The libvirt driver itself: its identity, the [`Driver`] lifecycle of
+//! defining, booting, stopping and destroying the domain, and recovering
+//! the instance state from what libvirt and the work dir hold.
+//!
+//! The other modules of this directory add the operations a running
+//! machine offers on top of it, one domain each.
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use virt::connect::Connect;
+use virt::domain::Domain;
+use virt::error as virt_error;
+use virt::network::Network;
+
+use crate::config::{SystemConfig, UserConfig};
+use crate::driver::{Driver, RecoverableDriver};
+use crate::error::Error;
+use crate::guest::AgentEndpoints;
+use crate::instance::InstanceState;
+use crate::layout::MachineLayout;
+use crate::{cloudinit, image, live_mount, qcow2};
+
+use super::disks::disk_tuning;
+use super::mounts::domain_mounts;
+use super::network::network_in_use;
+use super::ssh::{collect_ssh_keys, ensure_ssh_keypair};
+
+/// Libvirt-backed runtime driver for one configured instance.
+///
+/// This type owns libvirt-facing operations and other backend-specific
+/// behavior. Recovery and state reconstruction live in the instance layer.
+#[derive(Clone)]
+pub struct LibvirtDriver {
+    pub(super) system: Arc<SystemConfig>,
+    pub(super) layout: MachineLayout,
+}
+
+impl LibvirtDriver {
+    /// Create a libvirt driver for one configured instance identity.
+    pub fn new(system: SystemConfig) -> Self {
+        let layout = MachineLayout::from_config(&system);
+        Self {
+            system: Arc::new(system),
+            layout,
+        }
+    }
+
+    /// Access the system config backing this driver.
+    pub fn system(&self) -> &SystemConfig {
+        &self.system
+    }
+
+    /// Access the derived artifact layout for this driver.
+    pub fn layout(&self) -> &MachineLayout {
+        &self.layout
+    }
+
+    /// Ensure the configured base image is available in the local cache.
+    pub async fn ensure_image(&self, cache_dir: &Path) -> Result<std::path::PathBuf, Error> {
+        image::ensure_base_image(&self.system.config.image, cache_dir).await
+    }
+
+    /// Set the memory and vCPU counts among `changes` on the running domain.
+    /// Returns the parts of the definition that only the persistent
+    /// definition picks up, so they wait for the next restart.
+    fn apply_live_changes(
+        &self,
+        changes: &[domain::DomainChange],
+    ) -> Result<Vec<&'static str>, Error> {
+        let (mut cpus, mut memory_mb) = (None, None);
+        let mut pending = Vec::new();
+        for change in changes {
+            match change {
+                domain::DomainChange::Vcpus { count } => cpus = Some(*count),
+                domain::DomainChange::Memory { mb } => memory_mb = Some(*mb),
+                domain::DomainChange::Metadata => pending.push("metadata"),
+                domain::DomainChange::Restart { part } => pending.push(*part),
+            }
+        }
+        if cpus.is_some() || memory_mb.is_some() {
+            self.resize(cpus, memory_mb)?;
+        }
+        Ok(pending)
+    }
+
+    /// Change online vCPUs and/or balloon memory of the running domain.
+    ///
+    /// Only the live domain changes; the next boot uses `rum.toml` again.
+    pub fn resize(&self, cpus: Option<u32>, memory_mb: Option<u64>) -> Result<(), Error> {
+        let resources = &self.system.config.resources;
+        if let Some(cpus) = cpus {
+            let max = resources.cpus_max.unwrap_or(resources.cpus);
+            if cpus == 0 || cpus > max {
+                return Err(Error::Validation {
+                    message: format!("cpus must be between 1 and {max} (resources.cpus_max)"),
+                });
+            }
+        }
+        if let Some(memory_mb) = memory_mb {
+            let max = resources.memory_max_mb.unwrap_or(resources.memory_mb);
+            if !(256..=max).contains(&memory_mb) {
+                return Err(Error::Validation {
+                    message: format!(
+                        "memory must be between 256 and {max} MiB (resources.memory_max_mb)"
+                    ),
+                });
+            }
+        }
+
+        let vm_name = self.name();
+        let conn = self.connect()?;
+        let dom = Domain::lookup_by_name(&conn, vm_name).map_err(|_| Error::DomainNotFound {
+            name: vm_name.to_string(),
+        })?;
+        if !self.is_running(&dom) {
+            return Err(Error::ExecNotReady {
+                name: vm_name.to_string(),
+                reason: "VM is not running".into(),
+            });
+        }
+
+        let live = virt::sys::VIR_DOMAIN_AFFECT_LIVE;
+        if let Some(cpus) = cpus {
+            dom.set_vcpus_flags(cpus, live)
+                .map_err(|e| Error::Libvirt {
+                    message: format!("failed to set vCPUs to {cpus}: {e}"),
+                    hint: "vCPUs can only be removed if the guest supports CPU unplug".into(),
+                })?;
+        }
+        if let Some(memory_mb) = memory_mb {
+            dom.set_memory_flags(memory_mb * 1024, live)
+                .map_err(|e| Error::Libvirt {
+                    message: format!("failed to set memory to {memory_mb} MiB: {e}"),
+                    hint: "the guest needs a virtio balloon driver".into(),
+                })?;
+        }
+        Ok(())
+    }
+
+    fn cpu_config(&self) -> domain::CpuConfig {
+        let resources = &self.system.config.resources;
+        if resources.nested && !host_nested_enabled() {
+            tracing::warn!(
+                "nested = true but the host KVM module has nesting disabled; \
+                 the guest will not see hardware virtualization"
+            );
+        }
+        domain::CpuConfig {
+            model: resources.effective_cpu_model().map(str::to_string),
+            topology: resources.topology(),
+        }
+    }
+
+    fn display_config(&self) -> Option<domain::DisplayConfig> {
+        let display = &self.system.config.display;
+        (display.protocol != "none").then(|| domain::DisplayConfig {
+            protocol: display.protocol.clone(),
+            listen: display.listen.clone(),
+            port: display.port,
+        })
+    }
+
+    /// `spice://` or `vnc://` URI of the running domain's graphical console.
+    ///
+    /// `None` when the machine is configured headless.
+    pub fn display_uri(&self) -> Result<Option<String>, Error> {
+        if self.display_config().is_none() {
+            return Ok(None);
+        }
+        let vm_name = self.name();
+        let conn = self.connect()?;
+        let dom = Domain::lookup_by_name(&conn, vm_name).map_err(|_| Error::DomainNotFound {
+            name: vm_name.to_string(),
+        })?;
+        if !self.is_running(&dom) {
+            return Err(Error::ExecNotReady {
+                name: vm_name.to_string(),
+                reason: "VM is not running".into(),
+            });
+        }
+        let xml = dom.get_xml_desc(0).map_err(|e| Error::Libvirt {
+            message: format!("failed to read domain XML: {e}"),
+            hint: "check libvirt permissions".into(),
+        })?;
+        Ok(domain::parse_display_uri(&xml))
+    }
+
+    /// UEFI firmware for the domain, with the nvram kept in the work dir so
+    /// it survives redefinition and is purged with the instance.
+    fn uefi_config(&self) -> Option<domain::UefiConfig> {
+        if !self.system.uefi() {
+            return None;
+        }
+        let firmware = crate::firmware::detect(self.system.guest_arch());
+        if firmware.is_none() {
+            tracing::debug!("no UEFI firmware found, relying on libvirt autoselection");
+        }
+        Some(domain::UefiConfig {
+            loader: firmware.as_ref().map(|f| f.code.clone()),
+            nvram: self.layout.nvram_path.clone(),
+            nvram_template: firmware.map(|f| f.vars),
+        })
+    }
+
+    pub(super) fn connect(&self) -> Result<Connect, Error> {
+        virt_error::clear_error_callback();
+
+        Connect::open(Some(self.system.libvirt_uri())).map_err(|e| Error::Libvirt {
+            message: format!("failed to connect to libvirt: {e}"),
+            hint: format!(
+                "ensure libvirtd is running and you have access to {}",
+                self.system.libvirt_uri()
+            ),
+        })
+    }
+
+    pub(super) fn define_domain(&self, conn: &Connect, xml: &str) -> Result<Domain, Error> {
+        Domain::define_xml(conn, xml).map_err(|e| Error::Libvirt {
+            message: format!("failed to define domain: {e}"),
+            hint: "check the generated domain XML for errors".into(),
+        })
+    }
+
+    pub(super) fn is_running(&self, dom: &Domain) -> bool {
+        dom.is_active().unwrap_or(false)
+    }
+
+    async fn shutdown_domain(&self, dom: &Domain) -> Result<(), Error> {
+        if !self.is_running(dom) {
+            return Ok(());
+        }
+        dom.shutdown().map_err(|e| Error::Libvirt {
+            message: format!("shutdown failed: {e}"),
+            hint: "VM may not support ACPI shutdown".into(),
+        })?;
+
+        // `shutdown_s = 0` waits for the guest however long it takes.
+        let shutdown_s = self.system.config.timeouts.shutdown_s;
+        let mut waited = 0;
+        while shutdown_s == 0 || waited < shutdown_s {
+            if !self.is_running(dom) {
+                return Ok(());
+            }
+            tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+            waited += 1;
+        }
+
+        dom.destroy().map_err(|e| Error::Libvirt {
+            message: format!("force stop failed: {e}"),
+            hint: "check libvirt permissions".into(),
+        })?;
+        Ok(())
+    }
+
+    pub(super) fn running_domain(&self) -> Result<Domain, Error> {
+        let vm_name = self.name();
+        let conn = self.connect()?;
+
+        let dom = Domain::lookup_by_name(&conn, vm_name).map_err(|_| Error::DomainNotFound {
+            name: vm_name.to_string(),
+        })?;
+
+        if !self.is_running(&dom) {
+            return Err(Error::ExecNotReady {
+                name: vm_name.to_string(),
+                reason: "VM is not running".into(),
+            });
+        }
+        Ok(dom)
+    }
+}
+
+/// One rum-managed domain found on a libvirt host.
+#[derive(Debug, Clone)]
+pub struct DomainSummary {
+    pub name: String,
+    pub id: String,
+    pub running: bool,
+    pub labels: std::collections::BTreeMap<String, String>,
+}
+
+/// List every domain on `libvirt_uri` that carries rum instance metadata.
+///
+/// Domains defined by other tools are skipped.
+pub fn list_domains(libvirt_uri: &str) -> Result<Vec<DomainSummary>, Error> {
+    virt_error::clear_error_callback();
+
+    let conn = Connect::open(Some(libvirt_uri)).map_err(|e| Error::Libvirt {
+        message: format!("failed to connect to libvirt: {e}"),
+        hint: format!("ensure libvirtd is running and you have access to {libvirt_uri}"),
+    })?;
+    let domains = conn.list_all_domains(0).map_err(|e| Error::Libvirt {
+        message: format!("failed to list domains: {e}"),
+        hint: "check libvirt permissions".into(),
+    })?;
+
+    let mut summaries: Vec<DomainSummary> = domains
+        .iter()
+        .filter_map(|dom| {
+            let xml = dom.get_xml_desc(0).ok()?;
+            let metadata = domain::parse_instance_metadata(&xml)?;
+            Some(DomainSummary {
+                name: dom.get_name().ok()?,
+                id: metadata.id,
+                running: dom.is_active().unwrap_or(false),
+                labels: metadata.labels,
+            })
+        })
+        .collect();
+    summaries.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(summaries)
+}
+
+#[async_trait]
+impl Driver for LibvirtDriver {
+    type Error = Error;
+
+    fn id(&self) -> &str {
+        &self.system.id
+    }
+
+    fn name(&self) -> &str {
+        self.system.display_name()
+    }
+
+    async fn prepare(&self, base_image: &Path) -> Result<(), Error> {
+        let config = &self.system.config;
+
+        let mounts = self.system.resolve_mounts()?;
+        let drives = self.system.resolve_drives()?;
+
+        ensure_ssh_keypair(&self.layout.ssh_key_path).await?;
+        let ssh_keys =
+            collect_ssh_keys(&self.layout.ssh_key_path, &config.ssh.authorized_keys).await?;
+        let provision_files = self.system.provision_file_contents()?;
+        let users: Vec<UserConfig> = self.system.users().cloned().collect();
+        let nics = self.guest_nics();
+        let dotfiles = self.system.dotfile_contents()?;
+        let agent = crate::guest::agent_binary(self.system.guest_arch())?;
+
+        let seed_config = cloudinit::SeedConfig {
+            hostname: self.system.hostname(),
+            users: &users,
+            dotfiles: &dotfiles,
+            guest: &config.guest,
+            nics: &nics,
+            mounts: &mounts,
+            autologin: config.advanced.autologin,
+            ssh_keys: &ssh_keys,
+            agent_binary: Some(&agent),
+            provision_files: &provision_files,
+        };
+        let seed_hash = cloudinit::seed_hash(&seed_config);
+        let seed_path = self.layout.seed_path(&seed_hash);
+
+        let disk_size = crate::util::parse_size(&config.resources.disk)?;
+
+        // A guest that powered itself off never went through `shutdown`.
+        let running = self
+            .connect()
+            .ok()
+            .and_then(|conn| Domain::lookup_by_name(&conn, self.name()).ok())
+            .is_some_and(|dom| self.is_running(&dom));
+        if !running {
+            self.settle_provisioned_layer()?;
+        }
+
+        if !self.layout.overlay_path.exists() {
+            qcow2::create_qcow2_overlay(&self.layout.overlay_path, base_image, Some(disk_size))?;
+            crate::sync::forget_bases(&self.layout.work_dir)?;
+        } else {
+            self.grow_image(&self.layout.overlay_path, "qcow2", disk_size)?;
+        }
+        let filesystems = self.system.resolve_fs(&drives)?;
+        for drive in &drives {
+            if drive.external && !drive.path.exists() {
+                return Err(Error::Validation {
+                    message: format!(
+                        "drive '{}': {} does not exist",
+                        drive.name,
+                        drive.path.display()
+                    ),
+                });
+            }
+            if !drive.path.exists() {
+                self.create_drive(drive, &filesystems)?;
+            } else if !drive.external {
+                let size = crate::util::parse_size(&drive.size)?;
+                self.grow_image(&drive.path, &drive.format, size)?;
+            }
+        }
+
+        for cdrom in &config.cdroms {
+            if !Path::new(&cdrom.path).is_file() {
+                return Err(Error::Validation {
+                    message: format!("cdrom {} does not exist", cdrom.path),
+                });
+            }
+        }
+
+        if !seed_path.exists() {
+            if let Ok(mut entries) = tokio::fs::read_dir(&self.layout.work_dir).await {
+                while let Ok(Some(entry)) = entries.next_entry().await {
+                    let file_name = entry.file_name();
+                    if let Some(name) = file_name.to_str()
+                        && name.starts_with("seed-")
+                        && name.ends_with(".iso")
+                    {
+                        let _ = tokio::fs::remove_file(entry.path()).await;
+                    }
+                }
+            }
+            cloudinit::generate_seed_iso(&seed_path, &seed_config).await?;
+        }
+
+        let domain_config = domain::DomainConfig {
+            id: self.system.id.clone(),
+            name: self.name().to_string(),
+            domain_type: self.system.domain_type().to_string(),
+            machine: self.system.machine_type().to_string(),
+            arch: self.system.guest_arch().to_string(),
+            uefi: self.uefi_config(),
+            display: self.display_config(),
+            memory_mb: config.resources.memory_mb,
+            memory_max_mb: config.resources.memory_max_mb,
+            cpus: config.resources.cpus,
+            cpus_max: config.resources.cpus_max,
+            cpu: self.cpu_config(),
+            root_disk: disk_tuning(&config.root_disk),
+            nat: config.network.nat,
+            nat_mac: self.nat_mac(),
+            interfaces: config
+                .network
+                .interfaces
+                .iter()
+                .map(|iface| domain::InterfaceConfig {
+                    network: iface.network.clone(),
+                    shared: !iface.subnet.is_empty(),
+                })
+                .collect(),
+            cdroms: config
+                .cdroms
+                .iter()
+                .map(|c| PathBuf::from(&c.path))
+                .collect(),
+            labels: config.metadata.labels.clone(),
+            work_dir: Some(self.layout.work_dir.clone()),
+            profile: self.system.profile.clone(),
+            agent_channel: Some(self.layout.agent_channel.clone()),
+            vsock: crate::guest::host_has_vsock(),
+        };
+        let domain_mounts = domain_mounts(&mounts);
+        let domain_drives: Vec<domain::ResolvedDrive> = drives
+            .iter()
+            .map(|drive| domain::ResolvedDrive {
+                path: drive.path.clone(),
+                dev: drive.dev.clone(),
+                block: drive.block,
+                readonly: drive.readonly,
+                format: drive.format.clone(),
+                tuning: disk_tuning(&drive.io),
+            })
+            .collect();
+
+        let xml = domain::generate_domain_xml(
+            &domain_config,
+            &self.layout.overlay_path,
+            &seed_path,
+            &domain_mounts,
+            &domain_drives,
+        );
+        let conn = self.connect()?;
+
+        match Domain::lookup_by_name(&conn, self.name()) {
+            Ok(dom) => {
+                let changes = domain::xml_changes(
+                    &domain_config,
+                    &self.layout.overlay_path,
+                    &seed_path,
+                    &domain_mounts,
+                    &domain_drives,
+                    &self.layout.xml_path,
+                );
+                if !changes.is_empty() && self.is_running(&dom) {
+                    if !changes.iter().all(domain::DomainChange::is_live) {
+                        return Err(Error::RequiresRestart {
+                            name: self.name().to_string(),
+                        });
+                    }
+                    // Defining a running domain only replaces its next-boot
+                    // definition; memory and vCPUs are also set live.
+                    let pending = self.apply_live_changes(&changes)?;
+                    self.define_domain(&conn, &xml)?;
+                    if pending.is_empty() {
+                        tracing::info!(vm_name = self.name(), "domain updated without a restart");
+                    } else {
+                        tracing::warn!(
+                            vm_name = self.name(),
+                            "domain definition updated; applied after `rum restart`: {}",
+                            pending.join(", ")
+                        );
+                    }
+                } else if !changes.is_empty() {
+                    dom.undefine_flags(virt::sys::VIR_DOMAIN_UNDEFINE_KEEP_NVRAM)
+                        .map_err(|e| Error::Libvirt {
+                            message: format!("failed to undefine domain: {e}"),
+                            hint: "check libvirt permissions".into(),
+                        })?;
+                    self.define_domain(&conn, &xml)?;
+                    tracing::info!(
+                        vm_name = self.name(),
+                        "domain redefined with updated config"
+                    );
+                }
+            }
+            Err(_) => {
+                self.define_domain(&conn, &xml)?;
+                tracing::info!(vm_name = self.name(), "domain defined");
+            }
+        }
+
+        tokio::fs::write(&self.layout.xml_path, &xml)
+            .await
+            .map_err(|e| Error::Io {
+                context: format!("saving domain XML to {}", self.layout.xml_path.display()),
+                source: e,
+            })?;
+
+        tokio::fs::write(
+            &self.layout.config_path_file,
+            self.system.config_path.to_string_lossy().as_bytes(),
+        )
+        .await
+        .map_err(|e| Error::Io {
+            context: format!(
+                "saving config path to {}",
+                self.layout.config_path_file.display()
+            ),
+            source: e,
+        })?;
+        crate::config::record_applied(&self.layout.applied_config, &self.system.config)?;
+
+        self.ensure_networks(&conn)?;
+        Ok(())
+    }
+
+    async fn boot(&self) -> Result<AgentEndpoints, Error> {
+        let conn = self.connect()?;
+
+        // The guest agent starts over, so the transport is negotiated anew.
+        let _ = std::fs::remove_file(&self.layout.agent_transport);
+
+        let dom = Domain::lookup_by_name(&conn, self.name()).map_err(|e| Error::Libvirt {
+            message: format!("domain lookup failed: {e}"),
+            hint: "domain should have been defined in prepare".into(),
+        })?;
+
+        if !self.is_running(&dom) {
+            dom.create().map_err(|e| Error::Libvirt {
+                message: format!("failed to start domain: {e}"),
+                hint: "check `virsh -c qemu:///system start` for details".into(),
+            })?;
+            tracing::info!(vm_name = self.name(), "VM started");
+        }
+
+        let endpoints = self.agent_endpoints_of(&dom);
+        if endpoints == AgentEndpoints::default() {
+            return Err(Error::Libvirt {
+                message: "live XML has neither a vsock CID nor an agent channel".into(),
+                hint: "redefine the domain with `rum up`".into(),
+            });
+        }
+        // Connections to the agent of a previous boot are gone.
+        for connector in endpoints.connectors() {
+            crate::guest::forget_agent_client(&connector);
+        }
+        Ok(endpoints)
+    }
+
+    async fn shutdown(&self) -> Result<(), Error> {
+        let conn = self.connect()?;
+
+        let dom = Domain::lookup_by_name(&conn, self.name()).map_err(|e| Error::Libvirt {
+            message: format!("domain lookup failed: {e}"),
+            hint: "VM may not be defined".into(),
+        })?;
+
+        self.close_ssh_master().await;
+        self.shutdown_domain(&dom).await?;
+        self.settle_provisioned_layer()
+    }
+
+    async fn destroy(&self) -> Result<(), Error> {
+        let config = &self.system.config;
+        virt_error::clear_error_callback();
+        self.close_ssh_master().await;
+
+        if let Ok(conn) = self.connect() {
+            if let Ok(dom) = Domain::lookup_by_name(&conn, self.name()) {
+                if dom.is_active().unwrap_or(false) {
+                    let _ = dom.destroy();
+                }
+                let _ = dom.undefine_flags(virt::sys::VIR_DOMAIN_UNDEFINE_NVRAM);
+            }
+
+            for iface in &config.network.interfaces {
+                let net_name = if iface.subnet.is_empty() {
+                    domain::prefixed_name(&self.system.id, &iface.network)
+                } else {
+                    // Shared networks go with their last machine.
+                    let net_name = domain::shared_name(&iface.network);
+                    if network_in_use(&conn, &net_name) {
+                        continue;
+                    }
+                    net_name
+                };
+                if let Ok(net) = Network::lookup_by_name(&conn, &net_name) {
+                    if net.is_active().unwrap_or(false) {
+                        let _ = net.destroy();
+                    }
+                    let _ = net.undefine();
+                }
+            }
+        }
+
+        // Live mounts belong to the domain just undefined; a recreated
+        // machine must not inherit them even if removing the work dir fails.
+        live_mount::clear(&self.layout.live_mounts)?;
+
+        let ssh_config = crate::paths::user_ssh_config_path();
+        if let Err(error) = crate::ssh_config::uninstall(&ssh_config, &self.system.id) {
+            tracing::warn!(%error, "failed to remove the machine from the ssh config");
+        }
+
+        // The audit log outlives the machine, so `rum history` still shows
+        // who destroyed it.
+        if self.layout.work_dir.exists() {
+            let work_dir = &self.layout.work_dir;
+            let audit_log =
+                crate::paths::audit_log_path(&self.system.id, self.system.name.as_deref());
+            let removing = |e| Error::Io {
+                context: format!("removing {}", work_dir.display()),
+                source: e,
+            };
+            let mut entries = tokio::fs::read_dir(work_dir).await.map_err(removing)?;
+            while let Some(entry) = entries.next_entry().await.map_err(removing)? {
+                let path = entry.path();
+                if path == audit_log {
+                    continue;
+                }
+                let removed = if entry.file_type().await.map_err(removing)?.is_dir() {
+                    tokio::fs::remove_dir_all(&path).await
+                } else {
+                    tokio::fs::remove_file(&path).await
+                };
+                removed.map_err(removing)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl RecoverableDriver for LibvirtDriver {
+    fn recover(&self) -> Result<InstanceState, Error> {
+        let config = &self.system.config;
+        let mounts = self.system.resolve_mounts()?;
+        let drives = self.system.resolve_drives()?;
+
+        let ssh_keys = if self.layout.ssh_key_path.with_extension("pub").exists() {
+            std::fs::read_to_string(self.layout.ssh_key_path.with_extension("pub"))
+                .map(|k| vec![k.trim().to_string()])
+                .unwrap_or_default()
+        } else {
+            Vec::new()
+        };
+        let provision_files = self.system.provision_file_contents().unwrap_or_default();
+        let users: Vec<UserConfig> = self.system.users().cloned().collect();
+        let nics = self.guest_nics();
+        let dotfiles = self.system.dotfile_contents().unwrap_or_default();
+        let agent = crate::guest::agent_binary(self.system.guest_arch()).ok();
+
+        let seed_config = cloudinit::SeedConfig {
+            hostname: self.system.hostname(),
+            users: &users,
+            dotfiles: &dotfiles,
+            guest: &config.guest,
+            nics: &nics,
+            mounts: &mounts,
+            autologin: config.advanced.autologin,
+            ssh_keys: &ssh_keys,
+            agent_binary: agent.as_deref(),
+            provision_files: &provision_files,
+        };
+        let seed_hash = cloudinit::seed_hash(&seed_config);
+        let seed_path = self.layout.seed_path(&seed_hash);
+
+        let domain_config = domain::DomainConfig {
+            id: self.system.id.clone(),
+            name: self.system.display_name().to_string(),
+            domain_type: self.system.domain_type().to_string(),
+            machine: self.system.machine_type().to_string(),
+            arch: self.system.guest_arch().to_string(),
+            uefi: self.uefi_config(),
+            display: self.display_config(),
+            memory_mb: config.resources.memory_mb,
+            memory_max_mb: config.resources.memory_max_mb,
+            cpus: config.resources.cpus,
+            cpus_max: config.resources.cpus_max,
+            cpu: self.cpu_config(),
+            root_disk: disk_tuning(&config.root_disk),
+            nat: config.network.nat,
+            nat_mac: self.nat_mac(),
+            interfaces: config
+                .network
+                .interfaces
+                .iter()
+                .map(|iface| domain::InterfaceConfig {
+                    network: iface.network.clone(),
+                    shared: !iface.subnet.is_empty(),
+                })
+                .collect(),
+            cdroms: config
+                .cdroms
+                .iter()
+                .map(|c| PathBuf::from(&c.path))
+                .collect(),
+            labels: config.metadata.labels.clone(),
+            work_dir: Some(self.layout.work_dir.clone()),
+            profile: self.system.profile.clone(),
+            agent_channel: Some(self.layout.agent_channel.clone()),
+            vsock: crate::guest::host_has_vsock(),
+        };
+        let domain_mounts = domain_mounts(&mounts);
+        let domain_drives: Vec<domain::ResolvedDrive> = drives
+            .iter()
+            .map(|drive| domain::ResolvedDrive {
+                path: drive.path.clone(),
+                dev: drive.dev.clone(),
+                block: drive.block,
+                readonly: drive.readonly,
+                format: drive.format.clone(),
+                tuning: disk_tuning(&drive.io),
+            })
+            .collect();
+
+        let conn = self.connect()?;
+        let domain = Domain::lookup_by_name(&conn, self.name()).ok();
+        let running = domain
+            .as_ref()
+            .is_some_and(|dom| dom.is_active().unwrap_or(false));
+
+        // Changes that apply live leave a running domain usable.
+        let stale = running
+            && !domain::xml_changes(
+                &domain_config,
+                &self.layout.overlay_path,
+                &seed_path,
+                &domain_mounts,
+                &domain_drives,
+                &self.layout.xml_path,
+            )
+            .iter()
+            .all(domain::DomainChange::is_live);
+
+        let overlay_exists = self.layout.overlay_path.exists();
+        let marker_exists = self.layout.provisioned_marker.exists();
+        let image_cached = image::is_cached(&config.image.base, &crate::paths::cache_dir());
+
+        let state = match (
+            running,
+            stale,
+            overlay_exists,
+            marker_exists,
+            domain.is_some(),
+            image_cached,
+        ) {
+            (true, true, _, _, _, _) => InstanceState::StaleConfig,
+            (true, false, _, _, _, _) => InstanceState::Running,
+            (false, _, true, true, _, _) => InstanceState::Stopped,
+            (false, _, true, false, true, _) => InstanceState::PartialBoot,
+            (false, _, true, false, false, _) => InstanceState::Prepared,
+            (false, _, false, _, _, true) => InstanceState::ImageCached,
+            (false, _, false, _, _, false) => InstanceState::Missing,
+        };
+
+        Ok(state)
+    }
+}
+
+/// Whether the host's KVM module allows nested guests. Unknown (no KVM
+/// module loaded, non-x86 host) counts as enabled to avoid false warnings.
+fn host_nested_enabled() -> bool {
+    ["kvm_intel", "kvm_amd"]
+        .iter()
+        .filter_map(|module| {
+            std::fs::read_to_string(format!("/sys/module/{module}/parameters/nested")).ok()
+        })
+        .next()
+        .is_none_or(|value| matches!(value.trim(), "Y" | "y" | "1"))
+}
@@ -0,0 +1,10 @@
+mod agent;
+mod disks;
+mod lifecycle;
+mod mounts;
+mod network;
+mod peers;
+mod ssh;
+
+pub use lifecycle::{DomainSummary, LibvirtDriver, list_domains};
+pub use network::InterfaceExplain;
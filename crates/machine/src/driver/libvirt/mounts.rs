@@ -0,0 +1,157 @@
+//! Adding and removing shares while the machine runs.
+
+use virt::domain::Domain;
+
+use crate::config::{MountConfig, MountDriver, ResolvedMount};
+use crate::error::Error;
+use crate::{cloudinit, live_mount};
+
+use super::lifecycle::LibvirtDriver;
+
+impl LibvirtDriver {
+    /// Hot-plug a virtiofs share into the running domain and mount it in the
+    /// guest. The share is added to the persistent definition as well.
+    pub async fn attach_mount(&self, mount: &ResolvedMount) -> Result<(), Error> {
+        if mount.driver != MountDriver::Virtiofs {
+            return Err(Error::Validation {
+                message: format!(
+                    "{} uses {}; only virtiofs shares can be added to a running machine",
+                    mount.target,
+                    mount.driver.label()
+                ),
+            });
+        }
+        let dom = self.running_domain()?;
+        if !has_share(&dom, &mount.tag) {
+            let xml = domain::generate_filesystem_xml(&domain_mount(mount));
+            let flags = virt::sys::VIR_DOMAIN_AFFECT_LIVE | virt::sys::VIR_DOMAIN_AFFECT_CONFIG;
+            dom.attach_device_flags(&xml, flags)
+                .map_err(|e| Error::Libvirt {
+                    message: format!("failed to attach share '{}': {e}", mount.tag),
+                    hint: "virtiofs hotplug needs a libvirt that supports it and a machine that \
+                           booted with at least one mount; restart the machine instead"
+                        .into(),
+                })?;
+        }
+
+        let target = cloudinit::sh_quote(&mount.target);
+        let options = if mount.readonly { "-o ro " } else { "" };
+        self.guest_shell(format!(
+            "mountpoint -q {target} || \
+             {{ mkdir -p {target} && mount -t virtiofs {options}{} {target}; }}",
+            cloudinit::sh_quote(&mount.tag)
+        ))
+        .await
+    }
+
+    /// Share a host directory with the running machine and record it, so
+    /// later boots keep it.
+    pub async fn mount_live(&self, mount: MountConfig) -> Result<ResolvedMount, Error> {
+        if self
+            .system
+            .config
+            .mounts
+            .iter()
+            .any(|m| m.target == mount.target)
+        {
+            return Err(Error::Validation {
+                message: format!("{} is already a mount target", mount.target),
+            });
+        }
+        let mut system = (*self.system).clone();
+        system.config.mounts.push(mount.clone());
+        let resolved = system
+            .resolve_mounts()?
+            .into_iter()
+            .find(|m| m.target == mount.target)
+            .expect("the new mount is resolved with the others");
+
+        self.attach_mount(&resolved).await?;
+        live_mount::record(&self.layout.live_mounts, mount)?;
+        Ok(resolved)
+    }
+
+    /// Unplug the share at guest `target` from the running machine.
+    ///
+    /// Returns whether it was added with [`Self::mount_live`]; shares from
+    /// the config file come back on the next boot.
+    pub async fn umount_live(&self, target: &str) -> Result<bool, Error> {
+        let mount = self
+            .system
+            .resolve_mounts()?
+            .into_iter()
+            .find(|m| m.target == target)
+            .ok_or_else(|| Error::Validation {
+                message: format!("no share is mounted at {target}"),
+            })?;
+        self.detach_mount(&mount).await?;
+        live_mount::forget(&self.layout.live_mounts, target)
+    }
+
+    /// Unmount a share in the guest and unplug it from the domain. Shares
+    /// without a domain device are only unmounted.
+    pub async fn detach_mount(&self, mount: &ResolvedMount) -> Result<(), Error> {
+        let dom = self.running_domain()?;
+        self.guest_shell(format!(
+            "! mountpoint -q {target} || umount {target}",
+            target = cloudinit::sh_quote(&mount.target)
+        ))
+        .await?;
+        if mount.driver.fs_options().is_none() || !has_share(&dom, &mount.tag) {
+            return Ok(());
+        }
+
+        let xml = domain::generate_filesystem_xml(&domain_mount(mount));
+        let flags = virt::sys::VIR_DOMAIN_AFFECT_LIVE | virt::sys::VIR_DOMAIN_AFFECT_CONFIG;
+        dom.detach_device_flags(&xml, flags)
+            .map_err(|e| Error::Libvirt {
+                message: format!("failed to detach share '{}': {e}", mount.tag),
+                hint: "restart the machine to drop the share".into(),
+            })?;
+        Ok(())
+    }
+
+    /// Run `command` as root in the guest, failing on a non-zero exit.
+    async fn guest_shell(&self, command: String) -> Result<(), Error> {
+        let client = crate::guest::agent_client(self.agent_connector()?);
+        let code = client
+            .exec_with_output(command.clone(), Default::default(), |event| {
+                tracing::debug!(message = %event.message, "guest command output");
+            })
+            .await
+            .map_err(|e| Error::Daemon {
+                message: format!("`{command}` failed in the guest: {e}"),
+            })?;
+        if code != 0 {
+            return Err(Error::Daemon {
+                message: format!("`{command}` exited with {code} in the guest"),
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Whether the live definition of `dom` already has the share tagged `tag`.
+fn has_share(dom: &Domain, tag: &str) -> bool {
+    dom.get_xml_desc(0)
+        .is_ok_and(|xml| domain::parse_filesystem_tags(&xml).iter().any(|t| t == tag))
+}
+
+/// Mounts that are backed by a domain `<filesystem>` device.
+pub(super) fn domain_mounts(mounts: &[ResolvedMount]) -> Vec<domain::ResolvedMount> {
+    mounts
+        .iter()
+        .filter(|m| m.driver.fs_options().is_some())
+        .map(domain_mount)
+        .collect()
+}
+
+fn domain_mount(mount: &ResolvedMount) -> domain::ResolvedMount {
+    domain::ResolvedMount {
+        source: mount.source.clone(),
+        target: mount.target.clone(),
+        readonly: mount.readonly,
+        tag: mount.tag.clone(),
+        nine_p: mount.driver == MountDriver::NineP,
+    }
+}
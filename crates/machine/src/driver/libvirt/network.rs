@@ -0,0 +1,596 @@
+//! Libvirt networks of the machine and the addresses its NICs get on them.
+
+use virt::connect::Connect;
+use virt::domain::Domain;
+use virt::network::Network;
+
+use crate::config::{Config, InterfaceConfig};
+use crate::driver::Driver;
+use crate::error::Error;
+use crate::{cloudinit, util};
+
+use super::lifecycle::LibvirtDriver;
+
+impl LibvirtDriver {
+    /// Wait until the guest leased the address `rum ssh` connects to, for
+    /// at most `network.ip_wait_timeout_s`. Static and bridged addresses
+    /// have no lease to wait for, so only `rum ssh` looks for them.
+    pub async fn wait_for_ip(&self) -> Result<(), Error> {
+        let network = &self.system.config.network;
+        if !network.wait_for_ip {
+            return Ok(());
+        }
+        if !ssh_address_is_leased(&self.system.config) {
+            tracing::debug!("the ssh interface gets no DHCP lease; not waiting for its IP");
+            return Ok(());
+        }
+
+        let conn = self.connect()?;
+        let dom =
+            Domain::lookup_by_name(&conn, self.name()).map_err(|_| Error::DomainNotFound {
+                name: self.name().to_string(),
+            })?;
+        let macs = self.interface_macs(&dom, &self.system.config.ssh.interface);
+        let lease_src = virt::sys::VIR_DOMAIN_INTERFACE_ADDRESSES_SRC_LEASE;
+        let timeout_s = network.ip_wait_timeout_s;
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(timeout_s);
+        loop {
+            let leased = self.libvirt_addresses(&dom, lease_src, &macs);
+            if let Some(ip) = util::pick_routable(leased.iter().map(String::as_str), false) {
+                tracing::debug!(%ip, "guest has an IP");
+                return Ok(());
+            }
+            if std::time::Instant::now() >= deadline {
+                return Err(Error::IpTimeout {
+                    name: self.name().to_string(),
+                    timeout_s,
+                });
+            }
+            tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+        }
+    }
+
+    /// Guest IP address as `rum ssh` resolves it, or on the NIC attached to
+    /// `interface` when given. With `ipv6` only IPv6 addresses are returned.
+    pub async fn guest_ip(&self, interface: Option<&str>, ipv6: bool) -> Result<String, Error> {
+        let vm_name = self.name();
+        let interfaces = &self.system.config.network.interfaces;
+        if let Some(network) = interface
+            && !interfaces.iter().any(|i| i.network == network)
+        {
+            return Err(Error::Validation {
+                message: format!("no [[network.interfaces]] entry for network '{network}'"),
+            });
+        }
+        let conn = self.connect()?;
+        let dom = Domain::lookup_by_name(&conn, vm_name).map_err(|_| Error::DomainNotFound {
+            name: vm_name.to_string(),
+        })?;
+        if !self.is_running(&dom) {
+            return Err(Error::SshNotReady {
+                name: vm_name.to_string(),
+                reason: "VM is not running".into(),
+            });
+        }
+        match interface {
+            Some(network) => self.interface_ip(&dom, network, ipv6).await,
+            None => self.get_vm_ip(&dom, ipv6).await,
+        }
+    }
+
+    fn ensure_network_active(&self, conn: &Connect, name: &str) -> Result<Network, Error> {
+        let net = Network::lookup_by_name(conn, name).map_err(|_| Error::Libvirt {
+            message: format!("network '{name}' not found"),
+            hint: format!(
+                "define the network with `virsh net-define` and `virsh net-start {name}`"
+            ),
+        })?;
+
+        if !net.is_active().unwrap_or(false) {
+            tracing::info!(name, "starting inactive network");
+            net.create().map_err(|e| Error::Libvirt {
+                message: format!("failed to start network '{name}': {e}"),
+                hint: format!("try `sudo virsh net-start {name}`"),
+            })?;
+        }
+
+        Ok(net)
+    }
+
+    fn ensure_extra_network(
+        &self,
+        conn: &Connect,
+        name: &str,
+        iface: &InterfaceConfig,
+    ) -> Result<Network, Error> {
+        match Network::lookup_by_name(conn, name) {
+            Ok(net) => {
+                if !net.is_active().unwrap_or(false) {
+                    tracing::info!(name, "starting inactive network");
+                    net.create().map_err(|e| Error::Libvirt {
+                        message: format!("failed to start network '{name}': {e}"),
+                        hint: "check libvirt permissions".into(),
+                    })?;
+                }
+                Ok(net)
+            }
+            Err(_) => {
+                let existing = existing_subnets(conn)?;
+                let subnet = match iface.subnet_prefix() {
+                    Some(subnet) => match domain::subnet_collision(name, subnet, &existing) {
+                        Some(conflict) => Err(conflict.to_string()),
+                        None => Ok(subnet.to_string()),
+                    },
+                    None => domain::derive_free_subnet(name, iface.ipv4_hint(), &existing),
+                }
+                .map_err(|conflict| Error::SubnetCollision {
+                    network: name.to_string(),
+                    subnet: iface
+                        .subnet_prefix()
+                        .map(str::to_string)
+                        .unwrap_or_else(|| domain::derive_subnet(name, iface.ipv4_hint())),
+                    conflict,
+                })?;
+                let xml =
+                    domain::generate_network_xml(name, &subnet, &network_options(name, iface));
+                tracing::info!(name, subnet, "auto-creating host-only network");
+                let net = Network::define_xml(conn, &xml).map_err(|e| Error::Libvirt {
+                    message: format!("failed to define network '{name}': {e}"),
+                    hint: "check libvirt permissions".into(),
+                })?;
+                net.create().map_err(|e| Error::Libvirt {
+                    message: format!("failed to start network '{name}': {e}"),
+                    hint: "check libvirt permissions".into(),
+                })?;
+                Ok(net)
+            }
+        }
+    }
+
+    fn add_dhcp_reservation(
+        &self,
+        net: &Network,
+        net_name: &str,
+        mac: &str,
+        ip: &str,
+        hostname: &str,
+    ) -> Result<(), Error> {
+        // DHCPv6 identifies clients by DUID rather than MAC, so IPv6
+        // reservations go by hostname into the network's IPv6 `<ip>` element.
+        let ipv6 = ip.contains(':');
+        let host_xml = if ipv6 {
+            format!("<host name='{hostname}' ip='{ip}'/>")
+        } else {
+            format!("<host mac='{mac}' name='{hostname}' ip='{ip}'/>")
+        };
+        let family = if ipv6 { "IPv6" } else { "IPv4" };
+        let ip_index = net
+            .get_xml_desc(0)
+            .ok()
+            .and_then(|xml| domain::parse_ip_index(&xml, ipv6))
+            .ok_or_else(|| Error::Libvirt {
+                message: format!("network '{net_name}' has no {family} range for {ip}"),
+                hint: format!("enable {family} on network '{net_name}' or pick another address"),
+            })?;
+
+        let modify = virt::sys::VIR_NETWORK_UPDATE_COMMAND_ADD_LAST;
+        let section = virt::sys::VIR_NETWORK_SECTION_IP_DHCP_HOST;
+        let flags =
+            virt::sys::VIR_NETWORK_UPDATE_AFFECT_LIVE | virt::sys::VIR_NETWORK_UPDATE_AFFECT_CONFIG;
+
+        match net.update(modify, section, ip_index, &host_xml, flags) {
+            Ok(_) => {
+                tracing::info!(net_name, mac, ip, "added DHCP reservation");
+            }
+            Err(e) => {
+                let modify_cmd = virt::sys::VIR_NETWORK_UPDATE_COMMAND_MODIFY;
+                net.update(modify_cmd, section, ip_index, &host_xml, flags)
+                    .map_err(|e2| Error::Libvirt {
+                        message: format!(
+                            "failed to set DHCP reservation in '{net_name}': add={e}, modify={e2}"
+                        ),
+                        hint: format!("ensure network '{net_name}' has a DHCP range configured"),
+                    })?;
+                tracing::info!(net_name, mac, ip, "updated DHCP reservation");
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Report every derived networking value for the current config without
+    /// changing host state.
+    pub fn explain_networks(&self) -> Result<Vec<InterfaceExplain>, Error> {
+        let conn = self.connect()?;
+        let existing = existing_subnets(&conn)?;
+
+        let explained = self
+            .system
+            .config
+            .network
+            .interfaces
+            .iter()
+            .enumerate()
+            .map(|(i, iface)| {
+                let libvirt_name = self.system.network_name(iface);
+                let defined = existing
+                    .iter()
+                    .find(|(name, _)| *name == libvirt_name)
+                    .map(|(_, subnet)| subnet.clone());
+                let (subnet, subnet_source) = match (defined, iface.subnet_prefix()) {
+                    (Some(subnet), _) => (subnet, "defined"),
+                    (None, Some(subnet)) => (subnet.to_string(), "configured"),
+                    (None, None) if !iface.ipv4_hint().is_empty() => (
+                        domain::derive_subnet(&libvirt_name, iface.ipv4_hint()),
+                        "ip hint",
+                    ),
+                    (None, None) => (domain::derive_subnet(&libvirt_name, ""), "derived"),
+                };
+                let conflict =
+                    domain::subnet_collision(&libvirt_name, &subnet, &existing).map(str::to_string);
+                let options = network_options(&libvirt_name, iface);
+                let (dhcp_start, dhcp_end) = options
+                    .dhcp_range
+                    .unwrap_or_else(|| (format!("{subnet}.100"), format!("{subnet}.254")));
+                InterfaceExplain {
+                    index: i,
+                    network: iface.network.clone(),
+                    libvirt_name,
+                    mac: domain::generate_mac(self.name(), i),
+                    shared: !iface.subnet.is_empty(),
+                    dhcp_range: format!("{dhcp_start} - {dhcp_end}"),
+                    ipv6_prefix: options.ipv6_prefix.map(|p| format!("{p}::/64")),
+                    domain: (!iface.domain.is_empty()).then(|| iface.domain.clone()),
+                    hosts: iface
+                        .hosts
+                        .iter()
+                        .map(|h| format!("{} {}", h.ip, h.names.join(" ")))
+                        .collect(),
+                    subnet,
+                    subnet_source,
+                    reservation: (!iface.ip.is_empty())
+                        .then(|| format!("{} ({})", iface.ip, self.system.hostname())),
+                    conflict,
+                }
+            })
+            .collect();
+        Ok(explained)
+    }
+
+    /// MAC of the NAT interface. A defined domain keeps the one it has, so
+    /// its lease and address survive a redefine; otherwise the interface
+    /// only needs a fixed MAC when extra interfaces have to be told apart
+    /// from it in the guest's network config.
+    pub(super) fn nat_mac(&self) -> Option<String> {
+        let network = &self.system.config.network;
+        if !network.nat {
+            return None;
+        }
+        let defined = self
+            .connect()
+            .ok()
+            .and_then(|conn| Domain::lookup_by_name(&conn, self.name()).ok())
+            .and_then(|dom| dom.get_xml_desc(0).ok())
+            .and_then(|xml| {
+                domain::parse_network_macs(&xml, "default")
+                    .into_iter()
+                    .next()
+            });
+        defined.or_else(|| {
+            (!network.interfaces.is_empty()).then(|| domain::generate_nat_mac(self.name()))
+        })
+    }
+
+    /// Guest NICs for the seed's `network-config`, in domain order.
+    pub(super) fn guest_nics(&self) -> Vec<cloudinit::NicConfig<'_>> {
+        let network = &self.system.config.network;
+        let mut nics = Vec::new();
+        if network.nat {
+            nics.push(cloudinit::NicConfig {
+                mac: self.nat_mac().map(|mac| mac.to_lowercase()),
+                addresses: &[],
+                gateway: "",
+                nameservers: &network.nameservers,
+                search: &network.search,
+                mtu: None,
+                dhcp6: false,
+            });
+        }
+        for (i, iface) in network.interfaces.iter().enumerate() {
+            nics.push(cloudinit::NicConfig {
+                mac: Some(domain::generate_mac(self.name(), i).to_lowercase()),
+                addresses: &iface.addresses,
+                gateway: &iface.gateway,
+                nameservers: &iface.nameservers,
+                search: &iface.search,
+                mtu: iface.mtu,
+                dhcp6: iface.ipv6_enabled(),
+            });
+        }
+        nics
+    }
+
+    pub(super) fn ensure_networks(&self, conn: &Connect) -> Result<(), Error> {
+        let config = &self.system.config;
+
+        if config.network.nat {
+            self.ensure_network_active(conn, "default")?;
+        }
+
+        for (i, iface) in config.network.interfaces.iter().enumerate() {
+            let libvirt_name = self.system.network_name(iface);
+            let net = self.ensure_extra_network(conn, &libvirt_name, iface)?;
+
+            if !iface.ip.is_empty() {
+                let mac = domain::generate_mac(self.name(), i);
+                self.add_dhcp_reservation(
+                    &net,
+                    &libvirt_name,
+                    &mac,
+                    &iface.ip,
+                    self.system.hostname(),
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub(super) async fn get_vm_ip(&self, dom: &Domain, ipv6: bool) -> Result<String, Error> {
+        self.interface_ip(dom, &self.system.config.ssh.interface, ipv6)
+            .await
+    }
+
+    /// Address of the NIC attached to `network`, or of the default NIC when
+    /// `network` is empty.
+    ///
+    /// DHCP leases only cover libvirt-managed networks, so static and bridged
+    /// setups fall back to the rum agent, qemu-guest-agent, and finally the
+    /// host's neighbor table.
+    async fn interface_ip(&self, dom: &Domain, network: &str, ipv6: bool) -> Result<String, Error> {
+        let vm_name = self.name();
+        let macs = self.interface_macs(dom, network);
+
+        let lease_src = virt::sys::VIR_DOMAIN_INTERFACE_ADDRESSES_SRC_LEASE;
+        let lease = self.libvirt_addresses(dom, lease_src, &macs);
+        if let Some(ip) = util::pick_routable(lease.iter().map(String::as_str), ipv6) {
+            return Ok(ip);
+        }
+
+        if let Ok(connector) = self.agent_connector() {
+            let client = crate::guest::agent_client(connector);
+            let query =
+                tokio::time::timeout(std::time::Duration::from_secs(3), client.interfaces());
+            if let Ok(Ok(interfaces)) = query.await {
+                let addrs = interfaces
+                    .iter()
+                    .filter(|iface| macs.contains(&iface.mac))
+                    .flat_map(|iface| iface.addrs.iter().map(String::as_str));
+                if let Some(ip) = util::pick_routable(addrs, ipv6) {
+                    return Ok(ip);
+                }
+            }
+        }
+
+        let agent_src = virt::sys::VIR_DOMAIN_INTERFACE_ADDRESSES_SRC_AGENT;
+        let agent = self.libvirt_addresses(dom, agent_src, &macs);
+        if let Some(ip) = util::pick_routable(agent.iter().map(String::as_str), ipv6) {
+            return Ok(ip);
+        }
+
+        if let Ok(output) = tokio::process::Command::new("ip")
+            .args(["neigh", "show"])
+            .output()
+            .await
+        {
+            let neighbors = util::parse_neighbors(&String::from_utf8_lossy(&output.stdout));
+            let addrs = neighbors
+                .iter()
+                .filter(|(_, mac)| macs.contains(mac))
+                .map(|(addr, _)| addr.as_str());
+            if let Some(ip) = util::pick_routable(addrs, ipv6) {
+                return Ok(ip);
+            }
+        }
+
+        Err(Error::SshNotReady {
+            name: vm_name.to_string(),
+            reason: if ipv6 {
+                "no IPv6 address found (is ipv6 enabled on the network?)".into()
+            } else {
+                "no IP address found (VM may still be booting)".into()
+            },
+        })
+    }
+
+    /// Lowercase MACs of the NIC attached to `network`, or of every NIC not
+    /// declared in `[[network.interfaces]]` when `network` is empty.
+    fn interface_macs(&self, dom: &Domain, network: &str) -> Vec<String> {
+        let vm_name = self.name();
+        let interfaces = &self.system.config.network.interfaces;
+        if !network.is_empty() {
+            return interfaces
+                .iter()
+                .position(|i| i.network == network)
+                .map(|idx| domain::generate_mac(vm_name, idx).to_lowercase())
+                .into_iter()
+                .collect();
+        }
+
+        let extra_macs: Vec<String> = (0..interfaces.len())
+            .map(|i| domain::generate_mac(vm_name, i).to_lowercase())
+            .collect();
+        let xml = dom.get_xml_desc(0).unwrap_or_default();
+        domain::parse_interface_macs(&xml)
+            .into_iter()
+            .filter(|mac| !extra_macs.contains(mac))
+            .collect()
+    }
+
+    /// Addresses libvirt reports from `source` for the NICs in `macs`.
+    pub(super) fn libvirt_addresses(
+        &self,
+        dom: &Domain,
+        source: u32,
+        macs: &[String],
+    ) -> Vec<String> {
+        dom.interface_addresses(source, 0)
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|iface| macs.contains(&iface.hwaddr.to_lowercase()))
+            .flat_map(|iface| iface.addrs.into_iter().map(|addr| addr.addr))
+            .collect()
+    }
+}
+
+/// Whether the NIC `rum ssh` connects to gets its address from a libvirt
+/// DHCP lease. rum's own networks all serve DHCP, but a guest with static
+/// `addresses` on the interface never asks for a lease.
+fn ssh_address_is_leased(config: &Config) -> bool {
+    let ssh = &config.ssh.interface;
+    if ssh.is_empty() {
+        return config.network.nat;
+    }
+    config
+        .network
+        .interfaces
+        .iter()
+        .find(|iface| iface.network == *ssh)
+        .is_some_and(|iface| iface.addresses.is_empty())
+}
+
+/// DHCP, DNS and IPv6 settings of an interface's auto-created network.
+fn network_options(name: &str, iface: &InterfaceConfig) -> domain::NetworkOptions {
+    domain::NetworkOptions {
+        dhcp_range: iface
+            .dhcp
+            .as_ref()
+            .map(|d| (d.start.clone(), d.end.clone())),
+        domain: (!iface.domain.is_empty()).then(|| iface.domain.clone()),
+        hosts: iface
+            .hosts
+            .iter()
+            .map(|h| (h.ip.clone(), h.names.clone()))
+            .collect(),
+        ipv6_prefix: iface
+            .ipv6_enabled()
+            .then(|| domain::derive_ipv6_prefix(name, &iface.ip)),
+    }
+}
+
+/// Whether any defined domain, running or not, still attaches to the libvirt
+/// network `name`. Errs on the side of keeping the network when libvirt
+/// cannot be asked.
+pub(super) fn network_in_use(conn: &Connect, name: &str) -> bool {
+    let Ok(domains) = conn.list_all_domains(0) else {
+        return true;
+    };
+    domains.iter().any(|dom| {
+        dom.get_xml_desc(0)
+            .map_or(true, |xml| domain::attaches_to_network(&xml, name))
+    })
+}
+
+/// Subnets already in use on the host, as [`domain::subnet_collision`]
+/// takes them: those of libvirt networks, and the host's routes through
+/// interfaces other than libvirt's bridges (VPNs, docker, the LAN).
+fn existing_subnets(conn: &Connect) -> Result<Vec<(String, String)>, Error> {
+    let networks = conn.list_all_networks(0).map_err(|e| Error::Libvirt {
+        message: format!("failed to list networks: {e}"),
+        hint: "check libvirt permissions".into(),
+    })?;
+    let bridges: Vec<String> = networks
+        .iter()
+        .filter_map(|net| net.get_bridge_name().ok())
+        .collect();
+    let mut existing: Vec<(String, String)> = networks
+        .iter()
+        .filter_map(|net| {
+            let name = net.get_name().ok()?;
+            let subnet = domain::parse_network_subnet(&net.get_xml_desc(0).ok()?)?;
+            Some((name, subnet))
+        })
+        .collect();
+    let routes = std::fs::read_to_string("/proc/net/route").unwrap_or_default();
+    existing.extend(
+        host_routes(&routes)
+            .into_iter()
+            .filter(|(dev, _)| !bridges.contains(dev))
+            .map(|(dev, route)| (format!("host route via {dev}"), route)),
+    );
+    Ok(existing)
+}
+
+/// `(interface, address/len)` of every route in `/proc/net/route` except
+/// the default route.
+fn host_routes(table: &str) -> Vec<(String, String)> {
+    table
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let (dev, dest, mask) = (fields.first()?, fields.get(1)?, fields.get(7)?);
+            // Addresses are hex in host byte order, which is little endian
+            // on every architecture rum runs on.
+            let dest = u32::from_str_radix(dest, 16).ok()?.to_le_bytes();
+            let len = u32::from_str_radix(mask, 16).ok()?.count_ones();
+            (len > 0).then(|| {
+                let dest = std::net::Ipv4Addr::from(dest);
+                (dev.to_string(), format!("{dest}/{len}"))
+            })
+        })
+        .collect()
+}
+
+/// Derived networking values for one configured interface.
+#[derive(Debug, Clone)]
+pub struct InterfaceExplain {
+    pub index: usize,
+    pub network: String,
+    pub libvirt_name: String,
+    pub mac: String,
+    /// Whether the network is shared with other VMs using the same name.
+    pub shared: bool,
+    pub subnet: String,
+    /// Where `subnet` came from: `defined`, `configured`, `ip hint`, or `derived`.
+    pub subnet_source: &'static str,
+    /// `start - end` of the DHCP range.
+    pub dhcp_range: String,
+    /// `prefix::/64` when the network also carries IPv6.
+    pub ipv6_prefix: Option<String>,
+    pub domain: Option<String>,
+    /// Static DNS records as `ip name...`.
+    pub hosts: Vec<String>,
+    /// `ip (hostname)` when the interface has a DHCP reservation.
+    pub reservation: Option<String>,
+    /// Other libvirt network already using `subnet`, if any.
+    pub conflict: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_leased_ssh_addresses_are_waited_for() {
+        let mut config = crate::config::tests::valid_config();
+        config.network.nat = true;
+        assert!(ssh_address_is_leased(&config));
+
+        config.network.nat = false;
+        assert!(!ssh_address_is_leased(&config), "no NIC for rum ssh");
+
+        config.network.interfaces = vec![InterfaceConfig {
+            network: "lab".into(),
+            ..Default::default()
+        }];
+        config.ssh.interface = "lab".into();
+        assert!(ssh_address_is_leased(&config));
+
+        config.network.interfaces[0].addresses = vec!["192.168.50.10/24".into()];
+        assert!(!ssh_address_is_leased(&config), "static address");
+
+        config.ssh.interface = "missing".into();
+        assert!(!ssh_address_is_leased(&config));
+    }
+}
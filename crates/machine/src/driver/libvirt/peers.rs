@@ -0,0 +1,152 @@
+//! The other rum machines sharing a network with this one, for the guest's
+//! `/etc/hosts`.
+
+use guest::agent::PeerHost;
+
+use crate::config::Config;
+use crate::driver::Driver;
+use crate::error::Error;
+
+use super::lifecycle::LibvirtDriver;
+
+impl LibvirtDriver {
+    /// The other running rum machines on this machine's shared networks, at
+    /// the addresses libvirt leased them there and the static ones of the
+    /// config they were started with, named after their hostname.
+    pub fn peer_hosts(&self) -> Result<Vec<PeerHost>, Error> {
+        let networks: Vec<String> = self
+            .system
+            .config
+            .network
+            .interfaces
+            .iter()
+            .filter(|iface| !iface.subnet.is_empty())
+            .map(|iface| self.system.network_name(iface))
+            .collect();
+        if networks.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let conn = self.connect()?;
+        let domains = conn.list_all_domains(0).map_err(|e| Error::Libvirt {
+            message: format!("failed to list domains: {e}"),
+            hint: "check libvirt permissions".into(),
+        })?;
+        let lease_src = virt::sys::VIR_DOMAIN_INTERFACE_ADDRESSES_SRC_LEASE;
+        let mut peers = Vec::new();
+        for dom in &domains {
+            let Ok(name) = dom.get_name() else { continue };
+            if name == self.name() || !dom.is_active().unwrap_or(false) {
+                continue;
+            }
+            let Ok(xml) = dom.get_xml_desc(0) else {
+                continue;
+            };
+            let Some(metadata) = domain::parse_instance_metadata(&xml) else {
+                continue;
+            };
+            let macs: Vec<String> = networks
+                .iter()
+                .flat_map(|network| domain::parse_network_macs(&xml, network))
+                .collect();
+            if macs.is_empty() {
+                continue;
+            }
+            // The domain is named after the machine, so its work dir holds
+            // the config it runs with.
+            let machine_name = (name != metadata.id).then_some(name.as_str());
+            let applied = crate::config::applied_config(&crate::paths::applied_config_path(
+                &metadata.id,
+                machine_name,
+            ));
+            let names = peer_names(&name, applied.as_ref());
+            let mut ips = self.libvirt_addresses(dom, lease_src, &macs);
+            if let Some(applied) = &applied {
+                ips.extend(static_addresses(applied, &networks));
+            }
+            for ip in ips {
+                peers.push(PeerHost {
+                    ip,
+                    names: names.clone(),
+                });
+            }
+        }
+        peers.sort_by(|a, b| (&a.names, &a.ip).cmp(&(&b.names, &b.ip)));
+        Ok(peers)
+    }
+}
+
+/// The names a peer is listed under: its guest hostname, then the machine
+/// name when that differs.
+fn peer_names(machine: &str, applied: Option<&Config>) -> Vec<String> {
+    let hostname = applied
+        .map(|config| config.network.hostname.as_str())
+        .filter(|hostname| !hostname.is_empty())
+        .unwrap_or(machine);
+    let mut names = vec![hostname.to_string()];
+    if hostname != machine {
+        names.push(machine.to_string());
+    }
+    names
+}
+
+/// The static addresses `config` gives its interfaces on the shared
+/// `networks`, without their prefix length. No lease lists them.
+fn static_addresses(config: &Config, networks: &[String]) -> Vec<String> {
+    config
+        .network
+        .interfaces
+        .iter()
+        .filter(|iface| {
+            !iface.subnet.is_empty() && networks.contains(&domain::shared_name(&iface.network))
+        })
+        .flat_map(|iface| &iface.addresses)
+        .map(|address| address.split('/').next().unwrap_or_default().to_string())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::InterfaceConfig;
+
+    #[test]
+    fn peers_are_named_after_their_hostname() {
+        let mut config = crate::config::tests::valid_config();
+        assert_eq!(peer_names("web", None), ["web"]);
+        assert_eq!(peer_names("web", Some(&config)), ["web"]);
+
+        config.network.hostname = "web.lab".into();
+        assert_eq!(peer_names("web", Some(&config)), ["web.lab", "web"]);
+    }
+
+    #[test]
+    fn static_peer_addresses_come_from_shared_interfaces() {
+        let mut config = crate::config::tests::valid_config();
+        config.network.interfaces = vec![
+            InterfaceConfig {
+                network: "lab".into(),
+                subnet: "192.168.50.0/24".into(),
+                addresses: vec!["192.168.50.10/24".into(), "fd00::10/64".into()],
+                ..Default::default()
+            },
+            InterfaceConfig {
+                network: "other".into(),
+                subnet: "192.168.60.0/24".into(),
+                addresses: vec!["192.168.60.10/24".into()],
+                ..Default::default()
+            },
+            InterfaceConfig {
+                network: "lab".into(),
+                addresses: vec!["10.0.0.10/24".into()],
+                ..Default::default()
+            },
+        ];
+        let networks = vec![domain::shared_name("lab")];
+        assert_eq!(
+            static_addresses(&config, &networks),
+            ["192.168.50.10", "fd00::10"]
+        );
+        assert!(static_addresses(&config, &[]).is_empty());
+    }
+}
@@ -0,0 +1,301 @@
+//! `rum ssh` and `rum cp` over plain SSH, and the keys the machine is
+//! reached with.
+
+use std::path::Path;
+
+use guest::client::CopyDirection;
+use virt::domain::Domain;
+
+use crate::cloudinit;
+use crate::driver::Driver;
+use crate::error::Error;
+
+use super::lifecycle::LibvirtDriver;
+
+impl LibvirtDriver {
+    pub async fn ssh(&self, args: &[String]) -> Result<(), Error> {
+        let ip = self.ssh_ip().await?;
+        let ssh_config = &self.system.config.ssh;
+        let cmd_parts: Vec<&str> = ssh_config.command.split_whitespace().collect();
+        let program = cmd_parts[0];
+        let cmd_args = &cmd_parts[1..];
+
+        let key_str = self.layout.ssh_key_path.to_string_lossy();
+        let user_host = format!("{}@{}", ssh_config.user, ip);
+
+        use std::os::unix::process::CommandExt;
+        let mut command = std::process::Command::new(program);
+        command.args(cmd_args);
+        if program == "ssh" {
+            if ssh_config.forward_agent {
+                command.arg("-A");
+            }
+            command.args(self.ssh_options());
+        } else {
+            command.args(["-i", &key_str]);
+        }
+        command.arg(&user_host);
+        command.args(args);
+
+        let err = command.exec();
+        Err(Error::Io {
+            context: format!("exec {}", ssh_config.command),
+            source: err,
+        })
+    }
+
+    /// Copy a file or, with `recursive`, a directory between the host and
+    /// the guest with `scp`, for images without the rum agent.
+    pub async fn scp(&self, direction: &CopyDirection, recursive: bool) -> Result<(), Error> {
+        let ip = self.ssh_ip().await?;
+        let remote = |path: &str| scp_remote(&self.system.config.ssh.user, &ip, path);
+        let (src, dst) = match direction {
+            CopyDirection::Upload { local, guest } => {
+                (local.to_string_lossy().into_owned(), remote(guest))
+            }
+            CopyDirection::Download { guest, local } => {
+                (remote(guest), local.to_string_lossy().into_owned())
+            }
+        };
+
+        let mut command = tokio::process::Command::new("scp");
+        command.arg("-q");
+        if recursive {
+            command.arg("-r");
+        }
+        command.args(self.ssh_options()).arg(&src).arg(&dst);
+        let status = command.status().await.map_err(|e| Error::ExternalCommand {
+            command: "scp".into(),
+            message: e.to_string(),
+        })?;
+        if !status.success() {
+            return Err(Error::CopyFailed {
+                message: format!("scp {src} {dst} exited with {status}"),
+            });
+        }
+        Ok(())
+    }
+
+    /// IP `rum ssh` connects to, once the machine runs and has its key.
+    async fn ssh_ip(&self) -> Result<String, Error> {
+        let vm_name = self.name();
+        let conn = self.connect()?;
+
+        let dom = Domain::lookup_by_name(&conn, vm_name).map_err(|_| Error::SshNotReady {
+            name: vm_name.to_string(),
+            reason: "VM is not defined".into(),
+        })?;
+
+        if !self.is_running(&dom) {
+            return Err(Error::SshNotReady {
+                name: vm_name.to_string(),
+                reason: "VM is not running".into(),
+            });
+        }
+
+        let ip = self.get_vm_ip(&dom, false).await?;
+
+        if !self.layout.ssh_key_path.exists() {
+            return Err(Error::SshNotReady {
+                name: vm_name.to_string(),
+                reason: "SSH key not found (run `rum up` first)".into(),
+            });
+        }
+        Ok(ip)
+    }
+
+    /// OpenSSH options shared by `ssh` and `scp`: the machine key, host key
+    /// checks off, and the client options of `[ssh]`.
+    fn ssh_options(&self) -> Vec<String> {
+        let ssh_config = &self.system.config.ssh;
+        let mut options = vec![
+            "-i".to_string(),
+            self.layout.ssh_key_path.to_string_lossy().into_owned(),
+            "-o".into(),
+            "StrictHostKeyChecking=no".into(),
+            "-o".into(),
+            "UserKnownHostsFile=/dev/null".into(),
+        ];
+        if let Some(jump) = &ssh_config.proxy_jump {
+            options.extend(["-J".to_string(), jump.clone()]);
+        }
+        for option in &ssh_config.extra_options {
+            options.extend(["-o".to_string(), option.clone()]);
+        }
+        if ssh_config.multiplex {
+            // ssh takes the first value of an option, so the extra options
+            // above can still override these.
+            let control_path = match &ssh_config.control_path {
+                Some(path) => path.clone(),
+                None => self.layout.ssh_control_path.to_string_lossy().into_owned(),
+            };
+            options.extend([
+                "-o".to_string(),
+                "ControlMaster=auto".into(),
+                "-o".into(),
+                format!("ControlPath={control_path}"),
+                "-o".into(),
+                "ControlPersist=10m".into(),
+            ]);
+        }
+        options
+    }
+
+    /// Close the shared connection `ControlPersist` keeps open after the last
+    /// `rum ssh`, so it does not outlive the machine. Runs while the machine
+    /// is still up, since a `ControlPath` with `%C` needs its address.
+    pub(super) async fn close_ssh_master(&self) {
+        let ssh_config = &self.system.config.ssh;
+        if !ssh_config.multiplex
+            || (ssh_config.control_path.is_none() && !self.layout.ssh_control_path.exists())
+        {
+            return;
+        }
+        let host = match self.ssh_ip().await {
+            Ok(ip) => ip,
+            Err(_) => self.name().to_string(),
+        };
+        let status = tokio::process::Command::new("ssh")
+            .args(self.ssh_options())
+            .args(["-O", "exit"])
+            .arg(format!("{}@{host}", ssh_config.user))
+            .stdin(std::process::Stdio::null())
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::null())
+            .status()
+            .await;
+        match status {
+            Ok(status) if status.success() => tracing::debug!("closed the shared ssh connection"),
+            Ok(status) => tracing::debug!(%status, "no shared ssh connection to close"),
+            Err(error) => tracing::debug!(%error, "failed to run ssh -O exit"),
+        }
+    }
+
+    /// Private key for `sshfs` mounts, generated on first use.
+    ///
+    /// Its public half has to be authorized for the host user before the
+    /// guest can mount anything, restricted to SFTP so the guest gets no
+    /// shell on the host.
+    pub async fn sshfs_key(&self) -> Result<String, Error> {
+        let path = &self.layout.sshfs_key_path;
+        if !path.exists() {
+            ensure_ssh_keypair(path).await?;
+            let public = tokio::fs::read_to_string(path.with_extension("pub"))
+                .await
+                .unwrap_or_default();
+            tracing::warn!(
+                entry = %cloudinit::sshfs_authorized_key(&public),
+                "add this line to ~/.ssh/authorized_keys so the guest can mount sshfs shares"
+            );
+        }
+        tokio::fs::read_to_string(path)
+            .await
+            .map_err(|e| Error::Io {
+                context: format!("reading {}", path.display()),
+                source: e,
+            })
+    }
+}
+
+/// `user@ip:path` as scp reads it: an IPv6 address goes in brackets, or its
+/// colons would end the host part.
+fn scp_remote(user: &str, ip: &str, path: &str) -> String {
+    if ip.contains(':') {
+        format!("{user}@[{ip}]:{path}")
+    } else {
+        format!("{user}@{ip}:{path}")
+    }
+}
+
+pub(super) async fn ensure_ssh_keypair(key_path: &Path) -> Result<(), Error> {
+    if key_path.exists() {
+        return Ok(());
+    }
+
+    if let Some(parent) = key_path.parent() {
+        tokio::fs::create_dir_all(parent)
+            .await
+            .map_err(|e| Error::Io {
+                context: format!("creating directory {}", parent.display()),
+                source: e,
+            })?;
+    }
+
+    let keypair = ssh_key::private::Ed25519Keypair::random(&mut rand_core::OsRng);
+    let private = ssh_key::PrivateKey::from(keypair);
+
+    let openssh_private = private
+        .to_openssh(ssh_key::LineEnding::LF)
+        .map_err(|e| Error::Io {
+            context: format!("encoding SSH private key: {e}"),
+            source: std::io::Error::other(e.to_string()),
+        })?;
+    tokio::fs::write(key_path, openssh_private.as_bytes())
+        .await
+        .map_err(|e| Error::Io {
+            context: format!("writing SSH key to {}", key_path.display()),
+            source: e,
+        })?;
+
+    {
+        use std::os::unix::fs::PermissionsExt;
+        tokio::fs::set_permissions(key_path, std::fs::Permissions::from_mode(0o600))
+            .await
+            .map_err(|e| Error::Io {
+                context: format!("setting permissions on {}", key_path.display()),
+                source: e,
+            })?;
+    }
+
+    let pub_key = private.public_key().to_openssh().map_err(|e| Error::Io {
+        context: format!("encoding SSH public key: {e}"),
+        source: std::io::Error::other(e.to_string()),
+    })?;
+    let pub_path = key_path.with_extension("pub");
+    tokio::fs::write(&pub_path, pub_key.as_bytes())
+        .await
+        .map_err(|e| Error::Io {
+            context: format!("writing SSH public key to {}", pub_path.display()),
+            source: e,
+        })?;
+
+    tracing::info!(path = %key_path.display(), "generated SSH keypair");
+    Ok(())
+}
+
+pub(super) async fn collect_ssh_keys(
+    key_path: &Path,
+    extra_keys: &[String],
+) -> Result<Vec<String>, Error> {
+    let pub_path = key_path.with_extension("pub");
+    let auto_pub = tokio::fs::read_to_string(&pub_path)
+        .await
+        .map_err(|e| Error::Io {
+            context: format!("reading SSH public key from {}", pub_path.display()),
+            source: e,
+        })?;
+    let mut keys = vec![auto_pub.trim().to_string()];
+    keys.extend(extra_keys.iter().cloned());
+    Ok(keys)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scp_remote_brackets_ipv6_addresses() {
+        assert_eq!(
+            scp_remote("rum", "192.168.122.10", "/tmp/x"),
+            "rum@192.168.122.10:/tmp/x"
+        );
+        assert_eq!(
+            scp_remote("rum", "fd00::10", "/tmp/x"),
+            "rum@[fd00::10]:/tmp/x"
+        );
+        assert_eq!(
+            scp_remote("rum", "fe80::1%virbr0", "x"),
+            "rum@[fe80::1%virbr0]:x"
+        );
+    }
+}
@@ -1,9 +1,11 @@
+mod events;
 mod libvirt;
 
 use std::path::Path;
 
 use async_trait::async_trait;
 
+use crate::guest::AgentEndpoints;
 use crate::instance::InstanceState;
 
 /// Standard operational surface for one runtime backend handle.
@@ -26,8 +28,9 @@ pub trait Driver: Clone {
 
     /// Prepare backend state and artifacts needed before boot.
     async fn prepare(&self, base_image: &Path) -> Result<(), Self::Error>;
-    /// Boot the runtime and return the guest-agent endpoint identifier.
-    async fn boot(&self) -> Result<u32, Self::Error>;
+    /// Boot the runtime and return the transports its guest agent can be
+    /// reached on.
+    async fn boot(&self) -> Result<AgentEndpoints, Self::Error>;
     /// Request a graceful shutdown.
     async fn shutdown(&self) -> Result<(), Self::Error>;
     /// Tear down the runtime and its backend-managed resources.
@@ -43,4 +46,5 @@ pub trait RecoverableDriver: Driver {
     fn recover(&self) -> Result<InstanceState, Self::Error>;
 }
 
-pub use libvirt::LibvirtDriver;
+pub use events::{DomainEvents, DomainHealth};
+pub use libvirt::{DomainSummary, InterfaceExplain, LibvirtDriver, list_domains};
@@ -4,6 +4,7 @@ use thiserror::Error;
 #[derive(Debug, Error, Diagnostic)]
 pub enum Error {
     #[error("failed to load config from {path}")]
+    #[diagnostic(code("RUM-CONFIG-LOAD"))]
     ConfigLoad {
         path: String,
         #[source]
@@ -11,12 +12,15 @@ pub enum Error {
     },
 
     #[error("failed to parse config from {path}: {message}")]
+    #[diagnostic(code("RUM-CONFIG-PARSE"))]
     ConfigParse { path: String, message: String },
 
     #[error("validation error: {message}")]
+    #[diagnostic(code("RUM-CONFIG-INVALID"))]
     Validation { message: String },
 
     #[error("failed to download image: {message}")]
+    #[diagnostic(code("RUM-IMAGE-DOWNLOAD"))]
     ImageDownload {
         message: String,
         #[source]
@@ -24,25 +28,92 @@ pub enum Error {
     },
 
     #[error("{command} failed: {message}")]
-    #[diagnostic(help("ensure {command} is installed and accessible"))]
+    #[diagnostic(
+        code("RUM-EXTERNAL-COMMAND"),
+        help("ensure {command} is installed and accessible")
+    )]
     ExternalCommand { command: String, message: String },
 
+    #[error("{algorithm} checksum mismatch for {path}: expected {expected}, got {actual}")]
+    #[diagnostic(
+        code("RUM-IMAGE-CHECKSUM"),
+        help("delete the file to re-download it, or update image.{algorithm} in the config")
+    )]
+    ChecksumMismatch {
+        path: String,
+        algorithm: String,
+        expected: String,
+        actual: String,
+    },
+
     #[error("libvirt error: {message}")]
-    #[diagnostic(help("{hint}"))]
+    #[diagnostic(code("RUM-LIBVIRT"), help("{hint}"))]
     Libvirt { message: String, hint: String },
 
     #[error("config changed while VM '{name}' is running — restart required")]
-    #[diagnostic(help("run `rum down` then `rum up`, or use `rum up --reset`"))]
+    #[diagnostic(
+        code("RUM-RESTART-REQUIRED"),
+        help(
+            "run `rum diff` to see what changed, then `rum down` and `rum up`, or use `rum up --reset`"
+        )
+    )]
     RequiresRestart { name: String },
 
+    #[error("VM '{name}' is busy (held by PID {pid})")]
+    #[diagnostic(
+        code("RUM-VM-BUSY"),
+        help("wait for the other rum command to finish, then try again")
+    )]
+    VmBusy { name: String, pid: String },
+
     #[error("domain '{name}' not found")]
-    #[diagnostic(help("run `rum up` to create the VM first"))]
+    #[diagnostic(
+        code("RUM-DOMAIN-NOT-FOUND"),
+        help("run `rum up` to create the VM first")
+    )]
     DomainNotFound { name: String },
 
+    #[error("subnet {subnet}.0/24 for network '{network}' is already used by '{conflict}'")]
+    #[diagnostic(
+        code("RUM-NET-SUBNET-COLLISION"),
+        help("set an `ip` in a free subnet on the interface, or run `rum net explain`")
+    )]
+    SubnetCollision {
+        network: String,
+        subnet: String,
+        conflict: String,
+    },
+
     #[error("timed out waiting for IP on '{name}' after {timeout_s}s")]
+    #[diagnostic(
+        code("RUM-IP-TIMEOUT"),
+        help(
+            "check the boot output with `rum console`, raise network.ip_wait_timeout_s, or set \
+             network.wait_for_ip = false"
+        )
+    )]
     IpTimeout { name: String, timeout_s: u64 },
 
+    #[error("'{name}' not ready after {timeout_s}s: {failing} still failing")]
+    #[diagnostic(
+        code("RUM-NOT-READY"),
+        help("check the [ready] probes in the config, or raise ready.timeout_s")
+    )]
+    NotReady {
+        name: String,
+        failing: String,
+        timeout_s: u64,
+    },
+
+    #[error("{step} timed out after {timeout_s}s")]
+    #[diagnostic(
+        code("RUM-STEP-TIMEOUT"),
+        help("raise the matching [timeouts] setting in the config")
+    )]
+    StepTimeout { step: String, timeout_s: u64 },
+
     #[error("{context}")]
+    #[diagnostic(code("RUM-IO"))]
     Io {
         context: String,
         #[source]
@@ -50,39 +121,105 @@ pub enum Error {
     },
 
     #[error("mount source not found: {path}")]
-    #[diagnostic(help("check that the directory exists"))]
+    #[diagnostic(code("RUM-MOUNT-SOURCE"), help("check that the directory exists"))]
     MountSourceNotFound { path: String },
 
+    #[error("mount '{tag}' is not active in the guest at {target}")]
+    #[diagnostic(
+        code("RUM-MOUNT-INACTIVE"),
+        help(
+            "check the guest's `journalctl -b` for virtiofs errors and that the host path is still shared"
+        )
+    )]
+    MountNotActive { tag: String, target: String },
+
     #[error("failed to detect git repository: {message}")]
-    #[diagnostic(help("source = \"git\" requires rum.toml to be inside a git repository"))]
+    #[diagnostic(
+        code("RUM-GIT-REPO"),
+        help("source = \"git\" requires rum.toml to be inside a git repository")
+    )]
     GitRepoDetection { message: String },
 
     #[error("{command} is not yet implemented")]
+    #[diagnostic(code("RUM-NOT-IMPLEMENTED"))]
     NotImplemented { command: String },
 
     #[error("SSH not ready for '{name}': {reason}")]
-    #[diagnostic(help("ensure the VM is running with `rum status`"))]
+    #[diagnostic(
+        code("RUM-SSH-NOT-READY"),
+        help("ensure the VM is running with `rum status`")
+    )]
     SshNotReady { name: String, reason: String },
 
     #[error("exec not ready for '{name}': {reason}")]
-    #[diagnostic(help("ensure the VM is running with `rum up` first"))]
+    #[diagnostic(
+        code("RUM-EXEC-NOT-READY"),
+        help("ensure the VM is running with `rum up` first")
+    )]
     ExecNotReady { name: String, reason: String },
 
     #[error("init cancelled by user")]
+    #[diagnostic(code("RUM-INIT-CANCELLED"))]
     InitCancelled,
 
     #[error("{message}")]
-    #[diagnostic(help("check that the VM booted and rum-agent started"))]
+    #[diagnostic(
+        code("RUM-AGENT-TIMEOUT"),
+        help("check that the VM booted and rum-agent started")
+    )]
     AgentTimeout { message: String },
 
+    #[error("rum-agent in '{name}' not reachable after {timeout_s}s")]
+    #[diagnostic(
+        code("RUM-AGENT-UNREACHABLE"),
+        help("check the boot output with `rum console`, or raise timeouts.agent_connect_s")
+    )]
+    AgentUnreachable { name: String, timeout_s: u64 },
+
+    #[error("{message}")]
+    #[diagnostic(code("RUM-AGENT-INCOMPATIBLE"))]
+    AgentIncompatible { message: String },
+
+    #[error("no rum-agent binary for {arch} guests")]
+    #[diagnostic(
+        code("RUM-AGENT-ARCH"),
+        help(
+            "build one with `cargo build -p guest --release --target \
+             {arch}-unknown-linux-musl` and copy it to {path}"
+        )
+    )]
+    AgentUnavailable { arch: String, path: String },
+
+    #[error("VM '{name}' crashed: {reason}")]
+    #[diagnostic(
+        code("RUM-GUEST-CRASHED"),
+        help("`rum restart` boots it again; `rum down` leaves it stopped")
+    )]
+    GuestCrashed { name: String, reason: String },
+
+    #[error("cloud-init failed: {reason}{}", excerpt(.log_excerpt))]
+    #[diagnostic(
+        code("RUM-CLOUD-INIT-FAILED"),
+        help("`rum ssh` in and read /var/log/cloud-init.log for the full run")
+    )]
+    CloudInitFailed {
+        reason: String,
+        log_excerpt: Vec<String>,
+    },
+
     #[error("provisioning failed: script '{script}' exited with non-zero status")]
-    #[diagnostic(help("run `rum log --failed` to see the full script output"))]
+    #[diagnostic(
+        code("RUM-PROVISION-FAILED"),
+        help("run `rum log --failed` to see the full script output")
+    )]
     ProvisionFailed { script: String },
 
     #[error("daemon error: {message}")]
+    #[diagnostic(code("RUM-DAEMON"))]
     Daemon { message: String },
 
     #[error("failed to write config: {path}")]
+    #[diagnostic(code("RUM-CONFIG-WRITE"))]
     ConfigWrite {
         path: String,
         #[source]
@@ -90,6 +227,51 @@ pub enum Error {
     },
 
     #[error("copy failed: {message}")]
-    #[diagnostic(help("ensure the VM is running and the path is accessible"))]
+    #[diagnostic(
+        code("RUM-COPY-FAILED"),
+        help("ensure the VM is running and the path is accessible")
+    )]
     CopyFailed { message: String },
 }
+
+impl Error {
+    /// Stable identifier of the error class, e.g. `RUM-AGENT-TIMEOUT`, for
+    /// wrappers that branch on the kind of failure instead of the message.
+    pub fn code(&self) -> String {
+        Diagnostic::code(self)
+            .map(|code| code.to_string())
+            .unwrap_or_default()
+    }
+
+    /// The help text shown under the error, if any.
+    pub fn hint(&self) -> Option<String> {
+        self.help().map(|help| help.to_string())
+    }
+}
+
+/// Log lines appended to an error message, indented below it.
+fn excerpt(lines: &[String]) -> String {
+    lines.iter().map(|line| format!("\n    {line}")).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn code_and_hint_come_from_the_diagnostic() {
+        let error = Error::StepTimeout {
+            step: "boot".into(),
+            timeout_s: 30,
+        };
+        assert_eq!(error.code(), "RUM-STEP-TIMEOUT");
+        assert_eq!(
+            error.hint().as_deref(),
+            Some("raise the matching [timeouts] setting in the config")
+        );
+
+        let error = Error::InitCancelled;
+        assert_eq!(error.code(), "RUM-INIT-CANCELLED");
+        assert_eq!(error.hint(), None);
+    }
+}
@@ -0,0 +1,70 @@
+//! UEFI firmware discovery.
+//!
+//! Distros ship OVMF (x86_64) and AAVMF (aarch64) under different paths, so we
+//! probe the common locations. When nothing is found the domain still boots
+//! with `firmware="efi"` and libvirt autoselects from its firmware descriptors.
+
+use std::path::{Path, PathBuf};
+
+/// A firmware code image and the variable-store template that goes with it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UefiFirmware {
+    pub code: PathBuf,
+    pub vars: PathBuf,
+}
+
+const X86_64_CANDIDATES: &[(&str, &str)] = &[
+    // Debian/Ubuntu
+    (
+        "/usr/share/OVMF/OVMF_CODE_4M.fd",
+        "/usr/share/OVMF/OVMF_VARS_4M.fd",
+    ),
+    (
+        "/usr/share/OVMF/OVMF_CODE.fd",
+        "/usr/share/OVMF/OVMF_VARS.fd",
+    ),
+    // Fedora/RHEL
+    (
+        "/usr/share/edk2/ovmf/OVMF_CODE.fd",
+        "/usr/share/edk2/ovmf/OVMF_VARS.fd",
+    ),
+    // Arch
+    (
+        "/usr/share/edk2/x64/OVMF_CODE.4m.fd",
+        "/usr/share/edk2/x64/OVMF_VARS.4m.fd",
+    ),
+];
+
+const AARCH64_CANDIDATES: &[(&str, &str)] = &[
+    // Debian/Ubuntu
+    (
+        "/usr/share/AAVMF/AAVMF_CODE.fd",
+        "/usr/share/AAVMF/AAVMF_VARS.fd",
+    ),
+    // Fedora/RHEL
+    (
+        "/usr/share/edk2/aarch64/QEMU_EFI-pflash.raw",
+        "/usr/share/edk2/aarch64/vars-template-pflash.raw",
+    ),
+    // Arch
+    (
+        "/usr/share/edk2/aarch64/QEMU_CODE.fd",
+        "/usr/share/edk2/aarch64/QEMU_VARS.fd",
+    ),
+];
+
+/// Find installed UEFI firmware for `arch`.
+pub fn detect(arch: &str) -> Option<UefiFirmware> {
+    let candidates = match arch {
+        "x86_64" => X86_64_CANDIDATES,
+        "aarch64" => AARCH64_CANDIDATES,
+        _ => return None,
+    };
+    candidates
+        .iter()
+        .find(|(code, vars)| Path::new(code).is_file() && Path::new(vars).is_file())
+        .map(|(code, vars)| UefiFirmware {
+            code: PathBuf::from(code),
+            vars: PathBuf::from(vars),
+        })
+}
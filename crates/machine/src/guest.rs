@@ -1,14 +1,58 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fs::TryLockError;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::{LazyLock, Mutex, PoisonError};
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use guest::client::{Client, ClientError};
 use roam_stream::Connector;
-use tokio::io::AsyncWriteExt;
-use tokio::net::TcpListener;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf};
+use tokio::net::{TcpListener, UnixListener, UnixStream};
 use tokio::task::JoinHandle;
 use tokio_vsock::{VsockAddr, VsockStream};
 
-use crate::config::PortForward;
+use crate::config::{PortForward, SocketForward};
 use crate::error::Error;
 
+/// Static musl build of the guest agent for [`AGENT_ARCH`] guests.
 pub const AGENT_BINARY: &[u8] = include_bytes!(env!("CARGO_BIN_FILE_GUEST"));
 
+/// Guest architecture [`AGENT_BINARY`] is built for.
+pub const AGENT_ARCH: &str = "x86_64";
+
+/// aarch64 build of the guest agent, embedded with the `agent-aarch64`
+/// feature.
+#[cfg(feature = "agent-aarch64")]
+const AGENT_BINARY_AARCH64: &[u8] = include_bytes!(env!("CARGO_BIN_FILE_GUEST_AARCH64_guest"));
+
+/// The guest agent for `arch` guests: an embedded build, or else the one
+/// at [`paths::agent_binary_path`](crate::paths::agent_binary_path).
+pub fn agent_binary(arch: &str) -> Result<Cow<'static, [u8]>, Error> {
+    if arch == AGENT_ARCH {
+        return Ok(Cow::Borrowed(AGENT_BINARY));
+    }
+    #[cfg(feature = "agent-aarch64")]
+    if arch == "aarch64" {
+        return Ok(Cow::Borrowed(AGENT_BINARY_AARCH64));
+    }
+    let path = crate::paths::agent_binary_path(arch);
+    match std::fs::read(&path) {
+        Ok(binary) => Ok(Cow::Owned(binary)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Err(Error::AgentUnavailable {
+            arch: arch.to_string(),
+            path: path.display().to_string(),
+        }),
+        Err(source) => Err(Error::Io {
+            context: format!("reading agent binary {}", path.display()),
+            source,
+        }),
+    }
+}
+
 pub const AGENT_SERVICE: &str = "\
 [Unit]
 Description=rum guest agent
@@ -27,22 +71,237 @@ WantedBy=multi-user.target
 pub const RPC_PORT: u32 = 2222;
 const FORWARD_PORT: u32 = 2223;
 
-#[derive(Clone)]
-pub struct VsockConnector {
-    cid: u32,
+/// Where the guest agent listens.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum AgentConnector {
+    /// The RPC port on the guest's vsock CID.
+    Vsock(u32),
+    /// The host socket of the domain's virtio-serial agent channel. The
+    /// channel is a single byte stream, so a process shares one connection
+    /// among its callers through [`agent_client`], and other processes are
+    /// refused while it is open.
+    Serial(PathBuf),
 }
 
-impl VsockConnector {
-    pub fn new(cid: u32) -> Self {
-        Self { cid }
+impl AgentConnector {
+    /// Name of the transport, as recorded once negotiated.
+    pub fn transport(&self) -> &'static str {
+        match self {
+            Self::Vsock(_) => "vsock",
+            Self::Serial(_) => "serial",
+        }
     }
 }
 
-impl Connector for VsockConnector {
-    type Transport = VsockStream;
+impl Connector for AgentConnector {
+    type Transport = AgentStream;
+
+    async fn connect(&self) -> io::Result<AgentStream> {
+        match self {
+            Self::Vsock(cid) => VsockStream::connect(VsockAddr::new(*cid, RPC_PORT))
+                .await
+                .map(AgentStream::Vsock),
+            Self::Serial(path) => {
+                let lock = lock_channel(path)?;
+                let stream = UnixStream::connect(path).await?;
+                Ok(AgentStream::Serial(stream, lock))
+            }
+        }
+    }
+}
 
-    async fn connect(&self) -> std::io::Result<VsockStream> {
-        VsockStream::connect(VsockAddr::new(self.cid, RPC_PORT)).await
+/// Lock the agent channel at `path` for as long as the returned file is
+/// open. Frames of two connections would interleave on the channel, so a
+/// second one is refused rather than queued.
+fn lock_channel(path: &Path) -> io::Result<std::fs::File> {
+    let file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path.with_extension("lock"))?;
+    match file.try_lock() {
+        Ok(()) => Ok(file),
+        Err(TryLockError::WouldBlock) => Err(io::Error::new(
+            io::ErrorKind::ResourceBusy,
+            "the guest agent's serial channel is in use by another rum process; \
+             without vsock only the daemon can reach the agent",
+        )),
+        Err(TryLockError::Error(e)) => Err(e),
+    }
+}
+
+/// Shared clients of the guest agents this process talks to.
+static AGENT_CLIENTS: LazyLock<Mutex<HashMap<AgentConnector, Client<AgentConnector>>>> =
+    LazyLock::new(Default::default);
+
+/// A client for the agent behind `connector`. All clients of this process
+/// for one connector share a connection and multiplex their calls on it,
+/// which the serial channel requires and spares vsock a connection per call.
+pub fn agent_client(connector: AgentConnector) -> Client<AgentConnector> {
+    let mut clients = AGENT_CLIENTS.lock().unwrap_or_else(PoisonError::into_inner);
+    clients
+        .entry(connector.clone())
+        .or_insert_with(|| Client::connect(connector))
+        .clone()
+}
+
+/// Drop the shared client for `connector`, e.g. once its connection broke,
+/// so the next [`agent_client`] connects anew. The connection closes once
+/// the last caller lets go of it.
+pub fn forget_agent_client(connector: &AgentConnector) {
+    AGENT_CLIENTS
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .remove(connector);
+}
+
+/// Like [`guest::client::wait_for_agent`], through [`agent_client`].
+pub async fn wait_for_agent(
+    connector: AgentConnector,
+) -> Result<Client<AgentConnector>, ClientError> {
+    let client = agent_client(connector);
+    client.wait_ready().await?;
+    Ok(client)
+}
+
+/// Like [`wait_for_agent`], giving up after `timeout`, or never with `None`.
+pub async fn wait_for_agent_within(
+    connector: AgentConnector,
+    timeout: Option<Duration>,
+) -> Result<Client<AgentConnector>, ClientError> {
+    let client = agent_client(connector);
+    client.wait_ready_within(timeout).await?;
+    Ok(client)
+}
+
+/// Whether this host can give domains a vsock device. Without it the guest
+/// agent is only reachable over its virtio-serial channel.
+pub fn host_has_vsock() -> bool {
+    Path::new("/dev/vhost-vsock").exists()
+}
+
+/// The transports a booted machine offers its guest agent on. Which one is
+/// used is settled by [`negotiate`] once the agent answers.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AgentEndpoints {
+    /// CID of the domain's vsock device, on hosts with `vhost-vsock`.
+    pub vsock_cid: Option<u32>,
+    /// Host socket of the domain's virtio-serial agent channel.
+    pub channel: Option<PathBuf>,
+}
+
+impl AgentEndpoints {
+    /// A connector for every transport, vsock first.
+    pub fn connectors(&self) -> Vec<AgentConnector> {
+        self.vsock_cid
+            .map(AgentConnector::Vsock)
+            .into_iter()
+            .chain(self.channel.clone().map(AgentConnector::Serial))
+            .collect()
+    }
+
+    /// The connector for the `negotiated` transport, or before negotiation
+    /// (or when that transport is gone) the preferred one.
+    pub fn connector(&self, negotiated: Option<&str>) -> Option<AgentConnector> {
+        let connectors = self.connectors();
+        connectors
+            .iter()
+            .find(|connector| Some(connector.transport()) == negotiated)
+            .or(connectors.first())
+            .cloned()
+    }
+}
+
+/// How long a guest that answered over the serial channel first gets to
+/// answer over vsock as well.
+const VSOCK_GRACE: Duration = Duration::from_secs(2);
+
+/// Wait for the agent on every transport of `endpoints` and return the one
+/// it answered on, preferring vsock, which also carries port forwards and
+/// readiness probes. Gives up after `timeout`, or never with `None`.
+pub async fn negotiate(
+    endpoints: &AgentEndpoints,
+    timeout: Option<Duration>,
+) -> Result<AgentConnector, ClientError> {
+    let attempts: Vec<_> = endpoints
+        .connectors()
+        .into_iter()
+        .map(|connector| {
+            Box::pin(async move {
+                wait_for_agent_within(connector.clone(), timeout)
+                    .await
+                    .map(|_| connector)
+            })
+        })
+        .collect();
+    if attempts.is_empty() {
+        return Err(ClientError::AgentTimeout {
+            timeout_secs: timeout.unwrap_or_default().as_secs(),
+            message: "the machine offers neither vsock nor an agent channel".into(),
+        });
+    }
+    let (mut chosen, rest) = futures_util::future::select_ok(attempts).await?;
+    if let AgentConnector::Serial(_) = chosen
+        && let Some(vsock) = rest.into_iter().next()
+        && let Ok(Ok(connector)) = tokio::time::timeout(VSOCK_GRACE, vsock).await
+    {
+        chosen = connector;
+    }
+    // The serial attempt may still hold the channel.
+    for connector in endpoints.connectors() {
+        if connector != chosen {
+            forget_agent_client(&connector);
+        }
+    }
+    tracing::info!(transport = chosen.transport(), "reached the guest agent");
+    Ok(chosen)
+}
+
+/// Connection to the guest agent over either transport.
+pub enum AgentStream {
+    Vsock(VsockStream),
+    /// The channel's socket and the lock on it.
+    Serial(UnixStream, std::fs::File),
+}
+
+impl AsyncRead for AgentStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::Vsock(stream) => Pin::new(stream).poll_read(cx, buf),
+            Self::Serial(stream, _) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for AgentStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Self::Vsock(stream) => Pin::new(stream).poll_write(cx, buf),
+            Self::Serial(stream, _) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::Vsock(stream) => Pin::new(stream).poll_flush(cx),
+            Self::Serial(stream, _) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::Vsock(stream) => Pin::new(stream).poll_shutdown(cx),
+            Self::Serial(stream, _) => Pin::new(stream).poll_shutdown(cx),
+        }
     }
 }
 
@@ -53,8 +312,12 @@ pub async fn start_port_forwards(
     let mut handles = Vec::new();
 
     for pf in ports {
-        let bind_addr = format!("{}:{}", pf.bind_addr(), pf.host);
-        let listener = TcpListener::bind(&bind_addr).await.map_err(|e| Error::Io {
+        // Validation guarantees an IP or localhost; `SocketAddr` brackets
+        // IPv6 hosts.
+        let bind_addr = pf.socket_addr().ok_or_else(|| Error::Validation {
+            message: format!("port forward bind '{}' is not an IP address", pf.bind),
+        })?;
+        let listener = TcpListener::bind(bind_addr).await.map_err(|e| Error::Io {
             context: format!("binding port forward on {bind_addr}"),
             source: e,
         })?;
@@ -96,3 +359,161 @@ async fn proxy_connection(
     tokio::io::copy_bidirectional(&mut tcp, &mut vsock).await?;
     Ok(())
 }
+
+/// Listen on the unix socket `forward.host`, which only the current user
+/// may open, and proxy each connection to the guest socket `forward.guest`.
+pub fn start_socket_forward(cid: u32, forward: &SocketForward) -> Result<JoinHandle<()>, Error> {
+    use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+
+    let host = &forward.host;
+    let io_error = |context: String| move |source| Error::Io { context, source };
+    if let Some(dir) = host.parent() {
+        std::fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(dir)
+            .map_err(io_error(format!("creating {}", dir.display())))?;
+    }
+    // A socket left behind by an earlier daemon fails the bind.
+    match std::fs::remove_file(host) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => {
+            return Err(io_error(format!("removing {}", host.display()))(e));
+        }
+        _ => {}
+    }
+    let listener =
+        UnixListener::bind(host).map_err(io_error(format!("binding {}", host.display())))?;
+    std::fs::set_permissions(host, std::fs::Permissions::from_mode(0o600))
+        .map_err(io_error(format!("restricting {}", host.display())))?;
+
+    let guest = forward.guest.clone();
+    Ok(tokio::spawn(async move {
+        loop {
+            let (mut stream, _addr) = match listener.accept().await {
+                Ok(v) => v,
+                Err(e) => {
+                    tracing::error!(socket = guest, "forward accept error: {e}");
+                    continue;
+                }
+            };
+            let guest = guest.clone();
+            tokio::spawn(async move {
+                let proxied = async {
+                    let mut vsock = dial(cid, &guest, 0).await?;
+                    tokio::io::copy_bidirectional(&mut stream, &mut vsock).await
+                };
+                if let Err(e) = proxied.await {
+                    tracing::error!(socket = guest, "forward proxy error: {e}");
+                }
+            });
+        }
+    }))
+}
+
+/// Open a stream to `host:port` as resolved and reached from the guest.
+///
+/// Sent on the forward port as target port 0, followed by the
+/// length-prefixed host and the real port. A `host` starting with `/` is a
+/// unix socket in the guest. Agents without dial support try
+/// port 0 and close the stream, which surfaces here as an EOF error. The
+/// agent answers with one status byte, 0 once connected.
+pub async fn dial(cid: u32, host: &str, port: u16) -> std::io::Result<VsockStream> {
+    let len = u16::try_from(host.len()).map_err(|_| {
+        std::io::Error::new(std::io::ErrorKind::InvalidInput, "host name too long")
+    })?;
+    let mut vsock = VsockStream::connect(VsockAddr::new(cid, FORWARD_PORT)).await?;
+    vsock.write_u16(0).await?;
+    vsock.write_u16(len).await?;
+    vsock.write_all(host.as_bytes()).await?;
+    vsock.write_u16(port).await?;
+    match vsock.read_u8().await? {
+        0 => Ok(vsock),
+        _ => Err(std::io::Error::new(
+            std::io::ErrorKind::ConnectionRefused,
+            format!("guest could not connect to {host}:{port}"),
+        )),
+    }
+}
+
+/// Whether something in the guest accepts TCP connections on `port`.
+///
+/// The agent closes the forward right away when its own connect fails, so an
+/// immediate EOF means nothing is listening. A connection that stays open
+/// or sends data counts as up.
+pub async fn probe_tcp(cid: u32, port: u16) -> bool {
+    let Ok(mut vsock) = VsockStream::connect(VsockAddr::new(cid, FORWARD_PORT)).await else {
+        return false;
+    };
+    if vsock.write_u16(port).await.is_err() {
+        return false;
+    }
+    let mut buf = [0u8; 1];
+    match tokio::time::timeout(Duration::from_millis(500), vsock.read(&mut buf)).await {
+        Ok(Ok(n)) => n > 0,
+        Ok(Err(_)) => false,
+        Err(_) => true,
+    }
+}
+
+/// Whether `GET path` on guest `port` answers with a 2xx or 3xx status.
+pub async fn probe_http(cid: u32, port: u16, path: &str) -> bool {
+    let request = async {
+        let mut vsock = VsockStream::connect(VsockAddr::new(cid, FORWARD_PORT)).await?;
+        vsock.write_u16(port).await?;
+        vsock
+            .write_all(format!("GET {path} HTTP/1.0\r\nHost: localhost\r\n\r\n").as_bytes())
+            .await?;
+        let mut head = [0u8; 12];
+        vsock.read_exact(&mut head).await?;
+        Ok::<_, std::io::Error>(head)
+    };
+    let Ok(Ok(head)) = tokio::time::timeout(Duration::from_secs(5), request).await else {
+        return false;
+    };
+    // "HTTP/1.x NNN"
+    head.starts_with(b"HTTP/") && matches!(head[9], b'2' | b'3')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn endpoints_prefer_the_negotiated_transport_then_vsock() {
+        let both = AgentEndpoints {
+            vsock_cid: Some(3),
+            channel: Some(PathBuf::from("/w/agent.sock")),
+        };
+        let serial = AgentConnector::Serial(PathBuf::from("/w/agent.sock"));
+        assert_eq!(
+            both.connectors(),
+            [AgentConnector::Vsock(3), serial.clone()]
+        );
+        assert_eq!(both.connector(None), Some(AgentConnector::Vsock(3)));
+        assert_eq!(both.connector(Some("serial")), Some(serial.clone()));
+
+        let channel_only = AgentEndpoints {
+            vsock_cid: None,
+            ..both
+        };
+        assert_eq!(channel_only.connector(Some("vsock")), Some(serial));
+        assert_eq!(AgentEndpoints::default().connector(None), None);
+    }
+
+    #[test]
+    fn a_second_connection_to_the_agent_channel_is_refused() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("agent.sock");
+
+        let held = lock_channel(&path).unwrap();
+        let err = lock_channel(&path).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::ResourceBusy);
+        assert!(
+            err.to_string().contains("another rum process"),
+            "got: {err}"
+        );
+
+        drop(held);
+        lock_channel(&path).unwrap();
+    }
+}
@@ -1,10 +1,12 @@
-use std::path::{Path, PathBuf};
+use std::io::Read;
+use std::path::{Component, Path, PathBuf};
 use std::time::SystemTime;
 
 use futures_util::StreamExt;
 use indicatif::{ProgressBar, ProgressStyle};
 use tokio::io::AsyncWriteExt;
 
+use crate::config::ImageConfig;
 use crate::error::Error;
 
 /// Download a response body to a file, updating the progress bar as chunks arrive.
@@ -41,10 +43,21 @@ async fn download_to_file(
     Ok(())
 }
 
+/// Resolve a non-HTTP `image.base` (plain path or `file://` URL) to a path.
+///
+/// Returns `None` for HTTP(S) URLs. Relative paths are returned as-is;
+/// `load_config` anchors them to the config file's directory.
+pub fn local_path(base: &str) -> Option<PathBuf> {
+    if base.starts_with("http://") || base.starts_with("https://") {
+        return None;
+    }
+    Some(PathBuf::from(base.strip_prefix("file://").unwrap_or(base)))
+}
+
 /// Check whether the base image is already available locally (no download needed).
 pub fn is_cached(base: &str, cache_dir: &Path) -> bool {
-    if !base.starts_with("http://") && !base.starts_with("https://") {
-        return Path::new(base).exists();
+    if let Some(path) = local_path(base) {
+        return path.exists();
     }
     let filename = base.rsplit('/').next().unwrap_or("image.img");
     cache_dir.join(filename).exists()
@@ -52,16 +65,25 @@ pub fn is_cached(base: &str, cache_dir: &Path) -> bool {
 
 /// Ensure the base image is available locally, downloading if needed.
 /// Returns the path to the cached image file.
-pub async fn ensure_base_image(base: &str, cache_dir: &Path) -> Result<PathBuf, Error> {
-    if !base.starts_with("http://") && !base.starts_with("https://") {
-        let path = PathBuf::from(base);
+///
+/// When the config pins a checksum, local files and fresh downloads are
+/// verified against it. Cached downloads are verified once; the verified
+/// digest is stored next to the file so later runs skip re-hashing.
+pub async fn ensure_base_image(image: &ImageConfig, cache_dir: &Path) -> Result<PathBuf, Error> {
+    let base = image.base.as_str();
+    let expected = expected_digest(image);
+
+    if let Some(path) = local_path(base) {
         if !path.exists() {
             return Err(Error::Io {
                 context: format!("base image not found: {}", path.display()),
                 source: std::io::Error::new(std::io::ErrorKind::NotFound, "file not found"),
             });
         }
-        return Ok(path);
+        if let Some(expected) = &expected {
+            verify_digest(&path, expected).await?;
+        }
+        return link_local_image(&path, cache_dir).await;
     }
 
     let filename = base.rsplit('/').next().unwrap_or("image.img");
@@ -75,6 +97,9 @@ pub async fn ensure_base_image(base: &str, cache_dir: &Path) -> Result<PathBuf,
 
     let dest = cache_dir.join(filename);
     if dest.exists() {
+        if let Some(expected) = &expected {
+            verify_cached(&dest, expected).await?;
+        }
         tracing::info!(path = %dest.display(), "using cached base image");
         return Ok(dest);
     }
@@ -116,6 +141,14 @@ pub async fn ensure_base_image(base: &str, cache_dir: &Path) -> Result<PathBuf,
         return Err(e);
     }
 
+    if let Some(expected) = &expected {
+        if let Err(e) = verify_digest(&tmp_path, expected).await {
+            let _ = tokio::fs::remove_file(&tmp_path).await;
+            return Err(e);
+        }
+        write_verified(&dest, expected).await?;
+    }
+
     tokio::fs::rename(&tmp_path, &dest)
         .await
         .map_err(|e| Error::Io {
@@ -129,6 +162,277 @@ pub async fn ensure_base_image(base: &str, cache_dir: &Path) -> Result<PathBuf,
     Ok(dest)
 }
 
+/// Make a local base image available in the cache without re-downloading.
+///
+/// Tries a reflink copy first, which is instant on CoW filesystems and keeps
+/// the cache independent of the source file. Elsewhere a full copy would be
+/// too slow for multi-GB images, so the cache entry becomes a symlink.
+async fn link_local_image(source: &Path, cache_dir: &Path) -> Result<PathBuf, Error> {
+    let source = source.canonicalize().map_err(|e| Error::Io {
+        context: format!("resolving {}", source.display()),
+        source: e,
+    })?;
+    let meta = std::fs::metadata(&source).map_err(|e| Error::Io {
+        context: format!("reading metadata of {}", source.display()),
+        source: e,
+    })?;
+    let dest = cache_dir.join(local_cache_name(&source, &meta));
+    if std::fs::symlink_metadata(&dest).is_ok() {
+        return Ok(dest);
+    }
+
+    tokio::fs::create_dir_all(cache_dir)
+        .await
+        .map_err(|e| Error::Io {
+            context: format!("creating cache dir {}", cache_dir.display()),
+            source: e,
+        })?;
+    let _ = tokio::fs::remove_file(&dest).await;
+
+    let reflinked = tokio::process::Command::new("cp")
+        .arg("--reflink=always")
+        .arg(&source)
+        .arg(&dest)
+        .output()
+        .await
+        .is_ok_and(|output| output.status.success());
+    if reflinked {
+        tracing::info!(path = %dest.display(), "reflinked local base image into cache");
+        return Ok(dest);
+    }
+
+    let _ = tokio::fs::remove_file(&dest).await;
+    tokio::fs::symlink(&source, &dest)
+        .await
+        .map_err(|e| Error::Io {
+            context: format!("linking {} into cache", source.display()),
+            source: e,
+        })?;
+    tracing::info!(path = %dest.display(), "linked local base image into cache");
+    Ok(dest)
+}
+
+/// Cache entry name of the local image at `source`: its file name behind a
+/// hash of its path, size and modification time, so a different image with
+/// the same name, or the same file rewritten, gets an entry of its own.
+fn local_cache_name(source: &Path, meta: &std::fs::Metadata) -> String {
+    use sha2::Digest;
+
+    let modified = meta
+        .modified()
+        .ok()
+        .and_then(|time| time.duration_since(SystemTime::UNIX_EPOCH).ok())
+        .unwrap_or_default();
+    let mut hasher = sha2::Sha256::new();
+    hasher.update(source.as_os_str().as_encoded_bytes());
+    hasher.update(meta.len().to_le_bytes());
+    hasher.update(modified.as_nanos().to_le_bytes());
+    let key: String = hasher.finalize()[..8]
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect();
+    let filename = source.file_name().unwrap_or_default().to_string_lossy();
+    format!("local-{key}-{filename}")
+}
+
+/// A pinned image digest from the config.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Digest {
+    algorithm: &'static str,
+    hex: String,
+}
+
+/// Prefer SHA-512 when both digests are configured.
+fn expected_digest(image: &ImageConfig) -> Option<Digest> {
+    if let Some(hex) = &image.sha512 {
+        return Some(Digest {
+            algorithm: "sha512",
+            hex: hex.to_ascii_lowercase(),
+        });
+    }
+    image.sha256.as_ref().map(|hex| Digest {
+        algorithm: "sha256",
+        hex: hex.to_ascii_lowercase(),
+    })
+}
+
+/// Sidecar file recording the digest a cached image was verified against.
+fn verified_path(path: &Path, algorithm: &str) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(format!(".{algorithm}"));
+    path.with_file_name(name)
+}
+
+async fn verify_cached(path: &Path, expected: &Digest) -> Result<(), Error> {
+    let recorded = tokio::fs::read_to_string(verified_path(path, expected.algorithm)).await;
+    if recorded.is_ok_and(|digest| digest.trim() == expected.hex) {
+        return Ok(());
+    }
+    verify_digest(path, expected).await?;
+    write_verified(path, expected).await
+}
+
+async fn write_verified(path: &Path, expected: &Digest) -> Result<(), Error> {
+    let sidecar = verified_path(path, expected.algorithm);
+    tokio::fs::write(&sidecar, &expected.hex)
+        .await
+        .map_err(|e| Error::Io {
+            context: format!("writing {}", sidecar.display()),
+            source: e,
+        })
+}
+
+async fn verify_digest(path: &Path, expected: &Digest) -> Result<(), Error> {
+    tracing::info!(
+        path = %path.display(),
+        algorithm = expected.algorithm,
+        "verifying image checksum"
+    );
+    let file_path = path.to_path_buf();
+    let algorithm = expected.algorithm;
+    let actual = tokio::task::spawn_blocking(move || hash_file(&file_path, algorithm))
+        .await
+        .map_err(|e| Error::Io {
+            context: format!("hashing {}", path.display()),
+            source: std::io::Error::other(e),
+        })?
+        .map_err(|e| Error::Io {
+            context: format!("hashing {}", path.display()),
+            source: e,
+        })?;
+
+    if actual != expected.hex {
+        return Err(Error::ChecksumMismatch {
+            path: path.display().to_string(),
+            algorithm: expected.algorithm.into(),
+            expected: expected.hex.clone(),
+            actual,
+        });
+    }
+    Ok(())
+}
+
+fn hash_file(path: &Path, algorithm: &str) -> std::io::Result<String> {
+    fn stream<D: sha2::Digest>(path: &Path) -> std::io::Result<String> {
+        let mut file = std::fs::File::open(path)?;
+        let mut hasher = D::new();
+        let mut buf = vec![0u8; 1024 * 1024];
+        loop {
+            let n = file.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            hasher.update(&buf[..n]);
+        }
+        Ok(hasher
+            .finalize()
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect())
+    }
+
+    match algorithm {
+        "sha512" => stream::<sha2::Sha512>(path),
+        _ => stream::<sha2::Sha256>(path),
+    }
+}
+
+/// Flatten `overlay` and its backing chain into a standalone image at `dest`.
+///
+/// `source` is the configured base image the overlay was built from; it is
+/// stored next to the baked image so [`baked_image`] can tell when the config
+/// has moved on to a different base.
+pub async fn bake_overlay(overlay: &Path, dest: &Path, source: &str) -> Result<(), Error> {
+    if let Some(parent) = dest.parent() {
+        tokio::fs::create_dir_all(parent)
+            .await
+            .map_err(|e| Error::Io {
+                context: format!("creating cache dir {}", parent.display()),
+                source: e,
+            })?;
+    }
+
+    let tmp_path = dest.with_extension("part");
+    let _ = tokio::fs::remove_file(&tmp_path).await;
+
+    let output = tokio::process::Command::new("qemu-img")
+        .args(["convert", "-O", "qcow2"])
+        .arg(overlay)
+        .arg(&tmp_path)
+        .output()
+        .await
+        .map_err(|e| Error::ExternalCommand {
+            command: "qemu-img".into(),
+            message: e.to_string(),
+        })?;
+    if !output.status.success() {
+        let _ = tokio::fs::remove_file(&tmp_path).await;
+        return Err(Error::ExternalCommand {
+            command: "qemu-img".into(),
+            message: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        });
+    }
+
+    tokio::fs::rename(&tmp_path, dest)
+        .await
+        .map_err(|e| Error::Io {
+            context: format!("renaming {} to {}", tmp_path.display(), dest.display()),
+            source: e,
+        })?;
+    tokio::fs::write(baked_source_path(dest), source)
+        .await
+        .map_err(|e| Error::Io {
+            context: format!("recording source of {}", dest.display()),
+            source: e,
+        })?;
+
+    tracing::info!(path = %dest.display(), "baked image cached");
+    Ok(())
+}
+
+/// Return the baked image at `path` if it exists and was baked from `base`.
+pub fn baked_image(path: &Path, base: &str) -> Option<PathBuf> {
+    let source = std::fs::read_to_string(baked_source_path(path)).ok()?;
+    (path.exists() && source == base).then(|| path.to_path_buf())
+}
+
+fn baked_source_path(path: &Path) -> PathBuf {
+    path.with_extension("source")
+}
+
+/// Bookkeeping files stored next to cached images.
+fn is_sidecar(path: &Path) -> bool {
+    matches!(
+        path.extension().and_then(|e| e.to_str()),
+        Some("sha256" | "sha512" | "source")
+    )
+}
+
+/// Every sidecar a cached image at `path` can have.
+fn sidecars(path: &Path) -> [PathBuf; 3] {
+    [
+        verified_path(path, "sha256"),
+        verified_path(path, "sha512"),
+        baked_source_path(path),
+    ]
+}
+
+/// The cache entry `name` refers to. Names are a single plain file name, so
+/// `../x` or an absolute path cannot reach outside `cache_dir`.
+fn cache_entry(cache_dir: &Path, name: &str) -> Result<PathBuf, Error> {
+    let mut components = Path::new(name).components();
+    let plain = matches!(
+        (components.next(), components.next()),
+        (Some(Component::Normal(file)), None) if file == name
+    );
+    if !plain || is_sidecar(Path::new(name)) {
+        return Err(Error::Validation {
+            message: format!("invalid image name '{name}': expected a plain file name"),
+        });
+    }
+    Ok(cache_dir.join(name))
+}
+
 /// List all cached images with filename, size, and modification time.
 pub fn list_cached(cache_dir: &Path) -> Result<(), Error> {
     if !cache_dir.exists() {
@@ -143,6 +447,7 @@ pub fn list_cached(cache_dir: &Path) -> Result<(), Error> {
         })?
         .filter_map(|e| e.ok())
         .filter(|e| e.file_type().map(|t| t.is_file()).unwrap_or(false))
+        .filter(|e| !is_sidecar(&e.path()))
         .collect();
 
     if entries.is_empty() {
@@ -184,7 +489,7 @@ pub fn list_cached(cache_dir: &Path) -> Result<(), Error> {
 
 /// Delete a specific cached image by filename.
 pub fn delete_cached(cache_dir: &Path, name: &str) -> Result<(), Error> {
-    let path = cache_dir.join(name);
+    let path = cache_entry(cache_dir, name)?;
     if !path.exists() {
         return Err(Error::Io {
             context: format!(
@@ -207,6 +512,220 @@ pub fn delete_cached(cache_dir: &Path, name: &str) -> Result<(), Error> {
     Ok(())
 }
 
+/// Pack a cached image and its checksum/bake sidecars into a gzip tarball.
+pub fn export_cached(cache_dir: &Path, name: &str, dest: &Path) -> Result<(), Error> {
+    let path = cache_entry(cache_dir, name)?;
+    if !path.exists() {
+        return Err(Error::Io {
+            context: format!(
+                "cached image '{}' not found in {}",
+                name,
+                cache_dir.display()
+            ),
+            source: std::io::Error::new(std::io::ErrorKind::NotFound, "file not found"),
+        });
+    }
+
+    let members: Vec<PathBuf> = std::iter::once(path.clone())
+        .chain(sidecars(&path))
+        .filter(|p| p.exists())
+        .collect();
+
+    let mut command = std::process::Command::new("tar");
+    command
+        .args(["--create", "--gzip", "--sparse", "--dereference", "--file"])
+        .arg(dest)
+        .arg("--directory")
+        .arg(cache_dir);
+    for member in &members {
+        command.arg(member.file_name().unwrap_or_default());
+    }
+    run_tar(&mut command)?;
+
+    println!("Exported '{}' to {}", name, dest.display());
+    Ok(())
+}
+
+/// Add an image to the cache from a tarball made by [`export_cached`] or a
+/// plain image file. `name` overrides the cached file name, which must match
+/// the last path segment of `image.base` for `rum up` to pick it up.
+///
+/// Sidecars of an image previously cached under the same name are dropped,
+/// so a stale digest never vouches for the new file. Digests shipped in a
+/// tarball are checked against the image before they are kept.
+pub fn import_cached(cache_dir: &Path, src: &Path, name: Option<&str>) -> Result<(), Error> {
+    if let Some(name) = name {
+        cache_entry(cache_dir, name)?;
+    }
+    std::fs::create_dir_all(cache_dir).map_err(|e| Error::Io {
+        context: format!("creating cache dir {}", cache_dir.display()),
+        source: e,
+    })?;
+
+    let src_name = src
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .into_owned();
+    let is_tarball = [".tar", ".tar.gz", ".tgz"]
+        .iter()
+        .any(|ext| src_name.ends_with(ext));
+
+    if !is_tarball {
+        let dest = cache_entry(cache_dir, name.unwrap_or(&src_name))?;
+        remove_sidecars(&dest)?;
+        std::fs::copy(src, &dest).map_err(|e| Error::Io {
+            context: format!("copying {} to {}", src.display(), dest.display()),
+            source: e,
+        })?;
+        println!(
+            "Imported '{}'",
+            dest.file_name().unwrap_or_default().to_string_lossy()
+        );
+        return Ok(());
+    }
+
+    let staging = cache_dir.join(format!(".import-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&staging);
+    std::fs::create_dir_all(&staging).map_err(|e| Error::Io {
+        context: format!("creating {}", staging.display()),
+        source: e,
+    })?;
+    let result = import_tarball(cache_dir, src, &staging, name);
+    let _ = std::fs::remove_dir_all(&staging);
+    result
+}
+
+fn import_tarball(
+    cache_dir: &Path,
+    src: &Path,
+    staging: &Path,
+    name: Option<&str>,
+) -> Result<(), Error> {
+    run_tar(
+        std::process::Command::new("tar")
+            .args(["--extract", "--file"])
+            .arg(src)
+            .arg("--directory")
+            .arg(staging),
+    )?;
+
+    let files: Vec<PathBuf> = std::fs::read_dir(staging)
+        .map_err(|e| Error::Io {
+            context: format!("reading {}", staging.display()),
+            source: e,
+        })?
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| p.is_file())
+        .collect();
+    let images: Vec<&PathBuf> = files.iter().filter(|p| !is_sidecar(p)).collect();
+    let [image] = images.as_slice() else {
+        return Err(Error::Validation {
+            message: format!(
+                "expected exactly one image in {}, found {}",
+                src.display(),
+                images.len()
+            ),
+        });
+    };
+
+    let original = image
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .into_owned();
+    let dest = cache_entry(cache_dir, name.unwrap_or(&original))?;
+
+    // A recorded digest is only as good as the image it came with.
+    for sidecar in files.iter().filter(|p| is_sidecar(p)) {
+        if let Some(algorithm @ ("sha256" | "sha512")) =
+            sidecar.extension().and_then(|e| e.to_str())
+        {
+            check_sidecar(image, sidecar, algorithm)?;
+        }
+    }
+
+    remove_sidecars(&dest)?;
+    move_file(image, &dest)?;
+
+    // Sidecars travel with the image; rename them if the image was renamed.
+    for sidecar in files.iter().filter(|p| is_sidecar(p)) {
+        let target = match sidecar.extension().and_then(|e| e.to_str()) {
+            Some("source") => baked_source_path(&dest),
+            Some(algorithm) => verified_path(&dest, algorithm),
+            None => continue,
+        };
+        move_file(sidecar, &target)?;
+    }
+
+    println!(
+        "Imported '{}'",
+        dest.file_name().unwrap_or_default().to_string_lossy()
+    );
+    Ok(())
+}
+
+/// Fail unless the digest recorded in `sidecar` is the `algorithm` digest
+/// of `image`.
+fn check_sidecar(image: &Path, sidecar: &Path, algorithm: &str) -> Result<(), Error> {
+    let recorded = std::fs::read_to_string(sidecar).map_err(|e| Error::Io {
+        context: format!("reading {}", sidecar.display()),
+        source: e,
+    })?;
+    let expected = recorded.trim().to_ascii_lowercase();
+    let actual = hash_file(image, algorithm).map_err(|e| Error::Io {
+        context: format!("hashing {}", image.display()),
+        source: e,
+    })?;
+    if actual != expected {
+        return Err(Error::ChecksumMismatch {
+            path: image.display().to_string(),
+            algorithm: algorithm.into(),
+            expected,
+            actual,
+        });
+    }
+    Ok(())
+}
+
+fn remove_sidecars(path: &Path) -> Result<(), Error> {
+    for sidecar in sidecars(path) {
+        match std::fs::remove_file(&sidecar) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => {
+                return Err(Error::Io {
+                    context: format!("removing {}", sidecar.display()),
+                    source: e,
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+fn move_file(from: &Path, to: &Path) -> Result<(), Error> {
+    std::fs::rename(from, to).map_err(|e| Error::Io {
+        context: format!("moving {} to {}", from.display(), to.display()),
+        source: e,
+    })
+}
+
+fn run_tar(command: &mut std::process::Command) -> Result<(), Error> {
+    let output = command.output().map_err(|e| Error::ExternalCommand {
+        command: "tar".into(),
+        message: e.to_string(),
+    })?;
+    if !output.status.success() {
+        return Err(Error::ExternalCommand {
+            command: "tar".into(),
+            message: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        });
+    }
+    Ok(())
+}
+
 /// Delete all cached images.
 pub fn clear_cache(cache_dir: &Path) -> Result<(), Error> {
     if !cache_dir.exists() {
@@ -302,3 +821,134 @@ fn time_from_epoch(secs: u64) -> String {
 fn is_leap(y: i64) -> bool {
     y % 4 == 0 && (y % 100 != 0 || y % 400 == 0)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn local_cache_name_follows_path_and_contents() {
+        let dir = tempfile::tempdir().unwrap();
+        let (a, b) = (dir.path().join("a"), dir.path().join("b"));
+        std::fs::create_dir_all(&a).unwrap();
+        std::fs::create_dir_all(&b).unwrap();
+        let (first, second) = (a.join("disk.qcow2"), b.join("disk.qcow2"));
+        std::fs::write(&first, b"one").unwrap();
+        std::fs::write(&second, b"one").unwrap();
+
+        let name = |path: &Path| local_cache_name(path, &std::fs::metadata(path).unwrap());
+        let before = name(&first);
+        assert!(before.starts_with("local-") && before.ends_with("-disk.qcow2"));
+        assert_eq!(before, name(&first));
+        assert_ne!(before, name(&second));
+
+        std::fs::write(&first, b"rewritten").unwrap();
+        assert_ne!(before, name(&first));
+    }
+
+    fn sha256(bytes: &[u8]) -> String {
+        use sha2::Digest as _;
+        sha2::Sha256::digest(bytes)
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect()
+    }
+
+    #[test]
+    fn export_then_import_round_trips_image_and_sidecars() {
+        let (from, to) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        let image = from.path().join("base.qcow2");
+        std::fs::write(&image, b"image").unwrap();
+        std::fs::write(verified_path(&image, "sha256"), sha256(b"image")).unwrap();
+        std::fs::write(baked_source_path(&image), "https://example.com/base.qcow2").unwrap();
+        let tarball = from.path().join("base.tar.gz");
+
+        export_cached(from.path(), "base.qcow2", &tarball).unwrap();
+        import_cached(to.path(), &tarball, None).unwrap();
+
+        let imported = to.path().join("base.qcow2");
+        assert_eq!(std::fs::read(&imported).unwrap(), b"image");
+        assert_eq!(
+            std::fs::read_to_string(verified_path(&imported, "sha256")).unwrap(),
+            sha256(b"image")
+        );
+        assert_eq!(
+            baked_image(&imported, "https://example.com/base.qcow2"),
+            Some(imported)
+        );
+    }
+
+    #[test]
+    fn import_renames_the_image_and_its_sidecars() {
+        let (from, to) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        let image = from.path().join("base.qcow2");
+        std::fs::write(&image, b"image").unwrap();
+        std::fs::write(verified_path(&image, "sha256"), sha256(b"image")).unwrap();
+        let tarball = from.path().join("base.tgz");
+        export_cached(from.path(), "base.qcow2", &tarball).unwrap();
+
+        import_cached(to.path(), &tarball, Some("renamed.qcow2")).unwrap();
+        import_cached(to.path(), &image, Some("plain.qcow2")).unwrap();
+
+        let renamed = to.path().join("renamed.qcow2");
+        assert_eq!(std::fs::read(&renamed).unwrap(), b"image");
+        assert!(verified_path(&renamed, "sha256").exists());
+        assert!(!to.path().join("base.qcow2").exists());
+        let plain = to.path().join("plain.qcow2");
+        assert_eq!(std::fs::read(plain).unwrap(), b"image");
+    }
+
+    #[test]
+    fn import_rejects_names_outside_the_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = dir.path().join("cache");
+        let src = dir.path().join("disk.qcow2");
+        std::fs::write(&src, b"image").unwrap();
+
+        for name in ["../escaped.qcow2", "/tmp/escaped.qcow2", "a/b", "..", ""] {
+            let result = import_cached(&cache, &src, Some(name));
+            assert!(
+                matches!(result, Err(Error::Validation { .. })),
+                "{name:?} was accepted"
+            );
+        }
+        assert!(!dir.path().join("escaped.qcow2").exists());
+        assert!(matches!(
+            delete_cached(&cache, "../disk.qcow2"),
+            Err(Error::Validation { .. })
+        ));
+        assert!(src.exists());
+    }
+
+    #[test]
+    fn import_drops_sidecars_of_the_image_it_replaces() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = dir.path().join("cache");
+        std::fs::create_dir_all(&cache).unwrap();
+        let cached = cache.join("disk.qcow2");
+        std::fs::write(&cached, b"old").unwrap();
+        std::fs::write(verified_path(&cached, "sha256"), sha256(b"old")).unwrap();
+        std::fs::write(baked_source_path(&cached), "old-base").unwrap();
+        let src = dir.path().join("disk.qcow2");
+        std::fs::write(&src, b"new").unwrap();
+
+        import_cached(&cache, &src, None).unwrap();
+
+        assert_eq!(std::fs::read(&cached).unwrap(), b"new");
+        assert!(sidecars(&cached).iter().all(|sidecar| !sidecar.exists()));
+    }
+
+    #[test]
+    fn import_rejects_a_tarball_whose_digest_does_not_match() {
+        let (from, to) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        let image = from.path().join("base.qcow2");
+        std::fs::write(&image, b"tampered").unwrap();
+        std::fs::write(verified_path(&image, "sha256"), sha256(b"image")).unwrap();
+        let tarball = from.path().join("base.tar.gz");
+        export_cached(from.path(), "base.qcow2", &tarball).unwrap();
+
+        let result = import_cached(to.path(), &tarball, None);
+        assert!(matches!(result, Err(Error::ChecksumMismatch { .. })));
+        assert!(!to.path().join("base.qcow2").exists());
+    }
+}
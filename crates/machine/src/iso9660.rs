@@ -1,4 +1,4 @@
-//! Minimal ISO 9660 image generator with Rock Ridge extensions.
+//! Minimal ISO 9660 image generator with Rock Ridge and Joliet extensions.
 //!
 //! # Background
 //!
@@ -17,17 +17,23 @@
 //! in base ISO 9660 Level 1, so we need Rock Ridge NM (alternate name) entries to
 //! preserve them.
 //!
+//! Windows does not read Rock Ridge.  It reads Joliet instead: a second
+//! directory tree, announced by a Supplementary Volume Descriptor, whose names
+//! are UCS-2.  We write both trees pointing at the same file data, so every
+//! reader sees the real names.
+//!
 //! # Scope
 //!
-//! This module only supports flat ISOs (files in the root directory, no
-//! subdirectories).  It is not a general-purpose ISO authoring library — it does
-//! exactly what cloud-init seed images need and nothing more.
+//! This module only supports flat ISOs (files and symlinks in the root
+//! directory, no subdirectories).  It is not a general-purpose ISO authoring
+//! library — it does exactly what cloud-init seed images need and nothing more.
 //!
 //! # References
 //!
 //! - ECMA-119 (ISO 9660): <https://ecma-international.org/publications-and-standards/standards/ecma-119/>
 //! - SUSP (IEEE P1281):    System Use Sharing Protocol
 //! - RRIP (IEEE P1282):    Rock Ridge Interchange Protocol
+//! - Joliet:               Microsoft's CD-ROM Recording Spec for Unicode names
 
 /// Each sector (also called a "logical block") in an ISO 9660 image is 2048 bytes.
 /// This is the native sector size of CD-ROMs and is hardcoded in the spec.
@@ -38,11 +44,31 @@ pub struct IsoFile<'a> {
     /// The filename as it should appear on Linux (e.g. `"meta-data"`).
     /// Will be stored as a Rock Ridge alternate name.
     pub name: &'a str,
-    /// The file contents (arbitrary bytes).
+    /// The file contents (arbitrary bytes).  Ignored for symlinks.
     pub data: &'a [u8],
+    /// Make this entry a symlink to the given path instead of a regular
+    /// file.  Stored as a Rock Ridge SL entry; readers without Rock Ridge
+    /// see an empty file.
+    pub symlink: Option<&'a str>,
 }
 
-/// Build an ISO 9660 image with Rock Ridge extensions.
+// Sector layout; see the table in [`build_iso`].
+const PVD_SECTOR: usize = 16;
+const JOLIET_SVD_SECTOR: usize = 17;
+const VDST_SECTOR: usize = 18;
+const PATH_TABLE_L_SECTOR: usize = 19;
+const PATH_TABLE_M_SECTOR: usize = 20;
+const ROOT_DIR_SECTOR: u32 = 21;
+const CE_SECTOR: u32 = 22;
+const JOLIET_PATH_TABLE_L_SECTOR: usize = 23;
+const JOLIET_PATH_TABLE_M_SECTOR: usize = 24;
+const JOLIET_ROOT_DIR_SECTOR: u32 = 25;
+const FIRST_FILE_SECTOR: usize = 26;
+
+/// Joliet identifiers are limited to 64 UCS-2 characters.
+const JOLIET_MAX_NAME: usize = 64;
+
+/// Build an ISO 9660 image with Rock Ridge and Joliet extensions.
 ///
 /// Returns the complete ISO image as a byte vector, ready to be written to disk.
 ///
@@ -51,7 +77,8 @@ pub struct IsoFile<'a> {
 ///
 /// # Panics
 ///
-/// Panics if `volume_id` is not ASCII or exceeds 32 characters.
+/// Panics if `volume_id` is not ASCII or exceeds 32 characters, or if the
+/// symlink targets do not fit in the continuation area.
 pub fn build_iso(volume_id: &str, files: &[IsoFile<'_>]) -> Vec<u8> {
     assert!(
         volume_id.len() <= 32 && volume_id.is_ascii(),
@@ -65,25 +92,29 @@ pub fn build_iso(volume_id: &str, files: &[IsoFile<'_>]) -> Vec<u8> {
     // ├──────────┼──────────────────────────────────────────────────────┤
     // │  0 – 15  │ System Area (all zeros, reserved for boot loaders)   │
     // │    16    │ Primary Volume Descriptor (PVD)                      │
-    // │    17    │ Volume Descriptor Set Terminator                     │
-    // │    18    │ Path Table (L-type, little-endian)                   │
-    // │    19    │ Path Table (M-type, big-endian)                      │
-    // │    20    │ Root Directory (., .., and file entries)             │
-    // │    21    │ SUSP Continuation Area (Rock Ridge ER entry)         │
-    // │  22+     │ File data (each file starts on a sector boundary)    │
+    // │    17    │ Joliet Supplementary Volume Descriptor (SVD)         │
+    // │    18    │ Volume Descriptor Set Terminator                     │
+    // │    19    │ Path Table (L-type, little-endian)                   │
+    // │    20    │ Path Table (M-type, big-endian)                      │
+    // │    21    │ Root Directory (., .., and file entries)             │
+    // │    22    │ SUSP Continuation Area (ER entry, symlink SL data)   │
+    // │    23    │ Joliet Path Table (L-type)                           │
+    // │    24    │ Joliet Path Table (M-type)                           │
+    // │    25    │ Joliet Root Directory (UCS-2 names)                  │
+    // │  26+     │ File data (each file starts on a sector boundary)    │
     // └──────────┴──────────────────────────────────────────────────────┘
 
-    let root_dir_sector = 20u32;
-    let ce_sector = 21u32;
-    let first_file_sector = 22usize;
-
     // Pre-calculate where each file's data will land.  Every file starts on a
-    // fresh sector boundary (required by ISO 9660).
+    // fresh sector boundary (required by ISO 9660).  Symlinks have no data.
     let mut file_layout: Vec<(usize, usize)> = Vec::with_capacity(files.len());
-    let mut next_sector = first_file_sector;
+    let mut next_sector = FIRST_FILE_SECTOR;
     for f in files {
-        file_layout.push((next_sector, f.data.len()));
-        next_sector += sectors_for(f.data.len());
+        if f.symlink.is_some() {
+            file_layout.push((0, 0));
+        } else {
+            file_layout.push((next_sector, f.data.len()));
+            next_sector += sectors_for(f.data.len());
+        }
     }
     let total_sectors = next_sector;
 
@@ -91,38 +122,84 @@ pub fn build_iso(volume_id: &str, files: &[IsoFile<'_>]) -> Vec<u8> {
     let mut iso = vec![0u8; total_sectors * SECTOR_SIZE];
 
     // Write each structural component.
-    write_pvd(&mut iso, volume_id, total_sectors as u32, root_dir_sector);
+    write_volume_descriptor(
+        &mut iso,
+        VolumeKind::Primary,
+        volume_id,
+        total_sectors as u32,
+    );
+    write_volume_descriptor(
+        &mut iso,
+        VolumeKind::Joliet,
+        volume_id,
+        total_sectors as u32,
+    );
     write_vdst(&mut iso);
-    write_path_table(&mut iso, 18, root_dir_sector, Endian::Little);
-    write_path_table(&mut iso, 19, root_dir_sector, Endian::Big);
-
-    let er_entry = susp_er();
-    write_root_directory(
+    write_path_table(
         &mut iso,
-        root_dir_sector,
-        ce_sector,
-        &er_entry,
-        files,
-        &file_layout,
+        PATH_TABLE_L_SECTOR,
+        ROOT_DIR_SECTOR,
+        Endian::Little,
+    );
+    write_path_table(&mut iso, PATH_TABLE_M_SECTOR, ROOT_DIR_SECTOR, Endian::Big);
+    write_path_table(
+        &mut iso,
+        JOLIET_PATH_TABLE_L_SECTOR,
+        JOLIET_ROOT_DIR_SECTOR,
+        Endian::Little,
+    );
+    write_path_table(
+        &mut iso,
+        JOLIET_PATH_TABLE_M_SECTOR,
+        JOLIET_ROOT_DIR_SECTOR,
+        Endian::Big,
     );
 
-    // Write the SUSP Continuation Area (sector 21).  This contains the ER
+    // Write the SUSP Continuation Area (sector 22).  It starts with the ER
     // (Extension Reference) entry that identifies Rock Ridge.  It lives in its
     // own sector because the ER entry is ~240 bytes — too large to fit in the
-    // "." directory record's system use area alongside the SP entry.
-    let ce_start = ce_sector as usize * SECTOR_SIZE;
-    iso[ce_start..ce_start + er_entry.len()].copy_from_slice(&er_entry);
+    // "." directory record's system use area alongside the SP entry.  Symlink
+    // targets are stored after it, since SL entries can outgrow a record too.
+    let mut continuation = susp_er();
+    let er_len = continuation.len() as u32;
+    let mut sl_areas = Vec::with_capacity(files.len());
+    for f in files {
+        sl_areas.push(f.symlink.map(|target| {
+            let sl = rrip_sl(target);
+            let area = (continuation.len() as u32, sl.len() as u32);
+            continuation.extend_from_slice(&sl);
+            area
+        }));
+    }
+    assert!(
+        continuation.len() <= SECTOR_SIZE,
+        "symlink targets do not fit in the SUSP continuation area"
+    );
+    let ce_start = CE_SECTOR as usize * SECTOR_SIZE;
+    iso[ce_start..ce_start + continuation.len()].copy_from_slice(&continuation);
+
+    write_root_directory(&mut iso, er_len, files, &file_layout, &sl_areas);
+    write_joliet_root_directory(&mut iso, files, &file_layout);
 
     // Write file contents into their pre-calculated sectors.
     for (i, f) in files.iter().enumerate() {
-        let offset = file_layout[i].0 * SECTOR_SIZE;
-        iso[offset..offset + f.data.len()].copy_from_slice(f.data);
+        if f.symlink.is_none() {
+            let offset = file_layout[i].0 * SECTOR_SIZE;
+            iso[offset..offset + f.data.len()].copy_from_slice(f.data);
+        }
     }
 
     iso
 }
 
-/// Write the Primary Volume Descriptor (PVD) at sector 16.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum VolumeKind {
+    Primary,
+    Joliet,
+}
+
+/// Write the Primary Volume Descriptor (PVD) at sector 16, or the Joliet
+/// Supplementary Volume Descriptor (SVD) at sector 17.
 ///
 /// The PVD is the main metadata block of the ISO.  It always lives at sector 16
 /// (the first sector after the system area) and is exactly one sector (2048 bytes).
@@ -131,12 +208,13 @@ pub fn build_iso(volume_id: &str, files: &[IsoFile<'_>]) -> Vec<u8> {
 ///
 /// | Offset | Size | Field                           |
 /// |--------|------|---------------------------------|
-/// |   0    |   1  | Type (1 = PVD)                  |
+/// |   0    |   1  | Type (1 = PVD, 2 = SVD)         |
 /// |   1    |   5  | Standard Identifier (`"CD001"`) |
 /// |   6    |   1  | Version (1)                     |
 /// |   8    |  32  | System Identifier (space-padded) |
 /// |  40    |  32  | Volume Identifier (space-padded) — the "label" |
 /// |  80    |   8  | Volume Space Size (both-endian) — total sectors |
+/// |  88    |  32  | Escape Sequences (SVD only)     |
 /// | 120    |   4  | Volume Set Size (both-endian)   |
 /// | 124    |   4  | Volume Sequence Number (both-endian) |
 /// | 128    |   4  | Logical Block Size (both-endian) — always 2048 |
@@ -146,30 +224,61 @@ pub fn build_iso(volume_id: &str, files: &[IsoFile<'_>]) -> Vec<u8> {
 /// | 156    |  34  | Root Directory Record (inline!)  |
 /// | 190    | 624  | Identifier strings (space-padded) |
 /// | 881    |   1  | File Structure Version (1)      |
-fn write_pvd(iso: &mut [u8], volume_id: &str, total_sectors: u32, root_dir_sector: u32) {
-    let pvd = &mut iso[16 * SECTOR_SIZE..17 * SECTOR_SIZE];
-    pvd[0] = 1;
-    pvd[1..6].copy_from_slice(b"CD001");
-    pvd[6] = 1;
+///
+/// The Joliet SVD has the same shape.  Its escape sequence `%/E` announces
+/// UCS-2 level 3, and its identifiers and directory names are big-endian
+/// UCS-2, which lets Windows read long, mixed-case names without Rock Ridge.
+fn write_volume_descriptor(iso: &mut [u8], kind: VolumeKind, volume_id: &str, total_sectors: u32) {
+    let (sector, tables, root_dir_sector) = match kind {
+        VolumeKind::Primary => (
+            PVD_SECTOR,
+            (PATH_TABLE_L_SECTOR, PATH_TABLE_M_SECTOR),
+            ROOT_DIR_SECTOR,
+        ),
+        VolumeKind::Joliet => (
+            JOLIET_SVD_SECTOR,
+            (JOLIET_PATH_TABLE_L_SECTOR, JOLIET_PATH_TABLE_M_SECTOR),
+            JOLIET_ROOT_DIR_SECTOR,
+        ),
+    };
+    let vd = &mut iso[sector * SECTOR_SIZE..(sector + 1) * SECTOR_SIZE];
+    vd[0] = if kind == VolumeKind::Joliet { 2 } else { 1 };
+    vd[1..6].copy_from_slice(b"CD001");
+    vd[6] = 1;
 
     // System identifier and volume identifier are space-padded fixed fields.
-    pvd[8..40].fill(b' ');
-    pvd[40..72].fill(b' ');
-    let vid = volume_id.as_bytes();
-    pvd[40..40 + vid.len()].copy_from_slice(vid);
-
-    put_u32_both(&mut pvd[80..88], total_sectors);
-    put_u16_both(&mut pvd[120..124], 1);
-    put_u16_both(&mut pvd[124..128], 1);
-    put_u16_both(&mut pvd[128..132], SECTOR_SIZE as u16);
-    put_u32_both(&mut pvd[132..140], 10); // path table = 10 bytes (one root entry)
-    pvd[140..144].copy_from_slice(&18u32.to_le_bytes()); // L path table at sector 18
-    pvd[148..152].copy_from_slice(&19u32.to_be_bytes()); // M path table at sector 19
-
-    // The root directory record is embedded directly in the PVD at byte 156.
-    // Its name is a single 0x00 byte (meaning "self" / ".").
+    // In the SVD, "space" is the UCS-2 space 0x00 0x20.
+    match kind {
+        VolumeKind::Primary => {
+            vd[8..40].fill(b' ');
+            vd[40..72].fill(b' ');
+            let vid = volume_id.as_bytes();
+            vd[40..40 + vid.len()].copy_from_slice(vid);
+        }
+        VolumeKind::Joliet => {
+            for field in [8..40, 40..72] {
+                for pair in vd[field].chunks_exact_mut(2) {
+                    pair.copy_from_slice(&[0x00, b' ']);
+                }
+            }
+            let vid = ucs2_be(volume_id, 16);
+            vd[40..40 + vid.len()].copy_from_slice(&vid);
+            vd[88..91].copy_from_slice(b"%/E");
+        }
+    }
+
+    put_u32_both(&mut vd[80..88], total_sectors);
+    put_u16_both(&mut vd[120..124], 1);
+    put_u16_both(&mut vd[124..128], 1);
+    put_u16_both(&mut vd[128..132], SECTOR_SIZE as u16);
+    put_u32_both(&mut vd[132..140], 10); // path table = 10 bytes (one root entry)
+    vd[140..144].copy_from_slice(&(tables.0 as u32).to_le_bytes());
+    vd[148..152].copy_from_slice(&(tables.1 as u32).to_be_bytes());
+
+    // The root directory record is embedded directly in the descriptor at
+    // byte 156.  Its name is a single 0x00 byte (meaning "self" / ".").
     write_fixed_dir_record(
-        &mut pvd[156..190],
+        &mut vd[156..190],
         root_dir_sector,
         SECTOR_SIZE as u32,
         b"\x00",
@@ -177,8 +286,15 @@ fn write_pvd(iso: &mut [u8], volume_id: &str, total_sectors: u32, root_dir_secto
     );
 
     // Remaining identifier fields (publisher, preparer, etc.) — space-padded.
-    pvd[190..814].fill(b' ');
-    pvd[881] = 1; // file structure version
+    match kind {
+        VolumeKind::Primary => vd[190..814].fill(b' '),
+        VolumeKind::Joliet => {
+            for pair in vd[190..814].chunks_exact_mut(2) {
+                pair.copy_from_slice(&[0x00, b' ']);
+            }
+        }
+    }
+    vd[881] = 1; // file structure version
 }
 
 /// ═══════════════════════════════════════════════════════════════════════════
@@ -186,10 +302,10 @@ fn write_pvd(iso: &mut [u8], volume_id: &str, total_sectors: u32, root_dir_secto
 /// ═══════════════════════════════════════════════════════════════════════════
 ///
 /// Marks the end of the volume descriptor sequence.  Readers scan sectors 16, 17,
-/// 18... until they find type 255.  We only have one descriptor (the PVD), so the
-/// terminator goes right at sector 17.
+/// 18... until they find type 255.  We have two descriptors (the PVD and the
+/// Joliet SVD), so the terminator goes right after them at sector 18.
 fn write_vdst(iso: &mut [u8]) {
-    let vdst = &mut iso[17 * SECTOR_SIZE..18 * SECTOR_SIZE];
+    let vdst = &mut iso[VDST_SECTOR * SECTOR_SIZE..(VDST_SECTOR + 1) * SECTOR_SIZE];
     vdst[0] = 255; // type = terminator
     vdst[1..6].copy_from_slice(b"CD001");
     vdst[6] = 1;
@@ -206,7 +322,7 @@ enum Endian {
 /// Path tables provide a flat index of all directories for fast lookup.  The
 /// spec requires two copies: one in little-endian (L-type) and one in big-endian
 /// (M-type), because ISO 9660 was designed to work on both architectures without
-/// byte-swapping.
+/// byte-swapping.  The Joliet tree has its own pair.
 ///
 /// Since we only have the root directory, each path table is just one 10-byte
 /// entry:
@@ -237,7 +353,7 @@ fn write_path_table(iso: &mut [u8], sector: usize, root_extent: u32, endian: End
     buf[9] = 0x00; // padding
 }
 
-/// Write the root directory extent at the given sector.
+/// Write the root directory extent at sector 21.
 ///
 /// The root directory is a sequence of variable-length Directory Records packed
 /// into one or more sectors.  It always starts with `.` (self) and `..` (parent),
@@ -247,62 +363,68 @@ fn write_path_table(iso: &mut [u8], sector: usize, root_extent: u32, endian: End
 /// filename — this is where we put NM (alternate name) and PX (POSIX attributes)
 /// entries.  The `.` record also carries an SP (SUSP indicator) entry, plus a CE
 /// (continuation) pointer to the ER (extension reference) in a separate sector.
+/// Symlinks get a CE pointer to their SL entry in that sector as well.
 ///
 /// ```text
-/// ┌─────────────────────────────── Sector 20 ───────────────────────────────┐
-/// │ "."  record  [SP][CE→sector 21]                                        │
+/// ┌─────────────────────────────── Sector 21 ───────────────────────────────┐
+/// │ "."  record  [SP][CE→sector 22]                                        │
 /// │ ".." record                                                            │
 /// │ file record  [NM="meta-data"][PX=0644]                                 │
 /// │ file record  [NM="user-data"][PX=0644]                                 │
-/// │ file record  [NM="network-config"][PX=0644]                            │
+/// │ link record  [NM="latest"][PX=0777 link][CE→SL in sector 22]           │
 /// │ (zero padding to end of sector)                                        │
 /// └────────────────────────────────────────────────────────────────────────-┘
 ///
-/// ┌─────────────────────────────── Sector 21 ───────────────────────────────┐
+/// ┌─────────────────────────────── Sector 22 ───────────────────────────────┐
 /// │ ER entry (RRIP_1991A identification, ~240 bytes)                       │
+/// │ SL entries of symlinks                                                 │
 /// │ (zero padding to end of sector)                                        │
 /// └─────────────────────────────────────────────────────────────────────────┘
 /// ```
 fn write_root_directory(
     iso: &mut [u8],
-    root_sector: u32,
-    ce_sector: u32,
-    er_entry: &[u8],
+    er_len: u32,
     files: &[IsoFile<'_>],
     file_layout: &[(usize, usize)],
+    sl_areas: &[Option<(u32, u32)>],
 ) {
-    let dir_start = root_sector as usize * SECTOR_SIZE;
+    let dir_start = ROOT_DIR_SECTOR as usize * SECTOR_SIZE;
     let mut pos = dir_start;
     let root_size = SECTOR_SIZE as u32;
 
     // "." entry — includes SP (SUSP presence marker) and CE (pointer to the ER
-    // entry in the continuation area at sector 21).
+    // entry in the continuation area at sector 22).
     let sp = susp_sp();
-    let ce = susp_ce(ce_sector, 0, er_entry.len() as u32);
+    let ce = susp_ce(CE_SECTOR, 0, er_len);
     let mut dot_su = Vec::with_capacity(sp.len() + ce.len());
     dot_su.extend_from_slice(&sp);
     dot_su.extend_from_slice(&ce);
-    let dot = dir_record(root_sector, root_size, b"\x00", true, &dot_su);
+    let dot = dir_record(ROOT_DIR_SECTOR, root_size, b"\x00", true, &dot_su);
     iso[pos..pos + dot.len()].copy_from_slice(&dot);
     pos += dot.len();
 
     // ".." entry — for the root directory, parent is itself.
-    let dotdot = dir_record(root_sector, root_size, b"\x01", true, &[]);
+    let dotdot = dir_record(ROOT_DIR_SECTOR, root_size, b"\x01", true, &[]);
     iso[pos..pos + dotdot.len()].copy_from_slice(&dotdot);
     pos += dotdot.len();
 
     // File entries.  Each gets:
     //   - An ISO 9660 Level 1 name (8.3 uppercase, e.g. "META_DAT;1")
     //   - A Rock Ridge NM entry with the real filename (e.g. "meta-data")
-    //   - A Rock Ridge PX entry with POSIX permissions (0644, regular file)
+    //   - A Rock Ridge PX entry with POSIX permissions (0644, regular file,
+    //     or 0777 for a symlink)
+    //   - For symlinks, a CE pointer to the SL entry holding the target
     for (i, f) in files.iter().enumerate() {
         let (sector, size) = file_layout[i];
         let iso_name = to_level1_name(f.name);
-        let nm = rrip_nm(f.name);
-        let px = rrip_px(0o100644, 1);
-        let mut su = Vec::with_capacity(nm.len() + px.len());
-        su.extend_from_slice(&nm);
-        su.extend_from_slice(&px);
+        let mut su = rrip_nm(f.name);
+        match sl_areas[i] {
+            Some((offset, len)) => {
+                su.extend_from_slice(&rrip_px(0o120777, 1));
+                su.extend_from_slice(&susp_ce(CE_SECTOR, offset, len));
+            }
+            None => su.extend_from_slice(&rrip_px(0o100644, 1)),
+        }
 
         let rec = dir_record(sector as u32, size as u32, iso_name.as_bytes(), false, &su);
         iso[pos..pos + rec.len()].copy_from_slice(&rec);
@@ -310,6 +432,38 @@ fn write_root_directory(
     }
 }
 
+/// Write the Joliet root directory extent at sector 25.
+///
+/// Joliet directories are plain ISO 9660 directory records whose names are
+/// big-endian UCS-2 (with the `;1` version suffix) instead of Level 1 names.
+/// They point at the same file extents as the primary tree, so file data is
+/// stored only once.  Joliet has no symlinks; they show up as empty files.
+fn write_joliet_root_directory(
+    iso: &mut [u8],
+    files: &[IsoFile<'_>],
+    file_layout: &[(usize, usize)],
+) {
+    let mut pos = JOLIET_ROOT_DIR_SECTOR as usize * SECTOR_SIZE;
+    let root_size = SECTOR_SIZE as u32;
+    for name in [b"\x00", b"\x01"] {
+        let rec = dir_record(JOLIET_ROOT_DIR_SECTOR, root_size, name, true, &[]);
+        iso[pos..pos + rec.len()].copy_from_slice(&rec);
+        pos += rec.len();
+    }
+    for (i, f) in files.iter().enumerate() {
+        let (sector, size) = file_layout[i];
+        let rec = dir_record(
+            sector as u32,
+            size as u32,
+            &to_joliet_name(f.name),
+            false,
+            &[],
+        );
+        iso[pos..pos + rec.len()].copy_from_slice(&rec);
+        pos += rec.len();
+    }
+}
+
 /// Write a fixed-size (34-byte) directory record into a buffer.
 ///
 /// Used for the root directory record embedded in the PVD, which has no system
@@ -398,6 +552,8 @@ fn dir_record(extent: u32, size: u32, name: &[u8], is_dir: bool, su: &[u8]) -> V
 ///   e.g. `"meta-data"`, alongside the mangled ISO 9660 name `"META_DAT;1"`.
 /// - **PX** — POSIX Attributes (Rock Ridge).  Stores file mode, link count,
 ///   uid, gid.
+/// - **SL** — Symbolic Link (Rock Ridge).  Stores a symlink's target as a
+///   list of path components.
 ///
 ///   Bytes: "SP" | len=7 | ver=1 | check_byte_1=0xBE | check_byte_2=0xEF | skip=0
 fn susp_sp() -> Vec<u8> {
@@ -493,6 +649,54 @@ fn rrip_px(mode: u32, nlinks: u32) -> Vec<u8> {
     buf
 }
 
+/// RRIP SL — symbolic link target.
+///
+///   Bytes: "SL" | len | ver=1 | flags | component records...
+///
+/// The target is split into component records, each
+/// `flags | len | bytes`, where the component flags mark the special
+/// components: 0x02 = `.`, 0x04 = `..`, 0x08 = the root `/`.  An entry holds
+/// at most 255 bytes, so long targets are spread over several SL entries,
+/// each but the last with entry flag 0x01 ("continues in the next SL").
+fn rrip_sl(target: &str) -> Vec<u8> {
+    let mut components: Vec<Vec<u8>> = Vec::new();
+    if target.starts_with('/') {
+        components.push(vec![0x08, 0]);
+    }
+    for part in target.split('/').filter(|p| !p.is_empty()) {
+        match part {
+            "." => components.push(vec![0x02, 0]),
+            ".." => components.push(vec![0x04, 0]),
+            name => {
+                let mut component = vec![0, name.len() as u8];
+                component.extend_from_slice(name.as_bytes());
+                components.push(component);
+            }
+        }
+    }
+
+    let mut entries: Vec<Vec<u8>> = vec![Vec::new()];
+    for component in components {
+        if entries
+            .last()
+            .is_some_and(|e| 5 + e.len() + component.len() > 255)
+        {
+            entries.push(Vec::new());
+        }
+        if let Some(entry) = entries.last_mut() {
+            entry.extend_from_slice(&component);
+        }
+    }
+    let count = entries.len();
+    let mut buf = Vec::new();
+    for (i, body) in entries.into_iter().enumerate() {
+        let continues = u8::from(i + 1 < count);
+        buf.extend_from_slice(&[b'S', b'L', (5 + body.len()) as u8, 1, continues]);
+        buf.extend_from_slice(&body);
+    }
+    buf
+}
+
 /// Convert a filename to ISO 9660 Level 1 format.
 ///
 /// Level 1 is the most restrictive (and most compatible) filename format:
@@ -531,6 +735,35 @@ fn to_level1_name(name: &str) -> String {
     }
 }
 
+/// Convert a filename to a Joliet identifier: big-endian UCS-2 with a `;1`
+/// version suffix.
+///
+/// Joliet keeps case and long names, but still forbids `*`, `/`, `:`, `;`,
+/// `?` and `\`, which are replaced by `_`.  Names are cut at 64 characters.
+///
+/// Example: "meta-data" → 00 6D 00 65 ... 00 61 00 3B 00 31
+fn to_joliet_name(name: &str) -> Vec<u8> {
+    let sanitized: String = name
+        .chars()
+        .map(|c| match c {
+            '*' | '/' | ':' | ';' | '?' | '\\' => '_',
+            c => c,
+        })
+        .collect();
+    let mut id = ucs2_be(&sanitized, JOLIET_MAX_NAME);
+    id.extend_from_slice(&ucs2_be(";1", 2));
+    id
+}
+
+/// Encode up to `max_chars` UTF-16 code units of `value` as big-endian UCS-2.
+fn ucs2_be(value: &str, max_chars: usize) -> Vec<u8> {
+    value
+        .encode_utf16()
+        .take(max_chars)
+        .flat_map(u16::to_be_bytes)
+        .collect()
+}
+
 /// How many sectors are needed to hold `bytes` of data.
 /// Empty files still occupy one sector.
 fn sectors_for(bytes: usize) -> usize {
@@ -571,14 +804,17 @@ mod tests {
                 IsoFile {
                     name: "meta-data",
                     data: b"instance-id: test\n",
+                    symlink: None,
                 },
                 IsoFile {
                     name: "user-data",
                     data: b"#cloud-config\n",
+                    symlink: None,
                 },
                 IsoFile {
                     name: "network-config",
                     data: b"version: 2\n",
+                    symlink: None,
                 },
             ],
         )
@@ -600,8 +836,8 @@ mod tests {
     #[test]
     fn iso_has_terminator() {
         let iso = sample_iso();
-        assert_eq!(iso[17 * SECTOR_SIZE], 255);
-        assert_eq!(&iso[17 * SECTOR_SIZE + 1..17 * SECTOR_SIZE + 6], b"CD001");
+        assert_eq!(iso[18 * SECTOR_SIZE], 255);
+        assert_eq!(&iso[18 * SECTOR_SIZE + 1..18 * SECTOR_SIZE + 6], b"CD001");
     }
 
     #[test]
@@ -650,7 +886,7 @@ mod tests {
     #[test]
     fn iso_root_directory_has_dot_entries() {
         let iso = sample_iso();
-        let root_start = 20 * SECTOR_SIZE;
+        let root_start = 21 * SECTOR_SIZE;
         let first_name_len = iso[root_start + 32] as usize;
         assert_eq!(first_name_len, 1);
         assert_eq!(iso[root_start + 33], 0x00); // "." = 0x00
@@ -677,6 +913,7 @@ mod tests {
             &[IsoFile {
                 name: "empty",
                 data: b"",
+                symlink: None,
             }],
         );
         assert_eq!(&iso[0x8001..0x8006], b"CD001");
@@ -691,32 +928,113 @@ mod tests {
             &[IsoFile {
                 name: "big.bin",
                 data: &big,
+                symlink: None,
             }],
         );
-        // system(16) + pvd(1) + vdst(1) + pt_l(1) + pt_m(1) + rootdir(1) + ce(1) + file(3)
-        let expected_sectors = 16 + 1 + 1 + 1 + 1 + 1 + 1 + 3;
+        // system(16) + pvd(1) + svd(1) + vdst(1) + pt_l(1) + pt_m(1) + rootdir(1) + ce(1)
+        // + joliet pt_l(1) + joliet pt_m(1) + joliet rootdir(1) + file(3)
+        let expected_sectors = 16 + 1 + 1 + 1 + 1 + 1 + 1 + 1 + 1 + 1 + 1 + 3;
         assert_eq!(iso.len(), expected_sectors * SECTOR_SIZE);
-        let file_start = 22 * SECTOR_SIZE;
+        let file_start = 26 * SECTOR_SIZE;
         assert_eq!(&iso[file_start..file_start + 5000], big.as_slice());
     }
 
     #[test]
     fn iso_path_table_points_to_root() {
         let iso = sample_iso();
-        // L-type path table at sector 18.
-        let pt = &iso[18 * SECTOR_SIZE..];
+        // L-type path table at sector 19.
+        let pt = &iso[19 * SECTOR_SIZE..];
         let extent = u32::from_le_bytes([pt[2], pt[3], pt[4], pt[5]]);
         assert_eq!(
-            extent, 20,
-            "L path table should point to root dir at sector 20"
+            extent, 21,
+            "L path table should point to root dir at sector 21"
         );
 
-        // M-type path table at sector 19.
-        let pt = &iso[19 * SECTOR_SIZE..];
+        // M-type path table at sector 20.
+        let pt = &iso[20 * SECTOR_SIZE..];
         let extent = u32::from_be_bytes([pt[2], pt[3], pt[4], pt[5]]);
         assert_eq!(
-            extent, 20,
-            "M path table should point to root dir at sector 20"
+            extent, 21,
+            "M path table should point to root dir at sector 21"
         );
     }
+
+    #[test]
+    fn iso_has_joliet_supplementary_descriptor() {
+        let iso = sample_iso();
+        let svd = &iso[17 * SECTOR_SIZE..18 * SECTOR_SIZE];
+        assert_eq!(svd[0], 2);
+        assert_eq!(&svd[1..6], b"CD001");
+        assert_eq!(&svd[88..91], b"%/E");
+        assert_eq!(&svd[40..52], &ucs2_be("CIDATA", 16)[..]);
+        let root_extent = u32::from_le_bytes([svd[158], svd[159], svd[160], svd[161]]);
+        assert_eq!(root_extent, 25);
+    }
+
+    #[test]
+    fn iso_joliet_tree_keeps_long_names_and_shares_data() {
+        let iso = sample_iso();
+        let root = &iso[25 * SECTOR_SIZE..26 * SECTOR_SIZE];
+        let name = to_joliet_name("network-config");
+        assert_eq!(&name[name.len() - 4..], &[0, b';', 0, b'1']);
+        let at = root
+            .windows(name.len())
+            .position(|w| w == name.as_slice())
+            .expect("missing Joliet name for network-config");
+
+        // The Joliet record points at the same extent as the primary one.
+        let record = &root[at - 33..];
+        let extent = u32::from_le_bytes([record[2], record[3], record[4], record[5]]);
+        let data_start = extent as usize * SECTOR_SIZE;
+        assert_eq!(&iso[data_start..data_start + 11], b"version: 2\n");
+    }
+
+    #[test]
+    fn iso_joliet_name_replaces_forbidden_chars() {
+        assert_eq!(to_joliet_name("a:b"), ucs2_be("a_b;1", 8));
+        assert_eq!(to_joliet_name(&"x".repeat(80)).len(), (64 + 2) * 2);
+    }
+
+    #[test]
+    fn iso_symlink_has_sl_entry_and_no_data() {
+        let iso = build_iso(
+            "TEST",
+            &[
+                IsoFile {
+                    name: "data",
+                    data: b"hello",
+                    symlink: None,
+                },
+                IsoFile {
+                    name: "latest",
+                    data: b"",
+                    symlink: Some("/var/lib/../data"),
+                },
+            ],
+        );
+        // The symlink takes no data sector.
+        assert_eq!(iso.len(), 27 * SECTOR_SIZE);
+
+        let sl = rrip_sl("/var/lib/../data");
+        assert_eq!(&sl[..5], &[b'S', b'L', sl.len() as u8, 1, 0]);
+        assert_eq!(&sl[5..7], &[0x08, 0]); // root
+        assert!(sl.windows(5).any(|w| w == [0x04, 0, 0, 4, b'd']));
+        let ce = &iso[22 * SECTOR_SIZE..23 * SECTOR_SIZE];
+        assert!(ce.windows(sl.len()).any(|w| w == sl.as_slice()));
+
+        let link_mode = 0o120777u32.to_le_bytes();
+        let root = &iso[21 * SECTOR_SIZE..22 * SECTOR_SIZE];
+        assert!(root.windows(4).any(|w| w == link_mode));
+    }
+
+    #[test]
+    fn iso_long_symlink_target_spans_sl_entries() {
+        let target = vec!["component"; 40].join("/");
+        let sl = rrip_sl(&target);
+        assert!(sl.len() > 255);
+        assert_eq!(sl[4], 1, "first entry continues");
+        let second = sl[2] as usize;
+        assert_eq!(&sl[second..second + 2], b"SL");
+        assert_eq!(sl[second + 4], 0, "last entry does not continue");
+    }
 }
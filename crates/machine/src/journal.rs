@@ -0,0 +1,219 @@
+//! Lifecycle journal for crash recovery.
+//!
+//! The daemon appends one JSON line per lifecycle phase it enters to a file
+//! in the work dir, together with the base image it resolved. A run whose
+//! last entry is not a settled phase and whose daemon is gone was
+//! interrupted, for example by a crash or a killed process in the middle of
+//! a boot. `rum up --resume` reuses the journaled base image instead of
+//! resolving it again, and continues from the state recovery finds the
+//! machine in.
+//!
+//! A daemon counts as gone unless a process with its PID *and* its start
+//! time is running, so a PID the kernel handed out again after a crash does
+//! not keep an interrupted run looking live.
+
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use facet::Facet;
+
+use crate::error::Error;
+
+/// Phases a run can rest in; a journal ending in any other was interrupted.
+const SETTLED_PHASES: &[&str] = &["Running", "Stopped", "Failed"];
+
+/// One lifecycle phase entered by a daemon.
+#[derive(Debug, Clone, PartialEq, Eq, Facet)]
+pub struct JournalEntry {
+    /// Label of the phase, e.g. `Booting`.
+    pub phase: String,
+    /// Seconds since the Unix epoch.
+    pub at: u64,
+    /// PID of the daemon that wrote the entry.
+    pub pid: u32,
+    /// Start time of that daemon, in clock ticks since boot (field 22 of
+    /// `/proc/<pid>/stat`).
+    #[facet(default)]
+    pub started: Option<u64>,
+    pub base_image: Option<String>,
+}
+
+impl JournalEntry {
+    /// An entry for `phase` written by the current process, now.
+    pub fn now(phase: impl Into<String>) -> Self {
+        let at = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        let pid = std::process::id();
+        Self {
+            phase: phase.into(),
+            at,
+            pid,
+            started: process_start(pid),
+            base_image: None,
+        }
+    }
+}
+
+/// Start a new journal at `path`, dropping the previous run's entries.
+pub fn reset(path: &Path) -> Result<(), Error> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).map_err(|e| journal_error(path, e))?;
+    }
+    std::fs::write(path, "").map_err(|e| journal_error(path, e))
+}
+
+/// Append `entry` to the journal at `path`.
+pub fn append(path: &Path, entry: &JournalEntry) -> Result<(), Error> {
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|e| journal_error(path, e))?;
+    writeln!(file, "{}", facet_json::to_string(entry)).map_err(|e| journal_error(path, e))
+}
+
+/// Every entry of the journal at `path`. A missing journal is empty, and a
+/// line cut short by a crash is skipped.
+pub fn read(path: &Path) -> Vec<JournalEntry> {
+    let Ok(contents) = std::fs::read_to_string(path) else {
+        return Vec::new();
+    };
+    contents
+        .lines()
+        .filter_map(|line| facet_json::from_str(line).ok())
+        .collect()
+}
+
+/// What an interrupted run left behind.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InterruptedRun {
+    /// Label of the last phase the run entered.
+    pub phase: String,
+    pub base_image: Option<PathBuf>,
+}
+
+/// The interrupted run recorded in `entries`, if the last one was.
+///
+/// `is_alive` tells whether the daemon that wrote an entry is still
+/// running; a live daemon is still working on its run.
+pub fn interrupted(
+    entries: &[JournalEntry],
+    is_alive: impl Fn(&JournalEntry) -> bool,
+) -> Option<InterruptedRun> {
+    let last = entries.last()?;
+    if SETTLED_PHASES.contains(&last.phase.as_str()) || is_alive(last) {
+        return None;
+    }
+    Some(InterruptedRun {
+        phase: last.phase.clone(),
+        base_image: entries
+            .iter()
+            .rev()
+            .find_map(|e| e.base_image.as_ref())
+            .map(PathBuf::from),
+    })
+}
+
+/// The interrupted run journaled at `path`, checking daemons against `/proc`.
+pub fn interrupted_run(path: &Path) -> Option<InterruptedRun> {
+    interrupted(&read(path), |entry| {
+        let running = process_start(entry.pid);
+        running.is_some() && entry.started.is_none_or(|started| running == Some(started))
+    })
+}
+
+/// Start time of process `pid` in clock ticks since boot, or `None` when no
+/// such process runs.
+fn process_start(pid: u32) -> Option<u64> {
+    let stat = std::fs::read_to_string(format!("/proc/{pid}/stat")).ok()?;
+    parse_start_time(&stat)
+}
+
+/// Field 22 (`starttime`) of a `/proc/<pid>/stat` line. The command name in
+/// field 2 may contain spaces and parentheses, so fields are counted from
+/// the last `)`.
+fn parse_start_time(stat: &str) -> Option<u64> {
+    let (_, fields) = stat.rsplit_once(')')?;
+    fields.split_whitespace().nth(19)?.parse().ok()
+}
+
+fn journal_error(path: &Path, source: std::io::Error) -> Error {
+    Error::Io {
+        context: format!("writing journal {}", path.display()),
+        source,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(phase: &str, pid: u32) -> JournalEntry {
+        JournalEntry {
+            phase: phase.into(),
+            at: 0,
+            pid,
+            started: Some(100),
+            base_image: None,
+        }
+    }
+
+    #[test]
+    fn journal_round_trips_and_skips_torn_lines() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("work/journal.jsonl");
+        reset(&path).unwrap();
+        let mut booting = entry("Booting", 7);
+        booting.base_image = Some("/cache/base.qcow2".into());
+        append(&path, &entry("Preparing", 7)).unwrap();
+        append(&path, &booting).unwrap();
+        std::fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap()
+            .write_all(b"{\"phase\":\"Conn")
+            .unwrap();
+
+        assert_eq!(read(&path), vec![entry("Preparing", 7), booting]);
+        reset(&path).unwrap();
+        assert!(read(&path).is_empty());
+    }
+
+    #[test]
+    fn only_runs_stopped_midway_by_a_dead_daemon_are_interrupted() {
+        let mut booting = entry("Booting", 7);
+        booting.base_image = Some("/cache/base.qcow2".into());
+        let entries = vec![entry("Preparing", 7), booting, entry("Connecting guest", 7)];
+
+        assert_eq!(
+            interrupted(&entries, |_| false),
+            Some(InterruptedRun {
+                phase: "Connecting guest".into(),
+                base_image: Some("/cache/base.qcow2".into()),
+            })
+        );
+        assert_eq!(interrupted(&entries, |_| true), None);
+        assert_eq!(interrupted(&[entry("Running", 7)], |_| false), None);
+        assert_eq!(interrupted(&[], |_| false), None);
+    }
+
+    #[test]
+    fn start_time_is_counted_from_the_end_of_the_command_name() {
+        let stat = "4242 (rum (daemon) x) S 1 4242 4242 0 -1 4194560 1 0 0 0 \
+                    0 0 0 0 20 0 1 0 123456 1000 10";
+        assert_eq!(parse_start_time(stat), Some(123456));
+        assert_eq!(parse_start_time("4242 (rum) S 1"), None);
+        assert_eq!(parse_start_time(""), None);
+    }
+
+    #[test]
+    fn journal_entries_record_this_process_start_time() {
+        let entry = JournalEntry::now("Booting");
+        assert_eq!(entry.pid, std::process::id());
+        assert!(entry.started.is_some());
+        assert_eq!(entry.started, process_start(entry.pid));
+    }
+}
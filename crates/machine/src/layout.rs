@@ -10,11 +10,21 @@ pub struct MachineLayout {
     pub display_name: String,
     pub work_dir: PathBuf,
     pub overlay_path: PathBuf,
+    pub provisioned_layer: PathBuf,
     pub xml_path: PathBuf,
+    pub nvram_path: PathBuf,
     pub config_path_file: PathBuf,
     pub ssh_key_path: PathBuf,
+    pub sshfs_key_path: PathBuf,
+    pub ssh_control_path: PathBuf,
+    pub agent_channel: PathBuf,
+    pub agent_transport: PathBuf,
     pub logs_dir: PathBuf,
     pub provisioned_marker: PathBuf,
+    pub provision_hashes: PathBuf,
+    pub provisioned_hashes: PathBuf,
+    pub live_mounts: PathBuf,
+    pub applied_config: PathBuf,
 }
 
 impl MachineLayout {
@@ -29,11 +39,21 @@ impl MachineLayout {
             display_name: system.display_name().to_string(),
             work_dir: paths::work_dir(&system.id, name_opt),
             overlay_path: paths::overlay_path(&system.id, name_opt),
+            provisioned_layer: paths::provisioned_layer_path(&system.id, name_opt),
             xml_path: paths::domain_xml_path(&system.id, name_opt),
+            nvram_path: paths::nvram_path(&system.id, name_opt),
             config_path_file: paths::config_path_file(&system.id, name_opt),
             ssh_key_path: paths::ssh_key_path(&system.id, name_opt),
+            sshfs_key_path: paths::sshfs_key_path(&system.id, name_opt),
+            ssh_control_path: paths::ssh_control_path(&system.id, name_opt),
+            agent_channel: paths::agent_channel_path(&system.id, name_opt),
+            agent_transport: paths::agent_transport_path(&system.id, name_opt),
             logs_dir: paths::logs_dir(&system.id, name_opt),
             provisioned_marker: paths::provisioned_marker(&system.id, name_opt),
+            provision_hashes: paths::provision_hashes_path(&system.id, name_opt),
+            provisioned_hashes: paths::provisioned_hashes_path(&system.id, name_opt),
+            live_mounts: paths::live_mounts_path(&system.id, name_opt),
+            applied_config: paths::applied_config_path(&system.id, name_opt),
         }
     }
 
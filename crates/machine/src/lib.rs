@@ -1,14 +1,31 @@
 #![allow(unused_assignments)] // thiserror/miette proc macros trigger false positives
 
+pub mod audit;
+pub mod clean;
 pub mod cloudinit;
 pub mod config;
+pub mod doctor;
 pub mod guest;
 pub mod error;
+pub mod firmware;
 pub mod image;
 pub mod instance;
 pub mod iso9660;
+pub mod journal;
 pub mod layout;
+pub mod live_mount;
+pub mod lock;
+#[cfg(feature = "host-mkfs")]
+pub mod mkfs;
+pub mod nixos;
 pub mod paths;
+pub mod provision;
 pub mod driver;
 pub mod qcow2;
+pub mod registry;
+pub mod retry;
+pub mod socks;
+pub mod ssh_config;
+pub mod sync;
+pub mod test_run;
 pub mod util;
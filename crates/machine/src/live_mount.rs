@@ -0,0 +1,114 @@
+//! Bookkeeping for shares added to a running machine with `rum mount`.
+//!
+//! Live mounts are recorded in the work dir and merged into the config's
+//! `mounts` whenever it is loaded, so the next boot defines them in the
+//! domain and mounts them like any configured share.
+
+use std::path::Path;
+
+use crate::config::{Config, MountConfig};
+use crate::error::Error;
+
+/// Mounts recorded by `rum mount`; empty when none were added.
+pub fn recorded(path: &Path) -> Vec<MountConfig> {
+    std::fs::read_to_string(path)
+        .ok()
+        .and_then(|contents| facet_json::from_str(&contents).ok())
+        .unwrap_or_default()
+}
+
+/// Add the mounts recorded at `path` to `config`, unless the config already
+/// mounts something at the same target.
+pub fn merge(config: &mut Config, path: &Path) {
+    for mount in recorded(path) {
+        if !config.mounts.iter().any(|m| m.target == mount.target) {
+            config.mounts.push(mount);
+        }
+    }
+}
+
+/// Record `mount`, replacing an earlier one at the same target.
+pub fn record(path: &Path, mount: MountConfig) -> Result<(), Error> {
+    let mut mounts = recorded(path);
+    mounts.retain(|m| m.target != mount.target);
+    mounts.push(mount);
+    write(path, &mounts)
+}
+
+/// Drop the recorded mount at `target`. Returns whether there was one.
+pub fn forget(path: &Path, target: &str) -> Result<bool, Error> {
+    let mut mounts = recorded(path);
+    let before = mounts.len();
+    mounts.retain(|m| m.target != target);
+    if mounts.len() == before {
+        return Ok(false);
+    }
+    write(path, &mounts)?;
+    Ok(true)
+}
+
+/// Drop every recorded mount, e.g. when the machine is destroyed.
+pub fn clear(path: &Path) -> Result<(), Error> {
+    match std::fs::remove_file(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(Error::Io {
+            context: format!("removing {}", path.display()),
+            source: e,
+        }),
+        _ => Ok(()),
+    }
+}
+
+// Written atomically: a torn file would be silently read as "no mounts"
+// on every config load.
+fn write(path: &Path, mounts: &[MountConfig]) -> Result<(), Error> {
+    crate::util::write_atomic(path, facet_json::to_string(&mounts.to_vec()).as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mount(source: &str, target: &str) -> MountConfig {
+        MountConfig {
+            source: source.into(),
+            target: target.into(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn record_replaces_target_and_forget_removes_it() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("live-mounts.json");
+        assert!(recorded(&path).is_empty());
+
+        record(&path, mount("/srv/a", "/mnt/a")).unwrap();
+        record(&path, mount("/srv/b", "/mnt/b")).unwrap();
+        record(&path, mount("/srv/c", "/mnt/a")).unwrap();
+        let mounts = recorded(&path);
+        assert_eq!(mounts.len(), 2);
+        assert_eq!(mounts[1].source, "/srv/c");
+
+        assert!(forget(&path, "/mnt/b").unwrap());
+        assert!(!forget(&path, "/mnt/b").unwrap());
+        assert_eq!(recorded(&path).len(), 1);
+
+        clear(&path).unwrap();
+        clear(&path).unwrap();
+        assert!(recorded(&path).is_empty());
+    }
+
+    #[test]
+    fn merge_keeps_configured_targets() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("live-mounts.json");
+        record(&path, mount("/srv/live", "/mnt/a")).unwrap();
+        record(&path, mount("/srv/live", "/mnt/b")).unwrap();
+
+        let mut config = crate::config::tests::valid_config();
+        config.mounts = vec![mount("/srv/file", "/mnt/a")];
+        merge(&mut config, &path);
+        let sources: Vec<_> = config.mounts.iter().map(|m| m.source.as_str()).collect();
+        assert_eq!(sources, ["/srv/file", "/srv/live"]);
+    }
+}
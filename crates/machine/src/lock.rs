@@ -0,0 +1,81 @@
+//! Per-VM lock for commands that change a machine's state.
+//!
+//! `rum up`, `down`, `destroy` and friends take an exclusive `flock` on a
+//! file in the work dir, so a second concurrent command fails fast instead
+//! of racing libvirt defines and overlay creation. The holder's PID is
+//! written into the file for the error message. The lock goes away with the
+//! process, so a crashed command never leaves it behind.
+
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io::Write;
+use std::path::Path;
+
+use crate::error::Error;
+
+/// An acquired VM lock, released when dropped.
+#[derive(Debug)]
+pub struct VmLock {
+    _file: File,
+}
+
+impl VmLock {
+    /// Take the lock at `path` for the VM called `name` without waiting.
+    pub fn acquire(path: &Path, name: &str) -> Result<Self, Error> {
+        let io_error = |source| Error::Io {
+            context: format!("locking {}", path.display()),
+            source,
+        };
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).map_err(io_error)?;
+        }
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+            .map_err(io_error)?;
+
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => {
+                let pid = std::fs::read_to_string(path)
+                    .ok()
+                    .map(|pid| pid.trim().to_string())
+                    .filter(|pid| !pid.is_empty())
+                    .unwrap_or_else(|| "unknown".into());
+                return Err(Error::VmBusy {
+                    name: name.to_string(),
+                    pid,
+                });
+            }
+            Err(TryLockError::Error(e)) => return Err(io_error(e)),
+        }
+
+        file.set_len(0).map_err(io_error)?;
+        write!(file, "{}", std::process::id()).map_err(io_error)?;
+        Ok(Self { _file: file })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn second_holder_is_told_who_has_the_lock() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("work/rum.lock");
+
+        let lock = VmLock::acquire(&path, "dev").unwrap();
+        let err = VmLock::acquire(&path, "dev").unwrap_err();
+        let pid = std::process::id().to_string();
+        assert!(
+            matches!(&err, Error::VmBusy { pid: held, .. } if *held == pid),
+            "got: {err}"
+        );
+
+        drop(lock);
+        VmLock::acquire(&path, "dev").unwrap();
+    }
+}
@@ -0,0 +1,116 @@
+//! Host-side filesystem pre-build for extra drives.
+//!
+//! Minimal cloud images often ship without `mkfs.ext4` or `mkfs.xfs`, so the
+//! guest drive script has to install packages before it can format a new
+//! drive. When the host has the tools, we can format the drive before first
+//! boot instead: `mkfs` writes into a sparse raw file (no root or nbd needed)
+//! and `qemu-img convert` packs it into the qcow2 the domain expects. The
+//! guest script's `blkid` guard then sees an existing filesystem and skips
+//! both the package install and the format.
+//!
+//! Only compiled with the `host-mkfs` feature. Any failure here is reported
+//! to the caller, which falls back to an empty qcow2 and the guest script.
+
+use std::path::Path;
+use std::process::Command;
+
+use crate::error::Error;
+use crate::util::parse_size;
+
+/// Filesystems we know how to build on the host.
+pub fn supports(filesystem: &str) -> bool {
+    matches!(filesystem, "ext4" | "xfs")
+}
+
+/// Create a qcow2 image at `path` that already contains an empty
+/// `filesystem` spanning the whole virtual `size`.
+pub fn create_formatted_qcow2(path: &Path, size: &str, filesystem: &str) -> Result<(), Error> {
+    if !supports(filesystem) {
+        return Err(Error::Validation {
+            message: format!("host-side mkfs does not support '{filesystem}'"),
+        });
+    }
+    let virtual_size = parse_size(size)?;
+
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).map_err(|e| Error::Io {
+            context: format!("creating directory {}", parent.display()),
+            source: e,
+        })?;
+    }
+
+    let raw_path = path.with_extension("raw.tmp");
+    let result = build_raw(&raw_path, virtual_size, filesystem)
+        .and_then(|()| convert_to_qcow2(&raw_path, path));
+    let _ = std::fs::remove_file(&raw_path);
+    if result.is_err() {
+        let _ = std::fs::remove_file(path);
+    }
+    result?;
+
+    tracing::info!(path = %path.display(), size, filesystem, "created pre-formatted drive");
+    Ok(())
+}
+
+fn build_raw(raw_path: &Path, virtual_size: u64, filesystem: &str) -> Result<(), Error> {
+    let file = std::fs::File::create(raw_path).map_err(|e| Error::Io {
+        context: format!("creating raw image {}", raw_path.display()),
+        source: e,
+    })?;
+    file.set_len(virtual_size).map_err(|e| Error::Io {
+        context: format!("sizing raw image {}", raw_path.display()),
+        source: e,
+    })?;
+    drop(file);
+
+    let force = if filesystem == "xfs" { "-f" } else { "-F" };
+    run(Command::new(format!("mkfs.{filesystem}"))
+        .args(["-q", force])
+        .arg(raw_path))
+}
+
+fn convert_to_qcow2(raw_path: &Path, path: &Path) -> Result<(), Error> {
+    run(Command::new("qemu-img")
+        .args(["convert", "-f", "raw", "-O", "qcow2"])
+        .arg(raw_path)
+        .arg(path))
+}
+
+fn run(command: &mut Command) -> Result<(), Error> {
+    let program = command.get_program().to_string_lossy().into_owned();
+    let output = command.output().map_err(|e| Error::ExternalCommand {
+        command: program.clone(),
+        message: e.to_string(),
+    })?;
+    if !output.status.success() {
+        return Err(Error::ExternalCommand {
+            command: program,
+            message: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_ext4_and_xfs_are_built_on_the_host() {
+        assert!(supports("ext4"));
+        assert!(supports("xfs"));
+        assert!(!supports("btrfs"));
+        assert!(!supports("vfat"));
+    }
+
+    #[test]
+    fn invalid_requests_fail_before_touching_the_disk() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("drives/data.qcow2");
+
+        let error = create_formatted_qcow2(&path, "20G", "btrfs").unwrap_err();
+        assert!(matches!(error, Error::Validation { .. }), "{error}");
+        assert!(create_formatted_qcow2(&path, "lots", "ext4").is_err());
+        assert!(!path.parent().unwrap().exists());
+    }
+}
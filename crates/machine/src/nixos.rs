@@ -0,0 +1,297 @@
+//! NixOS module generation.
+//!
+//! NixOS keeps `/etc/fstab`, systemd units and packages in the Nix store, so
+//! the cloud-init and shell provisioning used for other distros cannot make
+//! them stick. Instead the host renders a module from the rum config that the
+//! guest agent installs as `/etc/nixos/rum.nix` and applies with
+//! `nixos-rebuild switch`.
+
+use std::collections::BTreeSet;
+use std::fmt::Write;
+
+use crate::config::{LuksDrive, MountDriver, ResolvedFs, ResolvedMount};
+
+/// Inputs for [`build_module`].
+pub struct ModuleConfig<'a> {
+    /// 8-hex-char instance id, reused as `networking.hostId` for ZFS.
+    pub host_id: &'a str,
+    pub user_name: &'a str,
+    pub user_groups: &'a [String],
+    pub autologin: bool,
+    /// `[provision] packages`, as nixpkgs attribute names.
+    pub packages: &'a [String],
+    pub mounts: &'a [ResolvedMount],
+    pub filesystems: &'a [ResolvedFs],
+    pub luks: &'a [LuksDrive],
+    /// `[docker] engine` when the engine is enabled.
+    pub docker: Option<&'a str>,
+}
+
+/// Quote `value` as a Nix string literal.
+fn nix_str(value: &str) -> String {
+    let escaped = value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace("${", "\\${");
+    format!("\"{escaped}\"")
+}
+
+fn nix_list(values: impl IntoIterator<Item = impl AsRef<str>>) -> String {
+    let items: Vec<String> = values.into_iter().map(|v| nix_str(v.as_ref())).collect();
+    if items.is_empty() {
+        "[ ]".into()
+    } else {
+        format!("[ {} ]", items.join(" "))
+    }
+}
+
+/// `pkgs` attribute of a package name such as `git` or
+/// `python3Packages.requests`, with every path segment quoted so a name can
+/// only ever select a package.
+fn nix_package(name: &str) -> String {
+    let path: Vec<String> = name.split('.').map(nix_str).collect();
+    format!("pkgs.{}", path.join("."))
+}
+
+fn file_system(module: &mut String, target: &str, device: &str, fs_type: &str, opts: &[&str]) {
+    writeln!(module, "  fileSystems.{} = {{", nix_str(target)).unwrap();
+    writeln!(module, "    device = {};", nix_str(device)).unwrap();
+    writeln!(module, "    fsType = {};", nix_str(fs_type)).unwrap();
+    writeln!(module, "    options = {};", nix_list(opts)).unwrap();
+    module.push_str("  };\n");
+}
+
+/// Render the NixOS module for a guest.
+///
+/// Drives are only declared as mounts here; creating filesystems and pools
+/// is still left to the drive script, which finds its tools already
+/// installed through `environment.systemPackages`.
+pub fn build_module(config: &ModuleConfig) -> String {
+    let mut module = String::from(
+        "# Generated by rum; overwritten on every provision.\n\
+         { config, lib, pkgs, ... }:\n\n{\n",
+    );
+
+    let mut groups: BTreeSet<&str> = config.user_groups.iter().map(String::as_str).collect();
+    groups.insert("wheel");
+    if config.docker == Some("docker") {
+        groups.insert("docker");
+    }
+    writeln!(module, "  users.users.{} = {{", nix_str(config.user_name)).unwrap();
+    module.push_str("    isNormalUser = true;\n");
+    writeln!(module, "    extraGroups = {};", nix_list(groups)).unwrap();
+    module.push_str("  };\n");
+    module.push_str("  security.sudo.wheelNeedsPassword = false;\n");
+    if config.autologin {
+        writeln!(
+            module,
+            "  services.getty.autologinUser = {};",
+            nix_str(config.user_name)
+        )
+        .unwrap();
+    }
+
+    let mut packages: BTreeSet<&str> = config.packages.iter().map(String::as_str).collect();
+    for mount in config.mounts {
+        // sshfs shares are mounted by their boot script, which only needs
+        // the tool installed.
+        let Some((fs_type, fs_options)) = mount.driver.fs_options() else {
+            if mount.driver == MountDriver::Sshfs {
+                packages.insert("sshfs");
+            }
+            continue;
+        };
+        let mut options: Vec<&str> = fs_options.split(',').filter(|o| *o != "defaults").collect();
+        if mount.readonly {
+            options.push("ro");
+        }
+        file_system(&mut module, &mount.target, &mount.tag, fs_type, &options);
+    }
+
+    let mut zfs_pools = Vec::new();
+    if !config.luks.is_empty() {
+        packages.insert("cryptsetup");
+    }
+    for entry in config.filesystems {
+        match entry {
+            ResolvedFs::Simple(s) => {
+                let tools = match s.filesystem.as_str() {
+                    "xfs" => Some("xfsprogs"),
+                    "btrfs" => Some("btrfs-progs"),
+                    _ => None,
+                };
+                packages.extend(tools);
+                // Encrypted devices only appear once the agent unlocks them.
+                let options: &[&str] = if s.dev.starts_with("/dev/mapper/") {
+                    &["nofail", "x-systemd.device-timeout=10s"]
+                } else {
+                    &["nofail"]
+                };
+                file_system(&mut module, &s.target, &s.dev, &s.filesystem, options);
+            }
+            ResolvedFs::Btrfs(b) => {
+                packages.insert("btrfs-progs");
+                file_system(&mut module, &b.target, &b.devs[0], "btrfs", &["nofail"]);
+            }
+            ResolvedFs::Zfs(z) => zfs_pools.push(z.pool.as_str()),
+        }
+    }
+
+    if !zfs_pools.is_empty() {
+        packages.insert("zfs");
+        module.push_str("  boot.supportedFilesystems = [ \"zfs\" ];\n");
+        writeln!(module, "  boot.zfs.extraPools = {};", nix_list(&zfs_pools)).unwrap();
+        writeln!(module, "  networking.hostId = {};", nix_str(config.host_id)).unwrap();
+    }
+    if !packages.is_empty() {
+        let names: Vec<String> = packages.into_iter().map(nix_package).collect();
+        writeln!(
+            module,
+            "  environment.systemPackages = [ {} ];",
+            names.join(" ")
+        )
+        .unwrap();
+    }
+
+    // The host forwards the engine's socket; nothing listens on TCP.
+    match config.docker {
+        Some("podman") => module.push_str(
+            "  virtualisation.podman.enable = true;\n\
+             \x20 systemd.sockets.podman.wantedBy = [ \"sockets.target\" ];\n",
+        ),
+        Some(_) => module.push_str("  virtualisation.docker.enable = true;\n"),
+        None => {}
+    }
+
+    // The agent applies this module itself, so a switch must never restart it.
+    module.push_str(
+        "  systemd.services.rum-agent = {\n\
+         \x20   description = \"rum guest agent\";\n\
+         \x20   after = [ \"local-fs.target\" ];\n\
+         \x20   wantedBy = [ \"multi-user.target\" ];\n\
+         \x20   restartIfChanged = false;\n\
+         \x20   serviceConfig = {\n\
+         \x20     ExecStart = \"/usr/local/bin/rum-agent\";\n\
+         \x20     Restart = \"always\";\n\
+         \x20     RestartSec = 2;\n\
+         \x20   };\n\
+         \x20 };\n",
+    );
+
+    module.push_str("}\n");
+    module
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{SimpleFs, ZfsFs};
+
+    fn module_config() -> ModuleConfig<'static> {
+        ModuleConfig {
+            host_id: "a1b2c3d4",
+            user_name: "rum",
+            user_groups: &[],
+            autologin: false,
+            packages: &[],
+            mounts: &[],
+            filesystems: &[],
+            luks: &[],
+            docker: None,
+        }
+    }
+
+    #[test]
+    fn module_declares_user_and_agent() {
+        let groups = vec!["docker".to_string()];
+        let module = build_module(&ModuleConfig {
+            user_groups: &groups,
+            autologin: true,
+            ..module_config()
+        });
+        assert!(module.contains("users.users.\"rum\" = {"), "got:\n{module}");
+        assert!(module.contains("extraGroups = [ \"docker\" \"wheel\" ];"));
+        assert!(module.contains("services.getty.autologinUser = \"rum\";"));
+        assert!(module.contains("restartIfChanged = false;"));
+        assert!(!module.contains("networking.hostId"));
+    }
+
+    #[test]
+    fn module_declares_mounts_and_drive_tooling() {
+        let mounts = vec![ResolvedMount {
+            source: "/home/user/project".into(),
+            target: "/mnt/project".into(),
+            readonly: true,
+            tag: "mnt_project".into(),
+            default: false,
+            driver: MountDriver::Virtiofs,
+        }];
+        let filesystems = vec![
+            ResolvedFs::Simple(SimpleFs {
+                filesystem: "xfs".into(),
+                dev: "/dev/vdb".into(),
+                target: "/mnt/data".into(),
+            }),
+            ResolvedFs::Zfs(ZfsFs {
+                pool: "tank".into(),
+                devs: vec!["/dev/vdc".into()],
+                target: "/mnt/tank".into(),
+                mode: None,
+            }),
+        ];
+        let packages = vec!["git".to_string()];
+        let module = build_module(&ModuleConfig {
+            packages: &packages,
+            mounts: &mounts,
+            filesystems: &filesystems,
+            ..module_config()
+        });
+        assert!(
+            module.contains("fileSystems.\"/mnt/project\" = {"),
+            "got:\n{module}"
+        );
+        assert!(module.contains("fsType = \"virtiofs\";"));
+        assert!(module.contains("options = [ \"nofail\" \"ro\" ];"));
+        assert!(module.contains("device = \"/dev/vdb\";"));
+        assert!(module.contains("boot.zfs.extraPools = [ \"tank\" ];"));
+        assert!(module.contains("networking.hostId = \"a1b2c3d4\";"));
+        assert!(
+            module.contains(r#"systemPackages = [ pkgs."git" pkgs."xfsprogs" pkgs."zfs" ];"#),
+            "got:\n{module}"
+        );
+    }
+
+    #[test]
+    fn module_enables_container_engine() {
+        let module = build_module(&ModuleConfig {
+            docker: Some("docker"),
+            ..module_config()
+        });
+        assert!(module.contains("extraGroups = [ \"docker\" \"wheel\" ];"));
+        assert!(module.contains("virtualisation.docker.enable = true;"));
+        assert!(!module.contains("listenOptions"), "got:\n{module}");
+
+        let module = build_module(&ModuleConfig {
+            docker: Some("podman"),
+            ..module_config()
+        });
+        assert!(module.contains("virtualisation.podman.enable = true;"));
+        assert!(module.contains("systemd.sockets.podman.wantedBy = [ \"sockets.target\" ];"));
+        assert!(!module.contains("system service"), "got:\n{module}");
+    }
+
+    #[test]
+    fn nix_strings_are_escaped() {
+        assert_eq!(nix_str(r#"a"b\c${d}"#), r#""a\"b\\c\${d}""#);
+    }
+
+    #[test]
+    fn package_names_are_quoted_attribute_paths() {
+        assert_eq!(nix_package("git"), r#"pkgs."git""#);
+        assert_eq!(
+            nix_package("python3Packages.requests"),
+            r#"pkgs."python3Packages"."requests""#
+        );
+        assert_eq!(nix_package("x]; evil = ["), r#"pkgs."x]; evil = [""#);
+    }
+}
@@ -8,16 +8,81 @@ pub fn cache_dir() -> PathBuf {
         .join("images")
 }
 
+/// Per-VM runtime directory: `$XDG_RUNTIME_DIR/rum/<id>[-<name>]/`, or the
+/// work dir where there is no runtime directory.
+pub fn runtime_dir(id: &str, name: Option<&str>) -> PathBuf {
+    match dirs::runtime_dir() {
+        Some(dir) => dir.join("rum").join(dir_name(id, name)),
+        None => work_dir(id, name),
+    }
+}
+
+/// Root of all per-VM work directories: `~/.local/share/rum/`
+pub fn data_dir() -> PathBuf {
+    dirs::data_local_dir()
+        .unwrap_or_else(|| PathBuf::from("/tmp"))
+        .join("rum")
+}
+
+/// User-level registry definitions: `~/.config/rum/registries.toml`
+pub fn registries_config_path() -> PathBuf {
+    dirs::config_dir()
+        .unwrap_or_else(|| PathBuf::from("/tmp"))
+        .join("rum")
+        .join("registries.toml")
+}
+
+/// Systemd user units written by `rum daemon install`:
+/// `~/.config/systemd/user/`, or `None` when there is no home directory.
+pub fn systemd_user_unit_dir() -> Option<PathBuf> {
+    Some(dirs::config_dir()?.join("systemd").join("user"))
+}
+
+/// The user's OpenSSH client config: `~/.ssh/config`
+pub fn user_ssh_config_path() -> PathBuf {
+    dirs::home_dir()
+        .unwrap_or_else(|| PathBuf::from("/tmp"))
+        .join(".ssh")
+        .join("config")
+}
+
+/// Cached registry catalogs: `~/.cache/rum/registries/`
+pub fn registry_cache_dir() -> PathBuf {
+    dirs::cache_dir()
+        .unwrap_or_else(|| PathBuf::from("/tmp"))
+        .join("rum")
+        .join("registries")
+}
+
+/// Guest agent built for `arch` when it is not embedded in this binary:
+/// `~/.cache/rum/agents/rum-agent-<arch>`
+pub fn agent_binary_path(arch: &str) -> PathBuf {
+    dirs::cache_dir()
+        .unwrap_or_else(|| PathBuf::from("/tmp"))
+        .join("rum")
+        .join("agents")
+        .join(format!("rum-agent-{arch}"))
+}
+
+/// Provisioned base image produced by `rum bake`: `~/.cache/rum/images/baked-<id>[-<name>].qcow2`
+pub fn baked_image_path(id: &str, name: Option<&str>) -> PathBuf {
+    let file_name = match name {
+        Some(n) => format!("baked-{id}-{n}.qcow2"),
+        None => format!("baked-{id}.qcow2"),
+    };
+    cache_dir().join(file_name)
+}
+
 /// Per-VM work directory: `~/.local/share/rum/<id>-<name>/` or `~/.local/share/rum/<id>/`
 pub fn work_dir(id: &str, name: Option<&str>) -> PathBuf {
-    let dir_name = match name {
+    data_dir().join(dir_name(id, name))
+}
+
+fn dir_name(id: &str, name: Option<&str>) -> String {
+    match name {
         Some(n) => format!("{id}-{n}"),
         None => id.to_string(),
-    };
-    dirs::data_local_dir()
-        .unwrap_or_else(|| PathBuf::from("/tmp"))
-        .join("rum")
-        .join(dir_name)
+    }
 }
 
 /// Path to the qcow2 overlay for a VM.
@@ -25,19 +90,30 @@ pub fn overlay_path(id: &str, name: Option<&str>) -> PathBuf {
     work_dir(id, name).join("overlay.qcow2")
 }
 
+/// Path to the post-provision layer that backs the working overlay.
+pub fn provisioned_layer_path(id: &str, name: Option<&str>) -> PathBuf {
+    work_dir(id, name).join("provisioned.qcow2")
+}
+
 /// Path to the cloud-init seed ISO for a VM, keyed by content hash.
 pub fn seed_path(id: &str, name: Option<&str>, hash: &str) -> PathBuf {
     work_dir(id, name).join(format!("seed-{hash}.iso"))
 }
 
+/// Path to the per-VM UEFI variable store.
+pub fn nvram_path(id: &str, name: Option<&str>) -> PathBuf {
+    work_dir(id, name).join("nvram.fd")
+}
+
 /// Path to the saved domain XML for a VM.
 pub fn domain_xml_path(id: &str, name: Option<&str>) -> PathBuf {
     work_dir(id, name).join("domain.xml")
 }
 
-/// Path to an extra drive image for a VM.
-pub fn drive_path(id: &str, name: Option<&str>, drive_name: &str) -> PathBuf {
-    work_dir(id, name).join(format!("drive-{drive_name}.qcow2"))
+/// Path to an extra drive image for a VM; the extension follows the image
+/// `format` (`qcow2` or `raw`).
+pub fn drive_path(id: &str, name: Option<&str>, drive_name: &str, format: &str) -> PathBuf {
+    work_dir(id, name).join(format!("drive-{drive_name}.{format}"))
 }
 
 /// Per-VM logs directory: `~/.local/share/rum/<id>[-<name>]/logs/`
@@ -50,6 +126,33 @@ pub fn provisioned_marker(id: &str, name: Option<&str>) -> PathBuf {
     work_dir(id, name).join(".provisioned")
 }
 
+/// Content hashes of the system scripts from the last successful provision.
+pub fn provision_hashes_path(id: &str, name: Option<&str>) -> PathBuf {
+    work_dir(id, name).join("provision-hashes")
+}
+
+/// The provision hashes as they were when the provisioned layer was last
+/// frozen, restored when the working layer is reset.
+pub fn provisioned_hashes_path(id: &str, name: Option<&str>) -> PathBuf {
+    work_dir(id, name).join("provisioned-hashes")
+}
+
+/// Mounts added to the running machine with `rum mount`.
+pub fn live_mounts_path(id: &str, name: Option<&str>) -> PathBuf {
+    work_dir(id, name).join("live-mounts.json")
+}
+
+/// The config the machine was last started with, for `rum diff`.
+pub fn applied_config_path(id: &str, name: Option<&str>) -> PathBuf {
+    work_dir(id, name).join("applied-config.json")
+}
+
+/// Per-file state of the last sync of a `mode = "sync"` mount. Every such
+/// `sync-*.json` file goes when the guest disk is reset.
+pub fn sync_state_path(id: &str, name: Option<&str>, tag: &str) -> PathBuf {
+    work_dir(id, name).join(format!("sync-{tag}.json"))
+}
+
 /// Path to the config_path file that records which config file created this work dir.
 pub fn config_path_file(id: &str, name: Option<&str>) -> PathBuf {
     work_dir(id, name).join("config_path")
@@ -60,11 +163,64 @@ pub fn ssh_key_path(id: &str, name: Option<&str>) -> PathBuf {
     work_dir(id, name).join("ssh_ed25519")
 }
 
+/// Path to the key the guest uses to mount `sshfs` shares from the host.
+pub fn sshfs_key_path(id: &str, name: Option<&str>) -> PathBuf {
+    work_dir(id, name).join("sshfs_ed25519")
+}
+
+/// ControlMaster socket shared by `rum ssh` invocations.
+pub fn ssh_control_path(id: &str, name: Option<&str>) -> PathBuf {
+    work_dir(id, name).join("ssh.sock")
+}
+
+/// Host end of the forwarded `[docker]` engine API.
+pub fn docker_socket_path(id: &str, name: Option<&str>) -> PathBuf {
+    runtime_dir(id, name).join("docker.sock")
+}
+
+/// Host end of the guest agent's virtio-serial channel.
+pub fn agent_channel_path(id: &str, name: Option<&str>) -> PathBuf {
+    work_dir(id, name).join("agent.sock")
+}
+
+/// Transport the host settled on with the guest agent since the last boot.
+pub fn agent_transport_path(id: &str, name: Option<&str>) -> PathBuf {
+    work_dir(id, name).join("agent-transport")
+}
+
 /// Path to the daemon Unix socket for a VM.
 pub fn socket_path(id: &str, name: Option<&str>) -> PathBuf {
     work_dir(id, name).join("rum.sock")
 }
 
+/// Lock file held by commands that change a VM's state.
+pub fn lock_path(id: &str, name: Option<&str>) -> PathBuf {
+    work_dir(id, name).join("rum.lock")
+}
+
+/// Lifecycle journal written by the daemon, see [`crate::journal`].
+pub fn journal_path(id: &str, name: Option<&str>) -> PathBuf {
+    work_dir(id, name).join("journal.jsonl")
+}
+
+/// File name of the audit log, which `destroy` leaves in the work dir.
+pub(crate) const AUDIT_LOG: &str = "audit.jsonl";
+
+/// Append-only log of the commands run on a machine, see [`crate::audit`].
+pub fn audit_log_path(id: &str, name: Option<&str>) -> PathBuf {
+    work_dir(id, name).join(AUDIT_LOG)
+}
+
+/// Log file of the daemon; rotated copies get a `.1`, `.2`, ... suffix.
+pub fn daemon_log_path(id: &str, name: Option<&str>) -> PathBuf {
+    work_dir(id, name).join("rum.log")
+}
+
+/// Bearer token of the daemon's HTTP control API (`serve.http`).
+pub fn http_token_path(id: &str, name: Option<&str>) -> PathBuf {
+    work_dir(id, name).join("http-token")
+}
+
 /// Path to the daemon PID file for a VM.
 pub fn pid_path(id: &str, name: Option<&str>) -> PathBuf {
     work_dir(id, name).join("rum.pid")
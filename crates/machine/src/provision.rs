@@ -0,0 +1,157 @@
+//! Bookkeeping for provisioning scripts across runs.
+//!
+//! After a successful provision the content hash of every system script is
+//! recorded in the work dir, so a script is skipped until its content
+//! changes. The hashes are copied along when the disk is frozen into the
+//! provisioned layer, and put back when the disk is reset to it.
+
+use std::collections::BTreeMap;
+use std::io::ErrorKind;
+use std::path::Path;
+
+use guest::agent::{ProvisionScript, RunOn};
+use sha2::Digest;
+
+use crate::error::Error;
+
+/// Hex SHA-256 of a script's content.
+pub fn script_hash(content: &str) -> String {
+    sha2::Sha256::digest(content.as_bytes())
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+/// Script name → content hash from the last successful provision.
+pub fn recorded_hashes(path: &Path) -> BTreeMap<String, String> {
+    std::fs::read_to_string(path)
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|line| line.split_once(' '))
+        .map(|(name, hash)| (name.to_string(), hash.to_string()))
+        .collect()
+}
+
+/// Record the hashes of the system scripts in `scripts`, keeping entries of
+/// scripts that were skipped this time.
+pub fn record_hashes(path: &Path, scripts: &[ProvisionScript]) -> Result<(), Error> {
+    let mut hashes = recorded_hashes(path);
+    for script in scripts.iter().filter(|s| matches!(s.run_on, RunOn::System)) {
+        hashes.insert(script.name.clone(), script_hash(&script.content));
+    }
+    let contents: String = hashes
+        .iter()
+        .map(|(name, hash)| format!("{name} {hash}\n"))
+        .collect();
+    std::fs::write(path, contents).map_err(|e| Error::Io {
+        context: format!("writing {}", path.display()),
+        source: e,
+    })
+}
+
+/// Leave out the system scripts whose content matches `recorded`. Boot
+/// scripts always stay, and `force` keeps everything.
+pub fn skip_unchanged(
+    scripts: &mut Vec<ProvisionScript>,
+    recorded: &BTreeMap<String, String>,
+    force: bool,
+) {
+    scripts.retain(|script| {
+        let unchanged = !force
+            && matches!(script.run_on, RunOn::System)
+            && recorded.get(&script.name) == Some(&script_hash(&script.content));
+        if unchanged {
+            tracing::info!(script = %script.name, "script unchanged since its last run; skipping");
+        }
+        !unchanged
+    });
+}
+
+/// Make `to` a copy of the hashes at `from`, or remove it when there are
+/// none, so no step counts as run that the disk never saw.
+pub fn copy_hashes(from: &Path, to: &Path) -> Result<(), Error> {
+    let copied = match std::fs::copy(from, to) {
+        Err(e) if e.kind() == ErrorKind::NotFound => match std::fs::remove_file(to) {
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+            removed => removed,
+        },
+        copied => copied.map(|_| ()),
+    };
+    copied.map_err(|e| Error::Io {
+        context: format!("copying {} to {}", from.display(), to.display()),
+        source: e,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn script(name: &str, content: &str, run_on: RunOn) -> ProvisionScript {
+        ProvisionScript::shell(name, name, content.into(), 0, run_on)
+    }
+
+    #[test]
+    fn hashes_round_trip_and_merge() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("provision-hashes");
+
+        record_hashes(&path, &[script("setup", "echo one", RunOn::System)]).unwrap();
+        record_hashes(
+            &path,
+            &[
+                script("extra", "echo two", RunOn::System),
+                script("boot", "echo boot", RunOn::Boot),
+            ],
+        )
+        .unwrap();
+
+        let hashes = recorded_hashes(&path);
+        assert_eq!(hashes.len(), 2);
+        assert_eq!(hashes["setup"], script_hash("echo one"));
+        assert_eq!(hashes["extra"], script_hash("echo two"));
+    }
+
+    #[test]
+    fn unchanged_system_scripts_are_skipped_unless_forced() {
+        let plan = vec![
+            script("same", "echo same", RunOn::System),
+            script("changed", "echo new", RunOn::System),
+            script("new", "echo first", RunOn::System),
+            script("boot", "echo boot", RunOn::Boot),
+        ];
+        let recorded = BTreeMap::from([
+            ("same".to_string(), script_hash("echo same")),
+            ("changed".to_string(), script_hash("echo old")),
+            ("boot".to_string(), script_hash("echo boot")),
+        ]);
+
+        let mut scripts = plan.clone();
+        skip_unchanged(&mut scripts, &recorded, false);
+        let names: Vec<&str> = scripts.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, ["changed", "new", "boot"]);
+
+        let mut scripts = plan.clone();
+        skip_unchanged(&mut scripts, &recorded, true);
+        assert_eq!(scripts.len(), plan.len());
+    }
+
+    #[test]
+    fn copying_hashes_follows_the_source() {
+        let dir = tempfile::tempdir().unwrap();
+        let hashes = dir.path().join("provision-hashes");
+        let snapshot = dir.path().join("provisioned-hashes");
+
+        record_hashes(&hashes, &[script("setup", "echo one", RunOn::System)]).unwrap();
+        copy_hashes(&hashes, &snapshot).unwrap();
+        record_hashes(&hashes, &[script("later", "echo two", RunOn::System)]).unwrap();
+        copy_hashes(&snapshot, &hashes).unwrap();
+        assert_eq!(recorded_hashes(&hashes).len(), 1);
+
+        // Without a snapshot nothing counts as run.
+        std::fs::remove_file(&snapshot).unwrap();
+        copy_hashes(&snapshot, &hashes).unwrap();
+        assert!(!hashes.exists());
+        copy_hashes(&snapshot, &hashes).unwrap();
+    }
+}
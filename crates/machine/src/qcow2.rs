@@ -58,7 +58,7 @@
 //! - Format overview: <https://people.gnome.org/~markmc/qcow-image-format.html>
 
 use std::io::Write;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 use crate::error::Error;
 use crate::util::parse_size;
@@ -118,6 +118,196 @@ pub fn create_qcow2(path: &Path, size: &str) -> Result<(), Error> {
     Ok(())
 }
 
+/// Create a `qcow2` or `raw` drive image with `preallocation` (`off`,
+/// `metadata`, or `full`).
+///
+/// Sparse raw images are just a truncated file. Everything else goes through
+/// `qemu-img create`, which knows how to lay out preallocated qcow2 metadata;
+/// raw has no metadata, so `metadata` maps to `falloc` (reserve without
+/// writing zeroes).
+pub fn create_preallocated(
+    path: &Path,
+    size: &str,
+    format: &str,
+    preallocation: &str,
+) -> Result<(), Error> {
+    let virtual_size = parse_size(size)?;
+
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).map_err(|e| Error::Io {
+            context: format!("creating directory {}", parent.display()),
+            source: e,
+        })?;
+    }
+
+    if format == "raw" && preallocation == "off" {
+        let file = std::fs::File::create(path).map_err(|e| Error::Io {
+            context: format!("creating raw image {}", path.display()),
+            source: e,
+        })?;
+        file.set_len(virtual_size).map_err(|e| Error::Io {
+            context: format!("sizing raw image {}", path.display()),
+            source: e,
+        })?;
+    } else {
+        let preallocation = match (format, preallocation) {
+            ("raw", "metadata") => "falloc",
+            (_, mode) => mode,
+        };
+        let output = std::process::Command::new("qemu-img")
+            .args(["create", "-q", "-f", format, "-o"])
+            .arg(format!("preallocation={preallocation}"))
+            .arg(path)
+            .arg(virtual_size.to_string())
+            .output()
+            .map_err(|e| Error::ExternalCommand {
+                command: "qemu-img".into(),
+                message: e.to_string(),
+            })?;
+        if !output.status.success() {
+            let _ = std::fs::remove_file(path);
+            return Err(Error::ExternalCommand {
+                command: "qemu-img".into(),
+                message: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+            });
+        }
+    }
+
+    tracing::info!(path = %path.display(), size, format, preallocation, "created drive image");
+    Ok(())
+}
+
+/// Virtual size of a `qcow2` or `raw` image in bytes.
+///
+/// For qcow2 this is the header's size field (bytes 24..32); for raw images
+/// and block devices it is the file length.
+pub fn virtual_size(path: &Path, format: &str) -> Result<u64, Error> {
+    use std::io::Read;
+
+    let io_err = |e| Error::Io {
+        context: format!("reading image size of {}", path.display()),
+        source: e,
+    };
+    if format != "qcow2" {
+        return std::fs::metadata(path).map(|m| m.len()).map_err(io_err);
+    }
+    let mut header = [0u8; 32];
+    std::fs::File::open(path)
+        .and_then(|mut f| f.read_exact(&mut header))
+        .map_err(io_err)?;
+    Ok(u64::from_be_bytes(header[24..32].try_into().unwrap()))
+}
+
+/// Backing file recorded in the qcow2 header of `path`, or `None` for an
+/// image without one (or a file that is not qcow2 at all).
+pub fn backing_file(path: &Path) -> Result<Option<PathBuf>, Error> {
+    use std::io::{Read, Seek, SeekFrom};
+
+    let io_err = |e| Error::Io {
+        context: format!("reading backing file of {}", path.display()),
+        source: e,
+    };
+    let mut file = std::fs::File::open(path).map_err(io_err)?;
+    let mut header = [0u8; 20];
+    match file.read_exact(&mut header) {
+        Ok(()) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(io_err(e)),
+    }
+    let offset = u64::from_be_bytes(header[8..16].try_into().unwrap());
+    let len = u32::from_be_bytes(header[16..20].try_into().unwrap());
+    // The spec caps backing file names at 1023 bytes.
+    if header[0..4] != [0x51, 0x46, 0x49, 0xFB] || offset == 0 || len == 0 || len > 1023 {
+        return Ok(None);
+    }
+    let mut name = vec![0u8; len as usize];
+    file.seek(SeekFrom::Start(offset))
+        .and_then(|_| file.read_exact(&mut name))
+        .map_err(io_err)?;
+    let name = String::from_utf8_lossy(&name).into_owned();
+    Ok(Some(PathBuf::from(name)))
+}
+
+/// Grow the image at `path` to `size` bytes with `qemu-img resize`.
+///
+/// Returns `false` without touching the image when it is already at least
+/// that large; images are never shrunk.
+pub fn grow(path: &Path, format: &str, size: u64) -> Result<bool, Error> {
+    let current = virtual_size(path, format)?;
+    if size <= current {
+        if size < current {
+            tracing::warn!(
+                path = %path.display(),
+                current,
+                requested = size,
+                "configured size is smaller than the image; shrinking is not supported"
+            );
+        }
+        return Ok(false);
+    }
+
+    let output = std::process::Command::new("qemu-img")
+        .args(["resize", "-q", "-f", format])
+        .arg(path)
+        .arg(size.to_string())
+        .output()
+        .map_err(|e| Error::ExternalCommand {
+            command: "qemu-img".into(),
+            message: e.to_string(),
+        })?;
+    if !output.status.success() {
+        return Err(Error::ExternalCommand {
+            command: "qemu-img".into(),
+            message: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        });
+    }
+
+    tracing::info!(path = %path.display(), from = current, to = size, "grew disk image");
+    Ok(true)
+}
+
+/// Point the qcow2 image at `path` to `backing` without copying any data.
+///
+/// Only safe when `backing` holds what the old backing file did, as after
+/// renaming it or committing it into `backing`.
+pub fn rebase_unsafe(path: &Path, backing: &Path) -> Result<(), Error> {
+    let canonical = std::fs::canonicalize(backing).map_err(|e| Error::Io {
+        context: format!("resolving backing file path {}", backing.display()),
+        source: e,
+    })?;
+    qemu_img(|command| {
+        command
+            .args(["rebase", "-q", "-u", "-f", "qcow2", "-F", "qcow2", "-b"])
+            .arg(canonical)
+            .arg(path)
+    })
+}
+
+/// Write the data of the qcow2 image at `path` into its backing file with
+/// `qemu-img commit`.
+pub fn commit(path: &Path) -> Result<(), Error> {
+    qemu_img(|command| command.args(["commit", "-q", "-f", "qcow2"]).arg(path))
+}
+
+fn qemu_img(
+    args: impl FnOnce(&mut std::process::Command) -> &mut std::process::Command,
+) -> Result<(), Error> {
+    let mut command = std::process::Command::new("qemu-img");
+    let output = args(&mut command)
+        .output()
+        .map_err(|e| Error::ExternalCommand {
+            command: "qemu-img".into(),
+            message: e.to_string(),
+        })?;
+    if !output.status.success() {
+        return Err(Error::ExternalCommand {
+            command: "qemu-img".into(),
+            message: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        });
+    }
+    Ok(())
+}
+
 /// Create a QCOW2 overlay image at `overlay_path` backed by `backing_file`.
 ///
 /// The backing file must be an existing QCOW2 image.  Its virtual size is
@@ -352,6 +542,24 @@ mod tests {
         assert_eq!(l1_table_entries(100 * 1024 * 1024 * 1024), 200);
     }
 
+    #[test]
+    fn backing_file_reads_overlay_header() {
+        let dir = tempfile::tempdir().unwrap();
+        let base = dir.path().join("base.qcow2");
+        let overlay = dir.path().join("overlay.qcow2");
+        create_qcow2(&base, "1G").unwrap();
+        create_qcow2_overlay(&overlay, &base, None).unwrap();
+
+        assert_eq!(
+            backing_file(&overlay).unwrap(),
+            Some(std::fs::canonicalize(&base).unwrap())
+        );
+        assert_eq!(backing_file(&base).unwrap(), None);
+        let raw = dir.path().join("disk.raw");
+        std::fs::write(&raw, b"raw").unwrap();
+        assert_eq!(backing_file(&raw).unwrap(), None);
+    }
+
     #[test]
     fn create_qcow2_writes_file() {
         let dir = tempfile::tempdir().unwrap();
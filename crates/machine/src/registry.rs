@@ -0,0 +1,371 @@
+//! Base image registries for `rum image search`.
+//!
+//! A registry is a remote index of cloud images. Two index formats are
+//! understood: simplestreams (Ubuntu and most LXD-style mirrors) and the
+//! Fedora `releases.json` list. Besides the built-in registries, users can add
+//! their own in `~/.config/rum/registries.toml`:
+//!
+//! ```toml
+//! [registries.internal]
+//! url = "https://mirror.example.com/streams/v1/images.json"
+//! format = "simplestreams"
+//! ```
+//!
+//! Fetched catalogs are normalized into [`ImageEntry`] lists and cached as
+//! JSON for [`CATALOG_TTL`] so repeated searches stay offline.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use facet::Facet;
+
+use crate::error::Error;
+use crate::paths;
+
+/// How long a fetched catalog is reused before it is fetched again.
+pub const CATALOG_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+const UBUNTU_STREAM: &str =
+    "https://cloud-images.ubuntu.com/releases/streams/v1/com.ubuntu.cloud:released:download.json";
+
+/// Index format of a registry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Facet)]
+#[repr(u8)]
+#[facet(rename_all = "kebab-case")]
+pub enum RegistryFormat {
+    #[default]
+    Simplestreams,
+    Fedora,
+}
+
+/// One registry from the built-ins or the user-level registries file.
+#[derive(Debug, Clone, Default, Facet)]
+#[facet(default)]
+pub struct RegistryConfig {
+    pub url: String,
+    pub format: RegistryFormat,
+}
+
+#[derive(Debug, Clone, Default, Facet)]
+#[facet(default)]
+struct RegistriesFile {
+    registries: BTreeMap<String, RegistryConfig>,
+}
+
+/// A downloadable base image found in a registry catalog.
+///
+/// Each architecture of a release is its own entry.
+#[derive(Debug, Clone, Facet)]
+pub struct ImageEntry {
+    pub registry: String,
+    pub distro: String,
+    pub release: String,
+    /// Human-facing release name, e.g. `24.04 LTS` or `Fedora 41`.
+    pub title: String,
+    /// Normalized to the `image.arch` spelling (`x86_64`, `aarch64`).
+    pub arch: String,
+    pub url: String,
+    pub sha256: Option<String>,
+}
+
+/// Search criteria; `None` matches everything.
+#[derive(Debug, Clone, Default)]
+pub struct SearchFilter {
+    pub distro: Option<String>,
+    pub release: Option<String>,
+    pub arch: Option<String>,
+}
+
+impl SearchFilter {
+    fn matches(&self, entry: &ImageEntry) -> bool {
+        let distro = self
+            .distro
+            .as_deref()
+            .is_none_or(|d| entry.distro.eq_ignore_ascii_case(d));
+        let release = self.release.as_deref().is_none_or(|r| {
+            entry.release.eq_ignore_ascii_case(r)
+                || entry.title.to_lowercase().contains(&r.to_lowercase())
+        });
+        let arch = self
+            .arch
+            .as_deref()
+            .is_none_or(|a| normalize_arch(a) == normalize_arch(&entry.arch));
+        distro && release && arch
+    }
+}
+
+/// Built-in registries merged with the user's `registries.toml`.
+///
+/// User entries with a built-in name replace the built-in.
+pub fn registries() -> Result<BTreeMap<String, RegistryConfig>, Error> {
+    let mut registries = BTreeMap::from([
+        (
+            "ubuntu".to_string(),
+            RegistryConfig {
+                url: UBUNTU_STREAM.into(),
+                format: RegistryFormat::Simplestreams,
+            },
+        ),
+        (
+            "fedora".to_string(),
+            RegistryConfig {
+                url: "https://fedoraproject.org/releases.json".into(),
+                format: RegistryFormat::Fedora,
+            },
+        ),
+    ]);
+
+    let path = paths::registries_config_path();
+    match std::fs::read_to_string(&path) {
+        Ok(contents) => {
+            let file: RegistriesFile =
+                facet_toml::from_str(&contents).map_err(|e| Error::ConfigParse {
+                    path: path.display().to_string(),
+                    message: e.to_string(),
+                })?;
+            registries.extend(file.registries);
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(source) => {
+            return Err(Error::ConfigLoad {
+                path: path.display().to_string(),
+                source,
+            });
+        }
+    }
+
+    Ok(registries)
+}
+
+/// Search every registry's catalog, fetching catalogs that are missing or
+/// older than [`CATALOG_TTL`] (or all of them when `refresh` is set).
+///
+/// A registry that cannot be fetched is skipped with a warning so one broken
+/// mirror does not hide results from the others.
+pub async fn search(filter: &SearchFilter, refresh: bool) -> Result<Vec<ImageEntry>, Error> {
+    let mut results = Vec::new();
+    for (name, registry) in registries()? {
+        match catalog(&name, &registry, refresh).await {
+            Ok(entries) => results.extend(entries.into_iter().filter(|e| filter.matches(e))),
+            Err(error) => tracing::warn!(registry = %name, %error, "skipping registry"),
+        }
+    }
+    // Newest releases first within each distro.
+    results.sort_by(|a, b| {
+        a.distro
+            .cmp(&b.distro)
+            .then_with(|| b.release.cmp(&a.release))
+            .then_with(|| a.arch.cmp(&b.arch))
+    });
+    Ok(results)
+}
+
+async fn catalog(
+    name: &str,
+    registry: &RegistryConfig,
+    refresh: bool,
+) -> Result<Vec<ImageEntry>, Error> {
+    let cache_path = catalog_cache_path(name);
+    if !refresh && let Some(entries) = read_cached_catalog(&cache_path) {
+        return Ok(entries);
+    }
+
+    let body = fetch(&registry.url).await?;
+    let entries = match registry.format {
+        RegistryFormat::Simplestreams => parse_simplestreams(name, &registry.url, &body)?,
+        RegistryFormat::Fedora => parse_fedora(name, &body)?,
+    };
+
+    if let Some(parent) = cache_path.parent() {
+        let _ = std::fs::create_dir_all(parent);
+    }
+    if let Err(error) = std::fs::write(&cache_path, facet_json::to_string(&entries)) {
+        tracing::warn!(path = %cache_path.display(), %error, "failed to cache registry catalog");
+    }
+    Ok(entries)
+}
+
+fn read_cached_catalog(path: &Path) -> Option<Vec<ImageEntry>> {
+    let age = std::fs::metadata(path)
+        .ok()?
+        .modified()
+        .ok()
+        .and_then(|m| SystemTime::now().duration_since(m).ok())?;
+    if age > CATALOG_TTL {
+        return None;
+    }
+    facet_json::from_str(&std::fs::read_to_string(path).ok()?).ok()
+}
+
+async fn fetch(url: &str) -> Result<String, Error> {
+    let response = reqwest::get(url).await.map_err(|e| Error::ImageDownload {
+        message: format!("request to {url} failed"),
+        source: Box::new(e),
+    })?;
+    if !response.status().is_success() {
+        return Err(Error::ImageDownload {
+            message: format!("HTTP {} from {url}", response.status()),
+            source: format!("HTTP {}", response.status()).into(),
+        });
+    }
+    response.text().await.map_err(|e| Error::ImageDownload {
+        message: format!("error reading catalog from {url}"),
+        source: Box::new(e),
+    })
+}
+
+// ── simplestreams ──────────────────────────────────────────
+
+#[derive(Facet)]
+struct StreamIndex {
+    products: BTreeMap<String, StreamProduct>,
+}
+
+#[derive(Facet)]
+struct StreamProduct {
+    arch: String,
+    os: String,
+    release: String,
+    release_title: Option<String>,
+    versions: BTreeMap<String, StreamVersion>,
+}
+
+#[derive(Facet)]
+struct StreamVersion {
+    items: BTreeMap<String, StreamItem>,
+}
+
+#[derive(Facet)]
+struct StreamItem {
+    ftype: String,
+    path: String,
+    sha256: Option<String>,
+}
+
+/// Keep the newest qcow2-compatible disk image of every product.
+fn parse_simplestreams(registry: &str, url: &str, body: &str) -> Result<Vec<ImageEntry>, Error> {
+    let index: StreamIndex = facet_json::from_str(body).map_err(|e| Error::ConfigParse {
+        path: url.to_string(),
+        message: e.to_string(),
+    })?;
+    // Item paths are relative to the mirror root, which sits above `streams/`.
+    let root = url.split("streams/v1/").next().unwrap_or(url);
+
+    Ok(index
+        .products
+        .into_values()
+        .filter_map(|product| {
+            let (_, latest) = product.versions.iter().next_back()?;
+            let item = latest
+                .items
+                .values()
+                .find(|item| matches!(item.ftype.as_str(), "disk1.img" | "qcow2"))?;
+            Some(ImageEntry {
+                registry: registry.to_string(),
+                distro: product.os,
+                title: product
+                    .release_title
+                    .unwrap_or_else(|| product.release.clone()),
+                release: product.release,
+                arch: normalize_arch(&product.arch).to_string(),
+                url: format!("{root}{}", item.path),
+                sha256: item.sha256.clone(),
+            })
+        })
+        .collect())
+}
+
+// ── fedora ─────────────────────────────────────────────────
+
+#[derive(Facet)]
+struct FedoraRelease {
+    version: String,
+    arch: String,
+    variant: String,
+    link: String,
+    sha256: Option<String>,
+}
+
+fn parse_fedora(registry: &str, body: &str) -> Result<Vec<ImageEntry>, Error> {
+    let releases: Vec<FedoraRelease> =
+        facet_json::from_str(body).map_err(|e| Error::ConfigParse {
+            path: registry.to_string(),
+            message: e.to_string(),
+        })?;
+
+    Ok(releases
+        .into_iter()
+        .filter(|r| r.variant == "Cloud" && r.link.ends_with(".qcow2"))
+        .map(|r| ImageEntry {
+            registry: registry.to_string(),
+            distro: "fedora".into(),
+            title: format!("Fedora {}", r.version),
+            release: r.version,
+            arch: normalize_arch(&r.arch).to_string(),
+            url: r.link,
+            sha256: r.sha256,
+        })
+        .collect())
+}
+
+/// Map Debian-style and kernel-style architecture names onto one spelling.
+fn normalize_arch(arch: &str) -> &str {
+    match arch {
+        "amd64" | "x86_64" => "x86_64",
+        "arm64" | "aarch64" => "aarch64",
+        other => other,
+    }
+}
+
+fn catalog_cache_path(name: &str) -> PathBuf {
+    paths::registry_cache_dir().join(format!("{name}.json"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn simplestreams_keeps_latest_disk_image_per_product() {
+        let body = r#"{
+            "products": {
+                "com.ubuntu.cloud:server:24.04:amd64": {
+                    "arch": "amd64",
+                    "os": "ubuntu",
+                    "release": "noble",
+                    "release_title": "24.04 LTS",
+                    "versions": {
+                        "20240401": {"items": {"disk1.img": {
+                            "ftype": "disk1.img", "path": "server/old.img", "sha256": "aa"
+                        }}},
+                        "20240423": {"items": {
+                            "manifest": {"ftype": "manifest", "path": "server/new.manifest"},
+                            "disk1.img": {
+                                "ftype": "disk1.img", "path": "server/new.img", "sha256": "bb"
+                            }
+                        }}
+                    }
+                }
+            }
+        }"#;
+        let url = "https://example.com/releases/streams/v1/index.json";
+        let entries = parse_simplestreams("ubuntu", url, body).unwrap();
+
+        assert_eq!(entries.len(), 1);
+        assert_eq!(
+            entries[0].url,
+            "https://example.com/releases/server/new.img"
+        );
+        assert_eq!(entries[0].sha256.as_deref(), Some("bb"));
+        assert_eq!(entries[0].title, "24.04 LTS");
+        assert_eq!(entries[0].arch, "x86_64");
+
+        let filter = SearchFilter {
+            release: Some("24.04".into()),
+            arch: Some("x86_64".into()),
+            ..Default::default()
+        };
+        assert!(filter.matches(&entries[0]));
+    }
+}
@@ -0,0 +1,218 @@
+//! Timeouts and retries for lifecycle steps, driven by `[timeouts]`.
+//!
+//! Only idempotent steps, such as downloads and agent connections, go
+//! through [`retry`]. Steps that must not run twice, such as booting or
+//! provisioning, only get a time limit from [`timed`].
+
+use std::fmt;
+use std::future::Future;
+use std::time::Duration;
+
+use crate::error::Error;
+
+/// Wait before the first retry; doubled for every further one.
+const FIRST_BACKOFF: Duration = Duration::from_secs(2);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// How often and how long one step may be tried.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Total attempts, at least one.
+    pub attempts: u32,
+    /// Limit per attempt; `None` waits indefinitely.
+    pub timeout: Option<Duration>,
+    pub backoff: Duration,
+}
+
+impl RetryPolicy {
+    /// `retries` extra attempts of at most `timeout_s` each (`0` for no
+    /// limit).
+    pub fn new(timeout_s: u64, retries: u32) -> Self {
+        Self {
+            attempts: retries.saturating_add(1),
+            timeout: (timeout_s > 0).then(|| Duration::from_secs(timeout_s)),
+            backoff: FIRST_BACKOFF,
+        }
+    }
+
+    fn backoff_before(&self, attempt: u32) -> Duration {
+        let doublings = attempt.saturating_sub(2).min(16);
+        (self.backoff * 2u32.pow(doublings)).min(MAX_BACKOFF)
+    }
+}
+
+/// A failed attempt that is about to be retried.
+#[derive(Debug, Clone)]
+pub struct Retry {
+    pub step: String,
+    /// The attempt that comes next, starting at 2.
+    pub attempt: u32,
+    pub attempts: u32,
+    pub error: String,
+}
+
+impl fmt::Display for Retry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} failed: {}; retrying ({}/{})",
+            self.step, self.error, self.attempt, self.attempts
+        )
+    }
+}
+
+/// Run the idempotent `op` under `policy`, calling `on_retry` before every
+/// retry.
+///
+/// An attempt that runs past the timeout counts as failed with
+/// [`Error::StepTimeout`]. The error of the last attempt is returned.
+pub async fn retry<T, F, Fut>(
+    policy: RetryPolicy,
+    step: &str,
+    mut on_retry: impl FnMut(&Retry),
+    mut op: F,
+) -> Result<T, Error>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, Error>>,
+{
+    let mut attempt = 1;
+    loop {
+        let result = match policy.timeout {
+            Some(limit) => tokio::time::timeout(limit, op()).await.unwrap_or_else(|_| {
+                Err(Error::StepTimeout {
+                    step: step.to_string(),
+                    timeout_s: limit.as_secs(),
+                })
+            }),
+            None => op().await,
+        };
+        let error = match result {
+            Ok(value) => return Ok(value),
+            Err(error) if attempt >= policy.attempts => return Err(error),
+            Err(error) => error,
+        };
+
+        attempt += 1;
+        on_retry(&Retry {
+            step: step.to_string(),
+            attempt,
+            attempts: policy.attempts,
+            error: error.to_string(),
+        });
+        tokio::time::sleep(policy.backoff_before(attempt)).await;
+    }
+}
+
+/// Run `step` once, failing with [`Error::StepTimeout`] if it takes longer
+/// than `timeout_s` (`0` for no limit).
+pub async fn timed<T>(
+    timeout_s: u64,
+    step: &str,
+    op: impl Future<Output = Result<T, Error>>,
+) -> Result<T, Error> {
+    if timeout_s == 0 {
+        return op.await;
+    }
+    tokio::time::timeout(Duration::from_secs(timeout_s), op)
+        .await
+        .unwrap_or_else(|_| {
+            Err(Error::StepTimeout {
+                step: step.to_string(),
+                timeout_s,
+            })
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn quick(attempts: u32, timeout: Option<Duration>) -> RetryPolicy {
+        RetryPolicy {
+            attempts,
+            timeout,
+            backoff: Duration::from_millis(1),
+        }
+    }
+
+    #[tokio::test]
+    async fn failed_attempts_are_retried_and_reported() {
+        let mut calls = 0;
+        let mut retries = Vec::new();
+        let result = retry(
+            quick(3, None),
+            "image download",
+            |r| retries.push(r.to_string()),
+            || {
+                calls += 1;
+                let fail = calls < 3;
+                async move {
+                    if fail {
+                        Err(Error::Daemon {
+                            message: "connection reset".into(),
+                        })
+                    } else {
+                        Ok(calls)
+                    }
+                }
+            },
+        )
+        .await;
+
+        assert_eq!(result.unwrap(), 3);
+        assert_eq!(
+            retries,
+            [
+                "image download failed: daemon error: connection reset; retrying (2/3)",
+                "image download failed: daemon error: connection reset; retrying (3/3)",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn hung_attempts_time_out() {
+        let result: Result<(), Error> = retry(
+            quick(2, Some(Duration::from_millis(10))),
+            "agent connection",
+            |_| {},
+            || std::future::pending(),
+        )
+        .await;
+
+        let Err(Error::StepTimeout { ref step, .. }) = result else {
+            panic!("got: {result:?}");
+        };
+        assert_eq!(step, "agent connection");
+    }
+
+    #[tokio::test]
+    async fn timed_steps_run_once_and_time_out() {
+        let mut calls = 0;
+        let result: Result<(), Error> = timed(1, "provisioning", async {
+            calls += 1;
+            std::future::pending().await
+        })
+        .await;
+
+        let Err(Error::StepTimeout {
+            ref step,
+            timeout_s,
+        }) = result
+        else {
+            panic!("got: {result:?}");
+        };
+        assert_eq!((step.as_str(), timeout_s), ("provisioning", 1));
+        assert_eq!(calls, 1);
+        assert_eq!(timed(0, "boot", async { Ok(7) }).await.unwrap(), 7);
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_a_cap() {
+        let policy = RetryPolicy::new(0, 10);
+        assert_eq!(policy.timeout, None);
+        assert_eq!(policy.backoff_before(2), Duration::from_secs(2));
+        assert_eq!(policy.backoff_before(4), Duration::from_secs(8));
+        assert_eq!(policy.backoff_before(10), MAX_BACKOFF);
+    }
+}
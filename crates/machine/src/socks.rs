@@ -0,0 +1,153 @@
+//! Minimal SOCKS5 server that dials through the guest agent.
+//!
+//! Backs `rum tunnel`: every CONNECT is resolved and opened from inside the
+//! guest (see [`crate::guest::dial`]), so host tools can reach anything the
+//! VM can, such as services on its private networks. Only the no-auth
+//! method and the CONNECT command of RFC 1928 are supported.
+
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+use crate::error::Error;
+use crate::guest;
+
+const VERSION: u8 = 5;
+const NO_AUTH: u8 = 0;
+const NO_ACCEPTABLE_METHOD: u8 = 0xff;
+const CMD_CONNECT: u8 = 1;
+
+const REPLY_SUCCEEDED: u8 = 0;
+const REPLY_HOST_UNREACHABLE: u8 = 4;
+const REPLY_COMMAND_NOT_SUPPORTED: u8 = 7;
+const REPLY_ADDRESS_NOT_SUPPORTED: u8 = 8;
+
+/// Accept SOCKS5 clients on `listener` until the task is dropped.
+pub async fn serve(listener: TcpListener, cid: u32) -> Result<(), Error> {
+    loop {
+        let (mut tcp, peer) = listener.accept().await.map_err(|e| Error::Io {
+            context: "accepting SOCKS connection".into(),
+            source: e,
+        })?;
+        tokio::spawn(async move {
+            let (host, port) = match negotiate(&mut tcp).await {
+                Ok(target) => target,
+                Err(e) => {
+                    tracing::debug!(%peer, error = %e, "socks: handshake failed");
+                    return;
+                }
+            };
+            let mut vsock = match guest::dial(cid, &host, port).await {
+                Ok(vsock) => vsock,
+                Err(e) => {
+                    tracing::debug!(host, port, error = %e, "socks: guest dial failed");
+                    let _ = reply(&mut tcp, REPLY_HOST_UNREACHABLE).await;
+                    return;
+                }
+            };
+            if reply(&mut tcp, REPLY_SUCCEEDED).await.is_err() {
+                return;
+            }
+            tracing::debug!(host, port, "socks: connected");
+            if let Err(e) = tokio::io::copy_bidirectional(&mut tcp, &mut vsock).await {
+                tracing::debug!(host, port, error = %e, "socks: proxy error");
+            }
+        });
+    }
+}
+
+/// Run the method selection and read the CONNECT request.
+///
+/// Returns the requested `(host, port)`. Unsupported requests are answered
+/// with the matching SOCKS error before the error is returned.
+async fn negotiate<S>(stream: &mut S) -> std::io::Result<(String, u16)>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let [version, method_count] = read_array(stream).await?;
+    if version != VERSION {
+        return Err(invalid(format!("unsupported SOCKS version {version}")));
+    }
+    let mut methods = vec![0u8; method_count as usize];
+    stream.read_exact(&mut methods).await?;
+    if !methods.contains(&NO_AUTH) {
+        stream.write_all(&[VERSION, NO_ACCEPTABLE_METHOD]).await?;
+        return Err(invalid("client requires authentication".into()));
+    }
+    stream.write_all(&[VERSION, NO_AUTH]).await?;
+
+    let [_, command, _, address_type] = read_array(stream).await?;
+    let host = match address_type {
+        1 => Ipv4Addr::from(read_array::<_, 4>(stream).await?).to_string(),
+        3 => {
+            let len = stream.read_u8().await?;
+            let mut name = vec![0u8; len as usize];
+            stream.read_exact(&mut name).await?;
+            String::from_utf8(name).map_err(|_| invalid("host name is not UTF-8".into()))?
+        }
+        4 => Ipv6Addr::from(read_array::<_, 16>(stream).await?).to_string(),
+        other => {
+            reply(stream, REPLY_ADDRESS_NOT_SUPPORTED).await?;
+            return Err(invalid(format!("unsupported address type {other}")));
+        }
+    };
+    let port = stream.read_u16().await?;
+    if command != CMD_CONNECT {
+        reply(stream, REPLY_COMMAND_NOT_SUPPORTED).await?;
+        return Err(invalid(format!("unsupported command {command}")));
+    }
+    Ok((host, port))
+}
+
+/// Send a reply with an unspecified bound address; clients only need it
+/// for BIND, which is not supported.
+async fn reply<S: AsyncWrite + Unpin>(stream: &mut S, code: u8) -> std::io::Result<()> {
+    stream
+        .write_all(&[VERSION, code, 0, 1, 0, 0, 0, 0, 0, 0])
+        .await
+}
+
+async fn read_array<S: AsyncRead + Unpin, const N: usize>(
+    stream: &mut S,
+) -> std::io::Result<[u8; N]> {
+    let mut buf = [0u8; N];
+    stream.read_exact(&mut buf).await?;
+    Ok(buf)
+}
+
+fn invalid(message: String) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn negotiate_reads_domain_connect_request() {
+        let (mut client, mut server) = tokio::io::duplex(64);
+        let mut request = vec![VERSION, 1, NO_AUTH, VERSION, CMD_CONNECT, 0, 3, 9];
+        request.extend_from_slice(b"db.svc.lo");
+        request.extend_from_slice(&5432u16.to_be_bytes());
+        client.write_all(&request).await.unwrap();
+
+        let target = negotiate(&mut server).await.unwrap();
+        assert_eq!(target, ("db.svc.lo".to_string(), 5432));
+        let mut method = [0u8; 2];
+        client.read_exact(&mut method).await.unwrap();
+        assert_eq!(method, [VERSION, NO_AUTH]);
+    }
+
+    #[tokio::test]
+    async fn negotiate_rejects_bind_command() {
+        let (mut client, mut server) = tokio::io::duplex(64);
+        let request = [VERSION, 1, NO_AUTH, VERSION, 2, 0, 1, 10, 0, 0, 1, 0, 80];
+        client.write_all(&request).await.unwrap();
+
+        assert!(negotiate(&mut server).await.is_err());
+        let mut replies = [0u8; 12];
+        client.read_exact(&mut replies).await.unwrap();
+        assert_eq!(replies[3], REPLY_COMMAND_NOT_SUPPORTED);
+    }
+}
@@ -0,0 +1,301 @@
+//! `Host` blocks for rum machines in the user's `~/.ssh/config`.
+//!
+//! `rum ssh-config --install` writes one block per machine between marker
+//! comments carrying the machine id, so installing again replaces the block
+//! in place and destroying the machine removes it without touching the rest
+//! of the file.
+
+use std::io::Write;
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+
+use crate::config::SshConfig;
+use crate::error::Error;
+
+/// The `Host` entry reaching a machine at `ip` with `identity_file`, using
+/// the user and client options of `ssh`.
+pub fn host_block(host: &str, ip: &str, ssh: &SshConfig, identity_file: &Path) -> String {
+    let mut block = format!(
+        "Host {}\n    \
+         HostName {ip}\n    \
+         User {}\n    \
+         IdentityFile \"{}\"\n    \
+         IdentitiesOnly yes\n    \
+         StrictHostKeyChecking no\n    \
+         UserKnownHostsFile /dev/null\n",
+        host_pattern(host),
+        ssh.user,
+        identity_file.display()
+    );
+    if ssh.forward_agent {
+        block.push_str("    ForwardAgent yes\n");
+    }
+    if let Some(jump) = &ssh.proxy_jump {
+        block.push_str(&format!("    ProxyJump {jump}\n"));
+    }
+    for option in &ssh.extra_options {
+        if let Some((key, value)) = option.split_once('=') {
+            block.push_str(&format!("    {key} {value}\n"));
+        }
+    }
+    block
+}
+
+/// `host` as a single literal `Host` pattern: characters ssh would read
+/// as whitespace, a quote, a comment or a wildcard become `_`, so the block
+/// can neither match other hosts nor spill into further lines.
+fn host_pattern(host: &str) -> String {
+    host.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+fn begin_marker(id: &str) -> String {
+    format!("# BEGIN rum {id}")
+}
+
+fn end_marker(id: &str) -> String {
+    format!("# END rum {id}")
+}
+
+/// `contents` with the block of machine `id` set to `block`: replaced where
+/// it was, or appended.
+pub fn upsert(contents: &str, id: &str, block: &str) -> String {
+    let managed = format!("{}\n{block}{}\n", begin_marker(id), end_marker(id));
+    match block_range(contents, id) {
+        Some((start, end)) => format!("{}{managed}{}", &contents[..start], &contents[end..]),
+        None if contents.is_empty() => managed,
+        None if contents.ends_with('\n') => format!("{contents}\n{managed}"),
+        None => format!("{contents}\n\n{managed}"),
+    }
+}
+
+/// `contents` without the block of machine `id`, or `None` if it has none.
+pub fn remove(contents: &str, id: &str) -> Option<String> {
+    let (start, end) = block_range(contents, id)?;
+    let before = contents[..start].trim_end_matches('\n');
+    let after = contents[end..].trim_start_matches('\n');
+    Some(match (before.is_empty(), after.is_empty()) {
+        (true, _) => after.to_string(),
+        (false, true) => format!("{before}\n"),
+        (false, false) => format!("{before}\n\n{after}"),
+    })
+}
+
+/// Byte range of the block of `id` including its markers and the newline
+/// after the end marker.
+fn block_range(contents: &str, id: &str) -> Option<(usize, usize)> {
+    let begin = begin_marker(id);
+    let end = end_marker(id);
+    let start = contents
+        .match_indices(&begin)
+        .map(|(at, _)| at)
+        .find(|&at| at == 0 || contents.as_bytes()[at - 1] == b'\n')?;
+    let mut end_at = start + contents[start..].find(&end)? + end.len();
+    if contents[end_at..].starts_with('\n') {
+        end_at += 1;
+    }
+    Some((start, end_at))
+}
+
+/// Write `block` for machine `id` into the ssh config at `path`.
+pub fn install(path: &Path, id: &str, block: &str) -> Result<(), Error> {
+    let contents = read(path)?;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).map_err(|e| io_error(path, e))?;
+    }
+    write(path, &upsert(&contents, id, block))
+}
+
+/// Remove the block of machine `id` from the ssh config at `path`. Returns
+/// whether there was one.
+pub fn uninstall(path: &Path, id: &str) -> Result<bool, Error> {
+    let contents = read(path)?;
+    let Some(updated) = remove(&contents, id) else {
+        return Ok(false);
+    };
+    write(path, &updated)?;
+    Ok(true)
+}
+
+/// Replace the ssh config at `path` with `contents` through a temp file and
+/// a rename, so a crash midway leaves the old config intact. A symlinked
+/// config is written through to its target, keeping its permissions.
+fn write(path: &Path, contents: &str) -> Result<(), Error> {
+    let target = std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    // ssh refuses a config others can write to, so a new one is private.
+    let mode = std::fs::metadata(&target)
+        .map(|metadata| metadata.permissions().mode() & 0o7777)
+        .unwrap_or(0o600);
+    let tmp = temp_path(&target);
+    std::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(&tmp)
+        .and_then(|mut file| {
+            file.set_permissions(std::fs::Permissions::from_mode(mode))?;
+            file.write_all(contents.as_bytes())?;
+            file.sync_all()
+        })
+        .and_then(|()| std::fs::rename(&tmp, &target))
+        .map_err(|e| {
+            let _ = std::fs::remove_file(&tmp);
+            io_error(path, e)
+        })
+}
+
+fn temp_path(path: &Path) -> PathBuf {
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!(".{file_name}.rum.tmp"))
+}
+
+fn read(path: &Path) -> Result<String, Error> {
+    match std::fs::read_to_string(path) {
+        Ok(contents) => Ok(contents),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(String::new()),
+        Err(e) => Err(io_error(path, e)),
+    }
+}
+
+fn io_error(path: &Path, source: std::io::Error) -> Error {
+    Error::Io {
+        context: format!("updating {}", path.display()),
+        source,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const USER_CONFIG: &str = "Host github.com\n    User git\n";
+
+    #[test]
+    fn upsert_appends_then_replaces_in_place() {
+        let block = host_block(
+            "dev",
+            "192.168.122.10",
+            &SshConfig::default(),
+            Path::new("/k/id"),
+        );
+        let installed = upsert(USER_CONFIG, "abcd1234", &block);
+        assert!(installed.starts_with(USER_CONFIG));
+        assert!(installed.contains("# BEGIN rum abcd1234\nHost dev\n"));
+        assert!(installed.ends_with("# END rum abcd1234\n"));
+
+        let moved = host_block(
+            "dev",
+            "192.168.122.20",
+            &SshConfig::default(),
+            Path::new("/k/id"),
+        );
+        let updated = upsert(&installed, "abcd1234", &moved);
+        assert!(updated.contains("HostName 192.168.122.20"));
+        assert!(!updated.contains("192.168.122.10"));
+        assert_eq!(updated.matches("# BEGIN rum").count(), 1);
+    }
+
+    #[test]
+    fn host_block_carries_the_client_options() {
+        let ssh = SshConfig {
+            forward_agent: true,
+            proxy_jump: Some("bastion".into()),
+            extra_options: vec!["ServerAliveInterval=30".into()],
+            ..SshConfig::default()
+        };
+        let block = host_block("dev", "10.0.0.2", &ssh, Path::new("/k/id"));
+        assert!(block.contains("    User rum\n"));
+        assert!(block.contains("    ForwardAgent yes\n"));
+        assert!(block.contains("    ProxyJump bastion\n"));
+        assert!(block.ends_with("    ServerAliveInterval 30\n"));
+    }
+
+    #[test]
+    fn remove_restores_the_rest_of_the_file() {
+        let block = host_block("dev", "10.0.0.2", &SshConfig::default(), Path::new("/k/id"));
+        let installed = upsert(USER_CONFIG, "abcd1234", &block);
+        let both = upsert(&installed, "ffff0000", &block);
+
+        let removed = remove(&both, "abcd1234").unwrap();
+        assert!(removed.starts_with(USER_CONFIG));
+        assert!(!removed.contains("abcd1234"));
+        assert!(removed.contains("# BEGIN rum ffff0000"));
+
+        assert_eq!(remove(&installed, "abcd1234").unwrap(), USER_CONFIG);
+        assert_eq!(remove(USER_CONFIG, "abcd1234"), None);
+        assert_eq!(
+            remove(&upsert("", "abcd1234", &block), "abcd1234").unwrap(),
+            ""
+        );
+    }
+    #[test]
+    fn host_names_cannot_break_out_of_the_host_line() {
+        let block = host_block(
+            "dev *\n    ProxyCommand sh",
+            "10.0.0.2",
+            &SshConfig::default(),
+            Path::new("/k/id"),
+        );
+        assert!(block.starts_with("Host dev_______ProxyCommand_sh\n"));
+        assert!(!block.contains("\n    ProxyCommand"));
+        assert_eq!(host_pattern("my-vm_1.local"), "my-vm_1.local");
+        assert_eq!(host_pattern("a\"b#c,d!e?"), "a_b_c_d_e_");
+    }
+
+    #[test]
+    fn install_replaces_the_file_and_keeps_its_mode() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config");
+        let block = host_block("dev", "10.0.0.2", &SshConfig::default(), Path::new("/k/id"));
+
+        install(&path, "abcd1234", &block).unwrap();
+        let mode = |path: &Path| std::fs::metadata(path).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode(&path), 0o600);
+
+        std::fs::write(&path, USER_CONFIG).unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o640)).unwrap();
+        install(&path, "abcd1234", &block).unwrap();
+        assert_eq!(mode(&path), 0o640);
+        assert!(
+            std::fs::read_to_string(&path)
+                .unwrap()
+                .starts_with(USER_CONFIG)
+        );
+        assert!(!temp_path(&path).exists());
+
+        assert!(uninstall(&path, "abcd1234").unwrap());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), USER_CONFIG);
+        assert_eq!(mode(&path), 0o640);
+    }
+
+    #[test]
+    fn install_writes_through_a_symlinked_config() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("dotfiles-ssh-config");
+        std::fs::write(&target, USER_CONFIG).unwrap();
+        let link = dir.path().join("config");
+        std::os::unix::fs::symlink(&target, &link).unwrap();
+
+        let block = host_block("dev", "10.0.0.2", &SshConfig::default(), Path::new("/k/id"));
+        install(&link, "abcd1234", &block).unwrap();
+        assert!(
+            std::fs::symlink_metadata(&link)
+                .unwrap()
+                .file_type()
+                .is_symlink()
+        );
+        assert!(
+            std::fs::read_to_string(&target)
+                .unwrap()
+                .contains("Host dev\n")
+        );
+    }
+}
@@ -0,0 +1,490 @@
+//! Two-way sync for `mode = "sync"` mounts.
+//!
+//! Host and guest keep independent copies of the tree, so builds in the
+//! guest run on its own disk instead of a shared filesystem. Both copies are
+//! watched, and every changed path is reconciled against the content both
+//! sides had after the last sync (the base):
+//!
+//! - changed on one side only: the change is copied to the other side,
+//!   deletions included;
+//! - changed on both sides: the host wins, and the guest's version is kept
+//!   next to the host file with a [`CONFLICT_SUFFIX`]. An edit always beats
+//!   a deletion on the other side.
+//!
+//! The base is kept in the work dir, so edits made while the machine was
+//! down are picked up by the full reconcile on the next start. It describes
+//! one guest disk: resetting or recreating the disk drops it (see
+//! [`forget_bases`]), and a guest copy found empty is filled from the host
+//! rather than taken as the user deleting every file.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+
+use guest::agent::{FsEvent, FsWatchOptions};
+use guest::client::{Client, ClientError};
+use guest::watch::IgnoreSet;
+use tokio::sync::mpsc;
+
+use crate::config::ResolvedMount;
+use crate::error::Error;
+use crate::guest::AgentConnector;
+
+/// Appended to the host path of the guest's copy of a conflicting file.
+pub const CONFLICT_SUFFIX: &str = ".rum-conflict";
+
+/// What reconciling one path does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Action {
+    InSync,
+    Push,
+    Pull,
+    RemoveInGuest,
+    RemoveOnHost,
+    /// Keep the guest's copy aside, then push the host's.
+    Conflict,
+}
+
+/// Decide how to reconcile one path from its content hash in the base and
+/// on each side; `None` means the file does not exist there.
+fn decide(base: Option<&str>, host: Option<&str>, guest: Option<&str>) -> Action {
+    if host == guest {
+        Action::InSync
+    } else if host == base {
+        match guest {
+            Some(_) => Action::Pull,
+            None => Action::RemoveOnHost,
+        }
+    } else if guest == base {
+        match host {
+            Some(_) => Action::Push,
+            None => Action::RemoveInGuest,
+        }
+    } else {
+        match (host, guest) {
+            (None, _) => Action::Pull,
+            (_, None) => Action::Push,
+            _ => Action::Conflict,
+        }
+    }
+}
+
+/// Where a change was seen.
+enum Change {
+    /// A file below the root, relative to it.
+    Path(String),
+    /// Something that needs the whole tree compared, such as a directory
+    /// being created, moved or removed.
+    Tree,
+}
+
+struct Session {
+    client: Client<AgentConnector>,
+    host_root: PathBuf,
+    guest_root: String,
+    ignore: IgnoreSet,
+    state_path: PathBuf,
+    /// Content hash per relative file path after the last sync.
+    base: BTreeMap<String, String>,
+}
+
+/// Keep `mount.source` and `mount.target` in the guest in sync until the
+/// returned future is dropped or the guest connection is lost.
+pub async fn run(
+    connector: AgentConnector,
+    mount: ResolvedMount,
+    options: FsWatchOptions,
+    state_path: PathBuf,
+) -> Result<(), Error> {
+    let client = crate::guest::wait_for_agent(connector.clone())
+        .await
+        .map_err(sync_error)?;
+    let mut session = Session {
+        client,
+        ignore: IgnoreSet::new(&mount.source, &options.ignore, options.gitignore),
+        host_root: mount.source,
+        guest_root: mount.target,
+        base: read_state(&state_path),
+        state_path,
+    };
+
+    let (changes, mut received) = mpsc::unbounded_channel();
+    let host_watch = tokio::spawn(watch_host(
+        session.host_root.clone(),
+        options.clone(),
+        changes.clone(),
+    ));
+    let guest_watch = tokio::spawn(watch_guest(
+        connector,
+        session.guest_root.clone(),
+        options,
+        changes,
+    ));
+
+    let result = session.sync(&mut received).await;
+    host_watch.abort();
+    guest_watch.abort();
+    result
+}
+
+impl Session {
+    async fn sync(&mut self, received: &mut mpsc::UnboundedReceiver<Change>) -> Result<(), Error> {
+        self.reconcile_tree().await?;
+        tracing::info!(
+            host = %self.host_root.display(),
+            guest = %self.guest_root,
+            files = self.base.len(),
+            "mount sync ready"
+        );
+
+        while let Some(change) = received.recv().await {
+            // Take everything that queued up meanwhile as one batch.
+            let mut paths = BTreeSet::new();
+            let mut tree = false;
+            for change in
+                std::iter::once(change).chain(std::iter::from_fn(|| received.try_recv().ok()))
+            {
+                match change {
+                    Change::Path(path) => {
+                        paths.insert(path);
+                    }
+                    Change::Tree => tree = true,
+                }
+            }
+
+            if tree {
+                self.reconcile_tree().await?;
+            } else {
+                for path in &paths {
+                    self.reconcile_logged(path).await;
+                }
+                self.write_state();
+            }
+        }
+        Err(Error::Daemon {
+            message: format!("sync of {} stopped watching", self.guest_root),
+        })
+    }
+
+    /// Compare every file on both sides and in the base.
+    async fn reconcile_tree(&mut self) -> Result<(), Error> {
+        let host_root = self.host_root.clone();
+        let host_entries = tokio::task::spawn_blocking(move || guest::tree::walk(&host_root))
+            .await
+            .map_err(|e| Error::Daemon {
+                message: format!("walk task: {e}"),
+            })?
+            .map_err(|e| Error::Io {
+                context: format!("listing {}", self.host_root.display()),
+                source: e,
+            })?;
+        let guest_entries = self
+            .client
+            .stat_tree(&self.guest_root)
+            .await
+            .map_err(sync_error)?;
+
+        let guest_files = guest_entries
+            .iter()
+            .filter(|entry| !entry.is_dir && !self.ignore.is_ignored(Path::new(&entry.path), false))
+            .count();
+        if stale_base(&self.base, guest_files) {
+            tracing::info!(
+                guest = %self.guest_root,
+                "guest copy is empty; copying the host tree instead of removing it"
+            );
+            self.base.clear();
+        }
+
+        let mut paths: BTreeSet<String> = self.base.keys().cloned().collect();
+        paths.extend(
+            host_entries
+                .into_iter()
+                .chain(guest_entries)
+                .filter(|entry| !entry.is_dir)
+                .map(|entry| entry.path),
+        );
+        for path in &paths {
+            self.reconcile_logged(path).await;
+        }
+        self.write_state();
+        Ok(())
+    }
+
+    /// Reconcile one path, logging failures so one unreadable file does not
+    /// stop the rest from syncing.
+    async fn reconcile_logged(&mut self, path: &str) {
+        if self.ignore.is_ignored(Path::new(path), false) {
+            return;
+        }
+        if let Err(error) = self.reconcile(path).await {
+            tracing::warn!(path, %error, "failed to sync file");
+        }
+    }
+
+    async fn reconcile(&mut self, path: &str) -> Result<(), Error> {
+        let host_path = self.host_root.join(path);
+        let guest_path = format!("{}/{path}", self.guest_root.trim_end_matches('/'));
+        let host = host_hash(&host_path).await?;
+        let guest = self
+            .client
+            .file_hash(&guest_path)
+            .await
+            .map_err(sync_error)?;
+        let base = self.base.get(path).map(String::as_str);
+
+        let action = decide(base, host.as_deref(), guest.as_deref());
+        match action {
+            Action::InSync => {}
+            Action::Push => {
+                self.client
+                    .copy_to_guest(&host_path, &guest_path)
+                    .await
+                    .map_err(sync_error)?;
+            }
+            Action::Pull => {
+                self.client
+                    .copy_from_guest(&guest_path, &host_path)
+                    .await
+                    .map_err(sync_error)?;
+            }
+            Action::RemoveInGuest => {
+                self.client
+                    .remove_path(&guest_path)
+                    .await
+                    .map_err(sync_error)?;
+            }
+            Action::RemoveOnHost => match tokio::fs::remove_file(&host_path).await {
+                Ok(()) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => {
+                    return Err(Error::Io {
+                        context: format!("removing {}", host_path.display()),
+                        source: e,
+                    });
+                }
+            },
+            Action::Conflict => {
+                let mut kept = host_path.clone().into_os_string();
+                kept.push(CONFLICT_SUFFIX);
+                self.client
+                    .copy_from_guest(&guest_path, Path::new(&kept))
+                    .await
+                    .map_err(sync_error)?;
+                self.client
+                    .copy_to_guest(&host_path, &guest_path)
+                    .await
+                    .map_err(sync_error)?;
+                tracing::warn!(
+                    path,
+                    kept = %Path::new(&kept).display(),
+                    "file changed on host and guest; kept the host version"
+                );
+            }
+        }
+        if action != Action::InSync {
+            tracing::debug!(path, ?action, "synced file");
+        }
+
+        let synced = match action {
+            Action::Pull => guest,
+            Action::RemoveInGuest | Action::RemoveOnHost => None,
+            _ => host,
+        };
+        match synced {
+            Some(hash) => self.base.insert(path.to_string(), hash),
+            None => self.base.remove(path),
+        };
+        Ok(())
+    }
+
+    fn write_state(&self) {
+        if let Err(error) = std::fs::write(&self.state_path, facet_json::to_string(&self.base)) {
+            tracing::warn!(path = %self.state_path.display(), %error, "failed to save sync state");
+        }
+    }
+}
+
+/// Whether `base` no longer describes a guest copy with `guest_files` files.
+///
+/// An empty guest copy next to a non-empty base means a fresh or reset
+/// disk far more often than a user deleting everything, and reading it as a
+/// deletion would wipe the host tree.
+fn stale_base(base: &BTreeMap<String, String>, guest_files: usize) -> bool {
+    guest_files == 0 && !base.is_empty()
+}
+
+/// Drop the sync bases kept in `work_dir`, e.g. because the guest disk they
+/// describe was reset or recreated.
+pub fn forget_bases(work_dir: &Path) -> Result<(), Error> {
+    let Ok(entries) = std::fs::read_dir(work_dir) else {
+        return Ok(());
+    };
+    for entry in entries.flatten() {
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        if name.starts_with("sync-") && name.ends_with(".json") {
+            std::fs::remove_file(entry.path()).map_err(|e| Error::Io {
+                context: format!("removing {}", entry.path().display()),
+                source: e,
+            })?;
+        }
+    }
+    Ok(())
+}
+
+/// Content hash of a host file, or `None` when there is no file at `path`.
+async fn host_hash(path: &Path) -> Result<Option<String>, Error> {
+    let owned = path.to_path_buf();
+    let result = tokio::task::spawn_blocking(move || {
+        if owned.is_file() {
+            guest::tree::hash_file(&owned).map(Some)
+        } else {
+            Ok(None)
+        }
+    })
+    .await
+    .map_err(|e| Error::Daemon {
+        message: format!("hash task: {e}"),
+    })?;
+    result.map_err(|e| Error::Io {
+        context: format!("hashing {}", path.display()),
+        source: e,
+    })
+}
+
+async fn watch_host(
+    root: PathBuf,
+    options: FsWatchOptions,
+    changes: mpsc::UnboundedSender<Change>,
+) {
+    let (events, mut received) = mpsc::unbounded_channel();
+    let watcher = guest::watch::watch(vec![root.display().to_string()], options, events);
+    tokio::pin!(watcher);
+    loop {
+        tokio::select! {
+            result = &mut watcher => {
+                if let Err(error) = result {
+                    tracing::warn!(root = %root.display(), %error, "host watch failed");
+                }
+                return;
+            }
+            Some(event) = received.recv() => {
+                if changes.send(change(&root.display().to_string(), event)).is_err() {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+/// Forward guest events over a connection of their own, so the long-lived
+/// subscription does not hold up the transfers. Over the serial channel it
+/// shares the one connection there is.
+async fn watch_guest(
+    connector: AgentConnector,
+    root: String,
+    options: FsWatchOptions,
+    changes: mpsc::UnboundedSender<Change>,
+) {
+    let client = crate::guest::agent_client(connector);
+    let prefix = root.clone();
+    let result = client
+        .subscribe_fs_events(vec![root], options, move |event| {
+            let _ = changes.send(change(&prefix, event));
+        })
+        .await;
+    if let Err(error) = result {
+        tracing::warn!(%error, "guest watch failed");
+    }
+}
+
+fn change(root: &str, event: FsEvent) -> Change {
+    let relative = Path::new(&event.path)
+        .strip_prefix(root)
+        .ok()
+        .map(|p| p.to_string_lossy().into_owned());
+    match relative {
+        Some(path) if !event.is_dir && !path.is_empty() => Change::Path(path),
+        _ => Change::Tree,
+    }
+}
+
+fn read_state(path: &Path) -> BTreeMap<String, String> {
+    std::fs::read_to_string(path)
+        .ok()
+        .and_then(|contents| facet_json::from_str(&contents).ok())
+        .unwrap_or_default()
+}
+
+fn sync_error(error: ClientError) -> Error {
+    Error::Daemon {
+        message: format!("mount sync: {error}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn one_sided_changes_are_copied_across() {
+        assert_eq!(decide(Some("a"), Some("b"), Some("a")), Action::Push);
+        assert_eq!(decide(Some("a"), Some("a"), Some("b")), Action::Pull);
+        assert_eq!(decide(Some("a"), None, Some("a")), Action::RemoveInGuest);
+        assert_eq!(decide(Some("a"), Some("a"), None), Action::RemoveOnHost);
+        assert_eq!(decide(None, Some("a"), None), Action::Push);
+        assert_eq!(decide(None, None, Some("a")), Action::Pull);
+        assert_eq!(decide(Some("a"), Some("b"), Some("b")), Action::InSync);
+    }
+
+    #[test]
+    fn guest_reset_leaves_the_host_tree_alone() {
+        let host: BTreeMap<String, String> = [("Cargo.toml", "h1"), ("src/main.rs", "h2")]
+            .into_iter()
+            .map(|(path, hash)| (path.to_string(), hash.to_string()))
+            .collect();
+        // Both sides were in sync, then the guest disk was reset.
+        let mut base = host.clone();
+        let guest: BTreeMap<String, String> = BTreeMap::new();
+
+        if stale_base(&base, guest.len()) {
+            base.clear();
+        }
+        let mut after = host.clone();
+        for path in host.keys() {
+            let action = decide(
+                base.get(path).map(String::as_str),
+                host.get(path).map(String::as_str),
+                guest.get(path).map(String::as_str),
+            );
+            assert_eq!(action, Action::Push, "{path}");
+            if action == Action::RemoveOnHost {
+                after.remove(path);
+            }
+        }
+        assert_eq!(after, host);
+
+        // A guest that still has files keeps its base, so deletions made
+        // in the guest reach the host.
+        assert!(!stale_base(&host, 1));
+        assert!(!stale_base(&BTreeMap::new(), 0));
+    }
+
+    #[test]
+    fn forget_bases_removes_only_sync_state() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("sync-mnt_src.json"), "{}").unwrap();
+        std::fs::write(dir.path().join("live-mounts.json"), "[]").unwrap();
+        forget_bases(dir.path()).unwrap();
+        assert!(!dir.path().join("sync-mnt_src.json").exists());
+        assert!(dir.path().join("live-mounts.json").exists());
+    }
+
+    #[test]
+    fn two_sided_changes_prefer_host_and_edits() {
+        assert_eq!(decide(Some("a"), Some("b"), Some("c")), Action::Conflict);
+        assert_eq!(decide(None, Some("b"), Some("c")), Action::Conflict);
+        assert_eq!(decide(Some("a"), None, Some("c")), Action::Pull);
+        assert_eq!(decide(Some("a"), Some("b"), None), Action::Push);
+    }
+}
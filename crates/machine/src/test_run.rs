@@ -0,0 +1,201 @@
+//! Results of `rum test`: whether a `[[tests]]` command ended the way its
+//! entry expects, and the JUnit and JSON reports CI systems read.
+
+use std::fmt::Write;
+
+use facet::Facet;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::config::TestConfig;
+use crate::error::Error;
+
+/// Output lines kept with a failed test.
+pub const OUTPUT_LINES: usize = 20;
+
+/// How one test ended.
+#[derive(Debug, Clone, PartialEq, Eq, Facet, Serialize, Deserialize)]
+pub struct TestOutcome {
+    pub name: String,
+    pub passed: bool,
+    /// Why the test failed; `None` when it passed.
+    pub failure: Option<String>,
+    pub duration_ms: u64,
+    /// Last [`OUTPUT_LINES`] lines the command printed, for failed tests.
+    pub output: Vec<String>,
+}
+
+/// A `[[tests]]` entry with its `stdout` regex compiled, so a run checks
+/// every result against the same pattern.
+#[derive(Debug, Clone)]
+pub struct TestCheck {
+    pub config: TestConfig,
+    stdout: Option<Regex>,
+}
+
+impl TestCheck {
+    pub fn new(config: TestConfig) -> Result<Self, Error> {
+        let stdout = match &config.stdout {
+            Some(pattern) => Some(Regex::new(pattern).map_err(|e| Error::Validation {
+                message: format!("test '{}': invalid stdout regex: {e}", config.name),
+            })?),
+            None => None,
+        };
+        Ok(Self { config, stdout })
+    }
+
+    /// Why a command that exited with `exit_code` after printing `stdout`
+    /// fails this test, or `None` if it passes.
+    pub fn failure(&self, exit_code: i32, stdout: &str) -> Option<String> {
+        if exit_code != self.config.exit_code {
+            return Some(format!(
+                "exited with status {exit_code}, expected {}",
+                self.config.exit_code
+            ));
+        }
+        match &self.stdout {
+            Some(regex) if !regex.is_match(stdout) => {
+                Some(format!("stdout does not match /{}/", regex.as_str()))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// `outcomes` as one JUnit `<testsuite>` named `suite`.
+pub fn junit_xml(suite: &str, outcomes: &[TestOutcome]) -> String {
+    let failures = outcomes.iter().filter(|o| !o.passed).count();
+    let total_ms: u64 = outcomes.iter().map(|o| o.duration_ms).sum();
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    writeln!(
+        xml,
+        "<testsuite name=\"{}\" tests=\"{}\" failures=\"{failures}\" time=\"{}\">",
+        escape(suite),
+        outcomes.len(),
+        seconds(total_ms)
+    )
+    .unwrap();
+    for outcome in outcomes {
+        write!(
+            xml,
+            "  <testcase name=\"{}\" classname=\"{}\" time=\"{}\"",
+            escape(&outcome.name),
+            escape(suite),
+            seconds(outcome.duration_ms)
+        )
+        .unwrap();
+        match &outcome.failure {
+            None => xml.push_str("/>\n"),
+            Some(failure) => {
+                writeln!(
+                    xml,
+                    ">\n    <failure message=\"{}\">{}</failure>\n  </testcase>",
+                    escape(failure),
+                    escape(&outcome.output.join("\n"))
+                )
+                .unwrap();
+            }
+        }
+    }
+    xml.push_str("</testsuite>\n");
+    xml
+}
+
+/// `outcomes` as a JSON array.
+pub fn json_report(outcomes: &[TestOutcome]) -> String {
+    facet_json::to_string(&outcomes.to_vec())
+}
+
+fn seconds(ms: u64) -> String {
+    format!("{:.3}", ms as f64 / 1000.0)
+}
+
+/// `text` escaped for XML, without the control characters XML 1.0 does not
+/// allow (such as the ESC of terminal colors).
+fn escape(text: &str) -> String {
+    text.chars()
+        .filter(|&c| !c.is_control() || matches!(c, '\t' | '\n' | '\r'))
+        .collect::<String>()
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(stdout: Option<&str>) -> TestCheck {
+        TestCheck::new(TestConfig {
+            name: "nginx".into(),
+            command: "curl -s localhost".into(),
+            stdout: stdout.map(str::to_string),
+            ..TestConfig::default()
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn failure_checks_exit_code_then_stdout() {
+        assert_eq!(check(None).failure(0, ""), None);
+        assert_eq!(
+            check(None).failure(7, ""),
+            Some("exited with status 7, expected 0".into())
+        );
+        assert_eq!(check(Some("^Welcome")).failure(0, "Welcome to nginx"), None);
+        assert_eq!(
+            check(Some("^Welcome")).failure(0, "502 Bad Gateway"),
+            Some("stdout does not match /^Welcome/".into())
+        );
+    }
+
+    #[test]
+    fn check_rejects_an_invalid_regex() {
+        let config = TestConfig {
+            name: "bad".into(),
+            stdout: Some("(".into()),
+            ..TestConfig::default()
+        };
+        let error = TestCheck::new(config).unwrap_err().to_string();
+        assert!(
+            error.contains("test 'bad': invalid stdout regex"),
+            "{error}"
+        );
+    }
+
+    #[test]
+    fn escape_drops_xml_invalid_control_characters() {
+        assert_eq!(
+            escape("\x1b[31mred\x1b[0m\t<b>\u{0}"),
+            "[31mred[0m\t&lt;b&gt;"
+        );
+    }
+
+    #[test]
+    fn junit_report_lists_failures() {
+        let outcomes = [
+            TestOutcome {
+                name: "ok".into(),
+                passed: true,
+                failure: None,
+                duration_ms: 1500,
+                output: vec![],
+            },
+            TestOutcome {
+                name: "broken".into(),
+                passed: false,
+                failure: Some("exited with status 1, expected 0".into()),
+                duration_ms: 20,
+                output: vec!["<error>".into()],
+            },
+        ];
+        let xml = junit_xml("web", &outcomes);
+        assert!(xml.contains("<testsuite name=\"web\" tests=\"2\" failures=\"1\" time=\"1.520\">"));
+        assert!(xml.contains("<testcase name=\"ok\" classname=\"web\" time=\"1.500\"/>"));
+        assert!(
+            xml.contains("<failure message=\"exited with status 1, expected 0\">&lt;error&gt;"),
+            "got:\n{xml}"
+        );
+    }
+}
@@ -1,3 +1,6 @@
+use std::net::IpAddr;
+use std::path::Path;
+
 use crate::error::Error;
 
 /// Parse a human-readable size string into bytes.
@@ -41,6 +44,77 @@ pub fn parse_size(s: &str) -> Result<u64, Error> {
         })
 }
 
+/// Whether libvirt can find a virtiofsd to back virtiofs mounts.
+///
+/// Checks `PATH` and the libexec locations distros install it to.
+pub fn virtiofsd_available() -> bool {
+    let path = std::env::var_os("PATH").unwrap_or_default();
+    std::env::split_paths(&path)
+        .chain(["/usr/libexec", "/usr/lib/qemu", "/usr/lib"].map(std::path::PathBuf::from))
+        .any(|dir| dir.join("virtiofsd").is_file())
+}
+
+/// First address in `addrs` that is reachable from the host.
+///
+/// IPv4 is preferred unless `ipv6` is set, in which case only IPv6
+/// addresses are considered. Loopback, link-local, unspecified and
+/// multicast addresses are skipped.
+pub fn pick_routable<'a>(addrs: impl IntoIterator<Item = &'a str>, ipv6: bool) -> Option<String> {
+    let routable: Vec<IpAddr> = addrs
+        .into_iter()
+        .filter_map(|addr| addr.parse().ok())
+        .filter(|ip| match ip {
+            IpAddr::V4(v4) => {
+                !(v4.is_loopback()
+                    || v4.is_link_local()
+                    || v4.is_unspecified()
+                    || v4.is_broadcast())
+            }
+            IpAddr::V6(v6) => {
+                !(v6.is_loopback()
+                    || v6.is_unicast_link_local()
+                    || v6.is_unspecified()
+                    || v6.is_multicast())
+            }
+        })
+        .collect();
+    let wanted = routable.iter().find(|ip| ip.is_ipv6() == ipv6);
+    let fallback = if ipv6 { None } else { routable.first() };
+    wanted.or(fallback).map(IpAddr::to_string)
+}
+
+/// `(address, mac)` pairs from `ip neigh show` output.
+///
+/// Entries without a link-layer address (`FAILED`, `INCOMPLETE`) are skipped.
+pub fn parse_neighbors(output: &str) -> Vec<(String, String)> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let addr = fields.next()?;
+            fields.find(|f| *f == "lladdr")?;
+            Some((addr.to_string(), fields.next()?.to_lowercase()))
+        })
+        .collect()
+}
+
+/// Replace `path` with `contents` so readers see either the old file or the
+/// new one, never a partial write: the contents go to a temporary file in
+/// the same directory, which is then renamed over `path`.
+pub fn write_atomic(path: &Path, contents: &[u8]) -> Result<(), Error> {
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    let tmp = path.with_file_name(format!(".{file_name}.tmp"));
+    std::fs::write(&tmp, contents)
+        .and_then(|()| std::fs::rename(&tmp, path))
+        .map_err(|e| {
+            let _ = std::fs::remove_file(&tmp);
+            Error::Io {
+                context: format!("writing {}", path.display()),
+                source: e,
+            }
+        })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -75,4 +149,47 @@ mod tests {
     fn parse_size_rejects_bad_suffix() {
         assert!(parse_size("10X").is_err());
     }
+
+    #[test]
+    fn pick_routable_prefers_ipv4_and_skips_link_local() {
+        let addrs = ["fe80::1", "127.0.0.1", "2001:db8::5", "192.168.122.10"];
+        assert_eq!(
+            pick_routable(addrs, false).as_deref(),
+            Some("192.168.122.10")
+        );
+        assert_eq!(pick_routable(addrs, true).as_deref(), Some("2001:db8::5"));
+        assert_eq!(
+            pick_routable(["fe80::1", "2001:db8::5"], false).as_deref(),
+            Some("2001:db8::5")
+        );
+        assert_eq!(pick_routable(["192.168.122.10"], true), None);
+        assert_eq!(pick_routable(["169.254.0.3", "not an ip"], false), None);
+    }
+
+    #[test]
+    fn parse_neighbors_keeps_entries_with_lladdr() {
+        let output = "\
+192.168.122.45 dev virbr0 lladdr 52:54:00:AB:CD:EF REACHABLE
+192.168.122.46 dev virbr0 FAILED
+fe80::5054:ff:feab:cdef dev virbr0 lladdr 52:54:00:ab:cd:ef router STALE
+";
+        let neighbors = parse_neighbors(output);
+        assert_eq!(neighbors.len(), 2);
+        assert_eq!(
+            neighbors[0],
+            ("192.168.122.45".into(), "52:54:00:ab:cd:ef".into())
+        );
+        assert_eq!(neighbors[1].0, "fe80::5054:ff:feab:cdef");
+    }
+
+    #[test]
+    fn write_atomic_replaces_without_leaving_temp_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.json");
+        write_atomic(&path, b"old").unwrap();
+        write_atomic(&path, b"new").unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"new");
+        let entries: Vec<_> = std::fs::read_dir(dir.path()).unwrap().collect();
+        assert_eq!(entries.len(), 1);
+    }
 }
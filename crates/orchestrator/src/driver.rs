@@ -1,8 +1,8 @@
 use async_trait::async_trait;
-use guest::agent::ProvisionScript;
-use machine::driver::{Driver, LibvirtDriver, RecoverableDriver};
+use guest::agent::{BootScriptResult, MountCheck, ProvisionScript};
+use machine::config::TimeoutsConfig;
+use machine::driver::{DomainEvents, Driver, LibvirtDriver, RecoverableDriver};
 use machine::error::Error;
-use machine::guest::VsockConnector;
 use std::sync::Arc;
 
 pub type OutputCallback = Arc<dyn Fn(String) + Send + Sync>;
@@ -21,6 +21,46 @@ pub trait OrchestrationDriver:
     /// Wait for the guest connection surface to become available.
     async fn connect_guest(&self) -> Result<(), Error>;
 
+    /// Timeouts and retries for the lifecycle steps.
+    fn timeouts(&self) -> TimeoutsConfig {
+        TimeoutsConfig::default()
+    }
+
+    /// Wait until the guest got its IP address after boot.
+    async fn wait_for_ip(&self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Wait for cloud-init to finish its first-boot run in the guest.
+    async fn wait_cloud_init(&self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// The boot scripts the guest ran on its own before the agent answered.
+    async fn boot_report(&self) -> Result<Vec<BootScriptResult>, Error> {
+        Ok(Vec::new())
+    }
+
+    /// Confirm the configured guest mounts are active after boot.
+    async fn verify_mounts(&self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Wait until the configured readiness probes pass.
+    async fn wait_ready(&self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Check that the guest agent still answers, without retrying.
+    async fn heartbeat(&self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Lifecycle events of the machine the orchestrator believes is running.
+    fn watch_domain(&self) -> Result<DomainEvents, Error> {
+        Ok(DomainEvents::silent())
+    }
+
     /// Run the current provisioning plan.
     async fn provision(&self, scripts: Vec<ProvisionScript>) -> Result<(), Error>;
 
@@ -39,27 +79,105 @@ pub trait OrchestrationDriver:
 #[async_trait]
 impl OrchestrationDriver for LibvirtDriver {
     async fn connect_guest(&self) -> Result<(), Error> {
-        let cid = self.get_vsock_cid()?;
-        guest::client::wait_for_agent(VsockConnector::new(cid))
+        let timeout_s = self.system().config.timeouts.agent_connect_s;
+        let timeout = (timeout_s > 0).then(|| std::time::Duration::from_secs(timeout_s));
+        let endpoints = self.agent_endpoints()?;
+        match machine::guest::negotiate(&endpoints, timeout).await {
+            Ok(connector) => self.record_agent_transport(&connector),
+            Err(guest::client::ClientError::AgentTimeout { message, .. }) => {
+                tracing::debug!(%message, "last agent ping failed");
+                Err(Error::AgentUnreachable {
+                    name: self.name().to_string(),
+                    timeout_s,
+                })
+            }
+            Err(error) => Err(map_guest_error(error)),
+        }
+    }
+
+    fn timeouts(&self) -> TimeoutsConfig {
+        self.system().config.timeouts.clone()
+    }
+
+    async fn wait_for_ip(&self) -> Result<(), Error> {
+        LibvirtDriver::wait_for_ip(self).await
+    }
+
+    async fn wait_cloud_init(&self) -> Result<(), Error> {
+        let client = machine::guest::wait_for_agent(self.agent_connector()?)
             .await
-            .map(|_| ())
-            .map_err(map_guest_error)
+            .map_err(map_guest_error)?;
+        client.wait_for_cloud_init().await.map_err(map_guest_error)
+    }
+
+    async fn boot_report(&self) -> Result<Vec<BootScriptResult>, Error> {
+        let client = machine::guest::wait_for_agent(self.agent_connector()?)
+            .await
+            .map_err(map_guest_error)?;
+        client.boot_report().await.map_err(map_guest_error)
+    }
+
+    async fn verify_mounts(&self) -> Result<(), Error> {
+        let mounts: Vec<MountCheck> = self
+            .system()
+            .resolve_mounts()?
+            .into_iter()
+            // Only fstab-backed shares can be recovered with `mount -a`.
+            .filter(|m| m.driver.fs_options().is_some())
+            .map(|m| MountCheck {
+                tag: m.tag,
+                target: m.target,
+            })
+            .collect();
+        if mounts.is_empty() {
+            return Ok(());
+        }
+
+        let client = machine::guest::wait_for_agent(self.agent_connector()?)
+            .await
+            .map_err(map_guest_error)?;
+
+        client.verify_mounts(mounts).await.map_err(map_guest_error)
+    }
+
+    async fn wait_ready(&self) -> Result<(), Error> {
+        LibvirtDriver::wait_ready(self).await
+    }
+
+    async fn heartbeat(&self) -> Result<(), Error> {
+        let connector = self.agent_connector()?;
+        let client = machine::guest::agent_client(connector.clone());
+        client.heartbeat().await.map(|_| ()).map_err(|error| {
+            // Reconnect on the next beat, e.g. after the agent restarted.
+            machine::guest::forget_agent_client(&connector);
+            map_guest_error(error)
+        })
+    }
+
+    fn watch_domain(&self) -> Result<DomainEvents, Error> {
+        LibvirtDriver::watch_domain(self)
     }
 
     async fn provision(&self, scripts: Vec<ProvisionScript>) -> Result<(), Error> {
         if scripts.is_empty() {
-            return Ok(());
+            return self.freeze_after_provision(&[]);
         }
 
-        let cid = self.get_vsock_cid()?;
-        let client = guest::client::wait_for_agent(VsockConnector::new(cid))
+        let ran = scripts.clone();
+        let client = machine::guest::wait_for_agent(self.agent_connector()?)
             .await
             .map_err(map_guest_error)?;
 
         client
-            .provision(scripts, &self.layout().logs_dir)
+            .provision(
+                scripts,
+                &self.layout().logs_dir,
+                self.system().config.logging.max_files,
+            )
             .await
-            .map_err(map_guest_error)
+            .map_err(map_guest_error)?;
+        self.record_provisioned_scripts(&ran)?;
+        self.freeze_after_provision(&ran)
     }
 
     async fn provision_with_output(
@@ -68,20 +186,26 @@ impl OrchestrationDriver for LibvirtDriver {
         on_output: OutputCallback,
     ) -> Result<(), Error> {
         if scripts.is_empty() {
-            return Ok(());
+            return self.freeze_after_provision(&[]);
         }
 
-        let cid = self.get_vsock_cid()?;
-        let client = guest::client::wait_for_agent(VsockConnector::new(cid))
+        let ran = scripts.clone();
+        let client = machine::guest::wait_for_agent(self.agent_connector()?)
             .await
             .map_err(map_guest_error)?;
 
         client
-            .provision_with_output(scripts, &self.layout().logs_dir, move |line| {
-                on_output(line);
-            })
+            .provision_with_output(
+                scripts,
+                &self.layout().logs_dir,
+                self.system().config.logging.max_files,
+                None,
+                move |line| on_output(line),
+            )
             .await
-            .map_err(map_guest_error)
+            .map_err(map_guest_error)?;
+        self.record_provisioned_scripts(&ran)?;
+        self.freeze_after_provision(&ran)
     }
 }
 
@@ -89,10 +213,23 @@ fn map_guest_error(error: guest::client::ClientError) -> Error {
     match error {
         guest::client::ClientError::Io { context, source } => Error::Io { context, source },
         guest::client::ClientError::AgentTimeout { message, .. } => Error::AgentTimeout { message },
+        guest::client::ClientError::IncompatibleAgent { message } => {
+            Error::AgentIncompatible { message }
+        }
         guest::client::ClientError::Rpc { context, message } => Error::Daemon {
             message: format!("{context}: {message}"),
         },
         guest::client::ClientError::CopyFailed { message } => Error::CopyFailed { message },
         guest::client::ClientError::ProvisionFailed { script } => Error::ProvisionFailed { script },
+        guest::client::ClientError::MountMissing { tag, target } => {
+            Error::MountNotActive { tag, target }
+        }
+        guest::client::ClientError::CloudInitFailed {
+            reason,
+            log_excerpt,
+        } => Error::CloudInitFailed {
+            reason,
+            log_excerpt,
+        },
     }
 }
@@ -1,9 +1,11 @@
 use std::path::PathBuf;
+use std::sync::Arc;
 
 use bevy::prelude::Deref;
 use ecsdk::prelude::*;
 use guest::agent::ProvisionScript;
 use serde::{Deserialize, Serialize};
+use tokio::sync::watch;
 
 use crate::driver::OrchestrationDriver;
 
@@ -28,8 +30,80 @@ pub struct ResolvedBaseImage(pub PathBuf);
 pub struct ProvisionPlan(pub Vec<ProvisionScript>);
 
 /// Recorded orchestration error for an entity.
-#[derive(Component, Clone, Debug, Deref, Serialize, Deserialize)]
-pub struct EntityError(pub String);
+#[derive(Component, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EntityError {
+    pub message: String,
+    /// Stable error code, see [`machine::error::Error::code`].
+    pub code: Option<String>,
+    pub hint: Option<String>,
+}
+
+impl EntityError {
+    /// An error that did not come from a [`machine::error::Error`].
+    pub fn message(message: impl Into<String>) -> Self {
+        Self {
+            message: message.into(),
+            code: None,
+            hint: None,
+        }
+    }
+}
+
+impl From<&machine::error::Error> for EntityError {
+    fn from(error: &machine::error::Error) -> Self {
+        Self {
+            message: error.to_string(),
+            code: Some(error.code()),
+            hint: error.hint(),
+        }
+    }
+}
+
+impl std::fmt::Display for EntityError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.code {
+            Some(code) => write!(f, "[{code}] {}", self.message),
+            None => f.write_str(&self.message),
+        }
+    }
+}
+
+/// Cancels the lifecycle work in flight for an entity, so a forced stop does
+/// not wait for a boot, guest connection or provisioning run to finish.
+#[derive(Component, Clone, Debug)]
+pub struct CancelToken(Arc<watch::Sender<bool>>);
+
+impl Default for CancelToken {
+    fn default() -> Self {
+        Self(Arc::new(watch::channel(false).0))
+    }
+}
+
+impl CancelToken {
+    pub fn cancel(&self) {
+        self.0.send_replace(true);
+    }
+
+    /// Make the token usable again, e.g. when a stopped instance reboots.
+    pub fn reset(&self) {
+        self.0.send_replace(false);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        *self.0.borrow()
+    }
+
+    /// Run `work` until it finishes or the token is cancelled, whichever
+    /// comes first. Cancelling drops `work`, which also closes any guest
+    /// connection it holds.
+    pub async fn run<F: Future>(&self, work: F) -> Option<F::Output> {
+        let mut cancelled = self.0.subscribe();
+        tokio::select! {
+            output = work => Some(output),
+            _ = cancelled.wait_for(|cancelled| *cancelled) => None,
+        }
+    }
+}
 
 /// Non-replicated buffer of line-oriented runtime output collected on the
 /// server before it is drained into replicated log entries.
@@ -92,6 +166,18 @@ pub struct ProvisionFinished;
 #[derive(Component, Clone, Copy, Debug, Default)]
 pub struct ShutdownFinished;
 
+/// Marker inserted while the guest agent misses heartbeats.
+#[derive(Component, Clone, Copy, Debug, Default)]
+pub struct GuestUnresponsive;
+
+/// Marker inserted once the domain crashed under a running instance.
+#[derive(Component, Clone, Copy, Debug, Default)]
+pub struct GuestCrashed;
+
+/// Stops the health checks of a running instance once it shuts down.
+#[derive(Component, Clone, Debug, Default)]
+pub struct HealthMonitor(pub CancelToken);
+
 /// Per-entity lifecycle phase driven by the orchestrator state machine.
 #[derive(Component, StateComponent, PartialEq, Eq, Clone, Copy, Debug, Serialize, Deserialize)]
 pub enum InstancePhase {
@@ -101,6 +187,10 @@ pub enum InstancePhase {
     ConnectingGuest,
     Provisioning,
     Running,
+    /// Running, but the guest agent stopped answering heartbeats.
+    Unhealthy,
+    /// The domain crashed or the guest kernel panicked while running.
+    Crashed,
     ShuttingDown,
     Stopped,
     Failed,
@@ -116,9 +206,19 @@ impl InstancePhase {
             Self::ConnectingGuest => "Connecting guest",
             Self::Provisioning => "Provisioning",
             Self::Running => "Running",
+            Self::Unhealthy => "Unhealthy",
+            Self::Crashed => "Crashed",
             Self::ShuttingDown => "Shutting down",
             Self::Stopped => "Stopped",
             Self::Failed => "Failed",
         }
     }
+
+    /// Whether the phase runs lifecycle work that a forced stop abandons.
+    pub fn is_in_flight(&self) -> bool {
+        matches!(
+            self,
+            Self::Preparing | Self::Booting | Self::ConnectingGuest | Self::Provisioning
+        )
+    }
 }
@@ -1,13 +1,19 @@
 pub mod driver;
 pub mod instance;
 pub mod lifecycle;
+#[cfg(any(test, feature = "test-util"))]
+pub mod mock;
 pub mod setup;
 
 pub use driver::OrchestrationDriver;
 pub use instance::{
-    BootFinished, EntityError, GuestConnected, InstanceLabel, InstancePhase, LogBuffer,
-    ManagedInstance, PrepareFinished, ProvisionFinished, ProvisionLogEntry, ProvisionLogView,
-    ProvisionPlan, RecoveredState, ResolvedBaseImage, ShutdownFinished,
+    BootFinished, CancelToken, EntityError, GuestConnected, GuestCrashed, GuestUnresponsive,
+    HealthMonitor, InstanceLabel, InstancePhase, LogBuffer, ManagedInstance, PrepareFinished,
+    ProvisionFinished, ProvisionLogEntry, ProvisionLogView, ProvisionPlan, RecoveredState,
+    ResolvedBaseImage, ShutdownFinished,
+};
+pub use lifecycle::{
+    OrchestratorMessage, OrchestratorPlugin, RebootRequested, ShutdownRequested, build_instance_sm,
+    planned_phases,
 };
-pub use lifecycle::{OrchestratorMessage, OrchestratorPlugin, ShutdownRequested, build_instance_sm};
 pub use setup::{ManagedInstanceSpec, spawn_managed_instance};
@@ -1,13 +1,22 @@
+use std::path::Path;
+use std::time::Duration;
+
 use ecsdk::prelude::*;
 use ecsdk::tasks::SpawnTask;
+use machine::driver::{DomainEvents, DomainHealth};
+use machine::retry::{RetryPolicy, retry, timed};
 use seldom_state::prelude::*;
 
-use crate::driver::OrchestrationDriver;
+use crate::driver::{OrchestrationDriver, OutputCallback};
 use crate::instance::{
-    BootFinished, EntityError, GuestConnected, InstanceLabel, LogBuffer, ManagedInstance,
-    PrepareFinished, ProvisionFinished, ProvisionLogEntry, ProvisionLogView, ProvisionPlan,
-    RecoveredState, ResolvedBaseImage, ShutdownFinished,
-    instance_phase::{Booting, ConnectingGuest, Failed, Preparing, Provisioning, Recovering, Running, ShuttingDown, Stopped},
+    BootFinished, CancelToken, EntityError, GuestConnected, GuestCrashed, GuestUnresponsive,
+    HealthMonitor, InstanceLabel, InstancePhase, LogBuffer, ManagedInstance, PrepareFinished,
+    ProvisionFinished, ProvisionLogEntry, ProvisionLogView, ProvisionPlan, RecoveredState,
+    ResolvedBaseImage, ShutdownFinished,
+    instance_phase::{
+        Booting, ConnectingGuest, Crashed, Failed, Preparing, Provisioning, Recovering, Running,
+        ShuttingDown, Stopped, Unhealthy,
+    },
 };
 
 const LOG_ENTRY_CAP: usize = 200;
@@ -16,6 +25,10 @@ const LOG_ENTRY_CAP: usize = 200;
 #[derive(Resource, Default)]
 pub struct ShutdownRequested(pub bool);
 
+/// Resource toggled when the instance should boot again once it has stopped.
+#[derive(Resource, Default)]
+pub struct RebootRequested(pub bool);
+
 /// Domain messages emitted by orchestrator tasks and applied back into ECS.
 #[derive(Clone, Debug)]
 pub enum OrchestratorMessage {
@@ -24,8 +37,21 @@ pub enum OrchestratorMessage {
     GuestConnected { entity: Entity },
     ProvisionFinished { entity: Entity },
     ShutdownFinished { entity: Entity },
-    OperationFailed { entity: Entity, message: String },
+    OperationFailed { entity: Entity, error: EntityError },
     RequestShutdown,
+    /// Shut down without waiting for the work in flight to finish.
+    ForceStop,
+    /// Shut down, then boot the same instance again without re-preparing it.
+    RequestReboot,
+    /// The guest agent stopped answering heartbeats.
+    HeartbeatMissed { entity: Entity },
+    /// The guest agent answers heartbeats again.
+    HeartbeatRestored { entity: Entity },
+    /// The domain crashed under the running instance.
+    DomainCrashed { entity: Entity, error: EntityError },
+    /// The domain powered off without being asked to, e.g. `poweroff` in the
+    /// guest.
+    DomainStopped { entity: Entity },
 }
 
 impl ApplyMessage for OrchestratorMessage {
@@ -56,51 +82,83 @@ impl ApplyMessage for OrchestratorMessage {
                     entity.insert(ShutdownFinished);
                 }
             }
-            Self::OperationFailed { entity, message } => {
+            Self::OperationFailed { entity, error } => {
                 if let Ok(mut entity) = world.get_entity_mut(*entity) {
-                    entity.insert(EntityError(message.clone()));
+                    entity.insert(error.clone());
                 }
             }
             Self::RequestShutdown => {
                 world.resource_mut::<ShutdownRequested>().0 = true;
             }
+            Self::ForceStop => {
+                world.resource_mut::<ShutdownRequested>().0 = true;
+                let mut tokens = world.query::<&CancelToken>();
+                for token in tokens.iter(world) {
+                    token.cancel();
+                }
+            }
+            Self::RequestReboot => {
+                world.resource_mut::<RebootRequested>().0 = true;
+                world.resource_mut::<ShutdownRequested>().0 = true;
+            }
+            Self::HeartbeatMissed { entity } => {
+                if let Ok(mut entity) = world.get_entity_mut(*entity) {
+                    entity.insert(GuestUnresponsive);
+                }
+            }
+            Self::HeartbeatRestored { entity } => {
+                if let Ok(mut entity) = world.get_entity_mut(*entity) {
+                    entity.remove::<GuestUnresponsive>();
+                }
+            }
+            Self::DomainCrashed { entity, error } => {
+                if let Ok(mut entity) = world.get_entity_mut(*entity) {
+                    entity.insert((GuestCrashed, error.clone()));
+                }
+            }
+            Self::DomainStopped { .. } => {
+                // Nothing is left to shut down; this only walks the lifecycle
+                // to Stopped so the daemon exits like after `rum down`.
+                world.resource_mut::<ShutdownRequested>().0 = true;
+            }
         }
     }
 }
 
-fn needs_prepare<D: OrchestrationDriver>(
-    In(entity): In<Entity>,
-    recovered: Query<&RecoveredState, With<ManagedInstance<D>>>,
-) -> bool {
-    matches!(
-        recovered.get(entity),
-        Ok(RecoveredState(
-            machine::instance::InstanceState::Missing
-                | machine::instance::InstanceState::ImageCached
-                | machine::instance::InstanceState::Prepared
-                | machine::instance::InstanceState::PartialBoot
-        ))
-    )
-}
-
-fn needs_boot<D: OrchestrationDriver>(
-    In(entity): In<Entity>,
-    recovered: Query<&RecoveredState, With<ManagedInstance<D>>>,
-) -> bool {
-    matches!(
-        recovered.get(entity),
-        Ok(RecoveredState(machine::instance::InstanceState::Stopped))
-    )
+/// Phases of a successful `rum up`, in order. Each one moves on to the next
+/// once its work finishes; see [`build_instance_sm`].
+const UP_PHASES: [InstancePhase; 5] = [
+    InstancePhase::Preparing,
+    InstancePhase::Booting,
+    InstancePhase::ConnectingGuest,
+    InstancePhase::Provisioning,
+    InstancePhase::Running,
+];
+
+/// Phase the lifecycle leaves `Recovering` for, given the recovered `state`.
+fn recovery_phase(state: machine::instance::InstanceState) -> InstancePhase {
+    use machine::instance::InstanceState;
+
+    match state {
+        InstanceState::Missing
+        | InstanceState::ImageCached
+        | InstanceState::Prepared
+        | InstanceState::PartialBoot => InstancePhase::Preparing,
+        InstanceState::Stopped => InstancePhase::Booting,
+        InstanceState::Running => InstancePhase::ConnectingGuest,
+        InstanceState::StaleConfig => InstancePhase::Failed,
+    }
 }
 
-fn needs_guest_connect<D: OrchestrationDriver>(
-    In(entity): In<Entity>,
-    recovered: Query<&RecoveredState, With<ManagedInstance<D>>>,
-) -> bool {
-    matches!(
-        recovered.get(entity),
-        Ok(RecoveredState(machine::instance::InstanceState::Running))
-    )
+/// Trigger for leaving `Recovering` for `phase`.
+fn recovers_to<D: OrchestrationDriver>(
+    phase: InstancePhase,
+) -> impl FnMut(In<Entity>, Query<&RecoveredState, With<ManagedInstance<D>>>) -> bool {
+    move |In(entity), recovered| {
+        recovered
+            .get(entity)
+            .is_ok_and(|state| recovery_phase(state.0) == phase)
+    }
 }
 
 fn failed_recovery<D: OrchestrationDriver>(
@@ -109,10 +167,9 @@ fn failed_recovery<D: OrchestrationDriver>(
     errors: Query<(), With<EntityError>>,
 ) -> bool {
     errors.get(entity).is_ok()
-        || matches!(
-            recovered.get(entity),
-            Ok(RecoveredState(machine::instance::InstanceState::StaleConfig))
-        )
+        || recovered
+            .get(entity)
+            .is_ok_and(|state| recovery_phase(state.0) == InstancePhase::Failed)
 }
 
 fn has_prepare_finished(In(entity): In<Entity>, finished: Query<(), With<PrepareFinished>>) -> bool {
@@ -145,17 +202,47 @@ fn has_error(In(entity): In<Entity>, errors: Query<(), With<EntityError>>) -> bo
     errors.get(entity).is_ok()
 }
 
+fn is_unresponsive(In(entity): In<Entity>, markers: Query<(), With<GuestUnresponsive>>) -> bool {
+    markers.get(entity).is_ok()
+}
+
+fn is_responsive(In(entity): In<Entity>, markers: Query<(), With<GuestUnresponsive>>) -> bool {
+    markers.get(entity).is_err()
+}
+
+fn has_crashed(In(entity): In<Entity>, markers: Query<(), With<GuestCrashed>>) -> bool {
+    markers.get(entity).is_ok()
+}
+
+fn is_cancelled(In(entity): In<Entity>, tokens: Query<&CancelToken>) -> bool {
+    tokens.get(entity).is_ok_and(CancelToken::is_cancelled)
+}
+
 fn shutdown_requested(In(_entity): In<Entity>, shutdown: Res<ShutdownRequested>) -> bool {
     shutdown.0
 }
 
+fn reboot_requested(In(_entity): In<Entity>, reboot: Res<RebootRequested>) -> bool {
+    reboot.0
+}
+
 /// Build the per-instance lifecycle state machine.
 pub fn build_instance_sm<D: OrchestrationDriver>() -> StateMachine {
     StateMachine::default()
-        .trans::<Recovering, _>(needs_prepare::<D>, Preparing)
-        .trans::<Recovering, _>(needs_boot::<D>, Booting)
-        .trans::<Recovering, _>(needs_guest_connect::<D>, ConnectingGuest)
+        .trans::<Recovering, _>(recovers_to::<D>(InstancePhase::Preparing), Preparing)
+        .trans::<Recovering, _>(recovers_to::<D>(InstancePhase::Booting), Booting)
+        .trans::<Recovering, _>(
+            recovers_to::<D>(InstancePhase::ConnectingGuest),
+            ConnectingGuest,
+        )
         .trans::<Recovering, _>(failed_recovery::<D>, Failed)
+        // A forced stop abandons whatever is in flight; nothing runs yet
+        // while preparing, so there is nothing to shut down either.
+        .trans::<Preparing, _>(is_cancelled, Stopped)
+        .trans::<Booting, _>(is_cancelled, ShuttingDown)
+        .trans::<ConnectingGuest, _>(is_cancelled, ShuttingDown)
+        .trans::<Provisioning, _>(is_cancelled, ShuttingDown)
+        // The `UP_PHASES` chain.
         .trans::<Preparing, _>(has_prepare_finished, Booting)
         .trans::<Preparing, _>(has_error, Failed)
         .trans::<Booting, _>(has_boot_finished, ConnectingGuest)
@@ -165,11 +252,28 @@ pub fn build_instance_sm<D: OrchestrationDriver>() -> StateMachine {
         .trans::<Provisioning, _>(has_provision_finished, Running)
         .trans::<Provisioning, _>(has_error, Failed)
         .trans::<Running, _>(shutdown_requested, ShuttingDown)
+        .trans::<Running, _>(has_crashed, Crashed)
+        .trans::<Running, _>(is_unresponsive, Unhealthy)
+        .trans::<Unhealthy, _>(shutdown_requested, ShuttingDown)
+        .trans::<Unhealthy, _>(has_crashed, Crashed)
+        .trans::<Unhealthy, _>(is_responsive, Running)
+        .trans::<Crashed, _>(shutdown_requested, ShuttingDown)
         .trans::<ShuttingDown, _>(has_shutdown_finished, Stopped)
         .trans::<ShuttingDown, _>(has_error, Failed)
+        .trans::<Stopped, _>(reboot_requested, Booting)
         .set_trans_logging(true)
 }
 
+/// Phases `rum up` passes through from a recovered `state` when every step
+/// succeeds, following the transitions of [`build_instance_sm`].
+pub fn planned_phases(state: machine::instance::InstanceState) -> Vec<InstancePhase> {
+    let first = recovery_phase(state);
+    match UP_PHASES.iter().position(|phase| *phase == first) {
+        Some(start) => UP_PHASES[start..].to_vec(),
+        None => vec![first],
+    }
+}
+
 fn on_recovering<D: OrchestrationDriver>(
     trigger: On<Insert, Recovering>,
     mut commands: Commands,
@@ -185,7 +289,7 @@ fn on_recovering<D: OrchestrationDriver>(
             commands.entity(entity).insert(RecoveredState(state));
         }
         Err(error) => {
-            commands.entity(entity).insert(EntityError(error.to_string()));
+            commands.entity(entity).insert(EntityError::from(&error));
         }
     }
 }
@@ -193,86 +297,221 @@ fn on_recovering<D: OrchestrationDriver>(
 fn on_preparing<D: OrchestrationDriver>(
     trigger: On<Insert, Preparing>,
     mut commands: Commands,
-    instances: Query<&ManagedInstance<D>>,
+    instances: Query<(&ManagedInstance<D>, &CancelToken)>,
     images: Query<&ResolvedBaseImage>,
 ) {
     let entity = trigger.event_target();
-    let Ok(instance) = instances.get(entity) else {
+    let Ok((instance, cancel)) = instances.get(entity) else {
         return;
     };
     let Ok(image) = images.get(entity) else {
         commands.send_msg(OrchestratorMessage::OperationFailed {
             entity,
-            message: "missing resolved base image".into(),
+            error: EntityError::message("missing resolved base image"),
         });
         return;
     };
 
     let driver = instance.0.driver();
     let image_path = image.0.clone();
+    let cancel = cancel.clone();
     commands.entity(entity).spawn_task(move |task| async move {
-        match driver.prepare(&image_path).await {
-            Ok(()) => task.send_msg(OrchestratorMessage::PrepareFinished { entity }),
-            Err(error) => task.send_msg(OrchestratorMessage::OperationFailed {
-                entity,
-                message: error.to_string(),
-            }),
+        if let Some(message) = run_prepare(&driver, &image_path, &cancel, entity).await {
+            task.send_msg(message);
         }
     });
 }
 
+/// `finished` when a step succeeded, otherwise the failure it reports.
+fn step_outcome(
+    entity: Entity,
+    result: Result<(), machine::error::Error>,
+    finished: OrchestratorMessage,
+) -> OrchestratorMessage {
+    match result {
+        Ok(()) => finished,
+        Err(error) => OrchestratorMessage::OperationFailed {
+            entity,
+            error: EntityError::from(&error),
+        },
+    }
+}
+
+/// The work of [`Preparing`]; `None` when `cancel` abandoned it.
+pub(crate) async fn run_prepare<D: OrchestrationDriver>(
+    driver: &D,
+    image: &Path,
+    cancel: &CancelToken,
+    entity: Entity,
+) -> Option<OrchestratorMessage> {
+    let prepared = cancel.run(driver.prepare(image)).await?;
+    Some(step_outcome(
+        entity,
+        prepared,
+        OrchestratorMessage::PrepareFinished { entity },
+    ))
+}
+
 fn on_booting<D: OrchestrationDriver>(
     trigger: On<Insert, Booting>,
     mut commands: Commands,
-    instances: Query<&ManagedInstance<D>>,
+    instances: Query<(&ManagedInstance<D>, &CancelToken)>,
+    mut reboot: ResMut<RebootRequested>,
+    mut shutdown: ResMut<ShutdownRequested>,
 ) {
     let entity = trigger.event_target();
-    let Ok(instance) = instances.get(entity) else {
+    let Ok((instance, cancel)) = instances.get(entity) else {
         return;
     };
 
+    if reboot.0 {
+        // Rebooting re-enters the lifecycle after Stopped, so drop the markers
+        // left behind by the previous run before they short-circuit it.
+        reboot.0 = false;
+        shutdown.0 = false;
+        cancel.reset();
+        commands.entity(entity).remove::<(
+            BootFinished,
+            GuestConnected,
+            ProvisionFinished,
+            ShutdownFinished,
+            GuestUnresponsive,
+            GuestCrashed,
+            EntityError,
+        )>();
+    }
+
     let driver = instance.0.driver();
+    let cancel = cancel.clone();
     commands.entity(entity).spawn_task(move |task| async move {
-        match driver.boot().await {
-            Ok(_) => task.send_msg(OrchestratorMessage::BootFinished { entity }),
-            Err(error) => task.send_msg(OrchestratorMessage::OperationFailed {
-                entity,
-                message: error.to_string(),
-            }),
+        if let Some(message) = run_boot(&driver, &cancel, entity).await {
+            task.send_msg(message);
         }
     });
 }
 
+/// The work of [`Booting`]; `None` when `cancel` abandoned it.
+pub(crate) async fn run_boot<D: OrchestrationDriver>(
+    driver: &D,
+    cancel: &CancelToken,
+    entity: Entity,
+) -> Option<OrchestratorMessage> {
+    let boot_s = driver.timeouts().boot_s;
+    let booted = async {
+        timed(boot_s, "boot", driver.boot()).await?;
+        driver.wait_for_ip().await
+    };
+    let booted = cancel.run(booted).await?;
+    Some(step_outcome(
+        entity,
+        booted,
+        OrchestratorMessage::BootFinished { entity },
+    ))
+}
+
 fn on_connecting_guest<D: OrchestrationDriver>(
     trigger: On<Insert, ConnectingGuest>,
     mut commands: Commands,
-    instances: Query<&ManagedInstance<D>>,
+    instances: Query<(&ManagedInstance<D>, &CancelToken)>,
 ) {
     let entity = trigger.event_target();
-    let Ok(instance) = instances.get(entity) else {
+    let Ok((instance, cancel)) = instances.get(entity) else {
         return;
     };
 
     let driver = instance.0.driver();
+    let cancel = cancel.clone();
     commands.entity(entity).spawn_task(move |task| async move {
-        match driver.connect_guest().await {
-            Ok(()) => task.send_msg(OrchestratorMessage::GuestConnected { entity }),
-            Err(error) => task.send_msg(OrchestratorMessage::OperationFailed {
-                entity,
-                message: error.to_string(),
-            }),
+        let log_task = task.clone();
+        let log = move |lines: Vec<String>| {
+            log_task.queue_cmd_tick(move |world: &mut World| {
+                if let Some(mut buffer) = world.get_mut::<LogBuffer>(entity) {
+                    for line in lines {
+                        buffer.push(line);
+                    }
+                }
+            });
+        };
+        if let Some(message) = run_connect(&driver, &cancel, entity, log).await {
+            task.send_msg(message);
         }
     });
 }
 
+/// The work of [`ConnectingGuest`], with retries and failed boot scripts
+/// sent to `log`; `None` when `cancel` abandoned it.
+pub(crate) async fn run_connect<D: OrchestrationDriver>(
+    driver: &D,
+    cancel: &CancelToken,
+    entity: Entity,
+    log: impl Fn(Vec<String>) + Send + Sync,
+) -> Option<OrchestratorMessage> {
+    let timeouts = driver.timeouts();
+    let policy = RetryPolicy::new(timeouts.agent_connect_s, timeouts.retries);
+    // The driver gives up on the agent after `agent_connect_s` itself,
+    // with an error that says where to look.
+    let connect_policy = RetryPolicy::new(0, timeouts.retries);
+    let mut on_retry = |attempt: &machine::retry::Retry| log(vec![attempt.to_string()]);
+    // Mounts and packages come from cloud-init, so its failures are
+    // reported before they show up as a missing mount or provisioning
+    // racing ahead of them.
+    let connected = async {
+        retry(connect_policy, "agent connection", &mut on_retry, || {
+            driver.connect_guest()
+        })
+        .await?;
+        match driver.boot_report().await {
+            Ok(scripts) => {
+                for script in scripts.iter().filter(|script| !script.success()) {
+                    tracing::warn!(
+                        script = %script.name,
+                        exit_code = ?script.exit_code,
+                        "boot script failed"
+                    );
+                    log(boot_failure_lines(script));
+                }
+            }
+            Err(error) => tracing::debug!(%error, "failed to read the boot script report"),
+        }
+        timed(
+            timeouts.cloud_init_s,
+            "cloud-init",
+            driver.wait_cloud_init(),
+        )
+        .await?;
+        retry(policy, "mount check", &mut on_retry, || {
+            driver.verify_mounts()
+        })
+        .await
+    };
+    let connected = cancel.run(connected).await?;
+    Some(step_outcome(
+        entity,
+        connected,
+        OrchestratorMessage::GuestConnected { entity },
+    ))
+}
+
+/// What a failed boot script prints under the instance: a summary, then the
+/// end of its output.
+fn boot_failure_lines(script: &guest::agent::BootScriptResult) -> Vec<String> {
+    let summary = match script.exit_code {
+        Some(code) => format!("boot script '{}' failed with exit code {code}", script.name),
+        None => format!("boot script '{}' did not finish", script.name),
+    };
+    std::iter::once(summary)
+        .chain(script.output.iter().map(|line| format!("  {line}")))
+        .collect()
+}
+
 fn on_provisioning<D: OrchestrationDriver>(
     trigger: On<Insert, Provisioning>,
     mut commands: Commands,
-    instances: Query<&ManagedInstance<D>>,
+    instances: Query<(&ManagedInstance<D>, &CancelToken)>,
     plans: Query<Option<&ProvisionPlan>>,
 ) {
     let entity = trigger.event_target();
-    let Ok(instance) = instances.get(entity) else {
+    let Ok((instance, cancel)) = instances.get(entity) else {
         return;
     };
 
@@ -284,6 +523,7 @@ fn on_provisioning<D: OrchestrationDriver>(
         .unwrap_or_default();
 
     let driver = instance.0.driver();
+    let cancel = cancel.clone();
     commands.entity(entity).spawn_task(move |task| async move {
         let log_task = task.clone();
         let on_output = std::sync::Arc::new(move |line: String| {
@@ -294,16 +534,163 @@ fn on_provisioning<D: OrchestrationDriver>(
             });
         });
 
-        match driver.provision_with_output(scripts, on_output).await {
-            Ok(()) => task.send_msg(OrchestratorMessage::ProvisionFinished { entity }),
-            Err(error) => task.send_msg(OrchestratorMessage::OperationFailed {
-                entity,
-                message: error.to_string(),
-            }),
+        if let Some(message) = run_provision(&driver, scripts, &cancel, entity, on_output).await {
+            task.send_msg(message);
         }
     });
 }
 
+/// The work of [`Provisioning`], with the script output sent to
+/// `on_output`; `None` when `cancel` abandoned it.
+pub(crate) async fn run_provision<D: OrchestrationDriver>(
+    driver: &D,
+    scripts: Vec<guest::agent::ProvisionScript>,
+    cancel: &CancelToken,
+    entity: Entity,
+    on_output: OutputCallback,
+) -> Option<OrchestratorMessage> {
+    // Running (and a detached `rum up`) waits for the readiness probes too.
+    let script_s = driver.timeouts().script_s;
+    // Scripts may not be safe to run twice, so provisioning is never
+    // retried.
+    let provisioned = async {
+        timed(
+            script_s,
+            "provisioning",
+            driver.provision_with_output(scripts, on_output),
+        )
+        .await?;
+        driver.wait_ready().await
+    };
+    let provisioned = cancel.run(provisioned).await?;
+    Some(step_outcome(
+        entity,
+        provisioned,
+        OrchestratorMessage::ProvisionFinished { entity },
+    ))
+}
+
+/// Watch a running instance for a crashed or stopped domain and for an agent
+/// that no longer answers. The checks keep going while it is unhealthy and
+/// stop once it shuts down or crashes.
+fn on_running<D: OrchestrationDriver>(
+    trigger: On<Insert, Running>,
+    mut commands: Commands,
+    instances: Query<(&ManagedInstance<D>, Option<&HealthMonitor>)>,
+) {
+    let entity = trigger.event_target();
+    let Ok((instance, monitor)) = instances.get(entity) else {
+        return;
+    };
+    if monitor.is_some() {
+        // Back from Unhealthy: the checks that noticed it never stopped.
+        return;
+    }
+
+    let monitor = HealthMonitor::default();
+    let cancel = monitor.0.clone();
+    commands.entity(entity).insert(monitor);
+    let driver = instance.0.driver();
+    commands.entity(entity).spawn_task(move |task| async move {
+        let checks = run_health_checks(&driver, entity, |message| task.send_msg(message));
+        cancel.run(checks).await;
+    });
+}
+
+/// The checks [`Running`] starts, reporting a stopped or crashed domain and
+/// missed or restored heartbeats through `report`. Returns once the domain
+/// is gone.
+pub(crate) async fn run_health_checks<D: OrchestrationDriver>(
+    driver: &D,
+    entity: Entity,
+    report: impl Fn(OrchestratorMessage),
+) {
+    let timeouts = driver.timeouts();
+    let mut events = subscribe(driver).await;
+    // Also how often a lost domain event subscription is renewed.
+    let mut heartbeats =
+        tokio::time::interval(Duration::from_secs(timeouts.heartbeat_interval_s.max(1)));
+    let mut missed = 0;
+    loop {
+        let event = async {
+            match events.as_mut() {
+                Some(events) => events.next().await,
+                None => std::future::pending().await,
+            }
+        };
+        tokio::select! {
+            event = event => match event {
+                Some(DomainHealth::Running) => {}
+                Some(DomainHealth::Stopped) => {
+                    report(OrchestratorMessage::DomainStopped { entity });
+                    return;
+                }
+                Some(DomainHealth::Crashed { reason }) => {
+                    let error = machine::error::Error::GuestCrashed {
+                        name: driver.name().to_string(),
+                        reason,
+                    };
+                    report(OrchestratorMessage::DomainCrashed {
+                        entity,
+                        error: EntityError::from(&error),
+                    });
+                    return;
+                }
+                // libvirtd went away; subscribe again on the next tick.
+                None => events = None,
+            },
+            _ = heartbeats.tick() => {
+                if events.is_none() {
+                    events = subscribe(driver).await;
+                }
+                let answered = timed(timeouts.heartbeat_s, "heartbeat", driver.heartbeat())
+                    .await
+                    .is_ok();
+                if answered {
+                    if missed >= timeouts.missed_heartbeats {
+                        report(OrchestratorMessage::HeartbeatRestored { entity });
+                    }
+                    missed = 0;
+                } else {
+                    missed += 1;
+                    if missed == timeouts.missed_heartbeats {
+                        tracing::warn!(missed, "guest agent stopped answering heartbeats");
+                        report(OrchestratorMessage::HeartbeatMissed { entity });
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Subscribe off the runtime: connecting to libvirt blocks.
+async fn subscribe<D: OrchestrationDriver>(driver: &D) -> Option<DomainEvents> {
+    let driver = driver.clone();
+    match tokio::task::spawn_blocking(move || driver.watch_domain()).await {
+        Ok(Ok(events)) => Some(events),
+        Ok(Err(error)) => {
+            tracing::debug!(%error, "failed to subscribe to domain events");
+            None
+        }
+        Err(error) => {
+            tracing::debug!(%error, "domain event subscription task failed");
+            None
+        }
+    }
+}
+
+fn stop_health_monitor(
+    trigger: On<Insert, ShuttingDown>,
+    mut commands: Commands,
+    monitors: Query<&HealthMonitor>,
+) {
+    let entity = trigger.event_target();
+    if let Ok(monitor) = monitors.get(entity) {
+        monitor.0.cancel();
+        commands.entity(entity).remove::<HealthMonitor>();
+    }
+}
+
 fn on_shutting_down<D: OrchestrationDriver>(
     trigger: On<Insert, ShuttingDown>,
     mut commands: Commands,
@@ -316,16 +703,23 @@ fn on_shutting_down<D: OrchestrationDriver>(
 
     let driver = instance.0.driver();
     commands.entity(entity).spawn_task(move |task| async move {
-        match driver.shutdown().await {
-            Ok(()) => task.send_msg(OrchestratorMessage::ShutdownFinished { entity }),
-            Err(error) => task.send_msg(OrchestratorMessage::OperationFailed {
-                entity,
-                message: error.to_string(),
-            }),
-        }
+        task.send_msg(run_shutdown(&driver, entity).await);
     });
 }
 
+/// The work of [`ShuttingDown`].
+pub(crate) async fn run_shutdown<D: OrchestrationDriver>(
+    driver: &D,
+    entity: Entity,
+) -> OrchestratorMessage {
+    let stopped = driver.shutdown().await;
+    step_outcome(
+        entity,
+        stopped,
+        OrchestratorMessage::ShutdownFinished { entity },
+    )
+}
+
 /// Registers the orchestrator state machine and side-effect observers.
 pub struct OrchestratorPlugin<D: OrchestrationDriver>(std::marker::PhantomData<D>);
 
@@ -339,12 +733,15 @@ impl<D: OrchestrationDriver> IsomorphicPlugin for OrchestratorPlugin<D> {
     fn build_shared(&self, app: &mut App) {
         app.add_plugins(StateMachinePlugin::default().schedule(PreUpdate));
         app.init_resource::<ShutdownRequested>();
+        app.init_resource::<RebootRequested>();
 
         app.add_observer(on_recovering::<D>);
         app.add_observer(on_preparing::<D>);
         app.add_observer(on_booting::<D>);
         app.add_observer(on_connecting_guest::<D>);
         app.add_observer(on_provisioning::<D>);
+        app.add_observer(on_running::<D>);
+        app.add_observer(stop_health_monitor);
         app.add_observer(on_shutting_down::<D>);
     }
 
@@ -392,86 +789,16 @@ fn sync_log_entries(
 
 #[cfg(test)]
 mod tests {
-    use std::path::Path;
-    use std::sync::{Arc, Mutex};
-
-    use async_trait::async_trait;
     use ecsdk::core::{CmdQueue, MessageQueue};
-    use machine::error::Error;
-    use machine::driver::{Driver, RecoverableDriver};
 
     use super::*;
-    use crate::driver::OrchestrationDriver;
-    use crate::instance::{RecoveredState, instance_phase::{Preparing, Running, Stopped}};
+    use crate::instance::{
+        RecoveredState,
+        instance_phase::{Booting, Preparing, Provisioning, Running, ShuttingDown, Stopped},
+    };
+    use crate::mock::MockDriver;
     use crate::setup::{ManagedInstanceSpec, spawn_managed_instance};
 
-    #[derive(Clone)]
-    struct MockDriver {
-        state: machine::instance::InstanceState,
-        calls: Arc<Mutex<Vec<&'static str>>>,
-    }
-
-    impl MockDriver {
-        fn new(state: machine::instance::InstanceState) -> Self {
-            Self {
-                state,
-                calls: Arc::new(Mutex::new(Vec::new())),
-            }
-        }
-    }
-
-    #[async_trait]
-    impl Driver for MockDriver {
-        type Error = Error;
-
-        fn id(&self) -> &str {
-            "mock"
-        }
-
-        fn name(&self) -> &str {
-            "mock"
-        }
-
-        async fn prepare(&self, _base_image: &Path) -> Result<(), Self::Error> {
-            self.calls.lock().unwrap().push("prepare");
-            Ok(())
-        }
-
-        async fn boot(&self) -> Result<u32, Self::Error> {
-            self.calls.lock().unwrap().push("boot");
-            Ok(7)
-        }
-
-        async fn shutdown(&self) -> Result<(), Self::Error> {
-            self.calls.lock().unwrap().push("shutdown");
-            Ok(())
-        }
-
-        async fn destroy(&self) -> Result<(), Self::Error> {
-            self.calls.lock().unwrap().push("destroy");
-            Ok(())
-        }
-    }
-
-    impl RecoverableDriver for MockDriver {
-        fn recover(&self) -> Result<machine::instance::InstanceState, Self::Error> {
-            Ok(self.state)
-        }
-    }
-
-    #[async_trait]
-    impl OrchestrationDriver for MockDriver {
-        async fn connect_guest(&self) -> Result<(), Error> {
-            self.calls.lock().unwrap().push("connect_guest");
-            Ok(())
-        }
-
-        async fn provision(&self, _scripts: Vec<guest::agent::ProvisionScript>) -> Result<(), Error> {
-            self.calls.lock().unwrap().push("provision");
-            Ok(())
-        }
-    }
-
     fn test_app() -> App {
         let mut app = App::new();
         app.insert_resource(CmdQueue::test());
@@ -526,7 +853,7 @@ mod tests {
                 MockDriver::new(machine::instance::InstanceState::Missing),
                 machine::instance::BackendKind::Libvirt,
             ))
-            .with_resolved_base_image("/tmp/mock-image.qcow2")
+            .with_resolved_base_image("mock-image.qcow2")
             .with_provision_plan(Vec::new()),
         );
 
@@ -551,4 +878,228 @@ mod tests {
         OrchestratorMessage::ShutdownFinished { entity }.apply(app.world_mut());
         advance_until(&mut app, entity, |world, entity| world.get::<Stopped>(entity).is_some());
     }
+
+    #[test]
+    fn reboot_request_boots_stopped_instance_again() {
+        let mut app = test_app();
+        let driver = MockDriver::new(machine::instance::InstanceState::Running);
+        let entity = spawn_managed_instance(
+            app.world_mut(),
+            ManagedInstanceSpec::new(machine::instance::Instance::new_with_driver(
+                driver.clone(),
+                machine::instance::BackendKind::Libvirt,
+            ))
+            .with_provision_plan(Vec::new()),
+        );
+
+        app.update();
+        OrchestratorMessage::GuestConnected { entity }.apply(app.world_mut());
+        app.update();
+        OrchestratorMessage::ProvisionFinished { entity }.apply(app.world_mut());
+        advance_until(&mut app, entity, |world, entity| world.get::<Running>(entity).is_some());
+
+        OrchestratorMessage::RequestReboot.apply(app.world_mut());
+        app.update();
+        OrchestratorMessage::ShutdownFinished { entity }.apply(app.world_mut());
+        advance_until(&mut app, entity, |world, entity| {
+            world.get::<Booting>(entity).is_some()
+        });
+        assert!(!app.world().resource::<ShutdownRequested>().0);
+        assert!(app.world().get::<ProvisionFinished>(entity).is_none());
+
+        OrchestratorMessage::BootFinished { entity }.apply(app.world_mut());
+        app.update();
+        OrchestratorMessage::GuestConnected { entity }.apply(app.world_mut());
+        app.update();
+        OrchestratorMessage::ProvisionFinished { entity }.apply(app.world_mut());
+        advance_until(&mut app, entity, |world, entity| world.get::<Running>(entity).is_some());
+
+        assert!(driver.calls().contains(&"boot"));
+    }
+
+    #[test]
+    fn running_instance_turns_unhealthy_and_reboots_after_a_crash() {
+        let mut app = test_app();
+        let entity = spawn_managed_instance(
+            app.world_mut(),
+            ManagedInstanceSpec::new(machine::instance::Instance::new_with_driver(
+                MockDriver::new(machine::instance::InstanceState::Running),
+                machine::instance::BackendKind::Libvirt,
+            ))
+            .with_provision_plan(Vec::new()),
+        );
+
+        app.update();
+        OrchestratorMessage::GuestConnected { entity }.apply(app.world_mut());
+        app.update();
+        OrchestratorMessage::ProvisionFinished { entity }.apply(app.world_mut());
+        advance_until(&mut app, entity, |world, entity| world.get::<Running>(entity).is_some());
+        assert!(app.world().get::<HealthMonitor>(entity).is_some());
+
+        OrchestratorMessage::HeartbeatMissed { entity }.apply(app.world_mut());
+        advance_until(&mut app, entity, |world, entity| {
+            world.get::<Unhealthy>(entity).is_some()
+        });
+        OrchestratorMessage::HeartbeatRestored { entity }.apply(app.world_mut());
+        advance_until(&mut app, entity, |world, entity| world.get::<Running>(entity).is_some());
+
+        OrchestratorMessage::DomainCrashed {
+            entity,
+            error: EntityError::message("the guest kernel panicked"),
+        }
+        .apply(app.world_mut());
+        advance_until(&mut app, entity, |world, entity| {
+            world.get::<Crashed>(entity).is_some()
+        });
+
+        OrchestratorMessage::RequestReboot.apply(app.world_mut());
+        advance_until(&mut app, entity, |world, entity| {
+            world.get::<ShuttingDown>(entity).is_some()
+        });
+        assert!(app.world().get::<HealthMonitor>(entity).is_none());
+        OrchestratorMessage::ShutdownFinished { entity }.apply(app.world_mut());
+        advance_until(&mut app, entity, |world, entity| {
+            world.get::<Booting>(entity).is_some()
+        });
+        assert!(app.world().get::<GuestCrashed>(entity).is_none());
+        assert!(app.world().get::<EntityError>(entity).is_none());
+    }
+
+    #[test]
+    fn failed_boot_scripts_print_their_output_under_a_summary() {
+        let script = guest::agent::BootScriptResult {
+            name: "10-docker.boot.sh".into(),
+            exit_code: Some(3),
+            output: vec!["docker: not found".into()],
+        };
+        assert_eq!(
+            boot_failure_lines(&script),
+            [
+                "boot script '10-docker.boot.sh' failed with exit code 3",
+                "  docker: not found",
+            ]
+        );
+    }
+
+    #[test]
+    fn planned_phases_start_where_recovery_left_off() {
+        use machine::instance::InstanceState;
+
+        assert_eq!(
+            planned_phases(InstanceState::PartialBoot),
+            [
+                InstancePhase::Preparing,
+                InstancePhase::Booting,
+                InstancePhase::ConnectingGuest,
+                InstancePhase::Provisioning,
+                InstancePhase::Running,
+            ]
+        );
+        assert_eq!(
+            planned_phases(InstanceState::Running),
+            [
+                InstancePhase::ConnectingGuest,
+                InstancePhase::Provisioning,
+                InstancePhase::Running,
+            ]
+        );
+        assert_eq!(
+            planned_phases(InstanceState::StaleConfig),
+            [InstancePhase::Failed]
+        );
+    }
+
+    /// Drive the state machine from `state` with every step succeeding and
+    /// return the phases it entered.
+    fn walk_up(state: machine::instance::InstanceState) -> Vec<InstancePhase> {
+        let mut app = test_app();
+        let entity = spawn_managed_instance(
+            app.world_mut(),
+            ManagedInstanceSpec::new(machine::instance::Instance::new_with_driver(
+                MockDriver::new(state),
+                machine::instance::BackendKind::Libvirt,
+            ))
+            .with_resolved_base_image("mock-image.qcow2")
+            .with_provision_plan(Vec::new()),
+        );
+
+        let mut phases: Vec<InstancePhase> = Vec::new();
+        for _ in 0..32 {
+            app.update();
+            let Some(phase) = app.world().get::<InstancePhase>(entity).copied() else {
+                continue;
+            };
+            if phases.last() != Some(&phase) && phase != InstancePhase::Recovering {
+                phases.push(phase);
+            }
+            let finished = match phase {
+                InstancePhase::Preparing => OrchestratorMessage::PrepareFinished { entity },
+                InstancePhase::Booting => OrchestratorMessage::BootFinished { entity },
+                InstancePhase::ConnectingGuest => OrchestratorMessage::GuestConnected { entity },
+                InstancePhase::Provisioning => OrchestratorMessage::ProvisionFinished { entity },
+                InstancePhase::Recovering => continue,
+                _ => break,
+            };
+            finished.apply(app.world_mut());
+        }
+        phases
+    }
+
+    #[test]
+    fn planned_phases_match_the_state_machine() {
+        use machine::instance::InstanceState;
+
+        for state in [
+            InstanceState::Missing,
+            InstanceState::PartialBoot,
+            InstanceState::Stopped,
+            InstanceState::Running,
+            InstanceState::StaleConfig,
+        ] {
+            assert_eq!(walk_up(state), planned_phases(state), "{state:?}");
+        }
+    }
+
+    #[test]
+    fn force_stop_abandons_provisioning() {
+        let mut app = test_app();
+        let entity = spawn_managed_instance(
+            app.world_mut(),
+            ManagedInstanceSpec::new(machine::instance::Instance::new_with_driver(
+                MockDriver::new(machine::instance::InstanceState::Running),
+                machine::instance::BackendKind::Libvirt,
+            ))
+            .with_provision_plan(Vec::new()),
+        );
+
+        app.update();
+        OrchestratorMessage::GuestConnected { entity }.apply(app.world_mut());
+        advance_until(&mut app, entity, |world, entity| {
+            world.get::<Provisioning>(entity).is_some()
+        });
+
+        OrchestratorMessage::ForceStop.apply(app.world_mut());
+        let cancel = app.world().get::<CancelToken>(entity).unwrap();
+        assert!(cancel.is_cancelled());
+        advance_until(&mut app, entity, |world, entity| {
+            world.get::<ShuttingDown>(entity).is_some()
+        });
+        OrchestratorMessage::ShutdownFinished { entity }.apply(app.world_mut());
+        advance_until(&mut app, entity, |world, entity| world.get::<Stopped>(entity).is_some());
+    }
+
+    #[tokio::test]
+    async fn cancelled_work_returns_within_bounded_time() {
+        let cancel = CancelToken::default();
+        let work = tokio::spawn({
+            let cancel = cancel.clone();
+            async move { cancel.run(std::future::pending::<()>()).await }
+        });
+
+        cancel.cancel();
+        let finished = tokio::time::timeout(std::time::Duration::from_secs(1), work)
+            .await
+            .expect("cancelled work did not return in time");
+        assert_eq!(finished.unwrap(), None);
+    }
 }
@@ -0,0 +1,709 @@
+//! A driver that needs neither libvirt nor KVM, a fake guest agent behind
+//! it, and a harness that runs the orchestrator flows on them, for tests here
+//! and in crates building on the orchestrator (behind the `test-util`
+//! feature).
+//!
+//! The harness runs the work of each phase on a runtime of its own, through
+//! the same step functions the daemon's tasks run, and applies what they
+//! report between updates.
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use ecsdk::core::{CmdQueue, MessageQueue};
+use ecsdk::network::AppRole;
+use ecsdk::prelude::*;
+use guest::agent::{BootScriptResult, ProvisionScript};
+use machine::config::TimeoutsConfig;
+use machine::driver::{Driver, RecoverableDriver};
+use machine::error::Error;
+use machine::guest::AgentEndpoints;
+use machine::instance::{BackendKind, Instance, InstanceState};
+use tokio::sync::mpsc;
+
+use crate::driver::{OrchestrationDriver, OutputCallback};
+use crate::instance::{
+    CancelToken, HealthMonitor, InstancePhase, LogBuffer, ProvisionLogEntry, ProvisionPlan,
+    ResolvedBaseImage,
+};
+use crate::lifecycle::{self, OrchestratorMessage, OrchestratorPlugin};
+use crate::setup::{ManagedInstanceSpec, spawn_managed_instance};
+
+/// How long [`FlowHarness::run_until`] gives the instance to get to a phase.
+const RUN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long one round of [`FlowHarness::run_until`] waits for a step to
+/// report before it updates again.
+const POLL: Duration = Duration::from_millis(10);
+
+type ErrorFactory = Arc<dyn Fn() -> Error + Send + Sync>;
+
+/// Guest agent a [`MockDriver`] talks to instead of a VM. It answers every
+/// guest step, prints its provisioning output, and stops answering
+/// heartbeats when told to.
+///
+/// Clones share whether it answers and what it provisioned.
+#[derive(Clone)]
+pub struct FakeAgent {
+    boot_scripts: Vec<BootScriptResult>,
+    output: Vec<String>,
+    responsive: Arc<AtomicBool>,
+    provisioned: Arc<Mutex<Vec<String>>>,
+}
+
+impl Default for FakeAgent {
+    fn default() -> Self {
+        Self {
+            boot_scripts: Vec::new(),
+            output: Vec::new(),
+            responsive: Arc::new(AtomicBool::new(true)),
+            provisioned: Arc::default(),
+        }
+    }
+}
+
+impl FakeAgent {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Report `scripts` as the boot scripts the guest ran before the host
+    /// connected.
+    pub fn with_boot_scripts(mut self, scripts: Vec<BootScriptResult>) -> Self {
+        self.boot_scripts = scripts;
+        self
+    }
+
+    /// Print `lines` whenever it provisions.
+    pub fn with_output(mut self, lines: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.output = lines.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Answer heartbeats again, or stop answering them.
+    pub fn set_responsive(&self, responsive: bool) {
+        self.responsive.store(responsive, Ordering::SeqCst);
+    }
+
+    /// Names of the scripts provisioned so far, in order.
+    pub fn provisioned(&self) -> Vec<String> {
+        self.provisioned
+            .lock()
+            .expect("fake agent provisioned lock poisoned")
+            .clone()
+    }
+
+    fn heartbeat(&self) -> Result<(), Error> {
+        if self.responsive.load(Ordering::SeqCst) {
+            Ok(())
+        } else {
+            Err(Error::AgentTimeout {
+                message: "the fake agent stopped answering".into(),
+            })
+        }
+    }
+
+    fn provision(&self, scripts: &[ProvisionScript], on_output: impl Fn(String)) {
+        self.provisioned
+            .lock()
+            .expect("fake agent provisioned lock poisoned")
+            .extend(scripts.iter().map(|script| script.name.clone()));
+        for line in &self.output {
+            on_output(line.clone());
+        }
+    }
+}
+
+/// Driver that records the steps it is asked to run and succeeds at all of
+/// them, except the ones set up to fail with [`MockDriver::fail_at`]. The
+/// guest steps go to its [`FakeAgent`].
+///
+/// Clones share the recorded calls and failures.
+#[derive(Clone)]
+pub struct MockDriver {
+    state: InstanceState,
+    calls: Arc<Mutex<Vec<&'static str>>>,
+    failures: Arc<Mutex<HashMap<&'static str, ErrorFactory>>>,
+    agent: FakeAgent,
+    timeouts: TimeoutsConfig,
+}
+
+impl MockDriver {
+    /// A driver whose machine recovers in `state`. Its agent gets a
+    /// heartbeat every second and counts as unhealthy after one miss.
+    pub fn new(state: InstanceState) -> Self {
+        Self {
+            state,
+            calls: Arc::default(),
+            failures: Arc::default(),
+            agent: FakeAgent::default(),
+            timeouts: TimeoutsConfig {
+                heartbeat_interval_s: 1,
+                missed_heartbeats: 1,
+                ..TimeoutsConfig::default()
+            },
+        }
+    }
+
+    /// Talk to `agent` instead of a default [`FakeAgent`].
+    pub fn with_agent(mut self, agent: FakeAgent) -> Self {
+        self.agent = agent;
+        self
+    }
+
+    pub fn agent(&self) -> &FakeAgent {
+        &self.agent
+    }
+
+    /// Fail `step` (e.g. `"boot"`, named like the driver method) with the
+    /// error `error` builds.
+    pub fn fail_at(
+        self,
+        step: &'static str,
+        error: impl Fn() -> Error + Send + Sync + 'static,
+    ) -> Self {
+        self.failures
+            .lock()
+            .expect("mock failures lock poisoned")
+            .insert(step, Arc::new(error));
+        self
+    }
+
+    /// The steps run so far, in order.
+    pub fn calls(&self) -> Vec<&'static str> {
+        self.calls.lock().expect("mock calls lock poisoned").clone()
+    }
+
+    /// The error `step` is set up to fail with, if any.
+    fn failure(&self, step: &'static str) -> Option<Error> {
+        let failures = self.failures.lock().expect("mock failures lock poisoned");
+        failures.get(step).map(|error| error())
+    }
+
+    fn step(&self, step: &'static str) -> Result<(), Error> {
+        self.calls
+            .lock()
+            .expect("mock calls lock poisoned")
+            .push(step);
+        self.failure(step).map_or(Ok(()), Err)
+    }
+}
+
+#[async_trait]
+impl Driver for MockDriver {
+    type Error = Error;
+
+    fn id(&self) -> &str {
+        "mock"
+    }
+
+    fn name(&self) -> &str {
+        "mock"
+    }
+
+    async fn prepare(&self, _base_image: &Path) -> Result<(), Self::Error> {
+        self.step("prepare")
+    }
+
+    async fn boot(&self) -> Result<AgentEndpoints, Self::Error> {
+        self.step("boot").map(|()| AgentEndpoints {
+            vsock_cid: Some(7),
+            channel: None,
+        })
+    }
+
+    async fn shutdown(&self) -> Result<(), Self::Error> {
+        self.step("shutdown")
+    }
+
+    async fn destroy(&self) -> Result<(), Self::Error> {
+        self.step("destroy")
+    }
+}
+
+impl RecoverableDriver for MockDriver {
+    fn recover(&self) -> Result<InstanceState, Self::Error> {
+        Ok(self.state)
+    }
+}
+
+#[async_trait]
+impl OrchestrationDriver for MockDriver {
+    async fn connect_guest(&self) -> Result<(), Error> {
+        self.step("connect_guest")
+    }
+
+    fn timeouts(&self) -> TimeoutsConfig {
+        self.timeouts.clone()
+    }
+
+    async fn wait_for_ip(&self) -> Result<(), Error> {
+        self.step("wait_for_ip")
+    }
+
+    async fn wait_cloud_init(&self) -> Result<(), Error> {
+        self.step("wait_cloud_init")
+    }
+
+    async fn boot_report(&self) -> Result<Vec<BootScriptResult>, Error> {
+        self.step("boot_report")
+            .map(|()| self.agent.boot_scripts.clone())
+    }
+
+    async fn verify_mounts(&self) -> Result<(), Error> {
+        self.step("verify_mounts")
+    }
+
+    async fn wait_ready(&self) -> Result<(), Error> {
+        self.step("wait_ready")
+    }
+
+    // Not recorded: the checks send one every interval.
+    async fn heartbeat(&self) -> Result<(), Error> {
+        self.agent.heartbeat()
+    }
+
+    async fn provision(&self, scripts: Vec<ProvisionScript>) -> Result<(), Error> {
+        self.step("provision")?;
+        self.agent.provision(&scripts, |_| {});
+        Ok(())
+    }
+
+    async fn provision_with_output(
+        &self,
+        scripts: Vec<ProvisionScript>,
+        on_output: OutputCallback,
+    ) -> Result<(), Error> {
+        self.step("provision")?;
+        self.agent.provision(&scripts, |line| on_output(line));
+        Ok(())
+    }
+}
+
+/// Phases the instance published, in order.
+#[derive(Resource, Default)]
+struct Transitions(Vec<InstancePhase>);
+
+/// World change a step sent back to the harness.
+type Report = Box<dyn FnOnce(&mut World) + Send>;
+
+/// Where the steps the harness runs report to, the way a daemon task sends
+/// messages and queues log lines.
+#[derive(Clone)]
+struct Reports {
+    tx: mpsc::UnboundedSender<Report>,
+    entity: Entity,
+}
+
+impl Reports {
+    fn message(&self, message: OrchestratorMessage) {
+        let _ = self
+            .tx
+            .send(Box::new(move |world: &mut World| message.apply(world)));
+    }
+
+    fn log(&self, lines: Vec<String>) {
+        let entity = self.entity;
+        let _ = self.tx.send(Box::new(move |world: &mut World| {
+            if let Some(mut buffer) = world.get_mut::<LogBuffer>(entity) {
+                for line in lines {
+                    buffer.push(line);
+                }
+            }
+        }));
+    }
+}
+
+/// A server app running the orchestrator for one [`MockDriver`] instance.
+pub struct FlowHarness {
+    app: App,
+    driver: MockDriver,
+    entity: Entity,
+    runtime: tokio::runtime::Runtime,
+    reports: Reports,
+    received: mpsc::UnboundedReceiver<Report>,
+    /// How many phases had been published when the work of the current one
+    /// was started, so each entry starts it once.
+    started: Option<usize>,
+    /// Whether the health checks of the current run are going.
+    watching: bool,
+}
+
+impl FlowHarness {
+    /// Spawn an instance of `driver` from [`FlowHarness::spec`].
+    pub fn new(driver: MockDriver) -> Self {
+        let spec = Self::spec(&driver);
+        Self::with_spec(driver, spec)
+    }
+
+    /// A labelled instance of `driver` with a base image and an empty
+    /// provisioning plan.
+    pub fn spec(driver: &MockDriver) -> ManagedInstanceSpec<MockDriver> {
+        ManagedInstanceSpec::new(Instance::new_with_driver(
+            driver.clone(),
+            BackendKind::Libvirt,
+        ))
+        .with_label("mock")
+        .with_resolved_base_image("mock-image.qcow2")
+        .with_provision_plan(Vec::new())
+    }
+
+    /// Spawn the instance `spec` describes; `driver` is the one it wraps.
+    pub fn with_spec(driver: MockDriver, spec: ManagedInstanceSpec<MockDriver>) -> Self {
+        let mut app = App::new();
+        app.insert_resource(CmdQueue::test());
+        app.insert_resource(MessageQueue::<OrchestratorMessage>::test());
+        app.add_isomorphic_plugin(AppRole::Server, OrchestratorPlugin::<MockDriver>::default());
+        app.init_resource::<Transitions>();
+        app.add_systems(Last, record_transitions);
+
+        let entity = spawn_managed_instance(app.world_mut(), spec);
+        app.update();
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .expect("failed to build the harness runtime");
+        let (tx, received) = mpsc::unbounded_channel();
+        Self {
+            app,
+            driver,
+            entity,
+            runtime,
+            reports: Reports { tx, entity },
+            received,
+            started: None,
+            watching: false,
+        }
+    }
+
+    pub fn entity(&self) -> Entity {
+        self.entity
+    }
+
+    pub fn app_mut(&mut self) -> &mut App {
+        &mut self.app
+    }
+
+    /// The phase the instance is in now.
+    pub fn phase(&self) -> Option<InstancePhase> {
+        self.app.world().get::<InstancePhase>(self.entity).copied()
+    }
+
+    /// Every phase the instance published so far, in order, as clients see
+    /// them.
+    pub fn transitions(&self) -> &[InstancePhase] {
+        &self.app.world().resource::<Transitions>().0
+    }
+
+    /// The log lines the instance published so far, in order.
+    pub fn log(&mut self) -> Vec<String> {
+        let entity = self.entity;
+        let world = self.app.world_mut();
+        let mut entries = world.query::<&ProvisionLogEntry>();
+        entries
+            .iter(world)
+            .filter(|entry| entry.target == entity)
+            .map(|entry| entry.message.clone())
+            .collect()
+    }
+
+    /// Apply `message` as if a task or client had sent it, then update.
+    pub fn send(&mut self, message: OrchestratorMessage) {
+        message.apply(self.app.world_mut());
+        self.app.update();
+    }
+
+    /// Update until the instance is in `phase`, running the work of each
+    /// phase on the way.
+    ///
+    /// # Panics
+    ///
+    /// If the instance does not get there within [`RUN_TIMEOUT`].
+    pub fn run_until(&mut self, phase: InstancePhase) {
+        let deadline = Instant::now() + RUN_TIMEOUT;
+        while Instant::now() < deadline {
+            if self.phase() == Some(phase) {
+                return;
+            }
+            self.start_step();
+            self.apply_reports();
+            self.app.update();
+        }
+        assert_eq!(
+            self.phase(),
+            Some(phase),
+            "instance never got there; went through {:?}",
+            self.transitions()
+        );
+    }
+
+    /// Start the work of the current phase once per entry, like the
+    /// lifecycle observers do in the daemon.
+    fn start_step(&mut self) {
+        let published = self.transitions().len();
+        if self.started == Some(published) {
+            return;
+        }
+        self.started = Some(published);
+
+        let world = self.app.world();
+        let entity = self.entity;
+        let driver = self.driver.clone();
+        let reports = self.reports.clone();
+        let Some(cancel) = world.get::<CancelToken>(entity).cloned() else {
+            return;
+        };
+        match self.phase() {
+            Some(InstancePhase::Preparing) => {
+                let Some(image) = world.get::<ResolvedBaseImage>(entity) else {
+                    return;
+                };
+                let image = image.0.clone();
+                self.runtime.spawn(async move {
+                    if let Some(message) =
+                        lifecycle::run_prepare(&driver, &image, &cancel, entity).await
+                    {
+                        reports.message(message);
+                    }
+                });
+            }
+            Some(InstancePhase::Booting) => {
+                self.runtime.spawn(async move {
+                    if let Some(message) = lifecycle::run_boot(&driver, &cancel, entity).await {
+                        reports.message(message);
+                    }
+                });
+            }
+            Some(InstancePhase::ConnectingGuest) => {
+                let log = reports.clone();
+                self.runtime.spawn(async move {
+                    let connected =
+                        lifecycle::run_connect(&driver, &cancel, entity, move |lines| {
+                            log.log(lines)
+                        });
+                    if let Some(message) = connected.await {
+                        reports.message(message);
+                    }
+                });
+            }
+            Some(InstancePhase::Provisioning) => {
+                let scripts = world
+                    .get::<ProvisionPlan>(entity)
+                    .map(|plan| plan.0.clone())
+                    .unwrap_or_default();
+                let log = reports.clone();
+                let on_output: OutputCallback = Arc::new(move |line| log.log(vec![line]));
+                self.runtime.spawn(async move {
+                    let provisioned =
+                        lifecycle::run_provision(&driver, scripts, &cancel, entity, on_output);
+                    if let Some(message) = provisioned.await {
+                        reports.message(message);
+                    }
+                });
+            }
+            // Back from Unhealthy the checks are still going.
+            Some(InstancePhase::Running) if !self.watching => {
+                let Some(monitor) = world.get::<HealthMonitor>(entity) else {
+                    return;
+                };
+                let monitor = monitor.0.clone();
+                self.watching = true;
+                self.runtime.spawn(async move {
+                    let checks = lifecycle::run_health_checks(&driver, entity, |message| {
+                        reports.message(message)
+                    });
+                    monitor.run(checks).await;
+                });
+            }
+            Some(InstancePhase::ShuttingDown) => {
+                self.watching = false;
+                self.runtime.spawn(async move {
+                    reports.message(lifecycle::run_shutdown(&driver, entity).await);
+                });
+            }
+            _ => {}
+        }
+    }
+
+    /// Run the steps until one reports or [`POLL`] passes, then apply what
+    /// they reported.
+    fn apply_reports(&mut self) {
+        let received = &mut self.received;
+        let first = self
+            .runtime
+            .block_on(async { tokio::time::timeout(POLL, received.recv()).await });
+        if let Ok(Some(report)) = first {
+            report(self.app.world_mut());
+        }
+        while let Ok(report) = self.received.try_recv() {
+            report(self.app.world_mut());
+        }
+    }
+}
+
+/// Record the phase each time it changes, once per update, which is what
+/// replication publishes to clients.
+fn record_transitions(
+    phases: Query<&InstancePhase, Changed<InstancePhase>>,
+    mut transitions: ResMut<Transitions>,
+) {
+    transitions.0.extend(phases.iter().copied());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lifecycle::planned_phases;
+
+    #[test]
+    fn missing_instance_runs_every_step_up_to_running() {
+        let agent = FakeAgent::new().with_output(["installing nginx"]);
+        let driver = MockDriver::new(InstanceState::Missing).with_agent(agent.clone());
+        let script = ProvisionScript::shell(
+            "10-nginx.sh",
+            "nginx",
+            "apt-get install -y nginx".into(),
+            10,
+            guest::agent::RunOn::System,
+        );
+        let spec = FlowHarness::spec(&driver).with_provision_plan(vec![script]);
+        let mut harness = FlowHarness::with_spec(driver.clone(), spec);
+        harness.run_until(InstancePhase::Running);
+        assert!(
+            harness
+                .transitions()
+                .ends_with(&planned_phases(InstanceState::Missing)),
+            "{:?}",
+            harness.transitions()
+        );
+        assert_eq!(
+            driver.calls(),
+            [
+                "prepare",
+                "boot",
+                "wait_for_ip",
+                "connect_guest",
+                "boot_report",
+                "wait_cloud_init",
+                "verify_mounts",
+                "provision",
+                "wait_ready",
+            ]
+        );
+        assert_eq!(agent.provisioned(), ["10-nginx.sh"]);
+
+        harness.send(OrchestratorMessage::RequestShutdown);
+        harness.run_until(InstancePhase::Stopped);
+        assert!(
+            harness
+                .transitions()
+                .ends_with(&[InstancePhase::ShuttingDown, InstancePhase::Stopped])
+        );
+        assert_eq!(driver.calls().last(), Some(&"shutdown"));
+        assert_eq!(harness.log(), ["installing nginx"]);
+    }
+
+    #[test]
+    fn failing_step_ends_the_flow_in_failed() {
+        let driver = MockDriver::new(InstanceState::Stopped).fail_at("boot", || Error::IpTimeout {
+            name: "mock".into(),
+            timeout_s: 1,
+        });
+        let mut harness = FlowHarness::new(driver.clone());
+        harness.run_until(InstancePhase::Failed);
+        assert!(
+            harness
+                .transitions()
+                .ends_with(&[InstancePhase::Booting, InstancePhase::Failed])
+        );
+        assert_eq!(driver.calls(), ["boot"]);
+    }
+
+    #[test]
+    fn missing_mount_fails_before_provisioning() {
+        let mut driver = MockDriver::new(InstanceState::Stopped).fail_at("verify_mounts", || {
+            Error::MountNotActive {
+                tag: "project".into(),
+                target: "/mnt/project".into(),
+            }
+        });
+        driver.timeouts.retries = 0;
+        let mut harness = FlowHarness::new(driver.clone());
+        harness.run_until(InstancePhase::Failed);
+        assert!(
+            harness
+                .transitions()
+                .ends_with(&[InstancePhase::ConnectingGuest, InstancePhase::Failed])
+        );
+        assert_eq!(driver.calls().last(), Some(&"verify_mounts"));
+        assert!(!driver.calls().contains(&"provision"));
+    }
+
+    #[test]
+    fn failed_boot_scripts_are_logged_while_connecting() {
+        let agent = FakeAgent::new().with_boot_scripts(vec![BootScriptResult {
+            name: "10-docker.boot.sh".into(),
+            exit_code: Some(3),
+            output: vec!["docker: not found".into()],
+        }]);
+        let driver = MockDriver::new(InstanceState::Running).with_agent(agent);
+        let mut harness = FlowHarness::new(driver);
+        harness.run_until(InstancePhase::Running);
+        assert_eq!(
+            harness.log(),
+            [
+                "boot script '10-docker.boot.sh' failed with exit code 3",
+                "  docker: not found",
+            ]
+        );
+    }
+
+    #[test]
+    fn unanswered_heartbeats_make_the_instance_unhealthy() {
+        let agent = FakeAgent::new();
+        let driver = MockDriver::new(InstanceState::Running).with_agent(agent.clone());
+        let mut harness = FlowHarness::new(driver);
+        harness.run_until(InstancePhase::Running);
+
+        agent.set_responsive(false);
+        harness.run_until(InstancePhase::Unhealthy);
+        agent.set_responsive(true);
+        harness.run_until(InstancePhase::Running);
+        assert!(harness.transitions().ends_with(&[
+            InstancePhase::Running,
+            InstancePhase::Unhealthy,
+            InstancePhase::Running,
+        ]));
+    }
+
+    #[test]
+    fn reboot_boots_the_stopped_instance_again() {
+        let driver = MockDriver::new(InstanceState::Running);
+        let mut harness = FlowHarness::new(driver.clone());
+        harness.run_until(InstancePhase::Running);
+
+        harness.send(OrchestratorMessage::RequestReboot);
+        harness.run_until(InstancePhase::Booting);
+        harness.run_until(InstancePhase::Running);
+        assert!(
+            harness.transitions().ends_with(&[
+                InstancePhase::Running,
+                InstancePhase::ShuttingDown,
+                InstancePhase::Stopped,
+                InstancePhase::Booting,
+                InstancePhase::ConnectingGuest,
+                InstancePhase::Provisioning,
+                InstancePhase::Running,
+            ]),
+            "{:?}",
+            harness.transitions()
+        );
+        let calls = driver.calls();
+        let shutdown = calls.iter().position(|call| *call == "shutdown").unwrap();
+        assert_eq!(calls[shutdown + 1..][..2], ["boot", "wait_for_ip"]);
+    }
+}
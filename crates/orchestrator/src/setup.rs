@@ -5,8 +5,8 @@ use guest::agent::ProvisionScript;
 
 use crate::driver::OrchestrationDriver;
 use crate::instance::{
-    InstanceLabel, LogBuffer, ManagedInstance, ProvisionLogView, ProvisionPlan, ResolvedBaseImage,
-    instance_phase::Recovering,
+    CancelToken, InstanceLabel, LogBuffer, ManagedInstance, ProvisionLogView, ProvisionPlan,
+    ResolvedBaseImage, instance_phase::Recovering,
 };
 use crate::lifecycle::build_instance_sm;
 
@@ -69,6 +69,7 @@ pub fn spawn_managed_instance<D: OrchestrationDriver>(
         ProvisionLogView::default(),
         ProvisionPlan(spec.provision_plan),
         build_instance_sm::<D>(),
+        CancelToken::default(),
         Recovering,
     ));
 